@@ -1,6 +1,6 @@
-use depyler_core::hir::{BinOp, HirExpr, HirStmt};
+use depyler_core::hir::{BinOp, HirExpr, HirStmt, Spanned};
 
-pub fn calculate_cyclomatic(body: &[HirStmt]) -> u32 {
+pub fn calculate_cyclomatic(body: &[Spanned<HirStmt>]) -> u32 {
     // Start with 1 for the function entry point
     let mut complexity = 1;
 
@@ -36,8 +36,8 @@ fn cyclomatic_stmt(stmt: &HirStmt) -> u32 {
     }
 }
 
-fn cyclomatic_body(body: &[HirStmt]) -> u32 {
-    body.iter().map(cyclomatic_stmt).sum()
+fn cyclomatic_body(body: &[Spanned<HirStmt>]) -> u32 {
+    body.iter().map(|stmt| cyclomatic_stmt(stmt)).sum()
 }
 
 fn cyclomatic_expr(expr: &HirExpr) -> u32 {
@@ -51,11 +51,11 @@ fn cyclomatic_expr(expr: &HirExpr) -> u32 {
     }
 }
 
-pub fn calculate_cognitive(body: &[HirStmt]) -> u32 {
+pub fn calculate_cognitive(body: &[Spanned<HirStmt>]) -> u32 {
     cognitive_body(body, 0).0
 }
 
-fn cognitive_body(body: &[HirStmt], nesting: u32) -> (u32, u32) {
+fn cognitive_body(body: &[Spanned<HirStmt>], nesting: u32) -> (u32, u32) {
     let mut total_complexity = 0;
     let mut max_nesting = nesting;
 
@@ -127,16 +127,16 @@ fn cognitive_condition(expr: &HirExpr) -> u32 {
     }
 }
 
-pub fn calculate_max_nesting(body: &[HirStmt]) -> usize {
+pub fn calculate_max_nesting(body: &[Spanned<HirStmt>]) -> usize {
     cognitive_body(body, 0).1 as usize
 }
 
-pub fn count_statements(body: &[HirStmt]) -> usize {
+pub fn count_statements(body: &[Spanned<HirStmt>]) -> usize {
     let mut count = 0;
 
     for stmt in body {
         count += 1;
-        count += match stmt {
+        count += match &stmt.node {
             HirStmt::If {
                 then_body,
                 else_body,
@@ -155,57 +155,63 @@ pub fn count_statements(body: &[HirStmt]) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use depyler_core::hir::{HirExpr, HirStmt, Literal};
+    use depyler_core::hir::{AssignTarget, HirExpr, HirStmt, Literal};
 
     #[test]
     fn test_cyclomatic_simple_function() {
         // Function with no control flow should have complexity 1
-        let body = vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42))))];
+        let body: Vec<Spanned<HirStmt>> =
+            vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42)))).into()];
         assert_eq!(calculate_cyclomatic(&body), 1);
     }
 
     #[test]
     fn test_cyclomatic_if_statement() {
         // Function with if statement should have complexity 2
-        let body = vec![HirStmt::If {
+        let body: Vec<Spanned<HirStmt>> = vec![HirStmt::If {
             condition: HirExpr::Literal(Literal::Bool(true)),
-            then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(1))))],
+            then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(1)))).into()],
             else_body: None,
-        }];
+        }
+        .into()];
         assert_eq!(calculate_cyclomatic(&body), 2);
     }
 
     #[test]
     fn test_cyclomatic_if_else_statement() {
         // Function with if-else should have complexity 2
-        let body = vec![HirStmt::If {
+        let body: Vec<Spanned<HirStmt>> = vec![HirStmt::If {
             condition: HirExpr::Literal(Literal::Bool(true)),
-            then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(1))))],
+            then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(1)))).into()],
             else_body: Some(vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(
                 2,
-            ))))]),
-        }];
+            ))))
+            .into()]),
+        }
+        .into()];
         assert_eq!(calculate_cyclomatic(&body), 2);
     }
 
     #[test]
     fn test_cyclomatic_while_loop() {
         // Function with while loop should have complexity 2
-        let body = vec![HirStmt::While {
+        let body: Vec<Spanned<HirStmt>> = vec![HirStmt::While {
             condition: HirExpr::Literal(Literal::Bool(true)),
-            body: vec![HirStmt::Return(None)],
-        }];
+            body: vec![HirStmt::Return(None).into()],
+        }
+        .into()];
         assert_eq!(calculate_cyclomatic(&body), 2);
     }
 
     #[test]
     fn test_cyclomatic_for_loop() {
         // Function with for loop should have complexity 2
-        let body = vec![HirStmt::For {
-            target: "i".to_string(),
+        let body: Vec<Spanned<HirStmt>> = vec![HirStmt::For {
+            target: AssignTarget::Symbol("i".to_string()),
             iter: HirExpr::Literal(Literal::Int(0)),
-            body: vec![HirStmt::Return(None)],
-        }];
+            body: vec![HirStmt::Return(None).into()],
+        }
+        .into()];
         assert_eq!(calculate_cyclomatic(&body), 2);
     }
 
@@ -218,14 +224,15 @@ mod tests {
             right: Box::new(HirExpr::Literal(Literal::Bool(false))),
         };
 
-        let body = vec![HirStmt::Expr(condition)];
+        let body: Vec<Spanned<HirStmt>> = vec![HirStmt::Expr(condition).into()];
         assert_eq!(calculate_cyclomatic(&body), 2); // 1 base + 1 for And
     }
 
     #[test]
     fn test_cognitive_simple_function() {
         // Simple function should have cognitive complexity 0
-        let body = vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42))))];
+        let body: Vec<Spanned<HirStmt>> =
+            vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42)))).into()];
         assert_eq!(calculate_cognitive(&body), 0);
     }
 
@@ -234,15 +241,16 @@ mod tests {
         // Nested if statements should have higher cognitive complexity
         let nested_if = HirStmt::If {
             condition: HirExpr::Literal(Literal::Bool(true)),
-            then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(2))))],
+            then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(2)))).into()],
             else_body: None,
         };
 
-        let body = vec![HirStmt::If {
+        let body: Vec<Spanned<HirStmt>> = vec![HirStmt::If {
             condition: HirExpr::Literal(Literal::Bool(true)),
-            then_body: vec![nested_if],
+            then_body: vec![nested_if.into()],
             else_body: None,
-        }];
+        }
+        .into()];
 
         // First if: 1 + 0 (nesting), nested if: 1 + 1 (nesting) = 3
         assert_eq!(calculate_cognitive(&body), 3);
@@ -260,11 +268,12 @@ mod tests {
             right: Box::new(HirExpr::Literal(Literal::Bool(true))),
         };
 
-        let body = vec![HirStmt::If {
+        let body: Vec<Spanned<HirStmt>> = vec![HirStmt::If {
             condition: complex_condition,
-            then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(1))))],
+            then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(1)))).into()],
             else_body: None,
-        }];
+        }
+        .into()];
 
         // If: 1, condition: 2 (for And and Or) = 3
         assert_eq!(calculate_cognitive(&body), 3);
@@ -272,19 +281,21 @@ mod tests {
 
     #[test]
     fn test_count_statements() {
-        let body = vec![
+        let body: Vec<Spanned<HirStmt>> = vec![
             HirStmt::Assign {
-                target: "x".to_string(),
+                target: AssignTarget::Symbol("x".to_string()),
                 value: HirExpr::Literal(Literal::Int(1)),
-            },
+            }
+            .into(),
             HirStmt::If {
                 condition: HirExpr::Literal(Literal::Bool(true)),
                 then_body: vec![
-                    HirStmt::Return(Some(HirExpr::Literal(Literal::Int(1)))),
-                    HirStmt::Return(None),
+                    HirStmt::Return(Some(HirExpr::Literal(Literal::Int(1)))).into(),
+                    HirStmt::Return(None).into(),
                 ],
-                else_body: Some(vec![HirStmt::Return(None)]),
-            },
+                else_body: Some(vec![HirStmt::Return(None).into()]),
+            }
+            .into(),
         ];
         // 2 top-level + 2 in then + 1 in else = 5
         assert_eq!(count_statements(&body), 5);
@@ -292,18 +303,21 @@ mod tests {
 
     #[test]
     fn test_max_nesting() {
-        let deeply_nested = vec![HirStmt::If {
+        let deeply_nested: Vec<Spanned<HirStmt>> = vec![HirStmt::If {
             condition: HirExpr::Literal(Literal::Bool(true)),
             then_body: vec![HirStmt::While {
                 condition: HirExpr::Literal(Literal::Bool(true)),
                 body: vec![HirStmt::For {
-                    target: "i".to_string(),
+                    target: AssignTarget::Symbol("i".to_string()),
                     iter: HirExpr::Literal(Literal::Int(0)),
-                    body: vec![HirStmt::Return(None)],
-                }],
-            }],
+                    body: vec![HirStmt::Return(None).into()],
+                }
+                .into()],
+            }
+            .into()],
             else_body: None,
-        }];
+        }
+        .into()];
         // if (1) -> while (2) -> for (3) = max nesting 3
         assert_eq!(calculate_max_nesting(&deeply_nested), 3);
     }
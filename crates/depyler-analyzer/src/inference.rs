@@ -0,0 +1,1421 @@
+//! Constraint-based type inference over a whole module.
+//!
+//! Every unannotated parameter, return type, class field and local gets a
+//! type variable. Walking the bodies generates equality constraints from
+//! assignments, returns, container displays, calls between the module's
+//! functions and the type schemes of builtins, which are solved by
+//! unification as they arise, as in Hindley-Milner's algorithm J. Two
+//! departures suit Python code: a variable holding an `int` widens to `float`
+//! when a float flows into it, and one holding `None` widens to `Optional`.
+//! A function returning values of different types returns a `Union` of them;
+//! any other constraint that cannot be satisfied leaves the variable as first
+//! inferred instead of failing the module.
+//!
+//! Resolved types replace `Type::Unknown` in signatures and fields; anything
+//! still unconstrained stays unknown.
+
+use crate::type_flow::TypeInferencer;
+use anyhow::Result;
+use depyler_core::exceptions::ERROR_TYPE_NAME;
+use depyler_core::hir::*;
+use std::collections::{HashMap, HashSet};
+
+/// A type with inference variables
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Var(usize),
+    Int,
+    Float,
+    Str,
+    Bool,
+    None,
+    List(Box<Term>),
+    Dict(Box<Term>, Box<Term>),
+    Set(Box<Term>),
+    Tuple(Vec<Term>),
+    Optional(Box<Term>),
+    Function(Vec<Term>, Box<Term>),
+    Custom(String),
+    /// A `Union` or `Literal` type, which becomes a generated enum
+    Enum(Type),
+}
+
+/// Parameter and return types of a function or method
+#[derive(Debug, Clone)]
+struct Signature {
+    params: Vec<Term>,
+    ret: Term,
+    /// Some `return` gives a value; otherwise the function returns `None`
+    returns_value: bool,
+}
+
+#[derive(Debug, Default)]
+struct ClassTerms {
+    fields: HashMap<Symbol, Term>,
+    methods: HashMap<Symbol, Signature>,
+    /// Parameters of the generated constructor
+    init: Vec<Term>,
+}
+
+/// Types inferred for one function: its signature and its locals
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionTypes {
+    pub params: Vec<Type>,
+    pub ret: Type,
+    pub locals: HashMap<Symbol, Type>,
+}
+
+/// Infer the unannotated types of a module and write them back into it
+pub fn infer_types(module: &mut HirModule) -> Result<()> {
+    let mut inference = Inference::for_module(module);
+    inference.solve(module);
+    inference.write_back(module);
+    Ok(())
+}
+
+/// Inferred signatures and locals of the module's functions, by name
+pub fn infer_function_types(module: &HirModule) -> HashMap<Symbol, FunctionTypes> {
+    let mut inference = Inference::for_module(module);
+    inference.solve(module);
+    inference
+        .locals_by_function
+        .iter()
+        .map(|(name, locals)| {
+            let sig = &inference.functions[name];
+            let types = FunctionTypes {
+                params: sig.params.iter().map(|p| inference.to_type(p)).collect(),
+                ret: inference.return_type(sig),
+                locals: locals
+                    .iter()
+                    .map(|(local, term)| (local.clone(), inference.to_type(term)))
+                    .collect(),
+            };
+            (name.clone(), types)
+        })
+        .collect()
+}
+
+struct Inference {
+    /// Binding of each type variable, `None` while unconstrained
+    bindings: Vec<Option<Term>>,
+    functions: HashMap<Symbol, Signature>,
+    classes: HashMap<Symbol, ClassTerms>,
+    /// Signatures of nested functions, in the order they are defined
+    nested: Vec<Signature>,
+    /// Locals of the body being walked
+    locals: HashMap<Symbol, Term>,
+    /// Locals of each module function, once walked
+    locals_by_function: HashMap<Symbol, HashMap<Symbol, Term>>,
+    /// Signature the `return` statements being walked belong to
+    current: Option<Signature>,
+    current_class: Option<Symbol>,
+    /// Concrete result types of operators and builtin methods
+    flow: TypeInferencer,
+}
+
+impl Inference {
+    fn for_module(module: &HirModule) -> Self {
+        let mut inference = Self {
+            bindings: Vec::new(),
+            functions: HashMap::new(),
+            classes: HashMap::new(),
+            nested: Vec::new(),
+            locals: HashMap::new(),
+            locals_by_function: HashMap::new(),
+            current: None,
+            current_class: None,
+            flow: TypeInferencer::new(),
+        };
+        for func in &module.functions {
+            let sig = inference.signature(&func.params, &func.ret_type);
+            inference.functions.insert(func.name.clone(), sig);
+        }
+        for class in &module.classes {
+            let mut terms = ClassTerms::default();
+            for field in &class.fields {
+                let term = inference.term(&field.field_type);
+                terms.fields.insert(field.name.clone(), term);
+            }
+            for method in &class.methods {
+                let sig = inference.signature(&method.params, &method.ret_type);
+                terms.methods.insert(method.name.clone(), sig);
+            }
+            terms.init = match terms.methods.get("__init__") {
+                Some(init) => init.params.clone(),
+                None => class
+                    .fields
+                    .iter()
+                    .filter(|f| !f.is_class_var)
+                    .map(|f| terms.fields[&f.name].clone())
+                    .collect(),
+            };
+            inference.classes.insert(class.name.clone(), terms);
+        }
+        inference
+    }
+
+    /// Walk the module until no signature changes, so that a widening found
+    /// in a later body reaches the bodies walked before it
+    fn solve(&mut self, module: &HirModule) {
+        const MAX_PASSES: usize = 4;
+        let mut previous = self.snapshot();
+        for _ in 0..MAX_PASSES {
+            self.walk_module(module);
+            let current = self.snapshot();
+            if current == previous {
+                break;
+            }
+            previous = current;
+        }
+    }
+
+    /// Resolved types of every signature and field
+    fn snapshot(&self) -> Vec<Type> {
+        let mut types = Vec::new();
+        let push_sig = |types: &mut Vec<Type>, sig: &Signature| {
+            types.extend(sig.params.iter().map(|p| self.to_type(p)));
+            types.push(self.return_type(sig));
+        };
+        let mut names: Vec<&Symbol> = self.functions.keys().collect();
+        names.sort();
+        for name in names {
+            push_sig(&mut types, &self.functions[name]);
+        }
+        let mut classes: Vec<&Symbol> = self.classes.keys().collect();
+        classes.sort();
+        for class in classes {
+            let terms = &self.classes[class];
+            let mut fields: Vec<&Symbol> = terms.fields.keys().collect();
+            fields.sort();
+            types.extend(fields.into_iter().map(|f| self.to_type(&terms.fields[f])));
+            let mut methods: Vec<&Symbol> = terms.methods.keys().collect();
+            methods.sort();
+            for method in methods {
+                push_sig(&mut types, &terms.methods[method]);
+            }
+        }
+        for sig in &self.nested {
+            push_sig(&mut types, sig);
+        }
+        types
+    }
+
+    fn walk_module(&mut self, module: &HirModule) {
+        self.nested.clear();
+        for func in &module.functions {
+            let sig = self.functions[&func.name].clone();
+            self.walk_function(&func.params, sig, &func.body);
+            let sig = self.current.take().expect("signature of walked function");
+            self.functions.insert(func.name.clone(), sig);
+            let locals = std::mem::take(&mut self.locals);
+            self.locals_by_function.insert(func.name.clone(), locals);
+        }
+        for class in &module.classes {
+            self.current_class = Some(class.name.clone());
+            for method in &class.methods {
+                let sig = self.classes[&class.name].methods[&method.name].clone();
+                self.walk_function(&method.params, sig, &method.body);
+                let sig = self.current.take().expect("signature of walked method");
+                if let Some(terms) = self.classes.get_mut(&class.name) {
+                    terms.methods.insert(method.name.clone(), sig);
+                }
+                self.locals.clear();
+            }
+            self.current_class = None;
+        }
+    }
+
+    fn walk_function(
+        &mut self,
+        params: &[(Symbol, Type)],
+        sig: Signature,
+        body: &[Spanned<HirStmt>],
+    ) {
+        self.locals = params
+            .iter()
+            .zip(&sig.params)
+            .map(|((name, _), term)| (name.clone(), term.clone()))
+            .collect();
+        self.current = Some(sig);
+        self.body(body);
+    }
+
+    // Type variables and unification
+
+    fn fresh(&mut self) -> Term {
+        self.bindings.push(None);
+        Term::Var(self.bindings.len() - 1)
+    }
+
+    /// The term of an annotated type; `Unknown` becomes a fresh variable
+    fn term(&mut self, ty: &Type) -> Term {
+        match ty {
+            Type::Unknown => self.fresh(),
+            Type::Int => Term::Int,
+            Type::Float => Term::Float,
+            Type::String => Term::Str,
+            Type::Bool => Term::Bool,
+            Type::None => Term::None,
+            Type::List(elem) => Term::List(Box::new(self.term(elem))),
+            Type::Dict(key, value) => {
+                Term::Dict(Box::new(self.term(key)), Box::new(self.term(value)))
+            }
+            Type::Set(elem) => Term::Set(Box::new(self.term(elem))),
+            Type::Tuple(types) => Term::Tuple(types.iter().map(|t| self.term(t)).collect()),
+            Type::Optional(inner) => Term::Optional(Box::new(self.term(inner))),
+            Type::Function { params, ret } => Term::Function(
+                params.iter().map(|t| self.term(t)).collect(),
+                Box::new(self.term(ret)),
+            ),
+            Type::Custom(name) => Term::Custom(name.clone()),
+            Type::Union(_) | Type::Literal(_) => Term::Enum(ty.clone()),
+        }
+    }
+
+    fn signature(&mut self, params: &[(Symbol, Type)], ret: &Type) -> Signature {
+        Signature {
+            params: params.iter().map(|(_, ty)| self.term(ty)).collect(),
+            ret: self.term(ret),
+            returns_value: false,
+        }
+    }
+
+    /// Follow bound variables to the outermost constructor or unbound variable
+    fn shallow(&self, term: &Term) -> Term {
+        let mut term = term.clone();
+        while let Term::Var(v) = term {
+            match &self.bindings[v] {
+                Some(bound) => term = bound.clone(),
+                None => break,
+            }
+        }
+        term
+    }
+
+    fn occurs(&self, var: usize, term: &Term) -> bool {
+        match self.shallow(term) {
+            Term::Var(v) => v == var,
+            Term::List(t) | Term::Set(t) | Term::Optional(t) => self.occurs(var, &t),
+            Term::Dict(k, v) => self.occurs(var, &k) || self.occurs(var, &v),
+            Term::Tuple(ts) => ts.iter().any(|t| self.occurs(var, t)),
+            Term::Function(ps, r) => ps.iter().any(|t| self.occurs(var, t)) || self.occurs(var, &r),
+            _ => false,
+        }
+    }
+
+    fn unify(&mut self, a: &Term, b: &Term) -> bool {
+        match (self.shallow(a), self.shallow(b)) {
+            (Term::Var(x), Term::Var(y)) if x == y => true,
+            (Term::Var(x), t) | (t, Term::Var(x)) => {
+                if self.occurs(x, &t) {
+                    return false;
+                }
+                self.bindings[x] = Some(t);
+                true
+            }
+            (Term::List(x), Term::List(y))
+            | (Term::Set(x), Term::Set(y))
+            | (Term::Optional(x), Term::Optional(y)) => self.unify(&x, &y),
+            (Term::Dict(k1, v1), Term::Dict(k2, v2)) => {
+                self.unify(&k1, &k2) && self.unify(&v1, &v2)
+            }
+            (Term::Tuple(xs), Term::Tuple(ys)) if xs.len() == ys.len() => {
+                xs.iter().zip(&ys).all(|(x, y)| self.unify(x, y))
+            }
+            (Term::Function(p1, r1), Term::Function(p2, r2)) if p1.len() == p2.len() => {
+                p1.iter().zip(&p2).all(|(x, y)| self.unify(x, y)) && self.unify(&r1, &r2)
+            }
+            (x, y) => x == y,
+        }
+    }
+
+    /// The variable whose binding `term` resolves through, if any
+    fn bound_var(&self, term: &Term) -> Option<usize> {
+        let mut var = None;
+        let mut term = term.clone();
+        while let Term::Var(v) = term {
+            var = Some(v);
+            match &self.bindings[v] {
+                Some(bound) => term = bound.clone(),
+                None => return None,
+            }
+        }
+        var
+    }
+
+    /// A value flows into `slot`: unify them, widening the slot's variable
+    /// from `int` to `float` or to `Optional` when plain unification fails.
+    /// Whether the value fits the slot
+    fn assign(&mut self, slot: &Term, value: &Term) -> bool {
+        if self.unify(slot, value) {
+            return true;
+        }
+        match (self.shallow(slot), self.shallow(value)) {
+            (Term::List(x), Term::List(y)) | (Term::Set(x), Term::Set(y)) => {
+                return self.assign(&x, &y)
+            }
+            (Term::Dict(k1, v1), Term::Dict(k2, v2)) => {
+                let key = self.assign(&k1, &k2);
+                return self.assign(&v1, &v2) && key;
+            }
+            (Term::Tuple(xs), Term::Tuple(ys)) if xs.len() == ys.len() => {
+                let fits: Vec<bool> = xs.iter().zip(&ys).map(|(x, y)| self.assign(x, y)).collect();
+                return fits.into_iter().all(|fits| fits);
+            }
+            _ => {}
+        }
+        let Some(var) = self.bound_var(slot) else {
+            return false;
+        };
+        let widened = match (self.shallow(slot), self.shallow(value)) {
+            (Term::Int, Term::Float) => Term::Float,
+            (Term::None, value) => Term::Optional(Box::new(value)),
+            (Term::Optional(_), Term::None) => return true,
+            (Term::Optional(inner), value) => return self.assign(&inner, &value),
+            (current, Term::None) => Term::Optional(Box::new(current)),
+            (current, Term::Optional(inner)) => {
+                let fits = self.assign(&current, &inner);
+                self.bindings[var] = Some(Term::Optional(Box::new(current)));
+                return fits;
+            }
+            _ => return false,
+        };
+        self.bindings[var] = Some(widened);
+        true
+    }
+
+    /// A function returning values of different types returns a `Union` of
+    /// them, once each is known; a declared return type stays as it is
+    fn widen_to_union(&mut self, slot: &Term, value: &Term) {
+        let Some(var) = self.bound_var(slot) else {
+            return;
+        };
+        let (current, value) = (self.to_type(slot), self.to_type(value));
+        if !is_known(&current) || !is_known(&value) {
+            return;
+        }
+        let (optional, current) = match current {
+            Type::Optional(inner) => (true, *inner),
+            current => (false, current),
+        };
+        let mut members = match current {
+            Type::Union(members) => members,
+            current => vec![current],
+        };
+        if !members.contains(&value) {
+            members.push(value);
+        }
+        let union = Term::Enum(Type::Union(members));
+        self.bindings[var] = Some(match optional {
+            true => Term::Optional(Box::new(union)),
+            false => union,
+        });
+    }
+
+    /// Fully resolve a term, with unbound variables as `Unknown`
+    fn to_type(&self, term: &Term) -> Type {
+        match self.shallow(term) {
+            Term::Var(_) => Type::Unknown,
+            Term::Int => Type::Int,
+            Term::Float => Type::Float,
+            Term::Str => Type::String,
+            Term::Bool => Type::Bool,
+            Term::None => Type::None,
+            Term::List(elem) => Type::List(Box::new(self.to_type(&elem))),
+            Term::Dict(key, value) => {
+                Type::Dict(Box::new(self.to_type(&key)), Box::new(self.to_type(&value)))
+            }
+            Term::Set(elem) => Type::Set(Box::new(self.to_type(&elem))),
+            Term::Tuple(terms) => Type::Tuple(terms.iter().map(|t| self.to_type(t)).collect()),
+            Term::Optional(inner) => Type::Optional(Box::new(self.to_type(&inner))),
+            Term::Function(params, ret) => Type::Function {
+                params: params.iter().map(|t| self.to_type(t)).collect(),
+                ret: Box::new(self.to_type(&ret)),
+            },
+            Term::Custom(name) => Type::Custom(name),
+            Term::Enum(ty) => ty,
+        }
+    }
+
+    fn return_type(&self, sig: &Signature) -> Type {
+        if sig.returns_value {
+            self.to_type(&sig.ret)
+        } else {
+            Type::None
+        }
+    }
+
+    // Constraint generation
+
+    fn local(&mut self, name: &str) -> Term {
+        if let Some(term) = self.locals.get(name) {
+            return term.clone();
+        }
+        let term = self.fresh();
+        self.locals.insert(name.to_string(), term.clone());
+        term
+    }
+
+    /// The item type of iterating over a container. An unconstrained
+    /// iterable is taken to be a list.
+    fn element(&mut self, container: &Term) -> Term {
+        match self.shallow(container) {
+            Term::List(elem) | Term::Set(elem) => *elem,
+            Term::Dict(key, _) => *key,
+            Term::Str => Term::Str,
+            Term::Tuple(terms) if !terms.is_empty() => terms[0].clone(),
+            Term::Var(_) => {
+                let elem = self.fresh();
+                self.unify(container, &Term::List(Box::new(elem.clone())));
+                elem
+            }
+            _ => self.fresh(),
+        }
+    }
+
+    fn body(&mut self, body: &[Spanned<HirStmt>]) {
+        for stmt in body {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &HirStmt) {
+        match stmt {
+            HirStmt::Assign { target, value } => {
+                let value = self.expr(value);
+                self.bind(target, &value);
+            }
+            HirStmt::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value),
+                    None => Term::None,
+                };
+                if let Some(mut sig) = self.current.take() {
+                    sig.returns_value |= value != Term::None;
+                    if !self.assign(&sig.ret, &value) {
+                        self.widen_to_union(&sig.ret, &value);
+                    }
+                    self.current = Some(sig);
+                }
+            }
+            HirStmt::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                self.expr(condition);
+                self.body(then_body);
+                if let Some(else_body) = else_body {
+                    self.body(else_body);
+                }
+            }
+            HirStmt::While { condition, body } => {
+                self.expr(condition);
+                self.body(body);
+            }
+            HirStmt::For { target, iter, body } => {
+                let iter = self.expr(iter);
+                let item = self.element(&iter);
+                self.bind(target, &item);
+                self.body(body);
+            }
+            HirStmt::Expr(expr) | HirStmt::YieldFrom(expr) => {
+                self.expr(expr);
+            }
+            HirStmt::Yield(value) | HirStmt::Raise { exception: value } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                self.body(body);
+                for handler in handlers {
+                    if let Some(name) = &handler.name {
+                        let error = Term::Custom(ERROR_TYPE_NAME.to_string());
+                        self.locals.insert(name.clone(), error);
+                    }
+                    self.body(&handler.body);
+                }
+                self.body(orelse);
+                self.body(finalbody);
+            }
+            HirStmt::With { items, body } => {
+                for item in items {
+                    let resource = self.expr(&item.context_expr);
+                    if let Some(name) = &item.optional_vars {
+                        let slot = self.local(name);
+                        self.assign(&slot, &resource);
+                    }
+                }
+                self.body(body);
+            }
+            HirStmt::Match { subject, cases } => {
+                self.expr(subject);
+                for case in cases {
+                    for name in case.pattern.bindings() {
+                        self.local(name);
+                    }
+                    if let Some(guard) = &case.guard {
+                        self.expr(guard);
+                    }
+                    self.body(&case.body);
+                }
+            }
+            HirStmt::FunctionDef { func, nonlocals } => self.nested_function(func, nonlocals),
+        }
+    }
+
+    /// A nested `def` sees the enclosing locals, except those it rebinds
+    /// without declaring them `nonlocal`
+    fn nested_function(&mut self, func: &HirFunction, nonlocals: &[Symbol]) {
+        let sig = self.signature(&func.params, &func.ret_type);
+        let function = Term::Function(sig.params.clone(), Box::new(sig.ret.clone()));
+        let slot = self.local(&func.name);
+        self.assign(&slot, &function);
+        let index = self.nested.len();
+        self.nested.push(sig.clone());
+
+        let outer = self.locals.clone();
+        let enclosing = self.current.take();
+        for name in assigned_names(&func.body) {
+            if !nonlocals.contains(&name) {
+                let term = self.fresh();
+                self.locals.insert(name, term);
+            }
+        }
+        for ((name, _), term) in func.params.iter().zip(&sig.params) {
+            self.locals.insert(name.clone(), term.clone());
+        }
+        self.current = Some(sig);
+        self.body(&func.body);
+        self.nested[index] = self.current.take().expect("signature of nested function");
+        self.current = enclosing;
+        self.locals = outer;
+    }
+
+    /// Bind the names of an assignment target to the parts of `value`
+    fn bind(&mut self, target: &AssignTarget, value: &Term) {
+        match target {
+            AssignTarget::Symbol(name) => {
+                let slot = self.local(name);
+                self.assign(&slot, value);
+            }
+            AssignTarget::Tuple(targets) => {
+                let parts = match self.shallow(value) {
+                    Term::Tuple(parts) if parts.len() == targets.len() => parts,
+                    Term::List(elem) => vec![*elem; targets.len()],
+                    _ => {
+                        let parts: Vec<Term> = targets.iter().map(|_| self.fresh()).collect();
+                        self.unify(value, &Term::Tuple(parts.clone()));
+                        parts
+                    }
+                };
+                for (target, part) in targets.iter().zip(&parts) {
+                    self.bind(target, part);
+                }
+            }
+            AssignTarget::Index { base, index } => {
+                let base = self.expr(base);
+                let index = self.expr(index);
+                let slot = self.subscript(&base, &index);
+                self.assign(&slot, value);
+            }
+            AssignTarget::Attribute { value: base, attr } => {
+                let base = self.expr(base);
+                let slot = self.attribute(&base, attr);
+                self.assign(&slot, value);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &HirExpr) -> Term {
+        match expr {
+            HirExpr::Literal(literal) => match literal {
+                Literal::Int(_) => Term::Int,
+                Literal::Float(_) => Term::Float,
+                Literal::String(_) => Term::Str,
+                Literal::Bool(_) => Term::Bool,
+                Literal::None => Term::None,
+            },
+            HirExpr::Var(name) if name == "self" => match &self.current_class {
+                Some(class) => Term::Custom(class.clone()),
+                None => self.local(name),
+            },
+            HirExpr::Var(name) => match self.locals.get(name) {
+                Some(term) => term.clone(),
+                None => match self.functions.get(name) {
+                    Some(sig) => Term::Function(sig.params.clone(), Box::new(sig.ret.clone())),
+                    None => self.fresh(),
+                },
+            },
+            HirExpr::Binary { op, left, right } => self.binary(*op, left, right),
+            HirExpr::Unary { op, operand } => {
+                let operand = self.expr(operand);
+                match op {
+                    UnaryOp::Not => Term::Bool,
+                    UnaryOp::Neg | UnaryOp::Pos => operand,
+                    UnaryOp::BitNot => {
+                        self.unify(&operand, &Term::Int);
+                        Term::Int
+                    }
+                }
+            }
+            HirExpr::Call { func, args, kwargs } => {
+                let args: Vec<Term> = args.iter().map(|a| self.expr(a)).collect();
+                for (_, value) in kwargs {
+                    self.expr(value);
+                }
+                self.call(func, &args)
+            }
+            HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                kwargs,
+            } => {
+                let receiver = self.expr(receiver);
+                let args: Vec<Term> = args.iter().map(|a| self.expr(a)).collect();
+                for (_, value) in kwargs {
+                    self.expr(value);
+                }
+                self.method_call(&receiver, method, &args)
+            }
+            HirExpr::Index { base, index } => {
+                let base = self.expr(base);
+                let index_term = self.expr(index);
+                match (self.shallow(&base), index.as_ref()) {
+                    (Term::Tuple(parts), HirExpr::Literal(Literal::Int(i))) => {
+                        let i = if *i < 0 { parts.len() as i64 + i } else { *i };
+                        match usize::try_from(i).ok().and_then(|i| parts.get(i)) {
+                            Some(part) => part.clone(),
+                            None => self.fresh(),
+                        }
+                    }
+                    _ => self.subscript(&base, &index_term),
+                }
+            }
+            // A slice has the type of the sliced list or string
+            HirExpr::Slice {
+                base,
+                start,
+                stop,
+                step,
+            } => {
+                for bound in [start, stop, step].into_iter().flatten() {
+                    self.expr(bound);
+                }
+                self.expr(base)
+            }
+            HirExpr::Attribute { value, attr } => {
+                let value = self.expr(value);
+                self.attribute(&value, attr)
+            }
+            HirExpr::List(elts) => {
+                let elem = self.fresh();
+                for elt in elts {
+                    let term = self.expr(elt);
+                    self.assign(&elem, &term);
+                }
+                Term::List(Box::new(elem))
+            }
+            HirExpr::Dict(items) => {
+                let (key, value) = (self.fresh(), self.fresh());
+                for (k, v) in items {
+                    let k = self.expr(k);
+                    self.assign(&key, &k);
+                    let v = self.expr(v);
+                    self.assign(&value, &v);
+                }
+                Term::Dict(Box::new(key), Box::new(value))
+            }
+            HirExpr::Set(elts) => {
+                let elem = self.fresh();
+                for elt in elts {
+                    let term = self.expr(elt);
+                    self.assign(&elem, &term);
+                }
+                Term::Set(Box::new(elem))
+            }
+            HirExpr::Tuple(elts) => Term::Tuple(elts.iter().map(|e| self.expr(e)).collect()),
+            HirExpr::Comprehension {
+                kind,
+                element,
+                value,
+                generators,
+            } => {
+                let saved: Vec<(Symbol, Option<Term>)> = generators
+                    .iter()
+                    .map(|g| (g.target.clone(), self.locals.get(&g.target).cloned()))
+                    .collect();
+                for generator in generators {
+                    let iter = self.expr(&generator.iter);
+                    let item = self.element(&iter);
+                    self.locals.insert(generator.target.clone(), item);
+                    for condition in &generator.conditions {
+                        self.expr(condition);
+                    }
+                }
+                let element = self.expr(element);
+                let result = match (kind, value) {
+                    (ComprehensionKind::Dict, Some(value)) => {
+                        let value = self.expr(value);
+                        Term::Dict(Box::new(element), Box::new(value))
+                    }
+                    (ComprehensionKind::Set, _) => Term::Set(Box::new(element)),
+                    _ => Term::List(Box::new(element)),
+                };
+                for (name, term) in saved.into_iter().rev() {
+                    match term {
+                        Some(term) => self.locals.insert(name, term),
+                        None => self.locals.remove(&name),
+                    };
+                }
+                result
+            }
+            HirExpr::Await { value } | HirExpr::Borrow { expr: value, .. } => self.expr(value),
+            HirExpr::FString { values, .. } => {
+                for value in values {
+                    self.expr(value);
+                }
+                Term::Str
+            }
+            HirExpr::Lambda { params, body } => {
+                let outer = self.locals.clone();
+                let params: Vec<Term> = params
+                    .iter()
+                    .map(|name| {
+                        let term = self.fresh();
+                        self.locals.insert(name.clone(), term.clone());
+                        term
+                    })
+                    .collect();
+                let ret = self.expr(body);
+                self.locals = outer;
+                Term::Function(params, Box::new(ret))
+            }
+        }
+    }
+
+    fn binary(&mut self, op: BinOp, left: &HirExpr, right: &HirExpr) -> Term {
+        let l = self.expr(left);
+        let r = self.expr(right);
+        match op {
+            BinOp::Eq | BinOp::NotEq | BinOp::Lt | BinOp::LtEq | BinOp::Gt | BinOp::GtEq => {
+                self.assign(&l, &r);
+                Term::Bool
+            }
+            BinOp::In | BinOp::NotIn => {
+                match self.shallow(&r) {
+                    Term::List(elem) | Term::Set(elem) => self.assign(&elem, &l),
+                    Term::Dict(key, _) => self.assign(&key, &l),
+                    Term::Str => self.assign(&l, &Term::Str),
+                    _ => true,
+                };
+                Term::Bool
+            }
+            BinOp::And | BinOp::Or => {
+                self.assign(&l, &r);
+                l
+            }
+            // Union, intersection, difference and symmetric difference of sets
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Sub if self.is_set(&l) => {
+                self.assign(&l, &r);
+                l
+            }
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::LShift | BinOp::RShift => {
+                self.unify(&l, &Term::Int);
+                self.unify(&r, &Term::Int);
+                Term::Int
+            }
+            // `"%d" % n` formats rather than computes a remainder
+            BinOp::Mod if self.shallow(&l) == Term::Str => Term::Str,
+            // Repetition of a string or list by a count
+            BinOp::Mul if self.is_sequence(&l) && self.shallow(&r) == Term::Int => l,
+            BinOp::Mul if self.is_sequence(&r) && self.shallow(&l) == Term::Int => r,
+            BinOp::Div => Term::Float,
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::FloorDiv | BinOp::Mod | BinOp::Pow => {
+                if self.unify(&l, &r) {
+                    return l;
+                }
+                // Mixed operands take the result of the operator table
+                let (lt, rt) = (self.to_type(&l), self.to_type(&r));
+                match self.flow.infer_binary_op(op, &lt, &rt) {
+                    Type::Unknown => l,
+                    ty => self.term(&ty),
+                }
+            }
+        }
+    }
+
+    fn is_set(&self, term: &Term) -> bool {
+        matches!(self.shallow(term), Term::Set(_))
+    }
+
+    fn is_sequence(&self, term: &Term) -> bool {
+        matches!(self.shallow(term), Term::Str | Term::List(_))
+    }
+
+    fn subscript(&mut self, base: &Term, index: &Term) -> Term {
+        match self.shallow(base) {
+            Term::List(elem) => {
+                self.unify(index, &Term::Int);
+                *elem
+            }
+            Term::Dict(key, value) => {
+                self.assign(&key, index);
+                *value
+            }
+            Term::Str => Term::Str,
+            // A string key makes the base a dict, an integer a list
+            Term::Var(_) => {
+                let value = self.fresh();
+                let container = match self.shallow(index) {
+                    Term::Str => Term::Dict(Box::new(Term::Str), Box::new(value.clone())),
+                    Term::Int => Term::List(Box::new(value.clone())),
+                    _ => return value,
+                };
+                self.unify(base, &container);
+                value
+            }
+            _ => self.fresh(),
+        }
+    }
+
+    fn attribute(&mut self, base: &Term, attr: &str) -> Term {
+        let Term::Custom(class) = self.shallow(base) else {
+            return self.fresh();
+        };
+        if let Some(field) = self.classes.get(&class).and_then(|c| c.fields.get(attr)) {
+            return field.clone();
+        }
+        self.fresh()
+    }
+
+    fn call(&mut self, func: &str, args: &[Term]) -> Term {
+        if let Some(callee) = self.locals.get(func).cloned() {
+            let ret = self.fresh();
+            self.unify(
+                &callee,
+                &Term::Function(args.to_vec(), Box::new(ret.clone())),
+            );
+            return ret;
+        }
+        if let Some(sig) = self.functions.get(func).cloned() {
+            for (param, arg) in sig.params.iter().zip(args) {
+                self.assign(param, arg);
+            }
+            return sig.ret;
+        }
+        if let Some(init) = self.classes.get(func).map(|c| c.init.clone()) {
+            for (param, arg) in init.iter().zip(args) {
+                self.assign(param, arg);
+            }
+            return Term::Custom(func.to_string());
+        }
+        self.builtin_call(func, args)
+    }
+
+    /// The type schemes of builtins, instantiated for one call
+    fn builtin_call(&mut self, func: &str, args: &[Term]) -> Term {
+        match (func, args) {
+            ("len", [_]) => Term::Int,
+            ("range", _) => {
+                for arg in args {
+                    self.unify(arg, &Term::Int);
+                }
+                Term::List(Box::new(Term::Int))
+            }
+            ("abs", [x]) => x.clone(),
+            ("round", [_, _]) => Term::Float,
+            ("min" | "max" | "sum", [xs]) => self.element(xs),
+            ("min" | "max", [first, rest @ ..]) => {
+                for arg in rest {
+                    self.assign(first, arg);
+                }
+                first.clone()
+            }
+            ("sorted" | "reversed" | "list", [xs]) => {
+                let elem = self.element(xs);
+                Term::List(Box::new(elem))
+            }
+            ("list", []) => Term::List(Box::new(self.fresh())),
+            ("dict", []) => Term::Dict(Box::new(self.fresh()), Box::new(self.fresh())),
+            ("set" | "frozenset", [xs]) => {
+                let elem = self.element(xs);
+                Term::Set(Box::new(elem))
+            }
+            ("set" | "frozenset", []) => Term::Set(Box::new(self.fresh())),
+            ("enumerate", [xs]) => {
+                let elem = self.element(xs);
+                Term::List(Box::new(Term::Tuple(vec![Term::Int, elem])))
+            }
+            ("zip", _) => {
+                let elems = args.iter().map(|xs| self.element(xs)).collect();
+                Term::List(Box::new(Term::Tuple(elems)))
+            }
+            ("str" | "input" | "repr" | "chr", _) => Term::Str,
+            ("int" | "round" | "ord", _) => Term::Int,
+            ("float", _) => Term::Float,
+            ("bool" | "isinstance" | "any" | "all" | "callable", _) => Term::Bool,
+            ("print", _) => Term::None,
+            _ => self.fresh(),
+        }
+    }
+
+    fn method_call(&mut self, receiver: &Term, method: &str, args: &[Term]) -> Term {
+        if let Term::Custom(class) = self.shallow(receiver) {
+            if let Some(sig) = self
+                .classes
+                .get(&class)
+                .and_then(|c| c.methods.get(method))
+                .cloned()
+            {
+                for (param, arg) in sig.params.iter().zip(args) {
+                    self.assign(param, arg);
+                }
+                return sig.ret;
+            }
+            // A callable stored in a field
+            if let Some(field) = self.classes.get(&class).and_then(|c| c.fields.get(method)) {
+                let field = field.clone();
+                let ret = self.fresh();
+                self.unify(
+                    &field,
+                    &Term::Function(args.to_vec(), Box::new(ret.clone())),
+                );
+                return ret;
+            }
+        }
+
+        if let Term::Set(elem) = self.shallow(receiver) {
+            return self.set_method(receiver, &elem, method, args);
+        }
+
+        // Methods only lists, dicts, sets or strings have constrain the receiver
+        match (method, args) {
+            ("add" | "discard", [item]) => {
+                self.unify(receiver, &Term::Set(Box::new(item.clone())));
+                return Term::None;
+            }
+            ("append", [item]) | ("remove", [item]) => {
+                self.unify(receiver, &Term::List(Box::new(item.clone())));
+                return Term::None;
+            }
+            ("insert", [index, item]) => {
+                self.unify(index, &Term::Int);
+                self.unify(receiver, &Term::List(Box::new(item.clone())));
+                return Term::None;
+            }
+            ("extend", [items]) => {
+                let elem = self.element(items);
+                self.unify(receiver, &Term::List(Box::new(elem)));
+                return Term::None;
+            }
+            ("items" | "keys" | "values" | "get" | "setdefault" | "update", _) => {
+                let (key, value) = (self.fresh(), self.fresh());
+                let dict = Term::Dict(Box::new(key.clone()), Box::new(value.clone()));
+                self.unify(receiver, &dict);
+                return match (method, args) {
+                    ("items", _) => Term::List(Box::new(Term::Tuple(vec![key, value]))),
+                    ("keys", _) => Term::List(Box::new(key)),
+                    ("values", _) => Term::List(Box::new(value)),
+                    ("get", [k]) => {
+                        self.assign(&key, k);
+                        Term::Optional(Box::new(value))
+                    }
+                    ("get" | "setdefault", [k, default]) => {
+                        self.assign(&key, k);
+                        self.assign(&value, default);
+                        value
+                    }
+                    _ => Term::None,
+                };
+            }
+            ("join", [items]) => {
+                self.unify(receiver, &Term::Str);
+                self.unify(items, &Term::List(Box::new(Term::Str)));
+                return Term::Str;
+            }
+            _ if is_string_method(method) => {
+                self.unify(receiver, &Term::Str);
+            }
+            _ => {}
+        }
+        let receiver = self.to_type(receiver);
+        match self.flow.infer_builtin_method(&receiver, method) {
+            Type::Unknown => self.fresh(),
+            ty => self.term(&ty),
+        }
+    }
+
+    fn set_method(&mut self, set: &Term, elem: &Term, method: &str, args: &[Term]) -> Term {
+        match (method, args) {
+            ("add" | "discard" | "remove", [item]) => {
+                self.assign(elem, item);
+                Term::None
+            }
+            ("pop", []) => elem.clone(),
+            ("copy", []) => set.clone(),
+            ("union" | "intersection" | "difference" | "symmetric_difference", [other]) => {
+                let other = self.element(other);
+                self.assign(elem, &other);
+                set.clone()
+            }
+            ("issubset" | "issuperset" | "isdisjoint", _) => Term::Bool,
+            _ => Term::None,
+        }
+    }
+
+    // Write-back
+
+    fn write_back(&self, module: &mut HirModule) {
+        // A stub has no body to tell what it returns; its type stays as declared
+        let stubs: HashSet<String> = module.skipped.iter().map(|s| s.function.clone()).collect();
+        let mut nested = self.nested.iter();
+        for func in &mut module.functions {
+            let sig = &self.functions[&func.name];
+            let mut ret = func.ret_type.clone();
+            let ret_type = if stubs.contains(&func.name) {
+                &mut ret
+            } else {
+                &mut func.ret_type
+            };
+            self.write_signature(
+                &mut func.params,
+                ret_type,
+                sig,
+                func.properties.is_generator,
+            );
+            self.write_nested(&mut func.body, &mut nested);
+        }
+        for class in &mut module.classes {
+            let terms = &self.classes[&class.name];
+            for field in &mut class.fields {
+                if field.field_type == Type::Unknown {
+                    field.field_type = self.to_type(&terms.fields[&field.name]);
+                }
+            }
+            for method in &mut class.methods {
+                let sig = &terms.methods[&method.name];
+                let is_generator = method.properties.is_generator;
+                // `__init__` returns the instance, whatever its body says,
+                // and stubs what they declare
+                let stub = stubs.contains(&format!("{}.{}", class.name, method.name));
+                if method.name == "__init__" || stub {
+                    let mut ret = method.ret_type.clone();
+                    self.write_signature(&mut method.params, &mut ret, sig, is_generator);
+                } else {
+                    self.write_signature(
+                        &mut method.params,
+                        &mut method.ret_type,
+                        sig,
+                        is_generator,
+                    );
+                }
+                self.write_nested(&mut method.body, &mut nested);
+            }
+        }
+    }
+
+    fn write_signature(
+        &self,
+        params: &mut [(Symbol, Type)],
+        ret: &mut Type,
+        sig: &Signature,
+        is_generator: bool,
+    ) {
+        for ((_, ty), term) in params.iter_mut().zip(&sig.params) {
+            if *ty == Type::Unknown {
+                *ty = self.to_type(term);
+            }
+        }
+        // A generator's return type is the iterator its body yields
+        if *ret == Type::Unknown && !is_generator {
+            *ret = self.return_type(sig);
+        }
+    }
+
+    /// Nested functions, in the order [`Inference::nested_function`] saw them
+    fn write_nested<'s>(
+        &self,
+        body: &mut [Spanned<HirStmt>],
+        nested: &mut impl Iterator<Item = &'s Signature>,
+    ) {
+        for stmt in body {
+            match &mut stmt.node {
+                HirStmt::FunctionDef { func, .. } => {
+                    if let Some(sig) = nested.next() {
+                        let is_generator = func.properties.is_generator;
+                        self.write_signature(
+                            &mut func.params,
+                            &mut func.ret_type,
+                            sig,
+                            is_generator,
+                        );
+                    }
+                    self.write_nested(&mut func.body, nested);
+                }
+                HirStmt::If {
+                    then_body,
+                    else_body,
+                    ..
+                } => {
+                    self.write_nested(then_body, nested);
+                    if let Some(else_body) = else_body {
+                        self.write_nested(else_body, nested);
+                    }
+                }
+                HirStmt::While { body, .. }
+                | HirStmt::For { body, .. }
+                | HirStmt::With { body, .. } => self.write_nested(body, nested),
+                HirStmt::Try {
+                    body,
+                    handlers,
+                    orelse,
+                    finalbody,
+                } => {
+                    self.write_nested(body, nested);
+                    for handler in handlers {
+                        self.write_nested(&mut handler.body, nested);
+                    }
+                    self.write_nested(orelse, nested);
+                    self.write_nested(finalbody, nested);
+                }
+                HirStmt::Match { cases, .. } => {
+                    for case in cases {
+                        self.write_nested(&mut case.body, nested);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn is_string_method(method: &str) -> bool {
+    matches!(
+        method,
+        "strip"
+            | "lstrip"
+            | "rstrip"
+            | "upper"
+            | "lower"
+            | "casefold"
+            | "swapcase"
+            | "split"
+            | "rsplit"
+            | "splitlines"
+            | "replace"
+            | "startswith"
+            | "endswith"
+            | "isdigit"
+            | "isdecimal"
+            | "isnumeric"
+            | "isalpha"
+            | "isalnum"
+            | "isspace"
+            | "isupper"
+            | "islower"
+            | "find"
+            | "rfind"
+            | "rindex"
+            | "title"
+            | "capitalize"
+            | "zfill"
+            | "ljust"
+            | "rjust"
+            | "center"
+            | "removeprefix"
+            | "removesuffix"
+            | "format"
+    )
+}
+
+/// Names a function body binds, outside its nested functions' bodies
+fn assigned_names(body: &[Spanned<HirStmt>]) -> Vec<Symbol> {
+    let mut names = Vec::new();
+    for stmt in body {
+        match &stmt.node {
+            HirStmt::Assign { target, .. } => names.extend(target.symbols().into_iter().cloned()),
+            HirStmt::For { target, body, .. } => {
+                names.extend(target.symbols().into_iter().cloned());
+                names.extend(assigned_names(body));
+            }
+            HirStmt::FunctionDef { func, .. } => names.push(func.name.clone()),
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                names.extend(assigned_names(then_body));
+                if let Some(else_body) = else_body {
+                    names.extend(assigned_names(else_body));
+                }
+            }
+            HirStmt::While { body, .. } => names.extend(assigned_names(body)),
+            HirStmt::With { items, body } => {
+                names.extend(items.iter().filter_map(|i| i.optional_vars.clone()));
+                names.extend(assigned_names(body));
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                names.extend(assigned_names(body));
+                for handler in handlers {
+                    names.extend(handler.name.clone());
+                    names.extend(assigned_names(&handler.body));
+                }
+                names.extend(assigned_names(orelse));
+                names.extend(assigned_names(finalbody));
+            }
+            HirStmt::Match { cases, .. } => {
+                for case in cases {
+                    names.extend(case.pattern.bindings().into_iter().cloned());
+                    names.extend(assigned_names(&case.body));
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Whether no part of `ty` is left unknown
+fn is_known(ty: &Type) -> bool {
+    match ty {
+        Type::Unknown => false,
+        Type::List(inner) | Type::Set(inner) | Type::Optional(inner) => is_known(inner),
+        Type::Dict(key, value) => is_known(key) && is_known(value),
+        Type::Tuple(types) | Type::Union(types) => types.iter().all(is_known),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use depyler_core::DepylerPipeline;
+
+    fn infer(source: &str) -> HirModule {
+        let mut module = DepylerPipeline::new().parse_to_hir(source).unwrap();
+        infer_types(&mut module).unwrap();
+        module
+    }
+
+    fn signature(module: &HirModule, name: &str) -> (Vec<Type>, Type) {
+        let func = module.functions.iter().find(|f| f.name == name).unwrap();
+        let params = func.params.iter().map(|(_, ty)| ty.clone()).collect();
+        (params, func.ret_type.clone())
+    }
+
+    #[test]
+    fn test_params_from_call_sites() {
+        let module =
+            infer("def double(x):\n    return x * 2\n\ndef main():\n    print(double(3))\n");
+        assert_eq!(signature(&module, "double"), (vec![Type::Int], Type::Int));
+        assert_eq!(signature(&module, "main"), (vec![], Type::None));
+    }
+
+    #[test]
+    fn test_list_built_by_append() {
+        let module = infer(
+            "def evens(n):\n    out = []\n    for i in range(n):\n        out.append(i * 2)\n    return out\n",
+        );
+        assert_eq!(
+            signature(&module, "evens"),
+            (vec![Type::Int], Type::List(Box::new(Type::Int)))
+        );
+    }
+
+    #[test]
+    fn test_none_return_widens_to_optional() {
+        let module = infer(
+            "def find(items, target):\n    for i in range(len(items)):\n        if items[i] == target:\n            return i\n    return None\n\ndef main():\n    find([\"a\"], \"a\")\n",
+        );
+        assert_eq!(
+            signature(&module, "find"),
+            (
+                vec![Type::List(Box::new(Type::String)), Type::String],
+                Type::Optional(Box::new(Type::Int))
+            )
+        );
+    }
+
+    #[test]
+    fn test_int_widens_to_float_across_functions() {
+        let module = infer(
+            "def total(xs):\n    acc = 0\n    for x in xs:\n        acc = acc + x\n    return acc\n\ndef main():\n    total([1.5, 2.5])\n",
+        );
+        assert_eq!(
+            signature(&module, "total"),
+            (vec![Type::List(Box::new(Type::Float))], Type::Float)
+        );
+    }
+
+    #[test]
+    fn test_annotations_are_kept() {
+        let module = infer("def f(x: float, y):\n    return x + y\n\ndef g():\n    f(1.0, 2)\n");
+        assert_eq!(
+            signature(&module, "f"),
+            (vec![Type::Float, Type::Float], Type::Float)
+        );
+    }
+
+    #[test]
+    fn test_unconstrained_stays_unknown() {
+        let module = infer("def ident(x):\n    return x\n");
+        assert_eq!(
+            signature(&module, "ident"),
+            (vec![Type::Unknown], Type::Unknown)
+        );
+    }
+
+    #[test]
+    fn test_dict_subscript_and_class_fields() {
+        let module = infer(
+            "class Counter:\n    def __init__(self, start):\n        self.count = start\n\ndef tally(words):\n    counts = {}\n    for w in words:\n        counts[w] = 1\n    return counts\n\ndef main():\n    tally([\"a\"])\n    Counter(0)\n",
+        );
+        assert_eq!(
+            signature(&module, "tally").1,
+            Type::Dict(Box::new(Type::String), Box::new(Type::Int))
+        );
+        let init = module.classes[0].init_method().unwrap();
+        assert_eq!(init.params[0].1, Type::Int);
+    }
+
+    #[test]
+    fn test_conflicting_returns_widen_to_union() {
+        let module = infer(
+            "def pick(flag: bool):\n    if flag:\n        return 1\n    return \"a\"\n\ndef maybe(n: int):\n    if n == 0:\n        return None\n    if n == 1:\n        return 1.5\n    return \"a\"\n\ndef declared(flag: bool) -> int:\n    if flag:\n        return 1\n    return \"a\"\n",
+        );
+        assert_eq!(
+            signature(&module, "pick").1,
+            Type::Union(vec![Type::Int, Type::String])
+        );
+        assert_eq!(
+            signature(&module, "maybe").1,
+            Type::Optional(Box::new(Type::Union(vec![Type::Float, Type::String])))
+        );
+        assert_eq!(signature(&module, "declared").1, Type::Int);
+    }
+
+    #[test]
+    fn test_function_types_include_locals() {
+        let module = DepylerPipeline::new()
+            .parse_to_hir("def f():\n    name = \"x\"\n    size = len(name)\n    return size\n")
+            .unwrap();
+        let types = infer_function_types(&module);
+        let f = &types["f"];
+        assert_eq!(f.ret, Type::Int);
+        assert_eq!(f.locals["name"], Type::String);
+        assert_eq!(f.locals["size"], Type::Int);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod complexity;
+pub mod inference;
 pub mod metrics;
 pub mod type_flow;
 
@@ -8,7 +9,7 @@ pub use complexity::{
 };
 
 use anyhow::Result;
-use depyler_core::hir::{HirFunction, HirModule};
+use depyler_core::hir::{HirFunction, HirModule, Type};
 use serde::{Deserialize, Serialize};
 
 #[cfg(test)]
@@ -49,11 +50,15 @@ pub struct TypeCoverage {
     pub annotated_parameters: usize,
     pub total_functions: usize,
     pub functions_with_return_type: usize,
+    /// Unannotated parameters whose type inference resolved
+    pub inferred_parameters: usize,
+    /// Unannotated return types that inference resolved
+    pub functions_with_inferred_return_type: usize,
+    /// Annotated and inferred types as a share of all parameters and returns
     pub coverage_percentage: f64,
 }
 
 pub struct Analyzer {
-    #[allow(dead_code)]
     enable_type_inference: bool,
 }
 
@@ -72,7 +77,10 @@ impl Analyzer {
             .collect::<Result<Vec<_>>>()?;
 
         let module_metrics = self.calculate_module_metrics(&function_metrics);
-        let type_coverage = self.calculate_type_coverage(module);
+        let mut type_coverage = self.calculate_type_coverage(module);
+        if self.enable_type_inference {
+            self.add_inferred_coverage(module, &mut type_coverage)?;
+        }
 
         Ok(AnalysisResult {
             module_metrics,
@@ -182,9 +190,41 @@ impl Analyzer {
             annotated_parameters,
             total_functions: module.functions.len(),
             functions_with_return_type,
+            inferred_parameters: 0,
+            functions_with_inferred_return_type: 0,
             coverage_percentage,
         }
     }
+
+    /// Count the types inference fills in and include them in the coverage
+    fn add_inferred_coverage(&self, module: &HirModule, coverage: &mut TypeCoverage) -> Result<()> {
+        let mut inferred = module.clone();
+        inference::infer_types(&mut inferred)?;
+
+        for (original, func) in module.functions.iter().zip(&inferred.functions) {
+            coverage.inferred_parameters += original
+                .params
+                .iter()
+                .zip(&func.params)
+                .filter(|((_, before), (_, after))| {
+                    *before == Type::Unknown && *after != Type::Unknown
+                })
+                .count();
+            if original.ret_type == Type::Unknown && func.ret_type != Type::Unknown {
+                coverage.functions_with_inferred_return_type += 1;
+            }
+        }
+
+        let covered = coverage.annotated_parameters
+            + coverage.inferred_parameters
+            + coverage.functions_with_return_type
+            + coverage.functions_with_inferred_return_type;
+        let total_possible = coverage.total_parameters + coverage.total_functions;
+        if total_possible > 0 {
+            coverage.coverage_percentage = (covered as f64 / total_possible as f64) * 100.0;
+        }
+        Ok(())
+    }
 }
 
 impl Default for Analyzer {
@@ -206,11 +246,14 @@ mod tests {
                 ("x".to_string(), Type::Int),
                 ("y".to_string(), Type::String)
             ],
+            param_spec: Default::default(),
             ret_type: Type::Int,
-            body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42))))],
+            body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42)))).into()],
             properties: FunctionProperties::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }
     }
 
@@ -228,7 +271,9 @@ mod tests {
         let analyzer = Analyzer::new();
         let module = HirModule {
             functions: vec![],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let result = analyzer.analyze(&module).unwrap();
@@ -244,7 +289,9 @@ mod tests {
         let func = create_test_function();
         let module = HirModule {
             functions: vec![func],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let result = analyzer.analyze(&module).unwrap();
@@ -265,26 +312,34 @@ mod tests {
         let func_with_types = HirFunction {
             name: "typed_func".to_string(),
             params: smallvec![("x".to_string(), Type::Int)],
+            param_spec: Default::default(),
             ret_type: Type::String,
             body: vec![],
             properties: FunctionProperties::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         };
 
         let func_without_types = HirFunction {
             name: "untyped_func".to_string(),
             params: smallvec![("y".to_string(), Type::Unknown)],
+            param_spec: Default::default(),
             ret_type: Type::Unknown,
             body: vec![],
             properties: FunctionProperties::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         };
 
         let module = HirModule {
             functions: vec![func_with_types, func_without_types],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let coverage = analyzer.calculate_type_coverage(&module);
@@ -295,6 +350,41 @@ mod tests {
         assert_eq!(coverage.coverage_percentage, 50.0); // 2 annotations out of 4 possible
     }
 
+    #[test]
+    fn test_inferred_type_coverage() {
+        let analyzer = Analyzer::new();
+        use smallvec::smallvec;
+        let func = HirFunction {
+            name: "increment".to_string(),
+            params: smallvec![("y".to_string(), Type::Unknown)],
+            param_spec: Default::default(),
+            ret_type: Type::Unknown,
+            body: vec![HirStmt::Return(Some(HirExpr::Binary {
+                op: BinOp::Add,
+                left: Box::new(HirExpr::Var("y".to_string())),
+                right: Box::new(HirExpr::Literal(Literal::Int(1))),
+            }))
+            .into()],
+            properties: FunctionProperties::default(),
+            annotations: TranspilationAnnotations::default(),
+            docstring: None,
+            is_async: false,
+            span: Default::default(),
+        };
+        let module = HirModule {
+            functions: vec![func],
+            classes: vec![],
+            imports: vec![],
+            skipped: vec![],
+        };
+
+        let coverage = analyzer.analyze(&module).unwrap().type_coverage;
+        assert_eq!(coverage.annotated_parameters, 0);
+        assert_eq!(coverage.inferred_parameters, 1);
+        assert_eq!(coverage.functions_with_inferred_return_type, 1);
+        assert_eq!(coverage.coverage_percentage, 100.0);
+    }
+
     #[test]
     fn test_module_metrics_calculation() {
         let analyzer = Analyzer::new();
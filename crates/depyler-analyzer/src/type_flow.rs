@@ -1,5 +1,8 @@
 use anyhow::Result;
-use depyler_core::hir::{HirExpr, HirFunction, HirStmt, Type};
+use depyler_core::hir::{
+    AssignTarget, ComprehensionClause, ComprehensionKind, HirExpr, HirFunction, HirStmt, Spanned,
+    Type,
+};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
@@ -105,7 +108,7 @@ impl TypeInferencer {
         Ok(self.env.variables.clone())
     }
 
-    fn infer_body(&mut self, body: &[HirStmt]) -> Result<()> {
+    fn infer_body(&mut self, body: &[Spanned<HirStmt>]) -> Result<()> {
         for stmt in body {
             self.infer_stmt(stmt)?;
         }
@@ -116,7 +119,7 @@ impl TypeInferencer {
         match stmt {
             HirStmt::Assign { target, value } => {
                 let value_type = self.infer_expr(value)?;
-                self.env.set_var_type(target.clone(), value_type);
+                self.bind_target(target, value_type);
             }
             HirStmt::If {
                 condition,
@@ -136,17 +139,69 @@ impl TypeInferencer {
             HirStmt::For { target, iter, body } => {
                 let iter_type = self.infer_expr(iter)?;
                 let element_type = self.get_element_type(&iter_type);
-                self.env.set_var_type(target.clone(), element_type);
+                self.bind_target(target, element_type);
                 self.infer_body(body)?;
             }
-            HirStmt::Return(expr) => {
+            HirStmt::Return(expr) | HirStmt::Yield(expr) => {
                 if let Some(e) = expr {
                     self.infer_expr(e)?;
                 }
             }
-            HirStmt::Expr(expr) => {
+            HirStmt::Expr(expr) | HirStmt::YieldFrom(expr) => {
                 self.infer_expr(expr)?;
             }
+            HirStmt::Raise { exception } => {
+                if let Some(e) = exception {
+                    self.infer_expr(e)?;
+                }
+            }
+            HirStmt::Match { subject, cases } => {
+                self.infer_expr(subject)?;
+                for case in cases {
+                    for name in case.pattern.bindings() {
+                        self.env.set_var_type(name.clone(), Type::Unknown);
+                    }
+                    if let Some(guard) = &case.guard {
+                        self.infer_expr(guard)?;
+                    }
+                    self.infer_body(&case.body)?;
+                }
+            }
+            HirStmt::With { items, body } => {
+                for item in items {
+                    let resource_type = self.infer_expr(&item.context_expr)?;
+                    if let Some(name) = &item.optional_vars {
+                        self.env.set_var_type(name.clone(), resource_type);
+                    }
+                }
+                self.infer_body(body)?;
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                self.infer_body(body)?;
+                for handler in handlers {
+                    if let Some(name) = &handler.name {
+                        let error_type =
+                            Type::Custom(depyler_core::exceptions::ERROR_TYPE_NAME.to_string());
+                        self.env.set_var_type(name.clone(), error_type);
+                    }
+                    self.infer_body(&handler.body)?;
+                }
+                self.infer_body(orelse)?;
+                self.infer_body(finalbody)?;
+            }
+            // The nested body has its own scope; only the name is bound here
+            HirStmt::FunctionDef { func, .. } => {
+                let func_type = Type::Function {
+                    params: func.params.iter().map(|(_, ty)| ty.clone()).collect(),
+                    ret: Box::new(func.ret_type.clone()),
+                };
+                self.env.set_var_type(func.name.clone(), func_type);
+            }
         }
         Ok(())
     }
@@ -157,11 +212,42 @@ impl TypeInferencer {
             HirExpr::Var(name) => Ok(self.infer_variable(name)),
             HirExpr::Binary { op, left, right } => self.infer_binary(op, left, right),
             HirExpr::Unary { op, operand } => self.infer_unary(op, operand),
-            HirExpr::Call { func, args } => self.infer_call(func, args),
+            HirExpr::Call { func, args, kwargs } => {
+                for (_, value) in kwargs {
+                    self.infer_expr(value)?;
+                }
+                self.infer_call(func, args)
+            }
+            HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                kwargs,
+            } => {
+                for (_, value) in kwargs {
+                    self.infer_expr(value)?;
+                }
+                self.infer_method_call(receiver, method, args)
+            }
             HirExpr::Index { base, index } => self.infer_index(base, index),
+            HirExpr::Slice { base, .. } => self.infer_expr(base),
             HirExpr::List(elts) => self.infer_list(elts),
             HirExpr::Dict(items) => self.infer_dict(items),
             HirExpr::Tuple(elts) => self.infer_tuple(elts),
+            HirExpr::Comprehension {
+                kind,
+                element,
+                value,
+                generators,
+            } => self.infer_comprehension(*kind, element, value.as_deref(), generators),
+            // Signatures record what the coroutine resolves to
+            HirExpr::Await { value } => self.infer_expr(value),
+            HirExpr::FString { values, .. } => {
+                for value in values {
+                    self.infer_expr(value)?;
+                }
+                Ok(Type::String)
+            }
             _ => Ok(Type::Unknown),
         }
     }
@@ -213,6 +299,52 @@ impl TypeInferencer {
         })
     }
 
+    fn infer_method_call(
+        &mut self,
+        receiver: &HirExpr,
+        method: &str,
+        args: &[HirExpr],
+    ) -> Result<Type> {
+        let receiver_type = self.infer_expr(receiver)?;
+        for arg in args {
+            self.infer_expr(arg)?;
+        }
+        Ok(self.infer_builtin_method(&receiver_type, method))
+    }
+
+    pub(crate) fn infer_builtin_method(&self, receiver: &Type, method: &str) -> Type {
+        match (receiver, method) {
+            (
+                Type::String,
+                "strip" | "lstrip" | "rstrip" | "upper" | "lower" | "casefold" | "capitalize"
+                | "swapcase" | "title" | "replace" | "join" | "zfill" | "ljust" | "rjust"
+                | "center" | "removeprefix" | "removesuffix" | "format",
+            ) => Type::String,
+            (Type::String, "split" | "rsplit" | "splitlines") => Type::List(Box::new(Type::String)),
+            (
+                Type::String,
+                "startswith" | "endswith" | "isdigit" | "isdecimal" | "isnumeric" | "isalpha"
+                | "isalnum" | "isspace" | "isupper" | "islower",
+            ) => Type::Bool,
+            (Type::String, "find" | "rfind" | "index" | "rindex" | "count") => Type::Int,
+            (Type::List(elem), "pop") => (**elem).clone(),
+            (Type::List(_), "index" | "count") => Type::Int,
+            (Type::Dict(_, val), "get") => Type::Optional(val.clone()),
+            (Type::Dict(key, _), "keys") => Type::List(key.clone()),
+            (Type::Dict(_, val), "values") => Type::List(val.clone()),
+            (Type::Dict(key, val), "items") => Type::List(Box::new(Type::Tuple(vec![
+                (**key).clone(),
+                (**val).clone(),
+            ]))),
+            (Type::Dict(_, val), "pop") => (**val).clone(),
+            (Type::List(_) | Type::Dict(_, _), "copy") => receiver.clone(),
+            (_, "append" | "extend" | "insert" | "clear" | "sort" | "reverse" | "update") => {
+                Type::None
+            }
+            _ => Type::Unknown,
+        }
+    }
+
     fn infer_index(&mut self, base: &HirExpr, index: &HirExpr) -> Result<Type> {
         let base_type = self.infer_expr(base)?;
         self.infer_expr(index)?;
@@ -239,6 +371,30 @@ impl TypeInferencer {
         }
     }
 
+    fn infer_comprehension(
+        &mut self,
+        kind: ComprehensionKind,
+        element: &HirExpr,
+        value: Option<&HirExpr>,
+        generators: &[ComprehensionClause],
+    ) -> Result<Type> {
+        for generator in generators {
+            let iter_type = self.infer_expr(&generator.iter)?;
+            let element_type = self.get_element_type(&iter_type);
+            self.env
+                .set_var_type(generator.target.clone(), element_type);
+        }
+        let element_type = self.infer_expr(element)?;
+        Ok(match (kind, value) {
+            (ComprehensionKind::Dict, Some(value)) => {
+                let value_type = self.infer_expr(value)?;
+                Type::Dict(Box::new(element_type), Box::new(value_type))
+            }
+            (ComprehensionKind::List, _) => Type::List(Box::new(element_type)),
+            _ => Type::Unknown,
+        })
+    }
+
     fn infer_tuple(&mut self, elts: &[HirExpr]) -> Result<Type> {
         let types: Vec<Type> = elts
             .iter()
@@ -247,12 +403,30 @@ impl TypeInferencer {
         Ok(Type::Tuple(types))
     }
 
-    fn infer_binary_op(&self, op: depyler_core::hir::BinOp, left: &Type, right: &Type) -> Type {
+    pub(crate) fn infer_binary_op(
+        &self,
+        op: depyler_core::hir::BinOp,
+        left: &Type,
+        right: &Type,
+    ) -> Type {
         use depyler_core::hir::BinOp;
 
         match op {
+            // True division always yields a float
+            BinOp::Div
+                if matches!(left, Type::Int | Type::Float)
+                    && matches!(right, Type::Int | Type::Float) =>
+            {
+                Type::Float
+            }
             // Arithmetic operators
-            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::FloorDiv | BinOp::Mod => {
+            BinOp::Add
+            | BinOp::Sub
+            | BinOp::Mul
+            | BinOp::Div
+            | BinOp::FloorDiv
+            | BinOp::Mod
+            | BinOp::Pow => {
                 if matches!(left, Type::Float) || matches!(right, Type::Float) {
                     Type::Float
                 } else if matches!(left, Type::Int) && matches!(right, Type::Int) {
@@ -277,7 +451,6 @@ impl TypeInferencer {
             }
             // Membership operators
             BinOp::In | BinOp::NotIn => Type::Bool,
-            _ => Type::Unknown,
         }
     }
 
@@ -297,6 +470,23 @@ impl TypeInferencer {
         }
     }
 
+    /// Record the types of the names an assignment target binds
+    fn bind_target(&mut self, target: &AssignTarget, ty: Type) {
+        match (target, ty) {
+            (AssignTarget::Symbol(name), ty) => self.env.set_var_type(name.clone(), ty),
+            (AssignTarget::Tuple(targets), Type::Tuple(types)) if targets.len() == types.len() => {
+                for (target, ty) in targets.iter().zip(types) {
+                    self.bind_target(target, ty);
+                }
+            }
+            (target, _) => {
+                for name in target.symbols() {
+                    self.env.set_var_type(name.clone(), Type::Unknown);
+                }
+            }
+        }
+    }
+
     fn get_element_type(&self, container: &Type) -> Type {
         match container {
             Type::List(elem) => (**elem).clone(),
@@ -686,4 +876,63 @@ mod tests {
         let result = inferencer.infer_call("unknown_func", &[]).unwrap();
         assert_eq!(result, Type::Unknown);
     }
+
+    #[test]
+    fn test_infer_method_call() {
+        let mut inferencer = TypeInferencer::new();
+        inferencer.env.set_var_type(
+            "d".to_string(),
+            Type::Dict(Box::new(Type::String), Box::new(Type::Int)),
+        );
+
+        let text = HirExpr::Literal(Literal::String(" hi ".to_string()));
+        let result = inferencer.infer_method_call(&text, "strip", &[]).unwrap();
+        assert_eq!(result, Type::String);
+
+        let result = inferencer.infer_method_call(&text, "split", &[]).unwrap();
+        assert_eq!(result, Type::List(Box::new(Type::String)));
+
+        let dict = HirExpr::Var("d".to_string());
+        let result = inferencer
+            .infer_method_call(
+                &dict,
+                "get",
+                &[HirExpr::Literal(Literal::String("k".to_string()))],
+            )
+            .unwrap();
+        assert_eq!(result, Type::Optional(Box::new(Type::Int)));
+
+        let result = inferencer
+            .infer_method_call(&dict, "frobnicate", &[])
+            .unwrap();
+        assert_eq!(result, Type::Unknown);
+    }
+
+    #[test]
+    fn test_infer_comprehension() {
+        let mut inferencer = TypeInferencer::new();
+        inferencer
+            .env
+            .set_var_type("xs".to_string(), Type::List(Box::new(Type::Int)));
+        let generators = vec![ComprehensionClause {
+            target: "x".to_string(),
+            iter: HirExpr::Var("xs".to_string()),
+            conditions: vec![],
+        }];
+        let element = HirExpr::Var("x".to_string());
+
+        let result = inferencer
+            .infer_comprehension(ComprehensionKind::List, &element, None, &generators)
+            .unwrap();
+        assert_eq!(result, Type::List(Box::new(Type::Int)));
+
+        let value = HirExpr::Literal(Literal::String("v".to_string()));
+        let result = inferencer
+            .infer_comprehension(ComprehensionKind::Dict, &element, Some(&value), &generators)
+            .unwrap();
+        assert_eq!(
+            result,
+            Type::Dict(Box::new(Type::Int), Box::new(Type::String))
+        );
+    }
 }
@@ -21,11 +21,15 @@ pub struct TranspilationAnnotations {
     pub performance_hints: Vec<PerformanceHint>,
     pub fallback_strategy: FallbackStrategy,
     pub bounds_checking: BoundsChecking,
+    pub integer_overflow: IntegerOverflow,
+    /// What `int` maps to; `None` follows the module's annotation
+    pub integer_type: Option<IntegerType>,
     pub optimization_level: OptimizationLevel,
     pub thread_safety: ThreadSafety,
     pub interior_mutability: InteriorMutability,
     pub string_strategy: StringStrategy,
     pub hash_strategy: HashStrategy,
+    pub kwargs_strategy: KwargsStrategy,
     pub panic_behavior: PanicBehavior,
     pub error_strategy: ErrorStrategy,
     pub global_strategy: GlobalStrategy,
@@ -49,11 +53,14 @@ impl Default for TranspilationAnnotations {
             performance_hints: Vec::new(),
             fallback_strategy: FallbackStrategy::Error,
             bounds_checking: BoundsChecking::Explicit,
+            integer_overflow: IntegerOverflow::Native,
+            integer_type: None,
             optimization_level: OptimizationLevel::Standard,
             thread_safety: ThreadSafety::NotRequired,
             interior_mutability: InteriorMutability::None,
             string_strategy: StringStrategy::Conservative,
             hash_strategy: HashStrategy::Standard,
+            kwargs_strategy: KwargsStrategy::HashMap,
             panic_behavior: PanicBehavior::Propagate,
             error_strategy: ErrorStrategy::Panic,
             global_strategy: GlobalStrategy::None,
@@ -158,6 +165,8 @@ pub enum PerformanceHint {
     OptimizeForLatency,
     OptimizeForThroughput,
     PerformanceCritical,
+    /// Emit the function as `async fn`
+    AsyncReady,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -174,6 +183,28 @@ pub enum BoundsChecking {
     Disabled,
 }
 
+/// What integer arithmetic that overflows its Rust type does
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IntegerOverflow {
+    /// Rust's operators: a panic in debug builds, wrapping in release builds
+    Native,
+    Wrapping,
+    /// A panic in every build
+    Checked,
+    /// An `OverflowError` returned as `Err`
+    ReturnError,
+}
+
+/// The Rust type of Python's `int`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntegerType {
+    /// The fixed-width integer type the type mapper is configured with
+    Native,
+    /// `num_bigint::BigInt`, which never overflows, apart from locals
+    /// proven to fit an `i64`
+    BigInt,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum OptimizationLevel {
     Standard,
@@ -209,6 +240,15 @@ pub enum HashStrategy {
     AHash,
 }
 
+/// How a `**kwargs` parameter is passed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum KwargsStrategy {
+    /// A `HashMap` from keyword to value
+    HashMap,
+    /// A generated options struct with one `Option` field per keyword
+    Struct,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum PanicBehavior {
     Propagate,
@@ -351,7 +391,7 @@ pub struct AnnotationExtractor {
 impl Default for AnnotationExtractor {
     fn default() -> Self {
         Self {
-            function_pattern: Regex::new(r"(?m)^def\s+(\w+)\s*\(").unwrap(),
+            function_pattern: Regex::new(r"(?m)^(?:async\s+)?def\s+(\w+)\s*\(").unwrap(),
             class_pattern: Regex::new(r"(?m)^class\s+(\w+)\s*[\(:]").unwrap(),
         }
     }
@@ -397,6 +437,17 @@ impl AnnotationExtractor {
         None
     }
 
+    /// Annotations of the module: those in the comments opening the file,
+    /// before its first statement
+    pub fn extract_module_annotations(&self, source: &str) -> Option<String> {
+        let annotations: Vec<&str> = source
+            .lines()
+            .take_while(|line| line.trim().starts_with('#') || line.trim().is_empty())
+            .filter(|line| line.contains("@depyler:"))
+            .collect();
+        (!annotations.is_empty()).then(|| annotations.join("\n"))
+    }
+
     pub fn extract_class_annotations(&self, source: &str, class_name: &str) -> Option<String> {
         let lines: Vec<&str> = source.lines().collect();
 
@@ -490,6 +541,12 @@ impl AnnotationParser {
                 "bounds_checking" => {
                     annotations.bounds_checking = self.parse_bounds_checking(&value)?;
                 }
+                "integer_overflow" => {
+                    annotations.integer_overflow = self.parse_integer_overflow(&value)?;
+                }
+                "integer_type" => {
+                    annotations.integer_type = Some(self.parse_integer_type(&value)?);
+                }
                 "optimization_level" => {
                     annotations.optimization_level = self.parse_optimization_level(&value)?;
                 }
@@ -522,27 +579,30 @@ impl AnnotationParser {
                         .performance_hints
                         .push(PerformanceHint::UnrollLoops(count));
                 }
-                "optimization_hint" => {
-                    match value.as_str() {
-                        "vectorize" => annotations
-                            .performance_hints
-                            .push(PerformanceHint::Vectorize),
-                        "latency" => annotations
-                            .performance_hints
-                            .push(PerformanceHint::OptimizeForLatency),
-                        "throughput" => annotations
-                            .performance_hints
-                            .push(PerformanceHint::OptimizeForThroughput),
-                        "async_ready" => {} // TODO: Add async support in future
-                        _ => return Err(AnnotationError::InvalidValue { key, value }),
-                    }
-                }
+                "optimization_hint" => match value.as_str() {
+                    "vectorize" => annotations
+                        .performance_hints
+                        .push(PerformanceHint::Vectorize),
+                    "latency" => annotations
+                        .performance_hints
+                        .push(PerformanceHint::OptimizeForLatency),
+                    "throughput" => annotations
+                        .performance_hints
+                        .push(PerformanceHint::OptimizeForThroughput),
+                    "async_ready" => annotations
+                        .performance_hints
+                        .push(PerformanceHint::AsyncReady),
+                    _ => return Err(AnnotationError::InvalidValue { key, value }),
+                },
                 "string_strategy" => {
                     annotations.string_strategy = self.parse_string_strategy(&value)?;
                 }
                 "hash_strategy" => {
                     annotations.hash_strategy = self.parse_hash_strategy(&value)?;
                 }
+                "kwargs_strategy" => {
+                    annotations.kwargs_strategy = self.parse_kwargs_strategy(&value)?;
+                }
                 "panic_behavior" => {
                     annotations.panic_behavior = self.parse_panic_behavior(&value)?;
                 }
@@ -702,6 +762,30 @@ impl AnnotationParser {
         }
     }
 
+    fn parse_integer_overflow(&self, value: &str) -> Result<IntegerOverflow, AnnotationError> {
+        match value {
+            "native" => Ok(IntegerOverflow::Native),
+            "wrapping" => Ok(IntegerOverflow::Wrapping),
+            "checked" => Ok(IntegerOverflow::Checked),
+            "return_error" => Ok(IntegerOverflow::ReturnError),
+            _ => Err(AnnotationError::InvalidValue {
+                key: "integer_overflow".to_string(),
+                value: value.to_string(),
+            }),
+        }
+    }
+
+    fn parse_integer_type(&self, value: &str) -> Result<IntegerType, AnnotationError> {
+        match value {
+            "native" => Ok(IntegerType::Native),
+            "bigint" => Ok(IntegerType::BigInt),
+            _ => Err(AnnotationError::InvalidValue {
+                key: "integer_type".to_string(),
+                value: value.to_string(),
+            }),
+        }
+    }
+
     fn parse_optimization_level(&self, value: &str) -> Result<OptimizationLevel, AnnotationError> {
         match value {
             "standard" => Ok(OptimizationLevel::Standard),
@@ -765,6 +849,17 @@ impl AnnotationParser {
         }
     }
 
+    fn parse_kwargs_strategy(&self, value: &str) -> Result<KwargsStrategy, AnnotationError> {
+        match value {
+            "hashmap" => Ok(KwargsStrategy::HashMap),
+            "struct" => Ok(KwargsStrategy::Struct),
+            _ => Err(AnnotationError::InvalidValue {
+                key: "kwargs_strategy".to_string(),
+                value: value.to_string(),
+            }),
+        }
+    }
+
     fn parse_panic_behavior(&self, value: &str) -> Result<PanicBehavior, AnnotationError> {
         match value {
             "propagate" => Ok(PanicBehavior::Propagate),
@@ -1047,6 +1142,22 @@ def optimized_function():
         );
     }
 
+    #[test]
+    fn test_async_ready_hint() {
+        let parser = AnnotationParser::new();
+        let source = r#"
+# @depyler: optimization_hint = "async_ready"
+def fetch_all():
+    pass
+        "#;
+
+        let annotations = parser.parse_annotations(source).unwrap();
+        assert_eq!(
+            annotations.performance_hints,
+            vec![PerformanceHint::AsyncReady]
+        );
+    }
+
     #[test]
     fn test_string_and_hash_strategies() {
         let parser = AnnotationParser::new();
@@ -1062,6 +1173,74 @@ def string_function():
         assert_eq!(annotations.hash_strategy, HashStrategy::Fnv);
     }
 
+    #[test]
+    fn test_kwargs_strategy() {
+        let parser = AnnotationParser::new();
+        let source = r#"
+# @depyler: kwargs_strategy = "struct"
+def plot(**options):
+    pass
+        "#;
+
+        let annotations = parser.parse_annotations(source).unwrap();
+        assert_eq!(annotations.kwargs_strategy, KwargsStrategy::Struct);
+        assert_eq!(
+            TranspilationAnnotations::default().kwargs_strategy,
+            KwargsStrategy::HashMap
+        );
+        assert!(parser
+            .parse_annotations("# @depyler: kwargs_strategy = \"tuple\"")
+            .is_err());
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let parser = AnnotationParser::new();
+        let source = r#"
+# @depyler: integer_overflow = "return_error"
+def factorial(n: int) -> int:
+    pass
+        "#;
+
+        let annotations = parser.parse_annotations(source).unwrap();
+        assert_eq!(annotations.integer_overflow, IntegerOverflow::ReturnError);
+        assert_eq!(
+            TranspilationAnnotations::default().integer_overflow,
+            IntegerOverflow::Native
+        );
+        assert!(parser
+            .parse_annotations("# @depyler: integer_overflow = \"saturating\"")
+            .is_err());
+    }
+
+    #[test]
+    fn test_integer_type() {
+        let parser = AnnotationParser::new();
+        let source = r#"# @depyler: integer_type = "bigint"
+
+import math
+
+# @depyler: integer_type = "native"
+def f(n: int) -> int:
+    pass
+"#;
+
+        let module = AnnotationExtractor::new()
+            .extract_module_annotations(source)
+            .unwrap();
+        let annotations = parser.parse_annotations(&module).unwrap();
+        assert_eq!(annotations.integer_type, Some(IntegerType::BigInt));
+        let function = AnnotationExtractor::new()
+            .extract_function_annotations(source, "f")
+            .unwrap();
+        let annotations = parser.parse_annotations(&function).unwrap();
+        assert_eq!(annotations.integer_type, Some(IntegerType::Native));
+        assert_eq!(TranspilationAnnotations::default().integer_type, None);
+        assert!(AnnotationExtractor::new()
+            .extract_module_annotations("import math\n# @depyler: integer_type = \"bigint\"")
+            .is_none());
+    }
+
     #[test]
     fn test_error_handling_annotations() {
         let parser = AnnotationParser::new();
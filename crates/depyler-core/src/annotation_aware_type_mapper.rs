@@ -1,8 +1,8 @@
+use crate::borrowing::ParamPassing;
 use crate::hir::Type as PythonType;
+use crate::sets::SetType;
 use crate::type_mapper::{RustType, TypeMapper};
-use depyler_annotations::{
-    OwnershipModel, StringStrategy as AnnotationStringStrategy, TranspilationAnnotations,
-};
+use depyler_annotations::{IntegerType, OwnershipModel, TranspilationAnnotations};
 
 /// An enhanced type mapper that considers annotations when mapping types
 pub struct AnnotationAwareTypeMapper {
@@ -33,33 +33,35 @@ impl AnnotationAwareTypeMapper {
         annotations: &TranspilationAnnotations,
     ) -> RustType {
         match py_type {
-            PythonType::String => self.map_string_type(annotations),
+            PythonType::Int if annotations.integer_type == Some(IntegerType::BigInt) => {
+                RustType::Custom(crate::bigint::BIGINT.to_string())
+            }
+            PythonType::String => RustType::String,
             PythonType::List(inner) => self.map_list_type(inner, annotations),
             PythonType::Dict(key, value) => self.map_dict_type(key, value, annotations),
+            PythonType::Set(inner) => self.map_set_type(inner, annotations),
             PythonType::Optional(inner) => self.map_optional_type(inner, annotations),
             _ => self.base_mapper.map_type(py_type),
         }
     }
 
-    /// Maps string types based on annotations
-    fn map_string_type(&self, annotations: &TranspilationAnnotations) -> RustType {
-        match annotations.string_strategy {
-            AnnotationStringStrategy::AlwaysOwned => RustType::String,
-            AnnotationStringStrategy::ZeroCopy => match annotations.ownership_model {
-                OwnershipModel::Borrowed => RustType::Str {
-                    lifetime: Some("'a".to_string()),
-                },
-                _ => RustType::String, // Can't do zero-copy without borrowing
-            },
-            AnnotationStringStrategy::Conservative => {
-                // Conservative defaults to owned unless explicitly borrowed
-                match annotations.ownership_model {
-                    OwnershipModel::Borrowed => RustType::Str {
-                        lifetime: Some("'a".to_string()),
-                    },
-                    _ => RustType::String,
-                }
+    /// Maps a parameter type, unsized when the parameter is borrowed; only
+    /// parameters are borrowed, under the borrowed ownership model too
+    pub fn map_param_type_with_annotations(
+        &self,
+        py_type: &PythonType,
+        annotations: &TranspilationAnnotations,
+        passing: ParamPassing,
+    ) -> RustType {
+        match (passing, py_type) {
+            (ParamPassing::Borrowed, PythonType::String) => RustType::Str { lifetime: None },
+            (ParamPassing::Borrowed, PythonType::List(inner)) => {
+                RustType::Slice(Box::new(self.map_type_with_annotations(inner, annotations)))
             }
+            (ParamPassing::Cow, PythonType::String) => RustType::Cow {
+                lifetime: "'_".to_string(),
+            },
+            _ => self.map_type_with_annotations(py_type, annotations),
         }
     }
 
@@ -72,11 +74,6 @@ impl AnnotationAwareTypeMapper {
         let inner_rust = self.map_type_with_annotations(inner, annotations);
 
         match annotations.ownership_model {
-            OwnershipModel::Borrowed => RustType::Reference {
-                lifetime: Some("'a".to_string()),
-                mutable: false,
-                inner: Box::new(RustType::Vec(Box::new(inner_rust))),
-            },
             OwnershipModel::Shared => {
                 // For thread-safe shared ownership
                 if annotations.thread_safety == depyler_annotations::ThreadSafety::Required {
@@ -85,7 +82,7 @@ impl AnnotationAwareTypeMapper {
                     RustType::Custom(format!("Rc<Vec<{}>>", inner_rust.to_rust_string()))
                 }
             }
-            OwnershipModel::Owned => RustType::Vec(Box::new(inner_rust)),
+            _ => RustType::Vec(Box::new(inner_rust)),
         }
     }
 
@@ -96,7 +93,7 @@ impl AnnotationAwareTypeMapper {
         value: &PythonType,
         annotations: &TranspilationAnnotations,
     ) -> RustType {
-        let key_rust = self.map_type_with_annotations(key, annotations);
+        let key_rust = self.map_hashable_with_annotations(key, annotations);
         let value_rust = self.map_type_with_annotations(value, annotations);
 
         // Choose hash map implementation based on hash strategy
@@ -112,13 +109,44 @@ impl AnnotationAwareTypeMapper {
             key_rust.to_rust_string(),
             value_rust.to_rust_string()
         ));
+        self.map_ownership(base_type, annotations)
+    }
 
+    /// Maps set types based on annotations, hashed as a dict would be
+    fn map_set_type(&self, inner: &PythonType, annotations: &TranspilationAnnotations) -> RustType {
+        let inner_rust = self.map_hashable_with_annotations(inner, annotations);
+        let base_type = RustType::Custom(format!(
+            "{}<{}>",
+            SetType::new(annotations).name(),
+            inner_rust.to_rust_string()
+        ));
+        self.map_ownership(base_type, annotations)
+    }
+
+    /// Maps the item type of a set or the key type of a dict; a set there
+    /// is a `BTreeSet`, as a `HashSet` is not `Hash`
+    fn map_hashable_with_annotations(
+        &self,
+        py_type: &PythonType,
+        annotations: &TranspilationAnnotations,
+    ) -> RustType {
+        match py_type {
+            PythonType::Set(inner) => RustType::Custom(format!(
+                "BTreeSet<{}>",
+                self.map_hashable_with_annotations(inner, annotations)
+                    .to_rust_string()
+            )),
+            _ => self.map_type_with_annotations(py_type, annotations),
+        }
+    }
+
+    /// Wraps a dict or set type as the ownership model says
+    fn map_ownership(
+        &self,
+        base_type: RustType,
+        annotations: &TranspilationAnnotations,
+    ) -> RustType {
         match annotations.ownership_model {
-            OwnershipModel::Borrowed => RustType::Reference {
-                lifetime: Some("'a".to_string()),
-                mutable: false,
-                inner: Box::new(base_type),
-            },
             OwnershipModel::Shared => {
                 if annotations.thread_safety == depyler_annotations::ThreadSafety::Required {
                     RustType::Custom(format!("Arc<{}>", base_type.to_rust_string()))
@@ -126,7 +154,7 @@ impl AnnotationAwareTypeMapper {
                     RustType::Custom(format!("Rc<{}>", base_type.to_rust_string()))
                 }
             }
-            OwnershipModel::Owned => base_type,
+            _ => base_type,
         }
     }
 
@@ -153,10 +181,13 @@ impl AnnotationAwareTypeMapper {
         &self,
         rust_type: &RustType,
         annotations: &TranspilationAnnotations,
+        passing: ParamPassing,
     ) -> bool {
         match annotations.ownership_model {
-            OwnershipModel::Borrowed => !self.base_mapper.can_copy(rust_type),
-            OwnershipModel::Owned => false,
+            // `&str` is already a reference
+            OwnershipModel::Owned | OwnershipModel::Borrowed => {
+                passing == ParamPassing::Borrowed && !matches!(rust_type, RustType::Str { .. })
+            }
             OwnershipModel::Shared => false, // Shared types are already wrapped
         }
     }
@@ -200,16 +231,18 @@ mod tests {
         let rust_type = mapper.map_type_with_annotations(&PythonType::String, &annotations);
         assert_eq!(rust_type, RustType::String);
 
-        // Test zero copy with borrowing
+        // Test zero copy with borrowing: string values are owned, and only
+        // a borrowed parameter is a `&str`
         annotations.string_strategy = StringStrategy::ZeroCopy;
         annotations.ownership_model = OwnershipModel::Borrowed;
         let rust_type = mapper.map_type_with_annotations(&PythonType::String, &annotations);
-        assert_eq!(
-            rust_type,
-            RustType::Str {
-                lifetime: Some("'a".to_string())
-            }
+        assert_eq!(rust_type, RustType::String);
+        let rust_type = mapper.map_param_type_with_annotations(
+            &PythonType::String,
+            &annotations,
+            ParamPassing::Borrowed,
         );
+        assert_eq!(rust_type, RustType::Str { lifetime: None });
 
         // Test zero copy without borrowing falls back to owned
         annotations.ownership_model = OwnershipModel::Owned;
@@ -231,24 +264,27 @@ mod tests {
             RustType::Vec(Box::new(RustType::Primitive(PrimitiveType::I32)))
         );
 
-        // Test borrowed
+        // Test borrowed: a list value is owned, a borrowed parameter a slice
         annotations.ownership_model = OwnershipModel::Borrowed;
         let rust_type = mapper.map_type_with_annotations(&list_type, &annotations);
-        match rust_type {
-            RustType::Reference {
-                lifetime,
-                mutable,
-                inner,
-            } => {
-                assert_eq!(lifetime, Some("'a".to_string()));
-                assert!(!mutable);
-                assert_eq!(
-                    *inner,
-                    RustType::Vec(Box::new(RustType::Primitive(PrimitiveType::I32)))
-                );
-            }
-            _ => panic!("Expected reference type"),
-        }
+        assert_eq!(
+            rust_type,
+            RustType::Vec(Box::new(RustType::Primitive(PrimitiveType::I32)))
+        );
+        let rust_type = mapper.map_param_type_with_annotations(
+            &list_type,
+            &annotations,
+            ParamPassing::Borrowed,
+        );
+        assert_eq!(
+            rust_type,
+            RustType::Slice(Box::new(RustType::Primitive(PrimitiveType::I32)))
+        );
+        assert!(mapper.needs_reference_with_annotations(
+            &rust_type,
+            &annotations,
+            ParamPassing::Borrowed
+        ));
 
         // Test shared with thread safety
         annotations.ownership_model = OwnershipModel::Shared;
@@ -257,6 +293,38 @@ mod tests {
         assert_eq!(rust_type, RustType::Custom("Arc<Vec<i32>>".to_string()));
     }
 
+    #[test]
+    fn test_set_mapping_with_hash_strategy() {
+        let mapper = AnnotationAwareTypeMapper::new();
+        let set_type = PythonType::Set(Box::new(PythonType::String));
+
+        let mut annotations = create_test_annotations();
+        let rust_type = mapper.map_type_with_annotations(&set_type, &annotations);
+        assert_eq!(rust_type, RustType::Custom("HashSet<String>".to_string()));
+
+        annotations.hash_strategy = HashStrategy::Fnv;
+        let rust_type = mapper.map_type_with_annotations(&set_type, &annotations);
+        assert_eq!(
+            rust_type,
+            RustType::Custom("FnvHashSet<String>".to_string())
+        );
+
+        // Sets of sets, and dicts keyed by them, hold `BTreeSet`s
+        annotations.hash_strategy = HashStrategy::AHash;
+        let nested = PythonType::Set(Box::new(set_type.clone()));
+        let rust_type = mapper.map_type_with_annotations(&nested, &annotations);
+        assert_eq!(
+            rust_type,
+            RustType::Custom("AHashSet<BTreeSet<String>>".to_string())
+        );
+        let dict_type = PythonType::Dict(Box::new(set_type), Box::new(PythonType::Int));
+        let rust_type = mapper.map_type_with_annotations(&dict_type, &annotations);
+        assert_eq!(
+            rust_type,
+            RustType::Custom("AHashMap<BTreeSet<String>, i32>".to_string())
+        );
+    }
+
     #[test]
     fn test_dict_mapping_with_hash_strategy() {
         let mapper = AnnotationAwareTypeMapper::new();
@@ -310,6 +378,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_borrowed_param_mapping() {
+        let mapper = AnnotationAwareTypeMapper::new();
+        let annotations = create_test_annotations();
+
+        let str_type = mapper.map_param_type_with_annotations(
+            &PythonType::String,
+            &annotations,
+            ParamPassing::Borrowed,
+        );
+        assert_eq!(str_type, RustType::Str { lifetime: None });
+        assert!(!mapper.needs_reference_with_annotations(
+            &str_type,
+            &annotations,
+            ParamPassing::Borrowed
+        ));
+
+        let list_type = mapper.map_param_type_with_annotations(
+            &PythonType::List(Box::new(PythonType::Int)),
+            &annotations,
+            ParamPassing::Borrowed,
+        );
+        assert_eq!(
+            list_type,
+            RustType::Slice(Box::new(RustType::Primitive(PrimitiveType::I32)))
+        );
+        assert!(mapper.needs_reference_with_annotations(
+            &list_type,
+            &annotations,
+            ParamPassing::Borrowed
+        ));
+
+        let cow_type = mapper.map_param_type_with_annotations(
+            &PythonType::String,
+            &annotations,
+            ParamPassing::Cow,
+        );
+        assert_eq!(
+            cow_type,
+            RustType::Cow {
+                lifetime: "'_".to_string()
+            }
+        );
+        assert!(!mapper.needs_reference_with_annotations(
+            &cow_type,
+            &annotations,
+            ParamPassing::Cow
+        ));
+    }
+
     #[test]
     fn test_return_type_mapping() {
         let mapper = AnnotationAwareTypeMapper::new();
@@ -329,5 +447,29 @@ mod tests {
                 Box::new(RustType::Custom("Error".to_string())),
             )
         );
+
+        // Returned strings are owned even when borrowing is asked for
+        annotations.string_strategy = StringStrategy::ZeroCopy;
+        annotations.ownership_model = OwnershipModel::Borrowed;
+        let rust_type = mapper.map_return_type_with_annotations(&PythonType::String, &annotations);
+        assert_eq!(rust_type, RustType::String);
+    }
+
+    #[test]
+    fn test_bigint_mapping() {
+        let mapper = AnnotationAwareTypeMapper::new();
+        let mut annotations = create_test_annotations();
+        annotations.integer_type = Some(IntegerType::BigInt);
+        let list_type = PythonType::List(Box::new(PythonType::Int));
+        assert_eq!(
+            mapper.map_type_with_annotations(&list_type, &annotations),
+            RustType::Vec(Box::new(RustType::Custom("BigInt".to_string())))
+        );
+
+        annotations.integer_type = Some(IntegerType::Native);
+        assert_eq!(
+            mapper.map_return_type_with_annotations(&PythonType::Int, &annotations),
+            RustType::Primitive(PrimitiveType::I32)
+        );
     }
 }
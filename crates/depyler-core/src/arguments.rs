@@ -0,0 +1,850 @@
+//! Python call arguments bound to parameters at compile time.
+//!
+//! Rust has no keyword arguments, default values or variadic parameters, so
+//! every call to a function, constructor or method of the module is rewritten
+//! to pass each parameter positionally: keywords move into place, omitted
+//! arguments take their default value, surplus positional arguments are
+//! packed into the `*args` list and surplus keywords into the `**kwargs` dict
+//! or options struct. Calls to anything else keep their keywords, which
+//! codegen rejects rather than dropping.
+
+use crate::error::{ErrorKind, SpanExt};
+use crate::hir::*;
+use crate::transpile_bail;
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Parameters a call is resolved against
+#[derive(Debug, Clone)]
+struct Signature {
+    params: Vec<(Symbol, Type)>,
+    spec: ParamSpec,
+}
+
+impl Signature {
+    fn of_function(params: &[(Symbol, Type)], spec: &ParamSpec) -> Self {
+        Signature {
+            params: params.to_vec(),
+            spec: spec.clone(),
+        }
+    }
+
+    /// The options struct generated for `**kwargs`, if any
+    fn kwargs_struct(&self) -> Option<&str> {
+        let (name, _) = self.spec.var_keyword.as_ref()?;
+        self.params.iter().find_map(|(param, ty)| match ty {
+            Type::Custom(s) if param == name => Some(s.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// `plot` becomes `PlotKwargs`, and `draw` on `Canvas` becomes `CanvasDrawKwargs`
+pub fn kwargs_struct_name(class: Option<&str>, function: &str) -> String {
+    let mut name = class.map(pascal_case).unwrap_or_default();
+    name.push_str(&pascal_case(function));
+    name.push_str("Kwargs");
+    name
+}
+
+/// `count_up` becomes `CountUp`
+pub fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Resolve the arguments of every call to a known function, constructor or
+/// method, and record the keywords each `**kwargs` options struct needs
+pub fn resolve_call_arguments(module: &mut HirModule) -> Result<()> {
+    let mut resolver = Resolver::for_module(module);
+
+    for func in &mut module.functions {
+        resolver.enter(None, &func.params, &func.param_spec);
+        resolver.body(&mut func.body)?;
+        resolver.leave(func.name.clone());
+    }
+    for class in &mut module.classes {
+        for method in &mut class.methods {
+            resolver.enter(Some(&class.name), &method.params, &method.param_spec);
+            resolver.body(&mut method.body)?;
+            resolver.leave(format!("{}.{}", class.name, method.name));
+        }
+    }
+
+    let mut fields = resolver.kwargs_fields;
+    for func in &mut module.functions {
+        if let Some(keys) = fields.remove(&func.name) {
+            func.param_spec.kwargs_fields = keys;
+        }
+    }
+    for class in &mut module.classes {
+        for method in &mut class.methods {
+            if let Some(keys) = fields.remove(&format!("{}.{}", class.name, method.name)) {
+                method.param_spec.kwargs_fields = keys;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Resolver {
+    /// Keyed like `exceptions::call_key`: functions by name, methods as
+    /// `Class.method` and constructors as `Class.__init__`
+    signatures: HashMap<String, Signature>,
+    base_classes: HashMap<String, Vec<Symbol>>,
+    /// Declared class of each field, by class
+    field_classes: HashMap<String, HashMap<Symbol, Symbol>>,
+    /// Keywords used with each options struct, keyed like `signatures`
+    kwargs_fields: HashMap<String, Vec<Symbol>>,
+    current_class: Option<String>,
+    /// Module class of each local whose type is known
+    local_classes: HashMap<Symbol, Symbol>,
+    /// The current function's `**kwargs`, when it is an options struct
+    kwargs_param: Option<Symbol>,
+    kwargs_read: Vec<Symbol>,
+}
+
+impl Resolver {
+    fn for_module(module: &HirModule) -> Self {
+        let mut signatures = HashMap::new();
+        for func in &module.functions {
+            signatures.insert(
+                func.name.clone(),
+                Signature::of_function(&func.params, &func.param_spec),
+            );
+        }
+        for class in module.classes.iter().filter(|c| !c.is_enum()) {
+            for method in &class.methods {
+                signatures.insert(
+                    format!("{}.{}", class.name, method.name),
+                    Signature::of_function(&method.params, &method.param_spec),
+                );
+            }
+            // A dataclass without `__init__` is constructed from its fields
+            if class.is_dataclass && class.init_method().is_none() {
+                let fields: Vec<&HirField> =
+                    class.fields.iter().filter(|f| !f.is_class_var).collect();
+                let spec = ParamSpec {
+                    defaults: fields
+                        .iter()
+                        .filter_map(|f| Some((f.name.clone(), f.default_value.clone()?)))
+                        .collect(),
+                    ..ParamSpec::default()
+                };
+                let params = fields
+                    .iter()
+                    .map(|f| (f.name.clone(), f.field_type.clone()))
+                    .collect();
+                signatures.insert(
+                    format!("{}.__init__", class.name),
+                    Signature { params, spec },
+                );
+            }
+        }
+        let field_classes = module
+            .classes
+            .iter()
+            .map(|class| {
+                let fields = class
+                    .fields
+                    .iter()
+                    .filter_map(|f| match &f.field_type {
+                        Type::Custom(c) => Some((f.name.clone(), c.clone())),
+                        _ => None,
+                    })
+                    .collect();
+                (class.name.clone(), fields)
+            })
+            .collect();
+        Resolver {
+            signatures,
+            base_classes: module
+                .classes
+                .iter()
+                .map(|c| (c.name.clone(), c.base_classes.clone()))
+                .collect(),
+            field_classes,
+            kwargs_fields: HashMap::new(),
+            current_class: None,
+            local_classes: HashMap::new(),
+            kwargs_param: None,
+            kwargs_read: Vec::new(),
+        }
+    }
+
+    fn enter(&mut self, class: Option<&str>, params: &[(Symbol, Type)], spec: &ParamSpec) {
+        self.current_class = class.map(str::to_string);
+        self.local_classes = params
+            .iter()
+            .filter_map(|(name, ty)| match ty {
+                Type::Custom(c) => Some((name.clone(), c.clone())),
+                _ => None,
+            })
+            .collect();
+        let signature = Signature::of_function(params, spec);
+        self.kwargs_param = signature
+            .kwargs_struct()
+            .and(spec.var_keyword.as_ref())
+            .map(|(name, _)| name.clone());
+        self.kwargs_read.clear();
+    }
+
+    /// Record the keywords the finished function reads from its options struct
+    fn leave(&mut self, key: String) {
+        if self.kwargs_param.take().is_some() {
+            let fields = self.kwargs_fields.entry(key).or_default();
+            for name in self.kwargs_read.drain(..) {
+                push_unique(fields, name);
+            }
+        }
+    }
+
+    fn body(&mut self, body: &mut [Spanned<HirStmt>]) -> Result<()> {
+        body.iter_mut().try_for_each(|stmt| {
+            let span = stmt.span;
+            self.stmt(stmt).at(span)
+        })
+    }
+
+    fn stmt(&mut self, stmt: &mut HirStmt) -> Result<()> {
+        match stmt {
+            HirStmt::Assign { target, value } => {
+                self.target(target)?;
+                self.expr(value)?;
+                if let AssignTarget::Symbol(name) = target {
+                    match self.constructed_class(value) {
+                        Some(class) => self.local_classes.insert(name.clone(), class),
+                        None => self.local_classes.remove(name),
+                    };
+                }
+                Ok(())
+            }
+            HirStmt::Return(value) | HirStmt::Yield(value) => {
+                value.iter_mut().try_for_each(|v| self.expr(v))
+            }
+            HirStmt::Expr(expr) | HirStmt::YieldFrom(expr) => self.expr(expr),
+            HirStmt::Raise { exception } => exception.iter_mut().try_for_each(|e| self.expr(e)),
+            HirStmt::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                self.expr(condition)?;
+                self.body(then_body)?;
+                else_body.iter_mut().try_for_each(|b| self.body(b))
+            }
+            HirStmt::While { condition, body } => {
+                self.expr(condition)?;
+                self.body(body)
+            }
+            HirStmt::For { target, iter, body } => {
+                self.target(target)?;
+                self.expr(iter)?;
+                self.body(body)
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                self.body(body)?;
+                for handler in handlers {
+                    self.body(&mut handler.body)?;
+                }
+                self.body(orelse)?;
+                self.body(finalbody)
+            }
+            HirStmt::With { items, body } => {
+                for item in items {
+                    self.expr(&mut item.context_expr)?;
+                }
+                self.body(body)
+            }
+            HirStmt::Match { subject, cases } => {
+                self.expr(subject)?;
+                for case in cases {
+                    case.guard.iter_mut().try_for_each(|g| self.expr(g))?;
+                    self.body(&mut case.body)?;
+                }
+                Ok(())
+            }
+            HirStmt::FunctionDef { func, .. } => self.body(&mut func.body),
+        }
+    }
+
+    fn target(&mut self, target: &mut AssignTarget) -> Result<()> {
+        match target {
+            AssignTarget::Symbol(_) => Ok(()),
+            AssignTarget::Attribute { value, .. } => self.expr(value),
+            AssignTarget::Index { base, index } => {
+                self.expr(base)?;
+                self.expr(index)
+            }
+            AssignTarget::Tuple(targets) => targets.iter_mut().try_for_each(|t| self.target(t)),
+        }
+    }
+
+    fn expr(&mut self, expr: &mut HirExpr) -> Result<()> {
+        self.record_kwargs_read(expr);
+        match expr {
+            HirExpr::Literal(_) | HirExpr::Var(_) => {}
+            HirExpr::Binary { left, right, .. } => {
+                self.expr(left)?;
+                self.expr(right)?;
+            }
+            HirExpr::Unary { operand, .. } => self.expr(operand)?,
+            HirExpr::Call { args, kwargs, .. } => {
+                args.iter_mut().try_for_each(|a| self.expr(a))?;
+                kwargs.iter_mut().try_for_each(|(_, v)| self.expr(v))?;
+            }
+            HirExpr::MethodCall {
+                receiver,
+                args,
+                kwargs,
+                ..
+            } => {
+                self.expr(receiver)?;
+                args.iter_mut().try_for_each(|a| self.expr(a))?;
+                kwargs.iter_mut().try_for_each(|(_, v)| self.expr(v))?;
+            }
+            HirExpr::Index { base, index } => {
+                self.expr(base)?;
+                self.expr(index)?;
+            }
+            HirExpr::Slice {
+                base,
+                start,
+                stop,
+                step,
+            } => {
+                self.expr(base)?;
+                for bound in [start, stop, step].into_iter().flatten() {
+                    self.expr(bound)?;
+                }
+            }
+            HirExpr::Attribute { value, .. }
+            | HirExpr::Await { value }
+            | HirExpr::Borrow { expr: value, .. }
+            | HirExpr::Lambda { body: value, .. } => self.expr(value)?,
+            HirExpr::List(elts)
+            | HirExpr::Set(elts)
+            | HirExpr::Tuple(elts)
+            | HirExpr::FString { values: elts, .. } => {
+                elts.iter_mut().try_for_each(|e| self.expr(e))?;
+            }
+            HirExpr::Dict(items) => {
+                for (key, value) in items {
+                    self.expr(key)?;
+                    self.expr(value)?;
+                }
+            }
+            HirExpr::Comprehension {
+                element,
+                value,
+                generators,
+                ..
+            } => {
+                self.expr(element)?;
+                value.iter_mut().try_for_each(|v| self.expr(v))?;
+                for clause in generators {
+                    self.expr(&mut clause.iter)?;
+                    clause
+                        .conditions
+                        .iter_mut()
+                        .try_for_each(|c| self.expr(c))?;
+                }
+            }
+        }
+        self.resolve(expr)
+    }
+
+    /// Keys read through `kwargs["key"]`, `kwargs.get("key")` or `"key" in kwargs`
+    fn record_kwargs_read(&mut self, expr: &HirExpr) {
+        let Some(param) = &self.kwargs_param else {
+            return;
+        };
+        let is_param = |e: &HirExpr| matches!(e, HirExpr::Var(v) if v == param);
+        let key = match expr {
+            HirExpr::Index { base, index } if is_param(base) => index.as_ref(),
+            HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                ..
+            } if is_param(receiver) && method == "get" && !args.is_empty() => &args[0],
+            HirExpr::Binary {
+                op: BinOp::In | BinOp::NotIn,
+                left,
+                right,
+            } if is_param(right) => left.as_ref(),
+            _ => return,
+        };
+        if let HirExpr::Literal(Literal::String(key)) = key {
+            push_unique(&mut self.kwargs_read, key.clone());
+        }
+    }
+
+    fn resolve(&mut self, expr: &mut HirExpr) -> Result<()> {
+        let Some(key) = self.callee_key(expr) else {
+            return Ok(());
+        };
+        let Some(signature) = self.signatures.get(&key).cloned() else {
+            return Ok(());
+        };
+        let (callee, args, kwargs) = match expr {
+            HirExpr::Call { func, args, kwargs } => (func.clone(), args, kwargs),
+            HirExpr::MethodCall {
+                method,
+                args,
+                kwargs,
+                ..
+            } => (method.clone(), args, kwargs),
+            _ => return Ok(()),
+        };
+        let (bound, extra_keywords) = bind(
+            &callee,
+            &signature,
+            std::mem::take(args),
+            std::mem::take(kwargs),
+        )?;
+        let packed_keywords = match signature.kwargs_struct() {
+            Some(struct_name) => {
+                let fields = self.kwargs_fields.entry(key).or_default();
+                for (name, _) in &extra_keywords {
+                    push_unique(fields, name.clone());
+                }
+                HirExpr::Call {
+                    func: struct_name.to_string(),
+                    args: vec![],
+                    kwargs: extra_keywords,
+                }
+            }
+            None => HirExpr::Dict(
+                extra_keywords
+                    .into_iter()
+                    .map(|(name, value)| (HirExpr::Literal(Literal::String(name)), value))
+                    .collect(),
+            ),
+        };
+        let mut packed_keywords = Some(packed_keywords);
+        *args = bound
+            .into_iter()
+            .map(|b| match b {
+                Bound::Value(value) => value,
+                Bound::VarKeyword => packed_keywords.take().unwrap_or(HirExpr::Dict(vec![])),
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Signature key of the callee, when it is defined in the module
+    fn callee_key(&self, expr: &HirExpr) -> Option<String> {
+        match expr {
+            HirExpr::Call { func, .. } if func == "cls" => {
+                Some(format!("{}.__init__", self.current_class.as_ref()?))
+            }
+            HirExpr::Call { func, .. } if self.signatures.contains_key(func) => Some(func.clone()),
+            HirExpr::Call { func, .. } => {
+                let key = format!("{func}.__init__");
+                self.signatures.contains_key(&key).then_some(key)
+            }
+            HirExpr::MethodCall {
+                receiver,
+                method,
+                kwargs,
+                ..
+            } => {
+                let class = match receiver.as_ref() {
+                    HirExpr::Var(v) if v == "self" || v == "cls" => self.current_class.clone(),
+                    HirExpr::Var(v) if self.base_classes.contains_key(v) => Some(v.clone()),
+                    HirExpr::Var(v) => self.local_classes.get(v).cloned(),
+                    HirExpr::Attribute { value, attr } if is_self(value) => self
+                        .current_class
+                        .as_ref()
+                        .and_then(|c| self.field_classes.get(c)?.get(attr))
+                        .cloned(),
+                    _ => None,
+                };
+                match class {
+                    Some(class) => self.method_key(&class, method),
+                    // With keywords the call cannot be a builtin method, so a
+                    // method name defined by one class only identifies it
+                    None if !kwargs.is_empty() => self.unique_method_key(method),
+                    None => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Look `method` up on `class` and then its base classes
+    fn method_key(&self, class: &str, method: &str) -> Option<String> {
+        let key = format!("{class}.{method}");
+        if self.signatures.contains_key(&key) {
+            return Some(key);
+        }
+        self.base_classes
+            .get(class)?
+            .iter()
+            .find_map(|base| self.method_key(base, method))
+    }
+
+    fn unique_method_key(&self, method: &str) -> Option<String> {
+        let suffix = format!(".{method}");
+        let mut keys = self.signatures.keys().filter(|k| k.ends_with(&suffix));
+        let key = keys.next()?;
+        keys.next().is_none().then(|| key.clone())
+    }
+
+    /// The module class a value is an instance of, when it is a constructor call
+    fn constructed_class(&self, value: &HirExpr) -> Option<Symbol> {
+        match value {
+            HirExpr::Call { func, .. } if self.base_classes.contains_key(func) => {
+                Some(func.clone())
+            }
+            HirExpr::Call { func, .. } if func == "cls" => self.current_class.clone(),
+            _ => None,
+        }
+    }
+}
+
+/// Keyword arguments in call order
+type Keywords = Vec<(Symbol, HirExpr)>;
+
+/// An argument bound to a parameter, or the slot `*args` and `**kwargs` are
+/// packed into once binding finishes
+enum Bound {
+    Value(HirExpr),
+    VarKeyword,
+}
+
+/// Bind call arguments to the parameters of `signature`, in parameter order.
+///
+/// Returns the bound parameters and the keywords left for `**kwargs`.
+fn bind(
+    callee: &str,
+    signature: &Signature,
+    args: Vec<HirExpr>,
+    kwargs: Keywords,
+) -> Result<(Vec<Bound>, Keywords)> {
+    let spec = &signature.spec;
+    let positional: Vec<&Symbol> = signature
+        .params
+        .iter()
+        .map(|(name, _)| name)
+        .filter(|name| spec.is_positional(name))
+        .collect();
+
+    if args.len() > positional.len() && spec.var_positional.is_none() {
+        transpile_bail!(ErrorKind::CodeGenerationError(format!(
+            "{}() takes {} positional arguments but {} were given",
+            callee,
+            positional.len(),
+            args.len()
+        )));
+    }
+    let mut slots: HashMap<&str, HirExpr> = HashMap::new();
+    let mut surplus = Vec::new();
+    for (i, arg) in args.into_iter().enumerate() {
+        match positional.get(i) {
+            Some(name) => {
+                slots.insert(name, arg);
+            }
+            None => surplus.push(arg),
+        }
+    }
+
+    let mut extra_keywords = Vec::new();
+    for (name, value) in kwargs {
+        // Positional-only parameters cannot be named, so their names go to `**kwargs`
+        let param = signature.params.iter().map(|(p, _)| p).find(|p| {
+            **p == name
+                && !spec.positional_only.contains(p)
+                && (spec.is_positional(p) || spec.keyword_only.contains(p))
+        });
+        if let Some(param) = param {
+            if slots.insert(param, value).is_some() {
+                transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                    "{}() got multiple values for argument '{}'",
+                    callee, name
+                )));
+            }
+        } else if spec.var_keyword.is_some() {
+            if extra_keywords.iter().any(|(k, _)| k == &name) {
+                transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                    "{}() got multiple values for keyword argument '{}'",
+                    callee, name
+                )));
+            }
+            extra_keywords.push((name, value));
+        } else {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "{}() got an unexpected keyword argument '{}'",
+                callee, name
+            )));
+        }
+    }
+
+    let mut surplus = Some(surplus);
+    let bound = signature
+        .params
+        .iter()
+        .map(|(name, _)| {
+            if spec.var_positional.as_ref() == Some(name) {
+                return Ok(Bound::Value(HirExpr::List(
+                    surplus.take().unwrap_or_default(),
+                )));
+            }
+            if spec.var_keyword.as_ref().map(|(k, _)| k) == Some(name) {
+                return Ok(Bound::VarKeyword);
+            }
+            match slots.remove(name.as_str()) {
+                Some(value) => Ok(Bound::Value(value)),
+                None => match spec.default_value(name) {
+                    Some(default) => Ok(Bound::Value(default.clone())),
+                    None => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                        "{}() missing required argument '{}'",
+                        callee, name
+                    ))),
+                },
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((bound, extra_keywords))
+}
+
+fn is_self(expr: &HirExpr) -> bool {
+    matches!(expr, HirExpr::Var(v) if v == "self")
+}
+
+fn push_unique(names: &mut Vec<Symbol>, name: Symbol) {
+    if !names.contains(&name) {
+        names.push(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::TranspileError;
+    use depyler_annotations::TranspilationAnnotations;
+
+    fn int(n: i64) -> HirExpr {
+        HirExpr::Literal(Literal::Int(n))
+    }
+
+    fn string(s: &str) -> HirExpr {
+        HirExpr::Literal(Literal::String(s.to_string()))
+    }
+
+    fn call(func: &str, args: Vec<HirExpr>, kwargs: Vec<(&str, HirExpr)>) -> HirExpr {
+        HirExpr::Call {
+            func: func.to_string(),
+            args,
+            kwargs: kwargs
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        }
+    }
+
+    fn function(
+        name: &str,
+        params: Vec<(&str, Type)>,
+        param_spec: ParamSpec,
+        body: Vec<Spanned<HirStmt>>,
+    ) -> HirFunction {
+        HirFunction {
+            name: name.to_string(),
+            params: params
+                .into_iter()
+                .map(|(name, ty)| (name.to_string(), ty))
+                .collect(),
+            param_spec,
+            ret_type: Type::Unknown,
+            body,
+            properties: FunctionProperties::default(),
+            annotations: TranspilationAnnotations::default(),
+            docstring: None,
+            is_async: false,
+            span: Span::default(),
+        }
+    }
+
+    /// `def scale(x, factor=2, *rest, offset=0, **extra)`
+    fn scale(kwargs_type: Type) -> HirFunction {
+        function(
+            "scale",
+            vec![
+                ("x", Type::Int),
+                ("factor", Type::Int),
+                ("rest", Type::List(Box::new(Type::Int))),
+                ("offset", Type::Int),
+                ("extra", kwargs_type),
+            ],
+            ParamSpec {
+                defaults: vec![
+                    ("factor".to_string(), int(2)),
+                    ("offset".to_string(), int(0)),
+                ],
+                keyword_only: vec!["offset".to_string()],
+                var_positional: Some("rest".to_string()),
+                var_keyword: Some(("extra".to_string(), Type::Int)),
+                ..ParamSpec::default()
+            },
+            vec![],
+        )
+    }
+
+    fn resolve(callee: HirFunction, call: HirExpr) -> Result<(HirExpr, HirModule)> {
+        let caller = function(
+            "caller",
+            vec![],
+            ParamSpec::default(),
+            vec![HirStmt::Expr(call).into()],
+        );
+        let mut module = HirModule {
+            functions: vec![callee, caller],
+            classes: vec![],
+            imports: vec![],
+            skipped: vec![],
+        };
+        resolve_call_arguments(&mut module)?;
+        let HirStmt::Expr(resolved) = module.functions[1].body[0].clone().node else {
+            unreachable!()
+        };
+        Ok((resolved, module))
+    }
+
+    #[test]
+    fn test_keywords_defaults_and_varargs() {
+        let dict = Type::Dict(Box::new(Type::String), Box::new(Type::Int));
+        let (resolved, _) = resolve(
+            scale(dict.clone()),
+            call(
+                "scale",
+                vec![int(1), int(3), int(4), int(5)],
+                vec![("offset", int(7)), ("bias", int(9))],
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            resolved,
+            call(
+                "scale",
+                vec![
+                    int(1),
+                    int(3),
+                    HirExpr::List(vec![int(4), int(5)]),
+                    int(7),
+                    HirExpr::Dict(vec![(string("bias"), int(9))]),
+                ],
+                vec![],
+            )
+        );
+
+        let (resolved, _) = resolve(
+            scale(dict.clone()),
+            call("scale", vec![], vec![("x", int(1))]),
+        )
+        .unwrap();
+        assert_eq!(
+            resolved,
+            call(
+                "scale",
+                vec![
+                    int(1),
+                    int(2),
+                    HirExpr::List(vec![]),
+                    int(0),
+                    HirExpr::Dict(vec![])
+                ],
+                vec![],
+            )
+        );
+
+        // Calls to anything outside the module keep their keywords
+        let (resolved, _) = resolve(
+            scale(dict),
+            call("sorted", vec![int(1)], vec![("reverse", int(1))]),
+        )
+        .unwrap();
+        assert_eq!(
+            resolved,
+            call("sorted", vec![int(1)], vec![("reverse", int(1))])
+        );
+    }
+
+    #[test]
+    fn test_binding_errors() {
+        let single = || {
+            function(
+                "single",
+                vec![("x", Type::Int)],
+                ParamSpec::default(),
+                vec![],
+            )
+        };
+        let error = |expr| {
+            let err = resolve(single(), expr).unwrap_err();
+            match err.downcast::<TranspileError>().unwrap().kind {
+                ErrorKind::CodeGenerationError(msg) => msg,
+                kind => panic!("unexpected error kind {kind:?}"),
+            }
+        };
+        assert_eq!(
+            error(call("single", vec![int(1), int(2)], vec![])),
+            "single() takes 1 positional arguments but 2 were given"
+        );
+        assert_eq!(
+            error(call("single", vec![int(1)], vec![("x", int(2))])),
+            "single() got multiple values for argument 'x'"
+        );
+        assert_eq!(
+            error(call("single", vec![int(1)], vec![("y", int(2))])),
+            "single() got an unexpected keyword argument 'y'"
+        );
+        assert_eq!(
+            error(call("single", vec![], vec![])),
+            "single() missing required argument 'x'"
+        );
+    }
+
+    #[test]
+    fn test_kwargs_struct_fields() {
+        // `extra["weight"]` in the body and `bias=` at the call site
+        let mut callee = scale(Type::Custom("ScaleKwargs".to_string()));
+        callee.body = vec![HirStmt::Return(Some(HirExpr::Index {
+            base: Box::new(HirExpr::Var("extra".to_string())),
+            index: Box::new(string("weight")),
+        }))
+        .into()];
+        let (resolved, module) =
+            resolve(callee, call("scale", vec![int(1)], vec![("bias", int(9))])).unwrap();
+        let HirExpr::Call { args, .. } = resolved else {
+            unreachable!()
+        };
+        assert_eq!(args[4], call("ScaleKwargs", vec![], vec![("bias", int(9))]));
+        assert_eq!(
+            module.functions[0].param_spec.kwargs_fields,
+            vec!["weight".to_string(), "bias".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_kwargs_struct_name() {
+        assert_eq!(kwargs_struct_name(None, "plot"), "PlotKwargs");
+        assert_eq!(
+            kwargs_struct_name(Some("Canvas"), "draw_line"),
+            "CanvasDrawLineKwargs"
+        );
+    }
+}
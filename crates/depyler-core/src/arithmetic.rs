@@ -0,0 +1,319 @@
+//! Lowering of Python arithmetic on ints and floats
+//!
+//! Python's `//` and `%` round toward negative infinity where Rust's truncate
+//! toward zero, `/` always yields a float, and ints never overflow. What an
+//! int operation overflowing its Rust type does is chosen per function by the
+//! `integer_overflow` annotation, see [`Overflow`].
+
+use crate::builtins;
+use crate::hir::{BinOp, HirExpr, Literal, UnaryOp};
+use crate::indexing;
+use depyler_annotations::{IntegerOverflow, TranspilationAnnotations};
+use quote::quote;
+use syn::parse_quote;
+
+/// What int arithmetic overflowing its Rust type does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Rust's operators; `integer_overflow = "native"`
+    #[default]
+    Native,
+    /// `wrapping_*` methods; `integer_overflow = "wrapping"`
+    Wrapping,
+    /// `checked_*` methods, panicking on `None`; `integer_overflow = "checked"`
+    Panic,
+    /// `checked_*` methods, raising `OverflowError` on `None` as a `raise`
+    /// statement would; `integer_overflow = "return_error"`
+    Raise,
+}
+
+impl Overflow {
+    pub fn new(annotations: &TranspilationAnnotations) -> Self {
+        match annotations.integer_overflow {
+            IntegerOverflow::Native => Overflow::Native,
+            IntegerOverflow::Wrapping => Overflow::Wrapping,
+            IntegerOverflow::Checked => Overflow::Panic,
+            IntegerOverflow::ReturnError => Overflow::Raise,
+        }
+    }
+
+    /// The method computing `op` as this policy asks, `None` for Rust's operator
+    pub fn method(self, op: BinOp) -> Option<&'static str> {
+        let wrapping = self == Overflow::Wrapping;
+        Some(match op {
+            _ if self == Overflow::Native => return None,
+            BinOp::Add if wrapping => "wrapping_add",
+            BinOp::Sub if wrapping => "wrapping_sub",
+            BinOp::Mul if wrapping => "wrapping_mul",
+            BinOp::Pow if wrapping => "wrapping_pow",
+            BinOp::Add => "checked_add",
+            BinOp::Sub => "checked_sub",
+            BinOp::Mul => "checked_mul",
+            BinOp::Pow => "checked_pow",
+            _ => return None,
+        })
+    }
+
+    /// Whether the method returns an `Option` to unwrap
+    pub fn is_checked(self) -> bool {
+        matches!(self, Overflow::Panic | Overflow::Raise)
+    }
+}
+
+/// Whether `expr` may be int arithmetic that overflows, and so raises
+/// `OverflowError` under [`Overflow::Raise`]. Only literal operands are
+/// known not to be ints.
+pub fn may_overflow(expr: &HirExpr) -> bool {
+    let int_operand = |e: &HirExpr| {
+        !matches!(
+            e,
+            HirExpr::Literal(Literal::Float(_) | Literal::String(_))
+                | HirExpr::List(_)
+                | HirExpr::FString { .. }
+        )
+    };
+    match expr {
+        HirExpr::Binary {
+            op: BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Pow,
+            left,
+            right,
+        } => int_operand(left) && int_operand(right),
+        HirExpr::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => !matches!(operand.as_ref(), HirExpr::Literal(_)),
+        _ => false,
+    }
+}
+
+/// Whether `expr` is a number literal other than zero, which a division
+/// by cannot raise `ZeroDivisionError`
+pub fn is_nonzero_literal(expr: &HirExpr) -> bool {
+    let literal = match expr {
+        HirExpr::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => operand.as_ref(),
+        _ => expr,
+    };
+    match literal {
+        HirExpr::Literal(Literal::Int(n)) => *n != 0,
+        HirExpr::Literal(Literal::Float(f)) => *f != 0.0,
+        _ => false,
+    }
+}
+
+/// Functions generated next to the code calling them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Helper {
+    /// `//` of ints
+    FloorDiv,
+    /// `%` of ints
+    FloorMod,
+    /// `%` of floats
+    FloatMod,
+    /// `//` of `BigInt`s
+    BigFloorDiv,
+    /// `%` of `BigInt`s
+    BigFloorMod,
+}
+
+impl Helper {
+    pub fn name(self) -> syn::Ident {
+        let name = match self {
+            Helper::FloorDiv => "py_floor_div",
+            Helper::FloorMod => "py_mod",
+            Helper::FloatMod => "py_fmod",
+            Helper::BigFloorDiv => "py_floor_div_big",
+            Helper::BigFloorMod => "py_mod_big",
+        };
+        syn::Ident::new(name, proc_macro2::Span::call_site())
+    }
+
+    /// The definition of the helper, for fixed-width ints of type `int`
+    pub fn tokens(self, int: &syn::Type) -> proc_macro2::TokenStream {
+        let name = self.name();
+        match self {
+            Helper::FloorDiv => quote! {
+                #[doc = " Python's `//`: the quotient rounded toward negative infinity"]
+                fn #name(a: #int, b: #int) -> #int {
+                    let q = a / b;
+                    if a % b != 0 && (a < 0) != (b < 0) { q - 1 } else { q }
+                }
+            },
+            Helper::FloorMod => quote! {
+                #[doc = " Python's `%`: the remainder with the sign of the divisor"]
+                fn #name(a: #int, b: #int) -> #int {
+                    let r = a % b;
+                    if r != 0 && (r < 0) != (b < 0) { r + b } else { r }
+                }
+            },
+            Helper::FloatMod => quote! {
+                #[doc = " Python's `%` of floats: the remainder with the sign of the divisor"]
+                fn #name(a: f64, b: f64) -> f64 {
+                    let r = a % b;
+                    if r != 0.0 && (r < 0.0) != (b < 0.0) { r + b } else { r }
+                }
+            },
+            Helper::BigFloorDiv => quote! {
+                #[doc = " Python's `//`: the quotient rounded toward negative infinity"]
+                fn #name(a: &BigInt, b: &BigInt) -> BigInt {
+                    let zero = BigInt::from(0);
+                    let q = a / b;
+                    if a % b != zero && (*a < zero) != (*b < zero) { q - BigInt::from(1) } else { q }
+                }
+            },
+            Helper::BigFloorMod => quote! {
+                #[doc = " Python's `%`: the remainder with the sign of the divisor"]
+                fn #name(a: &BigInt, b: &BigInt) -> BigInt {
+                    let zero = BigInt::from(0);
+                    let r = a % b;
+                    if r != zero && (r < zero) != (*b < zero) { r + b } else { r }
+                }
+            },
+        }
+    }
+}
+
+/// `int` as a float: a literal as a float literal, anything else cast
+pub fn int_to_float(hir: &HirExpr, expr: &syn::Expr) -> syn::Expr {
+    match indexing::int_literal(hir) {
+        Some(n) => {
+            let lit = float_literal(n.unsigned_abs());
+            if n < 0 {
+                parse_quote! { -#lit }
+            } else {
+                parse_quote! { #lit }
+            }
+        }
+        None => {
+            let expr = builtins::operand_expr(expr);
+            parse_quote! { #expr as f64 }
+        }
+    }
+}
+
+fn float_literal(n: u64) -> syn::LitFloat {
+    syn::LitFloat::new(&format!("{n}.0"), proc_macro2::Span::call_site())
+}
+
+/// A method receiver of type `int`: a literal gets its type as a suffix,
+/// since Rust cannot call methods on an integer of unknown type
+pub fn int_receiver(hir: &HirExpr, expr: &syn::Expr, int: &str) -> syn::Expr {
+    match indexing::int_literal(hir) {
+        Some(n) => {
+            let lit = syn::LitInt::new(
+                &format!("{}_{int}", n.unsigned_abs()),
+                proc_macro2::Span::call_site(),
+            );
+            if n < 0 {
+                parse_quote! { (-#lit) }
+            } else {
+                parse_quote! { #lit }
+            }
+        }
+        None => builtins::operand_expr(expr),
+    }
+}
+
+/// A method receiver of type `float`, converted from an int or float
+/// operand `hir`; a literal also needs the type as a suffix
+pub fn float_receiver(hir: &HirExpr, expr: &syn::Expr) -> syn::Expr {
+    let (negative, literal) = match hir {
+        HirExpr::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => (true, operand.as_ref()),
+        _ => (false, hir),
+    };
+    let value = match literal {
+        HirExpr::Literal(Literal::Float(f)) => *f,
+        HirExpr::Literal(Literal::Int(n)) => *n as f64,
+        _ => return builtins::operand_expr(expr),
+    };
+    let lit = syn::LitFloat::new(&format!("{value:?}_f64"), proc_macro2::Span::call_site());
+    if negative {
+        parse_quote! { (-#lit) }
+    } else {
+        parse_quote! { #lit }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::ToTokens;
+
+    fn code(expr: syn::Expr) -> String {
+        expr.to_token_stream().to_string()
+    }
+
+    #[test]
+    fn test_overflow_methods() {
+        assert_eq!(Overflow::Native.method(BinOp::Add), None);
+        assert_eq!(Overflow::Wrapping.method(BinOp::Mul), Some("wrapping_mul"));
+        assert_eq!(Overflow::Raise.method(BinOp::Pow), Some("checked_pow"));
+        assert_eq!(Overflow::Panic.method(BinOp::Div), None);
+        assert!(Overflow::Panic.is_checked() && !Overflow::Wrapping.is_checked());
+
+        let var = |name: &str| Box::new(HirExpr::Var(name.to_string()));
+        let text = Box::new(HirExpr::Literal(Literal::String("s".to_string())));
+        let sum = |left, right| HirExpr::Binary {
+            op: BinOp::Add,
+            left,
+            right,
+        };
+        assert!(may_overflow(&sum(var("a"), var("b"))));
+        assert!(!may_overflow(&sum(text, var("b"))));
+    }
+
+    #[test]
+    fn test_receivers() {
+        let two = HirExpr::Literal(Literal::Int(2));
+        let minus_two = HirExpr::Unary {
+            op: UnaryOp::Neg,
+            operand: Box::new(two.clone()),
+        };
+        let x = HirExpr::Var("x".to_string());
+        let lit: syn::Expr = parse_quote! { 2 };
+        let var: syn::Expr = parse_quote! { x };
+        assert_eq!(code(int_receiver(&two, &lit, "i64")), "2_i64");
+        assert_eq!(code(int_receiver(&minus_two, &lit, "i32")), "(- 2_i32)");
+        assert_eq!(code(int_receiver(&x, &var, "i32")), "x");
+        assert_eq!(code(int_to_float(&two, &lit)), "2.0");
+        assert_eq!(code(int_to_float(&x, &var)), "x as f64");
+        let half = HirExpr::Literal(Literal::Float(0.5));
+        assert_eq!(
+            code(float_receiver(&half, &parse_quote! { 0.5 })),
+            "0.5_f64"
+        );
+        assert_eq!(code(float_receiver(&minus_two, &lit)), "(- 2.0_f64)");
+        assert_eq!(
+            code(float_receiver(&x, &parse_quote! { x as f64 })),
+            "(x as f64)"
+        );
+    }
+    #[test]
+    fn test_floor_division_of_call_results() {
+        let python = r#"
+def h(a: int) -> int:
+    return a - 10
+
+def quotient(a: int) -> int:
+    return h(a) // 3
+
+def remainder(a: int) -> int:
+    x = h(a)
+    return x % 3
+
+def scaled(a: int) -> float:
+    return h(a) // 2.0
+"#;
+        let printed = crate::test_programs::run(
+            python,
+            r#"println!("{} {} {}", quotient(0), remainder(0), scaled(5));"#,
+        );
+        // Python: -10 // 3, -10 % 3 and -5 // 2.0
+        assert_eq!(printed, "-4 2 -3\n");
+    }
+}
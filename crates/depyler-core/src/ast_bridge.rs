@@ -1,9 +1,19 @@
+use crate::arguments;
+use crate::context_managers;
+use crate::error::{ErrorKind, SpanExt};
+use crate::exceptions::{propagate_fallibility, ExceptionTable};
+use crate::fallback::{self, SkippedConstruct};
 use crate::hir::*;
-use anyhow::{bail, Result};
-use depyler_annotations::{AnnotationExtractor, AnnotationParser, TranspilationAnnotations};
-use rustpython_ast::{self as ast};
+use crate::transpile_bail;
+use anyhow::Result;
+use depyler_annotations::{
+    AnnotationExtractor, AnnotationParser, KwargsStrategy, PerformanceHint,
+    TranspilationAnnotations,
+};
+use rustpython_ast::{self as ast, Ranged};
 
 mod converters;
+mod format_strings;
 mod properties;
 mod type_extraction;
 
@@ -13,6 +23,9 @@ pub use type_extraction::TypeExtractor;
 
 pub struct AstBridge {
     source_code: Option<String>,
+    /// Stub out every function that fails to convert, as with
+    /// `fallback = "manual"`
+    best_effort: bool,
     annotation_extractor: AnnotationExtractor,
     annotation_parser: AnnotationParser,
 }
@@ -27,6 +40,7 @@ impl AstBridge {
     pub fn new() -> Self {
         Self {
             source_code: None,
+            best_effort: false,
             annotation_extractor: AnnotationExtractor::new(),
             annotation_parser: AnnotationParser::new(),
         }
@@ -37,21 +51,44 @@ impl AstBridge {
         self
     }
 
+    pub fn with_best_effort(mut self) -> Self {
+        self.best_effort = true;
+        self
+    }
+
     pub fn python_to_hir(&self, module: ast::Mod) -> Result<HirModule> {
         match module {
             ast::Mod::Module(m) => self.convert_module(m),
-            _ => bail!("Only module-level code is supported"),
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Only module-level code is supported".to_string(),
+            )),
         }
     }
 
     fn convert_module(&self, module: ast::ModModule) -> Result<HirModule> {
         let mut functions = Vec::new();
+        let mut classes = Vec::new();
         let mut imports = Vec::new();
+        let mut entry_points = Vec::new();
+        let mut skipped = Vec::new();
 
         for stmt in module.body {
+            if let Some(coroutine) = asyncio_run_target(&stmt) {
+                entry_points.push(convert_expr(coroutine.clone())?);
+            }
             match stmt {
                 ast::Stmt::FunctionDef(f) => {
-                    functions.push(self.convert_function(f)?);
+                    functions.push(self.convert_function_or_stub(f, &mut skipped)?);
+                }
+                ast::Stmt::AsyncFunctionDef(f) => {
+                    let mut function =
+                        self.convert_function_or_stub(function_def(f), &mut skipped)?;
+                    function.is_async = true;
+                    functions.push(function);
+                }
+                ast::Stmt::ClassDef(c) => {
+                    let class = self.convert_class(c, &classes, &mut skipped)?;
+                    classes.push(class);
                 }
                 ast::Stmt::Import(i) => {
                     imports.extend(convert_import(i)?);
@@ -65,32 +102,260 @@ impl AstBridge {
             }
         }
 
-        Ok(HirModule { functions, imports })
+        for coroutine in entry_points {
+            if let Some(entry) = async_entry_point(coroutine, &functions)? {
+                functions.push(entry);
+            }
+        }
+
+        let mut module = HirModule {
+            functions,
+            classes,
+            imports,
+            skipped,
+        };
+        self.apply_module_annotations(&mut module);
+        arguments::resolve_call_arguments(&mut module)?;
+        propagate_fallibility(&mut module);
+        Ok(module)
     }
 
-    fn convert_function(&self, func: ast::StmtFunctionDef) -> Result<HirFunction> {
+    /// The module's `integer_type` applies to functions and methods that do
+    /// not choose their own
+    fn apply_module_annotations(&self, module: &mut HirModule) {
+        let Some(integer_type) = self
+            .source_code
+            .as_deref()
+            .and_then(|source| self.annotation_extractor.extract_module_annotations(source))
+            .and_then(|text| self.annotation_parser.parse_annotations(&text).ok())
+            .and_then(|annotations| annotations.integer_type)
+        else {
+            return;
+        };
+        let functions = module.functions.iter_mut().map(|f| &mut f.annotations);
+        let methods = module
+            .classes
+            .iter_mut()
+            .flat_map(|c| &mut c.methods)
+            .map(|m| &mut m.annotations);
+        for annotations in functions.chain(methods) {
+            annotations.integer_type.get_or_insert(integer_type);
+        }
+    }
+
+    /// Convert `class`, whose bases may be among the `defined` classes
+    fn convert_class(
+        &self,
+        class: ast::StmtClassDef,
+        defined: &[HirClass],
+        skipped: &mut Vec<SkippedConstruct>,
+    ) -> Result<HirClass> {
+        let name = class.name.to_string();
+        let mut base_classes = Vec::new();
+        for base in &class.bases {
+            let span = Span::from(base.range());
+            if let Some(base) = convert_base_class(base, &name, defined).at(span)? {
+                base_classes.push(base);
+            }
+        }
+        let is_dataclass = class.decorator_list.iter().any(is_dataclass_decorator);
+        let annotations = self.extract_class_annotations(&name);
+
+        let mut body = class.body;
+        let docstring = take_docstring(&mut body);
+
+        let mut fields = Vec::new();
+        let mut methods = Vec::new();
+        for stmt in body {
+            let span = Span::from(stmt.range());
+            match stmt {
+                ast::Stmt::AnnAssign(a) => fields.push(convert_class_field(a).at(span)?),
+                ast::Stmt::Assign(a) => fields.extend(convert_class_attribute(a).at(span)?),
+                ast::Stmt::FunctionDef(f) => {
+                    reject_property_accessors(&f.decorator_list).at(span)?;
+                    methods.push(self.convert_method_or_stub(f, &name, &annotations, skipped)?);
+                }
+                ast::Stmt::AsyncFunctionDef(f) => {
+                    let mut method =
+                        self.convert_method_or_stub(function_def(f), &name, &annotations, skipped)?;
+                    method.is_async = true;
+                    methods.push(method);
+                }
+                _ => {
+                    // Skip `pass` and other class-level statements for now
+                }
+            }
+        }
+
+        if let Some(init) = methods.iter().find(|m| m.name == "__init__") {
+            for field in infer_init_fields(init) {
+                if !fields.iter().any(|f| f.name == field.name) {
+                    fields.push(field);
+                }
+            }
+        }
+
+        Ok(HirClass {
+            name,
+            base_classes,
+            fields,
+            methods,
+            is_dataclass,
+            annotations,
+            docstring,
+        })
+    }
+
+    fn convert_method(
+        &self,
+        func: ast::StmtFunctionDef,
+        class_name: &str,
+        class_annotations: &TranspilationAnnotations,
+    ) -> Result<HirMethod> {
         let name = func.name.to_string();
-        let params = convert_parameters(&func.args)?;
+        let kind = method_kind(&func.decorator_list);
+        let annotations = self.method_annotations(&func, class_annotations);
+
+        let kwargs_struct = arguments::kwargs_struct_name(Some(class_name), &name);
+        let (mut params, mut param_spec) =
+            convert_parameters(&func.args, &annotations, &kwargs_struct)?;
+        drop_receiver(kind, &mut params, &mut param_spec);
         let ret_type = TypeExtractor::extract_return_type(&func.returns)?;
 
+        let (docstring, body) = extract_docstring_and_body(func.body)?;
+        let properties = FunctionAnalyzer::analyze_annotated(&params, &body, &annotations);
+
+        Ok(HirMethod {
+            name,
+            params: params.into(),
+            param_spec,
+            ret_type,
+            body,
+            kind,
+            properties,
+            is_async: is_async_ready(&annotations),
+            span: func.range.into(),
+            annotations,
+            docstring,
+        })
+    }
+
+    /// Convert method `func` of class `class_name`, or when that fails and its
+    /// fallback allows, stub it out as `convert_function_or_stub` does
+    fn convert_method_or_stub(
+        &self,
+        func: ast::StmtFunctionDef,
+        class_name: &str,
+        class_annotations: &TranspilationAnnotations,
+        skipped: &mut Vec<SkippedConstruct>,
+    ) -> Result<HirMethod> {
+        let annotations = self.method_annotations(&func, class_annotations);
+        // A constructor stub would leave the fields `__init__` assigns undeclared
+        if !fallback::falls_back(self.best_effort, &annotations) || func.name.as_str() == "__init__"
+        {
+            return self.convert_method(func, class_name, class_annotations);
+        }
+        match self.convert_method(func.clone(), class_name, class_annotations) {
+            Ok(method) => Ok(method),
+            Err(err) => {
+                let name = format!("{class_name}.{}", func.name);
+                skipped.push(SkippedConstruct::from_error(&name, func.range.into(), &err));
+                Ok(stub_method(func, class_name, annotations))
+            }
+        }
+    }
+
+    /// Methods inherit the class-level annotations unless their docstring overrides them
+    fn method_annotations(
+        &self,
+        func: &ast::StmtFunctionDef,
+        class_annotations: &TranspilationAnnotations,
+    ) -> TranspilationAnnotations {
+        self.extract_docstring_annotations(&func.body)
+            .unwrap_or_else(|| class_annotations.clone())
+    }
+
+    fn extract_class_annotations(&self, class_name: &str) -> TranspilationAnnotations {
+        self.source_code
+            .as_ref()
+            .and_then(|source| {
+                self.annotation_extractor
+                    .extract_class_annotations(source, class_name)
+            })
+            .and_then(|text| self.annotation_parser.parse_annotations(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn extract_docstring_annotations(
+        &self,
+        body: &[ast::Stmt],
+    ) -> Option<TranspilationAnnotations> {
+        if let Some(ast::Stmt::Expr(expr)) = body.first() {
+            if let ast::Expr::Constant(constant) = expr.value.as_ref() {
+                if let ast::Constant::Str(docstring) = &constant.value {
+                    if docstring.contains("@depyler:") {
+                        return self.annotation_parser.parse_annotations(docstring).ok();
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn convert_function(&self, func: ast::StmtFunctionDef) -> Result<HirFunction> {
+        let name = func.name.to_string();
+
         // Extract annotations from source code if available
         let annotations = self.extract_function_annotations(&func);
 
+        let kwargs_struct = arguments::kwargs_struct_name(None, &name);
+        let (params, param_spec) = convert_parameters(&func.args, &annotations, &kwargs_struct)?;
+
         // Extract docstring and filter it from the body
         let (docstring, filtered_body) = extract_docstring_and_body(func.body)?;
-        let properties = FunctionAnalyzer::analyze(&filtered_body);
+        let properties = FunctionAnalyzer::analyze_annotated(&params, &filtered_body, &annotations);
+
+        let ret_type = if properties.is_generator {
+            TypeExtractor::extract_generator_item_type(&func.returns)?
+        } else {
+            TypeExtractor::extract_return_type(&func.returns)?
+        };
 
         Ok(HirFunction {
             name,
             params: params.into(),
+            param_spec,
             ret_type,
             body: filtered_body,
             properties,
+            is_async: is_async_ready(&annotations),
+            span: func.range.into(),
             annotations,
             docstring,
         })
     }
 
+    /// Convert `func`, or when that fails and its fallback allows, stub it
+    /// out, recording what it failed on in `skipped`
+    fn convert_function_or_stub(
+        &self,
+        func: ast::StmtFunctionDef,
+        skipped: &mut Vec<SkippedConstruct>,
+    ) -> Result<HirFunction> {
+        let annotations = self.extract_function_annotations(&func);
+        if !fallback::falls_back(self.best_effort, &annotations) {
+            return self.convert_function(func);
+        }
+        match self.convert_function(func.clone()) {
+            Ok(function) => Ok(function),
+            Err(err) => {
+                let span = func.range.into();
+                skipped.push(SkippedConstruct::from_error(&func.name, span, &err));
+                Ok(stub_function(func, annotations))
+            }
+        }
+    }
+
     fn extract_function_annotations(
         &self,
         func: &ast::StmtFunctionDef,
@@ -123,39 +388,421 @@ impl AstBridge {
     }
 }
 
+/// A function with the signature of `func`, as far as its types convert,
+/// and no body; codegen fills the body in with `todo!()`
+fn stub_function(func: ast::StmtFunctionDef, annotations: TranspilationAnnotations) -> HirFunction {
+    let kwargs_struct = arguments::kwargs_struct_name(None, &func.name);
+    let (params, param_spec) =
+        convert_parameters(&func.args, &annotations, &kwargs_struct).unwrap_or_default();
+    let ret_type = stub_return_type(&func);
+    let docstring = extract_docstring_and_body(func.body)
+        .map(|(docstring, _)| docstring)
+        .unwrap_or_default();
+    HirFunction {
+        name: func.name.to_string(),
+        params: params.into(),
+        param_spec,
+        ret_type,
+        body: vec![],
+        properties: FunctionProperties::default(),
+        is_async: is_async_ready(&annotations),
+        span: func.range.into(),
+        annotations,
+        docstring,
+    }
+}
+
+/// A method stubbed out as [`stub_function`] stubs out functions
+fn stub_method(
+    func: ast::StmtFunctionDef,
+    class_name: &str,
+    annotations: TranspilationAnnotations,
+) -> HirMethod {
+    let kind = method_kind(&func.decorator_list);
+    let kwargs_struct = arguments::kwargs_struct_name(Some(class_name), &func.name);
+    let (mut params, mut param_spec) =
+        convert_parameters(&func.args, &annotations, &kwargs_struct).unwrap_or_default();
+    drop_receiver(kind, &mut params, &mut param_spec);
+    let ret_type = stub_return_type(&func);
+    let docstring = extract_docstring_and_body(func.body)
+        .map(|(docstring, _)| docstring)
+        .unwrap_or_default();
+    HirMethod {
+        name: func.name.to_string(),
+        params: params.into(),
+        param_spec,
+        ret_type,
+        body: vec![],
+        kind,
+        properties: FunctionProperties::default(),
+        is_async: is_async_ready(&annotations),
+        span: func.range.into(),
+        annotations,
+        docstring,
+    }
+}
+
+/// The return type of a stub: its annotation's, or without one `None` unless
+/// the body returns a value. An annotation that does not convert leaves
+/// the type unknown, so the stub still returns a value.
+fn stub_return_type(func: &ast::StmtFunctionDef) -> Type {
+    match &func.returns {
+        Some(_) => TypeExtractor::extract_return_type(&func.returns).unwrap_or(Type::Unknown),
+        None if returns_value(&func.body) => Type::Unknown,
+        None => Type::None,
+    }
+}
+
+/// Whether a `return` statement of `body`, outside nested functions, has a value
+fn returns_value(body: &[ast::Stmt]) -> bool {
+    body.iter().any(|stmt| match stmt {
+        ast::Stmt::Return(r) => r.value.is_some(),
+        ast::Stmt::If(s) => returns_value(&s.body) || returns_value(&s.orelse),
+        ast::Stmt::For(s) => returns_value(&s.body) || returns_value(&s.orelse),
+        ast::Stmt::AsyncFor(s) => returns_value(&s.body) || returns_value(&s.orelse),
+        ast::Stmt::While(s) => returns_value(&s.body) || returns_value(&s.orelse),
+        ast::Stmt::With(s) => returns_value(&s.body),
+        ast::Stmt::AsyncWith(s) => returns_value(&s.body),
+        ast::Stmt::Try(s) => {
+            returns_value(&s.body)
+                || s.handlers
+                    .iter()
+                    .any(|ast::ExceptHandler::ExceptHandler(h)| returns_value(&h.body))
+                || returns_value(&s.orelse)
+                || returns_value(&s.finalbody)
+        }
+        ast::Stmt::Match(s) => s.cases.iter().any(|c| returns_value(&c.body)),
+        _ => false,
+    })
+}
+
+/// Drop the `self`/`cls` receiver of a method's parameters; codegen emits
+/// the Rust receiver
+fn drop_receiver(kind: MethodKind, params: &mut Vec<(Symbol, Type)>, param_spec: &mut ParamSpec) {
+    if kind != MethodKind::Static
+        && params
+            .first()
+            .is_some_and(|(receiver, _)| param_spec.is_positional(receiver))
+    {
+        let (receiver, _) = params.remove(0);
+        param_spec.positional_only.retain(|p| p != &receiver);
+    }
+}
+
+/// `async def` has the same shape as `def`; callers record `is_async` themselves
+fn function_def(func: ast::StmtAsyncFunctionDef) -> ast::StmtFunctionDef {
+    ast::StmtFunctionDef {
+        range: func.range,
+        name: func.name,
+        args: func.args,
+        body: func.body,
+        decorator_list: func.decorator_list,
+        returns: func.returns,
+        type_comment: func.type_comment,
+        type_params: func.type_params,
+    }
+}
+
+fn is_async_ready(annotations: &TranspilationAnnotations) -> bool {
+    annotations
+        .performance_hints
+        .contains(&PerformanceHint::AsyncReady)
+}
+
+/// The coroutine passed to a module-level `asyncio.run(...)`, including one
+/// guarded by `if __name__ == "__main__":`
+fn asyncio_run_target(stmt: &ast::Stmt) -> Option<&ast::Expr> {
+    match stmt {
+        ast::Stmt::Expr(e) => match e.value.as_ref() {
+            ast::Expr::Call(call) if call.args.len() == 1 => match call.func.as_ref() {
+                ast::Expr::Attribute(a)
+                    if a.attr.as_str() == "run"
+                        && matches!(a.value.as_ref(), ast::Expr::Name(n) if n.id.as_str() == "asyncio") =>
+                {
+                    Some(&call.args[0])
+                }
+                _ => None,
+            },
+            _ => None,
+        },
+        ast::Stmt::If(i) => i.body.iter().find_map(asyncio_run_target),
+        _ => None,
+    }
+}
+
+/// The `main` function driving a module-level `asyncio.run(coroutine)`.
+///
+/// Codegen turns an async `main` without parameters into the `#[tokio::main]`
+/// entry point, so `asyncio.run(main())` needs nothing extra.
+fn async_entry_point(coroutine: HirExpr, functions: &[HirFunction]) -> Result<Option<HirFunction>> {
+    if let Some(main) = functions.iter().find(|f| f.name == "main") {
+        let runs_main = matches!(&coroutine, HirExpr::Call { func, args, .. } if func == "main" && args.is_empty());
+        if runs_main && main.is_async && main.params.is_empty() {
+            return Ok(None);
+        }
+        transpile_bail!(ErrorKind::UnsupportedFeature(
+            "asyncio.run entry point conflicts with the existing function 'main'".to_string(),
+        ));
+    }
+    let body = vec![HirStmt::Expr(HirExpr::Await {
+        value: Box::new(coroutine),
+    })
+    .into()];
+    Ok(Some(HirFunction {
+        name: "main".to_string(),
+        params: Default::default(),
+        param_spec: ParamSpec::default(),
+        ret_type: Type::None,
+        properties: FunctionAnalyzer::analyze(&body),
+        body,
+        annotations: TranspilationAnnotations::default(),
+        docstring: None,
+        is_async: true,
+        span: Span::default(),
+    }))
+}
+
 // Keep the old function for backwards compatibility
 pub fn python_to_hir(module: ast::Mod) -> Result<HirModule> {
     AstBridge::new().python_to_hir(module)
 }
 
-fn convert_parameters(args: &ast::Arguments) -> Result<Vec<(Symbol, Type)>> {
+/// Convert the parameter list of a `def`.
+///
+/// `*args` becomes a list parameter and `**kwargs` a dict or, with
+/// `kwargs_strategy = "struct"`, the options struct `kwargs_struct`.
+fn convert_parameters(
+    args: &ast::Arguments,
+    annotations: &TranspilationAnnotations,
+    kwargs_struct: &str,
+) -> Result<(Vec<(Symbol, Type)>, ParamSpec)> {
     let mut params = Vec::new();
+    let mut spec = ParamSpec::default();
 
-    for arg in args.args.iter() {
+    let mut convert = |arg: &ast::ArgWithDefault| -> Result<Symbol> {
         let name = arg.def.arg.to_string();
-        let ty = if let Some(annotation) = &arg.def.annotation {
-            TypeExtractor::extract_type(annotation)?
-        } else {
-            Type::Unknown
+        let default = arg
+            .default
+            .as_ref()
+            .map(|d| convert_expr(d.as_ref().clone()))
+            .transpose()?;
+        let ty = match (&arg.def.annotation, &default) {
+            (Some(annotation), _) => TypeExtractor::extract_type(annotation)?,
+            // An unannotated parameter takes the type of its default value
+            (None, Some(default)) => match literal_type(default) {
+                Type::None => Type::Unknown,
+                ty => ty,
+            },
+            (None, None) => Type::Unknown,
+        };
+        params.push((name.clone(), ty));
+        if let Some(default) = default {
+            spec.defaults.push((name.clone(), default));
+        }
+        Ok(name)
+    };
+
+    let positional_only = args
+        .posonlyargs
+        .iter()
+        .map(&mut convert)
+        .collect::<Result<_>>()?;
+    for arg in &args.args {
+        convert(arg)?;
+    }
+    let keyword_only = args
+        .kwonlyargs
+        .iter()
+        .map(&mut convert)
+        .collect::<Result<_>>()?;
+    spec.positional_only = positional_only;
+    spec.keyword_only = keyword_only;
+
+    // `*args` goes between the positional and keyword-only parameters
+    if let Some(vararg) = &args.vararg {
+        let name = vararg.arg.to_string();
+        let element = vararg
+            .annotation
+            .as_ref()
+            .map(|a| TypeExtractor::extract_type(a))
+            .transpose()?
+            .unwrap_or(Type::Unknown);
+        let position = params.len() - spec.keyword_only.len();
+        params.insert(position, (name.clone(), Type::List(Box::new(element))));
+        spec.var_positional = Some(name);
+    }
+
+    if let Some(kwarg) = &args.kwarg {
+        let name = kwarg.arg.to_string();
+        let value_type = kwarg
+            .annotation
+            .as_ref()
+            .map(|a| TypeExtractor::extract_type(a))
+            .transpose()?
+            .unwrap_or(Type::Unknown);
+        let ty = match annotations.kwargs_strategy {
+            KwargsStrategy::HashMap => {
+                Type::Dict(Box::new(Type::String), Box::new(value_type.clone()))
+            }
+            KwargsStrategy::Struct => Type::Custom(kwargs_struct.to_string()),
+        };
+        params.push((name.clone(), ty));
+        spec.var_keyword = Some((name, value_type));
+    }
+
+    Ok((params, spec))
+}
+
+pub(crate) fn convert_body(body: Vec<ast::Stmt>) -> Result<Vec<Spanned<HirStmt>>> {
+    let mut stmts = Vec::new();
+    for stmt in body {
+        let span = Span::from(stmt.range());
+        let converted = match stmt {
+            // `pass` has no HIR representation; an empty body is equivalent
+            ast::Stmt::Pass(_) => continue,
+            // A bare annotation such as `x: int` declares the name without binding it
+            ast::Stmt::AnnAssign(a) if a.value.is_none() => continue,
+            ast::Stmt::Assign(a) if a.targets.len() > 1 => StmtConverter::convert_chained_assign(a),
+            ast::Stmt::FunctionDef(f) => convert_nested_function(f).map(|s| vec![s]),
+            // Collected by `convert_nested_function` from the nested body
+            ast::Stmt::Nonlocal(_) => continue,
+            stmt => convert_stmt(stmt).map(|s| vec![s]),
         };
-        params.push((name, ty));
+        stmts.extend(
+            converted
+                .at(span)?
+                .into_iter()
+                .map(|stmt| Spanned::new(stmt, span)),
+        );
     }
+    Ok(stmts)
+}
 
-    Ok(params)
+/// A `def` inside a function body. Calls to it are not resolved against a
+/// signature, so it takes plain positional parameters
+fn convert_nested_function(func: ast::StmtFunctionDef) -> Result<HirStmt> {
+    let name = func.name.to_string();
+    if !func.decorator_list.is_empty() {
+        transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Decorated nested function '{}' is not supported",
+            name
+        )));
+    }
+    let (params, param_spec) =
+        convert_parameters(&func.args, &TranspilationAnnotations::default(), "")?;
+    if param_spec != ParamSpec::default() {
+        transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Nested function '{}' with default, keyword-only or variadic parameters is not supported",
+            name
+        )));
+    }
+    let nonlocals = nonlocal_names(&func.body);
+    let (docstring, body) = extract_docstring_and_body(func.body)?;
+    let properties = FunctionAnalyzer::analyze(&body);
+    if properties.is_generator {
+        transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Nested generator '{}' is not supported",
+            name
+        )));
+    }
+    if properties.can_fail {
+        transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Nested function '{}' raises; only module-level functions and methods can raise",
+            name
+        )));
+    }
+    let ret_type = TypeExtractor::extract_return_type(&func.returns)?;
+    Ok(HirStmt::FunctionDef {
+        func: Box::new(HirFunction {
+            name,
+            params: params.into(),
+            param_spec,
+            ret_type,
+            body,
+            properties,
+            annotations: TranspilationAnnotations::default(),
+            docstring,
+            is_async: false,
+            span: func.range.into(),
+        }),
+        nonlocals,
+    })
 }
 
-pub(crate) fn convert_body(body: Vec<ast::Stmt>) -> Result<Vec<HirStmt>> {
-    body.into_iter().map(convert_stmt).collect()
+/// Names declared `nonlocal` anywhere in a function body, outside nested functions
+fn nonlocal_names(body: &[ast::Stmt]) -> Vec<Symbol> {
+    let mut names = Vec::new();
+    for stmt in body {
+        let nested: Vec<&[ast::Stmt]> = match stmt {
+            ast::Stmt::Nonlocal(n) => {
+                names.extend(n.names.iter().map(|name| name.to_string()));
+                continue;
+            }
+            ast::Stmt::If(s) => vec![&s.body, &s.orelse],
+            ast::Stmt::While(s) => vec![&s.body, &s.orelse],
+            ast::Stmt::For(s) => vec![&s.body, &s.orelse],
+            ast::Stmt::With(s) => vec![&s.body],
+            ast::Stmt::Try(s) => {
+                let mut bodies = vec![&s.body[..], &s.orelse, &s.finalbody];
+                bodies.extend(s.handlers.iter().map(|h| {
+                    let ast::ExceptHandler::ExceptHandler(h) = h;
+                    &h.body[..]
+                }));
+                bodies
+            }
+            ast::Stmt::Match(s) => s.cases.iter().map(|c| &c.body[..]).collect(),
+            _ => vec![],
+        };
+        for body in nested {
+            names.extend(nonlocal_names(body));
+        }
+    }
+    names
 }
 
 fn convert_stmt(stmt: ast::Stmt) -> Result<HirStmt> {
     StmtConverter::convert(stmt)
 }
 
-pub(crate) fn extract_assign_target(expr: &ast::Expr) -> Result<Symbol> {
+pub(crate) fn extract_assign_target(expr: &ast::Expr) -> Result<AssignTarget> {
+    match expr {
+        ast::Expr::Name(n) => Ok(AssignTarget::Symbol(n.id.to_string())),
+        ast::Expr::Attribute(a) => Ok(AssignTarget::Attribute {
+            value: Box::new(convert_expr(a.value.as_ref().clone())?),
+            attr: a.attr.to_string(),
+        }),
+        ast::Expr::Subscript(s) => {
+            if matches!(s.slice.as_ref(), ast::Expr::Slice(_)) {
+                transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "Assignment to a slice is not supported".to_string(),
+                ));
+            }
+            Ok(AssignTarget::Index {
+                base: Box::new(convert_expr(s.value.as_ref().clone())?),
+                index: Box::new(convert_expr(s.slice.as_ref().clone())?),
+            })
+        }
+        ast::Expr::Tuple(ast::ExprTuple { elts, .. })
+        | ast::Expr::List(ast::ExprList { elts, .. }) => Ok(AssignTarget::Tuple(
+            elts.iter()
+                .map(extract_assign_target)
+                .collect::<Result<Vec<_>>>()?,
+        )),
+        ast::Expr::Starred(_) => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Starred assignment targets are not supported".to_string(),
+        )),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Unsupported assignment target".to_string(),
+        )),
+    }
+}
+
+pub(crate) fn extract_loop_target(expr: &ast::Expr) -> Result<Symbol> {
     match expr {
         ast::Expr::Name(n) => Ok(n.id.to_string()),
-        _ => bail!("Only simple name targets supported for assignment"),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Only simple name targets supported for loops".to_string(),
+        )),
     }
 }
 
@@ -177,7 +824,9 @@ pub(crate) fn convert_binop(op: &ast::Operator) -> Result<BinOp> {
         ast::Operator::BitXor => BinOp::BitXor,
         ast::Operator::LShift => BinOp::LShift,
         ast::Operator::RShift => BinOp::RShift,
-        _ => bail!("Unsupported binary operator"),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Unsupported binary operator".to_string(),
+        )),
     })
 }
 
@@ -205,11 +854,14 @@ pub(crate) fn convert_cmpop(op: &ast::CmpOp) -> Result<BinOp> {
         ast::CmpOp::GtE => BinOp::GtEq,
         ast::CmpOp::In => BinOp::In,
         ast::CmpOp::NotIn => BinOp::NotIn,
-        _ => bail!("Unsupported comparison operator"),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Unsupported comparison operator".to_string(),
+        )),
     })
 }
 
 fn convert_import(import: ast::StmtImport) -> Result<Vec<Import>> {
+    let span = Span::from(import.range);
     import
         .names
         .into_iter()
@@ -223,7 +875,13 @@ fn convert_import(import: ast::StmtImport) -> Result<Vec<Import>> {
             } else {
                 vec![ImportItem::Named(module.clone())]
             };
-            Ok(Import { module, items })
+            Ok(Import {
+                module,
+                level: 0,
+                is_from: false,
+                items,
+                span,
+            })
         })
         .collect()
 }
@@ -247,10 +905,254 @@ fn convert_import_from(import: ast::StmtImportFrom) -> Result<Vec<Import>> {
         })
         .collect();
 
-    Ok(vec![Import { module, items }])
+    Ok(vec![Import {
+        module,
+        level: import.level.map_or(0, |level| level.to_u32()),
+        is_from: true,
+        items,
+        span: import.range.into(),
+    }])
+}
+
+fn is_dataclass_decorator(decorator: &ast::Expr) -> bool {
+    match decorator {
+        ast::Expr::Name(n) => n.id.as_str() == "dataclass",
+        ast::Expr::Attribute(a) => a.attr.as_str() == "dataclass",
+        ast::Expr::Call(c) => is_dataclass_decorator(&c.func),
+        _ => false,
+    }
+}
+
+/// The name `base` contributes to the bases of class `class`: exceptions
+/// and enums are subclassed, and bases only typing needs are dropped
+fn convert_base_class(
+    base: &ast::Expr,
+    class: &str,
+    defined: &[HirClass],
+) -> Result<Option<Symbol>> {
+    let name = match base {
+        ast::Expr::Name(n) => n.id.as_str(),
+        ast::Expr::Attribute(a) => a.attr.as_str(),
+        // `Generic[T]` and `Protocol[T]`
+        ast::Expr::Subscript(s) if matches!(s.value.as_ref(), ast::Expr::Name(n) if matches!(n.id.as_str(), "Generic" | "Protocol")) =>
+        {
+            return Ok(None);
+        }
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Base class expression of class '{class}' is not supported"
+        ))),
+    };
+    match name {
+        "object" | "Generic" | "Protocol" | "ABC" => Ok(None),
+        "Enum" | "IntEnum" | "StrEnum" => Ok(Some(name.to_string())),
+        _ if is_exception_class(name, defined) => Ok(Some(name.to_string())),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Base class '{name}' of class '{class}' is not supported; only exceptions and enums can be subclassed"
+        ))),
+    }
+}
+
+/// Whether `name` is a builtin exception or one of the `defined` classes
+/// deriving from one
+fn is_exception_class(name: &str, defined: &[HirClass]) -> bool {
+    ExceptionTable::new().is_exception(name)
+        || defined.iter().filter(|c| c.name == name).any(|c| {
+            c.base_classes
+                .iter()
+                .any(|b| is_exception_class(b, defined))
+        })
+}
+
+/// Reject the setter and deleter of a `@property`, which would redefine it
+fn reject_property_accessors(decorators: &[ast::Expr]) -> Result<()> {
+    for decorator in decorators {
+        if let ast::Expr::Attribute(a) = decorator {
+            if matches!(a.attr.as_str(), "setter" | "deleter") {
+                transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                    "Property {}s are not supported",
+                    a.attr
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn method_kind(decorators: &[ast::Expr]) -> MethodKind {
+    for decorator in decorators {
+        if let ast::Expr::Name(n) = decorator {
+            match n.id.as_str() {
+                "staticmethod" => return MethodKind::Static,
+                "classmethod" => return MethodKind::Class,
+                "property" => return MethodKind::Property,
+                _ => {}
+            }
+        }
+    }
+    MethodKind::Instance
+}
+
+fn take_docstring(body: &mut Vec<ast::Stmt>) -> Option<String> {
+    if let Some(ast::Stmt::Expr(expr)) = body.first() {
+        if let ast::Expr::Constant(constant) = expr.value.as_ref() {
+            if let ast::Constant::Str(s) = &constant.value {
+                let docstring = s.clone();
+                body.remove(0);
+                return Some(docstring);
+            }
+        }
+    }
+    None
+}
+
+fn convert_class_field(a: ast::StmtAnnAssign) -> Result<HirField> {
+    let name = match a.target.as_ref() {
+        ast::Expr::Name(n) => n.id.to_string(),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Only simple names supported for class fields".to_string(),
+        )),
+    };
+    // `ClassVar[T]` marks a class attribute rather than an instance field
+    let (annotation, is_class_var) = match a.annotation.as_ref() {
+        ast::Expr::Subscript(s) if matches!(s.value.as_ref(), ast::Expr::Name(n) if n.id.as_str() == "ClassVar") => {
+            (s.slice.as_ref(), true)
+        }
+        other => (other, false),
+    };
+    let field_type = TypeExtractor::extract_type(annotation)?;
+    let default_value = match a.value {
+        Some(value) => convert_field_default(*value)?,
+        None => None,
+    };
+    Ok(HirField {
+        name,
+        field_type,
+        default_value,
+        is_class_var,
+    })
+}
+
+/// The default of a class field, including that given by `field(default=...)`
+/// or `field(default_factory=...)` on a dataclass field; `field()` gives none
+fn convert_field_default(value: ast::Expr) -> Result<Option<HirExpr>> {
+    let ast::Expr::Call(call) = value else {
+        return convert_expr(value).map(Some);
+    };
+    let is_field = match call.func.as_ref() {
+        ast::Expr::Name(n) => n.id.as_str() == "field",
+        ast::Expr::Attribute(a) => a.attr.as_str() == "field",
+        _ => false,
+    };
+    if !is_field {
+        return convert_expr(ast::Expr::Call(call)).map(Some);
+    }
+    if !call.args.is_empty() {
+        transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Positional arguments to 'field' are not supported".to_string(),
+        ));
+    }
+    let mut default = None;
+    for keyword in call.keywords {
+        let arg = keyword.arg.as_ref().map(|a| a.as_str());
+        default = Some(match (arg, keyword.value) {
+            (Some("default"), value) => convert_expr(value)?,
+            (Some("default_factory"), ast::Expr::Lambda(l)) if l.args.args.is_empty() => {
+                convert_expr(*l.body)?
+            }
+            (Some("default_factory"), factory) => match convert_expr(factory)? {
+                // The empty containers the factories make
+                HirExpr::Var(func) if func == "list" => HirExpr::List(vec![]),
+                HirExpr::Var(func) if func == "dict" => HirExpr::Dict(vec![]),
+                HirExpr::Var(func) if func == "set" => HirExpr::Set(vec![]),
+                HirExpr::Var(func) => HirExpr::Call {
+                    func,
+                    args: vec![],
+                    kwargs: vec![],
+                },
+                _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "Only names and lambdas are supported as a default_factory".to_string(),
+                )),
+            },
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "Argument '{}' to 'field' is not supported",
+                arg.unwrap_or("**")
+            ))),
+        });
+    }
+    Ok(default)
+}
+
+fn convert_class_attribute(a: ast::StmtAssign) -> Result<Vec<HirField>> {
+    let value = convert_expr(*a.value)?;
+    let field_type = literal_type(&value);
+    a.targets
+        .iter()
+        .map(|target| match target {
+            ast::Expr::Name(n) => Ok(HirField {
+                name: n.id.to_string(),
+                field_type: field_type.clone(),
+                default_value: Some(value.clone()),
+                is_class_var: true,
+            }),
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Only simple names supported for class attributes".to_string(),
+            )),
+        })
+        .collect()
+}
+
+/// Collect instance fields from `self.<name> = ...` assignments in `__init__`
+fn infer_init_fields(init: &HirMethod) -> Vec<HirField> {
+    let mut fields: Vec<HirField> = Vec::new();
+    let assignments = init.body.iter().flat_map(|stmt| match &stmt.node {
+        HirStmt::Assign { target, value } => target.split_value(value),
+        _ => vec![],
+    });
+    for (target, assigned) in assignments {
+        if let AssignTarget::Attribute { value, attr } = target {
+            if !matches!(value.as_ref(), HirExpr::Var(v) if v == "self")
+                || fields.iter().any(|f| &f.name == attr)
+            {
+                continue;
+            }
+            let field_type = match assigned {
+                HirExpr::Var(param) => init
+                    .params
+                    .iter()
+                    .find(|(p, _)| p == param)
+                    .map(|(_, ty)| ty.clone())
+                    .unwrap_or(Type::Unknown),
+                other => literal_type(other),
+            };
+            fields.push(HirField {
+                name: attr.clone(),
+                field_type,
+                default_value: None,
+                is_class_var: false,
+            });
+        }
+    }
+    fields
+}
+
+fn literal_type(expr: &HirExpr) -> Type {
+    match expr {
+        HirExpr::Literal(Literal::Int(_)) => Type::Int,
+        HirExpr::Literal(Literal::Float(_)) => Type::Float,
+        HirExpr::Literal(Literal::String(_)) => Type::String,
+        HirExpr::Literal(Literal::Bool(_)) => Type::Bool,
+        HirExpr::Literal(Literal::None) => Type::None,
+        HirExpr::List(_) => Type::List(Box::new(Type::Unknown)),
+        HirExpr::Dict(_) => Type::Dict(Box::new(Type::Unknown), Box::new(Type::Unknown)),
+        HirExpr::Set(_) => Type::Set(Box::new(Type::Unknown)),
+        expr if context_managers::is_lock_constructor(expr) => Type::Custom("Lock".to_string()),
+        _ => Type::Unknown,
+    }
 }
 
-fn extract_docstring_and_body(body: Vec<ast::Stmt>) -> Result<(Option<String>, Vec<HirStmt>)> {
+fn extract_docstring_and_body(
+    body: Vec<ast::Stmt>,
+) -> Result<(Option<String>, Vec<Spanned<HirStmt>>)> {
     if body.is_empty() {
         return Ok((None, vec![]));
     }
@@ -272,11 +1174,7 @@ fn extract_docstring_and_body(body: Vec<ast::Stmt>) -> Result<(Option<String>, V
 
     // Convert the body, skipping the docstring if it exists
     let start_index = if docstring.is_some() { 1 } else { 0 };
-    let filtered_body = body
-        .into_iter()
-        .skip(start_index)
-        .map(convert_stmt)
-        .collect::<Result<Vec<_>>>()?;
+    let filtered_body = convert_body(body.into_iter().skip(start_index).collect())?;
 
     Ok((docstring, filtered_body))
 }
@@ -284,6 +1182,7 @@ fn extract_docstring_and_body(body: Vec<ast::Stmt>) -> Result<(Option<String>, V
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::TranspileError;
     use rustpython_ast::Suite;
     use rustpython_parser::Parse;
 
@@ -324,6 +1223,48 @@ mod tests {
         assert_eq!(func.ret_type, Type::Optional(Box::new(Type::Int)));
     }
 
+    #[test]
+    fn test_rich_type_annotations() {
+        let source = r#"
+def f(a: Union[int, str], b: int | None, c: Tuple[int, str], d: tuple[float, ...],
+      e: Literal["r", "w"], g: Any, h: Sequence[int], i: typing.Mapping[str, int],
+      j: list[int], k: Union[int, Optional[str]], l: Literal[1, 2]) -> int | str | None:
+    return 0
+"#;
+        let hir = parse_python_to_hir(source);
+        let types: Vec<&Type> = hir.functions[0].params.iter().map(|(_, t)| t).collect();
+        let int_or_str = Type::Union(vec![Type::Int, Type::String]);
+        assert_eq!(types[0], &int_or_str);
+        assert_eq!(types[1], &Type::Optional(Box::new(Type::Int)));
+        assert_eq!(types[2], &Type::Tuple(vec![Type::Int, Type::String]));
+        assert_eq!(types[3], &Type::List(Box::new(Type::Float)));
+        assert_eq!(
+            types[4],
+            &Type::Literal(vec!["r".to_string(), "w".to_string()])
+        );
+        assert_eq!(types[5], &Type::Unknown);
+        assert_eq!(types[6], &Type::List(Box::new(Type::Int)));
+        assert_eq!(
+            types[7],
+            &Type::Dict(Box::new(Type::String), Box::new(Type::Int))
+        );
+        assert_eq!(types[8], &Type::List(Box::new(Type::Int)));
+        assert_eq!(types[9], &Type::Optional(Box::new(int_or_str.clone())));
+        assert_eq!(types[10], &Type::Int);
+        assert_eq!(
+            hir.functions[0].ret_type,
+            Type::Optional(Box::new(int_or_str))
+        );
+
+        let mixed = "def f(x: Literal[1, \"a\"]) -> None:\n    pass\n";
+        let ast = rustpython_ast::Mod::Module(rustpython_ast::ModModule {
+            body: Suite::parse(mixed, "<test>").unwrap(),
+            type_ignores: vec![],
+            range: Default::default(),
+        });
+        assert!(python_to_hir(ast).is_err());
+    }
+
     #[test]
     fn test_import_conversion() {
         let source = "from typing import List, Dict\nimport sys";
@@ -351,7 +1292,7 @@ def check(x: int) -> str:
             condition,
             then_body,
             else_body,
-        } = &func.body[0]
+        } = &func.body[0].node
         {
             assert!(matches!(condition, HirExpr::Binary { op: BinOp::Gt, .. }));
             assert_eq!(then_body.len(), 1);
@@ -367,7 +1308,7 @@ def check(x: int) -> str:
         let hir = parse_python_to_hir(source);
 
         let func = &hir.functions[0];
-        if let HirStmt::Return(Some(HirExpr::Binary { op, .. })) = &func.body[0] {
+        if let HirStmt::Return(Some(HirExpr::Binary { op, .. })) = &func.body[0].node {
             assert_eq!(*op, BinOp::Add);
         } else {
             panic!("Expected binary operation in return");
@@ -403,8 +1344,8 @@ def iterate(items: list) -> int:
 
         let func = &hir.functions[0];
         assert_eq!(func.body.len(), 3); // assign, for, return
-        if let HirStmt::For { target, iter, body } = &func.body[1] {
-            assert_eq!(target, "item");
+        if let HirStmt::For { target, iter, body } = &func.body[1].node {
+            assert_eq!(target.as_symbol().map(String::as_str), Some("item"));
             assert!(matches!(iter, HirExpr::Var(_)));
             assert_eq!(body.len(), 1);
         } else {
@@ -428,7 +1369,7 @@ def expressions():
         if let HirStmt::Assign {
             value: HirExpr::List(_),
             ..
-        } = &func.body[0]
+        } = &func.body[0].node
         {
             // OK
         } else {
@@ -439,7 +1380,7 @@ def expressions():
         if let HirStmt::Assign {
             value: HirExpr::Tuple(_),
             ..
-        } = &func.body[1]
+        } = &func.body[1].node
         {
             // OK
         } else {
@@ -456,7 +1397,7 @@ def compare(a: int, b: int) -> bool:
         let hir = parse_python_to_hir(source);
 
         let func = &hir.functions[0];
-        if let HirStmt::Return(Some(HirExpr::Binary { op: BinOp::Gt, .. })) = &func.body[0] {
+        if let HirStmt::Return(Some(HirExpr::Binary { op: BinOp::Gt, .. })) = &func.body[0].node {
             // OK - simple comparison works
         } else {
             panic!("Expected > comparison");
@@ -476,7 +1417,7 @@ def unary_ops(x: int) -> int:
             op: BinOp::Add,
             left,
             right,
-        })) = &func.body[0]
+        })) = &func.body[0].node
         {
             assert!(matches!(
                 left.as_ref(),
@@ -506,7 +1447,10 @@ def call_functions() -> int:
         let hir = parse_python_to_hir(source);
 
         let func = &hir.functions[0];
-        if let HirStmt::Return(Some(HirExpr::Call { func: fname, args })) = &func.body[0] {
+        if let HirStmt::Return(Some(HirExpr::Call {
+            func: fname, args, ..
+        })) = &func.body[0].node
+        {
             assert_eq!(fname, "len");
             assert_eq!(args.len(), 1);
             assert!(matches!(args[0], HirExpr::List(_)));
@@ -603,4 +1547,622 @@ def function_without_docstring(y: int) -> int:
         assert_eq!(func_without_docstring.docstring, None);
         assert_eq!(func_without_docstring.body.len(), 2); // print statement + return
     }
+
+    #[test]
+    fn test_dataclass_conversion() {
+        let source = r#"
+from dataclasses import dataclass
+
+@dataclass
+class Point:
+    """A 2D point"""
+    x: int
+    y: int = 0
+
+    def norm(self) -> int:
+        return self.x + self.y
+"#;
+        let hir = parse_python_to_hir(source);
+
+        assert_eq!(hir.classes.len(), 1);
+        let class = &hir.classes[0];
+        assert_eq!(class.name, "Point");
+        assert!(class.is_dataclass);
+        assert_eq!(class.docstring, Some("A 2D point".to_string()));
+        assert_eq!(class.fields.len(), 2);
+        assert_eq!(class.fields[0].field_type, Type::Int);
+        assert!(class.fields[0].default_value.is_none());
+        assert!(class.fields[1].default_value.is_some());
+
+        assert_eq!(class.methods.len(), 1);
+        let method = &class.methods[0];
+        assert_eq!(method.kind, MethodKind::Instance);
+        assert!(method.params.is_empty()); // `self` is the receiver
+    }
+
+    #[test]
+    fn test_class_fields_from_init() {
+        let source = r#"
+class Counter:
+    limit = 10
+
+    def __init__(self, name: str):
+        self.name = name
+        self.count = 0
+
+    @staticmethod
+    def zero() -> int:
+        return 0
+"#;
+        let hir = parse_python_to_hir(source);
+        let class = &hir.classes[0];
+        assert!(!class.is_dataclass);
+
+        let limit = class.fields.iter().find(|f| f.name == "limit").unwrap();
+        assert!(limit.is_class_var);
+        let name = class.fields.iter().find(|f| f.name == "name").unwrap();
+        assert_eq!(name.field_type, Type::String);
+        let count = class.fields.iter().find(|f| f.name == "count").unwrap();
+        assert_eq!(count.field_type, Type::Int);
+
+        assert!(class.init_method().is_some());
+        let zero = class.methods.iter().find(|m| m.name == "zero").unwrap();
+        assert_eq!(zero.kind, MethodKind::Static);
+    }
+
+    #[test]
+    fn test_attribute_assignment_target() {
+        let source = r#"
+def set_x(p: Point, v: int) -> None:
+    p.x = v
+"#;
+        let hir = parse_python_to_hir(source);
+        match &hir.functions[0].body[0].node {
+            HirStmt::Assign {
+                target: AssignTarget::Attribute { attr, .. },
+                ..
+            } => assert_eq!(attr, "x"),
+            other => panic!("expected attribute assignment, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_method_call_conversion() {
+        let source = r#"
+def add_item(items: list, x: int) -> None:
+    items.append(x)
+"#;
+        let hir = parse_python_to_hir(source);
+        match &hir.functions[0].body[0].node {
+            HirStmt::Expr(HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                ..
+            }) => {
+                assert_eq!(**receiver, HirExpr::Var("items".to_string()));
+                assert_eq!(method, "append");
+                assert_eq!(args.len(), 1);
+            }
+            other => panic!("expected method call, got {other:?}"),
+        }
+        assert!(!hir.functions[0].properties.is_pure);
+    }
+
+    #[test]
+    fn test_comprehension_conversion() {
+        let source = r#"
+def pairs(xs: list, ys: list) -> dict:
+    return {x: y for x in xs if x > 0 for y in ys}
+"#;
+        let hir = parse_python_to_hir(source);
+        match &hir.functions[0].body[0].node {
+            HirStmt::Return(Some(HirExpr::Comprehension {
+                kind,
+                element,
+                value,
+                generators,
+            })) => {
+                assert_eq!(*kind, ComprehensionKind::Dict);
+                assert_eq!(**element, HirExpr::Var("x".to_string()));
+                assert_eq!(value.as_deref(), Some(&HirExpr::Var("y".to_string())));
+                assert_eq!(generators.len(), 2);
+                assert_eq!(generators[0].target, "x");
+                assert_eq!(generators[0].conditions.len(), 1);
+                assert!(generators[1].conditions.is_empty());
+            }
+            other => panic!("expected comprehension, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_try_except_conversion() {
+        let source = r#"
+def read(path: str) -> int:
+    try:
+        value = load(path)
+    except (KeyError, IndexError) as e:
+        raise ValueError("bad input")
+    finally:
+        close(path)
+    return value
+"#;
+        let hir = parse_python_to_hir(source);
+        match &hir.functions[0].body[0].node {
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                assert_eq!(body.len(), 1);
+                assert_eq!(handlers.len(), 1);
+                assert_eq!(handlers[0].exception_types, vec!["KeyError", "IndexError"]);
+                assert_eq!(handlers[0].name.as_deref(), Some("e"));
+                assert!(matches!(handlers[0].body[0].node, HirStmt::Raise { .. }));
+                assert!(orelse.is_empty());
+                assert_eq!(finalbody.len(), 1);
+            }
+            other => panic!("expected try statement, got {other:?}"),
+        }
+        assert!(hir.functions[0].properties.can_fail);
+        assert_eq!(hir.functions[0].properties.error_types, vec!["ValueError"]);
+    }
+
+    #[test]
+    fn test_fallibility_propagates_to_callers() {
+        let source = r#"
+def check(x: int) -> int:
+    if x < 0:
+        raise ValueError("negative")
+    return x
+
+def caller(x: int) -> int:
+    return check(x) + 1
+
+def guarded(x: int) -> int:
+    try:
+        return check(x)
+    except ValueError:
+        return 0
+"#;
+        let hir = parse_python_to_hir(source);
+        assert!(hir.functions[0].properties.can_fail);
+        assert!(hir.functions[1].properties.can_fail);
+        assert_eq!(hir.functions[1].properties.error_types, vec!["ValueError"]);
+        assert!(!hir.functions[2].properties.can_fail);
+    }
+
+    #[test]
+    fn test_generator_conversion() {
+        let source = r#"
+from typing import Iterator
+
+def count_up(n: int) -> Iterator[int]:
+    i = 0
+    while i < n:
+        yield i
+        i = i + 1
+    yield from range(3)
+"#;
+        let hir = parse_python_to_hir(source);
+        let func = &hir.functions[0];
+        assert!(func.properties.is_generator);
+        assert_eq!(func.ret_type, Type::Int);
+        match &func.body[1].node {
+            HirStmt::While { body, .. } => {
+                assert_eq!(
+                    body[0],
+                    HirStmt::Yield(Some(HirExpr::Var("i".to_string()))).into()
+                );
+            }
+            other => panic!("expected while loop, got {other:?}"),
+        }
+        assert!(matches!(
+            func.body[2].node,
+            HirStmt::YieldFrom(HirExpr::Call { .. })
+        ));
+        // Each `next` call stops at a `yield`, so the loop cannot run forever
+        assert!(func.properties.always_terminates);
+        assert!(func.properties.is_pure);
+    }
+
+    #[test]
+    fn test_termination_of_nested_loops() {
+        let source = r#"
+def evens(n: int) -> Iterator[int]:
+    i = 0
+    while True:
+        if i % 2 == 0:
+            yield i
+        i = i + 1
+
+def signs(n: int) -> Iterator[int]:
+    while True:
+        if n > 0:
+            yield 1
+        else:
+            yield -1
+
+def spin(flag: bool) -> int:
+    if flag:
+        while True:
+            pass
+    return 0
+
+def spin_each(n: int) -> int:
+    for i in range(n):
+        while True:
+            pass
+    return 0
+"#;
+        let hir = parse_python_to_hir(source);
+        let terminates: Vec<bool> = hir
+            .functions
+            .iter()
+            .map(|f| f.properties.always_terminates)
+            .collect();
+        // Only a loop yielding on every iteration hands control back
+        assert_eq!(terminates, [false, true, false, false]);
+    }
+
+    #[test]
+    fn test_with_conversion() {
+        let source = r#"
+def copy(src: str, dst: str) -> None:
+    with open(src) as f, open(dst, "w") as out:
+        out.write(f.read())
+    with lock:
+        pass
+"#;
+        let hir = parse_python_to_hir(source);
+        match &hir.functions[0].body[0].node {
+            HirStmt::With { items, body } => {
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[0].optional_vars.as_deref(), Some("f"));
+                assert!(matches!(
+                    &items[1].context_expr,
+                    HirExpr::Call { func, args, .. } if func == "open" && args.len() == 2
+                ));
+                assert_eq!(body.len(), 1);
+            }
+            other => panic!("expected with statement, got {other:?}"),
+        }
+        assert!(matches!(
+            &hir.functions[0].body[1].node,
+            HirStmt::With { items, .. } if items[0].optional_vars.is_none()
+        ));
+        assert!(!hir.functions[0].properties.is_pure);
+    }
+
+    #[test]
+    fn test_assign_target_conversion() {
+        let source = r#"
+def shuffle(xs: list, d: dict) -> None:
+    a, (b, c) = 1, (2, 3)
+    xs[0] = a
+    x = y = xs.pop()
+    n: int
+    for k, v in d.items():
+        d[k] = v
+"#;
+        let hir = parse_python_to_hir(source);
+        let body = &hir.functions[0].body;
+        assert_eq!(body.len(), 5);
+        let sym = |name: &str| AssignTarget::Symbol(name.to_string());
+        assert!(matches!(
+            &body[0].node,
+            HirStmt::Assign { target: AssignTarget::Tuple(targets), .. }
+                if targets[0] == sym("a")
+                    && targets[1] == AssignTarget::Tuple(vec![sym("b"), sym("c")])
+        ));
+        assert!(matches!(
+            &body[1].node,
+            HirStmt::Assign { target: AssignTarget::Index { base, .. }, .. }
+                if **base == HirExpr::Var("xs".to_string())
+        ));
+        // The value is computed once, then copied into the second name
+        assert!(matches!(&body[2].node, HirStmt::Assign { target, .. } if *target == sym("x")));
+        assert!(matches!(
+            &body[3].node,
+            HirStmt::Assign { target, value: HirExpr::MethodCall { receiver, method, .. } }
+                if *target == sym("y") && **receiver == HirExpr::Var("x".to_string()) && method == "copy"
+        ));
+        match &body[4].node {
+            HirStmt::For { target, .. } => {
+                assert_eq!(target.symbols(), vec!["k", "v"]);
+            }
+            other => panic!("expected for loop, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_match_conversion() {
+        let source = r#"
+def describe(value) -> str:
+    match value:
+        case 0 | None:
+            return "empty"
+        case [first, *rest] if first > 0:
+            return "list"
+        case {"kind": kind}:
+            return kind
+        case Point(x, y=0) as p:
+            return "point"
+        case _:
+            return "other"
+"#;
+        let hir = parse_python_to_hir(source);
+        let HirStmt::Match { subject, cases } = &hir.functions[0].body[0].node else {
+            panic!("expected match statement");
+        };
+        assert_eq!(subject, &HirExpr::Var("value".to_string()));
+        assert_eq!(cases.len(), 5);
+        assert_eq!(
+            cases[0].pattern,
+            Pattern::Or(vec![
+                Pattern::Value(HirExpr::Literal(Literal::Int(0))),
+                Pattern::Value(HirExpr::Literal(Literal::None)),
+            ])
+        );
+        assert_eq!(
+            cases[1].pattern,
+            Pattern::Sequence(vec![
+                Pattern::Capture {
+                    name: "first".to_string(),
+                    pattern: None
+                },
+                Pattern::Star(Some("rest".to_string())),
+            ])
+        );
+        assert!(cases[1].guard.is_some());
+        assert!(matches!(
+            &cases[2].pattern,
+            Pattern::Mapping { keys, rest: None, .. } if keys.len() == 1
+        ));
+        match &cases[3].pattern {
+            Pattern::Capture {
+                name,
+                pattern: Some(inner),
+            } => {
+                assert_eq!(name, "p");
+                assert!(matches!(
+                    inner.as_ref(),
+                    Pattern::Class { class, patterns, keywords }
+                        if class == "Point" && patterns.len() == 1 && keywords[0].0 == "y"
+                ));
+            }
+            other => panic!("expected capture of a class pattern, got {other:?}"),
+        }
+        assert!(cases[4].pattern.is_irrefutable());
+        assert_eq!(cases[1].pattern.bindings(), vec!["first", "rest"]);
+    }
+
+    #[test]
+    fn test_async_conversion() {
+        let source = r#"
+import asyncio
+
+async def fetch(n: int) -> int:
+    await asyncio.sleep(0.1)
+    return n
+
+async def run(n: int) -> int:
+    return await fetch(n)
+
+if __name__ == "__main__":
+    asyncio.run(run(3))
+"#;
+        let hir = parse_python_to_hir(source);
+        assert!(hir.functions[0].is_async);
+        assert!(matches!(
+            &hir.functions[1].body[0].node,
+            HirStmt::Return(Some(HirExpr::Await { value }))
+                if matches!(value.as_ref(), HirExpr::Call { func, .. } if func == "fetch")
+        ));
+
+        // `asyncio.run` gets an async `main` entry point awaiting the coroutine
+        let main = &hir.functions[2];
+        assert_eq!(main.name, "main");
+        assert!(main.is_async);
+        assert!(matches!(
+            &main.body[0].node,
+            HirStmt::Expr(HirExpr::Await { .. })
+        ));
+    }
+
+    #[test]
+    fn test_asyncio_run_of_async_main() {
+        let source = r#"
+import asyncio
+
+async def main():
+    await asyncio.sleep(1)
+
+asyncio.run(main())
+"#;
+        let hir = parse_python_to_hir(source);
+        assert_eq!(hir.functions.len(), 1);
+        assert!(hir.functions[0].is_async);
+    }
+
+    #[test]
+    fn test_parameter_kinds_and_keyword_calls() {
+        let source = r#"
+def plot(x: int, /, y=1.5, *points: int, color: str = "red", **style: str) -> None:
+    pass
+
+def main() -> None:
+    plot(1, 2.0, 3, 4, color="blue", width="2")
+"#;
+        let hir = parse_python_to_hir(source);
+        let plot = &hir.functions[0];
+        let names: Vec<&str> = plot.params.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["x", "y", "points", "color", "style"]);
+        assert_eq!(plot.params[1].1, Type::Float);
+        assert_eq!(plot.params[2].1, Type::List(Box::new(Type::Int)));
+        assert_eq!(
+            plot.params[4].1,
+            Type::Dict(Box::new(Type::String), Box::new(Type::String))
+        );
+        assert_eq!(plot.param_spec.positional_only, ["x"]);
+        assert_eq!(plot.param_spec.keyword_only, ["color"]);
+        assert_eq!(plot.param_spec.var_positional.as_deref(), Some("points"));
+        assert_eq!(
+            plot.param_spec.default_value("color"),
+            Some(&HirExpr::Literal(Literal::String("red".to_string())))
+        );
+
+        // The call passes every parameter positionally
+        let HirStmt::Expr(HirExpr::Call { args, kwargs, .. }) = &hir.functions[1].body[0].node
+        else {
+            panic!("expected a call to plot");
+        };
+        assert!(kwargs.is_empty());
+        assert_eq!(args.len(), 5);
+        assert!(matches!(&args[2], HirExpr::List(points) if points.len() == 2));
+        assert_eq!(
+            args[3],
+            HirExpr::Literal(Literal::String("blue".to_string()))
+        );
+        assert!(matches!(&args[4], HirExpr::Dict(items) if items.len() == 1));
+    }
+
+    #[test]
+    fn test_method_receiver_and_unpacked_arguments() {
+        let source = r#"
+class Counter:
+    def incr(self, by: int = 1) -> None:
+        pass
+
+def bump(c: Counter) -> None:
+    c.incr()
+"#;
+        let hir = parse_python_to_hir(source);
+        let incr = &hir.classes[0].methods[0];
+        assert_eq!(incr.params.len(), 1);
+        assert!(matches!(
+            &hir.functions[0].body[0].node,
+            HirStmt::Expr(HirExpr::MethodCall { args, .. }) if args.len() == 1
+        ));
+
+        let unpacked = Suite::parse("def f(xs: list) -> None:\n    g(*xs)\n", "<test>").unwrap();
+        let ast = rustpython_ast::Mod::Module(rustpython_ast::ModModule {
+            body: unpacked,
+            type_ignores: vec![],
+            range: Default::default(),
+        });
+        assert!(python_to_hir(ast).is_err());
+    }
+
+    #[test]
+    fn test_lambda_and_nested_function_conversion() {
+        let source = r#"
+from typing import Callable
+
+def counter(step: Callable[[int], int]) -> int:
+    count = 0
+    def bump():
+        nonlocal count
+        count = step(count)
+    bump()
+    key = lambda p: p.age
+    return count
+"#;
+        let hir = parse_python_to_hir(source);
+        let func = &hir.functions[0];
+        assert_eq!(
+            func.params[0].1,
+            Type::Function {
+                params: vec![Type::Int],
+                ret: Box::new(Type::Int),
+            }
+        );
+        let HirStmt::FunctionDef {
+            func: bump,
+            nonlocals,
+        } = &func.body[1].node
+        else {
+            panic!("expected a nested function");
+        };
+        assert_eq!(bump.name, "bump");
+        assert_eq!(nonlocals, &["count"]);
+        // `nonlocal` leaves no statement behind
+        assert_eq!(bump.body.len(), 1);
+        assert!(matches!(
+            &func.body[3].node,
+            HirStmt::Assign { value: HirExpr::Lambda { params, .. }, .. } if params == &["p"]
+        ));
+
+        let raising = "def f() -> None:\n    def g():\n        raise ValueError()\n";
+        let ast = rustpython_ast::Mod::Module(rustpython_ast::ModModule {
+            body: Suite::parse(raising, "<test>").unwrap(),
+            type_ignores: vec![],
+            range: Default::default(),
+        });
+        assert!(python_to_hir(ast).is_err());
+    }
+
+    #[test]
+    fn test_source_spans() {
+        let source = "def f(x: int) -> int:\n    y = x + 1\n    return y\n";
+        let hir = parse_python_to_hir(source);
+        let func = &hir.functions[0];
+        assert_eq!(func.span.range(), 0..source.len() - 1);
+        let text = |span: Span| &source[span.range()];
+        assert_eq!(text(func.body[0].span), "y = x + 1");
+        assert_eq!(text(func.body[1].span), "return y");
+
+        // Errors point at the expression that caused them
+        let invalid = "def f(x: int) -> int:\n    return x @ 2\n";
+        let ast = rustpython_ast::Mod::Module(rustpython_ast::ModModule {
+            body: Suite::parse(invalid, "<test>").unwrap(),
+            type_ignores: vec![],
+            range: Default::default(),
+        });
+        let err = python_to_hir(ast).unwrap_err();
+        let span = err.downcast_ref::<TranspileError>().unwrap().span;
+        assert_eq!(&invalid[span.range()], "x @ 2");
+    }
+
+    #[test]
+    fn test_unsupported_class_features_located() {
+        let located = |source: &str| {
+            let ast = rustpython_ast::Mod::Module(rustpython_ast::ModModule {
+                body: Suite::parse(source, "<test>").unwrap(),
+                type_ignores: vec![],
+                range: Default::default(),
+            });
+            let err = python_to_hir(ast).unwrap_err();
+            let err = err.downcast_ref::<TranspileError>().unwrap();
+            assert!(matches!(err.kind, ErrorKind::UnsupportedFeature(_)));
+            source[err.span.range()].to_string()
+        };
+        assert_eq!(
+            located("class Base:\n    pass\n\nclass Child(Base):\n    pass\n"),
+            "Base"
+        );
+        assert_eq!(
+            located("@dataclass\nclass P:\n    x: int = field(init=False)\n"),
+            "x: int = field(init=False)"
+        );
+        assert_eq!(
+            located(
+                "class C:\n    @property\n    def x(self) -> int:\n        return 1\n\n    \
+                 @x.setter\n    def x(self, v: int):\n        pass\n"
+            ),
+            "def x(self, v: int):\n        pass"
+        );
+
+        // Exceptions and enums, including subclasses of the module's own
+        let hir = parse_python_to_hir(
+            "class AppError(ValueError):\n    pass\n\nclass Missing(AppError):\n    pass\n\n\
+             class Color(Enum):\n    RED = 1\n\nclass Box(Generic[T], object):\n    pass\n",
+        );
+        let bases: Vec<_> = hir.classes.iter().map(|c| c.base_classes.clone()).collect();
+        assert_eq!(
+            bases,
+            [vec!["ValueError"], vec!["AppError"], vec!["Enum"], vec![]]
+        );
+    }
 }
@@ -1,10 +1,12 @@
 use super::{
     convert_aug_op, convert_binop, convert_body, convert_cmpop, convert_unaryop,
-    extract_assign_target,
+    extract_assign_target, extract_loop_target, format_strings,
 };
+use crate::error::{ErrorKind, SpanExt};
 use crate::hir::*;
-use anyhow::{bail, Result};
-use rustpython_ast::{self as ast};
+use crate::transpile_bail;
+use anyhow::Result;
+use rustpython_ast::{self as ast, Ranged};
 
 /// Statement converter to reduce complexity
 pub struct StmtConverter;
@@ -20,25 +22,122 @@ impl StmtConverter {
             ast::Stmt::While(w) => Self::convert_while(w),
             ast::Stmt::For(f) => Self::convert_for(f),
             ast::Stmt::Expr(e) => Self::convert_expr_stmt(e),
-            _ => bail!("Statement type not yet supported"),
+            ast::Stmt::Raise(r) => Self::convert_raise(r),
+            ast::Stmt::Try(t) => Self::convert_try(t),
+            ast::Stmt::With(w) => Self::convert_with(w),
+            ast::Stmt::Match(m) => Self::convert_match(m),
+            other => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "`{}` statement",
+                Self::keyword(&other)
+            ))),
+        }
+    }
+
+    /// The keyword introducing an unsupported statement, for errors
+    fn keyword(stmt: &ast::Stmt) -> &'static str {
+        match stmt {
+            ast::Stmt::FunctionDef(_) => "def",
+            ast::Stmt::AsyncFunctionDef(_) => "async def",
+            ast::Stmt::Return(_) => "return",
+            ast::Stmt::Assign(_) | ast::Stmt::AnnAssign(_) | ast::Stmt::AugAssign(_) => {
+                "assignment"
+            }
+            ast::Stmt::For(_) => "for",
+            ast::Stmt::While(_) => "while",
+            ast::Stmt::If(_) => "if",
+            ast::Stmt::With(_) => "with",
+            ast::Stmt::Match(_) => "match",
+            ast::Stmt::Raise(_) => "raise",
+            ast::Stmt::Try(_) => "try",
+            ast::Stmt::Expr(_) => "expression",
+            ast::Stmt::Pass(_) => "pass",
+            ast::Stmt::Break(_) => "break",
+            ast::Stmt::Continue(_) => "continue",
+            ast::Stmt::Delete(_) => "del",
+            ast::Stmt::Global(_) => "global",
+            ast::Stmt::Nonlocal(_) => "nonlocal",
+            ast::Stmt::Assert(_) => "assert",
+            ast::Stmt::Import(_) | ast::Stmt::ImportFrom(_) => "import",
+            ast::Stmt::ClassDef(_) => "class",
+            ast::Stmt::AsyncFor(_) => "async for",
+            ast::Stmt::AsyncWith(_) => "async with",
+            ast::Stmt::TryStar(_) => "try/except*",
+            ast::Stmt::TypeAlias(_) => "type",
         }
     }
 
     fn convert_assign(a: ast::StmtAssign) -> Result<HirStmt> {
         if a.targets.len() != 1 {
-            bail!("Multiple assignment targets not supported");
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Chained assignment is only supported as a statement of a body".to_string(),
+            ));
         }
         let target = extract_assign_target(&a.targets[0])?;
         let value = super::convert_expr(*a.value)?;
         Ok(HirStmt::Assign { target, value })
     }
 
+    /// `a = b = value` evaluates `value` once and binds it to each target in
+    /// turn. Targets after the first plain name receive a copy of it, as Rust
+    /// values cannot be aliased the way Python objects are.
+    pub(crate) fn convert_chained_assign(a: ast::StmtAssign) -> Result<Vec<HirStmt>> {
+        let targets = a
+            .targets
+            .iter()
+            .map(extract_assign_target)
+            .collect::<Result<Vec<_>>>()?;
+        let value = super::convert_expr(*a.value)?;
+        // Literals are as cheap to repeat as to copy
+        if let HirExpr::Literal(_) = value {
+            return Ok(targets
+                .into_iter()
+                .map(|target| HirStmt::Assign {
+                    target,
+                    value: value.clone(),
+                })
+                .collect());
+        }
+        let Some((first, name)) = targets
+            .iter()
+            .enumerate()
+            .find_map(|(i, t)| Some((i, t.as_symbol()?.clone())))
+        else {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Chained assignment of a computed value needs a plain name among its targets"
+                    .to_string(),
+            ));
+        };
+        let copy = HirExpr::MethodCall {
+            receiver: Box::new(HirExpr::Var(name)),
+            method: "copy".to_string(),
+            args: vec![],
+            kwargs: vec![],
+        };
+        let mut stmts = vec![HirStmt::Assign {
+            target: targets[first].clone(),
+            value,
+        }];
+        stmts.extend(
+            targets
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| *i != first)
+                .map(|(_, target)| HirStmt::Assign {
+                    target,
+                    value: copy.clone(),
+                }),
+        );
+        Ok(stmts)
+    }
+
     fn convert_ann_assign(a: ast::StmtAnnAssign) -> Result<HirStmt> {
         let target = extract_assign_target(&a.target)?;
         let value = if let Some(v) = a.value {
             super::convert_expr(*v)?
         } else {
-            bail!("Annotated assignment without value not supported")
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Annotated assignment without value not supported".to_string(),
+            ))
         };
         Ok(HirStmt::Assign { target, value })
     }
@@ -63,6 +162,89 @@ impl StmtConverter {
         })
     }
 
+    fn convert_raise(r: ast::StmtRaise) -> Result<HirStmt> {
+        // `raise X from e` keeps X; the cause only affects the traceback
+        let exception = r.exc.map(|e| super::convert_expr(*e)).transpose()?;
+        Ok(HirStmt::Raise { exception })
+    }
+
+    fn convert_try(t: ast::StmtTry) -> Result<HirStmt> {
+        let body = convert_body(t.body)?;
+        let handlers = t
+            .handlers
+            .into_iter()
+            .map(Self::convert_except_handler)
+            .collect::<Result<Vec<_>>>()?;
+        let orelse = convert_body(t.orelse)?;
+        let finalbody = convert_body(t.finalbody)?;
+        Ok(HirStmt::Try {
+            body,
+            handlers,
+            orelse,
+            finalbody,
+        })
+    }
+
+    fn convert_except_handler(h: ast::ExceptHandler) -> Result<ExceptHandler> {
+        let ast::ExceptHandler::ExceptHandler(h) = h;
+        let exception_types = match h.type_.map(|t| *t) {
+            None => vec![],
+            Some(ast::Expr::Tuple(t)) => t
+                .elts
+                .iter()
+                .map(exception_class_name)
+                .collect::<Result<Vec<_>>>()?,
+            Some(other) => vec![exception_class_name(&other)?],
+        };
+        Ok(ExceptHandler {
+            exception_types,
+            name: h.name.map(|n| n.to_string()),
+            body: convert_body(h.body)?,
+        })
+    }
+
+    fn convert_with(w: ast::StmtWith) -> Result<HirStmt> {
+        let items = w
+            .items
+            .into_iter()
+            .map(|item| {
+                let optional_vars = item
+                    .optional_vars
+                    .map(|target| -> Result<Symbol> {
+                        match *target {
+                            ast::Expr::Name(n) => Ok(n.id.to_string()),
+                            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                                "Only simple name targets supported for 'with'".to_string(),
+                            )),
+                        }
+                    })
+                    .transpose()?;
+                Ok(WithItem {
+                    context_expr: super::convert_expr(item.context_expr)?,
+                    optional_vars,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let body = convert_body(w.body)?;
+        Ok(HirStmt::With { items, body })
+    }
+
+    fn convert_match(m: ast::StmtMatch) -> Result<HirStmt> {
+        let subject = super::convert_expr(*m.subject)?;
+        let cases = m
+            .cases
+            .into_iter()
+            .map(|case| {
+                Ok(MatchCase {
+                    pattern: convert_pattern(case.pattern)?,
+                    guard: case.guard.map(|g| super::convert_expr(*g)).transpose()?,
+                    body: convert_body(case.body)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(HirStmt::Match { subject, cases })
+    }
+
     fn convert_while(w: ast::StmtWhile) -> Result<HirStmt> {
         let condition = super::convert_expr(*w.test)?;
         let body = convert_body(w.body)?;
@@ -77,25 +259,115 @@ impl StmtConverter {
     }
 
     fn convert_expr_stmt(e: ast::StmtExpr) -> Result<HirStmt> {
-        let expr = super::convert_expr(*e.value)?;
-        Ok(HirStmt::Expr(expr))
+        match *e.value {
+            ast::Expr::Yield(y) => {
+                let value = y.value.map(|v| super::convert_expr(*v)).transpose()?;
+                Ok(HirStmt::Yield(value))
+            }
+            ast::Expr::YieldFrom(y) => Ok(HirStmt::YieldFrom(super::convert_expr(*y.value)?)),
+            value => Ok(HirStmt::Expr(super::convert_expr(value)?)),
+        }
     }
 
     fn convert_aug_assign(a: ast::StmtAugAssign) -> Result<HirStmt> {
         let target = extract_assign_target(&a.target)?;
         let op = convert_aug_op(&a.op)?;
-        let left = Box::new(HirExpr::Var(target.clone()));
+        let Some(left) = target.to_expr() else {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Augmented assignment cannot unpack".to_string(),
+            ));
+        };
+        let left = Box::new(left);
         let right = Box::new(super::convert_expr(*a.value)?);
         let value = HirExpr::Binary { op, left, right };
         Ok(HirStmt::Assign { target, value })
     }
 }
 
+fn convert_pattern(pattern: ast::Pattern) -> Result<Pattern> {
+    let convert_all = |patterns: Vec<ast::Pattern>| {
+        patterns
+            .into_iter()
+            .map(convert_pattern)
+            .collect::<Result<Vec<_>>>()
+    };
+    Ok(match pattern {
+        ast::Pattern::MatchValue(p) => Pattern::Value(super::convert_expr(*p.value)?),
+        ast::Pattern::MatchSingleton(p) => Pattern::Value(HirExpr::Literal(match p.value {
+            ast::Constant::None => Literal::None,
+            ast::Constant::Bool(b) => Literal::Bool(b),
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Unsupported singleton pattern".to_string(),
+            )),
+        })),
+        ast::Pattern::MatchSequence(p) => Pattern::Sequence(convert_all(p.patterns)?),
+        ast::Pattern::MatchStar(p) => Pattern::Star(p.name.map(|n| n.to_string())),
+        ast::Pattern::MatchMapping(p) => Pattern::Mapping {
+            keys: p
+                .keys
+                .into_iter()
+                .map(super::convert_expr)
+                .collect::<Result<Vec<_>>>()?,
+            patterns: convert_all(p.patterns)?,
+            rest: p.rest.map(|n| n.to_string()),
+        },
+        ast::Pattern::MatchClass(p) => {
+            let class = match *p.cls {
+                ast::Expr::Name(n) => n.id.to_string(),
+                ast::Expr::Attribute(a) => a.attr.to_string(),
+                _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "Unsupported class in class pattern".to_string(),
+                )),
+            };
+            let keywords = p
+                .kwd_attrs
+                .into_iter()
+                .map(|attr| attr.to_string())
+                .zip(convert_all(p.kwd_patterns)?)
+                .collect();
+            Pattern::Class {
+                class,
+                patterns: convert_all(p.patterns)?,
+                keywords,
+            }
+        }
+        ast::Pattern::MatchAs(p) => match (p.pattern, p.name) {
+            (None, None) => Pattern::Wildcard,
+            (pattern, Some(name)) => Pattern::Capture {
+                name: name.to_string(),
+                pattern: pattern
+                    .map(|p| convert_pattern(*p).map(Box::new))
+                    .transpose()?,
+            },
+            (Some(_), None) => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Pattern alias without a name".to_string(),
+            )),
+        },
+        ast::Pattern::MatchOr(p) => Pattern::Or(convert_all(p.patterns)?),
+    })
+}
+
+fn exception_class_name(expr: &ast::Expr) -> Result<Symbol> {
+    match expr {
+        ast::Expr::Name(n) => Ok(n.id.to_string()),
+        // `json.JSONDecodeError` is matched by its class name
+        ast::Expr::Attribute(a) => Ok(a.attr.to_string()),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Unsupported exception type in except clause".to_string(),
+        )),
+    }
+}
+
 /// Expression converter to reduce complexity
 pub struct ExprConverter;
 
 impl ExprConverter {
     pub fn convert(expr: ast::Expr) -> Result<HirExpr> {
+        let span = Span::from(expr.range());
+        Self::convert_node(expr).at(span)
+    }
+
+    fn convert_node(expr: ast::Expr) -> Result<HirExpr> {
         match expr {
             ast::Expr::Constant(c) => Self::convert_constant(c),
             ast::Expr::Name(n) => Self::convert_name(n),
@@ -105,10 +377,66 @@ impl ExprConverter {
             ast::Expr::Subscript(s) => Self::convert_subscript(s),
             ast::Expr::List(l) => Self::convert_list(l),
             ast::Expr::Dict(d) => Self::convert_dict(d),
+            ast::Expr::Set(s) => Self::convert_set(s),
             ast::Expr::Tuple(t) => Self::convert_tuple(t),
             ast::Expr::Compare(c) => Self::convert_compare(c),
-            _ => bail!("Expression type not yet supported"),
+            ast::Expr::Attribute(a) => Self::convert_attribute(a),
+            ast::Expr::ListComp(l) => {
+                Self::convert_comprehension(ComprehensionKind::List, *l.elt, None, l.generators)
+            }
+            ast::Expr::SetComp(s) => {
+                Self::convert_comprehension(ComprehensionKind::Set, *s.elt, None, s.generators)
+            }
+            ast::Expr::DictComp(d) => Self::convert_comprehension(
+                ComprehensionKind::Dict,
+                *d.key,
+                Some(*d.value),
+                d.generators,
+            ),
+            ast::Expr::GeneratorExp(g) => Self::convert_comprehension(
+                ComprehensionKind::Generator,
+                *g.elt,
+                None,
+                g.generators,
+            ),
+            ast::Expr::Await(a) => Self::convert_await(a),
+            ast::Expr::Lambda(l) => Self::convert_lambda(l),
+            ast::Expr::JoinedStr(j) => format_strings::joined_str(j),
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Expression type not yet supported".to_string(),
+            )),
+        }
+    }
+
+    fn convert_lambda(l: ast::ExprLambda) -> Result<HirExpr> {
+        let args = &l.args;
+        if args.vararg.is_some()
+            || args.kwarg.is_some()
+            || !args.kwonlyargs.is_empty()
+            || args
+                .posonlyargs
+                .iter()
+                .chain(&args.args)
+                .any(|a| a.default.is_some())
+        {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Lambda parameters with defaults, keywords or unpacking are not supported"
+                    .to_string(),
+            ));
         }
+        let params = args
+            .posonlyargs
+            .iter()
+            .chain(&args.args)
+            .map(|a| a.def.arg.to_string())
+            .collect();
+        let body = Box::new(Self::convert(*l.body)?);
+        Ok(HirExpr::Lambda { params, body })
+    }
+
+    fn convert_await(a: ast::ExprAwait) -> Result<HirExpr> {
+        let value = Box::new(Self::convert(*a.value)?);
+        Ok(HirExpr::Await { value })
     }
 
     fn convert_constant(c: ast::ExprConstant) -> Result<HirExpr> {
@@ -122,7 +450,9 @@ impl ExprConverter {
             ast::Constant::Str(s) => Literal::String(s.to_string()),
             ast::Constant::Bool(b) => Literal::Bool(*b),
             ast::Constant::None => Literal::None,
-            _ => bail!("Unsupported constant type"),
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Unsupported constant type".to_string(),
+            )),
         };
         Ok(HirExpr::Literal(lit))
     }
@@ -132,6 +462,9 @@ impl ExprConverter {
     }
 
     fn convert_binop_expr(b: ast::ExprBinOp) -> Result<HirExpr> {
+        if let (ast::Operator::Mod, Some(format)) = (&b.op, literal_str(&b.left)) {
+            return format_strings::percent_format(&format, Self::convert(*b.right)?);
+        }
         let op = convert_binop(&b.op)?;
         let left = Box::new(Self::convert(*b.left)?);
         let right = Box::new(Self::convert(*b.right)?);
@@ -145,25 +478,77 @@ impl ExprConverter {
     }
 
     fn convert_call(c: ast::ExprCall) -> Result<HirExpr> {
-        let func = if let ast::Expr::Name(n) = c.func.as_ref() {
-            n.id.to_string()
-        } else {
-            bail!("Only simple function calls supported")
-        };
+        if c.args
+            .iter()
+            .any(|arg| matches!(arg, ast::Expr::Starred(_)))
+        {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Unpacking arguments with * is not supported".to_string(),
+            ));
+        }
         let args = c
             .args
             .into_iter()
             .map(Self::convert)
             .collect::<Result<Vec<_>>>()?;
-        Ok(HirExpr::Call { func, args })
+        let kwargs = c
+            .keywords
+            .into_iter()
+            .map(|k| match k.arg {
+                Some(name) => Ok((name.to_string(), Self::convert(k.value)?)),
+                None => transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "Unpacking keyword arguments with ** is not supported".to_string(),
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        match *c.func {
+            ast::Expr::Name(n) => Ok(HirExpr::Call {
+                func: n.id.to_string(),
+                args,
+                kwargs,
+            }),
+            ast::Expr::Attribute(a) => {
+                if let ("format", Some(format)) = (a.attr.as_str(), literal_str(&a.value)) {
+                    return format_strings::str_format(&format, args, kwargs);
+                }
+                let receiver = Box::new(Self::convert(*a.value)?);
+                let method = a.attr.to_string();
+                Ok(HirExpr::MethodCall {
+                    receiver,
+                    method,
+                    args,
+                    kwargs,
+                })
+            }
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Only simple function and method calls supported".to_string(),
+            )),
+        }
     }
 
     fn convert_subscript(s: ast::ExprSubscript) -> Result<HirExpr> {
         let base = Box::new(Self::convert(*s.value)?);
+        if let ast::Expr::Slice(slice) = *s.slice {
+            let bound = |bound: Option<Box<ast::Expr>>| {
+                bound.map(|b| Self::convert(*b).map(Box::new)).transpose()
+            };
+            return Ok(HirExpr::Slice {
+                base,
+                start: bound(slice.lower)?,
+                stop: bound(slice.upper)?,
+                step: bound(slice.step)?,
+            });
+        }
         let index = Box::new(Self::convert(*s.slice)?);
         Ok(HirExpr::Index { base, index })
     }
 
+    fn convert_attribute(a: ast::ExprAttribute) -> Result<HirExpr> {
+        let value = Box::new(Self::convert(*a.value)?);
+        let attr = a.attr.to_string();
+        Ok(HirExpr::Attribute { value, attr })
+    }
+
     fn convert_list(l: ast::ExprList) -> Result<HirExpr> {
         let elts = l
             .elts
@@ -173,15 +558,26 @@ impl ExprConverter {
         Ok(HirExpr::List(elts))
     }
 
+    fn convert_set(s: ast::ExprSet) -> Result<HirExpr> {
+        let elts = s
+            .elts
+            .into_iter()
+            .map(Self::convert)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(HirExpr::Set(elts))
+    }
+
     fn convert_dict(d: ast::ExprDict) -> Result<HirExpr> {
         let mut items = Vec::new();
-        for (k, v) in d.keys.into_iter().zip(d.values.into_iter()) {
+        for (k, v) in d.keys.into_iter().zip(d.values) {
             if let Some(key) = k {
                 let key_expr = Self::convert(key)?;
                 let val_expr = Self::convert(v)?;
                 items.push((key_expr, val_expr));
             } else {
-                bail!("Dict unpacking not supported");
+                transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "Dict unpacking not supported".to_string(),
+                ));
             }
         }
         Ok(HirExpr::Dict(items))
@@ -196,10 +592,45 @@ impl ExprConverter {
         Ok(HirExpr::Tuple(elts))
     }
 
+    fn convert_comprehension(
+        kind: ComprehensionKind,
+        element: ast::Expr,
+        value: Option<ast::Expr>,
+        generators: Vec<ast::Comprehension>,
+    ) -> Result<HirExpr> {
+        let generators = generators
+            .into_iter()
+            .map(|g| {
+                if g.is_async {
+                    transpile_bail!(ErrorKind::UnsupportedFeature(
+                        "Async comprehensions not yet supported".to_string(),
+                    ));
+                }
+                Ok(ComprehensionClause {
+                    target: extract_loop_target(&g.target)?,
+                    iter: Self::convert(g.iter)?,
+                    conditions: g
+                        .ifs
+                        .into_iter()
+                        .map(Self::convert)
+                        .collect::<Result<Vec<_>>>()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(HirExpr::Comprehension {
+            kind,
+            element: Box::new(Self::convert(element)?),
+            value: value.map(Self::convert).transpose()?.map(Box::new),
+            generators,
+        })
+    }
+
     fn convert_compare(c: ast::ExprCompare) -> Result<HirExpr> {
         // Convert simple comparisons to binary ops
         if c.ops.len() != 1 || c.comparators.len() != 1 {
-            bail!("Chained comparisons not yet supported");
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Chained comparisons not yet supported".to_string(),
+            ));
         }
         let op = convert_cmpop(&c.ops[0])?;
         let left = Box::new(Self::convert(*c.left)?);
@@ -207,3 +638,14 @@ impl ExprConverter {
         Ok(HirExpr::Binary { op, left, right })
     }
 }
+
+/// The text of a string literal
+fn literal_str(expr: &ast::Expr) -> Option<String> {
+    match expr {
+        ast::Expr::Constant(ast::ExprConstant {
+            value: ast::Constant::Str(s),
+            ..
+        }) => Some(s.to_string()),
+        _ => None,
+    }
+}
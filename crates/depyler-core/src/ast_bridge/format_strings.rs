@@ -0,0 +1,386 @@
+//! Formatted strings: f-strings, and `str.format` and `%` formatting of
+//! literal format strings, all converted to `HirExpr::FString`
+//!
+//! Fields keep their Python format spec; codegen translates it. Values
+//! nested in a spec, as in `{x:>{width}}`, are written `{N}` in the spec.
+
+use crate::error::ErrorKind;
+use crate::hir::*;
+use crate::transpile_bail;
+use anyhow::Result;
+use rustpython_ast::{self as ast, ConversionFlag};
+
+/// An f-string
+pub(super) fn joined_str(j: ast::ExprJoinedStr) -> Result<HirExpr> {
+    let mut parts = Vec::new();
+    let mut values = Vec::new();
+    for value in j.values {
+        joined_part(value, &mut parts, &mut values)?;
+    }
+    Ok(HirExpr::FString { parts, values })
+}
+
+fn joined_part(
+    value: ast::Expr,
+    parts: &mut Vec<FStringPart>,
+    values: &mut Vec<HirExpr>,
+) -> Result<()> {
+    match value {
+        ast::Expr::Constant(ast::ExprConstant {
+            value: ast::Constant::Str(text),
+            ..
+        }) => push_text(parts, &text),
+        ast::Expr::FormattedValue(f) => {
+            let conversion = f.conversion.to_byte().map(char::from);
+            let spec = match f.format_spec {
+                Some(spec) => spec_text(*spec, values)?,
+                None => String::new(),
+            };
+            values.push(super::convert_expr(*f.value)?);
+            parts.push(FStringPart::Field {
+                value: values.len() - 1,
+                conversion,
+                spec,
+            });
+        }
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "f-string part other than text or a replacement field".to_string()
+        )),
+    }
+    Ok(())
+}
+
+/// The format spec of an f-string field, with the values nested in it
+/// written `{N}`
+fn spec_text(spec: ast::Expr, values: &mut Vec<HirExpr>) -> Result<String> {
+    let ast::Expr::JoinedStr(spec) = spec else {
+        transpile_bail!(ErrorKind::UnsupportedFeature(
+            "f-string format spec".to_string()
+        ));
+    };
+    let mut text = String::new();
+    for part in spec.values {
+        match part {
+            ast::Expr::Constant(ast::ExprConstant {
+                value: ast::Constant::Str(s),
+                ..
+            }) => text.push_str(&s),
+            ast::Expr::FormattedValue(f)
+                if f.conversion == ConversionFlag::None && f.format_spec.is_none() =>
+            {
+                values.push(super::convert_expr(*f.value)?);
+                text.push_str(&format!("{{{}}}", values.len() - 1));
+            }
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "f-string format spec with a formatted value".to_string()
+            )),
+        }
+    }
+    Ok(text)
+}
+
+fn push_text(parts: &mut Vec<FStringPart>, text: &str) {
+    match parts.last_mut() {
+        Some(FStringPart::Text(last)) => last.push_str(text),
+        _ => parts.push(FStringPart::Text(text.to_string())),
+    }
+}
+
+/// `format.format(*args, **kwargs)`
+pub(super) fn str_format(
+    format: &str,
+    args: Vec<HirExpr>,
+    kwargs: Vec<(Symbol, HirExpr)>,
+) -> Result<HirExpr> {
+    let positional = args.len();
+    let names: Vec<Symbol> = kwargs.iter().map(|(name, _)| name.clone()).collect();
+    let mut values = args;
+    values.extend(kwargs.into_iter().map(|(_, value)| value));
+
+    let mut next = 0;
+    let mut field = |name: &str| -> Result<usize> {
+        if name.is_empty() {
+            next += 1;
+            return Ok(next - 1);
+        }
+        if let Ok(index) = name.parse::<usize>() {
+            return Ok(index);
+        }
+        match names.iter().position(|n| n == name) {
+            Some(i) => Ok(positional + i),
+            None if name.contains(['.', '[']) => transpile_bail!(ErrorKind::UnsupportedFeature(
+                format!("str.format() field `{{{name}}}` with attribute or index access")
+            )),
+            None => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "str.format() has no argument for field `{{{name}}}`"
+            ))),
+        }
+    };
+
+    let mut parts = Vec::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.next_if_eq(&'{').is_some() => push_text(&mut parts, "{"),
+            '}' if chars.next_if_eq(&'}').is_some() => push_text(&mut parts, "}"),
+            '{' => {
+                let body = take_field(&mut chars)?;
+                let (name, spec) = body.split_once(':').unwrap_or((&body, ""));
+                let (name, conversion) = match name.split_once('!') {
+                    Some((name, conversion)) => (name, conversion.chars().next()),
+                    None => (name, None),
+                };
+                let value = field(name)?;
+                // Fields nested in the spec are numbered after the field
+                let mut spec_text = String::new();
+                let mut spec_chars = spec.chars().peekable();
+                while let Some(c) = spec_chars.next() {
+                    if c == '{' {
+                        let nested = take_field(&mut spec_chars)?;
+                        spec_text.push_str(&format!("{{{}}}", field(&nested)?));
+                    } else {
+                        spec_text.push(c);
+                    }
+                }
+                parts.push(FStringPart::Field {
+                    value,
+                    conversion,
+                    spec: spec_text,
+                });
+            }
+            '}' => transpile_bail!(ErrorKind::CodeGenerationError(
+                "Single '}' encountered in format string".to_string()
+            )),
+            c => push_text(&mut parts, &c.to_string()),
+        }
+    }
+    let missing = parts.iter().find_map(|part| match part {
+        FStringPart::Field { value, .. } if *value >= values.len() => Some(*value),
+        _ => None,
+    });
+    if let Some(index) = missing {
+        transpile_bail!(ErrorKind::CodeGenerationError(format!(
+            "str.format() has no positional argument {index}"
+        )));
+    }
+    Ok(HirExpr::FString { parts, values })
+}
+
+/// The text of a replacement field up to its closing brace, which may
+/// enclose fields of its own
+fn take_field(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String> {
+    let mut body = String::new();
+    let mut depth = 0;
+    for c in chars.by_ref() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Ok(body),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        body.push(c);
+    }
+    transpile_bail!(ErrorKind::CodeGenerationError(
+        "Unclosed '{' in format string".to_string()
+    ))
+}
+
+/// `format % right`, printf-style
+pub(super) fn percent_format(format: &str, right: HirExpr) -> Result<HirExpr> {
+    let mut parts = Vec::new();
+    let mut fields = 0;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            push_text(&mut parts, &c.to_string());
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            push_text(&mut parts, "%");
+            continue;
+        }
+        if chars.peek() == Some(&'(') {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "%-formatting with a mapping key".to_string()
+            ));
+        }
+        let mut flags = String::new();
+        while let Some(flag) = chars.next_if(|c| "-0+ #".contains(*c)) {
+            flags.push(flag);
+        }
+        let mut width = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            width.push(digit);
+        }
+        let mut precision = None;
+        if chars.next_if_eq(&'.').is_some() {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            precision = Some(digits);
+        }
+        let Some(kind) = chars.next() else {
+            transpile_bail!(ErrorKind::CodeGenerationError(
+                "Incomplete format in %-formatting".to_string()
+            ));
+        };
+        let (spec, conversion) = printf_spec(&flags, &width, precision.as_deref(), kind)?;
+        fields += 1;
+        parts.push(FStringPart::Field {
+            value: fields - 1,
+            conversion,
+            spec,
+        });
+    }
+
+    let values = match right {
+        HirExpr::Tuple(values) => values,
+        value => vec![value],
+    };
+    if values.len() != fields {
+        transpile_bail!(ErrorKind::CodeGenerationError(format!(
+            "%-formatting has {fields} formats for {} values",
+            values.len()
+        )));
+    }
+    Ok(HirExpr::FString { parts, values })
+}
+
+/// The Python format spec and conversion doing what a printf-style format
+/// does
+fn printf_spec(
+    flags: &str,
+    width: &str,
+    precision: Option<&str>,
+    kind: char,
+) -> Result<(String, Option<char>)> {
+    let (kind, conversion) = match kind {
+        's' => (String::new(), None),
+        'r' | 'a' => (String::new(), Some(kind)),
+        'd' | 'i' | 'u' => ("d".to_string(), None),
+        'f' | 'F' | 'e' | 'E' | 'x' | 'X' | 'o' => (kind.to_string(), None),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "%{kind} in %-formatting"
+        ))),
+    };
+    if flags.contains(' ') {
+        transpile_bail!(ErrorKind::UnsupportedFeature(
+            "% flag ' ' in %-formatting".to_string()
+        ));
+    }
+    let mut spec = String::new();
+    if flags.contains('-') {
+        spec.push('<');
+    } else if !width.is_empty() && !flags.contains('0') {
+        spec.push('>');
+    }
+    if flags.contains('+') {
+        spec.push('+');
+    }
+    if flags.contains('#') {
+        spec.push('#');
+    }
+    if flags.contains('0') && !flags.contains('-') {
+        spec.push('0');
+    }
+    spec.push_str(width);
+    if let Some(precision) = precision {
+        spec.push('.');
+        spec.push_str(if precision.is_empty() { "0" } else { precision });
+    }
+    spec.push_str(&kind);
+    Ok((spec, conversion))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustpython_parser::Parse;
+
+    fn convert(source: &str) -> Result<HirExpr> {
+        super::super::convert_expr(ast::Expr::parse(source, "<test>").unwrap())
+    }
+
+    fn formatted(source: &str) -> (Vec<FStringPart>, Vec<HirExpr>) {
+        match convert(source).unwrap() {
+            HirExpr::FString { parts, values } => (parts, values),
+            other => panic!("not a formatted string: {other:?}"),
+        }
+    }
+
+    fn text(s: &str) -> FStringPart {
+        FStringPart::Text(s.to_string())
+    }
+
+    fn field(value: usize, conversion: Option<char>, spec: &str) -> FStringPart {
+        FStringPart::Field {
+            value,
+            conversion,
+            spec: spec.to_string(),
+        }
+    }
+
+    fn var(name: &str) -> HirExpr {
+        HirExpr::Var(name.to_string())
+    }
+
+    #[test]
+    fn test_joined_str() {
+        let (parts, values) = formatted(r#"f"{x:.2f} and {name!r:>{width}}{{""#);
+        assert_eq!(
+            parts,
+            vec![
+                field(0, None, ".2f"),
+                text(" and "),
+                field(2, Some('r'), ">{1}"),
+                text("{"),
+            ]
+        );
+        assert_eq!(values, vec![var("x"), var("width"), var("name")]);
+    }
+
+    #[test]
+    fn test_str_format() {
+        let (parts, values) = formatted(r#""{} {1:>8} {name} {0!r} {{}}".format(a, b, name=c)"#);
+        assert_eq!(
+            parts,
+            vec![
+                field(0, None, ""),
+                text(" "),
+                field(1, None, ">8"),
+                text(" "),
+                field(2, None, ""),
+                text(" "),
+                field(0, Some('r'), ""),
+                text(" {}"),
+            ]
+        );
+        assert_eq!(values, vec![var("a"), var("b"), var("c")]);
+        assert!(convert(r#""{2}".format(a)"#).is_err());
+        assert!(convert(r#""{p.x}".format(p=q)"#).is_err());
+    }
+
+    #[test]
+    fn test_percent_format() {
+        let (parts, values) = formatted(r#""%-5s|%05.1f|%d%% %r" % (a, b, c, d)"#);
+        assert_eq!(
+            parts,
+            vec![
+                field(0, None, "<5"),
+                text("|"),
+                field(1, None, "05.1f"),
+                text("|"),
+                field(2, None, "d"),
+                text("% "),
+                field(3, Some('r'), ""),
+            ]
+        );
+        assert_eq!(values.len(), 4);
+        assert_eq!(formatted(r#""%8x" % n"#).0, vec![field(0, None, ">8x")]);
+        assert!(convert(r#""%s %s" % (a,)"#).is_err());
+        assert!(convert(r#""%(key)s" % d"#).is_err());
+        // `%` of anything but a literal format string stays an operator
+        assert!(matches!(convert("a % b").unwrap(), HirExpr::Binary { .. }));
+    }
+}
@@ -1,18 +1,93 @@
-use crate::hir::{BinOp, FunctionProperties, HirExpr, HirStmt};
+use crate::arithmetic::{self, Overflow};
+use crate::exceptions::{annotations_require_result, ExceptionTable};
+use crate::hir::{
+    for_each_expr_in, AssignTarget, BinOp, ComprehensionKind, FunctionProperties, HirExpr, HirStmt,
+    Spanned, Symbol, Type,
+};
+use crate::indexing::{self, IndexPolicy};
+use depyler_annotations::TranspilationAnnotations;
+use std::collections::HashMap;
 
 pub struct FunctionAnalyzer;
 
 impl FunctionAnalyzer {
-    pub fn analyze(body: &[HirStmt]) -> FunctionProperties {
+    pub fn analyze(body: &[Spanned<HirStmt>]) -> FunctionProperties {
+        // Calls to other fallible functions are accounted for once the whole
+        // module is known, see `exceptions::propagate_fallibility`
+        let error_types = ExceptionTable::new().raised_types(body, &HashMap::new(), None);
+        let is_generator = Self::contains_yield(body);
         FunctionProperties {
             is_pure: Self::check_pure(body),
-            always_terminates: Self::check_termination(body),
+            always_terminates: Self::check_termination(body, is_generator),
             panic_free: Self::check_panic_free(body),
             max_stack_depth: Self::calculate_max_stack_depth(body),
+            can_fail: !error_types.is_empty(),
+            error_types,
+            is_generator,
         }
     }
 
-    fn check_pure(body: &[HirStmt]) -> bool {
+    /// Properties of a function body under its annotations, which can make
+    /// it fail where its code raises nothing
+    pub fn analyze_annotated(
+        params: &[(Symbol, Type)],
+        body: &[Spanned<HirStmt>],
+        annotations: &TranspilationAnnotations,
+    ) -> FunctionProperties {
+        let mut properties = Self::analyze(body);
+        let mut table = ExceptionTable::new();
+        if IndexPolicy::new(annotations) == IndexPolicy::Raise {
+            table = table.raising_index_errors(indexing::local_types(params, body));
+        }
+        match Overflow::new(annotations) {
+            Overflow::Raise => table = table.raising_overflow_errors(),
+            // Checked arithmetic panics where it overflows
+            Overflow::Panic => {
+                let mut overflows = false;
+                for_each_expr_in(body, &mut |expr| {
+                    overflows |= arithmetic::may_overflow(expr)
+                });
+                properties.panic_free &= !overflows;
+            }
+            _ => {}
+        }
+        properties.error_types = table.raised_types(body, &HashMap::new(), None);
+        properties.can_fail =
+            !properties.error_types.is_empty() || annotations_require_result(annotations);
+        properties
+    }
+
+    fn contains_yield(body: &[Spanned<HirStmt>]) -> bool {
+        body.iter().any(|stmt| match &stmt.node {
+            HirStmt::Yield(_) | HirStmt::YieldFrom(_) => true,
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                Self::contains_yield(then_body)
+                    || else_body.as_deref().is_some_and(Self::contains_yield)
+            }
+            HirStmt::While { body, .. }
+            | HirStmt::For { body, .. }
+            | HirStmt::With { body, .. } => Self::contains_yield(body),
+            HirStmt::Match { cases, .. } => cases.iter().any(|c| Self::contains_yield(&c.body)),
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                Self::contains_yield(body)
+                    || handlers.iter().any(|h| Self::contains_yield(&h.body))
+                    || Self::contains_yield(orelse)
+                    || Self::contains_yield(finalbody)
+            }
+            _ => false,
+        })
+    }
+
+    fn check_pure(body: &[Spanned<HirStmt>]) -> bool {
         // V1: Conservative - only if no calls to unknown functions
         for stmt in body {
             if Self::has_side_effects(stmt) {
@@ -22,12 +97,28 @@ impl FunctionAnalyzer {
         true
     }
 
-    fn has_side_effects(stmt: &HirStmt) -> bool {
-        match stmt {
+    fn has_side_effects(stmt: &Spanned<HirStmt>) -> bool {
+        match &stmt.node {
             HirStmt::Expr(HirExpr::Call { func, .. }) => {
                 // Whitelist of pure functions
                 !matches!(func.as_str(), "len" | "max" | "min" | "sum" | "abs")
             }
+            // Method calls may mutate their receiver; only read-only builtins are pure
+            HirStmt::Expr(HirExpr::MethodCall { method, .. }) => !Self::is_pure_method(method),
+            // Awaiting runs the awaited call
+            HirStmt::Expr(HirExpr::Await { value }) => {
+                Self::has_side_effects(&HirStmt::Expr(value.as_ref().clone()).into())
+            }
+            HirStmt::Assign { value, .. } | HirStmt::Return(Some(value))
+                if value.is_mutating_method_call() =>
+            {
+                true
+            }
+            // Writing to an attribute mutates the receiver
+            HirStmt::Assign {
+                target: AssignTarget::Attribute { .. },
+                ..
+            } => true,
             HirStmt::If {
                 then_body,
                 else_body,
@@ -41,37 +132,141 @@ impl FunctionAnalyzer {
             HirStmt::While { body, .. } | HirStmt::For { body, .. } => {
                 body.iter().any(Self::has_side_effects)
             }
+            HirStmt::Raise { .. } => true,
+            // Entering and exiting a context manager runs arbitrary code
+            HirStmt::With { .. } => true,
+            HirStmt::Match { cases, .. } => cases
+                .iter()
+                .any(|c| c.body.iter().any(Self::has_side_effects)),
+            // Yielding only hands a value to the consumer
+            HirStmt::Yield(Some(value)) | HirStmt::YieldFrom(value) => {
+                value.is_mutating_method_call()
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                body.iter().any(Self::has_side_effects)
+                    || handlers
+                        .iter()
+                        .any(|h| h.body.iter().any(Self::has_side_effects))
+                    || orelse.iter().any(Self::has_side_effects)
+                    || finalbody.iter().any(Self::has_side_effects)
+            }
             _ => false,
         }
     }
 
-    fn check_termination(body: &[HirStmt]) -> bool {
+    fn is_pure_method(method: &str) -> bool {
+        matches!(
+            method,
+            "get"
+                | "keys"
+                | "values"
+                | "items"
+                | "copy"
+                | "count"
+                | "index"
+                | "strip"
+                | "lstrip"
+                | "rstrip"
+                | "upper"
+                | "lower"
+                | "split"
+                | "join"
+                | "replace"
+                | "startswith"
+                | "endswith"
+                | "find"
+        )
+    }
+
+    fn check_termination(body: &[Spanned<HirStmt>], is_generator: bool) -> bool {
         // V1: Only guarantee for simple cases
-        for stmt in body {
-            if let HirStmt::While { .. } = stmt {
-                return false; // Can't guarantee termination with while loops
+        body.iter().all(|stmt| match &stmt.node {
+            // A generator suspends at every `yield`, so a loop that yields on
+            // each iteration still hands control back on each call to `next`
+            HirStmt::While { body, .. } | HirStmt::For { body, .. }
+                if is_generator && Self::yields_every_iteration(body) =>
+            {
+                Self::check_termination(body, is_generator)
             }
-            if let HirStmt::For { iter, .. } = stmt {
-                // Only guarantee for finite iterators
-                if !Self::is_finite_iterator(iter) {
-                    return false;
-                }
+            // Can't guarantee termination with while loops
+            HirStmt::While { .. } => false,
+            // Only guarantee for finite iterators
+            HirStmt::For { iter, body, .. } => {
+                Self::is_finite_iterator(iter) && Self::check_termination(body, is_generator)
             }
-        }
-        true
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                Self::check_termination(then_body, is_generator)
+                    && else_body
+                        .as_deref()
+                        .is_none_or(|b| Self::check_termination(b, is_generator))
+            }
+            HirStmt::With { body, .. } => Self::check_termination(body, is_generator),
+            HirStmt::Match { cases, .. } => cases
+                .iter()
+                .all(|c| Self::check_termination(&c.body, is_generator)),
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                Self::check_termination(body, is_generator)
+                    && handlers
+                        .iter()
+                        .all(|h| Self::check_termination(&h.body, is_generator))
+                    && Self::check_termination(orelse, is_generator)
+                    && Self::check_termination(finalbody, is_generator)
+            }
+            _ => true,
+        })
+    }
+
+    /// Whether a loop body reaches a `yield` on every iteration: one that
+    /// runs unconditionally, or in both branches of an `if`
+    fn yields_every_iteration(body: &[Spanned<HirStmt>]) -> bool {
+        body.iter().any(|stmt| match &stmt.node {
+            HirStmt::Yield(_) => true,
+            HirStmt::If {
+                then_body,
+                else_body: Some(else_body),
+                ..
+            } => Self::yields_every_iteration(then_body) && Self::yields_every_iteration(else_body),
+            HirStmt::With { body, .. } => Self::yields_every_iteration(body),
+            _ => false,
+        })
     }
 
     fn is_finite_iterator(expr: &HirExpr) -> bool {
         match expr {
-            HirExpr::List(_) | HirExpr::Tuple(_) | HirExpr::Dict(_) => true,
+            HirExpr::List(_) | HirExpr::Tuple(_) | HirExpr::Dict(_) | HirExpr::Set(_) => true,
             HirExpr::Call { func, .. } => {
                 matches!(func.as_str(), "range" | "enumerate" | "zip")
             }
+            // Dict views are snapshots of a finite collection
+            HirExpr::MethodCall { method, .. } => {
+                matches!(method.as_str(), "keys" | "values" | "items")
+            }
+            // Generator expressions are only as finite as their sources
+            HirExpr::Comprehension {
+                kind: ComprehensionKind::Generator,
+                generators,
+                ..
+            } => generators.iter().all(|g| Self::is_finite_iterator(&g.iter)),
+            HirExpr::Comprehension { .. } => true,
             _ => false,
         }
     }
 
-    fn check_panic_free(body: &[HirStmt]) -> bool {
+    fn check_panic_free(body: &[Spanned<HirStmt>]) -> bool {
         // V1: Check for obvious panic cases
         for stmt in body {
             if Self::has_panic_risk(stmt) {
@@ -81,12 +276,14 @@ impl FunctionAnalyzer {
         true
     }
 
-    fn has_panic_risk(stmt: &HirStmt) -> bool {
-        match stmt {
+    fn has_panic_risk(stmt: &Spanned<HirStmt>) -> bool {
+        match &stmt.node {
             HirStmt::Expr(expr) | HirStmt::Assign { value: expr, .. } => {
                 Self::expr_has_panic_risk(expr)
             }
-            HirStmt::Return(Some(expr)) => Self::expr_has_panic_risk(expr),
+            HirStmt::Return(Some(expr)) | HirStmt::Yield(Some(expr)) | HirStmt::YieldFrom(expr) => {
+                Self::expr_has_panic_risk(expr)
+            }
             HirStmt::If {
                 condition,
                 then_body,
@@ -104,6 +301,34 @@ impl FunctionAnalyzer {
             HirStmt::For { iter, body, .. } => {
                 Self::expr_has_panic_risk(iter) || body.iter().any(Self::has_panic_risk)
             }
+            HirStmt::With { items, body } => {
+                items
+                    .iter()
+                    .any(|item| Self::expr_has_panic_risk(&item.context_expr))
+                    || body.iter().any(Self::has_panic_risk)
+            }
+            HirStmt::Match { subject, cases } => {
+                Self::expr_has_panic_risk(subject)
+                    || cases.iter().any(|c| {
+                        c.guard.as_ref().is_some_and(Self::expr_has_panic_risk)
+                            || c.body.iter().any(Self::has_panic_risk)
+                    })
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                body.iter().any(Self::has_panic_risk)
+                    || handlers
+                        .iter()
+                        .any(|h| h.body.iter().any(Self::has_panic_risk))
+                    || orelse.iter().any(Self::has_panic_risk)
+                    || finalbody.iter().any(Self::has_panic_risk)
+            }
+            // Conservatively assume the nested function is called
+            HirStmt::FunctionDef { func, .. } => func.body.iter().any(Self::has_panic_risk),
             _ => false,
         }
     }
@@ -118,19 +343,56 @@ impl FunctionAnalyzer {
             HirExpr::Binary { left, right, .. } => {
                 Self::expr_has_panic_risk(left) || Self::expr_has_panic_risk(right)
             }
-            HirExpr::Call { args, .. } => args.iter().any(Self::expr_has_panic_risk),
+            HirExpr::Call { args, kwargs, .. } => args
+                .iter()
+                .chain(kwargs.iter().map(|(_, v)| v))
+                .any(Self::expr_has_panic_risk),
+            // `pop`, `remove` and `index` raise when the element is missing
+            HirExpr::MethodCall { method, .. }
+                if matches!(method.as_str(), "pop" | "remove" | "index") =>
+            {
+                true
+            }
+            HirExpr::MethodCall {
+                receiver,
+                args,
+                kwargs,
+                ..
+            } => {
+                Self::expr_has_panic_risk(receiver)
+                    || args
+                        .iter()
+                        .chain(kwargs.iter().map(|(_, v)| v))
+                        .any(Self::expr_has_panic_risk)
+            }
+            HirExpr::Await { value } | HirExpr::Lambda { body: value, .. } => {
+                Self::expr_has_panic_risk(value)
+            }
+            HirExpr::Comprehension {
+                element,
+                value,
+                generators,
+                ..
+            } => {
+                Self::expr_has_panic_risk(element)
+                    || value.as_deref().is_some_and(Self::expr_has_panic_risk)
+                    || generators.iter().any(|g| {
+                        Self::expr_has_panic_risk(&g.iter)
+                            || g.conditions.iter().any(Self::expr_has_panic_risk)
+                    })
+            }
             _ => false,
         }
     }
 
-    fn calculate_max_stack_depth(body: &[HirStmt]) -> Option<usize> {
+    fn calculate_max_stack_depth(body: &[Spanned<HirStmt>]) -> Option<usize> {
         // Simple estimation for V1
         Some(Self::estimate_stack_depth(body, 0))
     }
 
-    fn estimate_stack_depth(body: &[HirStmt], current: usize) -> usize {
+    fn estimate_stack_depth(body: &[Spanned<HirStmt>], current: usize) -> usize {
         body.iter().fold(current, |max_depth, stmt| {
-            let stmt_depth = match stmt {
+            let stmt_depth = match &stmt.node {
                 HirStmt::If {
                     then_body,
                     else_body,
@@ -143,9 +405,29 @@ impl FunctionAnalyzer {
                         .unwrap_or(current);
                     then_depth.max(else_depth)
                 }
-                HirStmt::While { body, .. } | HirStmt::For { body, .. } => {
-                    Self::estimate_stack_depth(body, current + 1)
-                }
+                HirStmt::While { body, .. }
+                | HirStmt::For { body, .. }
+                | HirStmt::With { body, .. } => Self::estimate_stack_depth(body, current + 1),
+                HirStmt::Match { cases, .. } => cases
+                    .iter()
+                    .map(|c| Self::estimate_stack_depth(&c.body, current + 1))
+                    .max()
+                    .unwrap_or(current),
+                HirStmt::Try {
+                    body,
+                    handlers,
+                    orelse,
+                    finalbody,
+                } => handlers
+                    .iter()
+                    .map(|h| Self::estimate_stack_depth(&h.body, current + 1))
+                    .chain([
+                        Self::estimate_stack_depth(body, current + 1),
+                        Self::estimate_stack_depth(orelse, current + 1),
+                        Self::estimate_stack_depth(finalbody, current + 1),
+                    ])
+                    .max()
+                    .unwrap_or(current),
                 _ => current,
             };
             max_depth.max(stmt_depth)
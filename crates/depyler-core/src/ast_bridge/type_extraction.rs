@@ -1,6 +1,8 @@
+use crate::error::{ErrorKind, SpanExt};
 use crate::hir::Type;
-use anyhow::{bail, Result};
-use rustpython_ast::{self as ast};
+use crate::transpile_bail;
+use anyhow::Result;
+use rustpython_ast::{self as ast, Ranged};
 
 pub struct TypeExtractor;
 
@@ -13,11 +15,55 @@ impl TypeExtractor {
         }
     }
 
+    /// Item type of a generator's `Iterator[T]`, `Iterable[T]` or
+    /// `Generator[T, S, R]` return annotation
+    pub fn extract_generator_item_type(returns: &Option<Box<ast::Expr>>) -> Result<Type> {
+        let Some(ast::Expr::Subscript(s)) = returns.as_deref() else {
+            return Ok(Type::Unknown);
+        };
+        match s.value.as_ref() {
+            ast::Expr::Name(n) if matches!(n.id.as_str(), "Iterator" | "Iterable") => {
+                Self::extract_type(s.slice.as_ref())
+            }
+            ast::Expr::Name(n) if n.id.as_str() == "Generator" => match s.slice.as_ref() {
+                ast::Expr::Tuple(t) if !t.elts.is_empty() => Self::extract_type(&t.elts[0]),
+                _ => transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                    "Generator type requires yield, send and return type parameters".to_string(),
+                )),
+            },
+            _ => Ok(Type::Unknown),
+        }
+    }
+
     pub fn extract_type(expr: &ast::Expr) -> Result<Type> {
+        Self::extract_annotation(expr).at(expr.range().into())
+    }
+
+    fn extract_annotation(expr: &ast::Expr) -> Result<Type> {
         match expr {
             ast::Expr::Name(n) => Self::extract_simple_type(&n.id),
             ast::Expr::Subscript(s) => Self::extract_generic_type(s),
-            _ => bail!("Unsupported type annotation"),
+            ast::Expr::Constant(c) => match &c.value {
+                // Forward references such as `-> "Point"` inside a class body
+                ast::Constant::Str(s) => Self::extract_simple_type(s),
+                ast::Constant::None => Ok(Type::None),
+                _ => transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                    "Unsupported type annotation".to_string(),
+                )),
+            },
+            // Module-qualified classes such as `threading.Lock`
+            ast::Expr::Attribute(a) => Self::extract_simple_type(&a.attr),
+            // PEP 604 unions such as `int | None`
+            ast::Expr::BinOp(b) if matches!(b.op, ast::Operator::BitOr) => {
+                let members = [b.left.as_ref(), b.right.as_ref()]
+                    .into_iter()
+                    .map(Self::extract_type)
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Self::union_of(members))
+            }
+            _ => transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                "Unsupported type annotation".to_string(),
+            )),
         }
     }
 
@@ -32,20 +78,36 @@ impl TypeExtractor {
             "list" => Type::List(Box::new(Type::Unknown)),
             // Handle plain 'dict' as a generic dict
             "dict" => Type::Dict(Box::new(Type::Unknown), Box::new(Type::Unknown)),
+            "set" | "frozenset" => Type::Set(Box::new(Type::Unknown)),
+            "Any" | "object" => Type::Unknown,
             name => Type::Custom(name.to_string()),
         })
     }
 
     fn extract_generic_type(s: &ast::ExprSubscript) -> Result<Type> {
-        if let ast::Expr::Name(n) = s.value.as_ref() {
-            match n.id.as_str() {
-                "List" => Self::extract_list_type(s),
-                "Dict" => Self::extract_dict_type(s),
-                "Optional" => Self::extract_optional_type(s),
-                _ => Ok(Type::Custom(n.id.to_string())),
+        // `typing.List[int]` as well as `List[int]`
+        let name = match s.value.as_ref() {
+            ast::Expr::Name(n) => n.id.as_str(),
+            ast::Expr::Attribute(a) => a.attr.as_str(),
+            _ => transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                "Complex type annotations not yet supported".to_string(),
+            )),
+        };
+        match name {
+            // Read-only abstract collections take the concrete type, which
+            // read-only parameters borrow
+            "List" | "list" | "Sequence" | "MutableSequence" | "Iterable" | "Iterator"
+            | "Collection" => Self::extract_list_type(s),
+            "Dict" | "dict" | "Mapping" | "MutableMapping" => Self::extract_dict_type(s),
+            "Set" | "FrozenSet" | "set" | "frozenset" | "AbstractSet" | "MutableSet" => {
+                Self::extract_set_type(s)
             }
-        } else {
-            bail!("Complex type annotations not yet supported")
+            "Tuple" | "tuple" => Self::extract_tuple_type(s),
+            "Optional" => Self::extract_optional_type(s),
+            "Union" => Self::extract_union_type(s),
+            "Literal" => Self::extract_literal_type(s),
+            "Callable" => Self::extract_callable_type(s),
+            name => Ok(Type::Custom(name.to_string())),
         }
     }
 
@@ -54,6 +116,11 @@ impl TypeExtractor {
         Ok(Type::List(Box::new(inner)))
     }
 
+    fn extract_set_type(s: &ast::ExprSubscript) -> Result<Type> {
+        let inner = Self::extract_type(s.slice.as_ref())?;
+        Ok(Type::Set(Box::new(inner)))
+    }
+
     fn extract_dict_type(s: &ast::ExprSubscript) -> Result<Type> {
         if let ast::Expr::Tuple(t) = s.slice.as_ref() {
             if t.elts.len() == 2 {
@@ -61,15 +128,149 @@ impl TypeExtractor {
                 let value = Self::extract_type(&t.elts[1])?;
                 Ok(Type::Dict(Box::new(key), Box::new(value)))
             } else {
-                bail!("Dict type requires exactly 2 type parameters")
+                transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                    "Dict type requires exactly 2 type parameters".to_string(),
+                ))
+            }
+        } else {
+            transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                "Invalid Dict type annotation".to_string(),
+            ))
+        }
+    }
+
+    /// `Tuple[int, str]`, or `Tuple[int, ...]` of any length as a list
+    fn extract_tuple_type(s: &ast::ExprSubscript) -> Result<Type> {
+        let elts = match s.slice.as_ref() {
+            ast::Expr::Tuple(t) => t.elts.as_slice(),
+            slice => std::slice::from_ref(slice),
+        };
+        if let [item, ast::Expr::Constant(c)] = elts {
+            if matches!(c.value, ast::Constant::Ellipsis) {
+                return Ok(Type::List(Box::new(Self::extract_type(item)?)));
+            }
+        }
+        let items = elts
+            .iter()
+            .map(Self::extract_type)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Type::Tuple(items))
+    }
+
+    fn extract_union_type(s: &ast::ExprSubscript) -> Result<Type> {
+        let members = match s.slice.as_ref() {
+            ast::Expr::Tuple(t) => t
+                .elts
+                .iter()
+                .map(Self::extract_type)
+                .collect::<Result<Vec<_>>>()?,
+            slice => vec![Self::extract_type(slice)?],
+        };
+        Ok(Self::union_of(members))
+    }
+
+    /// Nested unions flattened and duplicates dropped: a member of `None`
+    /// makes the rest optional, and a single member stands alone
+    fn union_of(members: Vec<Type>) -> Type {
+        let mut flat: Vec<Type> = Vec::new();
+        let mut optional = false;
+        fn add(ty: Type, flat: &mut Vec<Type>) {
+            if !flat.contains(&ty) {
+                flat.push(ty);
             }
+        }
+        for member in members {
+            match member {
+                Type::None => optional = true,
+                Type::Optional(inner) => {
+                    optional = true;
+                    add(*inner, &mut flat);
+                }
+                Type::Union(inner) => inner.into_iter().for_each(|t| add(t, &mut flat)),
+                ty => add(ty, &mut flat),
+            }
+        }
+        let ty = match flat.len() {
+            0 => return Type::None,
+            1 => flat.remove(0),
+            _ => Type::Union(flat),
+        };
+        if optional {
+            Type::Optional(Box::new(ty))
         } else {
-            bail!("Invalid Dict type annotation")
+            ty
         }
     }
 
+    /// `Literal["r", "w"]` of strings; literals of ints or bools are typed
+    /// as their values
+    fn extract_literal_type(s: &ast::ExprSubscript) -> Result<Type> {
+        let elts = match s.slice.as_ref() {
+            ast::Expr::Tuple(t) => t.elts.as_slice(),
+            slice => std::slice::from_ref(slice),
+        };
+        let values: Vec<&ast::Constant> = elts
+            .iter()
+            .filter_map(|e| match e {
+                ast::Expr::Constant(c) => Some(&c.value),
+                _ => None,
+            })
+            .collect();
+        if values.len() == elts.len() {
+            if values.iter().all(|v| matches!(v, ast::Constant::Str(_))) {
+                let mut strings: Vec<String> = Vec::new();
+                for value in values {
+                    if let ast::Constant::Str(value) = value {
+                        if !strings.contains(value) {
+                            strings.push(value.clone());
+                        }
+                    }
+                }
+                return Ok(Type::Literal(strings));
+            }
+            if values.iter().all(|v| matches!(v, ast::Constant::Bool(_))) {
+                return Ok(Type::Bool);
+            }
+            if values.iter().all(|v| matches!(v, ast::Constant::Int(_))) {
+                return Ok(Type::Int);
+            }
+        }
+        transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+            "Literal type requires values that are all strings, all ints or all bools".to_string(),
+        ))
+    }
+
     fn extract_optional_type(s: &ast::ExprSubscript) -> Result<Type> {
         let inner = Self::extract_type(s.slice.as_ref())?;
         Ok(Type::Optional(Box::new(inner)))
     }
+
+    /// `Callable[[A, B], R]`
+    fn extract_callable_type(s: &ast::ExprSubscript) -> Result<Type> {
+        let ast::Expr::Tuple(t) = s.slice.as_ref() else {
+            transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                "Callable type requires a parameter list and a return type".to_string(),
+            ));
+        };
+        let [params, ret] = t.elts.as_slice() else {
+            transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                "Callable type requires a parameter list and a return type".to_string(),
+            ));
+        };
+        let ast::Expr::List(params) = params else {
+            transpile_bail!(ErrorKind::InvalidTypeAnnotation(
+                "Callable with unspecified parameters is not supported".to_string(),
+            ));
+        };
+        let params = params
+            .elts
+            .iter()
+            .map(Self::extract_type)
+            .collect::<Result<Vec<_>>>()?;
+        let ret = Self::extract_type(ret)?;
+        Ok(Type::Function {
+            params,
+            ret: Box::new(ret),
+        })
+    }
 }
@@ -0,0 +1,629 @@
+//! Arbitrary-precision ints, chosen by the `integer_type = "bigint"` annotation
+//!
+//! Python ints never overflow. Under this strategy `int` is
+//! `num_bigint::BigInt`, apart from int locals a range analysis proves to fit
+//! an `i64`: a `range` loop's counter, or a hash kept below a constant
+//! modulus. Those stay `i64`, and so does arithmetic on them whose result is
+//! proven to fit as well; anything else is computed on `BigInt`s.
+
+use crate::hir::{
+    AssignTarget, BinOp, HirExpr, HirModule, HirStmt, Literal, Symbol, Type, UnaryOp,
+};
+use crate::indexing;
+use depyler_annotations::{IntegerType, TranspilationAnnotations};
+use std::collections::HashMap;
+use syn::parse_quote;
+
+/// The Rust type of ints under this strategy
+pub const BIGINT: &str = "BigInt";
+
+/// How often the analysis lets a local's range grow before giving up on
+/// bounding it, as a loop growing it on each iteration would
+const WIDENING_LIMIT: usize = 32;
+
+/// The values an int may take, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: i64,
+    pub hi: i64,
+}
+
+impl Interval {
+    const ALL: Interval = Interval {
+        lo: i64::MIN,
+        hi: i64::MAX,
+    };
+
+    fn point(n: i64) -> Self {
+        Self { lo: n, hi: n }
+    }
+
+    /// The range spanning `values`, `None` when it does not fit an `i64`
+    fn spanning(values: impl IntoIterator<Item = i128>) -> Option<Self> {
+        let (lo, hi) = values
+            .into_iter()
+            .fold((i128::MAX, i128::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+        Some(Self {
+            lo: i64::try_from(lo).ok()?,
+            hi: i64::try_from(hi).ok()?,
+        })
+    }
+
+    fn hull(self, other: Self) -> Self {
+        Self {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    fn corners(self, other: Self, f: impl Fn(i128, i128) -> i128) -> Option<Self> {
+        let (a, b) = (
+            [self.lo as i128, self.hi as i128],
+            [other.lo as i128, other.hi as i128],
+        );
+        Self::spanning(a.into_iter().flat_map(|x| b.map(|y| f(x, y))))
+    }
+
+    fn magnitude(self) -> i128 {
+        (self.lo as i128).abs().max((self.hi as i128).abs())
+    }
+
+    pub fn is_non_negative(self) -> bool {
+        self.lo >= 0
+    }
+}
+
+/// The result of `a op b` under Python's semantics, when it fits an `i64`
+fn arithmetic(op: BinOp, a: Interval, b: Interval) -> Option<Interval> {
+    match op {
+        BinOp::Add => a.corners(b, |x, y| x + y),
+        BinOp::Sub => a.corners(b, |x, y| x - y),
+        BinOp::Mul => a.corners(b, |x, y| x * y),
+        // Only positive divisors, for which `//` and `%` are Rust's
+        // `div_euclid` and `rem_euclid`
+        BinOp::FloorDiv if b.lo > 0 => a.corners(b, i128::div_euclid),
+        BinOp::Mod if b.lo > 0 && a.is_non_negative() => {
+            Interval::spanning([0, a.hi.min(b.hi - 1) as i128])
+        }
+        BinOp::Mod if b.lo > 0 => Some(Interval {
+            lo: 0,
+            hi: b.hi - 1,
+        }),
+        BinOp::Pow if b.lo >= 0 => {
+            let exponent = u32::try_from(b.hi).ok()?;
+            let largest = a.magnitude().max(1).checked_pow(exponent)?;
+            let smallest = if a.is_non_negative() { 0 } else { -largest };
+            Interval::spanning([smallest, largest])
+        }
+        _ => None,
+    }
+}
+
+/// What the analysis knows of a local
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    /// No assignment to it has been evaluated yet
+    Unset,
+    Bounded(Interval),
+    Unbounded,
+}
+
+impl Value {
+    fn join(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Unset, v) | (v, Value::Unset) => v,
+            (Value::Bounded(a), Value::Bounded(b)) => Value::Bounded(a.hull(b)),
+            _ => Value::Unbounded,
+        }
+    }
+
+    /// `f` of the bounds of both values, unknown until both are
+    fn combine(self, other: Value, f: impl Fn(Interval, Interval) -> Option<Interval>) -> Value {
+        match (self, other) {
+            (Value::Unbounded, _) | (_, Value::Unbounded) => Value::Unbounded,
+            (Value::Bounded(a), Value::Bounded(b)) => {
+                f(a, b).map_or(Value::Unbounded, Value::Bounded)
+            }
+            _ => Value::Unset,
+        }
+    }
+
+    fn map(self, f: impl Fn(Interval) -> Option<Interval>) -> Value {
+        self.combine(Value::Bounded(Interval::ALL), |a, _| f(a))
+    }
+}
+
+/// The values of `expr`, with those of the locals as `local` gives them
+fn evaluate(expr: &HirExpr, local: &dyn Fn(&str) -> Value) -> Value {
+    match expr {
+        HirExpr::Literal(Literal::Int(n)) => Value::Bounded(Interval::point(*n)),
+        HirExpr::Var(name) => local(name),
+        HirExpr::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => {
+            evaluate(operand, local).map(|a| Interval::spanning([-(a.hi as i128), -(a.lo as i128)]))
+        }
+        HirExpr::Unary {
+            op: UnaryOp::Pos,
+            operand,
+        } => evaluate(operand, local),
+        HirExpr::Binary { op, left, right } => {
+            match (op, evaluate(left, local), evaluate(right, local)) {
+                // A positive modulus bounds the remainder of any int
+                (BinOp::Mod, Value::Unbounded, Value::Bounded(b)) if b.lo > 0 => {
+                    Value::Bounded(Interval {
+                        lo: 0,
+                        hi: b.hi - 1,
+                    })
+                }
+                (_, a, b) => a.combine(b, |a, b| arithmetic(*op, a, b)),
+            }
+        }
+        HirExpr::Call { func, args, .. } => match (func.as_str(), args.as_slice()) {
+            ("len", [_]) => Value::Bounded(Interval {
+                lo: 0,
+                hi: i64::MAX,
+            }),
+            ("abs", [value]) => {
+                evaluate(value, local).map(|a| Interval::spanning([0, a.magnitude()]))
+            }
+            ("min" | "max", [first, rest @ ..]) if !rest.is_empty() => {
+                let lower = |a: i64, b: i64| if func == "min" { a.min(b) } else { a.max(b) };
+                rest.iter().fold(evaluate(first, local), |acc, arg| {
+                    acc.combine(evaluate(arg, local), |a, b| {
+                        Some(Interval {
+                            lo: lower(a.lo, b.lo),
+                            hi: lower(a.hi, b.hi),
+                        })
+                    })
+                })
+            }
+            _ => Value::Unbounded,
+        },
+        _ => Value::Unbounded,
+    }
+}
+
+/// Where a local gets its values from
+enum Source<'a> {
+    Value(&'a HirExpr),
+    /// The counter of a `for` loop over `range(args)`. Bounds that are not
+    /// `i64`s are converted to one, failing when they do not fit.
+    Range(&'a [HirExpr]),
+    /// The index `enumerate` counts with
+    Position,
+    Unknown,
+}
+
+impl Source<'_> {
+    fn evaluate(&self, local: &dyn Fn(&str) -> Value) -> Value {
+        match self {
+            Source::Value(expr) => evaluate(expr, local),
+            Source::Range(args) => {
+                // An unbounded bound is converted to an `i64`
+                let bound = |e: &HirExpr| match evaluate(e, local) {
+                    Value::Unbounded => Value::Bounded(Interval::ALL),
+                    v => v,
+                };
+                let step = args.get(2).map(indexing::int_literal);
+                let (start, stop) = match args {
+                    [stop] => (Value::Bounded(Interval::point(0)), bound(stop)),
+                    [start, stop, ..] => (bound(start), bound(stop)),
+                    [] => return Value::Unbounded,
+                };
+                start.combine(stop, |start, stop| {
+                    Some(match step {
+                        None | Some(Some(1..)) => Interval {
+                            lo: start.lo,
+                            hi: start.lo.max(stop.hi.saturating_sub(1)),
+                        },
+                        Some(Some(..=-1)) => Interval {
+                            lo: start.hi.min(stop.lo.saturating_add(1)),
+                            hi: start.hi,
+                        },
+                        _ => Interval::ALL,
+                    })
+                })
+            }
+            Source::Position => Value::Bounded(Interval {
+                lo: 0,
+                hi: i64::MAX,
+            }),
+            Source::Unknown => Value::Unbounded,
+        }
+    }
+}
+
+fn collect_sources<'a>(
+    body: &'a [crate::hir::Spanned<HirStmt>],
+    out: &mut Vec<(&'a Symbol, Source<'a>)>,
+) {
+    for stmt in body {
+        match &stmt.node {
+            HirStmt::Assign { target, value } => {
+                for (part, value) in target.split_value(value) {
+                    match part {
+                        AssignTarget::Symbol(name) => out.push((name, Source::Value(value))),
+                        part => {
+                            out.extend(part.symbols().into_iter().map(|n| (n, Source::Unknown)))
+                        }
+                    }
+                }
+            }
+            HirStmt::For { target, iter, body } => {
+                match (target, iter) {
+                    (AssignTarget::Symbol(name), HirExpr::Call { func, args, .. })
+                        if func == "range" =>
+                    {
+                        out.push((name, Source::Range(args)));
+                    }
+                    (AssignTarget::Tuple(parts), HirExpr::Call { func, .. })
+                        if func == "enumerate" && parts.len() == 2 =>
+                    {
+                        if let AssignTarget::Symbol(index) = &parts[0] {
+                            out.push((index, Source::Position));
+                        }
+                        out.extend(parts[1].symbols().into_iter().map(|n| (n, Source::Unknown)));
+                    }
+                    _ => out.extend(target.symbols().into_iter().map(|n| (n, Source::Unknown))),
+                }
+                collect_sources(body, out);
+            }
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                collect_sources(then_body, out);
+                collect_sources(else_body.as_deref().unwrap_or_default(), out);
+            }
+            HirStmt::While { body, .. } => collect_sources(body, out),
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                collect_sources(body, out);
+                for handler in handlers {
+                    out.extend(handler.name.iter().map(|n| (n, Source::Unknown)));
+                    collect_sources(&handler.body, out);
+                }
+                collect_sources(orelse, out);
+                collect_sources(finalbody, out);
+            }
+            HirStmt::With { items, body } => {
+                out.extend(
+                    items
+                        .iter()
+                        .filter_map(|item| item.optional_vars.as_ref())
+                        .map(|n| (n, Source::Unknown)),
+                );
+                collect_sources(body, out);
+            }
+            HirStmt::Match { cases, .. } => {
+                for case in cases {
+                    out.extend(
+                        case.pattern
+                            .bindings()
+                            .into_iter()
+                            .map(|n| (n, Source::Unknown)),
+                    );
+                    collect_sources(&case.body, out);
+                }
+            }
+            HirStmt::FunctionDef { nonlocals, .. } => {
+                out.extend(nonlocals.iter().map(|n| (n, Source::Unknown)));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The int locals of a function that always fit an `i64`, with their range.
+/// Parameters are not bounded; a local is when every value assigned to it is.
+pub fn bounded_locals(
+    params: &[(Symbol, Type)],
+    body: &[crate::hir::Spanned<HirStmt>],
+) -> HashMap<Symbol, Interval> {
+    let mut sources = Vec::new();
+    collect_sources(body, &mut sources);
+    let mut values: HashMap<&str, (Value, usize)> = sources
+        .iter()
+        .map(|(name, _)| (name.as_str(), (Value::Unset, 0)))
+        .collect();
+    for (param, _) in params {
+        values.insert(param, (Value::Unbounded, 0));
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (name, source) in &sources {
+            let value =
+                source.evaluate(&|local| values.get(local).map_or(Value::Unbounded, |(v, _)| *v));
+            let (current, growths) = values[name.as_str()];
+            let mut joined = current.join(value);
+            if joined != current {
+                if growths >= WIDENING_LIMIT {
+                    joined = Value::Unbounded;
+                }
+                values.insert(name.as_str(), (joined, growths + 1));
+                changed = true;
+            }
+        }
+    }
+    values
+        .into_iter()
+        .filter_map(|(name, (value, _))| match value {
+            Value::Bounded(interval) => Some((name.to_string(), interval)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// How the function being generated represents ints
+#[derive(Debug, Clone, Default)]
+pub struct IntScope {
+    /// `int` is `BigInt`
+    pub big: bool,
+    /// The function returns a `BigInt`
+    pub returns_big: bool,
+    /// Int locals proven to fit an `i64`, which are one
+    bounded: HashMap<Symbol, Interval>,
+}
+
+impl IntScope {
+    pub fn new(
+        params: &[(Symbol, Type)],
+        body: &[crate::hir::Spanned<HirStmt>],
+        ret_type: &Type,
+        annotations: &TranspilationAnnotations,
+    ) -> Self {
+        if annotations.integer_type != Some(IntegerType::BigInt) {
+            return Self::default();
+        }
+        Self {
+            big: true,
+            returns_big: *ret_type == Type::Int,
+            bounded: bounded_locals(params, body),
+        }
+    }
+
+    pub fn is_bounded(&self, name: &str) -> bool {
+        self.bounded.contains_key(name)
+    }
+
+    /// The values of int expression `expr`, when they are proven to fit an `i64`
+    pub fn interval(&self, expr: &HirExpr) -> Option<Interval> {
+        let local = |name: &str| {
+            self.bounded
+                .get(name)
+                .map_or(Value::Unbounded, |i| Value::Bounded(*i))
+        };
+        match evaluate(expr, &local) {
+            Value::Bounded(interval) => Some(interval),
+            _ => None,
+        }
+    }
+}
+
+/// Which parts of a function's signature are `BigInt`s
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigIntSignature {
+    /// Whether each parameter is one
+    pub params: Vec<bool>,
+    pub returns_big: bool,
+}
+
+/// The module's functions whose ints are `BigInt`s, by name
+pub fn signatures(module: &HirModule) -> HashMap<Symbol, BigIntSignature> {
+    module
+        .functions
+        .iter()
+        .filter(|f| f.annotations.integer_type == Some(IntegerType::BigInt))
+        .map(|f| {
+            let signature = BigIntSignature {
+                params: f.params.iter().map(|(_, ty)| *ty == Type::Int).collect(),
+                returns_big: f.ret_type == Type::Int,
+            };
+            (f.name.clone(), signature)
+        })
+        .collect()
+}
+
+/// `expr`, an int that fits an `i64`, as a `BigInt`
+pub fn from_small(hir: &HirExpr, expr: &syn::Expr) -> syn::Expr {
+    let bigint = bigint_ident();
+    match indexing::int_literal(hir) {
+        // An integer literal is an `i32` unless its type is given
+        Some(n) if i32::try_from(n).is_err() => {
+            let lit = syn::LitInt::new(
+                &format!("{}_i64", n.unsigned_abs()),
+                proc_macro2::Span::call_site(),
+            );
+            if n < 0 {
+                parse_quote! { #bigint::from(-#lit) }
+            } else {
+                parse_quote! { #bigint::from(#lit) }
+            }
+        }
+        _ => match expr {
+            syn::Expr::Paren(inner) => {
+                let inner = &inner.expr;
+                parse_quote! { #bigint::from(#inner) }
+            }
+            expr => parse_quote! { #bigint::from(#expr) },
+        },
+    }
+}
+
+/// `value`, a `BigInt` or a reference to one, converted to an `i64`,
+/// failing with `message` when it does not fit
+pub fn to_i64(value: &syn::Expr, message: &str) -> syn::Expr {
+    parse_quote! { i64::try_from(#value).expect(#message) }
+}
+
+pub fn bigint_ident() -> syn::Ident {
+    syn::Ident::new(BIGINT, proc_macro2::Span::call_site())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hir::Spanned;
+
+    fn var(name: &str) -> HirExpr {
+        HirExpr::Var(name.to_string())
+    }
+
+    fn int(n: i64) -> HirExpr {
+        HirExpr::Literal(Literal::Int(n))
+    }
+
+    fn binary(op: BinOp, left: HirExpr, right: HirExpr) -> HirExpr {
+        HirExpr::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    fn assign(name: &str, value: HirExpr) -> Spanned<HirStmt> {
+        HirStmt::Assign {
+            target: AssignTarget::Symbol(name.to_string()),
+            value,
+        }
+        .into()
+    }
+
+    fn range_loop(name: &str, args: Vec<HirExpr>, body: Vec<Spanned<HirStmt>>) -> Spanned<HirStmt> {
+        HirStmt::For {
+            target: AssignTarget::Symbol(name.to_string()),
+            iter: HirExpr::Call {
+                func: "range".to_string(),
+                args,
+                kwargs: vec![],
+            },
+            body,
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_interval_arithmetic() {
+        let a = Interval { lo: -3, hi: 5 };
+        let b = Interval { lo: 2, hi: 4 };
+        assert_eq!(
+            arithmetic(BinOp::Mul, a, b),
+            Some(Interval { lo: -12, hi: 20 })
+        );
+        assert_eq!(
+            arithmetic(BinOp::FloorDiv, a, b),
+            Some(Interval { lo: -2, hi: 2 })
+        );
+        assert_eq!(
+            arithmetic(BinOp::Mod, a, b),
+            Some(Interval { lo: 0, hi: 3 })
+        );
+        assert_eq!(arithmetic(BinOp::Mod, b, a), None);
+        assert_eq!(
+            arithmetic(BinOp::Pow, a, b),
+            Some(Interval { lo: -625, hi: 625 })
+        );
+        let huge = Interval::point(i64::MAX);
+        assert_eq!(arithmetic(BinOp::Add, huge, Interval::point(1)), None);
+    }
+
+    #[test]
+    fn test_bounded_locals() {
+        let params = vec![("n".to_string(), Type::Int)];
+        // h = 7; for i in range(100): h = (h * 31 + i) % 1000003
+        // result = 1; for j in range(2, n + 1): result *= j
+        let body = vec![
+            assign("h", int(7)),
+            range_loop(
+                "i",
+                vec![int(100)],
+                vec![assign(
+                    "h",
+                    binary(
+                        BinOp::Mod,
+                        binary(BinOp::Add, binary(BinOp::Mul, var("h"), int(31)), var("i")),
+                        int(1000003),
+                    ),
+                )],
+            ),
+            assign("result", int(1)),
+            range_loop(
+                "j",
+                vec![int(2), binary(BinOp::Add, var("n"), int(1))],
+                vec![assign(
+                    "result",
+                    binary(BinOp::Mul, var("result"), var("j")),
+                )],
+            ),
+        ];
+        let bounded = bounded_locals(&params, &body);
+        assert_eq!(bounded["h"], Interval { lo: 0, hi: 1000002 });
+        assert_eq!(bounded["i"], Interval { lo: 0, hi: 99 });
+        assert_eq!(
+            bounded["j"],
+            Interval {
+                lo: 2,
+                hi: i64::MAX - 1
+            }
+        );
+        assert!(!bounded.contains_key("result"));
+        assert!(!bounded.contains_key("n"));
+    }
+
+    #[test]
+    fn test_bounded_by_modulus() {
+        let params = vec![("n".to_string(), Type::Int)];
+        let body = vec![assign("b", binary(BinOp::Mod, var("n"), int(1000)))];
+        let bounded = bounded_locals(&params, &body);
+        assert_eq!(bounded["b"], Interval { lo: 0, hi: 999 });
+    }
+
+    #[test]
+    fn test_big_int_collections() {
+        let python = r#"
+# @depyler: integer_type = "bigint"
+from typing import List
+
+def pick(xs: List[int], i: int) -> int:
+    return xs[i]
+
+def total(xs: List[int]) -> int:
+    return sum(xs)
+
+def bucket(n: int) -> int:
+    b = n % 1000
+    return b * 2
+
+def digest(xs: List[int]) -> int:
+    h = 0
+    for x in xs:
+        h = (h * 31 + x) % 1000003
+    return h
+"#;
+        let code = crate::DepylerPipeline::new().transpile(python).unwrap();
+        // Remainders of a positive modulus are computed on `BigInt`s, then kept in `i64`s
+        assert!(code.contains("let b = i64::try_from(py_mod_big(& n, & BigInt::from(1000)))"));
+        assert!(code.contains("let mut h: i64 = 0;"));
+        let printed = crate::test_programs::run(
+            python,
+            r#"
+            let big = |s: &str| s.parse::<BigInt>().unwrap();
+            let xs = vec![big("100000000000000000000"), BigInt::from(-7), BigInt::from(5)];
+            println!("{} {} {}", pick(&xs, BigInt::from(0)), pick(&xs, BigInt::from(-2)), total(&xs));
+            println!("{} {}", bucket(big("-123456789012345678901")), digest(&xs));
+            "#,
+        );
+        assert_eq!(
+            printed,
+            "100000000000000000000 -7 99999999999999999998\n198 405097\n"
+        );
+    }
+}
@@ -0,0 +1,976 @@
+//! Borrow inference for parameters.
+//!
+//! A `str`, `list` or `dict` parameter that its function only reads is passed
+//! by reference, as `&str`, `&[T]` or `&HashMap<K, V>`, so callers no longer
+//! hand over or clone their values. A `str` parameter the function rebinds but
+//! otherwise only reads becomes a `Cow<'_, str>`, which stays borrowed unless
+//! the rebinding happens. Any other use (storing the value, passing it on
+//! to something that takes ownership, mutating it in place, capturing it in a
+//! closure) keeps the parameter owned.
+//!
+//! Whether a parameter handed to another function of the module is only read
+//! depends on how that function takes it, so the table is solved for the whole
+//! module at once, starting from every parameter borrowed.
+
+use crate::builtins;
+use crate::hir::*;
+use crate::sets;
+use crate::strings;
+use crate::type_mapper::StringStrategy;
+use depyler_annotations::{
+    OwnershipModel, StringStrategy as AnnotationStringStrategy, TranspilationAnnotations,
+};
+use std::collections::{HashMap, HashSet};
+
+/// How a function takes one of its parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParamPassing {
+    /// By value, as annotated
+    #[default]
+    Owned,
+    /// By shared reference: `&str`, `&[T]` or `&HashMap<K, V>`
+    Borrowed,
+    /// As `Cow<'_, str>`, owned only once the function rebinds it
+    Cow,
+}
+
+/// How a function takes its parameters, and which of them it returns
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionBorrows {
+    pub params: Vec<ParamPassing>,
+    /// Borrowed `str` parameters the returned `&str` may come from; empty
+    /// unless every `return` gives back one of them
+    pub returned: Vec<usize>,
+    /// `Cow` parameters the function assigns to
+    pub rebound: Vec<usize>,
+}
+
+impl FunctionBorrows {
+    pub fn passing(&self, index: usize) -> ParamPassing {
+        self.params.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn borrows_any(&self) -> bool {
+        self.params.iter().any(|p| *p != ParamPassing::Owned)
+    }
+}
+
+/// How the module's functions and methods take their parameters
+#[derive(Debug, Clone, Default)]
+pub struct BorrowTable {
+    functions: HashMap<Symbol, FunctionBorrows>,
+    /// Methods by name; only a name a single class defines is borrowed
+    /// through, since a call does not say which class it reaches
+    methods: HashMap<Symbol, FunctionBorrows>,
+}
+
+impl BorrowTable {
+    pub fn for_module(module: &HirModule, strategy: StringStrategy) -> Self {
+        let candidates = Candidates::for_module(module, strategy);
+        let mut table = candidates.optimistic();
+        loop {
+            let next = BorrowTable {
+                functions: candidates
+                    .functions
+                    .iter()
+                    .map(|(name, c)| (name.clone(), c.solve(&table)))
+                    .collect(),
+                methods: candidates
+                    .methods
+                    .iter()
+                    .map(|(name, c)| (name.clone(), c.solve(&table)))
+                    .collect(),
+            };
+            if next.functions == table.functions && next.methods == table.methods {
+                return table;
+            }
+            table = next;
+        }
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionBorrows> {
+        self.functions.get(name)
+    }
+
+    pub fn method(&self, name: &str) -> Option<&FunctionBorrows> {
+        self.methods.get(name)
+    }
+
+    /// Take the parameters of a function another module defines, imported
+    /// as `name`, as that module's table says
+    pub fn import_function(&mut self, name: &str, borrows: FunctionBorrows) {
+        self.functions.insert(name.to_string(), borrows);
+    }
+
+    /// Take the parameters of a method of a class another module defines,
+    /// unless the table already has a method by the name
+    pub fn import_method(&mut self, name: &str, borrows: FunctionBorrows) {
+        self.methods.entry(name.to_string()).or_insert(borrows);
+    }
+}
+
+/// Methods whose calls the code generator lowers itself, whatever the
+/// receiver, besides the `str` methods
+const LOWERED_METHODS: &[&str] = &[
+    "append", "extend", "insert", "pop", "remove", "index", "count", "copy", "sort", "get", "keys",
+    "values", "items", "update", "read", "write",
+];
+
+/// A function or method whose parameters may be borrowed
+struct Candidate<'m> {
+    params: Vec<(&'m Symbol, ParamKind)>,
+    ret_type: &'m Type,
+    body: &'m [Spanned<HirStmt>],
+    /// Returns `Result`, which a borrowed return value is not lent through
+    can_fail: bool,
+    cow_by_default: bool,
+}
+
+/// The kinds of parameter that can be borrowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParamKind {
+    Str,
+    List,
+    Dict,
+    Set,
+    /// Never borrowed
+    Other,
+}
+
+struct Candidates<'m> {
+    functions: HashMap<Symbol, Candidate<'m>>,
+    methods: HashMap<Symbol, Candidate<'m>>,
+}
+
+impl<'m> Candidates<'m> {
+    fn for_module(module: &'m HirModule, strategy: StringStrategy) -> Self {
+        let mut candidates = Candidates {
+            functions: HashMap::new(),
+            methods: HashMap::new(),
+        };
+        if strategy == StringStrategy::AlwaysOwned {
+            return candidates;
+        }
+        let cow_by_default = strategy == StringStrategy::CowByDefault;
+        let values = names_used_as_values(module);
+
+        for func in &module.functions {
+            if func.properties.is_generator || func.is_async || values.contains(&func.name) {
+                continue;
+            }
+            let params = candidate_params(&func.params, &func.param_spec, &func.annotations);
+            candidates.functions.insert(
+                func.name.clone(),
+                Candidate {
+                    params,
+                    ret_type: &func.ret_type,
+                    body: &func.body,
+                    can_fail: func.properties.can_fail,
+                    cow_by_default,
+                },
+            );
+        }
+
+        let mut definitions: HashMap<&Symbol, usize> = HashMap::new();
+        for method in module.classes.iter().flat_map(|c| &c.methods) {
+            *definitions.entry(&method.name).or_default() += 1;
+        }
+        for class in &module.classes {
+            for method in &class.methods {
+                if definitions[&method.name] > 1
+                    || method.name.starts_with("__")
+                    || LOWERED_METHODS.contains(&method.name.as_str())
+                    || strings::is_method(&method.name)
+                    || method.properties.is_generator
+                    || method.is_async
+                    || values.contains(&method.name)
+                {
+                    continue;
+                }
+                let params =
+                    candidate_params(&method.params, &method.param_spec, &method.annotations);
+                candidates.methods.insert(
+                    method.name.clone(),
+                    Candidate {
+                        params,
+                        ret_type: &method.ret_type,
+                        body: &method.body,
+                        can_fail: method.properties.can_fail,
+                        cow_by_default,
+                    },
+                );
+            }
+        }
+        candidates
+    }
+
+    /// Every candidate parameter taken by reference
+    fn optimistic(&self) -> BorrowTable {
+        let optimistic = |c: &Candidate| FunctionBorrows {
+            params: c
+                .params
+                .iter()
+                .map(|(_, kind)| match kind {
+                    ParamKind::Other => ParamPassing::Owned,
+                    _ => ParamPassing::Borrowed,
+                })
+                .collect(),
+            returned: vec![],
+            rebound: vec![],
+        };
+        BorrowTable {
+            functions: self
+                .functions
+                .iter()
+                .map(|(name, c)| (name.clone(), optimistic(c)))
+                .collect(),
+            methods: self
+                .methods
+                .iter()
+                .map(|(name, c)| (name.clone(), optimistic(c)))
+                .collect(),
+        }
+    }
+}
+
+/// Parameters by kind; annotations asking for owned strings or shared
+/// ownership rule borrowing out
+fn candidate_params<'m>(
+    params: &'m [(Symbol, Type)],
+    spec: &ParamSpec,
+    annotations: &TranspilationAnnotations,
+) -> Vec<(&'m Symbol, ParamKind)> {
+    params
+        .iter()
+        .map(|(name, ty)| {
+            let packed = spec.var_positional.as_ref() == Some(name)
+                || spec.var_keyword.as_ref().is_some_and(|(n, _)| n == name);
+            let kind = match ty {
+                _ if packed || annotations.ownership_model == OwnershipModel::Shared => {
+                    ParamKind::Other
+                }
+                Type::String
+                    if annotations.string_strategy != AnnotationStringStrategy::AlwaysOwned =>
+                {
+                    ParamKind::Str
+                }
+                Type::List(_) => ParamKind::List,
+                Type::Dict(_, _) => ParamKind::Dict,
+                Type::Set(_) => ParamKind::Set,
+                _ => ParamKind::Other,
+            };
+            (name, kind)
+        })
+        .collect()
+}
+
+impl Candidate<'_> {
+    fn solve(&self, table: &BorrowTable) -> FunctionBorrows {
+        let mut uses = Uses {
+            table,
+            params: self
+                .params
+                .iter()
+                .filter(|(_, kind)| *kind != ParamKind::Other)
+                .map(|(name, kind)| ((*name).clone(), *kind))
+                .collect(),
+            consumed: HashSet::new(),
+            rebound: HashSet::new(),
+            returned: HashSet::new(),
+            other_returns: false,
+        };
+        uses.body(self.body);
+
+        // Returning a parameter hands it to the caller: a `str` may be lent
+        // back as `&str`, anything else must be owned
+        let lends = self.ret_type == &Type::String
+            && !self.can_fail
+            && !self.cow_by_default
+            && !uses.other_returns
+            && uses.returned.iter().all(|name| {
+                uses.params.get(name) == Some(&ParamKind::Str)
+                    && !uses.rebound.contains(name)
+                    && !uses.consumed.contains(name)
+            });
+        let params: Vec<ParamPassing> = self
+            .params
+            .iter()
+            .map(|(name, kind)| {
+                let (rebound, returned) =
+                    (uses.rebound.contains(*name), uses.returned.contains(*name));
+                match kind {
+                    _ if uses.consumed.contains(*name) => ParamPassing::Owned,
+                    ParamKind::Other => ParamPassing::Owned,
+                    ParamKind::Str if rebound || self.cow_by_default => ParamPassing::Cow,
+                    ParamKind::Str if returned && !lends => ParamPassing::Owned,
+                    ParamKind::Str => ParamPassing::Borrowed,
+                    ParamKind::List | ParamKind::Dict | ParamKind::Set if rebound || returned => {
+                        ParamPassing::Owned
+                    }
+                    ParamKind::List | ParamKind::Dict | ParamKind::Set => ParamPassing::Borrowed,
+                }
+            })
+            .collect();
+        let indices = |f: &dyn Fn(&Symbol, ParamPassing) -> bool| {
+            self.params
+                .iter()
+                .enumerate()
+                .filter(|(i, (name, _))| f(name, params[*i]))
+                .map(|(i, _)| i)
+                .collect()
+        };
+        let returned = indices(&|name, passing| {
+            uses.returned.contains(name) && passing == ParamPassing::Borrowed
+        });
+        let rebound =
+            indices(&|name, passing| uses.rebound.contains(name) && passing == ParamPassing::Cow);
+        FunctionBorrows {
+            params,
+            returned,
+            rebound,
+        }
+    }
+}
+
+/// How a body uses its candidate parameters
+struct Uses<'t> {
+    table: &'t BorrowTable,
+    params: HashMap<Symbol, ParamKind>,
+    /// Moved, stored, mutated or captured
+    consumed: HashSet<Symbol>,
+    /// Assigned a new value
+    rebound: HashSet<Symbol>,
+    /// Given back by a `return`
+    returned: HashSet<Symbol>,
+    /// Some `return` gives back something other than a parameter
+    other_returns: bool,
+}
+
+impl Uses<'_> {
+    fn param<'e>(&self, expr: &'e HirExpr) -> Option<&'e Symbol> {
+        match expr {
+            HirExpr::Var(name) if self.params.contains_key(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn consume(&mut self, name: &Symbol) {
+        if self.params.contains_key(name) {
+            self.consumed.insert(name.clone());
+        }
+    }
+
+    fn body(&mut self, body: &[Spanned<HirStmt>]) {
+        for stmt in body {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &HirStmt) {
+        match stmt {
+            HirStmt::Assign { target, value } => {
+                match target {
+                    AssignTarget::Symbol(name) if self.params.contains_key(name) => {
+                        self.rebound.insert(name.clone());
+                    }
+                    AssignTarget::Symbol(_) => {}
+                    AssignTarget::Index { base, index } => {
+                        match self.param(base) {
+                            Some(name) => self.consume(&name.clone()),
+                            None => self.operand(base),
+                        }
+                        self.value(index);
+                    }
+                    AssignTarget::Attribute { value: base, .. } => self.operand(base),
+                    AssignTarget::Tuple(_) => {
+                        for name in target.symbols() {
+                            self.consume(name);
+                        }
+                    }
+                }
+                self.value(value);
+            }
+            HirStmt::Return(Some(value)) => match self.param(value) {
+                Some(name) => {
+                    self.returned.insert(name.clone());
+                }
+                None => {
+                    self.other_returns = true;
+                    self.value(value);
+                }
+            },
+            HirStmt::Return(None) => self.other_returns = true,
+            HirStmt::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                self.operand(condition);
+                self.body(then_body);
+                if let Some(else_body) = else_body {
+                    self.body(else_body);
+                }
+            }
+            HirStmt::While { condition, body } => {
+                self.operand(condition);
+                self.body(body);
+            }
+            HirStmt::For { target, iter, body } => {
+                for name in target.symbols() {
+                    self.consume(name);
+                }
+                // Borrowed sequences are iterated by cloning each item
+                if self.param(iter).is_none() {
+                    self.value(iter);
+                }
+                self.body(body);
+            }
+            HirStmt::Expr(expr) => self.operand(expr),
+            HirStmt::Raise { exception } => {
+                if let Some(exception) = exception {
+                    self.value(exception);
+                }
+            }
+            HirStmt::Yield(value) => {
+                if let Some(value) = value {
+                    self.value(value);
+                }
+            }
+            HirStmt::YieldFrom(value) => self.value(value),
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                self.body(body);
+                for handler in handlers {
+                    if let Some(name) = &handler.name {
+                        self.consume(name);
+                    }
+                    self.body(&handler.body);
+                }
+                self.body(orelse);
+                self.body(finalbody);
+            }
+            HirStmt::With { items, body } => {
+                for item in items {
+                    self.value(&item.context_expr);
+                    if let Some(name) = &item.optional_vars {
+                        self.consume(name);
+                    }
+                }
+                self.body(body);
+            }
+            HirStmt::Match { subject, cases } => {
+                self.value(subject);
+                for case in cases {
+                    for name in case.pattern.bindings() {
+                        self.consume(name);
+                    }
+                    if let Some(guard) = &case.guard {
+                        self.operand(guard);
+                    }
+                    self.body(&case.body);
+                }
+            }
+            // Closures capture by value
+            HirStmt::FunctionDef { func, .. } => {
+                let names: Vec<Symbol> = self.params.keys().cloned().collect();
+                for name in names {
+                    if func.params.iter().any(|(p, _)| *p == name) {
+                        continue;
+                    }
+                    if body_mentions(&func.body, &name) {
+                        self.consumed.insert(name);
+                    }
+                }
+            }
+        }
+    }
+
+    /// An expression whose value is taken: a bare parameter is moved
+    fn value(&mut self, expr: &HirExpr) {
+        match self.param(expr) {
+            Some(name) => self.consume(&name.clone()),
+            None => self.operand(expr),
+        }
+    }
+
+    /// An expression whose value is only inspected; its own operands are
+    /// classified by how the operation uses them
+    fn operand(&mut self, expr: &HirExpr) {
+        match expr {
+            HirExpr::Literal(_) | HirExpr::Var(_) => {}
+            HirExpr::Binary { op, left, right } => {
+                let kind = |e: &HirExpr| self.param(e).map(|n| self.params[n]);
+                // `String + &str` and `==` between owned and borrowed values
+                // compile; membership only looks into its container, and
+                // looks a key up in a dict, set or string by reference; set
+                // operators take both sets by reference
+                let compares = |k: ParamKind| !matches!(k, ParamKind::Dict | ParamKind::Set);
+                let set_operator = matches!(
+                    op,
+                    BinOp::BitOr | BinOp::BitAnd | BinOp::BitXor | BinOp::Sub
+                );
+                let left_read = match op {
+                    BinOp::In | BinOp::NotIn => matches!(
+                        kind(right),
+                        Some(ParamKind::Dict | ParamKind::Set | ParamKind::Str)
+                    ),
+                    BinOp::Eq | BinOp::NotEq => kind(left).is_some_and(compares),
+                    _ => set_operator && kind(left) == Some(ParamKind::Set),
+                };
+                let right_read = match op {
+                    BinOp::Add | BinOp::In | BinOp::NotIn => true,
+                    BinOp::Eq | BinOp::NotEq => kind(right).is_some_and(compares),
+                    _ => set_operator && kind(right) == Some(ParamKind::Set),
+                };
+                if !left_read {
+                    self.value(left);
+                }
+                if !right_read {
+                    self.value(right);
+                }
+            }
+            HirExpr::Unary { operand, .. } => self.value(operand),
+            HirExpr::Call { func, args, kwargs } => {
+                let callee = self.table.function(func).cloned();
+                for (i, arg) in args.iter().enumerate() {
+                    let reads = match &callee {
+                        Some(callee) => callee.passing(i) != ParamPassing::Owned,
+                        None => builtins::reads_arguments(func, args.len()),
+                    };
+                    if !reads {
+                        self.value(arg);
+                    }
+                }
+                for (_, value) in kwargs {
+                    self.value(value);
+                }
+            }
+            HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                kwargs,
+            } => {
+                let set_receiver =
+                    self.param(receiver).map(|n| self.params[n]) == Some(ParamKind::Set);
+                let reads_receiver = match set_receiver {
+                    true => sets::reads_receiver(method),
+                    false => reads_receiver(method, args.len()),
+                };
+                if self.param(receiver).is_none() || !reads_receiver {
+                    self.value(receiver);
+                }
+                // Modifying an element or entry modifies the container
+                if !reads_receiver {
+                    let mut container = receiver.as_ref();
+                    while let HirExpr::Index { base, .. } = container {
+                        container = base;
+                    }
+                    if let Some(name) = self.param(container) {
+                        self.consume(&name.clone());
+                    }
+                }
+                let callee = self.table.method(method).cloned();
+                for (i, arg) in args.iter().enumerate() {
+                    let reads = match &callee {
+                        Some(callee) => callee.passing(i) != ParamPassing::Owned,
+                        None => {
+                            sets::reads_argument(method) || reads_argument(method, args.len(), i)
+                        }
+                    };
+                    if !reads {
+                        self.value(arg);
+                    }
+                }
+                for (_, value) in kwargs {
+                    self.value(value);
+                }
+            }
+            HirExpr::Index { base, index } => {
+                self.operand(base);
+                self.value(index);
+            }
+            HirExpr::Slice {
+                base,
+                start,
+                stop,
+                step,
+            } => {
+                self.operand(base);
+                for bound in [start, stop, step].into_iter().flatten() {
+                    self.value(bound);
+                }
+            }
+            HirExpr::Attribute { value, .. } => self.value(value),
+            // `format!` takes its arguments by reference
+            HirExpr::FString { values, .. } => {
+                for value in values {
+                    self.operand(value);
+                }
+            }
+            HirExpr::List(elts) | HirExpr::Set(elts) | HirExpr::Tuple(elts) => {
+                for elt in elts {
+                    self.value(elt);
+                }
+            }
+            HirExpr::Dict(items) => {
+                for (key, value) in items {
+                    self.value(key);
+                    self.value(value);
+                }
+            }
+            HirExpr::Comprehension {
+                element,
+                value,
+                generators,
+                ..
+            } => {
+                for generator in generators {
+                    self.consume(&generator.target);
+                    // Sources are iterated by reference or by cloning each item
+                    self.operand(&generator.iter);
+                    for condition in &generator.conditions {
+                        self.operand(condition);
+                    }
+                }
+                self.value(element);
+                if let Some(value) = value {
+                    self.value(value);
+                }
+            }
+            HirExpr::Lambda { params, body } => {
+                let names: Vec<Symbol> = self.params.keys().cloned().collect();
+                for name in names {
+                    if !params.contains(&name) && mentions(body, &name) {
+                        self.consumed.insert(name);
+                    }
+                }
+            }
+            HirExpr::Await { value } => self.value(value),
+            HirExpr::Borrow { expr, .. } => self.operand(expr),
+        }
+    }
+}
+
+/// Whether `expr` only reads `name`, bound to items of type `item`, so a
+/// reference to each item serves as well as the item itself
+pub fn only_reads(expr: &HirExpr, name: &Symbol, item: &Type, table: &BorrowTable) -> bool {
+    let kind = match item {
+        Type::String => ParamKind::Str,
+        Type::List(_) => ParamKind::List,
+        Type::Dict(_, _) => ParamKind::Dict,
+        Type::Set(_) => ParamKind::Set,
+        _ => return false,
+    };
+    let mut uses = Uses {
+        table,
+        params: HashMap::from([(name.clone(), kind)]),
+        consumed: HashSet::new(),
+        rebound: HashSet::new(),
+        returned: HashSet::new(),
+        other_returns: false,
+    };
+    uses.value(expr);
+    uses.consumed.is_empty()
+}
+
+/// Lowered methods that only read a borrowed receiver
+fn reads_receiver(method: &str, arity: usize) -> bool {
+    strings::is_method(method)
+        || matches!(
+            (method, arity),
+            ("get", 1 | 2) | ("keys" | "values" | "items", 0)
+        )
+}
+
+/// Lowered methods that only read their `index`th borrowed argument; a
+/// list compares the argument of `index` and `count` by value, and the
+/// default of `get` is returned
+fn reads_argument(method: &str, arity: usize, index: usize) -> bool {
+    (strings::is_method(method) && !matches!(method, "index" | "count"))
+        || (method, arity) == ("extend", 1)
+        || (method, index) == ("get", 0)
+}
+
+/// Functions and methods referred to without being called
+fn names_used_as_values(module: &HirModule) -> HashSet<Symbol> {
+    let functions: HashSet<&Symbol> = module.functions.iter().map(|f| &f.name).collect();
+    let mut names = HashSet::new();
+    let bodies = module.functions.iter().map(|f| &f.body).chain(
+        module
+            .classes
+            .iter()
+            .flat_map(|c| c.methods.iter().map(|m| &m.body)),
+    );
+    for body in bodies {
+        for_each_expr_in(body, &mut |expr| match expr {
+            HirExpr::Var(name) if functions.contains(name) => {
+                names.insert(name.clone());
+            }
+            HirExpr::Attribute { attr, .. } => {
+                names.insert(attr.clone());
+            }
+            _ => {}
+        });
+    }
+    names
+}
+
+fn body_mentions(body: &[Spanned<HirStmt>], name: &str) -> bool {
+    let mut found = false;
+    for_each_expr_in(body, &mut |expr| {
+        found |= matches!(expr, HirExpr::Var(v) if v == name);
+    });
+    found
+        || body.iter().any(|stmt| match &stmt.node {
+            HirStmt::Assign { target, .. } | HirStmt::For { target, .. } => {
+                target.symbols().iter().any(|s| *s == name)
+            }
+            _ => false,
+        })
+}
+
+fn mentions(expr: &HirExpr, name: &str) -> bool {
+    body_mentions(&[HirStmt::Expr(expr.clone()).into()], name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_bridge::AstBridge;
+    use rustpython_ast::Suite;
+    use rustpython_parser::Parse;
+    use ParamPassing::*;
+
+    fn module(source: &str) -> HirModule {
+        let statements = Suite::parse(source, "<test>").unwrap();
+        let ast = rustpython_ast::Mod::Module(rustpython_ast::ModModule {
+            body: statements,
+            type_ignores: vec![],
+            range: Default::default(),
+        });
+        AstBridge::new().python_to_hir(ast).unwrap()
+    }
+
+    fn table(source: &str) -> BorrowTable {
+        BorrowTable::for_module(&module(source), StringStrategy::InferBorrowing)
+    }
+
+    #[test]
+    fn test_read_only_parameters_are_borrowed() {
+        let table = table(
+            r#"
+from typing import Dict, List
+
+def describe(name: str, xs: List[int], counts: Dict[str, int], n: int) -> int:
+    total = 0
+    for x in xs:
+        total = total + x
+    if name == "x":
+        total = total + len(counts)
+    return total + len(name) + n
+"#,
+        );
+        let borrows = table.function("describe").unwrap();
+        assert_eq!(borrows.params, vec![Borrowed, Borrowed, Borrowed, Owned]);
+        assert!(borrows.returned.is_empty());
+    }
+
+    #[test]
+    fn test_keys_and_parsed_strings_are_borrowed() {
+        let source = r#"
+from typing import Dict, Set
+
+def lookup(d: Dict[str, int], k: str) -> int:
+    if k in d:
+        return d.get(k, 0) + 1
+    return d.get(k, -1)
+
+def member(s: Set[str], k: str, text: str) -> bool:
+    if k in s:
+        return True
+    return k in text
+
+def parse(s: str) -> int:
+    return int(s) * 2
+
+def label(name: str, n: int) -> str:
+    return "n" + name + ": " + str(n)
+"#;
+        let table = table(source);
+        assert_eq!(
+            table.function("lookup").unwrap().params,
+            vec![Borrowed, Borrowed]
+        );
+        assert_eq!(
+            table.function("member").unwrap().params,
+            vec![Borrowed, Borrowed, Borrowed]
+        );
+        assert_eq!(table.function("parse").unwrap().params, vec![Borrowed]);
+        let output = crate::test_programs::run(
+            source,
+            r#"
+let d: std::collections::HashMap<String, i32> = [("a".to_string(), 4)].into_iter().collect();
+let s: std::collections::HashSet<String> = ["b".to_string()].into_iter().collect();
+println!("{} {}", lookup(&d, "a"), lookup(&d, "z"));
+println!("{} {} {}", member(&s, "b", "xyz"), member(&s, "y", "xyz"), member(&s, "q", "xyz"));
+println!("{} {}", parse(" 21 "), label("x", 3));
+"#,
+        );
+        assert_eq!(output, "5 -1\ntrue true false\n42 nx: 3\n");
+    }
+
+    #[test]
+    fn test_borrowed_model_borrows_what_the_body_allows() {
+        let source = r#"
+from typing import List
+
+# @depyler: string_strategy = "zero_copy"
+# @depyler: ownership = "borrowed"
+def shout(text: str) -> str:
+    return text.upper()
+
+# @depyler: ownership = "borrowed"
+def keep(names: List[str], name: str) -> List[str]:
+    names.append(name)
+    return names
+
+# @depyler: string_strategy = "zero_copy"
+# @depyler: ownership = "borrowed"
+def difficulty(level: int) -> str:
+    if level == 1:
+        return "Easy"
+    return "Hard"
+"#;
+        let table = table(source);
+        assert_eq!(table.function("shout").unwrap().params, vec![Borrowed]);
+        assert_eq!(table.function("keep").unwrap().params, vec![Owned, Owned]);
+        let output = crate::test_programs::run(
+            source,
+            r#"println!("{} {:?} {}", shout("hi"), keep(vec![], "a".to_string()), difficulty(1));"#,
+        );
+        assert_eq!(output, "HI [\"a\"] Easy\n");
+    }
+
+    #[test]
+    fn test_consumed_parameters_stay_owned() {
+        let table = table(
+            r#"
+from typing import List
+
+class Holder:
+    def __init__(self, label: str):
+        self.label = label
+
+def push(xs: List[int]) -> None:
+    xs.append(1)
+
+def keep(label: str) -> Holder:
+    return Holder(label)
+"#,
+        );
+        assert_eq!(table.function("push").unwrap().params, vec![Owned]);
+        assert_eq!(table.function("keep").unwrap().params, vec![Owned]);
+    }
+
+    #[test]
+    fn test_rebound_string_is_cow() {
+        let table = table(
+            r#"
+def normalize(s: str) -> str:
+    if s == "x":
+        s = s.upper()
+    return s
+"#,
+        );
+        let borrows = table.function("normalize").unwrap();
+        assert_eq!(borrows.params, vec![Cow]);
+        assert_eq!(borrows.rebound, vec![0]);
+        assert!(borrows.returned.is_empty());
+    }
+
+    #[test]
+    fn test_returned_string_is_lent() {
+        let table = table(
+            r#"
+from typing import List
+
+def first(a: str, b: str) -> str:
+    if len(a) > 0:
+        return a
+    return b
+
+def label(a: str) -> str:
+    return a + "!"
+
+def same(xs: List[int]) -> List[int]:
+    return xs
+"#,
+        );
+        let first = table.function("first").unwrap();
+        assert_eq!(first.params, vec![Borrowed, Borrowed]);
+        assert_eq!(first.returned, vec![0, 1]);
+        // The returned value is built, not one of the parameters
+        assert!(table.function("label").unwrap().returned.is_empty());
+        assert_eq!(table.function("same").unwrap().params, vec![Owned]);
+    }
+
+    #[test]
+    fn test_borrowing_follows_calls() {
+        let table = table(
+            r#"
+class Holder:
+    def __init__(self, label: str):
+        self.label = label
+
+def show(s: str) -> int:
+    return len(s)
+
+def store(s: str) -> Holder:
+    return Holder(s)
+
+def reads(s: str) -> int:
+    return show(s)
+
+def moves(s: str) -> Holder:
+    return store(s)
+"#,
+        );
+        assert_eq!(table.function("reads").unwrap().params, vec![Borrowed]);
+        assert_eq!(table.function("moves").unwrap().params, vec![Owned]);
+    }
+
+    #[test]
+    fn test_strategies_and_ambiguous_methods() {
+        let source = r#"
+class A:
+    def name(self, s: str) -> int:
+        return len(s)
+
+class B:
+    def name(self, s: str) -> int:
+        return len(s)
+
+    def size(self, s: str) -> int:
+        return len(s)
+
+def show(s: str) -> int:
+    return len(s)
+"#;
+        let module = module(source);
+        let table = BorrowTable::for_module(&module, StringStrategy::InferBorrowing);
+        // A call to `name` does not say which class it reaches
+        assert!(table.method("name").is_none());
+        assert_eq!(table.method("size").unwrap().params, vec![Borrowed]);
+
+        let cows = BorrowTable::for_module(&module, StringStrategy::CowByDefault);
+        assert_eq!(cows.function("show").unwrap().params, vec![Cow]);
+
+        let owned = BorrowTable::for_module(&module, StringStrategy::AlwaysOwned);
+        assert!(owned.function("show").is_none());
+    }
+}
@@ -0,0 +1,783 @@
+//! Lowering of calls to Python builtins into idiomatic Rust
+//!
+//! `BUILTINS` maps each builtin, by name and by the types of its
+//! arguments as far as codegen knows them, to the Rust the call becomes.
+//! The first entry whose parameters match is used. Its template refers to
+//! the arguments: `$0` is the first, `@0` an iterator over the owned items
+//! of the first, `$*` all of them, and `$int` is the Rust type of `int`.
+//! `print`, `isinstance` and the set constructors do not fit a template
+//! and are lowered in code.
+
+use crate::error::ErrorKind;
+use crate::hir::{FStringPart, HirExpr, Literal, Symbol, Type};
+use crate::strings::{self, Format};
+use crate::transpile_bail;
+use crate::type_enums;
+use crate::type_mapper::TypeMapper;
+use anyhow::Result;
+use quote::quote;
+use std::collections::BTreeSet;
+use syn::parse_quote;
+
+/// What an argument must be for an entry to apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Param {
+    Any,
+    Int,
+    Float,
+    Bool,
+    Str,
+    List,
+    /// A list of floats, which are not `Ord`
+    FloatItems,
+    /// A list or set of ints
+    IntItems,
+    /// A list or set of strings
+    StrItems,
+    /// A number, or a value whose type is unknown
+    Numeric,
+    /// A string or a collection, which has a length
+    Sized,
+}
+
+impl Param {
+    fn matches(self, ty: &Type) -> bool {
+        match self {
+            Param::Any => true,
+            Param::Int => *ty == Type::Int,
+            Param::Float => *ty == Type::Float,
+            Param::Bool => *ty == Type::Bool,
+            Param::Str => *ty == Type::String,
+            Param::List => matches!(ty, Type::List(_)),
+            Param::FloatItems => matches!(ty, Type::List(item) if **item == Type::Float),
+            Param::IntItems => {
+                matches!(ty, Type::List(item) | Type::Set(item) if **item == Type::Int)
+            }
+            Param::StrItems => {
+                matches!(ty, Type::List(item) | Type::Set(item) if **item == Type::String)
+            }
+            Param::Numeric => matches!(ty, Type::Int | Type::Float | Type::Bool | Type::Unknown),
+            Param::Sized => matches!(
+                ty,
+                Type::String | Type::List(_) | Type::Dict(_, _) | Type::Set(_) | Type::Tuple(_)
+            ),
+        }
+    }
+}
+
+struct Builtin {
+    name: &'static str,
+    params: &'static [Param],
+    /// The last parameter repeats
+    variadic: bool,
+    template: &'static str,
+}
+
+const fn rule(name: &'static str, params: &'static [Param], template: &'static str) -> Builtin {
+    Builtin {
+        name,
+        params,
+        variadic: false,
+        template,
+    }
+}
+
+const fn variadic(name: &'static str, params: &'static [Param], template: &'static str) -> Builtin {
+    Builtin {
+        name,
+        params,
+        variadic: true,
+        template,
+    }
+}
+
+use Param::*;
+
+const BUILTINS: &[Builtin] = &[
+    rule("len", &[Any], "($0.len() as $int)"),
+    rule("range", &[Any], "0..$0"),
+    rule("range", &[Any, Any], "$0..$1"),
+    rule("range", &[Any, Any, Any], "($0..$1).step_by($2 as usize)"),
+    rule("abs", &[Any], "$0.abs()"),
+    rule(
+        "min",
+        &[FloatItems],
+        "@0.reduce(f64::min).expect(\"min() arg is an empty sequence\")",
+    ),
+    rule(
+        "min",
+        &[Any],
+        "@0.min().expect(\"min() arg is an empty sequence\")",
+    ),
+    rule("min", &[Float, Any], "$0.min($1)"),
+    rule("min", &[Any, Float], "$1.min($0)"),
+    rule("min", &[Any, Any], "std::cmp::min($0, $1)"),
+    variadic("min", &[Any, Any, Any], "[$*].into_iter().min().unwrap()"),
+    rule(
+        "max",
+        &[FloatItems],
+        "@0.reduce(f64::max).expect(\"max() arg is an empty sequence\")",
+    ),
+    rule(
+        "max",
+        &[Any],
+        "@0.max().expect(\"max() arg is an empty sequence\")",
+    ),
+    rule("max", &[Float, Any], "$0.max($1)"),
+    rule("max", &[Any, Float], "$1.max($0)"),
+    rule("max", &[Any, Any], "std::cmp::max($0, $1)"),
+    variadic("max", &[Any, Any, Any], "[$*].into_iter().max().unwrap()"),
+    rule("sum", &[FloatItems], "@0.sum::<f64>()"),
+    rule("sum", &[Any], "@0.sum::<$int>()"),
+    // Items are true unless zero or empty
+    rule("any", &[IntItems], "@0.any(|__x| __x != 0)"),
+    rule("any", &[FloatItems], "@0.any(|__x| __x != 0.0)"),
+    rule("any", &[StrItems], "@0.any(|__x| !__x.is_empty())"),
+    rule("any", &[Any], "@0.any(|__x| __x)"),
+    rule("all", &[IntItems], "@0.all(|__x| __x != 0)"),
+    rule("all", &[FloatItems], "@0.all(|__x| __x != 0.0)"),
+    rule("all", &[StrItems], "@0.all(|__x| !__x.is_empty())"),
+    rule("all", &[Any], "@0.all(|__x| __x)"),
+    rule(
+        "enumerate",
+        &[Any],
+        "@0.enumerate().map(|(__i, __x)| (__i as $int, __x))",
+    ),
+    rule(
+        "enumerate",
+        &[Any, Any],
+        "@0.enumerate().map(|(__i, __x)| (__i as $int + $1, __x))",
+    ),
+    rule("zip", &[Any, Any], "@0.zip(@1)"),
+    rule(
+        "zip",
+        &[Any, Any, Any],
+        "@0.zip(@1).zip(@2).map(|((__a, __b), __c)| (__a, __b, __c))",
+    ),
+    rule("reversed", &[Any], "@0.rev()"),
+    rule("list", &[List], "$0.to_vec()"),
+    rule("list", &[Any], "@0.collect::<Vec<_>>()"),
+    rule("str", &[Any], "$0.to_string()"),
+    rule("int", &[Int], "$0"),
+    rule(
+        "int",
+        &[Str],
+        "$0.trim().parse::<$int>().expect(\"invalid literal for int()\")",
+    ),
+    rule("int", &[Numeric], "$0 as $int"),
+    rule("float", &[Float], "$0"),
+    rule(
+        "float",
+        &[Str],
+        "$0.trim().parse::<f64>().expect(\"could not convert string to float\")",
+    ),
+    rule("float", &[Numeric], "$0 as f64"),
+    rule("bool", &[Bool], "$0"),
+    rule("bool", &[Int], "$0 != 0"),
+    rule("bool", &[Float], "$0 != 0.0"),
+    rule("bool", &[Sized], "!$0.is_empty()"),
+    rule("round", &[Int], "$0"),
+    // Python rounds halves to even
+    rule("round", &[Float], "$0.round_ties_even() as $int"),
+    rule(
+        "round",
+        &[Float, Int],
+        "{ let __scale = 10f64.powi($1 as i32); ($0 * __scale).round_ties_even() / __scale }",
+    ),
+    rule(
+        "ord",
+        &[Str],
+        "{ let __s = &$0; let mut __chars = __s.chars(); match (__chars.next(), __chars.next()) { \
+         (Some(__c), None) => __c as $int, \
+         _ => panic!(\"ord() expected a character, but string of length {} found\", __s.chars().count()) } }",
+    ),
+    rule(
+        "chr",
+        &[Int],
+        "u32::try_from($0).ok().and_then(char::from_u32).expect(\"chr() arg not in range(0x110000)\").to_string()",
+    ),
+];
+
+/// Builtins lowered in code rather than by `BUILTINS`
+const SPECIAL: &[&str] = &["print", "isinstance", "sorted", "set", "frozenset"];
+
+/// Builtins whose value is an iterator, iterated as it is
+const ITERATORS: &[&str] = &["range", "enumerate", "zip", "reversed"];
+
+/// Builtins that only read their arguments, as the borrow inference sees
+/// them: passing a parameter to one does not need it owned
+const READS_ARGUMENTS: &[&str] = &[
+    "len",
+    "str",
+    "print",
+    "isinstance",
+    "sum",
+    "any",
+    "all",
+    "round",
+    "ord",
+    "chr",
+    "int",
+    "float",
+];
+
+/// Whether calls to `name` are lowered here, unless the module shadows it
+pub fn is_builtin(name: &str) -> bool {
+    SPECIAL.contains(&name) || BUILTINS.iter().any(|b| b.name == name)
+}
+
+/// Whether a call to builtin `name` with `argc` arguments only reads them
+pub fn reads_arguments(name: &str, argc: usize) -> bool {
+    READS_ARGUMENTS.contains(&name) || (matches!(name, "min" | "max") && argc == 1)
+}
+
+/// An argument of a call to a builtin
+pub struct Arg<'a> {
+    pub hir: &'a HirExpr,
+    pub expr: syn::Expr,
+    /// Its type, `Unknown` where codegen does not know it
+    pub ty: Type,
+    /// A local read for the last time, which iterating may consume
+    pub movable: bool,
+}
+
+/// The Rust a call to builtin `name` becomes, or `None` when `name` is
+/// not one `BUILTINS` lowers
+pub fn lower(name: &str, args: &[Arg], type_mapper: &TypeMapper) -> Result<Option<syn::Expr>> {
+    let candidates: Vec<&Builtin> = BUILTINS.iter().filter(|b| b.name == name).collect();
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    let arity = |b: &Builtin| {
+        if b.variadic {
+            args.len() >= b.params.len()
+        } else {
+            args.len() == b.params.len()
+        }
+    };
+    if !candidates.iter().any(|b| arity(b)) {
+        transpile_bail!(ErrorKind::CodeGenerationError(format!(
+            "Invalid number of arguments for {}()",
+            name
+        )));
+    }
+    let param = |b: &Builtin, i: usize| b.params[i.min(b.params.len() - 1)];
+    let Some(builtin) = candidates.into_iter().find(|b| {
+        arity(b)
+            && args
+                .iter()
+                .enumerate()
+                .all(|(i, arg)| param(b, i).matches(&arg.ty))
+    }) else {
+        let types: Vec<String> = args.iter().map(|arg| format!("{:?}", arg.ty)).collect();
+        transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "{}() of {}",
+            name,
+            types.join(", ")
+        )));
+    };
+    let int_type = type_mapper.map_type(&Type::Int).to_rust_string();
+    let code = expand(builtin.template, args, &int_type);
+    let expr = syn::parse_str(&code).map_err(|err| {
+        anyhow::anyhow!(
+            "builtin {}() lowered to invalid Rust `{}`: {}",
+            name,
+            code,
+            err
+        )
+    })?;
+    Ok(Some(expr))
+}
+
+/// `template` with its placeholders replaced by `args`
+fn expand(template: &str, args: &[Arg], int_type: &str) -> String {
+    let template = template.replace("$int", int_type);
+    let mut out = String::new();
+    let mut chars = template.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        if c != '$' && c != '@' {
+            out.push(c);
+            continue;
+        }
+        if c == '$' && chars.next_if(|&(_, c)| c == '*').is_some() {
+            let all: Vec<String> = args.iter().map(|arg| tokens(&arg.expr)).collect();
+            out.push_str(&all.join(", "));
+            continue;
+        }
+        let mut end = at + 1;
+        while let Some((i, _)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+            end = i + 1;
+        }
+        let Ok(index) = template[at + 1..end].parse::<usize>() else {
+            out.push(c);
+            continue;
+        };
+        let arg = &args[index];
+        // Arguments followed by a method call or an operator keep their
+        // precedence; those standing alone need no parentheses
+        let standalone = matches!(chars.peek(), None | Some((_, ',' | ')' | ']')));
+        let expr = match c {
+            '@' => iterator(arg, standalone),
+            // A method of a literal number needs the literal's type
+            _ if template[end..].starts_with('.') && !template[end..].starts_with("..") => {
+                typed_literal(&arg.expr, int_type).unwrap_or_else(|| arg.expr.clone())
+            }
+            _ => arg.expr.clone(),
+        };
+        if standalone {
+            out.push_str(&tokens(&expr));
+        } else {
+            out.push_str(&operand(&expr));
+        }
+    }
+    out
+}
+
+/// `expr`, a literal number or its negation, with the literal suffixed by
+/// its type
+fn typed_literal(expr: &syn::Expr, int_type: &str) -> Option<syn::Expr> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(lit),
+            ..
+        }) if lit.suffix().is_empty() => {
+            let lit = syn::LitInt::new(&format!("{}{int_type}", lit.base10_digits()), lit.span());
+            Some(parse_quote! { #lit })
+        }
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Float(lit),
+            ..
+        }) if lit.suffix().is_empty() => {
+            let lit = syn::LitFloat::new(&format!("{}f64", lit.base10_digits()), lit.span());
+            Some(parse_quote! { #lit })
+        }
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => {
+            let lit = typed_literal(expr, int_type)?;
+            Some(parse_quote! { (-#lit) })
+        }
+        syn::Expr::Paren(paren) => typed_literal(&paren.expr, int_type),
+        _ => None,
+    }
+}
+
+/// An iterator over the owned items of `arg`; as an argument, where any
+/// `IntoIterator` will do, a value owned already is passed as it is
+pub(crate) fn iterator(arg: &Arg, into: bool) -> syn::Expr {
+    let expr = &arg.expr;
+    if matches!(arg.hir, HirExpr::Call { func, .. } if ITERATORS.contains(&func.as_str())) {
+        return expr.clone();
+    }
+    let place = !arg.movable && matches!(arg.hir, HirExpr::Var(_) | HirExpr::Attribute { .. });
+    let receiver = operand_expr(expr);
+    match &arg.ty {
+        Type::String => parse_quote! { #receiver.chars() },
+        Type::Dict(_, _) if place => parse_quote! { #receiver.keys().cloned() },
+        Type::Dict(_, _) => parse_quote! { #receiver.into_keys() },
+        _ if place => parse_quote! { #receiver.iter().cloned() },
+        _ if into => expr.clone(),
+        _ => parse_quote! { #receiver.into_iter() },
+    }
+}
+
+fn tokens(expr: &syn::Expr) -> String {
+    quote! { #expr }.to_string()
+}
+
+/// `expr` as the receiver of a method call or an operand of an operator
+fn operand(expr: &syn::Expr) -> String {
+    tokens(&operand_expr(expr))
+}
+
+pub(crate) fn operand_expr(expr: &syn::Expr) -> syn::Expr {
+    match expr {
+        syn::Expr::Path(_)
+        | syn::Expr::Call(_)
+        | syn::Expr::MethodCall(_)
+        | syn::Expr::Paren(_)
+        | syn::Expr::Field(_)
+        | syn::Expr::Index(_)
+        | syn::Expr::Macro(_)
+        | syn::Expr::Array(_)
+        | syn::Expr::Tuple(_)
+        | syn::Expr::Block(_)
+        | syn::Expr::Lit(_) => expr.clone(),
+        _ => parse_quote! { (#expr) },
+    }
+}
+
+/// An argument of `print`
+pub enum PrintArg<'a> {
+    Value(Arg<'a>),
+    /// A formatted string, formatted in place by `println!`
+    Formatted(&'a [FStringPart], Vec<Arg<'a>>),
+}
+
+/// `print(...)` as `println!`, formatting each argument the way Python
+/// prints it, with the `sep` and `end` keywords given as string literals.
+/// Containers print in Rust's debug format, close to Python's `repr`. The
+/// helpers the formatting calls are added to `helpers`.
+pub fn lower_print(
+    args: &[PrintArg],
+    kwargs: &[(Symbol, HirExpr)],
+    helpers: &mut BTreeSet<strings::Helper>,
+) -> Result<syn::Expr> {
+    let mut sep = " ".to_string();
+    let mut end = "\n".to_string();
+    for (name, value) in kwargs {
+        let slot = match name.as_str() {
+            "sep" => &mut sep,
+            "end" => &mut end,
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "Keyword argument '{}' to print()",
+                name
+            ))),
+        };
+        match value {
+            HirExpr::Literal(Literal::String(text)) => *slot = text.clone(),
+            HirExpr::Literal(Literal::None) => {}
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "print() with a '{}' other than a string literal",
+                name
+            ))),
+        }
+    }
+
+    let mut format = Format::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            format.text(&sep);
+        }
+        match arg {
+            PrintArg::Value(arg) => format.value(arg)?,
+            PrintArg::Formatted(parts, values) => format.fstring(parts, values)?,
+        }
+    }
+    helpers.append(&mut format.helpers);
+    let newline = end.ends_with('\n');
+    format.text(end.strip_suffix('\n').unwrap_or(&end));
+    let empty = format.is_empty();
+    let (format, values) = format.finish();
+    Ok(match (newline, empty, values.is_empty()) {
+        (true, true, _) => parse_quote! { println!() },
+        (true, false, true) => parse_quote! { println!(#format) },
+        (true, false, false) => parse_quote! { println!(#format, #(#values),*) },
+        (false, _, true) => parse_quote! { print!(#format) },
+        (false, _, false) => parse_quote! { print!(#format, #(#values),*) },
+    })
+}
+
+/// `isinstance(value, class)` as a constant, since the type of `value` is
+/// known statically; an optional value is an instance when it is not `None`
+pub fn lower_isinstance(value: &Arg, class: &HirExpr) -> Result<syn::Expr> {
+    let classes = isinstance_classes(class)?;
+    let is_instance = |ty: &Type| classes.iter().any(|class| instance_of(ty, class));
+    match &value.ty {
+        Type::Unknown => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "isinstance() of a value whose type is not declared".to_string()
+        )),
+        Type::Optional(inner) if is_instance(inner) => {
+            let expr = operand_expr(&value.expr);
+            Ok(parse_quote! { #expr.is_some() })
+        }
+        // The variants of a union holding instances
+        ty @ Type::Union(members) if members.iter().any(is_instance) => {
+            let variants = members
+                .iter()
+                .filter(|member| is_instance(member))
+                .filter_map(|member| type_enums::union_variant(ty, member));
+            let expr = &value.expr;
+            Ok(parse_quote! { matches!(#expr, #(#variants(_))|*) })
+        }
+        ty => {
+            let result = is_instance(ty);
+            Ok(parse_quote! { #result })
+        }
+    }
+}
+
+/// The members of union `members` that are instances of `class`, the
+/// second argument of `isinstance`
+pub fn isinstance_members<'t>(members: &'t [Type], class: &HirExpr) -> Result<Vec<&'t Type>> {
+    let classes = isinstance_classes(class)?;
+    Ok(members
+        .iter()
+        .filter(|ty| classes.iter().any(|class| instance_of(ty, class)))
+        .collect())
+}
+
+/// The classes named by the second argument of `isinstance`
+fn isinstance_classes(class: &HirExpr) -> Result<Vec<&Symbol>> {
+    match class {
+        HirExpr::Var(name) => Ok(vec![name]),
+        HirExpr::Tuple(classes) => classes
+            .iter()
+            .map(|class| match class {
+                HirExpr::Var(name) => Ok(name),
+                _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "isinstance() with a class other than a name".to_string()
+                )),
+            })
+            .collect(),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "isinstance() with a class other than a name".to_string()
+        )),
+    }
+}
+
+fn instance_of(ty: &Type, class: &str) -> bool {
+    match class {
+        "object" => true,
+        // `bool` is a subclass of `int`
+        "int" => matches!(ty, Type::Int | Type::Bool),
+        "float" => *ty == Type::Float,
+        "str" => *ty == Type::String,
+        "bool" => *ty == Type::Bool,
+        "list" => matches!(ty, Type::List(_)),
+        "dict" => matches!(ty, Type::Dict(_, _)),
+        "set" | "frozenset" => matches!(ty, Type::Set(_)),
+        "tuple" => matches!(ty, Type::Tuple(_)),
+        _ => matches!(ty, Type::Custom(name) if name == class),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str, ty: Type) -> (HirExpr, Type) {
+        (HirExpr::Var(name.to_string()), ty)
+    }
+
+    fn call(name: &str, args: &[(HirExpr, Type)]) -> String {
+        let args: Vec<Arg> = args
+            .iter()
+            .map(|(hir, ty)| Arg {
+                hir,
+                expr: match hir {
+                    HirExpr::Var(name) => syn::parse_str(name).unwrap(),
+                    HirExpr::Literal(Literal::Int(n)) => syn::parse_str(&n.to_string()).unwrap(),
+                    _ => parse_quote! { f() },
+                },
+                ty: ty.clone(),
+                movable: false,
+            })
+            .collect();
+        let expr = lower(name, &args, &TypeMapper::new()).unwrap().unwrap();
+        quote! { #expr }.to_string()
+    }
+
+    #[test]
+    fn test_lower_by_argument_types() {
+        let ints = var("xs", Type::List(Box::new(Type::Int)));
+        let floats = var("ys", Type::List(Box::new(Type::Float)));
+        assert_eq!(
+            call("sum", std::slice::from_ref(&ints)),
+            "xs . iter () . cloned () . sum :: < i32 > ()"
+        );
+        assert_eq!(
+            call("sum", std::slice::from_ref(&floats)),
+            "ys . iter () . cloned () . sum :: < f64 > ()"
+        );
+        assert!(call("max", &[floats])
+            .contains("reduce (f64 :: max) . expect (\"max() arg is an empty sequence\")"));
+        assert_eq!(
+            call("min", &[var("a", Type::Int), var("b", Type::Int)]),
+            "std :: cmp :: min (a , b)"
+        );
+        assert_eq!(call("bool", &[var("s", Type::String)]), "! s . is_empty ()");
+        assert_eq!(call("float", &[var("n", Type::Int)]), "n as f64");
+        assert!(call("enumerate", &[ints]).starts_with("xs . iter () . cloned () . enumerate ()"));
+        assert_eq!(
+            call("round", &[var("x", Type::Float)]),
+            "x . round_ties_even () as i32"
+        );
+        assert!(
+            call("ord", &[var("c", Type::String)]).contains("(Some (__c) , None) => __c as i32")
+        );
+        assert!(call("chr", &[var("n", Type::Int)])
+            .starts_with("u32 :: try_from (n) . ok () . and_then (char :: from_u32)"));
+    }
+
+    #[test]
+    fn test_lower_methods_of_literals() {
+        let three = HirExpr::Literal(Literal::Int(3));
+        let lowered = |expr: syn::Expr, ty: Type| {
+            let args = [Arg {
+                hir: &three,
+                expr,
+                ty,
+                movable: false,
+            }];
+            let expr = lower("abs", &args, &TypeMapper::new()).unwrap().unwrap();
+            quote! { #expr }.to_string()
+        };
+        assert_eq!(lowered(parse_quote! { -3 }, Type::Int), "(- 3i32) . abs ()");
+        assert_eq!(
+            lowered(parse_quote! { 2.5 }, Type::Float),
+            "2.5f64 . abs ()"
+        );
+        assert_eq!(lowered(parse_quote! { x }, Type::Int), "x . abs ()");
+    }
+
+    #[test]
+    fn test_lower_iterators_and_precedence() {
+        let range = HirExpr::Call {
+            func: "range".to_string(),
+            args: vec![],
+            kwargs: vec![],
+        };
+        let args = [Arg {
+            hir: &range,
+            expr: parse_quote! { 0..n },
+            ty: Type::Unknown,
+            movable: false,
+        }];
+        let expr = lower("reversed", &args, &TypeMapper::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "(0 .. n) . rev ()");
+        assert!(lower("print", &args, &TypeMapper::new()).unwrap().is_none());
+        assert!(lower("len", &[], &TypeMapper::new()).is_err());
+        assert!(lower(
+            "int",
+            &[Arg {
+                ty: Type::List(Box::new(Type::Int)),
+                ..args.into_iter().next().unwrap()
+            }],
+            &TypeMapper::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_lower_print() {
+        let (x, _) = var("x", Type::Float);
+        let (flag, _) = var("flag", Type::Bool);
+        let label = HirExpr::Literal(Literal::String("x = {}".to_string()));
+        let args = [
+            PrintArg::Value(Arg {
+                hir: &label,
+                expr: parse_quote! { "x = {}" },
+                ty: Type::String,
+                movable: false,
+            }),
+            PrintArg::Value(Arg {
+                hir: &x,
+                expr: parse_quote! { x },
+                ty: Type::Float,
+                movable: false,
+            }),
+            PrintArg::Value(Arg {
+                hir: &flag,
+                expr: parse_quote! { flag },
+                ty: Type::Bool,
+                movable: false,
+            }),
+        ];
+        let expr = lower_print(&args, &[], &mut BTreeSet::new()).unwrap();
+        assert_eq!(
+            quote! { #expr }.to_string(),
+            "println ! (\"x = {{}} {x:?} {}\" , if flag { \"True\" } else { \"False\" })"
+        );
+        let end = vec![(
+            "end".to_string(),
+            HirExpr::Literal(Literal::String("".to_string())),
+        )];
+        let expr = lower_print(&args[1..2], &end, &mut BTreeSet::new()).unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "print ! (\"{x:?}\")");
+        let parts = [
+            FStringPart::Field {
+                value: 0,
+                conversion: None,
+                spec: ".1f".to_string(),
+            },
+            FStringPart::Text("!".to_string()),
+        ];
+        let formatted = [PrintArg::Formatted(
+            &parts,
+            vec![Arg {
+                hir: &x,
+                expr: parse_quote! { x },
+                ty: Type::Float,
+                movable: false,
+            }],
+        )];
+        let expr = lower_print(&formatted, &[], &mut BTreeSet::new()).unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "println ! (\"{x:.1}!\")");
+    }
+
+    #[test]
+    fn test_lower_isinstance() {
+        let (x, _) = var("x", Type::Bool);
+        let value = |ty: Type| Arg {
+            hir: &x,
+            expr: parse_quote! { x },
+            ty,
+            movable: false,
+        };
+        let class = |name: &str| HirExpr::Var(name.to_string());
+        let lowered = |arg: Arg, class: HirExpr| {
+            let expr = lower_isinstance(&arg, &class).unwrap();
+            quote! { #expr }.to_string()
+        };
+        assert_eq!(lowered(value(Type::Bool), class("int")), "true");
+        assert_eq!(lowered(value(Type::String), class("int")), "false");
+        assert_eq!(
+            lowered(
+                value(Type::Float),
+                HirExpr::Tuple(vec![class("int"), class("float")])
+            ),
+            "true"
+        );
+        assert_eq!(
+            lowered(value(Type::Optional(Box::new(Type::Int))), class("int")),
+            "x . is_some ()"
+        );
+        assert_eq!(
+            lowered(
+                value(Type::Union(vec![Type::Int, Type::String])),
+                class("str")
+            ),
+            "matches ! (x , IntOrStr :: Str (_))"
+        );
+        assert!(lower_isinstance(&value(Type::Unknown), &class("int")).is_err());
+    }
+
+    #[test]
+    fn test_builtin_programs_run() {
+        let python = r#"
+from typing import Dict, List
+
+def numbers(xs: List[float], s: str, n: int, x: float) -> float:
+    a = abs(-3) + abs(n) + ord(s) + round(x) + ord(chr(n))
+    return min(xs) + max(xs) + round(x, 2) + abs(-2.5) + a
+
+def codes(s: str) -> List[int]:
+    out = []
+    for c in s:
+        out.append(ord(c))
+    return out
+
+def truths(xs: List[int], ys: List[int], words: List[str]) -> List[bool]:
+    return [all(xs), any(ys), all(words), any(words)]
+
+def report(words: List[str], flags: Dict[str, bool], n: int) -> None:
+    print(words, n)
+    print(flags)
+    print(len(words) * n - 1)
+"#;
+        let main = r#"let words = vec!["a".to_string(), "it's".to_string()];
+report(&words, &HashMap::from([("k".to_string(), true)]), 3);
+let empty = vec!["".to_string(), "b".to_string()];
+println!("{:?} {:?}", numbers(&[1.5, -2.0, 3.25], "A", 66, 2.5), codes("Hi"));
+println!("{:?}", truths(&[1, 2, 0], &[0, 0, 3], &empty));"#;
+        assert_eq!(
+            crate::test_programs::run(python, main),
+            "['a', \"it's\"] 3\n{'k': True}\n5\n208.25 [72, 105]\n[false, true, false, true]\n"
+        );
+    }
+}
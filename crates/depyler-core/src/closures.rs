@@ -0,0 +1,616 @@
+//! Capture analysis for lambdas and nested functions.
+//!
+//! Both become Rust closures. A closure captures the locals of the enclosing
+//! function it reads or writes; how it uses them decides which of `Fn`,
+//! `FnMut` and `FnOnce` it implements. Whether it must take them by `move`
+//! depends on whether the closure outlives the enclosing call, see
+//! [`escapes`].
+
+use crate::hir::*;
+use std::collections::{HashMap, HashSet};
+
+/// The closure trait a lambda or nested function implements
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ClosureKind {
+    /// Only reads its captures
+    Fn,
+    /// Mutates a capture in place, or rebinds it through `nonlocal`
+    FnMut,
+    /// Moves a capture out, so it can only be called once
+    FnOnce,
+}
+
+impl ClosureKind {
+    pub fn trait_name(self) -> &'static str {
+        match self {
+            ClosureKind::Fn => "Fn",
+            ClosureKind::FnMut => "FnMut",
+            ClosureKind::FnOnce => "FnOnce",
+        }
+    }
+
+    /// The trait implemented by a value that may be either closure
+    pub fn join(self, other: ClosureKind) -> ClosureKind {
+        self.max(other)
+    }
+}
+
+/// Locals of the enclosing function a closure captures
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Captures {
+    /// Every captured local, in order of first use
+    pub captured: Vec<Symbol>,
+    /// Captured locals mutated in place or rebound through `nonlocal`
+    pub mutated: Vec<Symbol>,
+    /// Captured locals of a non-`Copy` type the closure gives away by value
+    pub moved: Vec<Symbol>,
+}
+
+impl Captures {
+    pub fn kind(&self) -> ClosureKind {
+        if !self.moved.is_empty() {
+            ClosureKind::FnOnce
+        } else if !self.mutated.is_empty() {
+            ClosureKind::FnMut
+        } else {
+            ClosureKind::Fn
+        }
+    }
+}
+
+/// The enclosing function as seen from a closure defined in it
+pub struct Scope<'a> {
+    /// Whether a name is a parameter or local bound before the closure
+    pub is_local: &'a dyn Fn(&str) -> bool,
+    /// Known types of the enclosing locals; the others are assumed to be `Copy`
+    pub types: &'a HashMap<Symbol, Type>,
+}
+
+impl Scope<'_> {
+    fn is_copy(&self, name: &str) -> bool {
+        self.types.get(name).is_none_or(is_copy_type)
+    }
+}
+
+pub(crate) fn is_copy_type(ty: &Type) -> bool {
+    match ty {
+        Type::Int | Type::Float | Type::Bool | Type::None => true,
+        Type::Tuple(types) => types.iter().all(is_copy_type),
+        _ => false,
+    }
+}
+
+/// Captures of `lambda params: body`
+pub fn lambda_captures(params: &[Symbol], body: &HirExpr, scope: &Scope) -> Captures {
+    let mut analysis = Analysis {
+        scope,
+        captures: Captures::default(),
+        nonlocals: HashSet::new(),
+    };
+    let bound = params.iter().cloned().collect();
+    analysis.moved_value(body, &bound);
+    analysis.expr(body, &bound);
+    analysis.captures
+}
+
+/// Captures of a nested `def`, given the names its body declares `nonlocal`
+pub fn function_captures(func: &HirFunction, nonlocals: &[Symbol], scope: &Scope) -> Captures {
+    let mut analysis = Analysis {
+        scope,
+        captures: Captures::default(),
+        nonlocals: nonlocals.iter().cloned().collect(),
+    };
+    let bound = analysis.function_bound(&func.params, &func.body, &HashSet::new());
+    analysis.body(&func.body, &bound, true);
+    analysis.captures
+}
+
+/// Whether the closure bound to `name` outlives the body defining it: it is
+/// returned or yielded, stored in an attribute, subscript or collection, or
+/// assigned to another name
+pub fn escapes(name: &str, body: &[Spanned<HirStmt>]) -> bool {
+    fn is_name(expr: &HirExpr, name: &str) -> bool {
+        match expr {
+            HirExpr::Var(v) => v == name,
+            HirExpr::Tuple(elts) | HirExpr::List(elts) => elts.iter().any(|e| is_name(e, name)),
+            _ => false,
+        }
+    }
+    body.iter().any(|stmt| match &stmt.node {
+        HirStmt::Return(Some(value)) | HirStmt::Yield(Some(value)) => is_name(value, name),
+        HirStmt::Assign { value, .. } => is_name(value, name),
+        HirStmt::Expr(HirExpr::MethodCall { args, .. }) if stmt_is_mutating_call(stmt) => {
+            args.iter().any(|a| is_name(a, name))
+        }
+        HirStmt::If {
+            then_body,
+            else_body,
+            ..
+        } => escapes(name, then_body) || else_body.as_deref().is_some_and(|b| escapes(name, b)),
+        HirStmt::While { body, .. } | HirStmt::For { body, .. } | HirStmt::With { body, .. } => {
+            escapes(name, body)
+        }
+        HirStmt::Match { cases, .. } => cases.iter().any(|c| escapes(name, &c.body)),
+        HirStmt::Try {
+            body,
+            handlers,
+            orelse,
+            finalbody,
+        } => {
+            escapes(name, body)
+                || handlers.iter().any(|h| escapes(name, &h.body))
+                || escapes(name, orelse)
+                || escapes(name, finalbody)
+        }
+        _ => false,
+    })
+}
+
+fn stmt_is_mutating_call(stmt: &HirStmt) -> bool {
+    matches!(stmt, HirStmt::Expr(call) if call.is_mutating_method_call())
+}
+
+/// The local a place expression such as `a.b[0]` is rooted in
+fn root_name(expr: &HirExpr) -> Option<&Symbol> {
+    match expr {
+        HirExpr::Var(name) => Some(name),
+        HirExpr::Attribute { value, .. } => root_name(value),
+        HirExpr::Index { base, .. } => root_name(base),
+        _ => None,
+    }
+}
+
+struct Analysis<'s, 'a> {
+    scope: &'s Scope<'a>,
+    captures: Captures,
+    /// Names the closure rebinds in the enclosing function
+    nonlocals: HashSet<Symbol>,
+}
+
+impl Analysis<'_, '_> {
+    fn capture(&mut self, name: &str, bound: &HashSet<Symbol>) -> bool {
+        if bound.contains(name) || !(self.scope.is_local)(name) {
+            return false;
+        }
+        if !self.captures.captured.iter().any(|c| c == name) {
+            self.captures.captured.push(name.to_string());
+        }
+        true
+    }
+
+    fn mutate(&mut self, name: &str, bound: &HashSet<Symbol>) {
+        if self.capture(name, bound) && !self.captures.mutated.iter().any(|m| m == name) {
+            self.captures.mutated.push(name.to_string());
+        }
+    }
+
+    /// A value handed out of the closure, moving the captures it consists of
+    fn moved_value(&mut self, value: &HirExpr, bound: &HashSet<Symbol>) {
+        match value {
+            HirExpr::Var(name)
+                if !self.scope.is_copy(name)
+                    && self.capture(name, bound)
+                    && !self.captures.moved.contains(name) =>
+            {
+                self.captures.moved.push(name.clone());
+            }
+            HirExpr::Tuple(elts) | HirExpr::List(elts) | HirExpr::Set(elts) => {
+                elts.iter().for_each(|e| self.moved_value(e, bound));
+            }
+            _ => {}
+        }
+    }
+
+    /// Names local to a function: its parameters and everything its body
+    /// binds, except the names it declares `nonlocal`
+    fn function_bound(
+        &self,
+        params: &[(Symbol, Type)],
+        body: &[Spanned<HirStmt>],
+        outer: &HashSet<Symbol>,
+    ) -> HashSet<Symbol> {
+        let mut bound = outer.clone();
+        bound.extend(params.iter().map(|(name, _)| name.clone()));
+        bind_names(body, &mut bound);
+        for name in &self.nonlocals {
+            bound.remove(name);
+        }
+        bound
+    }
+
+    /// `top_level` is set for the closure's own body, whose returns leave it
+    fn body(&mut self, body: &[Spanned<HirStmt>], bound: &HashSet<Symbol>, top_level: bool) {
+        for stmt in body {
+            self.stmt(stmt, bound, top_level);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &HirStmt, bound: &HashSet<Symbol>, top_level: bool) {
+        match stmt {
+            HirStmt::Assign { target, value } => {
+                self.target(target, bound);
+                self.moved_value(value, bound);
+                self.expr(value, bound);
+            }
+            HirStmt::Return(value) => {
+                if let Some(value) = value {
+                    if top_level {
+                        self.moved_value(value, bound);
+                    }
+                    self.expr(value, bound);
+                }
+            }
+            HirStmt::Expr(expr) | HirStmt::YieldFrom(expr) => self.expr(expr, bound),
+            HirStmt::Yield(value) => value.iter().for_each(|v| self.expr(v, bound)),
+            HirStmt::Raise { exception } => exception.iter().for_each(|e| self.expr(e, bound)),
+            HirStmt::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                self.expr(condition, bound);
+                self.body(then_body, bound, top_level);
+                if let Some(else_body) = else_body {
+                    self.body(else_body, bound, top_level);
+                }
+            }
+            HirStmt::While { condition, body } => {
+                self.expr(condition, bound);
+                self.body(body, bound, top_level);
+            }
+            HirStmt::For { target, iter, body } => {
+                self.target(target, bound);
+                self.expr(iter, bound);
+                self.body(body, bound, top_level);
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                self.body(body, bound, top_level);
+                for handler in handlers {
+                    self.body(&handler.body, bound, top_level);
+                }
+                self.body(orelse, bound, top_level);
+                self.body(finalbody, bound, top_level);
+            }
+            HirStmt::With { items, body } => {
+                for item in items {
+                    self.expr(&item.context_expr, bound);
+                }
+                self.body(body, bound, top_level);
+            }
+            HirStmt::Match { subject, cases } => {
+                self.expr(subject, bound);
+                for case in cases {
+                    if let Some(guard) = &case.guard {
+                        self.expr(guard, bound);
+                    }
+                    self.body(&case.body, bound, top_level);
+                }
+            }
+            // What a closure nested in the closure captures from the
+            // enclosing function, the outer closure captures as well
+            HirStmt::FunctionDef { func, .. } => {
+                let inner = self.function_bound(&func.params, &func.body, bound);
+                self.body(&func.body, &inner, false);
+            }
+        }
+    }
+
+    fn target(&mut self, target: &AssignTarget, bound: &HashSet<Symbol>) {
+        match target {
+            AssignTarget::Symbol(name) => {
+                if self.nonlocals.contains(name) {
+                    self.mutate(name, bound);
+                }
+            }
+            AssignTarget::Attribute { value, .. } => {
+                if let Some(root) = root_name(value) {
+                    self.mutate(root, bound);
+                }
+                self.expr(value, bound);
+            }
+            AssignTarget::Index { base, index } => {
+                if let Some(root) = root_name(base) {
+                    self.mutate(root, bound);
+                }
+                self.expr(base, bound);
+                self.expr(index, bound);
+            }
+            AssignTarget::Tuple(targets) => {
+                targets.iter().for_each(|t| self.target(t, bound));
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &HirExpr, bound: &HashSet<Symbol>) {
+        match expr {
+            HirExpr::Literal(_) => {}
+            HirExpr::Var(name) => {
+                self.capture(name, bound);
+            }
+            HirExpr::Binary { left, right, .. } => {
+                self.expr(left, bound);
+                self.expr(right, bound);
+            }
+            HirExpr::Unary { operand, .. } => self.expr(operand, bound),
+            HirExpr::Call { func, args, kwargs } => {
+                // Calling a closure bound in the enclosing function captures it
+                self.capture(func, bound);
+                args.iter().for_each(|a| self.expr(a, bound));
+                kwargs.iter().for_each(|(_, v)| self.expr(v, bound));
+            }
+            HirExpr::MethodCall {
+                receiver,
+                args,
+                kwargs,
+                ..
+            } => {
+                if expr.is_mutating_method_call() {
+                    if let Some(root) = root_name(receiver) {
+                        self.mutate(root, bound);
+                    }
+                }
+                self.expr(receiver, bound);
+                args.iter().for_each(|a| self.expr(a, bound));
+                kwargs.iter().for_each(|(_, v)| self.expr(v, bound));
+            }
+            HirExpr::Index { base, index } => {
+                self.expr(base, bound);
+                self.expr(index, bound);
+            }
+            HirExpr::Slice {
+                base,
+                start,
+                stop,
+                step,
+            } => {
+                self.expr(base, bound);
+                for bound_expr in [start, stop, step].into_iter().flatten() {
+                    self.expr(bound_expr, bound);
+                }
+            }
+            HirExpr::Attribute { value, .. }
+            | HirExpr::Await { value }
+            | HirExpr::Borrow { expr: value, .. } => self.expr(value, bound),
+            HirExpr::List(elts)
+            | HirExpr::Set(elts)
+            | HirExpr::Tuple(elts)
+            | HirExpr::FString { values: elts, .. } => {
+                elts.iter().for_each(|e| self.expr(e, bound));
+            }
+            HirExpr::Dict(items) => {
+                for (key, value) in items {
+                    self.expr(key, bound);
+                    self.expr(value, bound);
+                }
+            }
+            HirExpr::Comprehension {
+                element,
+                value,
+                generators,
+                ..
+            } => {
+                let mut inner = bound.clone();
+                for clause in generators {
+                    self.expr(&clause.iter, &inner);
+                    inner.insert(clause.target.clone());
+                    clause.conditions.iter().for_each(|c| self.expr(c, &inner));
+                }
+                self.expr(element, &inner);
+                if let Some(value) = value {
+                    self.expr(value, &inner);
+                }
+            }
+            HirExpr::Lambda { params, body } => {
+                let mut inner = bound.clone();
+                inner.extend(params.iter().cloned());
+                self.expr(body, &inner);
+            }
+        }
+    }
+}
+
+/// Add the names a body binds, outside nested functions, to `bound`
+fn bind_names(body: &[Spanned<HirStmt>], bound: &mut HashSet<Symbol>) {
+    for stmt in body {
+        match &stmt.node {
+            HirStmt::Assign { target, .. } | HirStmt::For { target, .. } => {
+                bound.extend(target.symbols().into_iter().cloned());
+            }
+            HirStmt::FunctionDef { func, .. } => {
+                bound.insert(func.name.clone());
+            }
+            _ => {}
+        }
+        match &stmt.node {
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                bind_names(then_body, bound);
+                if let Some(else_body) = else_body {
+                    bind_names(else_body, bound);
+                }
+            }
+            HirStmt::While { body, .. } | HirStmt::For { body, .. } => bind_names(body, bound),
+            HirStmt::With { items, body } => {
+                bound.extend(items.iter().filter_map(|i| i.optional_vars.clone()));
+                bind_names(body, bound);
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                bind_names(body, bound);
+                for handler in handlers {
+                    bound.extend(handler.name.clone());
+                    bind_names(&handler.body, bound);
+                }
+                bind_names(orelse, bound);
+                bind_names(finalbody, bound);
+            }
+            HirStmt::Match { cases, .. } => {
+                for case in cases {
+                    bound.extend(case.pattern.bindings().into_iter().cloned());
+                    bind_names(&case.body, bound);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_bridge::AstBridge;
+    use rustpython_ast::Suite;
+    use rustpython_parser::Parse;
+
+    fn module(source: &str) -> HirModule {
+        let statements = Suite::parse(source, "<test>").unwrap();
+        let ast = rustpython_ast::Mod::Module(rustpython_ast::ModModule {
+            body: statements,
+            type_ignores: vec![],
+            range: Default::default(),
+        });
+        AstBridge::new().python_to_hir(ast).unwrap()
+    }
+
+    /// Captures of the closures defined in `outer`, given the enclosing
+    /// locals, in source order
+    fn closure_captures(source: &str, types: &HashMap<Symbol, Type>) -> Vec<Captures> {
+        let module = module(source);
+        let outer = &module.functions[0];
+        let mut locals: HashSet<Symbol> = outer.params.iter().map(|(n, _)| n.clone()).collect();
+        bind_names(&outer.body, &mut locals);
+        let is_local = |name: &str| locals.contains(name);
+        let scope = Scope {
+            is_local: &is_local,
+            types,
+        };
+        let mut captures = Vec::new();
+        for stmt in &outer.body {
+            match &stmt.node {
+                HirStmt::FunctionDef { func, nonlocals } => {
+                    captures.push(function_captures(func, nonlocals, &scope));
+                }
+                HirStmt::Assign {
+                    value: HirExpr::Lambda { params, body },
+                    ..
+                }
+                | HirStmt::Return(Some(HirExpr::Lambda { params, body })) => {
+                    captures.push(lambda_captures(params, body, &scope));
+                }
+                _ => {}
+            }
+        }
+        captures
+    }
+
+    #[test]
+    fn test_reading_captures_is_fn() {
+        let captures = closure_captures(
+            r#"
+def outer(factor: int, offset: int) -> int:
+    scale = lambda x: x * factor
+    def shift(x: int) -> int:
+        y = x + offset
+        return y
+    return scale(shift(1))
+"#,
+            &HashMap::new(),
+        );
+        assert_eq!(captures[0].captured, vec!["factor"]);
+        assert_eq!(captures[0].kind(), ClosureKind::Fn);
+        // `y` is local to the nested function
+        assert_eq!(captures[1].captured, vec!["offset"]);
+        assert_eq!(captures[1].kind(), ClosureKind::Fn);
+    }
+
+    #[test]
+    fn test_mutation_is_fn_mut() {
+        let captures = closure_captures(
+            r#"
+def outer(items: list) -> int:
+    count = 0
+    def bump() -> None:
+        nonlocal count
+        count += 1
+    record = lambda x: items.append(x)
+    def shadow() -> int:
+        count = 5
+        return count
+    bump()
+    return count
+"#,
+            &HashMap::new(),
+        );
+        assert_eq!(captures[0].mutated, vec!["count"]);
+        assert_eq!(captures[0].kind(), ClosureKind::FnMut);
+        assert_eq!(captures[1].mutated, vec!["items"]);
+        assert_eq!(captures[1].kind(), ClosureKind::FnMut);
+        // Without `nonlocal` the assignment binds a new local
+        assert!(captures[2].captured.is_empty());
+        assert_eq!(captures[2].kind(), ClosureKind::Fn);
+    }
+
+    #[test]
+    fn test_moving_a_capture_is_fn_once() {
+        let types = HashMap::from([
+            ("names".to_string(), Type::List(Box::new(Type::String))),
+            ("n".to_string(), Type::Int),
+        ]);
+        let captures = closure_captures(
+            r#"
+def outer(names: list, n: int) -> int:
+    take = lambda: names
+    copy = lambda: n
+    return 0
+"#,
+            &types,
+        );
+        assert_eq!(captures[0].moved, vec!["names"]);
+        assert_eq!(captures[0].kind(), ClosureKind::FnOnce);
+        // Copy values are copied out
+        assert!(captures[1].moved.is_empty());
+        assert_eq!(captures[1].kind(), ClosureKind::Fn);
+    }
+
+    #[test]
+    fn test_escapes() {
+        let module = module(
+            r#"
+def outer(n: int) -> int:
+    def add(x: int) -> int:
+        return x + n
+    def local(x: int) -> int:
+        return x
+    handlers = []
+    handlers.append(local)
+    return add
+"#,
+        );
+        let body = &module.functions[0].body;
+        assert!(escapes("add", body));
+        assert!(escapes("local", body));
+        assert!(!escapes("n", body));
+    }
+
+    #[test]
+    fn test_kind_join() {
+        assert_eq!(ClosureKind::Fn.join(ClosureKind::FnMut), ClosureKind::FnMut);
+        assert_eq!(
+            ClosureKind::FnOnce.join(ClosureKind::Fn),
+            ClosureKind::FnOnce
+        );
+        assert_eq!(ClosureKind::FnMut.trait_name(), "FnMut");
+    }
+}
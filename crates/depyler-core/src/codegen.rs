@@ -1,4 +1,6 @@
+use crate::error::ErrorKind;
 use crate::hir::*;
+use crate::transpile_bail;
 use anyhow::Result;
 use quote::{quote, ToTokens};
 use std::collections::HashSet;
@@ -51,12 +53,12 @@ fn uses_hashmap(ty: &Type) -> bool {
     }
 }
 
-fn function_body_uses_hashmap(body: &[HirStmt]) -> bool {
+fn function_body_uses_hashmap(body: &[Spanned<HirStmt>]) -> bool {
     body.iter().any(stmt_uses_hashmap)
 }
 
-fn stmt_uses_hashmap(stmt: &HirStmt) -> bool {
-    match stmt {
+fn stmt_uses_hashmap(stmt: &Spanned<HirStmt>) -> bool {
+    match &stmt.node {
         HirStmt::Assign { value, .. } => expr_uses_hashmap(value),
         HirStmt::Return(Some(expr)) => expr_uses_hashmap(expr),
         HirStmt::If {
@@ -86,9 +88,17 @@ fn expr_uses_hashmap(expr: &HirExpr) -> bool {
         HirExpr::Dict(_) => true,
         HirExpr::Binary { left, right, .. } => expr_uses_hashmap(left) || expr_uses_hashmap(right),
         HirExpr::Unary { operand, .. } => expr_uses_hashmap(operand),
-        HirExpr::Call { args, .. } => args.iter().any(expr_uses_hashmap),
+        HirExpr::Call { args, kwargs, .. } => args
+            .iter()
+            .chain(kwargs.iter().map(|(_, v)| v))
+            .any(expr_uses_hashmap),
+        HirExpr::MethodCall { receiver, args, .. } => {
+            expr_uses_hashmap(receiver) || args.iter().any(expr_uses_hashmap)
+        }
         HirExpr::Index { base, index } => expr_uses_hashmap(base) || expr_uses_hashmap(index),
-        HirExpr::List(items) | HirExpr::Tuple(items) => items.iter().any(expr_uses_hashmap),
+        HirExpr::List(items) | HirExpr::Set(items) | HirExpr::Tuple(items) => {
+            items.iter().any(expr_uses_hashmap)
+        }
         _ => false,
     }
 }
@@ -156,8 +166,9 @@ fn convert_function_to_rust(func: &HirFunction) -> Result<proc_macro2::TokenStre
         .map(|stmt| stmt_to_rust_tokens_with_scope(stmt, &mut scope_tracker))
         .collect::<Result<Vec<_>>>()?;
 
+    let asyncness = func.is_async.then(|| quote! { async });
     Ok(quote! {
-        pub fn #name(#(#params),*) -> #return_type {
+        pub #asyncness fn #name(#(#params),*) -> #return_type {
             #(#body_stmts)*
         }
     })
@@ -179,6 +190,10 @@ fn type_to_rust_type(ty: &Type) -> proc_macro2::TokenStream {
             let value_type = type_to_rust_type(value);
             quote! { HashMap<#key_type, #value_type> }
         }
+        Type::Set(inner) => {
+            let inner_type = type_to_rust_type(inner);
+            quote! { std::collections::HashSet<#inner_type> }
+        }
         Type::Tuple(types) => {
             let rust_types: Vec<_> = types.iter().map(type_to_rust_type).collect();
             quote! { (#(#rust_types),*) }
@@ -196,6 +211,11 @@ fn type_to_rust_type(ty: &Type) -> proc_macro2::TokenStream {
             let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
             quote! { #ident }
         }
+        Type::Union(_) | Type::Literal(_) => {
+            let name = crate::type_enums::enum_name(ty).expect("a generated enum");
+            let ident = syn::Ident::new(&name, proc_macro2::Span::call_site());
+            quote! { #ident }
+        }
         Type::Unknown => quote! { () },
     }
 }
@@ -212,7 +232,19 @@ fn stmt_to_rust_tokens_with_scope(
     scope_tracker: &mut ScopeTracker,
 ) -> Result<proc_macro2::TokenStream> {
     match stmt {
-        HirStmt::Assign { target, value } => {
+        HirStmt::Assign {
+            target: AssignTarget::Attribute { value: base, attr },
+            value,
+        } => {
+            let base_tokens = expr_to_rust_tokens(base)?;
+            let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+            let value_tokens = expr_to_rust_tokens(value)?;
+            Ok(quote! { #base_tokens.#attr_ident = #value_tokens; })
+        }
+        HirStmt::Assign {
+            target: AssignTarget::Symbol(target),
+            value,
+        } => {
             let target_ident = syn::Ident::new(target, proc_macro2::Span::call_site());
             let value_tokens = expr_to_rust_tokens(value)?;
 
@@ -282,7 +314,16 @@ fn stmt_to_rust_tokens_with_scope(
                 }
             })
         }
+        HirStmt::Assign { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Subscript and unpacking assignments are only supported by the rust_gen backend"
+                .to_string(),
+        )),
         HirStmt::For { target, iter, body } => {
+            let Some(target) = target.as_symbol() else {
+                transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "Unpacking loop targets are only supported by the rust_gen backend".to_string(),
+                ))
+            };
             let target_ident = syn::Ident::new(target, proc_macro2::Span::call_site());
             let iter_tokens = expr_to_rust_tokens(iter)?;
             scope_tracker.enter_scope();
@@ -302,6 +343,25 @@ fn stmt_to_rust_tokens_with_scope(
             let expr_tokens = expr_to_rust_tokens(expr)?;
             Ok(quote! { #expr_tokens; })
         }
+        HirStmt::Raise { .. } | HirStmt::Try { .. } => {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Exception handling is only supported by the rust_gen backend".to_string(),
+            ))
+        }
+        HirStmt::Yield(_) | HirStmt::YieldFrom(_) => {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Generators are only supported by the rust_gen backend".to_string(),
+            ))
+        }
+        HirStmt::With { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Context managers are only supported by the rust_gen backend".to_string(),
+        )),
+        HirStmt::Match { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Pattern matching is only supported by the rust_gen backend".to_string(),
+        )),
+        HirStmt::FunctionDef { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Nested functions are only supported by the rust_gen backend".to_string(),
+        )),
     }
 }
 
@@ -333,7 +393,19 @@ fn expr_to_rust_tokens(expr: &HirExpr) -> Result<proc_macro2::TokenStream> {
             let op_tokens = unaryop_to_rust_tokens(op);
             Ok(quote! { (#op_tokens #operand_tokens) })
         }
-        HirExpr::Call { func, args } => {
+        HirExpr::Call { func, kwargs, .. } if !kwargs.is_empty() => {
+            transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "Keyword arguments to '{}' are not supported",
+                func
+            )))
+        }
+        HirExpr::MethodCall { method, kwargs, .. } if !kwargs.is_empty() => {
+            transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "Keyword arguments to method '{}' are not supported",
+                method
+            )))
+        }
+        HirExpr::Call { func, args, .. } => {
             let func_ident = syn::Ident::new(func, proc_macro2::Span::call_site());
             let arg_tokens: Vec<_> = args
                 .iter()
@@ -341,6 +413,20 @@ fn expr_to_rust_tokens(expr: &HirExpr) -> Result<proc_macro2::TokenStream> {
                 .collect::<Result<Vec<_>>>()?;
             Ok(quote! { #func_ident(#(#arg_tokens),*) })
         }
+        HirExpr::MethodCall {
+            receiver,
+            method,
+            args,
+            ..
+        } => {
+            let receiver_tokens = expr_to_rust_tokens(receiver)?;
+            let method_ident = syn::Ident::new(method, proc_macro2::Span::call_site());
+            let arg_tokens: Vec<_> = args
+                .iter()
+                .map(expr_to_rust_tokens)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(quote! { #receiver_tokens.#method_ident(#(#arg_tokens),*) })
+        }
         HirExpr::Index { base, index } => {
             let base_tokens = expr_to_rust_tokens(base)?;
             let index_tokens = expr_to_rust_tokens(index)?;
@@ -380,6 +466,25 @@ fn expr_to_rust_tokens(expr: &HirExpr) -> Result<proc_macro2::TokenStream> {
             let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
             Ok(quote! { #value_tokens.#attr_ident })
         }
+        HirExpr::Comprehension { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Comprehensions are only supported by the rust_gen backend".to_string(),
+        )),
+        HirExpr::Set(_) => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Sets are only supported by the rust_gen backend".to_string(),
+        )),
+        HirExpr::Lambda { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Lambdas are only supported by the rust_gen backend".to_string(),
+        )),
+        HirExpr::FString { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Formatted strings are only supported by the rust_gen backend".to_string(),
+        )),
+        HirExpr::Slice { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Slicing is only supported by the rust_gen backend".to_string(),
+        )),
+        HirExpr::Await { value } => {
+            let value_tokens = expr_to_rust_tokens(value)?;
+            Ok(quote! { #value_tokens.await })
+        }
         HirExpr::Borrow { expr, mutable } => {
             let expr_tokens = expr_to_rust_tokens(expr)?;
             if *mutable {
@@ -469,7 +574,7 @@ fn prettify_rust_code(code: String) -> String {
 
 /// Check if an expression is a len() call
 fn is_len_call(expr: &HirExpr) -> bool {
-    matches!(expr, HirExpr::Call { func, args } if func == "len" && args.len() == 1)
+    matches!(expr, HirExpr::Call { func, args, .. } if func == "len" && args.len() == 1)
 }
 
 #[cfg(test)]
@@ -482,20 +587,26 @@ mod tests {
         let func = HirFunction {
             name: "add".to_string(),
             params: vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)].into(),
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Add,
                 left: Box::new(HirExpr::Var("a".to_string())),
                 right: Box::new(HirExpr::Var("b".to_string())),
-            }))],
+            }))
+            .into()],
             properties: FunctionProperties::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Span::default(),
         };
 
         let module = HirModule {
             functions: vec![func],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let rust_code = hir_to_rust(&module).unwrap();
@@ -540,10 +651,12 @@ mod tests {
             },
             then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::String(
                 "positive".to_string(),
-            ))))],
+            ))))
+            .into()],
             else_body: Some(vec![HirStmt::Return(Some(HirExpr::Literal(
                 Literal::String("negative".to_string()),
-            )))]),
+            )))
+            .into()]),
         };
 
         let tokens = stmt_to_rust_tokens(&if_stmt).unwrap();
@@ -563,13 +676,18 @@ mod tests {
                     Type::Dict(Box::new(Type::String), Box::new(Type::Int)),
                 )]
                 .into(),
+                param_spec: Default::default(),
                 ret_type: Type::None,
                 body: vec![],
                 properties: FunctionProperties::default(),
                 annotations: TranspilationAnnotations::default(),
                 docstring: None,
+                is_async: false,
+                span: Span::default(),
             }],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         assert!(needs_std_collections(&module_with_dict));
@@ -578,13 +696,18 @@ mod tests {
             functions: vec![HirFunction {
                 name: "test".to_string(),
                 params: vec![("x".to_string(), Type::Int)].into(),
+                param_spec: Default::default(),
                 ret_type: Type::Int,
                 body: vec![],
                 properties: FunctionProperties::default(),
                 annotations: TranspilationAnnotations::default(),
                 docstring: None,
+                is_async: false,
+                span: Span::default(),
             }],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         assert!(!needs_std_collections(&module_without_dict));
@@ -593,7 +716,7 @@ mod tests {
     #[test]
     fn test_assignment_generation() {
         let assign = HirStmt::Assign {
-            target: "x".to_string(),
+            target: AssignTarget::Symbol("x".to_string()),
             value: HirExpr::Literal(Literal::Int(42)),
         };
 
@@ -607,6 +730,7 @@ mod tests {
         let call = HirExpr::Call {
             func: "len".to_string(),
             args: vec![HirExpr::List(vec![HirExpr::Literal(Literal::Int(1))])],
+            kwargs: vec![],
         };
 
         let tokens = expr_to_rust_tokens(&call).unwrap();
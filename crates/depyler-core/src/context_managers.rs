@@ -0,0 +1,359 @@
+//! Python context managers lowered to RAII scopes.
+//!
+//! A `with` statement becomes a Rust block that binds each resource and
+//! drops it at the end of the block, so cleanup also runs when the block is
+//! left early by `return`, `break` or a propagated error. Files, locks and
+//! `tempfile` resources map onto their Rust counterparts; module classes
+//! defining `__exit__` get a generated guard whose `Drop` calls it.
+
+use crate::error::ErrorKind;
+use crate::hir::*;
+use crate::transpile_bail;
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Rust type of `threading.Lock` and `threading.RLock` values
+pub const LOCK_RUST_TYPE: &str = "Arc<std::sync::Mutex<()>>";
+
+/// How `open` accesses its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileMode {
+    Read,
+    Write,
+    Append,
+}
+
+/// What the context expression of a `with` item manages
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContextManager<'e> {
+    /// `open(path, mode)`, held as a `std::fs::File`
+    File { path: &'e HirExpr, mode: FileMode },
+    /// A lock, held through its `MutexGuard`
+    Lock,
+    /// `tempfile.TemporaryFile()`, removed once closed
+    TempFile,
+    /// `tempfile.NamedTemporaryFile()`
+    NamedTempFile,
+    /// `tempfile.TemporaryDirectory()`, bound to its path
+    TempDir,
+    /// An instance of a module class defining `__exit__`
+    Class(&'e str),
+    /// Any other value is bound and dropped at the end of the block
+    Value,
+}
+
+impl ContextManager<'_> {
+    /// Whether the bound value supports `read` and `write`
+    pub fn is_file(&self) -> bool {
+        matches!(
+            self,
+            ContextManager::File { .. } | ContextManager::TempFile | ContextManager::NamedTempFile
+        )
+    }
+}
+
+/// How the instances of a class defining `__exit__` are entered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassGuard {
+    /// `__enter__` does more than `return self`, so its result is bound
+    pub calls_enter: bool,
+}
+
+/// Classes of the module usable as context managers
+pub fn guarded_classes(module: &HirModule) -> HashMap<String, ClassGuard> {
+    module
+        .classes
+        .iter()
+        .filter(|class| class.methods.iter().any(|m| m.name == "__exit__"))
+        .map(|class| {
+            let calls_enter = class
+                .methods
+                .iter()
+                .any(|m| m.name == "__enter__" && !returns_self(m));
+            (class.name.clone(), ClassGuard { calls_enter })
+        })
+        .collect()
+}
+
+/// Whether a method body is just `return self`
+pub fn returns_self(method: &HirMethod) -> bool {
+    match method.body.as_slice() {
+        [stmt] => matches!(&stmt.node, HirStmt::Return(Some(HirExpr::Var(v))) if v == "self"),
+        _ => false,
+    }
+}
+
+/// Whether a type is a `threading` lock
+pub fn is_lock_type(ty: &Type) -> bool {
+    matches!(ty, Type::Custom(name) if name == "Lock" || name == "RLock")
+}
+
+/// Whether an expression creates a new `threading` lock
+pub fn is_lock_constructor(expr: &HirExpr) -> bool {
+    matches!(
+        expr,
+        HirExpr::MethodCall { receiver, method, args, .. }
+            if args.is_empty()
+                && matches!(method.as_str(), "Lock" | "RLock")
+                && matches!(receiver.as_ref(), HirExpr::Var(v) if v == "threading")
+    )
+}
+
+/// Classify the context expression of a `with` item.
+///
+/// `expr_type` gives the declared type of variables and fields, which is how
+/// locks other than new ones are recognised.
+pub fn classify<'e>(
+    expr: &'e HirExpr,
+    classes: &HashMap<String, ClassGuard>,
+    expr_type: impl Fn(&HirExpr) -> Option<Type>,
+) -> Result<ContextManager<'e>> {
+    if is_lock_constructor(expr) || expr_type(expr).is_some_and(|ty| is_lock_type(&ty)) {
+        return Ok(ContextManager::Lock);
+    }
+    match expr {
+        HirExpr::Call { func, args, kwargs } if func == "open" => {
+            let mode = match (args.as_slice(), kwargs.as_slice()) {
+                ([_], []) => None,
+                ([_, mode], []) | ([_], [(_, mode)]) => Some(mode),
+                _ => transpile_bail!(ErrorKind::CodeGenerationError(
+                    "open() needs a path and an optional literal mode".to_string(),
+                )),
+            };
+            if kwargs.iter().any(|(name, _)| name != "mode") {
+                transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "open() only accepts the 'mode' keyword argument".to_string(),
+                ));
+            }
+            let mode = match mode {
+                None => FileMode::Read,
+                Some(HirExpr::Literal(Literal::String(mode))) => file_mode(mode)?,
+                Some(_) => transpile_bail!(ErrorKind::CodeGenerationError(
+                    "open() needs a path and an optional literal mode".to_string(),
+                )),
+            };
+            Ok(ContextManager::File {
+                path: &args[0],
+                mode,
+            })
+        }
+        HirExpr::Call { func, .. } if classes.contains_key(func) => Ok(ContextManager::Class(func)),
+        HirExpr::Call { func, args, kwargs } => match tempfile_manager(func, args, kwargs)? {
+            Some(manager) => Ok(manager),
+            None => Ok(ContextManager::Value),
+        },
+        HirExpr::MethodCall {
+            receiver,
+            method,
+            args,
+            kwargs,
+        } if matches!(receiver.as_ref(), HirExpr::Var(v) if v == "tempfile") => {
+            match tempfile_manager(method, args, kwargs)? {
+                Some(manager) => Ok(manager),
+                None => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                    "tempfile.{} is not supported",
+                    method
+                ))),
+            }
+        }
+        _ => Ok(ContextManager::Value),
+    }
+}
+
+fn file_mode(mode: &str) -> Result<FileMode> {
+    Ok(match mode {
+        "r" | "rb" | "rt" => FileMode::Read,
+        "w" | "wb" | "wt" => FileMode::Write,
+        "a" | "ab" | "at" => FileMode::Append,
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "File mode '{}' is not supported",
+            mode
+        ))),
+    })
+}
+
+fn tempfile_manager(
+    name: &str,
+    args: &[HirExpr],
+    kwargs: &[(Symbol, HirExpr)],
+) -> Result<Option<ContextManager<'static>>> {
+    let manager = match name {
+        "TemporaryFile" => ContextManager::TempFile,
+        "NamedTemporaryFile" => ContextManager::NamedTempFile,
+        "TemporaryDirectory" => ContextManager::TempDir,
+        _ => return Ok(None),
+    };
+    if !args.is_empty() || !kwargs.is_empty() {
+        transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Arguments to tempfile.{} are not supported",
+            name
+        )));
+    }
+    Ok(Some(manager))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> HirExpr {
+        HirExpr::Var(name.to_string())
+    }
+
+    fn string(s: &str) -> HirExpr {
+        HirExpr::Literal(Literal::String(s.to_string()))
+    }
+
+    fn method_call(receiver: &str, method: &str) -> HirExpr {
+        HirExpr::MethodCall {
+            receiver: Box::new(var(receiver)),
+            method: method.to_string(),
+            args: vec![],
+            kwargs: vec![],
+        }
+    }
+
+    #[test]
+    fn test_classify_open_modes() {
+        let classes = HashMap::new();
+        let path = var("path");
+        let open = |args: Vec<HirExpr>| HirExpr::Call {
+            func: "open".to_string(),
+            args,
+            kwargs: vec![],
+        };
+
+        let read = open(vec![path.clone()]);
+        assert_eq!(
+            classify(&read, &classes, |_| None).unwrap(),
+            ContextManager::File {
+                path: &path,
+                mode: FileMode::Read
+            }
+        );
+        let append = open(vec![path.clone(), string("a")]);
+        assert!(matches!(
+            classify(&append, &classes, |_| None).unwrap(),
+            ContextManager::File {
+                mode: FileMode::Append,
+                ..
+            }
+        ));
+        assert!(classify(&open(vec![path.clone(), string("r+")]), &classes, |_| None).is_err());
+
+        let keyword_mode = HirExpr::Call {
+            func: "open".to_string(),
+            args: vec![path.clone()],
+            kwargs: vec![("mode".to_string(), string("w"))],
+        };
+        assert!(matches!(
+            classify(&keyword_mode, &classes, |_| None).unwrap(),
+            ContextManager::File {
+                mode: FileMode::Write,
+                ..
+            }
+        ));
+        let encoding = HirExpr::Call {
+            func: "open".to_string(),
+            args: vec![path],
+            kwargs: vec![("encoding".to_string(), string("utf-8"))],
+        };
+        assert!(classify(&encoding, &classes, |_| None).is_err());
+    }
+
+    #[test]
+    fn test_classify_locks_and_tempfiles() {
+        let classes = HashMap::new();
+        let field = HirExpr::Attribute {
+            value: Box::new(var("self")),
+            attr: "lock".to_string(),
+        };
+        let lock_type = |e: &HirExpr| (e == &field).then(|| Type::Custom("Lock".to_string()));
+        assert_eq!(
+            classify(&field, &classes, lock_type).unwrap(),
+            ContextManager::Lock
+        );
+        assert!(is_lock_constructor(&method_call("threading", "RLock")));
+        assert_eq!(
+            classify(&method_call("threading", "Lock"), &classes, |_| None).unwrap(),
+            ContextManager::Lock
+        );
+        assert_eq!(
+            classify(
+                &method_call("tempfile", "TemporaryDirectory"),
+                &classes,
+                |_| None
+            )
+            .unwrap(),
+            ContextManager::TempDir
+        );
+        assert!(classify(&method_call("tempfile", "mkdtemp"), &classes, |_| None).is_err());
+        assert_eq!(
+            classify(&var("resource"), &classes, |_| None).unwrap(),
+            ContextManager::Value
+        );
+    }
+
+    #[test]
+    fn test_guarded_classes() {
+        let method = |name: &str, body: Vec<Spanned<HirStmt>>| HirMethod {
+            name: name.to_string(),
+            params: Default::default(),
+            param_spec: Default::default(),
+            ret_type: Type::Unknown,
+            body,
+            kind: MethodKind::Instance,
+            properties: FunctionProperties::default(),
+            annotations: Default::default(),
+            docstring: None,
+            is_async: false,
+            span: Span::default(),
+        };
+        let class = |name: &str, methods: Vec<HirMethod>| HirClass {
+            name: name.to_string(),
+            base_classes: vec![],
+            fields: vec![],
+            methods,
+            is_dataclass: false,
+            docstring: None,
+            annotations: Default::default(),
+        };
+        let module = HirModule {
+            functions: vec![],
+            classes: vec![
+                class(
+                    "Timer",
+                    vec![
+                        method("__enter__", vec![HirStmt::Return(Some(var("self"))).into()]),
+                        method("__exit__", vec![]),
+                    ],
+                ),
+                class(
+                    "Session",
+                    vec![
+                        method("__enter__", vec![HirStmt::Return(Some(var("conn"))).into()]),
+                        method("__exit__", vec![]),
+                    ],
+                ),
+                class("Plain", vec![method("run", vec![])]),
+            ],
+            imports: vec![],
+            skipped: vec![],
+        };
+
+        let guarded = guarded_classes(&module);
+        assert_eq!(guarded.len(), 2);
+        assert!(!guarded["Timer"].calls_enter);
+        assert!(guarded["Session"].calls_enter);
+
+        let call = HirExpr::Call {
+            func: "Timer".to_string(),
+            args: vec![],
+            kwargs: vec![],
+        };
+        assert_eq!(
+            classify(&call, &guarded, |_| None).unwrap(),
+            ContextManager::Class("Timer")
+        );
+    }
+}
@@ -1,6 +1,9 @@
+use crate::builtins;
+use crate::error::ErrorKind;
 use crate::hir::*;
+use crate::transpile_bail;
 use crate::type_mapper::{RustType, TypeMapper};
-use anyhow::{bail, Result};
+use anyhow::Result;
 use syn::{self, parse_quote};
 
 pub fn apply_rules(module: &HirModule, type_mapper: &TypeMapper) -> Result<syn::File> {
@@ -11,6 +14,11 @@ pub fn apply_rules(module: &HirModule, type_mapper: &TypeMapper) -> Result<syn::
         use std::collections::HashMap;
     });
 
+    // Convert classes
+    for class in &module.classes {
+        items.extend(convert_class(class, type_mapper)?);
+    }
+
     // Convert functions
     for func in &module.functions {
         let rust_func = convert_function(func, type_mapper)?;
@@ -97,7 +105,7 @@ fn convert_function(func: &HirFunction, type_mapper: &TypeMapper) -> Result<syn:
         vis: syn::Visibility::Public(Default::default()),
         sig: syn::Signature {
             constness: None,
-            asyncness: None,
+            asyncness: func.is_async.then(Default::default),
             unsafety: None,
             abi: None,
             fn_token: Default::default(),
@@ -112,6 +120,99 @@ fn convert_function(func: &HirFunction, type_mapper: &TypeMapper) -> Result<syn:
     })
 }
 
+fn convert_class(class: &HirClass, type_mapper: &TypeMapper) -> Result<Vec<syn::Item>> {
+    let name = syn::Ident::new(&class.name, proc_macro2::Span::call_site());
+
+    let mut fields = Vec::new();
+    for field in class.fields.iter().filter(|f| !f.is_class_var) {
+        let field_ident = syn::Ident::new(&field.name, proc_macro2::Span::call_site());
+        let ty = rust_type_to_syn(&type_mapper.map_type(&field.field_type))?;
+        fields.push(quote::quote! { pub #field_ident: #ty });
+    }
+
+    let derives: syn::Attribute = if class.is_dataclass {
+        parse_quote! { #[derive(Debug, Clone, PartialEq)] }
+    } else {
+        parse_quote! { #[derive(Debug, Clone)] }
+    };
+    let struct_item: syn::Item = parse_quote! {
+        #derives
+        pub struct #name {
+            #(#fields),*
+        }
+    };
+
+    // `__init__` needs field-aware lowering that only the main generator does
+    let mut methods = Vec::new();
+    for method in class.methods.iter().filter(|m| m.name != "__init__") {
+        methods.push(convert_method(method, type_mapper)?);
+    }
+    if methods.is_empty() {
+        return Ok(vec![struct_item]);
+    }
+
+    let impl_item: syn::Item = parse_quote! {
+        impl #name {
+            #(#methods)*
+        }
+    };
+    Ok(vec![struct_item, impl_item])
+}
+
+fn convert_method(method: &HirMethod, type_mapper: &TypeMapper) -> Result<syn::ImplItemFn> {
+    let func = HirFunction {
+        name: method.name.clone(),
+        params: method.params.clone(),
+        param_spec: method.param_spec.clone(),
+        ret_type: method.ret_type.clone(),
+        body: method.body.clone(),
+        properties: method.properties.clone(),
+        annotations: method.annotations.clone(),
+        docstring: method.docstring.clone(),
+        is_async: method.is_async,
+        span: Span::default(),
+    };
+    let mut item_fn = convert_function(&func, type_mapper)?;
+
+    let receiver: Option<syn::FnArg> = match method.kind {
+        MethodKind::Instance if assigns_self_attribute(&method.body) => {
+            Some(parse_quote! { &mut self })
+        }
+        MethodKind::Instance | MethodKind::Property => Some(parse_quote! { &self }),
+        MethodKind::Static | MethodKind::Class => None,
+    };
+    if let Some(receiver) = receiver {
+        item_fn.sig.inputs.insert(0, receiver);
+    }
+
+    Ok(syn::ImplItemFn {
+        attrs: item_fn.attrs,
+        vis: item_fn.vis,
+        defaultness: None,
+        sig: item_fn.sig,
+        block: *item_fn.block,
+    })
+}
+
+fn assigns_self_attribute(body: &[Spanned<HirStmt>]) -> bool {
+    body.iter().any(|stmt| match &stmt.node {
+        HirStmt::Assign {
+            target: AssignTarget::Attribute { value, .. },
+            ..
+        } => matches!(value.as_ref(), HirExpr::Var(v) if v == "self"),
+        HirStmt::If {
+            then_body,
+            else_body,
+            ..
+        } => {
+            assigns_self_attribute(then_body)
+                || else_body.as_deref().is_some_and(assigns_self_attribute)
+        }
+        HirStmt::While { body, .. } | HirStmt::For { body, .. } => assigns_self_attribute(body),
+        _ => false,
+    })
+}
+
 fn rust_type_to_syn(rust_type: &RustType) -> Result<syn::Type> {
     Ok(match rust_type {
         RustType::Primitive(p) => {
@@ -123,21 +224,36 @@ fn rust_type_to_syn(rust_type: &RustType) -> Result<syn::Type> {
             let inner_ty = rust_type_to_syn(inner)?;
             parse_quote! { Vec<#inner_ty> }
         }
+        RustType::Slice(inner) => {
+            let inner_ty = rust_type_to_syn(inner)?;
+            parse_quote! { [#inner_ty] }
+        }
         RustType::HashMap(k, v) => {
             let key_ty = rust_type_to_syn(k)?;
             let val_ty = rust_type_to_syn(v)?;
             parse_quote! { HashMap<#key_ty, #val_ty> }
         }
+        RustType::HashSet(inner) => {
+            let inner_ty = rust_type_to_syn(inner)?;
+            parse_quote! { HashSet<#inner_ty> }
+        }
+        RustType::BTreeSet(inner) => {
+            let inner_ty = rust_type_to_syn(inner)?;
+            parse_quote! { BTreeSet<#inner_ty> }
+        }
         RustType::Option(inner) => {
             let inner_ty = rust_type_to_syn(inner)?;
             parse_quote! { Option<#inner_ty> }
         }
         RustType::Unit => parse_quote! { () },
-        _ => bail!("Unsupported Rust type: {:?}", rust_type),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Unsupported Rust type: {:?}",
+            rust_type
+        ))),
     })
 }
 
-fn convert_body(stmts: &[HirStmt], type_mapper: &TypeMapper) -> Result<Vec<syn::Stmt>> {
+fn convert_body(stmts: &[Spanned<HirStmt>], type_mapper: &TypeMapper) -> Result<Vec<syn::Stmt>> {
     stmts
         .iter()
         .map(|stmt| convert_stmt(stmt, type_mapper))
@@ -146,7 +262,22 @@ fn convert_body(stmts: &[HirStmt], type_mapper: &TypeMapper) -> Result<Vec<syn::
 
 fn convert_stmt(stmt: &HirStmt, type_mapper: &TypeMapper) -> Result<syn::Stmt> {
     match stmt {
-        HirStmt::Assign { target, value } => {
+        HirStmt::Assign {
+            target: AssignTarget::Attribute { value: base, attr },
+            value,
+        } => {
+            let base_expr = convert_expr(base, type_mapper)?;
+            let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+            let value_expr = convert_expr(value, type_mapper)?;
+            Ok(syn::Stmt::Expr(
+                parse_quote! { #base_expr.#attr_ident = #value_expr },
+                Some(Default::default()),
+            ))
+        }
+        HirStmt::Assign {
+            target: AssignTarget::Symbol(target),
+            value,
+        } => {
             let target_ident = syn::Ident::new(target, proc_macro2::Span::call_site());
             let value_expr = convert_expr(value, type_mapper)?;
 
@@ -211,7 +342,15 @@ fn convert_stmt(stmt: &HirStmt, type_mapper: &TypeMapper) -> Result<syn::Stmt> {
 
             Ok(syn::Stmt::Expr(while_expr, Some(Default::default())))
         }
+        HirStmt::Assign { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Subscript and unpacking assignments are not yet supported by direct rules".to_string(),
+        )),
         HirStmt::For { target, iter, body } => {
+            let Some(target) = target.as_symbol() else {
+                transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "Unpacking loop targets are not yet supported by direct rules".to_string(),
+                ))
+            };
             let target_ident = syn::Ident::new(target, proc_macro2::Span::call_site());
             let iter_expr = convert_expr(iter, type_mapper)?;
             let body_block = convert_block(body, type_mapper)?;
@@ -226,10 +365,29 @@ fn convert_stmt(stmt: &HirStmt, type_mapper: &TypeMapper) -> Result<syn::Stmt> {
             let rust_expr = convert_expr(expr, type_mapper)?;
             Ok(syn::Stmt::Expr(rust_expr, Some(Default::default())))
         }
+        HirStmt::Raise { .. } | HirStmt::Try { .. } => {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Exception handling is not yet supported by direct rules".to_string(),
+            ))
+        }
+        HirStmt::Yield(_) | HirStmt::YieldFrom(_) => {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Generators are not yet supported by direct rules".to_string(),
+            ))
+        }
+        HirStmt::With { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Context managers are not yet supported by direct rules".to_string(),
+        )),
+        HirStmt::Match { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Pattern matching is not yet supported by direct rules".to_string(),
+        )),
+        HirStmt::FunctionDef { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Nested functions are not yet supported by direct rules".to_string(),
+        )),
     }
 }
 
-fn convert_block(stmts: &[HirStmt], type_mapper: &TypeMapper) -> Result<syn::Block> {
+fn convert_block(stmts: &[Spanned<HirStmt>], type_mapper: &TypeMapper) -> Result<syn::Block> {
     let rust_stmts = convert_body(stmts, type_mapper)?;
     Ok(syn::Block {
         brace_token: Default::default(),
@@ -245,7 +403,6 @@ fn convert_expr(expr: &HirExpr, type_mapper: &TypeMapper) -> Result<syn::Expr> {
 
 /// Expression converter using strategy pattern to reduce complexity
 struct ExprConverter<'a> {
-    #[allow(dead_code)]
     type_mapper: &'a TypeMapper,
 }
 
@@ -260,15 +417,43 @@ impl<'a> ExprConverter<'a> {
             HirExpr::Var(name) => self.convert_variable(name),
             HirExpr::Binary { op, left, right } => self.convert_binary(*op, left, right),
             HirExpr::Unary { op, operand } => self.convert_unary(*op, operand),
-            HirExpr::Call { func, args } => self.convert_call(func, args),
+            HirExpr::Call { func, kwargs, .. } if !kwargs.is_empty() => {
+                transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                    "Keyword arguments to '{}' are not supported",
+                    func
+                )))
+            }
+            HirExpr::MethodCall { method, kwargs, .. } if !kwargs.is_empty() => {
+                transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                    "Keyword arguments to method '{}' are not supported",
+                    method
+                )))
+            }
+            HirExpr::Call { func, args, .. } => self.convert_call(func, args),
+            HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                ..
+            } => self.convert_method_call(receiver, method, args),
             HirExpr::Index { base, index } => self.convert_index(base, index),
             HirExpr::List(elts) => self.convert_list(elts),
             HirExpr::Dict(items) => self.convert_dict(items),
             HirExpr::Tuple(elts) => self.convert_tuple(elts),
-            _ => bail!("Expression type not yet supported: {:?}", expr),
+            HirExpr::Attribute { value, attr } => self.convert_attribute(value, attr),
+            HirExpr::Await { value } => self.convert_await(value),
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "Expression type not yet supported: {:?}",
+                expr
+            ))),
         }
     }
 
+    fn convert_await(&self, value: &HirExpr) -> Result<syn::Expr> {
+        let value_expr = self.convert(value)?;
+        Ok(parse_quote! { #value_expr.await })
+    }
+
     fn convert_literal(&self, lit: &Literal) -> Result<syn::Expr> {
         Ok(convert_literal(lit))
     }
@@ -292,16 +477,6 @@ impl<'a> ExprConverter<'a> {
                 // Convert "x not in dict" to "!dict.contains_key(&x)"
                 Ok(parse_quote! { !#right_expr.contains_key(&#left_expr) })
             }
-            BinOp::Sub => {
-                // Check if we're subtracting from a .len() call to prevent underflow
-                if is_len_call(left) {
-                    // Use saturating_sub to prevent underflow when subtracting from array length
-                    Ok(parse_quote! { #left_expr.saturating_sub(#right_expr) })
-                } else {
-                    let rust_op = convert_binop(op)?;
-                    Ok(parse_quote! { #left_expr #rust_op #right_expr })
-                }
-            }
             _ => {
                 let rust_op = convert_binop(op)?;
                 Ok(parse_quote! { #left_expr #rust_op #right_expr })
@@ -325,37 +500,27 @@ impl<'a> ExprConverter<'a> {
             .map(|arg| self.convert(arg))
             .collect::<Result<Vec<_>>>()?;
 
-        match func {
-            "len" => self.convert_len_call(&arg_exprs),
-            "range" => self.convert_range_call(&arg_exprs),
-            _ => self.convert_generic_call(func, &arg_exprs),
-        }
-    }
-
-    fn convert_len_call(&self, args: &[syn::Expr]) -> Result<syn::Expr> {
-        if args.len() != 1 {
-            bail!("len() requires exactly one argument");
+        // Without type information every builtin takes its untyped form
+        let builtin_args: Vec<builtins::Arg> = args
+            .iter()
+            .zip(&arg_exprs)
+            .map(|(hir, expr)| builtins::Arg {
+                hir,
+                expr: expr.clone(),
+                ty: Type::Unknown,
+                movable: false,
+            })
+            .collect();
+        if func == "print" {
+            let args: Vec<builtins::PrintArg> = builtin_args
+                .into_iter()
+                .map(builtins::PrintArg::Value)
+                .collect();
+            return builtins::lower_print(&args, &[], &mut Default::default());
         }
-        let arg = &args[0];
-        Ok(parse_quote! { #arg.len() })
-    }
-
-    fn convert_range_call(&self, args: &[syn::Expr]) -> Result<syn::Expr> {
-        match args.len() {
-            1 => {
-                let end = &args[0];
-                Ok(parse_quote! { 0..#end })
-            }
-            2 => {
-                let start = &args[0];
-                let end = &args[1];
-                Ok(parse_quote! { #start..#end })
-            }
-            3 => {
-                // TODO: Handle step parameter
-                bail!("range() with step parameter not yet supported")
-            }
-            _ => bail!("Invalid number of arguments for range()"),
+        match builtins::lower(func, &builtin_args, self.type_mapper)? {
+            Some(call) => Ok(call),
+            None => self.convert_generic_call(func, &arg_exprs),
         }
     }
 
@@ -364,6 +529,24 @@ impl<'a> ExprConverter<'a> {
         Ok(parse_quote! { #func_ident(#(#args),*) })
     }
 
+    fn convert_method_call(
+        &self,
+        receiver: &HirExpr,
+        method: &str,
+        args: &[HirExpr],
+    ) -> Result<syn::Expr> {
+        let recv = self.convert(receiver)?;
+        let arg_exprs: Vec<syn::Expr> = args
+            .iter()
+            .map(|arg| self.convert(arg))
+            .collect::<Result<Vec<_>>>()?;
+
+        // V1: Only the most common list mutation is renamed
+        let method = if method == "append" { "push" } else { method };
+        let method_ident = syn::Ident::new(method, proc_macro2::Span::call_site());
+        Ok(parse_quote! { #recv.#method_ident(#(#arg_exprs),*) })
+    }
+
     fn convert_index(&self, base: &HirExpr, index: &HirExpr) -> Result<syn::Expr> {
         let base_expr = self.convert(base)?;
         let index_expr = self.convert(index)?;
@@ -408,11 +591,12 @@ impl<'a> ExprConverter<'a> {
             .collect::<Result<Vec<_>>>()?;
         Ok(parse_quote! { (#(#elt_exprs),*) })
     }
-}
 
-/// Check if an expression is a len() call
-fn is_len_call(expr: &HirExpr) -> bool {
-    matches!(expr, HirExpr::Call { func, args } if func == "len" && args.len() == 1)
+    fn convert_attribute(&self, value: &HirExpr, attr: &str) -> Result<syn::Expr> {
+        let value_expr = self.convert(value)?;
+        let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+        Ok(parse_quote! { #value_expr.#attr_ident })
+    }
 }
 
 fn convert_literal(lit: &Literal) -> syn::Expr {
@@ -463,9 +647,9 @@ fn convert_binop(op: BinOp) -> Result<syn::BinOp> {
         }
 
         // Special membership operators
-        BinOp::In | BinOp::NotIn => {
-            bail!("in/not in operators should be handled by convert_binary")
-        }
+        BinOp::In | BinOp::NotIn => transpile_bail!(ErrorKind::InternalError(
+            "in/not in operators should be handled by convert_binary".to_string(),
+        )),
     }
 }
 
@@ -478,7 +662,9 @@ fn convert_arithmetic_op(op: BinOp) -> Result<syn::BinOp> {
         Div => Ok(parse_quote! { / }),
         Mod => Ok(parse_quote! { % }),
         FloorDiv => Ok(parse_quote! { / }), // TODO: Handle floor division properly
-        Pow => bail!("Power operator not directly supported in Rust"),
+        Pow => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Power operator not directly supported in Rust".to_string(),
+        )),
         _ => unreachable!("Non-arithmetic operator passed to convert_arithmetic_op"),
     }
 }
@@ -574,11 +760,15 @@ mod tests {
         let call_expr = HirExpr::Call {
             func: "len".to_string(),
             args: vec![HirExpr::Var("arr".to_string())],
+            kwargs: vec![],
         };
 
         let result = converter.convert(&call_expr).unwrap();
-        // Should generate a method call expression
-        assert!(matches!(result, syn::Expr::MethodCall(_)));
+        // The length is an int
+        let syn::Expr::Paren(paren) = result else {
+            panic!("expected a parenthesized cast");
+        };
+        assert!(matches!(*paren.expr, syn::Expr::Cast(_)));
     }
 
     #[test]
@@ -589,6 +779,7 @@ mod tests {
         let call_expr = HirExpr::Call {
             func: "range".to_string(),
             args: vec![HirExpr::Literal(Literal::Int(10))],
+            kwargs: vec![],
         };
 
         let result = converter.convert(&call_expr).unwrap();
@@ -607,6 +798,7 @@ mod tests {
                 HirExpr::Literal(Literal::Int(1)),
                 HirExpr::Literal(Literal::Int(10)),
             ],
+            kwargs: vec![],
         };
 
         let result = converter.convert(&call_expr).unwrap();
@@ -735,16 +927,22 @@ mod tests {
         let func = HirFunction {
             name: "test_func".to_string(),
             params: vec![("x".to_string(), Type::Int)].into(),
+            param_spec: Default::default(),
             ret_type: Type::Int,
-            body: vec![HirStmt::Return(Some(HirExpr::Var("x".to_string())))],
+            body: vec![HirStmt::Return(Some(HirExpr::Var("x".to_string()))).into()],
             properties: FunctionProperties {
                 is_pure: true,
                 always_terminates: true,
                 panic_free: true,
                 max_stack_depth: Some(1),
+                can_fail: false,
+                error_types: vec![],
+                is_generator: false,
             },
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Span::default(),
         };
 
         let result = convert_function(&func, &type_mapper).unwrap();
@@ -762,17 +960,23 @@ mod tests {
             functions: vec![HirFunction {
                 name: "add".to_string(),
                 params: vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)].into(),
+                param_spec: Default::default(),
                 ret_type: Type::Int,
                 body: vec![HirStmt::Return(Some(HirExpr::Binary {
                     op: BinOp::Add,
                     left: Box::new(HirExpr::Var("a".to_string())),
                     right: Box::new(HirExpr::Var("b".to_string())),
-                }))],
+                }))
+                .into()],
                 properties: FunctionProperties::default(),
                 annotations: TranspilationAnnotations::default(),
                 docstring: None,
+                is_async: false,
+                span: Span::default(),
             }],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let result = apply_rules(&module, &type_mapper).unwrap();
@@ -1,3 +1,4 @@
+use crate::hir::Span;
 use std::fmt;
 use thiserror::Error;
 
@@ -9,6 +10,20 @@ pub struct SourceLocation {
     pub column: usize,
 }
 
+impl SourceLocation {
+    /// The 1-based line and column of byte `offset` in `source`
+    pub fn from_offset(file: &str, source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Self {
+            file: file.to_string(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
 impl fmt::Display for SourceLocation {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}:{}:{}", self.file, self.line, self.column)
@@ -21,22 +36,22 @@ pub enum ErrorKind {
     #[error("Python parse error")]
     ParseError,
 
-    #[error("Unsupported Python feature")]
+    #[error("Unsupported Python feature: {0}")]
     UnsupportedFeature(String),
 
-    #[error("Type inference error")]
+    #[error("Type inference error: {0}")]
     TypeInferenceError(String),
 
-    #[error("Invalid type annotation")]
+    #[error("Invalid type annotation: {0}")]
     InvalidTypeAnnotation(String),
 
-    #[error("Code generation error")]
+    #[error("Code generation error: {0}")]
     CodeGenerationError(String),
 
-    #[error("Verification failed")]
+    #[error("Verification failed: {0}")]
     VerificationError(String),
 
-    #[error("Internal error")]
+    #[error("Internal error: {0}")]
     InternalError(String),
 }
 
@@ -44,6 +59,9 @@ pub enum ErrorKind {
 #[derive(Debug, Error)]
 pub struct TranspileError {
     pub kind: ErrorKind,
+    /// The source the error is about, until `locate` resolves it to
+    /// `location`; empty when unknown
+    pub span: Span,
     pub location: Option<SourceLocation>,
     pub context: Vec<String>,
     pub source: Option<Box<dyn std::error::Error + Send + Sync>>,
@@ -54,6 +72,7 @@ impl TranspileError {
     pub fn new(kind: ErrorKind) -> Self {
         Self {
             kind,
+            span: Span::default(),
             location: None,
             context: Vec::new(),
             source: None,
@@ -66,6 +85,50 @@ impl TranspileError {
         self
     }
 
+    /// Add the span of the source the error is about
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Resolve the span to a location in `source`, read from `file`
+    pub fn locate(mut self, file: &str, source: &str) -> Self {
+        if !self.span.is_empty() {
+            let offset = self.span.range().start;
+            self.location = Some(SourceLocation::from_offset(file, source, offset));
+        }
+        self
+    }
+
+    /// The error as rustc renders a diagnostic: the message, its location,
+    /// and the source line with the span underlined
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let span = self.span;
+        if span.is_empty() {
+            return self.to_string();
+        }
+        let location = SourceLocation::from_offset(file, source, span.range().start);
+        let text = source.lines().nth(location.line - 1).unwrap_or_default();
+        let underlined = source
+            .get(span.range())
+            .and_then(|s| s.lines().next())
+            .map_or(0, |s| s.chars().count());
+        let number = location.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        let mut out = format!("{}\n{gutter}--> {location}\n{gutter} |", self.kind);
+        out.push_str(&format!("\n{number} | {text}"));
+        out.push_str(&format!(
+            "\n{gutter} | {}{}",
+            " ".repeat(location.column - 1),
+            "^".repeat(underlined.max(1))
+        ));
+        for ctx in &self.context {
+            out.push_str(&format!("\n{gutter} = note: {ctx}"));
+        }
+        out
+    }
+
     /// Add context to the error
     pub fn with_context(mut self, ctx: impl Into<String>) -> Self {
         self.context.push(ctx.into());
@@ -118,6 +181,40 @@ where
     }
 }
 
+/// Extension trait pointing the errors of converting a node at its source
+pub trait SpanExt<T> {
+    /// Attach `span` to the error, unless a node inside already did
+    fn at(self, span: Span) -> anyhow::Result<T>;
+}
+
+impl<T> SpanExt<T> for anyhow::Result<T> {
+    fn at(self, span: Span) -> anyhow::Result<T> {
+        self.map_err(|err| {
+            if span.is_empty() {
+                return err;
+            }
+            let err = match err.downcast::<TranspileError>() {
+                Ok(err) => err,
+                Err(err) => TranspileError::from(err),
+            };
+            if err.span.is_empty() {
+                err.with_span(span).into()
+            } else {
+                err.into()
+            }
+        })
+    }
+}
+
+/// Resolve the span of a transpile error inside `err` to a location in
+/// `source`; other errors pass through unchanged
+pub fn locate(err: anyhow::Error, file: &str, source: &str) -> anyhow::Error {
+    match err.downcast::<TranspileError>() {
+        Ok(err) => err.locate(file, source).into(),
+        Err(err) => err,
+    }
+}
+
 /// Convert anyhow errors to TranspileError
 impl From<anyhow::Error> for TranspileError {
     fn from(err: anyhow::Error) -> Self {
@@ -141,15 +238,16 @@ macro_rules! transpile_error {
     }};
 }
 
-/// Helper macro for bailing with a transpile error
+/// Helper macro for bailing with a transpile error, from functions returning
+/// either `TranspileResult` or `anyhow::Result`
 #[macro_export]
 macro_rules! transpile_bail {
     ($kind:expr) => {
-        return Err($crate::transpile_error!($kind))
+        return Err($crate::transpile_error!($kind).into())
     };
 
     ($kind:expr, $($ctx:expr),+) => {
-        return Err($crate::transpile_error!($kind, $($ctx),+))
+        return Err($crate::transpile_error!($kind, $($ctx),+).into())
     };
 }
 
@@ -220,4 +318,54 @@ mod tests {
         );
         assert_eq!(err2.context.len(), 2);
     }
+
+    #[test]
+    fn test_location_from_offset() {
+        let source = "x = 1\ny = é + z\n";
+        let loc = SourceLocation::from_offset("m.py", source, source.find('z').unwrap());
+        assert_eq!((loc.line, loc.column), (2, 9));
+        assert_eq!(SourceLocation::from_offset("m.py", source, 0).line, 1);
+    }
+
+    #[test]
+    fn test_render_underlines_span() {
+        let source = "def f():\n    global y\n";
+        let start = source.find("global").unwrap() as u32;
+        let err = TranspileError::new(ErrorKind::UnsupportedFeature("global".to_string()))
+            .with_span(Span::new(start, start + 8))
+            .with_context("in function 'f'");
+
+        let rendered = err.render("m.py", source);
+        assert_eq!(
+            rendered,
+            "Unsupported Python feature: global\n --> m.py:2:5\n  |\n2 |     global y\n  |     ^^^^^^^^\n  = note: in function 'f'"
+        );
+    }
+
+    #[test]
+    fn test_span_ext_keeps_innermost_span() {
+        let inner: anyhow::Result<()> = Err(TranspileError::new(ErrorKind::CodeGenerationError(
+            "bad".to_string(),
+        ))
+        .with_span(Span::new(4, 6))
+        .into());
+        let err = inner.at(Span::new(0, 10)).unwrap_err();
+        let err = err.downcast_ref::<TranspileError>().unwrap();
+        assert_eq!(err.span, Span::new(4, 6));
+
+        let plain: anyhow::Result<()> = Err(anyhow::anyhow!("boom"));
+        let err = plain.at(Span::new(0, 10)).unwrap_err();
+        let err = err.downcast_ref::<TranspileError>().unwrap();
+        assert_eq!(err.span, Span::new(0, 10));
+        assert!(matches!(err.kind, ErrorKind::InternalError(_)));
+    }
+
+    #[test]
+    fn test_locate_resolves_span() {
+        let err: anyhow::Error = TranspileError::new(ErrorKind::ParseError)
+            .with_span(Span::new(6, 7))
+            .into();
+        let err = locate(err, "m.py", "x = 1\ny +\n");
+        assert!(err.to_string().contains("m.py:2:1"));
+    }
 }
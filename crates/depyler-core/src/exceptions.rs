@@ -0,0 +1,947 @@
+//! Python exception semantics for lowering `raise`/`try` to `Result`.
+//!
+//! Raised exception classes become variants of a generated `PythonError`
+//! enum. Inside Lambda handlers they map onto `LambdaError` using the
+//! exception table in [`crate::lambda_errors`].
+
+use crate::arithmetic::{self, Overflow};
+use crate::generators;
+use crate::hir::*;
+use crate::indexing::{self, IndexPolicy};
+use crate::lambda_errors::LambdaErrorHandler;
+use depyler_annotations::{ErrorStrategy, PanicBehavior, TranspilationAnnotations};
+use quote::quote;
+use std::collections::{HashMap, HashSet};
+use syn::parse_quote;
+
+/// Name of the generated error enum
+pub const ERROR_TYPE_NAME: &str = "PythonError";
+
+/// Builtin exception classes with their direct base class
+const BUILTIN_EXCEPTIONS: &[(&str, &str)] = &[
+    ("Exception", "BaseException"),
+    ("ArithmeticError", "Exception"),
+    ("ZeroDivisionError", "ArithmeticError"),
+    ("OverflowError", "ArithmeticError"),
+    ("LookupError", "Exception"),
+    ("KeyError", "LookupError"),
+    ("IndexError", "LookupError"),
+    ("ValueError", "Exception"),
+    ("UnicodeError", "ValueError"),
+    ("TypeError", "Exception"),
+    ("AttributeError", "Exception"),
+    ("NameError", "Exception"),
+    ("AssertionError", "Exception"),
+    ("StopIteration", "Exception"),
+    ("RuntimeError", "Exception"),
+    ("NotImplementedError", "RuntimeError"),
+    ("RecursionError", "RuntimeError"),
+    ("OSError", "Exception"),
+    ("FileNotFoundError", "OSError"),
+    ("FileExistsError", "OSError"),
+    ("PermissionError", "OSError"),
+    ("ConnectionError", "OSError"),
+    ("TimeoutError", "OSError"),
+];
+
+/// `LambdaError` variants a raised Python exception can be lowered to
+const LAMBDA_VARIANTS: &[&str] = &["Handler", "MissingParameter", "Serialization"];
+
+/// Whether the annotations ask for `Result` returns even without a `raise`
+pub fn annotations_require_result(annotations: &TranspilationAnnotations) -> bool {
+    annotations.error_strategy == ErrorStrategy::ResultType
+        || annotations.panic_behavior == PanicBehavior::ReturnError
+}
+
+/// Whether errors abort instead of propagating, so the function keeps its plain return type
+pub fn panics_on_error(annotations: &TranspilationAnnotations) -> bool {
+    annotations.panic_behavior == PanicBehavior::Abort
+}
+
+/// Exception class hierarchy used to decide which handler catches what
+#[derive(Debug, Clone)]
+pub struct ExceptionTable {
+    bases: HashMap<String, String>,
+    /// Indexing a sequence raises `IndexError`, see [`IndexPolicy::Raise`]
+    index_errors: bool,
+    /// Int arithmetic raises `OverflowError`, see [`Overflow::Raise`]
+    overflow_errors: bool,
+    /// Types of the locals, telling which indexes are of sequences
+    local_types: HashMap<Symbol, Type>,
+}
+
+impl Default for ExceptionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExceptionTable {
+    pub fn new() -> Self {
+        let bases = BUILTIN_EXCEPTIONS
+            .iter()
+            .map(|(name, base)| (name.to_string(), base.to_string()))
+            .collect();
+        Self {
+            bases,
+            index_errors: false,
+            overflow_errors: false,
+            local_types: HashMap::new(),
+        }
+    }
+
+    /// The table for a body whose indexes of sequences raise `IndexError`
+    /// when out of range, the locals having `local_types`
+    pub fn raising_index_errors(mut self, local_types: HashMap<Symbol, Type>) -> Self {
+        self.index_errors = true;
+        self.local_types = local_types;
+        self
+    }
+
+    /// The table for a body whose int arithmetic raises `OverflowError`
+    pub fn raising_overflow_errors(mut self) -> Self {
+        self.overflow_errors = true;
+        self
+    }
+
+    /// Builtins plus the module's own exception classes
+    pub fn for_module(module: &HirModule) -> Self {
+        let mut table = Self::new();
+        // Classes may subclass each other in any order, so iterate to a fixpoint
+        loop {
+            let mut changed = false;
+            for class in &module.classes {
+                if table.bases.contains_key(&class.name) {
+                    continue;
+                }
+                if let Some(base) = class.base_classes.iter().find(|b| table.is_exception(b)) {
+                    table.bases.insert(class.name.clone(), base.clone());
+                    changed = true;
+                }
+            }
+            if !changed {
+                return table;
+            }
+        }
+    }
+
+    pub fn is_exception(&self, name: &str) -> bool {
+        name == "BaseException" || self.bases.contains_key(name)
+    }
+
+    pub fn is_subclass(&self, name: &str, ancestor: &str) -> bool {
+        let mut current = name;
+        loop {
+            if current == ancestor {
+                return true;
+            }
+            match self.bases.get(current) {
+                Some(base) => current = base,
+                None => return false,
+            }
+        }
+    }
+
+    /// Whether the handler catches every exception
+    pub fn is_catch_all(&self, handler: &ExceptHandler) -> bool {
+        handler.exception_types.is_empty()
+            || handler
+                .exception_types
+                .iter()
+                .any(|t| t == "Exception" || t == "BaseException")
+    }
+
+    pub fn catches(&self, handler: &ExceptHandler, raised: &str) -> bool {
+        self.is_catch_all(handler)
+            || handler
+                .exception_types
+                .iter()
+                .any(|t| self.is_subclass(raised, t))
+    }
+
+    /// Exception types that can escape `body`.
+    ///
+    /// `fallible` maps call keys (see [`call_key`]) of functions known to
+    /// fail onto the exceptions they raise.
+    pub fn raised_types(
+        &self,
+        body: &[Spanned<HirStmt>],
+        fallible: &HashMap<String, Vec<String>>,
+        class: Option<&str>,
+    ) -> Vec<String> {
+        let mut raised = Vec::new();
+        self.collect_raised(body, fallible, class, &[], &mut raised);
+        raised
+    }
+
+    fn collect_raised(
+        &self,
+        body: &[Spanned<HirStmt>],
+        fallible: &HashMap<String, Vec<String>>,
+        class: Option<&str>,
+        active: &[Symbol],
+        out: &mut Vec<String>,
+    ) {
+        for stmt in body {
+            // An assignment to an element indexes its list too
+            let mut indexes = matches!(&stmt.node, HirStmt::Assign { target, .. }
+                if target.to_expr().is_some_and(|t| indexing::indexes_sequence(&t, &self.local_types)));
+            let mut overflows = false;
+            for_each_expr(stmt, &mut |expr| {
+                collect_call_errors(expr, fallible, class, out);
+                indexes |= indexing::indexes_sequence(expr, &self.local_types);
+                overflows |= arithmetic::may_overflow(expr);
+            });
+            if indexes && self.index_errors {
+                push_unique(out, "IndexError".to_string());
+            }
+            if overflows && self.overflow_errors {
+                push_unique(out, "OverflowError".to_string());
+            }
+            match &stmt.node {
+                HirStmt::Raise { exception } => {
+                    let names = match exception {
+                        Some(expr) => match self.raised_class(expr) {
+                            Some(name) => vec![name],
+                            // Re-raising a bound exception value
+                            None => active_or_exception(active),
+                        },
+                        None => active_or_exception(active),
+                    };
+                    for name in names {
+                        push_unique(out, name);
+                    }
+                }
+                HirStmt::Try {
+                    body,
+                    handlers,
+                    orelse,
+                    finalbody,
+                } => {
+                    let mut from_body = Vec::new();
+                    self.collect_raised(body, fallible, class, active, &mut from_body);
+                    for raised in from_body {
+                        if !handlers.iter().any(|h| self.catches(h, &raised)) {
+                            push_unique(out, raised);
+                        }
+                    }
+                    for handler in handlers {
+                        let caught = if self.is_catch_all(handler) {
+                            vec![]
+                        } else {
+                            handler.exception_types.clone()
+                        };
+                        self.collect_raised(&handler.body, fallible, class, &caught, out);
+                    }
+                    self.collect_raised(orelse, fallible, class, active, out);
+                    self.collect_raised(finalbody, fallible, class, active, out);
+                }
+                HirStmt::If {
+                    then_body,
+                    else_body,
+                    ..
+                } => {
+                    self.collect_raised(then_body, fallible, class, active, out);
+                    if let Some(else_body) = else_body {
+                        self.collect_raised(else_body, fallible, class, active, out);
+                    }
+                }
+                HirStmt::While { body, .. }
+                | HirStmt::For { body, .. }
+                | HirStmt::With { body, .. } => {
+                    self.collect_raised(body, fallible, class, active, out);
+                }
+                HirStmt::Match { cases, .. } => {
+                    for case in cases {
+                        self.collect_raised(&case.body, fallible, class, active, out);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Builtin exceptions that operations of a `try` body raise, rather than
+    /// `raise` statements, which one of its `handlers` catches. A `try`
+    /// catching them lowers the operations to raise errors instead of panicking.
+    pub fn implicit_raises(
+        &self,
+        body: &[Spanned<HirStmt>],
+        handlers: &[ExceptHandler],
+        types: &HashMap<Symbol, Type>,
+    ) -> Vec<String> {
+        let mut raised = Vec::new();
+        for_each_expr_in(body, &mut |expr| {
+            if let Some(name) = implicitly_raised(expr, types) {
+                if handlers.iter().any(|h| self.catches(h, name)) {
+                    push_unique(&mut raised, name.to_string());
+                }
+            }
+        });
+        raised
+    }
+
+    /// The exception class named by a `raise` operand, if it names one
+    pub fn raised_class(&self, expr: &HirExpr) -> Option<String> {
+        match expr {
+            HirExpr::Call { func, .. } | HirExpr::Var(func) if self.is_exception(func) => {
+                Some(func.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+fn active_or_exception(active: &[Symbol]) -> Vec<String> {
+    if active.is_empty() {
+        vec!["Exception".to_string()]
+    } else {
+        active.to_vec()
+    }
+}
+
+fn push_unique(out: &mut Vec<String>, name: String) {
+    if !out.contains(&name) {
+        out.push(name);
+    }
+}
+
+fn collect_call_errors(
+    expr: &HirExpr,
+    fallible: &HashMap<String, Vec<String>>,
+    class: Option<&str>,
+    out: &mut Vec<String>,
+) {
+    if let Some(errors) = call_key(expr, class).and_then(|key| fallible.get(&key)) {
+        for error in errors {
+            push_unique(out, error.clone());
+        }
+    }
+}
+
+/// The builtin exception Python raises from `expr` itself: `int()` and
+/// `float()` of a string, looking up a dict key, indexing a sequence, and
+/// dividing by a divisor that may be zero
+pub fn implicitly_raised(expr: &HirExpr, types: &HashMap<Symbol, Type>) -> Option<&'static str> {
+    match expr {
+        HirExpr::Call { func, args, .. }
+            if matches!(func.as_str(), "int" | "float")
+                && matches!(args.as_slice(), [arg] if generators::infer_type(arg, types) == Type::String) =>
+        {
+            Some("ValueError")
+        }
+        HirExpr::Index { base, index } => match generators::infer_type(base, types) {
+            Type::Dict(_, _) => Some("KeyError"),
+            Type::Tuple(_) => None,
+            Type::Unknown if matches!(index.as_ref(), HirExpr::Literal(Literal::String(_))) => {
+                Some("KeyError")
+            }
+            _ => Some("IndexError"),
+        },
+        HirExpr::Binary {
+            op: BinOp::Div | BinOp::FloorDiv | BinOp::Mod,
+            right,
+            ..
+        } if !arithmetic::is_nonzero_literal(right) => Some("ZeroDivisionError"),
+        _ => None,
+    }
+}
+
+/// Key identifying the callee of a call expression.
+///
+/// Functions use their name, methods `Class.method`, and constructor calls
+/// `Class(...)` the class's `__init__`. Classes are recognised by their
+/// capitalised names.
+pub fn call_key(expr: &HirExpr, class: Option<&str>) -> Option<String> {
+    match expr {
+        HirExpr::Call { func, .. } if func == "cls" => class.map(|c| format!("{c}.__init__")),
+        HirExpr::Call { func, .. } if func.starts_with(char::is_uppercase) => {
+            Some(format!("{func}.__init__"))
+        }
+        HirExpr::Call { func, .. } => Some(func.clone()),
+        HirExpr::MethodCall {
+            receiver, method, ..
+        } => match receiver.as_ref() {
+            HirExpr::Var(v) if v == "self" || v == "cls" => class.map(|c| format!("{c}.{method}")),
+            HirExpr::Var(v) if v.starts_with(char::is_uppercase) => Some(format!("{v}.{method}")),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Visit every expression in a statement, without descending into nested bodies
+fn for_each_expr(stmt: &HirStmt, f: &mut impl FnMut(&HirExpr)) {
+    let roots: Vec<&HirExpr> = match stmt {
+        HirStmt::Assign { target, value } => {
+            let mut roots = target.operands();
+            roots.push(value);
+            roots
+        }
+        HirStmt::Return(Some(expr))
+        | HirStmt::Expr(expr)
+        | HirStmt::Yield(Some(expr))
+        | HirStmt::YieldFrom(expr) => vec![expr],
+        HirStmt::If { condition, .. } | HirStmt::While { condition, .. } => vec![condition],
+        HirStmt::For { target, iter, .. } => {
+            let mut roots = target.operands();
+            roots.push(iter);
+            roots
+        }
+        HirStmt::Raise {
+            exception: Some(expr),
+        } => vec![expr],
+        HirStmt::With { items, .. } => items.iter().map(|item| &item.context_expr).collect(),
+        HirStmt::Match { subject, cases } => std::iter::once(subject)
+            .chain(cases.iter().filter_map(|case| case.guard.as_ref()))
+            .collect(),
+        _ => vec![],
+    };
+    for root in roots {
+        visit_expr(root, f);
+    }
+}
+
+fn visit_expr(expr: &HirExpr, f: &mut impl FnMut(&HirExpr)) {
+    f(expr);
+    match expr {
+        HirExpr::Binary { left, right, .. } => {
+            visit_expr(left, f);
+            visit_expr(right, f);
+        }
+        HirExpr::Unary { operand, .. } => visit_expr(operand, f),
+        HirExpr::List(args)
+        | HirExpr::Set(args)
+        | HirExpr::Tuple(args)
+        | HirExpr::FString { values: args, .. } => {
+            args.iter().for_each(|a| visit_expr(a, f));
+        }
+        HirExpr::Call { args, kwargs, .. } => {
+            args.iter().for_each(|a| visit_expr(a, f));
+            kwargs.iter().for_each(|(_, v)| visit_expr(v, f));
+        }
+        HirExpr::MethodCall {
+            receiver,
+            args,
+            kwargs,
+            ..
+        } => {
+            visit_expr(receiver, f);
+            args.iter().for_each(|a| visit_expr(a, f));
+            kwargs.iter().for_each(|(_, v)| visit_expr(v, f));
+        }
+        HirExpr::Index { base, index } => {
+            visit_expr(base, f);
+            visit_expr(index, f);
+        }
+        HirExpr::Slice {
+            base,
+            start,
+            stop,
+            step,
+        } => {
+            visit_expr(base, f);
+            for bound in [start, stop, step].into_iter().flatten() {
+                visit_expr(bound, f);
+            }
+        }
+        HirExpr::Attribute { value, .. } => visit_expr(value, f),
+        HirExpr::Dict(items) => items.iter().for_each(|(k, v)| {
+            visit_expr(k, f);
+            visit_expr(v, f);
+        }),
+        HirExpr::Borrow { expr, .. } | HirExpr::Await { value: expr } => visit_expr(expr, f),
+        HirExpr::Comprehension {
+            element,
+            value,
+            generators,
+            ..
+        } => {
+            visit_expr(element, f);
+            if let Some(value) = value {
+                visit_expr(value, f);
+            }
+            for generator in generators {
+                visit_expr(&generator.iter, f);
+                generator.conditions.iter().for_each(|c| visit_expr(c, f));
+            }
+        }
+        // A lambda's body runs when it is called, in its own error scope
+        HirExpr::Literal(_) | HirExpr::Var(_) | HirExpr::Lambda { .. } => {}
+    }
+}
+
+/// Whether `name` is read anywhere in `body`, or a bare `raise` re-raises it
+pub fn body_uses_exception(body: &[Spanned<HirStmt>], name: &str) -> bool {
+    body.iter().any(|stmt| {
+        let mut used = false;
+        for_each_expr(stmt, &mut |expr| {
+            used |= match expr {
+                HirExpr::Var(v) => v == name,
+                // Lambda bodies are not visited, but read the names they capture
+                HirExpr::Lambda { params, body } => {
+                    !params.iter().any(|p| p == name)
+                        && body_uses_exception(&[HirStmt::Expr(body.as_ref().clone()).into()], name)
+                }
+                _ => false,
+            };
+        });
+        used || match &stmt.node {
+            HirStmt::Raise { exception: None } => true,
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                body_uses_exception(then_body, name)
+                    || else_body
+                        .as_deref()
+                        .is_some_and(|b| body_uses_exception(b, name))
+            }
+            HirStmt::While { body, .. }
+            | HirStmt::For { body, .. }
+            | HirStmt::With { body, .. } => body_uses_exception(body, name),
+            HirStmt::Match { cases, .. } => cases
+                .iter()
+                .any(|case| body_uses_exception(&case.body, name)),
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                body_uses_exception(body, name)
+                    || handlers.iter().any(|h| body_uses_exception(&h.body, name))
+                    || body_uses_exception(orelse, name)
+                    || body_uses_exception(finalbody, name)
+            }
+            HirStmt::FunctionDef { func, .. } => {
+                !func.params.iter().any(|(p, _)| p == name) && body_uses_exception(&func.body, name)
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Propagate fallibility through calls until every caller of a failing
+/// function is itself marked as failing
+pub fn propagate_fallibility(module: &mut HirModule) {
+    let table = ExceptionTable::for_module(module);
+
+    // Functions that panic on error never hand a failure to their callers, and
+    // calling a generator only builds it
+    let mut fallible: HashMap<String, Vec<String>> = HashMap::new();
+    for func in &module.functions {
+        if func.properties.can_fail
+            && !func.properties.is_generator
+            && !panics_on_error(&func.annotations)
+        {
+            fallible.insert(func.name.clone(), func.properties.error_types.clone());
+        }
+    }
+    for class in &module.classes {
+        for method in &class.methods {
+            if method.properties.can_fail && !panics_on_error(&method.annotations) {
+                let key = format!("{}.{}", class.name, method.name);
+                fallible.insert(key, method.properties.error_types.clone());
+            }
+        }
+    }
+
+    loop {
+        let mut changed = false;
+        let update = |key: String, body: &[Spanned<HirStmt>], class: Option<&str>| {
+            let raised = table.raised_types(body, &fallible, class);
+            let known = fallible.get(&key);
+            if raised.is_empty() && known.is_none() {
+                return None;
+            }
+            let mut merged = known.cloned().unwrap_or_default();
+            for name in raised {
+                push_unique(&mut merged, name);
+            }
+            (known != Some(&merged)).then_some((key, merged))
+        };
+        let mut updates = Vec::new();
+        for func in module
+            .functions
+            .iter()
+            .filter(|f| !f.properties.is_generator && !panics_on_error(&f.annotations))
+        {
+            updates.extend(update(func.name.clone(), &func.body, None));
+        }
+        for class in &module.classes {
+            for method in class
+                .methods
+                .iter()
+                .filter(|m| !panics_on_error(&m.annotations))
+            {
+                let key = format!("{}.{}", class.name, method.name);
+                updates.extend(update(key, &method.body, Some(&class.name)));
+            }
+        }
+        for (key, errors) in updates {
+            fallible.insert(key, errors);
+            changed = true;
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for func in &mut module.functions {
+        if let Some(errors) = fallible.get(&func.name) {
+            func.properties.can_fail = true;
+            func.properties.error_types = errors.clone();
+        }
+    }
+    for class in &mut module.classes {
+        for method in &mut class.methods {
+            if let Some(errors) = fallible.get(&format!("{}.{}", class.name, method.name)) {
+                method.properties.can_fail = true;
+                method.properties.error_types = errors.clone();
+            }
+        }
+    }
+}
+
+/// Every exception class raised anywhere in the module, in source order
+pub fn raised_exception_names(module: &HirModule, table: &ExceptionTable) -> Vec<String> {
+    fn walk(
+        body: &[Spanned<HirStmt>],
+        table: &ExceptionTable,
+        types: &HashMap<Symbol, Type>,
+        out: &mut Vec<String>,
+    ) {
+        for stmt in body {
+            match &stmt.node {
+                HirStmt::Raise {
+                    exception: Some(expr),
+                } => {
+                    if let Some(name) = table.raised_class(expr) {
+                        push_unique(out, name);
+                    }
+                }
+                HirStmt::If {
+                    then_body,
+                    else_body,
+                    ..
+                } => {
+                    walk(then_body, table, types, out);
+                    if let Some(else_body) = else_body {
+                        walk(else_body, table, types, out);
+                    }
+                }
+                HirStmt::While { body, .. }
+                | HirStmt::For { body, .. }
+                | HirStmt::With { body, .. } => walk(body, table, types, out),
+                HirStmt::Match { cases, .. } => {
+                    for case in cases {
+                        walk(&case.body, table, types, out);
+                    }
+                }
+                HirStmt::Try {
+                    body,
+                    handlers,
+                    orelse,
+                    finalbody,
+                } => {
+                    for name in table.implicit_raises(body, handlers, types) {
+                        push_unique(out, name);
+                    }
+                    walk(body, table, types, out);
+                    for handler in handlers {
+                        walk(&handler.body, table, types, out);
+                    }
+                    walk(orelse, table, types, out);
+                    walk(finalbody, table, types, out);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Indexes out of range raise `IndexError` under `verify_bounds`, and
+    // overflowing ints `OverflowError` under `integer_overflow = "return_error"`
+    let annotated = |annotations: &TranspilationAnnotations, names: &mut Vec<String>| {
+        if IndexPolicy::new(annotations) == IndexPolicy::Raise {
+            push_unique(names, "IndexError".to_string());
+        }
+        if Overflow::new(annotations) == Overflow::Raise {
+            push_unique(names, "OverflowError".to_string());
+        }
+    };
+    let mut names = Vec::new();
+    for func in &module.functions {
+        annotated(&func.annotations, &mut names);
+        let types = indexing::local_types(&func.params, &func.body);
+        walk(&func.body, table, &types, &mut names);
+    }
+    for class in &module.classes {
+        for method in &class.methods {
+            annotated(&method.annotations, &mut names);
+            let types = indexing::local_types(&method.params, &method.body);
+            walk(&method.body, table, &types, &mut names);
+        }
+    }
+    names
+}
+
+/// Generate the `PythonError` enum with one message-carrying variant per exception
+pub fn error_enum_tokens(variants: &[String]) -> proc_macro2::TokenStream {
+    let idents: Vec<syn::Ident> = variants
+        .iter()
+        .map(|v| syn::Ident::new(v, proc_macro2::Span::call_site()))
+        .collect();
+    let error_ident = syn::Ident::new(ERROR_TYPE_NAME, proc_macro2::Span::call_site());
+    quote! {
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum #error_ident {
+            #(#idents(String)),*
+        }
+
+        impl std::fmt::Display for #error_ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    #(#error_ident::#idents(msg) => write!(f, "{}: {}", stringify!(#idents), msg)),*
+                }
+            }
+        }
+
+        impl std::error::Error for #error_ident {}
+    }
+}
+
+/// The `LambdaError` variant a Python exception maps to inside a Lambda handler
+pub fn lambda_error_variant(handler: &LambdaErrorHandler, exception: &str) -> &'static str {
+    handler
+        .mapping_for_exception(exception)
+        .and_then(|mapping| mapping.rust_error_type.strip_prefix("LambdaError::"))
+        .and_then(|variant| LAMBDA_VARIANTS.iter().find(|v| **v == variant).copied())
+        .unwrap_or("Handler")
+}
+
+/// Construct the `LambdaError` for a raised `exception` carrying `message`
+pub fn lambda_error_expr(variant: &str, exception: &str, message: &syn::Expr) -> syn::Expr {
+    match variant {
+        "MissingParameter" => {
+            parse_quote! { LambdaError::MissingParameter { parameter: #message } }
+        }
+        "Serialization" => {
+            parse_quote! { LambdaError::Serialization { message: #message, cause: None } }
+        }
+        _ => parse_quote! {
+            LambdaError::Handler { message: #message, context: Some(#exception.to_string()) }
+        },
+    }
+}
+
+/// Variant names reachable from `handler`, among the variants the module defines
+pub fn caught_variants<'v>(
+    table: &ExceptionTable,
+    handler: &ExceptHandler,
+    variants: &'v [String],
+) -> Vec<&'v String> {
+    let mut seen = HashSet::new();
+    variants
+        .iter()
+        .filter(|v| table.catches(handler, v))
+        .filter(|v| seen.insert(v.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raise(name: &str) -> HirStmt {
+        HirStmt::Raise {
+            exception: Some(HirExpr::Call {
+                func: name.to_string(),
+                args: vec![HirExpr::Literal(Literal::String("boom".to_string()))],
+                kwargs: vec![],
+            }),
+        }
+    }
+
+    fn handler(types: &[&str]) -> ExceptHandler {
+        ExceptHandler {
+            exception_types: types.iter().map(|t| t.to_string()).collect(),
+            name: None,
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn test_builtin_hierarchy() {
+        let table = ExceptionTable::new();
+        assert!(table.is_subclass("KeyError", "LookupError"));
+        assert!(table.is_subclass("ZeroDivisionError", "Exception"));
+        assert!(!table.is_subclass("ValueError", "LookupError"));
+        assert!(table.catches(&handler(&[]), "ValueError"));
+        assert!(table.catches(&handler(&["LookupError"]), "IndexError"));
+        assert!(!table.catches(&handler(&["TypeError"]), "IndexError"));
+    }
+
+    #[test]
+    fn test_raised_types_respects_handlers() {
+        let table = ExceptionTable::new();
+        let body = vec![HirStmt::Try {
+            body: vec![raise("KeyError").into(), raise("ValueError").into()],
+            handlers: vec![handler(&["LookupError"])],
+            orelse: vec![],
+            finalbody: vec![],
+        }
+        .into()];
+        let raised = table.raised_types(&body, &HashMap::new(), None);
+        assert_eq!(raised, vec!["ValueError".to_string()]);
+    }
+
+    #[test]
+    fn test_bare_raise_reraises_caught_type() {
+        let table = ExceptionTable::new();
+        let mut reraise = handler(&["KeyError"]);
+        reraise.body = vec![HirStmt::Raise { exception: None }.into()];
+        let body = vec![HirStmt::Try {
+            body: vec![raise("KeyError").into()],
+            handlers: vec![reraise],
+            orelse: vec![],
+            finalbody: vec![],
+        }
+        .into()];
+        let raised = table.raised_types(&body, &HashMap::new(), None);
+        assert_eq!(raised, vec!["KeyError".to_string()]);
+    }
+
+    #[test]
+    fn test_user_exception_classes() {
+        let module = HirModule {
+            functions: vec![],
+            classes: vec![HirClass {
+                name: "ConfigError".to_string(),
+                base_classes: vec!["ValueError".to_string()],
+                fields: vec![],
+                methods: vec![],
+                is_dataclass: false,
+                annotations: TranspilationAnnotations::default(),
+                docstring: None,
+            }],
+            imports: vec![],
+            skipped: vec![],
+        };
+        let table = ExceptionTable::for_module(&module);
+        assert!(table.is_exception("ConfigError"));
+        assert!(table.catches(&handler(&["ValueError"]), "ConfigError"));
+    }
+
+    #[test]
+    fn test_lambda_error_variant() {
+        let handler = LambdaErrorHandler::new();
+        assert_eq!(
+            lambda_error_variant(&handler, "KeyError"),
+            "MissingParameter"
+        );
+        assert_eq!(lambda_error_variant(&handler, "ValueError"), "Handler");
+        assert_eq!(lambda_error_variant(&handler, "TypeError"), "Serialization");
+        assert_eq!(
+            lambda_error_variant(&handler, "ZeroDivisionError"),
+            "Handler"
+        );
+    }
+    #[test]
+    fn test_handlers_catch_errors_of_builtins() {
+        let python = r#"
+from typing import Dict, List
+
+def parse(s: str) -> int:
+    try:
+        return int(s)
+    except ValueError:
+        return -1
+
+def look(d: Dict[str, int], k: str) -> int:
+    try:
+        return d[k]
+    except KeyError:
+        return 0
+
+def at(xs: List[int], i: int) -> int:
+    try:
+        return xs[i]
+    except IndexError:
+        return -2
+
+def ratio(a: int, b: int) -> int:
+    try:
+        return a // b
+    except ZeroDivisionError:
+        return -3
+"#;
+        let printed = crate::test_programs::run(
+            python,
+            r#"
+            let d: HashMap<String, i32> = [("a".to_string(), 1)].into_iter().collect();
+            println!("{} {}", parse(" 42 "), parse("x"));
+            println!("{} {}", look(&d, "a".to_string()), look(&d, "b".to_string()));
+            println!("{} {}", at(&[5, 6], -1), at(&[5, 6], 2));
+            println!("{} {}", ratio(7, -2), ratio(7, 0));
+            "#,
+        );
+        assert_eq!(printed, "42 -1\n1 0\n6 -2\n-4 -3\n");
+    }
+
+    #[test]
+    fn test_try_without_errors_runs_its_body() {
+        let python = r#"
+from typing import List
+
+def size(xs: List[int]) -> int:
+    n = 0
+    try:
+        n = sum(xs)
+    except KeyError:
+        n = -1
+    finally:
+        n += 1
+    return n
+"#;
+        let printed = crate::test_programs::run(python, r#"println!("{}", size(&[1, 2]));"#);
+        assert_eq!(printed, "4\n");
+    }
+
+    #[test]
+    fn test_try_returning_on_every_path_runs() {
+        let python = r#"
+def parse(x: int) -> int:
+    if x < 0:
+        raise ValueError("negative")
+    return x
+
+def parse_or(x: int, d: int) -> int:
+    try:
+        return parse(x)
+    except ValueError:
+        return d
+    finally:
+        print("done")
+
+def parse_else(x: int, d: int) -> int:
+    try:
+        y = parse(x)
+    except ValueError:
+        return d
+    else:
+        return y
+"#;
+        let printed = crate::test_programs::run(
+            python,
+            r#"
+            println!("{}", parse_or(3, 0));
+            println!("{}", parse_or(-3, 0));
+            println!("{} {}", parse_else(4, 0), parse_else(-4, 9));
+            "#,
+        );
+        // `finally` runs on both paths, before the value is returned
+        assert_eq!(printed, "done\n3\ndone\n0\n4 9\n");
+    }
+}
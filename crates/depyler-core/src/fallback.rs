@@ -0,0 +1,220 @@
+//! Best-effort transpilation: stubbing out the functions that fail to
+//! convert instead of failing the whole module
+//!
+//! A function stubbed out keeps its signature, as far as its types are
+//! known, and codegen gives it a body of a single marker macro call naming
+//! the stub. Once the file is formatted, `expand_stubs` replaces each
+//! marker with the function's Python, commented out, and a
+//! `todo!("unsupported: <construct> at line N")`.
+
+use crate::error::{ErrorKind, SourceLocation, TranspileError};
+use crate::hir::{Span, Symbol};
+use depyler_annotations::{FallbackStrategy, TranspilationAnnotations};
+use quote::quote;
+use serde::{Deserialize, Serialize};
+
+/// The macro standing in for the body of a stub until it is expanded
+const MARKER: &str = "__depyler_todo";
+
+/// A function left as a `todo!()` stub, and the construct it failed on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkippedConstruct {
+    pub function: Symbol,
+    /// What could not be transpiled, as the error about it describes it
+    pub construct: String,
+    /// Where the construct is; the whole function when the error did not
+    /// point inside it
+    pub span: Span,
+    pub function_span: Span,
+    /// Line and column of `span`, counted from 1; 0 until located in the
+    /// Python source
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SkippedConstruct {
+    /// The construct `err`, raised transpiling `function`, is about
+    pub fn from_error(function: &str, function_span: Span, err: &anyhow::Error) -> Self {
+        let (construct, span) = match err.downcast_ref::<TranspileError>() {
+            Some(err) => {
+                let construct = match &err.kind {
+                    ErrorKind::UnsupportedFeature(feature) => feature.clone(),
+                    kind => kind.to_string(),
+                };
+                (construct, err.span)
+            }
+            None => (err.to_string(), Span::default()),
+        };
+        Self {
+            function: function.to_string(),
+            construct,
+            span: if span.is_empty() { function_span } else { span },
+            function_span,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    /// Resolve `span` to a line and column of `source`
+    pub fn locate(&mut self, source: &str) {
+        let location = SourceLocation::from_offset("", source, self.span.range().start);
+        self.line = location.line;
+        self.column = location.column;
+    }
+
+    /// The message of the `todo!()` the stub panics with
+    pub fn message(&self) -> String {
+        if self.line == 0 {
+            format!("unsupported: {}", self.construct)
+        } else {
+            format!("unsupported: {} at line {}", self.construct, self.line)
+        }
+    }
+}
+
+/// Whether a function that fails to transpile is stubbed out rather than
+/// failing the module
+pub fn falls_back(best_effort: bool, annotations: &TranspilationAnnotations) -> bool {
+    best_effort || annotations.fallback_strategy == FallbackStrategy::Manual
+}
+
+/// Marker tokens for the body of stub `index` of the skipped constructs
+pub fn marker(index: usize) -> proc_macro2::TokenStream {
+    let name = syn::Ident::new(MARKER, proc_macro2::Span::call_site());
+    let index = proc_macro2::Literal::usize_unsuffixed(index);
+    quote! { #name!(#index) }
+}
+
+/// Replace the stub markers of formatted `code` with the body of each
+/// stub: its Python as comments, when `python_source` is known, and a
+/// `todo!()`. Locates `skipped` in `python_source` along the way.
+///
+/// Returns the code, and the line of each marker, counted from 1, with
+/// the number of lines inserted after it.
+pub fn expand_stubs(
+    code: &str,
+    skipped: &mut [SkippedConstruct],
+    python_source: Option<&str>,
+) -> (String, Vec<(usize, usize)>) {
+    if let Some(source) = python_source {
+        skipped.iter_mut().for_each(|s| s.locate(source));
+    }
+
+    let mut out = Vec::new();
+    let mut inserted = Vec::new();
+    for (i, line) in code.lines().enumerate() {
+        let Some((start, index, rest)) = find_marker(line) else {
+            out.push(line.to_string());
+            continue;
+        };
+        let Some(stub) = skipped.get(index) else {
+            out.push(line.to_string());
+            continue;
+        };
+        let before = out.len();
+        let prefix = &line[..start];
+        let indent: String = prefix.chars().take_while(|c| c.is_whitespace()).collect();
+        let indent = if prefix.trim().is_empty() {
+            indent
+        } else {
+            out.push(prefix.trim_end().to_string());
+            format!("{indent}    ")
+        };
+        if let Some(source) = python_source {
+            for python in function_lines(source, stub.function_span) {
+                let comment = format!("{indent}// {python}");
+                out.push(comment.trim_end().to_string());
+            }
+        }
+        out.push(format!("{indent}todo!({:?}){rest}", stub.message()));
+        inserted.push((i + 1, out.len() - before - 1));
+    }
+
+    let mut expanded = out.join("\n");
+    if code.ends_with('\n') {
+        expanded.push('\n');
+    }
+    (expanded, inserted)
+}
+
+/// Where the marker on `line` starts, the stub it names, and what follows it
+fn find_marker(line: &str) -> Option<(usize, usize, &str)> {
+    let start = line.find(MARKER)?;
+    let len = line[start..].find(')')?;
+    let index = line[start + MARKER.len()..start + len]
+        .split(|c: char| !c.is_ascii_digit())
+        .find_map(|n| n.parse().ok())?;
+    Some((start, index, &line[start + len + 1..]))
+}
+
+/// The lines of `source` spanned by a function, with their indentation
+fn function_lines(source: &str, span: Span) -> impl Iterator<Item = &str> {
+    let range = span.range();
+    let end = range.end.min(source.len());
+    let start = source[..range.start.min(end)]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    source[start..end].lines()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skipped(span: Span, function_span: Span) -> SkippedConstruct {
+        SkippedConstruct {
+            function: "f".to_string(),
+            construct: "`del` statement".to_string(),
+            span,
+            function_span,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    #[test]
+    fn test_expand_stubs() {
+        let source = "def f(x: int) -> int:\n    del x\n    return 1\n";
+        let mut stubs = [skipped(Span::new(26, 31), Span::new(0, 44))];
+        let code = "pub fn f(x: i32) -> i32 {\n    __depyler_todo!(0)\n}\n";
+        let (code, inserted) = expand_stubs(code, &mut stubs, Some(source));
+        assert_eq!(
+            code,
+            "pub fn f(x: i32) -> i32 {\n    \
+             // def f(x: int) -> int:\n    \
+             //     del x\n    \
+             //     return 1\n    \
+             todo!(\"unsupported: `del` statement at line 2\")\n}\n"
+        );
+        assert_eq!(inserted, vec![(2, 3)]);
+        assert_eq!((stubs[0].line, stubs[0].column), (2, 5));
+    }
+
+    #[test]
+    fn test_expand_stubs_without_source() {
+        let mut stubs = [skipped(Span::default(), Span::default())];
+        let code = "pub fn f() { __depyler_todo !(0)\n}";
+        let (code, inserted) = expand_stubs(code, &mut stubs, None);
+        assert_eq!(
+            code,
+            "pub fn f() {\n    todo!(\"unsupported: `del` statement\")\n}"
+        );
+        assert_eq!(inserted, vec![(1, 1)]);
+    }
+
+    #[test]
+    fn test_from_error() {
+        let err: anyhow::Error =
+            TranspileError::new(ErrorKind::UnsupportedFeature("`del` statement".to_string()))
+                .with_span(Span::new(3, 8))
+                .into();
+        let stub = SkippedConstruct::from_error("f", Span::new(0, 20), &err);
+        assert_eq!(stub.construct, "`del` statement");
+        assert_eq!(stub.span, Span::new(3, 8));
+
+        // Errors that point nowhere point at the whole function
+        let err = anyhow::anyhow!("no span");
+        let stub = SkippedConstruct::from_error("f", Span::new(0, 20), &err);
+        assert_eq!(stub.span, Span::new(0, 20));
+    }
+}
@@ -0,0 +1,990 @@
+//! Lowering of generator functions to resumable state machines.
+//!
+//! A generator body is split into states at every `yield`. Parameters and
+//! locals become fields of the generated struct so their values survive
+//! between calls to `next`, and every loop over an iterator keeps that
+//! iterator in a slot of its own.
+
+use crate::error::{ErrorKind, SpanExt};
+use crate::hir::*;
+use crate::indexing;
+use crate::sets;
+use crate::strings;
+use crate::transpile_bail;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Work done on entering a state, before it exits
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenOp {
+    /// A statement that neither yields nor returns
+    Stmt(HirStmt),
+    /// Start iterating over `iter`, keeping the iterator in `slot`
+    StartIter { slot: usize, iter: HirExpr },
+}
+
+/// How control leaves a state
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenExit {
+    Goto(usize),
+    Branch {
+        condition: HirExpr,
+        then: usize,
+        otherwise: usize,
+    },
+    /// Produce an item, resuming at `next` on the following call
+    Yield {
+        value: Option<HirExpr>,
+        next: usize,
+    },
+    /// Bind the next item of `slot` to `target` and enter `body`, or go to
+    /// `done` once the iterator is exhausted
+    Next {
+        slot: usize,
+        target: AssignTarget,
+        body: usize,
+        done: usize,
+    },
+    /// Produce every item of `slot` before going to `done` (`yield from`)
+    Delegate {
+        slot: usize,
+        done: usize,
+    },
+    Finish,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenState {
+    pub ops: Vec<GenOp>,
+    pub exit: GenExit,
+}
+
+/// A generator function split into states; state 0 is the entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateMachine {
+    pub states: Vec<GenState>,
+    /// Item type of the iterator kept in each slot
+    pub iter_slots: Vec<Type>,
+    /// Locals that live across states, in order of first assignment
+    pub locals: Vec<(Symbol, Type)>,
+    pub item_type: Type,
+}
+
+impl StateMachine {
+    /// Split a generator function into states.
+    ///
+    /// Reads and writes of parameters and locals are rewritten to fields of
+    /// `self`, so the states can be generated like method bodies.
+    pub fn build(func: &HirFunction) -> Result<Self> {
+        let mut types: HashMap<Symbol, Type> = func.params.iter().cloned().collect();
+        let mut locals = Vec::new();
+        collect_locals(&func.body, &mut types, &mut locals);
+
+        let item_type = if func.ret_type != Type::Unknown {
+            func.ret_type.clone()
+        } else {
+            first_yield_type(&func.body, &types).unwrap_or(Type::Unknown)
+        };
+
+        let fields: HashSet<Symbol> = types.keys().cloned().collect();
+        let mut builder = Builder {
+            states: vec![GenState {
+                ops: vec![],
+                exit: GenExit::Finish,
+            }],
+            current: 0,
+            iter_slots: vec![],
+            fields,
+            types,
+            item_type: item_type.clone(),
+        };
+        builder.lower_body(&func.body)?;
+        builder.finish(GenExit::Finish);
+
+        Ok(Self {
+            states: builder.states,
+            iter_slots: builder.iter_slots,
+            locals,
+            item_type,
+        })
+    }
+}
+
+struct Builder {
+    states: Vec<GenState>,
+    current: usize,
+    iter_slots: Vec<Type>,
+    fields: HashSet<Symbol>,
+    types: HashMap<Symbol, Type>,
+    item_type: Type,
+}
+
+impl Builder {
+    fn new_state(&mut self) -> usize {
+        self.states.push(GenState {
+            ops: vec![],
+            exit: GenExit::Finish,
+        });
+        self.states.len() - 1
+    }
+
+    fn finish(&mut self, exit: GenExit) {
+        self.states[self.current].exit = exit;
+    }
+
+    /// End the current state with `exit` and continue in `next`
+    fn jump(&mut self, exit: GenExit, next: usize) {
+        self.finish(exit);
+        self.current = next;
+    }
+
+    fn push(&mut self, op: GenOp) {
+        self.states[self.current].ops.push(op);
+    }
+
+    fn rewrite(&self, expr: &HirExpr) -> HirExpr {
+        rewrite_expr(expr, &self.fields)
+    }
+
+    fn lower_body(&mut self, body: &[Spanned<HirStmt>]) -> Result<()> {
+        body.iter()
+            .try_for_each(|stmt| self.lower_stmt(stmt).at(stmt.span))
+    }
+
+    fn lower_stmt(&mut self, stmt: &Spanned<HirStmt>) -> Result<()> {
+        match &stmt.node {
+            HirStmt::FunctionDef { func, .. } => {
+                transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                    "Nested function '{}' is not supported in generator functions",
+                    func.name
+                )))
+            }
+            _ if !suspends(stmt) => {
+                let stmt = rewrite_stmt(stmt, &self.fields);
+                self.push(GenOp::Stmt(stmt));
+            }
+            HirStmt::Try { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "`yield` and `return` inside `try` are not supported in generator functions"
+                    .to_string(),
+            )),
+            HirStmt::With { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "`yield` and `return` inside `with` are not supported in generator functions"
+                    .to_string(),
+            )),
+            HirStmt::Match { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "`yield` and `return` inside `match` are not supported in generator functions"
+                    .to_string(),
+            )),
+            HirStmt::Yield(value) => {
+                let next = self.new_state();
+                let value = value.as_ref().map(|v| self.rewrite(v));
+                self.jump(GenExit::Yield { value, next }, next);
+            }
+            HirStmt::YieldFrom(iter) => {
+                let slot = self.start_iter(iter, self.item_type.clone());
+                let (head, done) = (self.new_state(), self.new_state());
+                self.jump(GenExit::Goto(head), head);
+                self.jump(GenExit::Delegate { slot, done }, done);
+            }
+            HirStmt::Return(_) => {
+                // Anything after the `return` is unreachable
+                let dead = self.new_state();
+                self.jump(GenExit::Finish, dead);
+            }
+            HirStmt::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                let then = self.new_state();
+                let after = self.new_state();
+                let otherwise = match else_body {
+                    Some(_) => self.new_state(),
+                    None => after,
+                };
+                let condition = self.rewrite(condition);
+                self.finish(GenExit::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                });
+                self.current = then;
+                self.lower_body(then_body)?;
+                self.finish(GenExit::Goto(after));
+                if let Some(else_body) = else_body {
+                    self.current = otherwise;
+                    self.lower_body(else_body)?;
+                    self.finish(GenExit::Goto(after));
+                }
+                self.current = after;
+            }
+            HirStmt::While { condition, body } => {
+                let (head, start, after) = (self.new_state(), self.new_state(), self.new_state());
+                self.jump(GenExit::Goto(head), head);
+                let condition = self.rewrite(condition);
+                self.jump(
+                    GenExit::Branch {
+                        condition,
+                        then: start,
+                        otherwise: after,
+                    },
+                    start,
+                );
+                self.lower_body(body)?;
+                self.jump(GenExit::Goto(head), after);
+            }
+            HirStmt::For { target, iter, body } => {
+                if !target.operands().is_empty() {
+                    transpile_bail!(ErrorKind::CodeGenerationError(
+                        "Loops that yield must bind plain names".to_string(),
+                    ));
+                }
+                let element = match target.as_symbol() {
+                    Some(name) if name == "_" => element_type(iter, &self.types),
+                    _ => target_type(target, &self.types),
+                };
+                let slot = self.start_iter(iter, element);
+                let (head, start, after) = (self.new_state(), self.new_state(), self.new_state());
+                self.jump(GenExit::Goto(head), head);
+                self.jump(
+                    GenExit::Next {
+                        slot,
+                        target: target.clone(),
+                        body: start,
+                        done: after,
+                    },
+                    start,
+                );
+                self.lower_body(body)?;
+                self.jump(GenExit::Goto(head), after);
+            }
+            HirStmt::Assign { .. } | HirStmt::Expr(_) | HirStmt::Raise { .. } => {
+                unreachable!("never suspends")
+            }
+        }
+        Ok(())
+    }
+
+    fn start_iter(&mut self, iter: &HirExpr, item: Type) -> usize {
+        let slot = self.iter_slots.len();
+        self.iter_slots.push(item);
+        let iter = self.rewrite(iter);
+        self.push(GenOp::StartIter { slot, iter });
+        slot
+    }
+}
+
+/// Whether a statement yields or returns, so it must be split into states
+fn suspends(stmt: &Spanned<HirStmt>) -> bool {
+    match &stmt.node {
+        HirStmt::Yield(_) | HirStmt::YieldFrom(_) | HirStmt::Return(_) => true,
+        HirStmt::If {
+            then_body,
+            else_body,
+            ..
+        } => {
+            then_body.iter().any(suspends)
+                || else_body.as_ref().is_some_and(|b| b.iter().any(suspends))
+        }
+        HirStmt::While { body, .. } | HirStmt::For { body, .. } | HirStmt::With { body, .. } => {
+            body.iter().any(suspends)
+        }
+        HirStmt::Match { cases, .. } => cases.iter().any(|c| c.body.iter().any(suspends)),
+        HirStmt::Try {
+            body,
+            handlers,
+            orelse,
+            finalbody,
+        } => {
+            body.iter().any(suspends)
+                || handlers.iter().any(|h| h.body.iter().any(suspends))
+                || orelse.iter().any(suspends)
+                || finalbody.iter().any(suspends)
+        }
+        _ => false,
+    }
+}
+
+/// Record the type of every local, from its first assignment or loop
+fn collect_locals(
+    body: &[Spanned<HirStmt>],
+    types: &mut HashMap<Symbol, Type>,
+    locals: &mut Vec<(Symbol, Type)>,
+) {
+    fn declare(
+        name: &Symbol,
+        ty: Type,
+        types: &mut HashMap<Symbol, Type>,
+        locals: &mut Vec<(Symbol, Type)>,
+    ) {
+        // `_` discards the value rather than binding a local
+        if name != "_" && !types.contains_key(name) {
+            types.insert(name.clone(), ty.clone());
+            locals.push((name.clone(), ty));
+        }
+    }
+    for stmt in body {
+        match &stmt.node {
+            HirStmt::Assign { target, value } => {
+                for (target, value) in target.split_value(value) {
+                    let ty = infer_type(value, types);
+                    for (name, ty) in bound_types(target, ty) {
+                        declare(name, ty, types, locals);
+                    }
+                }
+            }
+            HirStmt::For { target, iter, body } => {
+                let ty = element_type(iter, types);
+                for (name, ty) in bound_types(target, ty) {
+                    declare(name, ty, types, locals);
+                }
+                collect_locals(body, types, locals);
+            }
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                collect_locals(then_body, types, locals);
+                if let Some(else_body) = else_body {
+                    collect_locals(else_body, types, locals);
+                }
+            }
+            // The bound resources stay locals of the `with` block
+            HirStmt::While { body, .. } | HirStmt::With { body, .. } => {
+                collect_locals(body, types, locals)
+            }
+            HirStmt::Match { cases, .. } => {
+                for case in cases {
+                    collect_locals(&case.body, types, locals);
+                }
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                collect_locals(body, types, locals);
+                for handler in handlers {
+                    collect_locals(&handler.body, types, locals);
+                }
+                collect_locals(orelse, types, locals);
+                collect_locals(finalbody, types, locals);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn first_yield_type(body: &[Spanned<HirStmt>], types: &HashMap<Symbol, Type>) -> Option<Type> {
+    body.iter().find_map(|stmt| match &stmt.node {
+        HirStmt::Yield(Some(value)) => Some(infer_type(value, types)),
+        HirStmt::Yield(None) => Some(Type::None),
+        HirStmt::YieldFrom(iter) => Some(element_type(iter, types)),
+        HirStmt::If {
+            then_body,
+            else_body,
+            ..
+        } => first_yield_type(then_body, types).or_else(|| {
+            else_body
+                .as_deref()
+                .and_then(|b| first_yield_type(b, types))
+        }),
+        HirStmt::While { body, .. } | HirStmt::For { body, .. } => first_yield_type(body, types),
+        _ => None,
+    })
+}
+
+/// Best-effort type of an expression, from literals and known locals
+pub(crate) fn infer_type(expr: &HirExpr, types: &HashMap<Symbol, Type>) -> Type {
+    match expr {
+        HirExpr::Literal(lit) => match lit {
+            Literal::Int(_) => Type::Int,
+            Literal::Float(_) => Type::Float,
+            Literal::String(_) => Type::String,
+            Literal::Bool(_) => Type::Bool,
+            Literal::None => Type::None,
+        },
+        HirExpr::Var(name) => types.get(name).cloned().unwrap_or(Type::Unknown),
+        HirExpr::Binary { op, left, right } => match op {
+            BinOp::Eq
+            | BinOp::NotEq
+            | BinOp::Lt
+            | BinOp::LtEq
+            | BinOp::Gt
+            | BinOp::GtEq
+            | BinOp::And
+            | BinOp::Or
+            | BinOp::In
+            | BinOp::NotIn => Type::Bool,
+            BinOp::Div => Type::Float,
+            // A negative power of an int is a float
+            BinOp::Pow if indexing::int_literal(right).is_some_and(|n| n < 0) => Type::Float,
+            _ => match (infer_type(left, types), infer_type(right, types)) {
+                (Type::Float, _) | (_, Type::Float) => Type::Float,
+                (Type::Unknown, right) => right,
+                (left, _) => left,
+            },
+        },
+        HirExpr::Unary {
+            op: UnaryOp::Not, ..
+        } => Type::Bool,
+        HirExpr::Unary { operand, .. } => infer_type(operand, types),
+        HirExpr::Call { func, args, .. } => match func.as_str() {
+            "len" | "int" | "ord" => Type::Int,
+            "round" if args.len() == 1 => Type::Int,
+            "float" | "round" => Type::Float,
+            "str" | "chr" => Type::String,
+            "bool" => Type::Bool,
+            // Of a single list, `min`, `max` and `sum` take its items
+            "min" | "max" | "sum" if args.len() == 1 => match infer_type(&args[0], types) {
+                Type::List(item) => *item,
+                _ => Type::Unknown,
+            },
+            "abs" | "min" | "max" => args
+                .first()
+                .map(|a| infer_type(a, types))
+                .unwrap_or(Type::Unknown),
+            "set" | "frozenset" => Type::Set(Box::new(
+                args.first()
+                    .map(|a| element_type(a, types))
+                    .unwrap_or(Type::Unknown),
+            )),
+            _ => Type::Unknown,
+        },
+        HirExpr::Index { base, .. } => match infer_type(base, types) {
+            Type::List(elem) => *elem,
+            Type::Dict(_, value) => *value,
+            Type::String => Type::String,
+            _ => Type::Unknown,
+        },
+        HirExpr::List(elts) => Type::List(Box::new(
+            elts.first()
+                .map(|e| infer_type(e, types))
+                .unwrap_or(Type::Unknown),
+        )),
+        HirExpr::Set(elts) => Type::Set(Box::new(
+            elts.first()
+                .map(|e| infer_type(e, types))
+                .unwrap_or(Type::Unknown),
+        )),
+        HirExpr::Tuple(elts) => Type::Tuple(elts.iter().map(|e| infer_type(e, types)).collect()),
+        HirExpr::Dict(items) => match items.first() {
+            Some((k, v)) => Type::Dict(
+                Box::new(infer_type(k, types)),
+                Box::new(infer_type(v, types)),
+            ),
+            None => Type::Dict(Box::new(Type::Unknown), Box::new(Type::Unknown)),
+        },
+        HirExpr::Slice { base, .. } => infer_type(base, types),
+        HirExpr::FString { .. } => Type::String,
+        HirExpr::MethodCall {
+            receiver, method, ..
+        } if strings::is_method(method) && infer_type(receiver, types) == Type::String => {
+            strings::method_type(method)
+        }
+        HirExpr::MethodCall {
+            receiver, method, ..
+        } if sets::is_method(method) => match infer_type(receiver, types) {
+            set @ Type::Set(_) => sets::method_type(method, &set),
+            _ => Type::Unknown,
+        },
+        HirExpr::Comprehension {
+            kind: kind @ (ComprehensionKind::List | ComprehensionKind::Set),
+            element,
+            generators,
+            ..
+        } => {
+            let mut scope = types.clone();
+            for generator in generators {
+                let ty = element_type(&generator.iter, &scope);
+                scope.insert(generator.target.clone(), ty);
+            }
+            let element = Box::new(infer_type(element, &scope));
+            match kind {
+                ComprehensionKind::Set => Type::Set(element),
+                _ => Type::List(element),
+            }
+        }
+        HirExpr::MethodCall { method, .. }
+            if matches!(
+                method.as_str(),
+                "upper" | "lower" | "strip" | "lstrip" | "rstrip" | "replace" | "join"
+            ) =>
+        {
+            Type::String
+        }
+        _ => Type::Unknown,
+    }
+}
+
+/// Names bound by a target receiving a value of type `ty`, with their types
+fn bound_types(target: &AssignTarget, ty: Type) -> Vec<(&Symbol, Type)> {
+    match (target, ty) {
+        (AssignTarget::Symbol(name), ty) => vec![(name, ty)],
+        (AssignTarget::Tuple(targets), Type::Tuple(types)) if targets.len() == types.len() => {
+            targets
+                .iter()
+                .zip(types)
+                .flat_map(|(t, ty)| bound_types(t, ty))
+                .collect()
+        }
+        (target, _) => target
+            .symbols()
+            .into_iter()
+            .map(|name| (name, Type::Unknown))
+            .collect(),
+    }
+}
+
+/// Type of the value a target of known locals receives
+fn target_type(target: &AssignTarget, types: &HashMap<Symbol, Type>) -> Type {
+    match target {
+        AssignTarget::Symbol(name) => types.get(name).cloned().unwrap_or(Type::Unknown),
+        AssignTarget::Tuple(targets) => {
+            Type::Tuple(targets.iter().map(|t| target_type(t, types)).collect())
+        }
+        AssignTarget::Attribute { .. } | AssignTarget::Index { .. } => Type::Unknown,
+    }
+}
+
+/// Type of the items produced by iterating over `iter`
+fn element_type(iter: &HirExpr, types: &HashMap<Symbol, Type>) -> Type {
+    match iter {
+        HirExpr::Call { func, .. } if func == "range" => Type::Int,
+        HirExpr::Call { func, args, .. } if func == "enumerate" && args.len() == 1 => {
+            Type::Tuple(vec![Type::Int, element_type(&args[0], types)])
+        }
+        HirExpr::Call { func, args, .. } if func == "zip" => {
+            Type::Tuple(args.iter().map(|a| element_type(a, types)).collect())
+        }
+        HirExpr::MethodCall {
+            receiver, method, ..
+        } if method == "items" => match infer_type(receiver, types) {
+            Type::Dict(key, value) => Type::Tuple(vec![*key, *value]),
+            _ => Type::Unknown,
+        },
+        _ => match infer_type(iter, types) {
+            Type::List(elem) | Type::Set(elem) => *elem,
+            Type::Dict(key, _) => *key,
+            Type::String => Type::String,
+            _ => Type::Unknown,
+        },
+    }
+}
+
+fn self_field(name: &str) -> HirExpr {
+    HirExpr::Attribute {
+        value: Box::new(HirExpr::Var("self".to_string())),
+        attr: name.to_string(),
+    }
+}
+
+/// Rewrite an assignment target so locals become fields
+fn rewrite_target(target: &AssignTarget, fields: &HashSet<Symbol>) -> AssignTarget {
+    match target {
+        AssignTarget::Symbol(name) if fields.contains(name) => AssignTarget::Attribute {
+            value: Box::new(HirExpr::Var("self".to_string())),
+            attr: name.clone(),
+        },
+        AssignTarget::Symbol(_) => target.clone(),
+        AssignTarget::Attribute { value, attr } => AssignTarget::Attribute {
+            value: Box::new(rewrite_expr(value, fields)),
+            attr: attr.clone(),
+        },
+        AssignTarget::Index { base, index } => AssignTarget::Index {
+            base: Box::new(rewrite_expr(base, fields)),
+            index: Box::new(rewrite_expr(index, fields)),
+        },
+        AssignTarget::Tuple(targets) => {
+            AssignTarget::Tuple(targets.iter().map(|t| rewrite_target(t, fields)).collect())
+        }
+    }
+}
+
+/// Rewrite a statement that does not suspend so locals become fields
+fn rewrite_stmt(stmt: &HirStmt, fields: &HashSet<Symbol>) -> HirStmt {
+    let rewrite_body = |body: &[Spanned<HirStmt>]| {
+        body.iter()
+            .map(|s| s.map(|s| rewrite_stmt(s, fields)))
+            .collect()
+    };
+    match stmt {
+        HirStmt::Assign { target, value } => HirStmt::Assign {
+            target: rewrite_target(target, fields),
+            value: rewrite_expr(value, fields),
+        },
+        HirStmt::If {
+            condition,
+            then_body,
+            else_body,
+        } => HirStmt::If {
+            condition: rewrite_expr(condition, fields),
+            then_body: rewrite_body(then_body),
+            else_body: else_body.as_deref().map(rewrite_body),
+        },
+        HirStmt::While { condition, body } => HirStmt::While {
+            condition: rewrite_expr(condition, fields),
+            body: rewrite_body(body),
+        },
+        // The loop binds plain locals, copied into their fields on every pass
+        HirStmt::For { target, iter, body } => {
+            let mut lowered: Vec<Spanned<HirStmt>> = target
+                .symbols()
+                .into_iter()
+                .map(|name| {
+                    HirStmt::Assign {
+                        target: AssignTarget::Attribute {
+                            value: Box::new(HirExpr::Var("self".to_string())),
+                            attr: name.clone(),
+                        },
+                        value: HirExpr::Var(name.clone()),
+                    }
+                    .into()
+                })
+                .collect();
+            lowered.extend(body.iter().map(|s| s.map(|s| rewrite_stmt(s, fields))));
+            HirStmt::For {
+                target: target.clone(),
+                iter: rewrite_expr(iter, fields),
+                body: lowered,
+            }
+        }
+        HirStmt::Expr(expr) => HirStmt::Expr(rewrite_expr(expr, fields)),
+        HirStmt::Raise { exception } => HirStmt::Raise {
+            exception: exception.as_ref().map(|e| rewrite_expr(e, fields)),
+        },
+        HirStmt::Try {
+            body,
+            handlers,
+            orelse,
+            finalbody,
+        } => HirStmt::Try {
+            body: rewrite_body(body),
+            handlers: handlers
+                .iter()
+                .map(|h| ExceptHandler {
+                    exception_types: h.exception_types.clone(),
+                    name: h.name.clone(),
+                    body: rewrite_body(&h.body),
+                })
+                .collect(),
+            orelse: rewrite_body(orelse),
+            finalbody: rewrite_body(finalbody),
+        },
+        // Names bound by the `with` shadow locals of the same name
+        HirStmt::With { items, body } => {
+            let mut scope = fields.clone();
+            let items = items
+                .iter()
+                .map(|item| {
+                    let context_expr = rewrite_expr(&item.context_expr, &scope);
+                    if let Some(name) = &item.optional_vars {
+                        scope.remove(name);
+                    }
+                    WithItem {
+                        context_expr,
+                        optional_vars: item.optional_vars.clone(),
+                    }
+                })
+                .collect();
+            HirStmt::With {
+                items,
+                body: body
+                    .iter()
+                    .map(|s| s.map(|s| rewrite_stmt(s, &scope)))
+                    .collect(),
+            }
+        }
+        // As do names captured by a `case` pattern
+        HirStmt::Match { subject, cases } => HirStmt::Match {
+            subject: rewrite_expr(subject, fields),
+            cases: cases
+                .iter()
+                .map(|case| {
+                    let mut scope = fields.clone();
+                    for name in case.pattern.bindings() {
+                        scope.remove(name);
+                    }
+                    MatchCase {
+                        pattern: case.pattern.clone(),
+                        guard: case.guard.as_ref().map(|g| rewrite_expr(g, &scope)),
+                        body: case
+                            .body
+                            .iter()
+                            .map(|s| s.map(|s| rewrite_stmt(s, &scope)))
+                            .collect(),
+                    }
+                })
+                .collect(),
+        },
+        // Only statements that do not suspend are rewritten, and nested
+        // functions are rejected before rewriting
+        HirStmt::Return(_)
+        | HirStmt::Yield(_)
+        | HirStmt::YieldFrom(_)
+        | HirStmt::FunctionDef { .. } => stmt.clone(),
+    }
+}
+
+fn rewrite_expr(expr: &HirExpr, fields: &HashSet<Symbol>) -> HirExpr {
+    let rewrite_all = |exprs: &[HirExpr]| exprs.iter().map(|e| rewrite_expr(e, fields)).collect();
+    let rewrite_box = |expr: &HirExpr| Box::new(rewrite_expr(expr, fields));
+    let rewrite_keywords = |kwargs: &[(Symbol, HirExpr)]| {
+        kwargs
+            .iter()
+            .map(|(name, value)| (name.clone(), rewrite_expr(value, fields)))
+            .collect()
+    };
+    match expr {
+        HirExpr::Var(name) if fields.contains(name) => self_field(name),
+        HirExpr::Literal(_) | HirExpr::Var(_) => expr.clone(),
+        HirExpr::Binary { op, left, right } => HirExpr::Binary {
+            op: *op,
+            left: rewrite_box(left),
+            right: rewrite_box(right),
+        },
+        HirExpr::Unary { op, operand } => HirExpr::Unary {
+            op: *op,
+            operand: rewrite_box(operand),
+        },
+        HirExpr::Call { func, args, kwargs } => HirExpr::Call {
+            func: func.clone(),
+            args: rewrite_all(args),
+            kwargs: rewrite_keywords(kwargs),
+        },
+        HirExpr::MethodCall {
+            receiver,
+            method,
+            args,
+            kwargs,
+        } => HirExpr::MethodCall {
+            receiver: rewrite_box(receiver),
+            method: method.clone(),
+            args: rewrite_all(args),
+            kwargs: rewrite_keywords(kwargs),
+        },
+        HirExpr::Index { base, index } => HirExpr::Index {
+            base: rewrite_box(base),
+            index: rewrite_box(index),
+        },
+        HirExpr::Slice {
+            base,
+            start,
+            stop,
+            step,
+        } => HirExpr::Slice {
+            base: rewrite_box(base),
+            start: start.as_deref().map(rewrite_box),
+            stop: stop.as_deref().map(rewrite_box),
+            step: step.as_deref().map(rewrite_box),
+        },
+        HirExpr::Attribute { value, attr } => HirExpr::Attribute {
+            value: rewrite_box(value),
+            attr: attr.clone(),
+        },
+        HirExpr::List(elts) => HirExpr::List(rewrite_all(elts)),
+        HirExpr::Set(elts) => HirExpr::Set(rewrite_all(elts)),
+        HirExpr::Tuple(elts) => HirExpr::Tuple(rewrite_all(elts)),
+        HirExpr::FString { parts, values } => HirExpr::FString {
+            parts: parts.clone(),
+            values: rewrite_all(values),
+        },
+        HirExpr::Dict(items) => HirExpr::Dict(
+            items
+                .iter()
+                .map(|(k, v)| (rewrite_expr(k, fields), rewrite_expr(v, fields)))
+                .collect(),
+        ),
+        HirExpr::Borrow { expr, mutable } => HirExpr::Borrow {
+            expr: rewrite_box(expr),
+            mutable: *mutable,
+        },
+        HirExpr::Await { value } => HirExpr::Await {
+            value: rewrite_box(value),
+        },
+        // Comprehension variables shadow locals of the same name
+        HirExpr::Comprehension {
+            kind,
+            element,
+            value,
+            generators,
+        } => {
+            let mut scope = fields.clone();
+            let generators = generators
+                .iter()
+                .map(|g| {
+                    let iter = rewrite_expr(&g.iter, &scope);
+                    scope.remove(&g.target);
+                    ComprehensionClause {
+                        target: g.target.clone(),
+                        iter,
+                        conditions: g
+                            .conditions
+                            .iter()
+                            .map(|c| rewrite_expr(c, &scope))
+                            .collect(),
+                    }
+                })
+                .collect();
+            HirExpr::Comprehension {
+                kind: *kind,
+                element: Box::new(rewrite_expr(element, &scope)),
+                value: value.as_deref().map(|v| Box::new(rewrite_expr(v, &scope))),
+                generators,
+            }
+        }
+        // As do lambda parameters
+        HirExpr::Lambda { params, body } => {
+            let mut scope = fields.clone();
+            for param in params {
+                scope.remove(param);
+            }
+            HirExpr::Lambda {
+                params: params.clone(),
+                body: Box::new(rewrite_expr(body, &scope)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use depyler_annotations::TranspilationAnnotations;
+
+    fn generator(params: Vec<(Symbol, Type)>, body: Vec<Spanned<HirStmt>>) -> HirFunction {
+        HirFunction {
+            name: "gen".to_string(),
+            params: params.into(),
+            param_spec: Default::default(),
+            ret_type: Type::Unknown,
+            body,
+            properties: FunctionProperties {
+                is_generator: true,
+                ..Default::default()
+            },
+            annotations: TranspilationAnnotations::default(),
+            docstring: None,
+            is_async: false,
+            span: Span::default(),
+        }
+    }
+
+    #[test]
+    fn test_yields_split_states() {
+        let func = generator(
+            vec![],
+            vec![
+                HirStmt::Yield(Some(HirExpr::Literal(Literal::Int(1)))).into(),
+                HirStmt::Yield(Some(HirExpr::Literal(Literal::Int(2)))).into(),
+            ],
+        );
+        let machine = StateMachine::build(&func).unwrap();
+        assert_eq!(machine.item_type, Type::Int);
+        assert_eq!(machine.states.len(), 3);
+        assert!(matches!(
+            machine.states[0].exit,
+            GenExit::Yield { next: 1, .. }
+        ));
+        assert!(matches!(
+            machine.states[1].exit,
+            GenExit::Yield { next: 2, .. }
+        ));
+        assert_eq!(machine.states[2].exit, GenExit::Finish);
+    }
+
+    #[test]
+    fn test_loop_keeps_iterator_and_locals() {
+        let func = generator(
+            vec![("n".to_string(), Type::Int)],
+            vec![
+                HirStmt::Assign {
+                    target: AssignTarget::Symbol("total".to_string()),
+                    value: HirExpr::Literal(Literal::Int(0)),
+                }
+                .into(),
+                HirStmt::For {
+                    target: AssignTarget::Symbol("i".to_string()),
+                    iter: HirExpr::Call {
+                        func: "range".to_string(),
+                        args: vec![HirExpr::Var("n".to_string())],
+                        kwargs: vec![],
+                    },
+                    body: vec![HirStmt::Yield(Some(HirExpr::Var("i".to_string()))).into()],
+                }
+                .into(),
+            ],
+        );
+        let machine = StateMachine::build(&func).unwrap();
+        assert_eq!(
+            machine.locals,
+            vec![
+                ("total".to_string(), Type::Int),
+                ("i".to_string(), Type::Int)
+            ]
+        );
+        assert_eq!(machine.iter_slots, vec![Type::Int]);
+        assert_eq!(machine.item_type, Type::Int);
+        assert_eq!(
+            machine.states[0].ops[0],
+            GenOp::Stmt(HirStmt::Assign {
+                target: AssignTarget::Attribute {
+                    value: Box::new(HirExpr::Var("self".to_string())),
+                    attr: "total".to_string(),
+                },
+                value: HirExpr::Literal(Literal::Int(0)),
+            })
+        );
+        assert!(matches!(
+            &machine.states[0].ops[1],
+            GenOp::StartIter { slot: 0, iter: HirExpr::Call { args, .. } }
+                if args[0] == self_field("n")
+        ));
+    }
+
+    #[test]
+    fn test_yield_inside_try_is_rejected() {
+        let func = generator(
+            vec![],
+            vec![HirStmt::Try {
+                body: vec![HirStmt::Yield(None).into()],
+                handlers: vec![],
+                orelse: vec![],
+                finalbody: vec![],
+            }
+            .into()],
+        );
+        assert!(StateMachine::build(&func).is_err());
+    }
+
+    #[test]
+    fn test_generators_run() {
+        let python = r#"
+from typing import Iterator
+
+def count(n: int) -> Iterator[int]:
+    for i in range(n):
+        yield i * 2
+
+def repeat(n: int, value: int) -> Iterator[int]:
+    for _ in range(n + 1):
+        yield value
+
+def evens(n: int) -> Iterator[int]:
+    i = 0
+    while i < n:
+        if i % 2 == 0:
+            yield i
+        i = i + 1
+"#;
+        let main = r#"println!("{:?}", count(3).collect::<Vec<_>>());
+println!("{:?}", repeat(2, 7).collect::<Vec<_>>());
+println!("{:?}", evens(7).collect::<Vec<_>>());"#;
+        assert_eq!(
+            crate::test_programs::run(python, main),
+            "[0, 2, 4]\n[7, 7, 7]\n[0, 2, 4, 6]\n"
+        );
+    }
+}
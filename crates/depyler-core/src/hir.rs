@@ -1,9 +1,86 @@
+use crate::fallback::SkippedConstruct;
 use depyler_annotations::TranspilationAnnotations;
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 
 pub type Symbol = String;
 
+/// Byte range of a node in the Python source; empty for nodes the
+/// transpiler synthesizes. Offsets are `u32`, as in the parser.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Span {
+    pub fn new(start: u32, end: u32) -> Self {
+        Self { start, end }
+    }
+
+    /// The span as a range for slicing the source
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start as usize..self.end as usize
+    }
+
+    /// Whether the span points at no source text
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl From<rustpython_ast::text_size::TextRange> for Span {
+    fn from(range: rustpython_ast::text_size::TextRange) -> Self {
+        Self::new(range.start().into(), range.end().into())
+    }
+}
+
+/// A node together with the source it was converted from. Spans take no
+/// part in comparisons, so nodes compare equal wherever they came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spanned<T> {
+    pub node: T,
+    #[serde(default)]
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+
+    /// Convert the node with `f`, keeping its span
+    pub fn map<U>(&self, f: impl FnOnce(&T) -> U) -> Spanned<U> {
+        Spanned::new(f(&self.node), self.span)
+    }
+}
+
+impl<T> From<T> for Spanned<T> {
+    fn from(node: T) -> Self {
+        Self::new(node, Span::default())
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T> std::ops::Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T> std::ops::DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+}
+
 /// Helper for creating parameter SmallVecs in tests
 #[cfg(test)]
 #[macro_export]
@@ -16,13 +93,22 @@ macro_rules! params {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HirModule {
     pub functions: Vec<HirFunction>,
+    pub classes: Vec<HirClass>,
     pub imports: Vec<Import>,
+    /// Functions that failed to convert and were stubbed out in their place
+    pub skipped: Vec<SkippedConstruct>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Import {
+    /// The dotted module name, without the leading dots of a relative import
     pub module: String,
+    /// Leading dots of a relative `from` import; 0 when absolute
+    pub level: u32,
+    /// `from module import ...`, rather than `import module`
+    pub is_from: bool,
     pub items: Vec<ImportItem>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -35,11 +121,124 @@ pub enum ImportItem {
 pub struct HirFunction {
     pub name: Symbol,
     pub params: SmallVec<[(Symbol, Type); 4]>, // Most functions have < 4 params
+    pub param_spec: ParamSpec,
+    pub ret_type: Type,
+    pub body: Vec<Spanned<HirStmt>>,
+    pub properties: FunctionProperties,
+    pub annotations: TranspilationAnnotations,
+    pub docstring: Option<String>,
+    /// Declared with `async def`, or hinted `async_ready`
+    pub is_async: bool,
+    #[serde(default)]
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HirClass {
+    pub name: Symbol,
+    pub base_classes: Vec<Symbol>,
+    pub fields: Vec<HirField>,
+    pub methods: Vec<HirMethod>,
+    pub is_dataclass: bool,
+    pub annotations: TranspilationAnnotations,
+    pub docstring: Option<String>,
+}
+
+impl HirClass {
+    /// The user-defined `__init__`, if the class has one
+    pub fn init_method(&self) -> Option<&HirMethod> {
+        self.methods.iter().find(|m| m.name == "__init__")
+    }
+
+    /// Subclasses of `enum.Enum` and its variants become Rust enums
+    pub fn is_enum(&self) -> bool {
+        self.base_classes
+            .iter()
+            .any(|b| matches!(b.as_str(), "Enum" | "IntEnum" | "StrEnum"))
+    }
+
+    /// Member names of an enum class, in declaration order
+    pub fn enum_members(&self) -> Vec<&Symbol> {
+        self.fields
+            .iter()
+            .filter(|f| f.is_class_var)
+            .map(|f| &f.name)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HirField {
+    pub name: Symbol,
+    pub field_type: Type,
+    pub default_value: Option<HirExpr>,
+    /// Class attributes (`ClassVar` or unannotated class-level assignments)
+    /// become associated constants rather than struct fields
+    pub is_class_var: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HirMethod {
+    pub name: Symbol,
+    /// Parameters excluding the `self`/`cls` receiver
+    pub params: SmallVec<[(Symbol, Type); 4]>,
+    pub param_spec: ParamSpec,
     pub ret_type: Type,
-    pub body: Vec<HirStmt>,
+    pub body: Vec<Spanned<HirStmt>>,
+    pub kind: MethodKind,
     pub properties: FunctionProperties,
     pub annotations: TranspilationAnnotations,
     pub docstring: Option<String>,
+    /// Declared with `async def`, or hinted `async_ready`
+    pub is_async: bool,
+    #[serde(default)]
+    pub span: Span,
+}
+
+/// How call arguments bind to the parameters of a function or method.
+///
+/// `params` holds one Rust parameter per Python parameter, in declaration
+/// order: the positional ones, `*args` as a list, the keyword-only ones and
+/// finally `**kwargs`. Calls are resolved against this spec, so Rust callers
+/// always pass every parameter positionally.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParamSpec {
+    /// Default values, filled in at call sites that omit the argument
+    pub defaults: Vec<(Symbol, HirExpr)>,
+    /// Parameters declared before `/`, which cannot be passed by keyword
+    pub positional_only: Vec<Symbol>,
+    /// Parameters declared after `*` or `*args`
+    pub keyword_only: Vec<Symbol>,
+    /// The `*args` parameter, typed as a list
+    pub var_positional: Option<Symbol>,
+    /// The `**kwargs` parameter and the type of its values
+    pub var_keyword: Option<(Symbol, Type)>,
+    /// Keywords given a field in the options struct generated for `**kwargs`
+    pub kwargs_fields: Vec<Symbol>,
+}
+
+impl ParamSpec {
+    pub fn default_value(&self, param: &str) -> Option<&HirExpr> {
+        self.defaults
+            .iter()
+            .find(|(name, _)| name == param)
+            .map(|(_, value)| value)
+    }
+
+    /// Whether `param` is `*args`, `**kwargs` or keyword-only
+    pub fn is_positional(&self, param: &str) -> bool {
+        self.var_positional.as_deref() != Some(param)
+            && self.var_keyword.as_ref().map(|(n, _)| n.as_str()) != Some(param)
+            && !self.keyword_only.iter().any(|n| n == param)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MethodKind {
+    Instance,
+    Static,
+    Class,
+    Property,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
@@ -48,30 +247,248 @@ pub struct FunctionProperties {
     pub max_stack_depth: Option<usize>,
     pub always_terminates: bool,
     pub panic_free: bool,
+    /// The function can raise, so it is lowered to return `Result`
+    pub can_fail: bool,
+    /// Exception types that can escape the function
+    pub error_types: Vec<String>,
+    /// The function contains `yield`; its `ret_type` is then the item type
+    pub is_generator: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum HirStmt {
     Assign {
-        target: Symbol,
+        target: AssignTarget,
         value: HirExpr,
     },
     Return(Option<HirExpr>),
     If {
         condition: HirExpr,
-        then_body: Vec<HirStmt>,
-        else_body: Option<Vec<HirStmt>>,
+        then_body: Vec<Spanned<HirStmt>>,
+        else_body: Option<Vec<Spanned<HirStmt>>>,
     },
     While {
         condition: HirExpr,
-        body: Vec<HirStmt>,
+        body: Vec<Spanned<HirStmt>>,
     },
     For {
-        target: Symbol,
+        target: AssignTarget,
         iter: HirExpr,
-        body: Vec<HirStmt>,
+        body: Vec<Spanned<HirStmt>>,
     },
     Expr(HirExpr),
+    Yield(Option<HirExpr>),
+    YieldFrom(HirExpr),
+    Raise {
+        /// `None` for a bare `raise` re-raising the active exception
+        exception: Option<HirExpr>,
+    },
+    Try {
+        body: Vec<Spanned<HirStmt>>,
+        handlers: Vec<ExceptHandler>,
+        orelse: Vec<Spanned<HirStmt>>,
+        finalbody: Vec<Spanned<HirStmt>>,
+    },
+    With {
+        items: Vec<WithItem>,
+        body: Vec<Spanned<HirStmt>>,
+    },
+    Match {
+        subject: HirExpr,
+        cases: Vec<MatchCase>,
+    },
+    /// A `def` inside a function body, lowered to a closure over the
+    /// enclosing function's locals
+    FunctionDef {
+        func: Box<HirFunction>,
+        /// Enclosing locals the body rebinds through `nonlocal`
+        nonlocals: Vec<Symbol>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExceptHandler {
+    /// Exception classes caught; empty for a bare `except:`
+    pub exception_types: Vec<Symbol>,
+    pub name: Option<Symbol>,
+    pub body: Vec<Spanned<HirStmt>>,
+}
+
+/// One `expr as name` clause of a `with` statement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WithItem {
+    pub context_expr: HirExpr,
+    pub optional_vars: Option<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchCase {
+    pub pattern: Pattern,
+    pub guard: Option<HirExpr>,
+    pub body: Vec<Spanned<HirStmt>>,
+}
+
+/// A PEP 634 pattern of a `case` clause
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Pattern {
+    /// `_`
+    Wildcard,
+    /// `name`, or `pattern as name`
+    Capture {
+        name: Symbol,
+        pattern: Option<Box<Pattern>>,
+    },
+    /// A literal or dotted constant such as `Color.RED`, compared by value
+    Value(HirExpr),
+    /// `[a, b, *rest]` or `(a, b)`
+    Sequence(Vec<Pattern>),
+    /// `*rest` inside a sequence pattern; `None` for `*_`
+    Star(Option<Symbol>),
+    /// `{"key": pattern, **rest}`
+    Mapping {
+        keys: Vec<HirExpr>,
+        patterns: Vec<Pattern>,
+        rest: Option<Symbol>,
+    },
+    /// `Point(x, y=0)`
+    Class {
+        class: Symbol,
+        patterns: Vec<Pattern>,
+        keywords: Vec<(Symbol, Pattern)>,
+    },
+    /// `a | b`
+    Or(Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Whether the pattern matches every subject
+    pub fn is_irrefutable(&self) -> bool {
+        match self {
+            Pattern::Wildcard => true,
+            Pattern::Capture { pattern, .. } => {
+                pattern.as_deref().is_none_or(Pattern::is_irrefutable)
+            }
+            Pattern::Or(alternatives) => alternatives.iter().any(Pattern::is_irrefutable),
+            _ => false,
+        }
+    }
+
+    /// Names bound by the pattern, in source order
+    pub fn bindings(&self) -> Vec<&Symbol> {
+        let mut names = Vec::new();
+        self.collect_bindings(&mut names);
+        names
+    }
+
+    fn collect_bindings<'p>(&'p self, names: &mut Vec<&'p Symbol>) {
+        match self {
+            Pattern::Capture { name, pattern } => {
+                if let Some(pattern) = pattern {
+                    pattern.collect_bindings(names);
+                }
+                names.push(name);
+            }
+            Pattern::Star(Some(name)) => names.push(name),
+            Pattern::Sequence(patterns) => {
+                patterns.iter().for_each(|p| p.collect_bindings(names));
+            }
+            Pattern::Mapping { patterns, rest, .. } => {
+                patterns.iter().for_each(|p| p.collect_bindings(names));
+                names.extend(rest);
+            }
+            Pattern::Class {
+                patterns, keywords, ..
+            } => {
+                patterns.iter().for_each(|p| p.collect_bindings(names));
+                keywords.iter().for_each(|(_, p)| p.collect_bindings(names));
+            }
+            // Every alternative binds the same names
+            Pattern::Or(alternatives) => {
+                if let Some(first) = alternatives.first() {
+                    first.collect_bindings(names);
+                }
+            }
+            Pattern::Wildcard | Pattern::Value(_) | Pattern::Star(None) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AssignTarget {
+    Symbol(Symbol),
+    Attribute {
+        value: Box<HirExpr>,
+        attr: Symbol,
+    },
+    /// `base[index] = value`
+    Index {
+        base: Box<HirExpr>,
+        index: Box<HirExpr>,
+    },
+    /// `a, (b, c) = value`, unpacking the value into each element
+    Tuple(Vec<AssignTarget>),
+}
+
+impl AssignTarget {
+    /// The bound name for simple `name = value` targets
+    pub fn as_symbol(&self) -> Option<&Symbol> {
+        match self {
+            AssignTarget::Symbol(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Names bound by the target, in order
+    pub fn symbols(&self) -> Vec<&Symbol> {
+        match self {
+            AssignTarget::Symbol(s) => vec![s],
+            AssignTarget::Tuple(targets) => targets.iter().flat_map(|t| t.symbols()).collect(),
+            AssignTarget::Attribute { .. } | AssignTarget::Index { .. } => vec![],
+        }
+    }
+
+    /// Expressions evaluated to locate the target, such as `base` and `index`
+    pub fn operands(&self) -> Vec<&HirExpr> {
+        match self {
+            AssignTarget::Symbol(_) => vec![],
+            AssignTarget::Attribute { value, .. } => vec![value],
+            AssignTarget::Index { base, index } => vec![base, index],
+            AssignTarget::Tuple(targets) => targets.iter().flat_map(|t| t.operands()).collect(),
+        }
+    }
+
+    /// Pair each part of the target with the part of `value` it receives, as
+    /// far as a tuple display on the right lines up with the target
+    pub fn split_value<'a>(&'a self, value: &'a HirExpr) -> Vec<(&'a AssignTarget, &'a HirExpr)> {
+        match (self, value) {
+            (AssignTarget::Tuple(targets), HirExpr::Tuple(values) | HirExpr::List(values))
+                if targets.len() == values.len() =>
+            {
+                targets
+                    .iter()
+                    .zip(values)
+                    .flat_map(|(t, v)| t.split_value(v))
+                    .collect()
+            }
+            _ => vec![(self, value)],
+        }
+    }
+
+    /// The target read as an expression, as augmented assignment does
+    pub fn to_expr(&self) -> Option<HirExpr> {
+        Some(match self {
+            AssignTarget::Symbol(name) => HirExpr::Var(name.clone()),
+            AssignTarget::Attribute { value, attr } => HirExpr::Attribute {
+                value: value.clone(),
+                attr: attr.clone(),
+            },
+            AssignTarget::Index { base, index } => HirExpr::Index {
+                base: base.clone(),
+                index: index.clone(),
+            },
+            AssignTarget::Tuple(_) => return None,
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -90,18 +507,60 @@ pub enum HirExpr {
     Call {
         func: Symbol,
         args: Vec<HirExpr>,
+        /// Keyword arguments not bound to a known parameter
+        kwargs: Vec<(Symbol, HirExpr)>,
+    },
+    MethodCall {
+        receiver: Box<HirExpr>,
+        method: Symbol,
+        args: Vec<HirExpr>,
+        /// Keyword arguments not bound to a known parameter
+        kwargs: Vec<(Symbol, HirExpr)>,
     },
     Index {
         base: Box<HirExpr>,
         index: Box<HirExpr>,
     },
+    /// `base[start:stop:step]`, with each bound optional
+    Slice {
+        base: Box<HirExpr>,
+        start: Option<Box<HirExpr>>,
+        stop: Option<Box<HirExpr>>,
+        step: Option<Box<HirExpr>>,
+    },
     Attribute {
         value: Box<HirExpr>,
         attr: Symbol,
     },
     List(Vec<HirExpr>),
     Dict(Vec<(HirExpr, HirExpr)>),
+    /// A set display, `{a, b}`
+    Set(Vec<HirExpr>),
     Tuple(Vec<HirExpr>),
+    /// List, set and dict comprehensions and generator expressions
+    Comprehension {
+        kind: ComprehensionKind,
+        /// The produced element, or the key of a dict comprehension
+        element: Box<HirExpr>,
+        /// The value of a dict comprehension
+        value: Option<Box<HirExpr>>,
+        generators: Vec<ComprehensionClause>,
+    },
+    Await {
+        value: Box<HirExpr>,
+    },
+    /// `lambda params: body`
+    Lambda {
+        params: Vec<Symbol>,
+        body: Box<HirExpr>,
+    },
+    /// An f-string, or `str.format` or `%` formatting of a literal format
+    /// string: text with values formatted into it
+    FString {
+        parts: Vec<FStringPart>,
+        /// The values formatted, each evaluated once in order
+        values: Vec<HirExpr>,
+    },
     // Ownership hints from analysis
     Borrow {
         expr: Box<HirExpr>,
@@ -109,6 +568,206 @@ pub enum HirExpr {
     },
 }
 
+impl HirExpr {
+    /// Whether this is a call to a builtin collection method that mutates its receiver
+    pub fn is_mutating_method_call(&self) -> bool {
+        matches!(self, HirExpr::MethodCall { method, .. } if is_mutating_method(method))
+    }
+}
+
+/// A piece of a formatted string
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FStringPart {
+    Text(String),
+    /// `values[value]` converted by `!r`, `!s` or `!a`, then formatted by a
+    /// Python format spec, in which `{N}` stands for `values[N]`
+    Field {
+        value: usize,
+        conversion: Option<char>,
+        spec: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ComprehensionKind {
+    List,
+    Set,
+    Dict,
+    Generator,
+}
+
+/// One `for target in iter if cond...` clause of a comprehension
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComprehensionClause {
+    pub target: Symbol,
+    pub iter: HirExpr,
+    pub conditions: Vec<HirExpr>,
+}
+
+/// Builtin list, dict and set methods that modify the receiver in place
+pub fn is_mutating_method(method: &str) -> bool {
+    matches!(
+        method,
+        "append"
+            | "extend"
+            | "insert"
+            | "remove"
+            | "pop"
+            | "clear"
+            | "sort"
+            | "reverse"
+            | "update"
+            | "setdefault"
+            | "popitem"
+            | "add"
+            | "discard"
+    )
+}
+
+/// Visit every expression of a body, nested bodies included
+pub fn for_each_expr_in(body: &[Spanned<HirStmt>], f: &mut impl FnMut(&HirExpr)) {
+    for stmt in body {
+        match &stmt.node {
+            HirStmt::Assign { target, value } => {
+                for operand in target.operands() {
+                    visit_expr(operand, f);
+                }
+                visit_expr(value, f);
+            }
+            HirStmt::Return(value) | HirStmt::Yield(value) => {
+                if let Some(value) = value {
+                    visit_expr(value, f);
+                }
+            }
+            HirStmt::Raise { exception } => {
+                if let Some(exception) = exception {
+                    visit_expr(exception, f);
+                }
+            }
+            HirStmt::Expr(expr) | HirStmt::YieldFrom(expr) => visit_expr(expr, f),
+            HirStmt::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                visit_expr(condition, f);
+                for_each_expr_in(then_body, f);
+                if let Some(else_body) = else_body {
+                    for_each_expr_in(else_body, f);
+                }
+            }
+            HirStmt::While { condition, body } => {
+                visit_expr(condition, f);
+                for_each_expr_in(body, f);
+            }
+            HirStmt::For { iter, body, .. } => {
+                visit_expr(iter, f);
+                for_each_expr_in(body, f);
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                for_each_expr_in(body, f);
+                for handler in handlers {
+                    for_each_expr_in(&handler.body, f);
+                }
+                for_each_expr_in(orelse, f);
+                for_each_expr_in(finalbody, f);
+            }
+            HirStmt::With { items, body } => {
+                for item in items {
+                    visit_expr(&item.context_expr, f);
+                }
+                for_each_expr_in(body, f);
+            }
+            HirStmt::Match { subject, cases } => {
+                visit_expr(subject, f);
+                for case in cases {
+                    if let Some(guard) = &case.guard {
+                        visit_expr(guard, f);
+                    }
+                    for_each_expr_in(&case.body, f);
+                }
+            }
+            HirStmt::FunctionDef { func, .. } => for_each_expr_in(&func.body, f),
+        }
+    }
+}
+
+/// Visit an expression and every expression nested in it, lambda bodies included
+pub fn visit_expr(expr: &HirExpr, f: &mut impl FnMut(&HirExpr)) {
+    f(expr);
+    match expr {
+        HirExpr::Literal(_) | HirExpr::Var(_) => {}
+        HirExpr::Binary { left, right, .. } => {
+            visit_expr(left, f);
+            visit_expr(right, f);
+        }
+        HirExpr::Unary { operand, .. } => visit_expr(operand, f),
+        HirExpr::Call { args, kwargs, .. } => {
+            args.iter().for_each(|a| visit_expr(a, f));
+            kwargs.iter().for_each(|(_, v)| visit_expr(v, f));
+        }
+        HirExpr::MethodCall {
+            receiver,
+            args,
+            kwargs,
+            ..
+        } => {
+            visit_expr(receiver, f);
+            args.iter().for_each(|a| visit_expr(a, f));
+            kwargs.iter().for_each(|(_, v)| visit_expr(v, f));
+        }
+        HirExpr::Index { base, index } => {
+            visit_expr(base, f);
+            visit_expr(index, f);
+        }
+        HirExpr::Slice {
+            base,
+            start,
+            stop,
+            step,
+        } => {
+            visit_expr(base, f);
+            for bound in [start, stop, step].into_iter().flatten() {
+                visit_expr(bound, f);
+            }
+        }
+        HirExpr::Attribute { value, .. }
+        | HirExpr::Await { value }
+        | HirExpr::Borrow { expr: value, .. } => visit_expr(value, f),
+        HirExpr::List(elts) | HirExpr::Set(elts) | HirExpr::Tuple(elts) => {
+            elts.iter().for_each(|e| visit_expr(e, f))
+        }
+        HirExpr::Dict(items) => {
+            for (key, value) in items {
+                visit_expr(key, f);
+                visit_expr(value, f);
+            }
+        }
+        HirExpr::Comprehension {
+            element,
+            value,
+            generators,
+            ..
+        } => {
+            visit_expr(element, f);
+            if let Some(value) = value {
+                visit_expr(value, f);
+            }
+            for generator in generators {
+                visit_expr(&generator.iter, f);
+                generator.conditions.iter().for_each(|c| visit_expr(c, f));
+            }
+        }
+        HirExpr::Lambda { body, .. } => visit_expr(body, f),
+        HirExpr::FString { values, .. } => values.iter().for_each(|v| visit_expr(v, f)),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Literal {
     Int(i64),
@@ -162,9 +821,18 @@ pub enum Type {
     None,
     List(Box<Type>),
     Dict(Box<Type>, Box<Type>),
+    /// `set` and `frozenset`
+    Set(Box<Type>),
     Tuple(Vec<Type>),
     Optional(Box<Type>),
-    Function { params: Vec<Type>, ret: Box<Type> },
+    /// `Union[A, B]` and `A | B` of two or more types besides `None`
+    Union(Vec<Type>),
+    /// `Literal["a", "b"]` of strings
+    Literal(Vec<String>),
+    Function {
+        params: Vec<Type>,
+        ret: Box<Type>,
+    },
     Custom(String),
 }
 
@@ -174,6 +842,9 @@ impl Type {
     }
 
     pub fn is_container(&self) -> bool {
-        matches!(self, Type::List(_) | Type::Dict(_, _) | Type::Tuple(_))
+        matches!(
+            self,
+            Type::List(_) | Type::Dict(_, _) | Type::Set(_) | Type::Tuple(_)
+        )
     }
 }
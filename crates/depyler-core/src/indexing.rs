@@ -0,0 +1,558 @@
+//! Lowering of Python subscripts of sequences: indexing and slicing
+//!
+//! Indexes count from the end when negative, as in `xs[-1]`. What an index
+//! out of range does is chosen per function by the `bounds_checking` and
+//! `verify_bounds` annotations, see [`IndexPolicy`]. Slices never fail:
+//! their bounds are clamped to the sequence, and strings are sliced by
+//! character.
+
+use crate::error::ErrorKind;
+use crate::generators;
+use crate::hir::{AssignTarget, HirExpr, HirStmt, Literal, Spanned, Symbol, Type, UnaryOp};
+use crate::strings;
+use crate::transpile_bail;
+use anyhow::Result;
+use depyler_annotations::{BoundsChecking, TranspilationAnnotations};
+use std::collections::HashMap;
+use syn::parse_quote;
+
+/// What indexing a sequence out of range does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexPolicy {
+    /// Panic with the message of Python's `IndexError`; `bounds_checking = "explicit"`
+    #[default]
+    Panic,
+    /// Raise `IndexError` as a `raise` statement would, making the function
+    /// return `Result`; `verify_bounds = true`
+    Raise,
+    /// Leave it to Rust's bounds check; `bounds_checking = "implicit"`
+    Native,
+    /// Yield the default value of the element type; `bounds_checking = "disabled"`
+    Default,
+}
+
+impl IndexPolicy {
+    pub fn new(annotations: &TranspilationAnnotations) -> Self {
+        match annotations.bounds_checking {
+            BoundsChecking::Disabled => IndexPolicy::Default,
+            _ if annotations.verify_bounds => IndexPolicy::Raise,
+            BoundsChecking::Explicit => IndexPolicy::Panic,
+            BoundsChecking::Implicit => IndexPolicy::Native,
+        }
+    }
+}
+
+/// Whether `expr` indexes a sequence, and so raises `IndexError` under
+/// [`IndexPolicy::Raise`], going by the type of what it indexes in `types`.
+/// Of a container of unknown type, string keys index dicts.
+pub fn indexes_sequence(expr: &HirExpr, types: &HashMap<Symbol, Type>) -> bool {
+    let HirExpr::Index { base, index } = expr else {
+        return false;
+    };
+    match generators::infer_type(base, types) {
+        Type::Dict(_, _) => false,
+        Type::List(_) | Type::String | Type::Tuple(_) => true,
+        _ => !matches!(index.as_ref(), HirExpr::Literal(Literal::String(_))),
+    }
+}
+
+/// The types of a function's parameters, and of the locals its body
+/// assigns values of a known type
+pub fn local_types(params: &[(Symbol, Type)], body: &[Spanned<HirStmt>]) -> HashMap<Symbol, Type> {
+    let mut types: HashMap<Symbol, Type> = params.iter().cloned().collect();
+    collect_local_types(body, &mut types);
+    types
+}
+
+fn collect_local_types(body: &[Spanned<HirStmt>], types: &mut HashMap<Symbol, Type>) {
+    for stmt in body {
+        match &stmt.node {
+            HirStmt::Assign {
+                target: AssignTarget::Symbol(name),
+                value,
+            } if !types.contains_key(name) => {
+                let ty = generators::infer_type(value, types);
+                if ty != Type::Unknown {
+                    types.insert(name.clone(), ty);
+                }
+            }
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                collect_local_types(then_body, types);
+                collect_local_types(else_body.as_deref().unwrap_or_default(), types);
+            }
+            HirStmt::While { body, .. }
+            | HirStmt::For { body, .. }
+            | HirStmt::With { body, .. } => collect_local_types(body, types),
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                collect_local_types(body, types);
+                for handler in handlers {
+                    collect_local_types(&handler.body, types);
+                }
+                collect_local_types(orelse, types);
+                collect_local_types(finalbody, types);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The value of an integer literal, negated ones included
+pub fn int_literal(expr: &HirExpr) -> Option<i64> {
+    match expr {
+        HirExpr::Literal(Literal::Int(n)) => Some(*n),
+        HirExpr::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => match operand.as_ref() {
+            HirExpr::Literal(Literal::Int(n)) => Some(-n),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// What is known of an integer index besides its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sign {
+    /// An `int` that cannot be negative, such as `len(xs)` or a variable
+    /// counting up `range(n)`
+    NonNegative,
+    /// Any `int`
+    Signed,
+}
+
+/// An index, or a bound of a slice
+pub enum Bound {
+    Literal(i64),
+    Expr(syn::Expr, Sign),
+}
+
+impl Bound {
+    /// Whether the position depends on the length of the sequence
+    fn counts_from_end(&self) -> bool {
+        match self {
+            Bound::Literal(n) => *n < 0,
+            Bound::Expr(_, sign) => *sign == Sign::Signed,
+        }
+    }
+
+    /// `offset(index)` for an index not counting from the end, and `from_end`
+    /// of the distance from the end for one that does
+    fn lower(
+        &self,
+        offset: impl Fn(syn::Expr) -> syn::Expr,
+        from_end: impl Fn(syn::Expr) -> syn::Expr,
+    ) -> syn::Expr {
+        match self {
+            Bound::Literal(n) if *n < 0 => from_end(usize_literal(n.unsigned_abs())),
+            Bound::Literal(n) => offset(usize_literal(*n as u64)),
+            Bound::Expr(expr, Sign::NonNegative) => offset(as_usize(expr)),
+            Bound::Expr(expr, Sign::Signed) if strings::is_place(expr) => {
+                let back = from_end(parse_quote! { #expr.unsigned_abs() as usize });
+                let front = offset(parse_quote! { #expr as usize });
+                parse_quote! { if #expr < 0 { #back } else { #front } }
+            }
+            // Evaluated once
+            Bound::Expr(expr, Sign::Signed) => {
+                let back = from_end(parse_quote! { __i.unsigned_abs() as usize });
+                let front = offset(parse_quote! { __i as usize });
+                parse_quote! {
+                    match #expr {
+                        __i if __i < 0 => #back,
+                        __i => #front,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Non-negative int `expr` as a `usize`; the length of a sequence, an
+/// int only by a cast, is one already
+fn as_usize(expr: &syn::Expr) -> syn::Expr {
+    let mut inner = expr;
+    while let syn::Expr::Paren(paren) = inner {
+        inner = &paren.expr;
+    }
+    match inner {
+        syn::Expr::Cast(cast) if is_length(&cast.expr) => (*cast.expr).clone(),
+        _ => parse_quote! { #expr as usize },
+    }
+}
+
+fn is_length(expr: &syn::Expr) -> bool {
+    matches!(expr, syn::Expr::MethodCall(call)
+        if call.args.is_empty() && (call.method == "len" || call.method == "count"))
+}
+
+fn usize_literal(n: u64) -> syn::Expr {
+    let lit = proc_macro2::Literal::u64_unsuffixed(n);
+    parse_quote! { #lit }
+}
+
+/// The position `index` refers to in a sequence of `len` items; a negative
+/// index too far back wraps around past the end, so either is out of range
+pub fn position(index: &Bound, len: &syn::Expr) -> syn::Expr {
+    index.lower(|i| i, |back| parse_quote! { #len.wrapping_sub(#back) })
+}
+
+/// `Option` of a reference to the element of `list` at `position`
+pub fn get(list: &syn::Expr, position: &syn::Expr, mutable: bool) -> syn::Expr {
+    let first = matches!(position, syn::Expr::Lit(lit) if is_zero(lit));
+    match (first, mutable) {
+        (true, false) => parse_quote! { #list.first() },
+        (true, true) => parse_quote! { #list.first_mut() },
+        (false, false) => parse_quote! { #list.get(#position) },
+        (false, true) => parse_quote! { #list.get_mut(#position) },
+    }
+}
+
+/// Whether finding the position of `index` takes the length of the sequence
+pub fn needs_len(index: &Bound) -> bool {
+    index.counts_from_end()
+}
+
+/// The items of a sliced sequence, and what the slice collects them into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Items {
+    /// A list of `Copy` items
+    Copied,
+    Cloned,
+    /// A string, sliced by character
+    Chars,
+}
+
+/// `base[start:stop:step]`
+pub fn slice(
+    base: &syn::Expr,
+    items: Items,
+    start: Option<Bound>,
+    stop: Option<Bound>,
+    step: i64,
+) -> Result<syn::Expr> {
+    if step == 0 {
+        transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Slice step cannot be zero".to_string(),
+        ));
+    }
+    if start.is_none() && stop.is_none() && step == 1 {
+        return Ok(match items {
+            Items::Chars => parse_quote! { #base.to_string() },
+            _ => parse_quote! { #base.to_vec() },
+        });
+    }
+    let from_end = [&start, &stop]
+        .into_iter()
+        .flatten()
+        .any(Bound::counts_from_end);
+    let lower = |base: &syn::Expr| {
+        let len: syn::Expr = match items {
+            Items::Chars => parse_quote! { #base.chars().count() },
+            _ => parse_quote! { #base.len() },
+        };
+        // The items before a bound, clamped to the sequence
+        let before =
+            |bound: &Bound| bound.lower(|i| i, |back| parse_quote! { #len.saturating_sub(#back) });
+        let through = |bound: &Bound| through(bound, &len);
+        // Items `lo..hi` are the first `hi`, less the first `lo`; a negative
+        // step walks `hi..=lo` backwards
+        let (take, skip) = if step > 0 {
+            (stop.as_ref().map(before), start.as_ref().map(before))
+        } else {
+            (start.as_ref().map(through), stop.as_ref().map(through))
+        };
+        let mut chain: syn::Expr = match items {
+            // Reversing a part of the characters takes their count
+            Items::Chars if step < 0 && (take.is_some() || skip.is_some()) => {
+                parse_quote! { #base.chars().collect::<Vec<char>>().into_iter() }
+            }
+            Items::Chars => parse_quote! { #base.chars() },
+            _ => parse_quote! { #base.iter() },
+        };
+        if let Some(take) = take {
+            chain = parse_quote! { #chain.take(#take) };
+        }
+        if let Some(skip) = skip.filter(|s| !matches!(s, syn::Expr::Lit(l) if is_zero(l))) {
+            chain = parse_quote! { #chain.skip(#skip) };
+        }
+        if step < 0 {
+            chain = parse_quote! { #chain.rev() };
+        }
+        if step.unsigned_abs() > 1 {
+            let step = usize_literal(step.unsigned_abs());
+            chain = parse_quote! { #chain.step_by(#step) };
+        }
+        match items {
+            Items::Copied => parse_quote! { #chain.copied().collect::<Vec<_>>() },
+            Items::Cloned => parse_quote! { #chain.cloned().collect::<Vec<_>>() },
+            Items::Chars => parse_quote! { #chain.collect::<String>() },
+        }
+    };
+    Ok(if from_end {
+        strings::with_receiver(base, lower)
+    } else {
+        lower(base)
+    })
+}
+
+/// `base[start:stop:step]` for a `step` known only when it runs: the
+/// positions are found as Python's `slice.indices` finds them
+pub fn slice_by_step(
+    base: &syn::Expr,
+    items: Items,
+    start: Option<syn::Expr>,
+    stop: Option<syn::Expr>,
+    step: &syn::Expr,
+) -> syn::Expr {
+    let (sequence, item, collect): (syn::Expr, syn::Expr, syn::Expr) = match items {
+        Items::Copied => (
+            parse_quote! { &#base },
+            parse_quote! { __items[__i as usize] },
+            parse_quote! { collect::<Vec<_>>() },
+        ),
+        Items::Cloned => (
+            parse_quote! { &#base },
+            parse_quote! { __items[__i as usize].clone() },
+            parse_quote! { collect::<Vec<_>>() },
+        ),
+        Items::Chars => (
+            parse_quote! { #base.chars().collect::<Vec<char>>() },
+            parse_quote! { __items[__i as usize] },
+            parse_quote! { collect::<String>() },
+        ),
+    };
+    let bound = |bound: Option<syn::Expr>, default: syn::Expr| -> syn::Expr {
+        match bound {
+            Some(bound) => parse_quote! { __clamp((#bound) as i64) },
+            None => default,
+        }
+    };
+    let start = bound(
+        start,
+        parse_quote! { if __step > 0 { 0 } else { __len - 1 } },
+    );
+    let stop = bound(stop, parse_quote! { if __step > 0 { __len } else { -1 } });
+    parse_quote! {
+        {
+            let __items = #sequence;
+            let __len = __items.len() as i64;
+            let __step = (#step) as i64;
+            assert!(__step != 0, "slice step cannot be zero");
+            // Negative bounds count from the end; both are clamped to the
+            // positions a step in that direction can reach
+            let __clamp = |__b: i64| {
+                let __b = if __b < 0 { __b + __len } else { __b };
+                if __step > 0 { __b.clamp(0, __len) } else { __b.clamp(-1, __len - 1) }
+            };
+            let __stop = #stop;
+            std::iter::successors(Some(#start), |__i| Some(__i + __step))
+                .take_while(|__i| if __step > 0 { *__i < __stop } else { *__i > __stop })
+                .map(|__i| #item)
+                .#collect
+        }
+    }
+}
+
+/// The items up to and including `bound`, clamped to the sequence
+fn through(bound: &Bound, len: &syn::Expr) -> syn::Expr {
+    match bound {
+        Bound::Literal(n) if *n < 0 => {
+            let back = usize_literal(n.unsigned_abs() - 1);
+            parse_quote! { #len.saturating_sub(#back) }
+        }
+        Bound::Literal(n) => usize_literal(*n as u64 + 1),
+        Bound::Expr(expr, Sign::NonNegative) => {
+            let index = as_usize(expr);
+            parse_quote! { #index + 1 }
+        }
+        Bound::Expr(expr, Sign::Signed) if strings::is_place(expr) => parse_quote! {
+            if #expr < 0 {
+                #len.saturating_sub((#expr + 1).unsigned_abs() as usize)
+            } else {
+                #expr as usize + 1
+            }
+        },
+        Bound::Expr(expr, Sign::Signed) => parse_quote! {
+            match #expr {
+                __i if __i < 0 => #len.saturating_sub((__i + 1).unsigned_abs() as usize),
+                __i => __i as usize + 1,
+            }
+        },
+    }
+}
+
+fn is_zero(lit: &syn::ExprLit) -> bool {
+    matches!(&lit.lit, syn::Lit::Int(n) if n.base10_digits() == "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::ToTokens;
+
+    fn var(name: &str, sign: Sign) -> Bound {
+        Bound::Expr(syn::parse_str(name).unwrap(), sign)
+    }
+
+    fn code(expr: syn::Expr) -> String {
+        expr.to_token_stream().to_string()
+    }
+
+    #[test]
+    fn test_indexes_sequence_by_type() {
+        let index = |base: &str, key: HirExpr| HirExpr::Index {
+            base: Box::new(HirExpr::Var(base.to_string())),
+            index: Box::new(key),
+        };
+        let params = [
+            (
+                "d".to_string(),
+                Type::Dict(Box::new(Type::Int), Box::new(Type::Int)),
+            ),
+            ("xs".to_string(), Type::List(Box::new(Type::Int))),
+        ];
+        let body = [Spanned::from(HirStmt::Assign {
+            target: AssignTarget::Symbol("counts".to_string()),
+            value: HirExpr::Dict(vec![]),
+        })];
+        let types = local_types(&params, &body);
+        let zero = || HirExpr::Literal(Literal::Int(0));
+        assert!(!indexes_sequence(&index("d", zero()), &types));
+        assert!(!indexes_sequence(&index("counts", zero()), &types));
+        assert!(indexes_sequence(&index("xs", zero()), &types));
+        assert!(indexes_sequence(&index("ys", zero()), &types));
+        let key = HirExpr::Literal(Literal::String("k".to_string()));
+        assert!(!indexes_sequence(&index("ys", key), &types));
+    }
+
+    #[test]
+    fn test_positions() {
+        let len: syn::Expr = parse_quote! { xs.len() };
+        assert_eq!(code(position(&Bound::Literal(2), &len)), "2");
+        assert_eq!(
+            code(position(&Bound::Literal(-1), &len)),
+            "xs . len () . wrapping_sub (1)"
+        );
+        assert_eq!(
+            code(position(&var("(xs.len() as i32)", Sign::NonNegative), &len)),
+            "xs . len ()"
+        );
+        assert_eq!(
+            code(position(&var("i", Sign::NonNegative), &len)),
+            "i as usize"
+        );
+        assert_eq!(
+            code(position(&var("i", Sign::Signed), &len)),
+            "if i < 0 { xs . len () . wrapping_sub (i . unsigned_abs () as usize) } else { i as usize }"
+        );
+        let xs: syn::Expr = parse_quote! { xs };
+        assert_eq!(code(get(&xs, &parse_quote! { 0 }, false)), "xs . first ()");
+        assert_eq!(
+            code(get(&xs, &parse_quote! { i }, true)),
+            "xs . get_mut (i)"
+        );
+        assert!(!needs_len(&var("i", Sign::NonNegative)));
+        assert!(needs_len(&Bound::Literal(-1)));
+    }
+
+    #[test]
+    fn test_slices() {
+        let xs: syn::Expr = parse_quote! { xs };
+        let s: syn::Expr = parse_quote! { s };
+        let slice =
+            |base, items, start, stop, step| code(slice(base, items, start, stop, step).unwrap());
+        assert_eq!(
+            slice(
+                &xs,
+                Items::Copied,
+                Some(Bound::Literal(1)),
+                Some(Bound::Literal(3)),
+                1
+            ),
+            "xs . iter () . take (3) . skip (1) . copied () . collect :: < Vec < _ > > ()"
+        );
+        assert_eq!(
+            slice(&xs, Items::Cloned, None, Some(Bound::Literal(-1)), 2),
+            "xs . iter () . take (xs . len () . saturating_sub (1)) . step_by (2) . cloned () . collect :: < Vec < _ > > ()"
+        );
+        assert_eq!(
+            slice(&s, Items::Chars, None, None, -1),
+            "s . chars () . rev () . collect :: < String > ()"
+        );
+        assert_eq!(
+            slice(&s, Items::Chars, Some(Bound::Literal(3)), None, -1),
+            "s . chars () . collect :: < Vec < char > > () . into_iter () . take (4) . rev () . collect :: < String > ()"
+        );
+        assert_eq!(slice(&s, Items::Chars, None, None, 1), "s . to_string ()");
+        assert!(super::slice(&xs, Items::Copied, None, None, 0).is_err());
+    }
+
+    #[test]
+    fn test_slices_by_variable_steps() {
+        let output = crate::test_programs::run(
+            r#"
+from typing import List
+
+def part(xs: List[int], a: int, b: int, k: int) -> List[int]:
+    return xs[a:b:k]
+
+def every(s: str, k: int) -> str:
+    return s[::k]
+"#,
+            r#"
+let xs: Vec<i32> = (0..10).collect();
+for (a, b, k) in [(0, 10, 3), (8, 1, -2), (-3, 100, 1), (100, -100, -3), (-1, -11, -1), (2, 2, 1)] {
+    println!("{:?}", part(&xs, a, b, k));
+}
+println!("{} {}", every("abcdef", 2), every("abcdef", -2));
+"#,
+        );
+        assert_eq!(
+            output,
+            "[0, 3, 6, 9]\n[8, 6, 4, 2]\n[7, 8, 9]\n[9, 6, 3, 0]\n\
+             [9, 8, 7, 6, 5, 4, 3, 2, 1, 0]\n[]\nace fdb\n"
+        );
+    }
+
+    #[test]
+    fn test_lengths_are_ints() {
+        let output = crate::test_programs::run(
+            r#"
+from typing import List
+
+def binary_search(arr: List[int], target: int) -> int:
+    left: int = 0
+    right: int = len(arr) - 1
+    while left <= right:
+        mid: int = (left + right) // 2
+        if arr[mid] == target:
+            return mid
+        elif arr[mid] < target:
+            left = mid + 1
+        else:
+            right = mid - 1
+    return -1
+
+def weighted(xs: List[int]) -> int:
+    total = 0
+    for i in range(len(xs)):
+        total = total + xs[i] * i - len(xs)
+    return total
+"#,
+            r#"
+println!("{} {} {}", binary_search(&[1, 3, 5, 7], 7), binary_search(&[1, 3], 2), binary_search(&[], 2));
+println!("{}", weighted(&[4, 5, 6]));
+"#,
+        );
+        assert_eq!(output, "3 -1 -1\n8\n");
+    }
+}
@@ -717,6 +717,14 @@ where
     pub fn get_error_mapping(&self, pattern: &PythonErrorPattern) -> Option<&LambdaErrorMapping> {
         self.error_mappings.get(pattern)
     }
+
+    /// Get the mapping registered for a Python exception class, in any context
+    pub fn mapping_for_exception(&self, error_type: &str) -> Option<&LambdaErrorMapping> {
+        self.error_mappings
+            .iter()
+            .find(|(pattern, _)| pattern.error_type == error_type)
+            .map(|(_, mapping)| mapping)
+    }
 }
 
 impl fmt::Display for PythonErrorPattern {
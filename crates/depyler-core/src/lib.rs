@@ -1,22 +1,44 @@
 pub mod annotation_aware_type_mapper;
+pub mod arguments;
+pub mod arithmetic;
 pub mod ast_bridge;
+pub mod bigint;
+pub mod borrowing;
+pub mod builtins;
+pub mod closures;
 pub mod codegen;
+pub mod context_managers;
 pub mod direct_rules;
 pub mod error;
+pub mod exceptions;
+pub mod fallback;
+pub mod generators;
 pub mod hir;
+pub mod indexing;
 pub mod lambda_codegen;
 pub mod lambda_errors;
 pub mod lambda_inference;
 pub mod lambda_optimizer;
 pub mod lambda_testing;
 pub mod lambda_types;
+pub mod mutability;
 pub mod optimization;
+pub mod package;
 pub mod rust_gen;
+pub mod sets;
+pub mod source_map;
+pub mod strings;
+#[cfg(test)]
+mod test_programs;
+pub mod type_enums;
 pub mod type_mapper;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+/// The file name reported for source passed to the pipeline as a string
+const SOURCE_NAME: &str = "<input>";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DepylerPipeline {
     analyzer: CoreAnalyzer,
@@ -26,8 +48,14 @@ pub struct DepylerPipeline {
     #[serde(skip)]
     #[allow(dead_code)]
     mcp_client: LazyMcpClient,
+    #[serde(skip)]
+    type_inference: Option<TypeInferencePass>,
 }
 
+/// A pass that fills in the unannotated types of a module, such as
+/// `depyler_analyzer::inference::infer_types`
+pub type TypeInferencePass = fn(&mut hir::HirModule) -> Result<()>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CoreAnalyzer {
     pub metrics_enabled: bool,
@@ -85,6 +113,7 @@ impl DepylerPipeline {
             },
             verifier: None,
             mcp_client: LazyMcpClient::default(),
+            type_inference: None,
         }
     }
 
@@ -96,22 +125,128 @@ impl DepylerPipeline {
         self
     }
 
+    /// Map Python types to Rust ones with `type_mapper`, such as one for `i64` ints
+    pub fn with_type_mapper(mut self, type_mapper: type_mapper::TypeMapper) -> Self {
+        self.transpiler.type_mapper = type_mapper;
+        self
+    }
+
+    /// Run `pass` over the HIR before code generation
+    pub fn with_type_inference(mut self, pass: TypeInferencePass) -> Self {
+        self.type_inference = Some(pass);
+        self
+    }
+
     pub fn transpile(&self, python_source: &str) -> Result<String> {
+        let options = rust_gen::FileOptions {
+            python_source: Some(python_source),
+            ..Default::default()
+        };
+        self.optimized_hir(python_source)
+            .and_then(|hir| {
+                rust_gen::generate_rust_file_with_options(
+                    &hir,
+                    &self.transpiler.type_mapper,
+                    options,
+                )
+            })
+            .map(|file| file.code)
+            .map_err(|err| error::locate(err, SOURCE_NAME, python_source))
+    }
+
+    /// Transpile, mapping the generated lines back to `python_source`. With
+    /// `line_comments`, statements also end in a `// py:LINE` comment.
+    pub fn transpile_with_source_map(
+        &self,
+        python_source: &str,
+        line_comments: bool,
+    ) -> Result<(String, source_map::SourceMap)> {
+        let (rust_code, mut map) = self
+            .optimized_hir(python_source)
+            .and_then(|hir| {
+                rust_gen::generate_rust_file_with_source_map(
+                    &hir,
+                    &self.transpiler.type_mapper,
+                    python_source,
+                    line_comments,
+                )
+            })
+            .map_err(|err| error::locate(err, SOURCE_NAME, python_source))?;
+        map.python_file = SOURCE_NAME.to_string();
+        Ok((rust_code, map))
+    }
+
+    /// Transpile as `options` ask, which may combine a source map with
+    /// best-effort stubs; their `python_source` is `python_source`
+    pub fn transpile_with_options(
+        &self,
+        python_source: &str,
+        options: rust_gen::FileOptions,
+    ) -> Result<rust_gen::GeneratedFile> {
+        let options = rust_gen::FileOptions {
+            python_source: Some(python_source),
+            ..options
+        };
+        let mut file = self
+            .optimized_hir_with(python_source, options.best_effort)
+            .and_then(|hir| {
+                rust_gen::generate_rust_file_with_options(
+                    &hir,
+                    &self.transpiler.type_mapper,
+                    options,
+                )
+            })
+            .map_err(|err| error::locate(err, SOURCE_NAME, python_source))?;
+        file.source_map.python_file = SOURCE_NAME.to_string();
+        Ok(file)
+    }
+
+    /// Transpile, stubbing out each function that fails to convert with
+    /// its signature and a `todo!()` body, as if annotated
+    /// `fallback = "manual"`. Returns the constructs left unsupported.
+    pub fn transpile_best_effort(
+        &self,
+        python_source: &str,
+    ) -> Result<(String, Vec<fallback::SkippedConstruct>)> {
+        self.optimized_hir_with(python_source, true)
+            .and_then(|hir| {
+                rust_gen::generate_rust_file_best_effort(
+                    &hir,
+                    &self.transpiler.type_mapper,
+                    python_source,
+                )
+            })
+            .map_err(|err| error::locate(err, SOURCE_NAME, python_source))
+    }
+
+    /// Transpile each module of `package` into a module of a Cargo crate,
+    /// resolving the imports between them
+    pub fn transpile_package(&self, package: &package::Package) -> Result<package::CrateProject> {
+        package::transpile(self, package)
+    }
+
+    fn optimized_hir(&self, python_source: &str) -> Result<hir::HirModule> {
+        self.optimized_hir_with(python_source, false)
+    }
+
+    fn optimized_hir_with(&self, python_source: &str, best_effort: bool) -> Result<hir::HirModule> {
         // Parse Python source
         let ast = self.parse_python(python_source)?;
 
         // Convert to HIR with annotation support
-        let mut hir = ast_bridge::AstBridge::new()
-            .with_source(python_source.to_string())
-            .python_to_hir(ast)?;
+        let mut bridge = ast_bridge::AstBridge::new().with_source(python_source.to_string());
+        if best_effort {
+            bridge = bridge.with_best_effort();
+        }
+        let mut hir = bridge.python_to_hir(ast)?;
+
+        // Fill in types the source leaves unannotated
+        self.infer_types(&mut hir)?;
 
         // Apply optimization passes based on annotations
         optimization::optimize_module(&mut hir);
 
-        // Generate Rust code using the unified generation system
-        let rust_code = rust_gen::generate_rust_file(&hir, &self.transpiler.type_mapper)?;
-
-        Ok(rust_code)
+        Ok(hir)
     }
 
     pub fn parse_to_hir(&self, source: &str) -> Result<hir::HirModule> {
@@ -119,20 +254,34 @@ impl DepylerPipeline {
         ast_bridge::AstBridge::new()
             .with_source(source.to_string())
             .python_to_hir(ast)
+            .map_err(|err| error::locate(err, SOURCE_NAME, source))
     }
 
     pub fn analyze_to_typed_hir(&self, source: &str) -> Result<hir::HirModule> {
-        // For now, just return the HIR without type analysis
-        // In the future, this would add type inference
-        self.parse_to_hir(source)
+        let mut hir = self.parse_to_hir(source)?;
+        self.infer_types(&mut hir)
+            .map_err(|err| error::locate(err, SOURCE_NAME, source))?;
+        Ok(hir)
+    }
+
+    fn infer_types(&self, hir: &mut hir::HirModule) -> Result<()> {
+        match self.type_inference {
+            Some(pass) if self.analyzer.type_inference_enabled => pass(hir),
+            _ => Ok(()),
+        }
     }
 
     pub fn parse_python(&self, source: &str) -> Result<rustpython_ast::Mod> {
         use rustpython_ast::Suite;
         use rustpython_parser::Parse;
 
-        let statements = Suite::parse(source, "<input>")
-            .map_err(|e| anyhow::anyhow!("Python parse error: {}", e))?;
+        let statements = Suite::parse(source, SOURCE_NAME).map_err(|e| {
+            let offset = u32::from(e.offset);
+            let err = error::TranspileError::new(error::ErrorKind::ParseError)
+                .with_span(hir::Span::new(offset, offset + 1))
+                .with_context(e.error.to_string());
+            error::locate(err.into(), SOURCE_NAME, source)
+        })?;
 
         Ok(rustpython_ast::Mod::Module(rustpython_ast::ModModule {
             body: statements,
@@ -219,6 +368,508 @@ def add(a: int, b: int) -> int:
         assert!(rust_code.contains("i32"));
     }
 
+    #[test]
+    fn test_class_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+class Account:
+    def __init__(self, owner: str, balance: int):
+        self.owner = owner
+        self.balance = balance
+
+    def deposit(self, amount: int) -> None:
+        self.balance = self.balance + amount
+
+    def get_balance(self) -> int:
+        return self.balance
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("pub struct Account"));
+        assert!(rust_code.contains("impl Account"));
+        assert!(rust_code.contains("pub fn new(owner: String, balance: i32)"));
+        assert!(rust_code.contains("pub fn deposit(&mut self, amount: i32)"));
+        assert!(rust_code.contains("pub fn get_balance(&self)"));
+    }
+
+    #[test]
+    fn test_exception_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+def safe_divide(a: int, b: int) -> int:
+    if b == 0:
+        raise ZeroDivisionError("division by zero")
+    return a // b
+
+def average(total: int, count: int) -> int:
+    return safe_divide(total, count)
+
+def average_or_zero(total: int, count: int) -> int:
+    try:
+        return safe_divide(total, count)
+    except ZeroDivisionError:
+        return 0
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("pub enum PythonError"));
+        assert!(rust_code.contains("ZeroDivisionError(String)"));
+        assert!(rust_code.contains("Result<i32, PythonError>"));
+        // The call's `Result` is returned as it is
+        assert!(rust_code.contains("PythonError>{\n    safe_divide(total, count)\n}"));
+        assert!(rust_code.contains("pub fn average_or_zero(total: i32, count: i32)  -> i32"));
+    }
+
+    #[test]
+    fn test_generator_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import Iterator, List
+
+def evens(items: List[int]) -> Iterator[int]:
+    for x in items:
+        if x % 2 == 0:
+            yield x
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("pub struct EvensGenerator"));
+        assert!(rust_code.contains("impl Iterator for EvensGenerator"));
+        assert!(rust_code.contains("type Item = i32;"));
+        assert!(rust_code.contains("-> EvensGenerator"));
+    }
+
+    #[test]
+    fn test_async_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+import asyncio
+
+async def double(n: int) -> int:
+    await asyncio.sleep(0.1)
+    return n * 2
+
+async def main():
+    value = await double(21)
+
+asyncio.run(main())
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("pub async fn double(n: i32)"));
+        assert!(rust_code.contains("tokio::time::sleep"));
+        assert!(rust_code.contains("double(21).await"));
+        assert!(rust_code.contains("#[tokio::main]"));
+        assert!(rust_code.contains("pub async fn main"));
+    }
+
+    #[test]
+    fn test_context_manager_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+class Transaction:
+    def __init__(self, name: str):
+        self.name = name
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exc_type, exc_value, traceback) -> None:
+        self.name = ""
+
+def load(path: str) -> str:
+    with open(path) as f:
+        text = f.read()
+    return text
+
+def commit() -> None:
+    with Transaction("t") as tx:
+        tx.name = "done"
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("std::fs::File::open(& path)"));
+        assert!(rust_code.contains("std::io::read_to_string(& mut f)"));
+        assert!(rust_code.contains("pub struct TransactionGuard(pub Transaction)"));
+        assert!(rust_code.contains("impl Drop for TransactionGuard"));
+        assert!(rust_code.contains("let mut tx = TransactionGuard(Transaction::new"));
+        assert!(!rust_code.contains("__enter__"));
+    }
+
+    #[test]
+    fn test_match_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from enum import Enum
+from dataclasses import dataclass
+from typing import List
+
+class Shape(Enum):
+    CIRCLE = 1
+    SQUARE = 2
+
+@dataclass
+class Point:
+    x: int
+    y: int
+
+def sides(shape: Shape) -> int:
+    match shape:
+        case Shape.CIRCLE:
+            return 0
+        case Shape.SQUARE:
+            return 4
+
+def locate(p: Point) -> str:
+    match p:
+        case Point(0, 0):
+            return "origin"
+        case Point(x, y) if x == y:
+            return "diagonal"
+        case _:
+            return "elsewhere"
+
+def total(items: List[int]) -> int:
+    match items:
+        case []:
+            return 0
+        case [first, *rest]:
+            return first
+    return -1
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("pub enum Shape"));
+        assert!(rust_code.contains("CIRCLE = 1, SQUARE = 2"));
+        assert!(rust_code.contains("Shape::CIRCLE =>"));
+        assert!(rust_code.contains("match items.as_slice()"));
+        assert!(rust_code.contains("[first, rest @..]"));
+        assert!(rust_code.contains("(* x) == (* y)"));
+
+        let non_exhaustive = r#"
+from enum import Enum
+
+class Shape(Enum):
+    CIRCLE = 1
+    SQUARE = 2
+
+def sides(shape: Shape) -> int:
+    match shape:
+        case Shape.CIRCLE:
+            return 0
+    return 4
+"#;
+        let err = pipeline.transpile(non_exhaustive).unwrap_err();
+        assert!(err.to_string().contains("SQUARE not covered"));
+    }
+
+    #[test]
+    fn test_assign_target_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import Dict, List
+
+class Pair:
+    def __init__(self, first: int, second: int):
+        self.first, self.second = first, second
+
+    def swap(self) -> None:
+        self.first, self.second = self.second, self.first
+
+def fib(n: int) -> int:
+    a, b = 0, 1
+    for i in range(n):
+        a, b = b, a + b
+    return a
+
+def tally(words: List[str]) -> Dict[str, int]:
+    counts = {}
+    for w in words:
+        counts[w] = counts.get(w, 0) + 1
+    return counts
+
+def total(d: Dict[str, int]) -> int:
+    low = high = 0
+    for k, v in d.items():
+        high += v
+    return high - low
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("pub fn swap(&mut self)"));
+        assert!(rust_code.contains("(self.first, self.second)"));
+        assert!(rust_code.contains("let(mut a, mut b)"));
+        assert!(rust_code.contains("counts.insert(w.clone()"));
+        // `k` is never read
+        assert!(rust_code.contains("for(_k, v) in d.iter()"));
+        // Only `high` is assigned again
+        assert!(rust_code.contains("let low: i32 = 0;"));
+        assert!(rust_code.contains("let mut high: i32 = 0;"));
+    }
+
+    #[test]
+    fn test_idiomatic_tails_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import Dict, List
+
+def describe(n: int) -> str:
+    match n:
+        case 0:
+            return "zero"
+        case _:
+            return "many"
+
+def doubled(d: Dict[int, float]) -> List[float]:
+    out = []
+    for k, v in d.items():
+        out.append(v * 2.0)
+    return out
+
+def square(n: int) -> int:
+    result = n * n
+    return result
+
+def build() -> List[int]:
+    xs = []
+    xs.append(1)
+    xs.append(2)
+    return xs
+
+def apply(n: int) -> int:
+    f = lambda x: x + n
+    return f(2)
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        // Exhaustive match arms are the function's tail
+        assert!(!rust_code.contains("return"));
+        assert!(rust_code.contains("\"zero\".to_string()"));
+        // Copy entries are copied out of the dict
+        assert!(rust_code.contains("for(_k, v) in d.iter().map(|(k, v) |(* k, * v))"));
+        assert!(rust_code.contains("n * n\n}"));
+        assert!(rust_code.contains("vec ! [1, 2]"));
+        assert!(rust_code.contains("let f = | x | x + n;"));
+    }
+
+    #[test]
+    fn test_keyword_argument_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import List
+
+def scale(values: List[int], factor: int = 2, *, offset: int = 0) -> List[int]:
+    return [v * factor + offset for v in values]
+
+def total(*numbers: int) -> int:
+    return sum(numbers)
+
+# @depyler: kwargs_strategy = "struct"
+def label(text: str, **options: str) -> str:
+    return options.get("color", text)
+
+class Counter:
+    def __init__(self, start: int = 0):
+        self.count = start
+
+def demo() -> int:
+    xs = scale([1, 2], offset=1)
+    c = Counter(start=3)
+    name = label("x", color="red")
+    return total(1, 2, 3)
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("scale(& [1, 2], 2, 1)"));
+        assert!(rust_code.contains("pub fn total(numbers: Vec<i32>)"));
+        assert!(rust_code.contains("total(vec ! [1, 2, 3])"));
+        assert!(rust_code.contains("Counter::new(3)"));
+        assert!(rust_code.contains("pub struct LabelKwargs"));
+        assert!(rust_code.contains("pub color: Option<String>"));
+        assert!(rust_code.contains("options.color.clone().unwrap_or(text)"));
+
+        let dropped = "def f(xs: list) -> None:\n    print(xs, flush=True)\n";
+        assert!(pipeline.transpile(dropped).is_err());
+    }
+
+    #[test]
+    fn test_builtin_lowering() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import List
+
+def report(xs: List[int], ys: List[float], flag: bool) -> bool:
+    for i, x in enumerate(xs):
+        print("item", i, x, flag)
+    print(sum(xs), max(ys), sep="; ")
+    return isinstance(flag, int)
+
+def abs(x: int) -> int:
+    return x
+
+def magnitude(x: int) -> int:
+    return abs(x)
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("xs.iter().cloned().enumerate()"));
+        assert!(rust_code.contains("println !(\"item {i} {x} {}\", if flag"));
+        assert!(rust_code.contains("xs.into_iter().sum::<i32>()"));
+        assert!(rust_code.contains("ys.iter().cloned().reduce(f64::max)"));
+        assert!(rust_code.contains("ys: &[f64]"));
+        // A module function shadows the builtin
+        assert!(rust_code.contains("    abs(x)"));
+
+        let empty = "from typing import List\n\ndef f(xs: List[int]) -> int:\n    return min(xs)\n";
+        let rust_code = pipeline.transpile(empty).unwrap();
+        assert!(rust_code.contains(".min().expect(\"min() arg is an empty sequence\")"));
+
+        let unknown = "def f(x) -> bool:\n    return isinstance(x, int)\n";
+        assert!(pipeline.transpile(unknown).is_err());
+
+        let narrowed = "from typing import Union\n\n\
+            def f(x: Union[int, str]) -> str:\n    if isinstance(x, int):\n        return str(x + 1)\n    return x\n";
+        let rust_code = pipeline.transpile(narrowed).unwrap();
+        assert!(rust_code.contains("    match x {"));
+        assert!(rust_code.contains("IntOrStr::Int(x) =>"));
+        assert!(rust_code.contains("IntOrStr::Str(x) =>x,"));
+    }
+
+    #[test]
+    fn test_string_formatting_and_methods() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import List
+
+def row(name: str, score: float, width: int) -> str:
+    return f"{name:>{width}}: {score:.2f}"
+
+def legacy(name: str, n: int) -> str:
+    return "%-8s|%03d" % (name, n) + "{} {n}".format(name, n=n)
+
+def words(line: str) -> List[str]:
+    total = 0
+    for word in line.split(","):
+        total += word.count("a")
+    print(f"{total} in {line!r}", sep="")
+    return line.strip().split()
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("format !(\"{name:>width$}: {score:.2}\", width"));
+        assert!(rust_code.contains("format !(\"{name:<8}|{n:03}\")"));
+        assert!(rust_code.contains("format !(\"{name} {n}\")"));
+        assert!(rust_code.contains("word.matches(\"a\").count() as i32"));
+        assert!(rust_code.contains("println !(\"{total} in {}\", py_repr(& line))"));
+        assert!(rust_code.contains("fn py_repr(s: &str)  -> String"));
+        assert!(rust_code.contains("split_whitespace()"));
+
+        let grouped = "def f(n: int) -> str:\n    return f\"{n:,}\"\n";
+        assert!(pipeline.transpile(grouped).is_err());
+    }
+
+    #[test]
+    fn test_slicing_and_negative_indexing() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import Dict, List
+
+def ends(xs: List[int], s: str) -> str:
+    middle = xs[1:3]
+    return s[::-1] + str(xs[-1] + middle[0])
+
+# @depyler: verify_bounds = true
+def checked(xs: List[int], i: int) -> int:
+    return xs[i]
+
+# @depyler: verify_bounds = true
+def lookup(d: Dict[str, int], k: str) -> int:
+    return d[k]
+
+# @depyler: bounds_checking = "disabled"
+def lenient(xs: List[int]) -> int:
+    return xs[5]
+
+# @depyler: bounds_checking = "implicit"
+def native(xs: List[int]) -> int:
+    return xs[-2]
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("xs.iter().take(3).skip(1).copied().collect::<Vec<_>>()"));
+        assert!(rust_code.contains("s.chars().rev().collect::<String>()"));
+        assert!(rust_code.contains(
+            "xs.get(xs.len().wrapping_sub(1)).copied().expect(\"list index out of range\")"
+        ));
+        assert!(rust_code.contains("-> Result<i32, PythonError>"));
+        // Indexing a dict raises `KeyError`, not `IndexError`
+        assert!(rust_code.contains("k: String)  -> i32"));
+        assert!(
+            rust_code.contains("PythonError::IndexError(\"list index out of range\".to_string())")
+        );
+        assert!(rust_code.contains("xs.get(5).copied().unwrap_or_default()"));
+        assert!(rust_code.contains("xs [xs.len().wrapping_sub(2)]"));
+
+        let zero_step = "def f(s: str) -> str:\n    return s[::0]\n";
+        assert!(pipeline.transpile(zero_step).is_err());
+    }
+
+    #[test]
+    fn test_python_integer_semantics() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+def arith(a: int, b: int, x: float) -> float:
+    q = a // b + a % 2 + a // 4
+    return q / b + x ** 2 + x % b + b ** 3
+
+# @depyler: integer_overflow = "wrapping"
+def step(h: int, c: int) -> int:
+    return h * 31 + c
+
+# @depyler: integer_overflow = "checked"
+def square(n: int) -> int:
+    return n * n
+
+# @depyler: integer_overflow = "return_error"
+def cube(n: int) -> int:
+    return n ** 3
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("fn py_floor_div(a: i32, b: i32)  -> i32"));
+        assert!(rust_code.contains("py_floor_div(a, b) + a.rem_euclid(2)"));
+        assert!(rust_code.contains("a.div_euclid(4)"));
+        assert!(rust_code.contains("q as f64 / b as f64"));
+        assert!(rust_code.contains("x.powi(2)"));
+        assert!(rust_code.contains("py_fmod(x, b as f64)"));
+        assert!(rust_code.contains("b.pow(3)"));
+        assert!(rust_code.contains("h.wrapping_mul(31).wrapping_add(c)"));
+        assert!(rust_code.contains("n.checked_mul(n).expect(\"integer overflow\")"));
+        assert!(rust_code.contains("OverflowError(String)"));
+        assert!(rust_code.contains("pub fn cube(n: i32)  -> Result<i32, PythonError>"));
+        assert!(rust_code.contains("n.checked_pow(3).ok_or_else("));
+
+        let wide =
+            DepylerPipeline::new().with_type_mapper(type_mapper::TypeMapper::new().with_i64());
+        let rust_code = wide
+            .transpile("def f(a: int) -> int:\n    n = 2\n    return n ** a - a // -3\n")
+            .unwrap();
+        assert!(rust_code.contains("let n: i64 = 2;"));
+        assert!(rust_code.contains("fn py_floor_div(a: i64, b: i64)  -> i64"));
+        assert!(rust_code.contains(
+            "n.pow(u32::try_from(a).expect(\"Integers to negative integer powers are not allowed.\"))"
+        ));
+
+        // Checked arithmetic panics, so the function is not panic-free
+        let checked =
+            "# @depyler: integer_overflow = \"checked\"\ndef neg(n: int) -> int:\n    return -n\n";
+        let hir = pipeline.parse_to_hir(checked).unwrap();
+        assert!(!hir.functions[0].properties.panic_free);
+        let rust_code = pipeline.transpile(checked).unwrap();
+        assert!(rust_code.contains("n.checked_neg().expect(\"integer overflow\")"));
+        assert!(!rust_code.contains("panic-free"));
+    }
+
     #[test]
     fn test_parse_to_hir() {
         let pipeline = DepylerPipeline::new();
@@ -416,4 +1067,387 @@ def create_map() -> Dict[str, int]:
             depyler_annotations::HashStrategy::Fnv
         );
     }
+
+    #[test]
+    fn test_bigint_integer_type() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+# @depyler: integer_type = "bigint"
+
+def factorial(n: int) -> int:
+    result = 1
+    for i in range(2, n + 1):
+        result *= i
+    return result
+
+def bucket() -> int:
+    h = 0
+    for x in range(10):
+        h = (h * 31 + x) % 1000003
+    return h
+
+# @depyler: integer_type = "native"
+def small(n: int) -> int:
+    return n + 1
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("use num_bigint::BigInt;"));
+        assert!(rust_code.contains("pub fn factorial(n: BigInt)  -> BigInt"));
+        assert!(rust_code.contains("let mut result = BigInt::from(1);"));
+        assert!(rust_code.contains("2..i64::try_from(& n + BigInt::from(1))"));
+        assert!(rust_code.contains("result *= BigInt::from(i);"));
+        // A hash kept below its modulus stays an `i64`
+        assert!(rust_code.contains("let mut h: i64 = 0;"));
+        assert!(rust_code.contains("((h * 31) + x) % 1000003"));
+        assert!(rust_code.contains("BigInt::from(h)"));
+        assert!(rust_code.contains("pub fn small(n: i32)  -> i32"));
+    }
+
+    #[test]
+    fn test_set_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import List, Set, FrozenSet
+
+def common(a: Set[int], b: Set[int]) -> Set[int]:
+    return (a | b) - (a & b)
+
+def seen(words: List[str], word: str) -> bool:
+    s = {"x", "y"}
+    s.add(word)
+    s.discard("x")
+    t = s.union(words)
+    return word in t
+
+def groups() -> Set[FrozenSet[int]]:
+    return {frozenset({1, 2})}
+
+# @depyler: hash_strategy = "fnv"
+def unique(xs: List[int]) -> Set[int]:
+    return set(xs)
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("use std::collections::HashSet;"));
+        assert!(rust_code.contains("use fnv::FnvHashSet;"));
+        assert!(rust_code.contains("pub fn common(a: &HashSet<i32>, b: &HashSet<i32>)"));
+        assert!(rust_code.contains("&(a | b) - &(a & b)"));
+        assert!(rust_code.contains("HashSet::from([\"x\".to_string(), \"y\".to_string()])"));
+        assert!(rust_code.contains("s.insert(word.clone())"));
+        assert!(rust_code.contains("s.remove(\"x\")"));
+        assert!(rust_code.contains("& s | & words.iter().cloned().collect::<HashSet<_>>()"));
+        assert!(rust_code.contains("t.contains(& word)"));
+        assert!(rust_code.contains("-> HashSet<BTreeSet<i32>>"));
+        assert!(rust_code.contains("HashSet::from([BTreeSet::from([1, 2])])"));
+        assert!(rust_code.contains("pub fn unique(xs: Vec<i32>)  -> FnvHashSet<i32>"));
+        assert!(rust_code.contains("xs.into_iter().collect::<FnvHashSet<_>>()"));
+    }
+
+    #[test]
+    fn test_rich_typing_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import Union, Literal, Sequence, Tuple
+
+def describe(value: Union[int, str]) -> str:
+    return f"value {value}"
+
+def parse(text: str, strict: bool) -> int | str:
+    if strict:
+        return 0
+    return text
+
+def is_write(mode: Literal["r", "w"]) -> bool:
+    return mode == "w"
+
+def total(values: Sequence[int], pair: Tuple[int, ...]) -> int:
+    return 0
+
+def main() -> None:
+    print(describe(3))
+    print(is_write("w"))
+"#;
+
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("pub enum IntOrStr"));
+        assert!(rust_code.contains("Int(i32), Str(String)"));
+        assert!(rust_code.contains("impl From<String>for IntOrStr"));
+        assert!(rust_code.contains("pub enum ROrW"));
+        assert!(rust_code.contains("format !(\"value {value}\")"));
+        assert!(rust_code.contains("return IntOrStr::Int(0);"));
+        assert!(rust_code.contains("IntOrStr::Str(text)"));
+        assert!(rust_code.contains("mode == ROrW::W"));
+        assert!(rust_code.contains("pub fn total(values: &[i32], pair: &[i32])"));
+        assert!(rust_code.contains("describe(IntOrStr::Int(3))"));
+        assert!(rust_code.contains("is_write(ROrW::W)"));
+    }
+
+    #[test]
+    fn test_lambda_and_closure_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import Callable, List
+
+def by_length(words: List[str]) -> List[str]:
+    return sorted(words, key=lambda w: len(w), reverse=True)
+
+def make_adder(n: int) -> Callable[[int], int]:
+    return lambda x: x + n
+
+def apply(f: Callable[[int], int], x: int) -> int:
+    return f(x)
+
+def counter() -> int:
+    count = 0
+    def bump():
+        nonlocal count
+        count = count + 1
+    bump()
+    return count
+"#;
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("sort_by"));
+        assert!(rust_code.contains("__kb.partial_cmp(__ka)"));
+        assert!(rust_code.contains("-> impl Fn(i32)  -> i32"));
+        assert!(rust_code.contains("move | x |"));
+        assert!(rust_code.contains("f: impl Fn(i32)  -> i32"));
+        // Rebinding a `nonlocal` makes the closure `FnMut`
+        assert!(rust_code.contains("let mut bump = | |"));
+    }
+
+    #[test]
+    fn test_borrow_inference_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import List
+
+def total(xs: List[int]) -> int:
+    t = 0
+    for x in xs:
+        t = t + x
+    return t
+
+def is_root(name: str) -> bool:
+    return name == "root"
+
+def first(a: str, b: str) -> str:
+    return a
+
+def normalize(s: str) -> str:
+    if s == "x":
+        s = s.upper()
+    return s
+
+def demo(word: str) -> int:
+    ok = is_root(word)
+    f = first(word, "y")
+    n = normalize("abc")
+    return total([1, 2])
+"#;
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("pub fn total(xs: &[i32])"));
+        assert!(rust_code.contains("for x in xs.iter().cloned()"));
+        assert!(rust_code.contains("pub fn is_root(name: &str)"));
+        assert!(rust_code.contains("pub fn first<'a>(a: &'a str, b: &str)  -> &'a str"));
+        assert!(rust_code.contains("pub fn normalize(mut s: Cow<'_, str>)"));
+        assert!(rust_code.contains("s = Cow::Owned(s.to_uppercase())"));
+        assert!(rust_code.contains("use std::borrow::Cow;"));
+        // Call sites lend their arguments
+        assert!(rust_code.contains("is_root(word)"));
+        assert!(rust_code.contains("first(word, \"y\").to_string()"));
+        assert!(rust_code.contains("normalize(Cow::Borrowed(\"abc\"))"));
+        assert!(rust_code.contains("total(& [1, 2])"));
+    }
+
+    #[test]
+    fn test_mutability_and_move_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import List
+
+def tally(xs: List[int], ys: List[int]) -> List[int]:
+    t = 0
+    n = len(xs)
+    out = []
+    for x in xs:
+        t = t + x
+        out.append(t * n)
+    for y in sorted(ys):
+        out.append(y)
+    return out
+
+def bump(counts: List[int]) -> None:
+    counts.append(1)
+"#;
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("let mut t: i32 = 0;"));
+        assert!(rust_code.contains("let n = xs.len() as i32;"));
+        assert!(rust_code.contains("let mut out = vec ! [];"));
+        assert!(rust_code.contains("t += x;"));
+        assert!(rust_code.contains("out.push(t * n);"));
+        // `ys` is not read again, so `sorted` takes it rather than a clone
+        assert!(rust_code.contains("ys.into_iter()"));
+        assert!(!rust_code.contains(".clone()"));
+        // The returned value is the body's tail expression
+        assert!(rust_code.contains("\nout\n}"));
+        assert!(rust_code.contains("pub fn bump(mut counts: Vec<i32>)"));
+    }
+
+    #[test]
+    fn test_errors_carry_source_locations() {
+        let pipeline = DepylerPipeline::new();
+        let located = |source: &str| {
+            let err = pipeline.transpile(source).unwrap_err();
+            let err = err.downcast::<error::TranspileError>().unwrap();
+            let loc = err.location.expect("error has a location");
+            (loc.line, loc.column)
+        };
+
+        // Parse errors, conversion errors and codegen errors alike
+        assert_eq!(located("def f(x):\n    return x +\n"), (2, 15));
+        assert_eq!(
+            located("def f(x: int) -> int:\n    return x @ 2\n"),
+            (2, 12)
+        );
+        let non_exhaustive = r#"
+from enum import Enum
+
+class Shape(Enum):
+    CIRCLE = 1
+    SQUARE = 2
+
+def sides(shape: Shape) -> int:
+    match shape:
+        case Shape.CIRCLE:
+            return 0
+    return 4
+"#;
+        assert_eq!(located(non_exhaustive), (9, 5));
+        let bad_call = "def one(x: int) -> int:\n    return x\n\ndef g() -> int:\n    y = 1\n    return one(y, y)\n";
+        assert_eq!(located(bad_call), (6, 5));
+        assert_eq!(
+            located("def f(n: int) -> int:\n    while n > 0:\n        break\n    return n\n"),
+            (3, 9)
+        );
+        let err = pipeline.transpile("def f() -> None:\n    while True:\n        continue\n");
+        assert!(err
+            .unwrap_err()
+            .to_string()
+            .contains("`continue` statement"));
+    }
+
+    #[test]
+    fn test_source_map_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+class Counter:
+    def __init__(self, start: int):
+        self.count = start
+
+    def bump(self) -> int:
+        self.count = self.count + 1
+        return self.count
+
+def total(xs: list[int]) -> int:
+    t = 0
+    for x in xs:
+        if x > 0:
+            t = t + x
+    return t
+"#;
+        let plain = pipeline.transpile(python_code).unwrap();
+        let (mapped, map) = pipeline
+            .transpile_with_source_map(python_code, false)
+            .unwrap();
+        // The source map leaves the code as it is
+        assert_eq!(mapped, plain);
+        assert_eq!(map.python_file, "<input>");
+
+        let rust_line = |text: &str| plain.lines().position(|l| l.contains(text)).unwrap() + 1;
+        let python_line = |text: &str| map.lookup(rust_line(text)).unwrap().line;
+        assert_eq!(python_line("pub fn total"), 10);
+        assert_eq!(python_line("let mut t: i32 = 0;"), 11);
+        assert_eq!(python_line("t += x;"), 14);
+        assert_eq!(python_line("pub fn bump"), 6);
+
+        let (commented, _) = pipeline
+            .transpile_with_source_map(python_code, true)
+            .unwrap();
+        assert!(commented.contains("let mut t: i32 = 0; // py:11"));
+    }
+
+    #[test]
+    fn test_best_effort_transpilation() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+def double(x: int) -> int:
+    return x * 2
+
+def reset(name: str) -> str:
+    global counter
+    return name
+
+def quadruple(x: int) -> int:
+    return double(double(x))
+"#;
+        // One unsupported statement fails the whole module
+        assert!(pipeline.transpile(python_code).is_err());
+
+        let (rust_code, skipped) = pipeline.transpile_best_effort(python_code).unwrap();
+        assert!(rust_code.contains("pub fn double(x: i32)"));
+        assert!(rust_code.contains("pub fn quadruple(x: i32)"));
+        assert!(rust_code.contains("pub fn reset(name: &str)"));
+        assert!(rust_code.contains("    //     global counter\n"));
+        assert!(rust_code.contains("todo!(\"unsupported: `global` statement at line 6\")"));
+
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].function, "reset");
+        assert_eq!(skipped[0].construct, "`global` statement");
+        assert_eq!((skipped[0].line, skipped[0].column), (6, 5));
+    }
+
+    #[test]
+    fn test_best_effort_stubs_methods() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+from typing import Dict
+
+class Counter:
+    def __init__(self) -> None:
+        self.count = 0
+
+    def bump(self, by: int) -> int:
+        global total
+        return by
+
+    def get(self) -> int:
+        return self.count
+
+def table(n: int) -> Dict[int]:
+    return {}
+"#;
+        let (rust_code, skipped) = pipeline.transpile_best_effort(python_code).unwrap();
+        assert!(rust_code.contains("pub fn bump(&self, by: i32)  -> i32 {"));
+        assert!(rust_code.contains("todo!(\"unsupported: `global` statement at line 9\")"));
+        assert!(rust_code.contains("pub fn get(&self)  -> i32"));
+        // A return annotation that does not convert still leaves a value
+        assert!(rust_code.contains("pub fn table(n: i32)  -> serde_json::Value"));
+
+        let names: Vec<&str> = skipped.iter().map(|s| s.function.as_str()).collect();
+        assert_eq!(names, ["Counter.bump", "table"]);
+    }
+
+    #[test]
+    fn test_manual_fallback_stubs_function() {
+        let pipeline = DepylerPipeline::new();
+        let python_code = r#"
+# @depyler: fallback = "manual"
+def reset(name: str) -> str:
+    del name
+    return ""
+"#;
+        let rust_code = pipeline.transpile(python_code).unwrap();
+        assert!(rust_code.contains("todo!(\"unsupported: `del` statement at line 4\")"));
+    }
 }
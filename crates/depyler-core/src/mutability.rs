@@ -0,0 +1,246 @@
+//! Mutability and liveness of the bindings of a function body.
+//!
+//! Code generation declares a binding `mut` only when a later statement of
+//! its block assigns to it or modifies it in place, and moves a collection
+//! instead of cloning it when nothing after the move reads it again.
+
+use crate::hir::*;
+use std::collections::{HashMap, HashSet};
+
+/// What a run of statements does with the bindings it mentions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Effects {
+    /// How many times each name is read
+    reads: HashMap<Symbol, usize>,
+    /// Names assigned to, or modified in place through a method call or a
+    /// subscript or attribute store
+    writes: HashSet<Symbol>,
+}
+
+impl Effects {
+    /// The effects of `body`. `mutating_methods` are the methods of the
+    /// module's classes that modify `self`.
+    pub fn of(body: &[Spanned<HirStmt>], mutating_methods: &HashSet<Symbol>) -> Self {
+        let mut effects = Effects::default();
+        for_each_expr_in(body, &mut |expr| match expr {
+            HirExpr::Var(name) => *effects.reads.entry(name.clone()).or_default() += 1,
+            HirExpr::MethodCall {
+                receiver, method, ..
+            } if is_mutating_method(method) || mutating_methods.contains(method) => {
+                if let Some(name) = root(receiver) {
+                    effects.writes.insert(name.clone());
+                }
+            }
+            _ => {}
+        });
+        effects.add_stores(body, false);
+        effects
+    }
+
+    /// The effects of each statement of `body`, followed by the effects of
+    /// the statements after it
+    pub fn of_each(
+        body: &[Spanned<HirStmt>],
+        mutating_methods: &HashSet<Symbol>,
+    ) -> Vec<(Effects, Effects)> {
+        let mut later = Effects::default();
+        let mut effects: Vec<_> = body
+            .iter()
+            .rev()
+            .map(|stmt| {
+                let current = Effects::of(std::slice::from_ref(stmt), mutating_methods);
+                let after = later.clone();
+                later.merge(&current);
+                (current, after)
+            })
+            .collect();
+        effects.reverse();
+        effects
+    }
+
+    pub fn reads(&self, name: &str) -> usize {
+        self.reads.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn writes(&self, name: &str) -> bool {
+        self.writes.contains(name)
+    }
+
+    pub fn merge(&mut self, other: &Effects) {
+        for (name, count) in &other.reads {
+            *self.reads.entry(name.clone()).or_default() += count;
+        }
+        self.writes.extend(other.writes.iter().cloned());
+    }
+
+    /// Keep only the effects on the names `keep` accepts
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.reads.retain(|name, _| keep(name));
+        self.writes.retain(|name| keep(name));
+    }
+
+    /// Names stored to by assignments. A nested function's own locals are
+    /// not the enclosing function's, but what it rebinds through `nonlocal`
+    /// and the places it stores into are.
+    fn add_stores(&mut self, body: &[Spanned<HirStmt>], nested: bool) {
+        for stmt in body {
+            match &stmt.node {
+                HirStmt::Assign { target, .. } => self.add_target(target, nested),
+                HirStmt::If {
+                    then_body,
+                    else_body,
+                    ..
+                } => {
+                    self.add_stores(then_body, nested);
+                    if let Some(else_body) = else_body {
+                        self.add_stores(else_body, nested);
+                    }
+                }
+                HirStmt::While { body, .. }
+                | HirStmt::For { body, .. }
+                | HirStmt::With { body, .. } => self.add_stores(body, nested),
+                HirStmt::Try {
+                    body,
+                    handlers,
+                    orelse,
+                    finalbody,
+                } => {
+                    self.add_stores(body, nested);
+                    for handler in handlers {
+                        self.add_stores(&handler.body, nested);
+                    }
+                    self.add_stores(orelse, nested);
+                    self.add_stores(finalbody, nested);
+                }
+                HirStmt::Match { cases, .. } => {
+                    for case in cases {
+                        self.add_stores(&case.body, nested);
+                    }
+                }
+                HirStmt::FunctionDef { func, nonlocals } => {
+                    self.writes.extend(nonlocals.iter().cloned());
+                    self.add_stores(&func.body, true);
+                }
+                HirStmt::Return(_)
+                | HirStmt::Expr(_)
+                | HirStmt::Yield(_)
+                | HirStmt::YieldFrom(_)
+                | HirStmt::Raise { .. } => {}
+            }
+        }
+    }
+
+    fn add_target(&mut self, target: &AssignTarget, nested: bool) {
+        match target {
+            AssignTarget::Symbol(name) => {
+                if !nested {
+                    self.writes.insert(name.clone());
+                }
+            }
+            AssignTarget::Attribute { value: base, .. } | AssignTarget::Index { base, .. } => {
+                if let Some(name) = root(base) {
+                    self.writes.insert(name.clone());
+                }
+            }
+            AssignTarget::Tuple(targets) => {
+                for target in targets {
+                    self.add_target(target, nested);
+                }
+            }
+        }
+    }
+}
+
+/// The binding a place expression such as `a.b[0]` belongs to
+fn root(expr: &HirExpr) -> Option<&Symbol> {
+    match expr {
+        HirExpr::Var(name) => Some(name),
+        HirExpr::Attribute { value, .. } => root(value),
+        HirExpr::Index { base, .. } => root(base),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_bridge::AstBridge;
+    use rustpython_ast::Suite;
+    use rustpython_parser::Parse;
+
+    fn body(source: &str) -> Vec<Spanned<HirStmt>> {
+        let statements = Suite::parse(source, "<test>").unwrap();
+        let ast = rustpython_ast::Mod::Module(rustpython_ast::ModModule {
+            body: statements,
+            type_ignores: vec![],
+            range: Default::default(),
+        });
+        let module = AstBridge::new().python_to_hir(ast).unwrap();
+        module.functions[0].body.clone()
+    }
+
+    #[test]
+    fn test_writes() {
+        let body = body(
+            r#"
+def f(a: list, b: dict, c: int, d: list, e: int) -> int:
+    a.append(1)
+    b["k"] = 2
+    c = c + 1
+    total = len(d) + e
+    return total
+"#,
+        );
+        let effects = Effects::of(&body, &HashSet::new());
+        assert!(effects.writes("a"));
+        assert!(effects.writes("b"));
+        assert!(effects.writes("c"));
+        assert!(effects.writes("total"));
+        assert!(!effects.writes("d"));
+        assert!(!effects.writes("e"));
+    }
+
+    #[test]
+    fn test_user_methods_and_nested_functions() {
+        let body = body(
+            r#"
+def f(counter: Counter, items: list, label: str) -> int:
+    counter.bump()
+    count = 0
+    def add(x: int):
+        nonlocal count
+        count = count + x
+        label = "shadowed"
+        items.append(x)
+    add(1)
+    return count
+"#,
+        );
+        let methods = HashSet::from(["bump".to_string()]);
+        let effects = Effects::of(&body, &methods);
+        assert!(effects.writes("counter"));
+        assert!(effects.writes("count"));
+        assert!(effects.writes("items"));
+        // The nested function's `label` is its own local
+        assert!(!effects.writes("label"));
+    }
+
+    #[test]
+    fn test_reads_after_each_statement() {
+        let body = body(
+            r#"
+def f(xs: list) -> int:
+    ys = sorted(xs)
+    n = len(ys) + len(ys)
+    return n
+"#,
+        );
+        let effects = Effects::of_each(&body, &HashSet::new());
+        let (current, later) = &effects[0];
+        assert_eq!(current.reads("xs"), 1);
+        assert_eq!(later.reads("xs"), 0);
+        assert_eq!(later.reads("ys"), 2);
+        assert!(later.writes("n"));
+        assert_eq!(effects[2].1, Effects::default());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::hir::{BinOp, HirExpr, HirFunction, HirStmt};
+use crate::hir::{BinOp, HirExpr, HirFunction, HirStmt, Spanned};
 use depyler_annotations::{OptimizationLevel, PerformanceHint};
 
 /// Performance optimizer that applies transformations based on annotations
@@ -84,13 +84,15 @@ impl PerformanceOptimizer {
                 self.inline_small_functions(&mut func.body);
                 self.vectorize_loops(&mut func.body);
             }
+            // Handled by codegen, which emits the function as `async fn`
+            PerformanceHint::AsyncReady => {}
         }
     }
 
     /// Constant folding optimization
-    fn constant_folding(&mut self, stmts: &mut Vec<HirStmt>) {
+    fn constant_folding(&mut self, stmts: &mut Vec<Spanned<HirStmt>>) {
         for stmt in stmts {
-            match stmt {
+            match &mut stmt.node {
                 HirStmt::Assign { value, .. } => {
                     self.fold_constants_expr(value);
                 }
@@ -160,14 +162,14 @@ impl PerformanceOptimizer {
     }
 
     /// Dead code elimination
-    fn dead_code_elimination(&mut self, stmts: &mut Vec<HirStmt>) {
+    fn dead_code_elimination(&mut self, stmts: &mut Vec<Spanned<HirStmt>>) {
         // Simple DCE: remove statements after unconditional return
         let mut found_return = false;
         stmts.retain(|stmt| {
             if found_return {
                 false
             } else {
-                if matches!(stmt, HirStmt::Return(_)) {
+                if matches!(&stmt.node, HirStmt::Return(_)) {
                     found_return = true;
                 }
                 true
@@ -179,16 +181,16 @@ impl PerformanceOptimizer {
     }
 
     /// Common subexpression elimination
-    fn common_subexpression_elimination(&mut self, _stmts: &mut [HirStmt]) {
+    fn common_subexpression_elimination(&mut self, _stmts: &mut [Spanned<HirStmt>]) {
         // Simplified CSE - would need data flow analysis for real implementation
         self.optimizations_applied
             .push("common_subexpression_elimination".to_string());
     }
 
     /// Strength reduction (e.g., x * 2 -> x << 1)
-    fn strength_reduction(&mut self, stmts: &mut [HirStmt]) {
+    fn strength_reduction(&mut self, stmts: &mut [Spanned<HirStmt>]) {
         for stmt in stmts {
-            match stmt {
+            match &mut stmt.node {
                 HirStmt::Assign { value, .. } => {
                     self.reduce_strength_expr(value);
                 }
@@ -236,9 +238,9 @@ impl PerformanceOptimizer {
     }
 
     /// Loop unrolling
-    fn loop_unrolling(&mut self, stmts: &mut Vec<HirStmt>, factor: usize) {
+    fn loop_unrolling(&mut self, stmts: &mut Vec<Spanned<HirStmt>>, factor: usize) {
         for stmt in stmts {
-            if let HirStmt::For { body, .. } = stmt {
+            if let HirStmt::For { body, .. } = &mut stmt.node {
                 // Simple unrolling - duplicate loop body
                 let original_body = body.clone();
                 for _ in 1..factor {
@@ -252,35 +254,35 @@ impl PerformanceOptimizer {
     }
 
     /// Vectorization for SIMD operations
-    fn vectorize_loops(&mut self, _stmts: &mut [HirStmt]) {
+    fn vectorize_loops(&mut self, _stmts: &mut [Spanned<HirStmt>]) {
         // Simplified vectorization - would need pattern matching for real implementation
         self.optimizations_applied
             .push("vectorize_loops".to_string());
     }
 
     /// Inline small functions
-    fn inline_small_functions(&mut self, _stmts: &mut [HirStmt]) {
+    fn inline_small_functions(&mut self, _stmts: &mut [Spanned<HirStmt>]) {
         // Simplified inlining - would need call graph analysis
         self.optimizations_applied
             .push("inline_small_functions".to_string());
     }
 
     /// Remove bounds checks (unsafe optimization)
-    fn remove_bounds_checks(&mut self, _stmts: &mut [HirStmt]) {
+    fn remove_bounds_checks(&mut self, _stmts: &mut [Spanned<HirStmt>]) {
         // Would remove array bounds checks - requires careful analysis
         self.optimizations_applied
             .push("remove_bounds_checks".to_string());
     }
 
     /// Optimize for low latency
-    fn optimize_for_latency(&mut self, _stmts: &mut [HirStmt]) {
+    fn optimize_for_latency(&mut self, _stmts: &mut [Spanned<HirStmt>]) {
         // Prioritize reducing critical path length
         self.optimizations_applied
             .push("optimize_for_latency".to_string());
     }
 
     /// Optimize for high throughput
-    fn optimize_for_throughput(&mut self, _stmts: &mut [HirStmt]) {
+    fn optimize_for_throughput(&mut self, _stmts: &mut [Spanned<HirStmt>]) {
         // Prioritize parallelism and vectorization
         self.optimizations_applied
             .push("optimize_for_throughput".to_string());
@@ -308,7 +310,7 @@ pub fn optimize_module(module: &mut crate::hir::HirModule) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::hir::{HirModule, Literal, Type};
+    use crate::hir::{AssignTarget, HirModule, Literal, Span, Type};
     use depyler_annotations::TranspilationAnnotations;
     use smallvec::smallvec;
 
@@ -319,24 +321,28 @@ mod tests {
         let mut func = HirFunction {
             name: "test".to_string(),
             params: smallvec![],
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Add,
                 left: Box::new(HirExpr::Literal(Literal::Int(2))),
                 right: Box::new(HirExpr::Literal(Literal::Int(3))),
-            }))],
+            }))
+            .into()],
             properties: Default::default(),
             annotations: TranspilationAnnotations {
                 optimization_level: OptimizationLevel::Standard,
                 ..Default::default()
             },
             docstring: None,
+            is_async: false,
+            span: Span::default(),
         };
 
         optimizer.optimize_function(&mut func);
 
         // Check that constant folding was applied
-        if let HirStmt::Return(Some(HirExpr::Literal(Literal::Int(n)))) = &func.body[0] {
+        if let HirStmt::Return(Some(HirExpr::Literal(Literal::Int(n)))) = &func.body[0].node {
             assert_eq!(*n, 5);
         } else {
             panic!("Expected constant folding to produce literal 5");
@@ -350,25 +356,29 @@ mod tests {
         let mut func = HirFunction {
             name: "test".to_string(),
             params: smallvec![("x".to_string(), Type::Int)],
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Mul,
                 left: Box::new(HirExpr::Var("x".to_string())),
                 right: Box::new(HirExpr::Literal(Literal::Int(8))),
-            }))],
+            }))
+            .into()],
             properties: Default::default(),
             annotations: TranspilationAnnotations {
                 optimization_level: OptimizationLevel::Standard,
                 ..Default::default()
             },
             docstring: None,
+            is_async: false,
+            span: Span::default(),
         };
 
         optimizer.optimize_function(&mut func);
 
         // Check that multiplication by 8 is NOT replaced with left shift for correctness
         // Strength reduction is disabled to maintain semantic equivalence
-        if let HirStmt::Return(Some(HirExpr::Binary { op, right, .. })) = &func.body[0] {
+        if let HirStmt::Return(Some(HirExpr::Binary { op, right, .. })) = &func.body[0].node {
             assert_eq!(
                 *op,
                 BinOp::Mul,
@@ -389,13 +399,15 @@ mod tests {
         let mut func = HirFunction {
             name: "test".to_string(),
             params: smallvec![],
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![
-                HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42)))),
+                HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42)))).into(),
                 HirStmt::Assign {
-                    target: "unreachable".to_string(),
+                    target: AssignTarget::Symbol("unreachable".to_string()),
                     value: HirExpr::Literal(Literal::Int(0)),
-                },
+                }
+                .into(),
             ],
             properties: Default::default(),
             annotations: TranspilationAnnotations {
@@ -403,13 +415,15 @@ mod tests {
                 ..Default::default()
             },
             docstring: None,
+            is_async: false,
+            span: Span::default(),
         };
 
         optimizer.optimize_function(&mut func);
 
         // Check that unreachable code was removed
         assert_eq!(func.body.len(), 1);
-        assert!(matches!(func.body[0], HirStmt::Return(_)));
+        assert!(matches!(func.body[0].node, HirStmt::Return(_)));
     }
 
     #[test]
@@ -427,11 +441,14 @@ mod tests {
         let mut func = HirFunction {
             name: "test".to_string(),
             params: smallvec![],
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![],
             properties: Default::default(),
             annotations,
             docstring: None,
+            is_async: false,
+            span: Span::default(),
         };
 
         optimizer.optimize_function(&mut func);
@@ -449,6 +466,7 @@ mod tests {
                 HirFunction {
                     name: "func1".to_string(),
                     params: smallvec![],
+                    param_spec: Default::default(),
                     ret_type: Type::Int,
                     body: vec![],
                     properties: Default::default(),
@@ -457,10 +475,13 @@ mod tests {
                         ..Default::default()
                     },
                     docstring: None,
+                    is_async: false,
+                    span: Span::default(),
                 },
                 HirFunction {
                     name: "func2".to_string(),
                     params: smallvec![],
+                    param_spec: Default::default(),
                     ret_type: Type::Int,
                     body: vec![],
                     properties: Default::default(),
@@ -469,9 +490,13 @@ mod tests {
                         ..Default::default()
                     },
                     docstring: None,
+                    is_async: false,
+                    span: Span::default(),
                 },
             ],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let optimizations = optimize_module(&mut module);
@@ -0,0 +1,757 @@
+//! Transpiling a Python package into a Cargo crate
+//!
+//! Each module of the package becomes a module of the crate at the same
+//! path: the package's `__init__.py` becomes `lib.rs`, those of its
+//! subpackages `mod.rs`; a top-level `main.py` or `lib.py` is renamed so
+//! Cargo does not take it for a crate root. Imports between modules of the
+//! package become `use crate::...` items, and what they import is known to
+//! the code generator as if it were defined in the importing module.
+
+use crate::error;
+use crate::hir::{HirModule, Import, ImportItem, Span};
+use crate::rust_gen::{self, FileOptions, Imported};
+use crate::DepylerPipeline;
+use anyhow::{Context, Result};
+use proc_macro2::{Spacing, TokenStream, TokenTree};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Modules whose imports the transpiler lowers itself, so need no crate
+const SUPPORTED_MODULES: &[&str] = &[
+    "__future__",
+    "typing",
+    "typing_extensions",
+    "dataclasses",
+    "enum",
+    "abc",
+    "asyncio",
+    "threading",
+    "tempfile",
+];
+
+/// Crates generated code may use, by the name its paths start with
+const DEPENDENCIES: &[(&str, &str)] = &[
+    ("serde_json", "serde_json = \"1.0\""),
+    (
+        "tokio",
+        "tokio = { version = \"1\", features = [\"full\"] }",
+    ),
+    ("fnv", "fnv = \"1.0\""),
+    ("ahash", "ahash = \"0.8\""),
+    ("num_bigint", "num-bigint = \"0.4\""),
+    ("num_traits", "num-traits = \"0.2\""),
+    ("tempfile", "tempfile = \"3\""),
+];
+
+/// A Python package: a directory of modules and subpackages
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub name: String,
+    pub modules: Vec<PackageModule>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageModule {
+    /// Where the module is below the package: `["sub", "m"]` for
+    /// `sub/m.py`, `["sub"]` for `sub/__init__.py`, `[]` for the
+    /// package's own `__init__.py`
+    pub path: Vec<String>,
+    /// An `__init__.py`, the module of a (sub)package
+    pub is_init: bool,
+    /// The file, as errors report it
+    pub file: PathBuf,
+    pub source: String,
+}
+
+impl Package {
+    /// Read the package in directory `root`, with every `.py` file below it
+    pub fn from_dir(root: &Path) -> Result<Self> {
+        let name = root
+            .canonicalize()?
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("package directory has no name")?
+            .to_string();
+        let mut modules = Vec::new();
+        read_modules(root, &[], Path::new(&name), &mut modules)?;
+        Ok(Self { name, modules })
+    }
+
+    /// Whether `path` names a module or a subpackage of the package
+    fn contains(&self, path: &[String]) -> bool {
+        self.modules.iter().any(|m| m.path.starts_with(path))
+    }
+
+    /// The dotted name of the module at `path`
+    fn dotted(&self, path: &[String]) -> String {
+        std::iter::once(self.name.as_str())
+            .chain(path.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+fn read_modules(
+    dir: &Path,
+    path: &[String],
+    file: &Path,
+    modules: &mut Vec<PackageModule>,
+) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let entry_path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if !name.starts_with('.') && name != "__pycache__" {
+                let mut sub = path.to_vec();
+                sub.push(name.clone());
+                read_modules(&entry_path, &sub, &file.join(&name), modules)?;
+            }
+            continue;
+        }
+        let Some(stem) = name.strip_suffix(".py") else {
+            continue;
+        };
+        let is_init = stem == "__init__";
+        let mut module_path = path.to_vec();
+        if !is_init {
+            module_path.push(stem.to_string());
+        }
+        modules.push(PackageModule {
+            path: module_path,
+            is_init,
+            file: file.join(&name),
+            source: std::fs::read_to_string(&entry_path)?,
+        });
+    }
+    Ok(())
+}
+
+/// A Cargo crate generated from a package
+#[derive(Debug, Clone)]
+pub struct CrateProject {
+    /// Files of the crate by path below its root, `Cargo.toml` included
+    pub files: BTreeMap<PathBuf, String>,
+    pub report: PackageReport,
+}
+
+/// What needs a look before the crate builds as the package runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PackageReport {
+    /// Modules importing each other in a cycle, by dotted name; Rust
+    /// allows the cycle, but Python runs such modules half-initialized
+    pub cycles: Vec<Vec<String>>,
+    /// Imports of modules outside the package, and of names modules of
+    /// the package do not define
+    pub unresolved: Vec<UnresolvedImport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnresolvedImport {
+    /// The importing module, by dotted name
+    pub module: String,
+    /// What it imports, by dotted name
+    pub import: String,
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+/// What a module of the package imports, resolved
+#[derive(Default)]
+struct ModuleImports {
+    /// The `use` items of the generated module
+    uses: Vec<String>,
+    /// Modules of the package it depends on, by index
+    depends_on: BTreeSet<usize>,
+    /// Items and modules of the package it imports: the name it binds, the
+    /// item or `None` for a module, and the module defining it by index
+    imported: Vec<(String, Option<String>, usize)>,
+}
+
+/// Transpile each module of `package` and lay them out as a crate
+pub fn transpile(pipeline: &DepylerPipeline, package: &Package) -> Result<CrateProject> {
+    let modules = package
+        .modules
+        .iter()
+        .map(|module| {
+            pipeline
+                .optimized_hir(&module.source)
+                .map_err(|err| locate(err, module))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut report = PackageReport::default();
+    let imports: Vec<ModuleImports> = package
+        .modules
+        .iter()
+        .zip(&modules)
+        .map(|(module, hir)| resolve_imports(package, module, hir, &modules, &mut report))
+        .collect();
+    report.cycles = cycles(&imports)
+        .into_iter()
+        .map(|cycle| {
+            cycle
+                .into_iter()
+                .map(|i| package.dotted(&package.modules[i].path))
+                .collect()
+        })
+        .collect();
+
+    let mut files = BTreeMap::new();
+    for (i, module) in package.modules.iter().enumerate() {
+        let imported: Vec<Imported> = imports[i]
+            .imported
+            .iter()
+            .map(|(name, item, from)| match item {
+                Some(item) => Imported::Item {
+                    name,
+                    item,
+                    module: &modules[*from],
+                },
+                None => Imported::Module {
+                    name,
+                    module: &modules[*from],
+                },
+            })
+            .collect();
+        let options = FileOptions {
+            python_source: Some(&module.source),
+            imports: &imported,
+            ..Default::default()
+        };
+        let file = rust_gen::generate_rust_file_with_options(
+            &modules[i],
+            &pipeline.transpiler.type_mapper,
+            options,
+        )
+        .map_err(|err| locate(err, module))?;
+        let code = [imports[i].uses.join("\n"), file.code]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        files.insert(crate_file(package, &module.path), code);
+    }
+
+    // Declare each module in its parent, creating the files of packages
+    // that have no `__init__.py`
+    let mut children: BTreeMap<Vec<String>, BTreeSet<String>> = BTreeMap::new();
+    for module in &package.modules {
+        for depth in 0..module.path.len() {
+            children
+                .entry(module.path[..depth].to_vec())
+                .or_default()
+                .insert(module.path[depth].clone());
+        }
+    }
+    for (parent, names) in children {
+        let decls: String = names
+            .iter()
+            .map(|name| {
+                let mut path = parent.clone();
+                path.push(name.clone());
+                match renamed_file(package, &path) {
+                    Some(file) => format!("#[path = \"{file}\"]\npub mod {name};\n"),
+                    None => format!("pub mod {};\n", rust_name(name)),
+                }
+            })
+            .collect();
+        let code = files.entry(crate_file(package, &parent)).or_default();
+        let separator = if code.is_empty() { "" } else { "\n" };
+        *code = format!("{decls}{separator}{code}");
+    }
+
+    let mut roots = BTreeSet::new();
+    for (file, code) in &files {
+        let tokens = code
+            .parse()
+            .map_err(|err| anyhow::anyhow!("generated {} does not parse: {err}", file.display()))?;
+        path_roots(&tokens, &mut roots);
+    }
+    let dependencies: Vec<&str> = DEPENDENCIES
+        .iter()
+        .filter(|(root, _)| roots.contains(*root))
+        .map(|(_, dependency)| *dependency)
+        .collect();
+    files.insert(
+        PathBuf::from("Cargo.toml"),
+        cargo_toml(&package.name, &dependencies),
+    );
+
+    Ok(CrateProject { files, report })
+}
+
+/// Add to `roots` the names the paths in `tokens` start with, such as
+/// `serde_json` of `serde_json::Value`
+fn path_roots(tokens: &TokenStream, roots: &mut BTreeSet<String>) {
+    let tokens: Vec<TokenTree> = tokens.clone().into_iter().collect();
+    let path_sep = |i: usize| match (tokens.get(i), tokens.get(i + 1)) {
+        (Some(TokenTree::Punct(first)), Some(TokenTree::Punct(second))) => {
+            first.as_char() == ':' && first.spacing() == Spacing::Joint && second.as_char() == ':'
+        }
+        _ => false,
+    };
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            TokenTree::Group(group) => path_roots(&group.stream(), roots),
+            // Not a segment inside a path such as `crate::tempfile::f`
+            TokenTree::Ident(ident) if path_sep(i + 1) && !(i >= 2 && path_sep(i - 2)) => {
+                roots.insert(ident.to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn locate(err: anyhow::Error, module: &PackageModule) -> anyhow::Error {
+    error::locate(err, &module.file.display().to_string(), &module.source)
+}
+
+/// Resolve the imports of `module`, recording in `report` those that lead
+/// outside the package
+fn resolve_imports(
+    package: &Package,
+    module: &PackageModule,
+    hir: &HirModule,
+    modules: &[HirModule],
+    report: &mut PackageReport,
+) -> ModuleImports {
+    let mut resolved = ModuleImports::default();
+    let index = |path: &[String]| package.modules.iter().position(|m| m.path == path);
+    let mut unresolved = |import: String, span: Span| {
+        let line = error::SourceLocation::from_offset("", &module.source, span.range().start).line;
+        report.unresolved.push(UnresolvedImport {
+            module: package.dotted(&module.path),
+            import,
+            file: module.file.clone(),
+            line,
+        });
+    };
+    // What a package imports is an attribute of it, as items it exports
+    let keyword = if module.is_init { "pub use" } else { "use" };
+    let mut uses = Vec::new();
+    let mut use_as = |path: &[String], alias: &str| {
+        let (last, parent) = path.split_last().expect("imports name something");
+        if last == alias {
+            // The package's own submodules are declared in it already
+            if !(module.is_init && parent == module.path.as_slice()) {
+                uses.push(format!("{keyword} {};", crate_path(path)));
+            }
+        } else {
+            uses.push(format!("{keyword} {} as {alias};", crate_path(path)));
+        }
+    };
+
+    let mut binds_root = false;
+    for import in &hir.imports {
+        let Some(target) = import_target(package, module, import) else {
+            let first = import.module.split('.').next().unwrap_or_default();
+            if !SUPPORTED_MODULES.contains(&first) {
+                let dots = ".".repeat(import.level as usize);
+                unresolved(format!("{dots}{}", import.module), import.span);
+            }
+            continue;
+        };
+        if !package.contains(&target) {
+            unresolved(package.dotted(&target), import.span);
+            continue;
+        }
+        if !import.is_from {
+            let from = index(&target);
+            resolved.depends_on.extend(from);
+            for item in &import.items {
+                match (item, from) {
+                    (ImportItem::Aliased { alias, .. }, Some(from)) => {
+                        use_as(&target, alias);
+                        resolved.imported.push((alias.clone(), None, from));
+                    }
+                    // `import package.module` binds the package, the crate
+                    // root, through which its modules are named by their
+                    // dotted names
+                    (ImportItem::Named(_), _) => {
+                        binds_root = true;
+                        for depth in 0..=target.len() {
+                            if let Some(prefix) = index(&target[..depth]) {
+                                let name = package.dotted(&target[..depth]);
+                                resolved.imported.push((name, None, prefix));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            continue;
+        }
+        let from = index(&target);
+        resolved.depends_on.extend(from);
+        for item in &import.items {
+            let (name, alias) = match item {
+                ImportItem::Named(name) => (name, name),
+                ImportItem::Aliased { name, alias } => (name, alias),
+            };
+            let mut item_path = target.clone();
+            item_path.push(name.clone());
+
+            if let (Some(from), "*") = (from, name.as_str()) {
+                use_as(&item_path, alias);
+                let items = modules[from]
+                    .functions
+                    .iter()
+                    .map(|f| &f.name)
+                    .chain(modules[from].classes.iter().map(|c| &c.name));
+                for item in items {
+                    resolved
+                        .imported
+                        .push((item.clone(), Some(item.clone()), from));
+                }
+                continue;
+            }
+            // An attribute of the module comes before a submodule by the name
+            if let Some((defined_in, item)) =
+                from.and_then(|from| definition(package, modules, from, name))
+            {
+                let mut path = package.modules[defined_in].path.clone();
+                path.push(item.clone());
+                use_as(&path, alias);
+                resolved
+                    .imported
+                    .push((alias.clone(), Some(item), defined_in));
+            } else if let Some(submodule) = index(&item_path) {
+                use_as(&item_path, alias);
+                resolved.depends_on.insert(submodule);
+                resolved.imported.push((alias.clone(), None, submodule));
+            } else {
+                unresolved(package.dotted(&item_path), import.span);
+            }
+        }
+    }
+    if binds_root {
+        uses.push(format!("use crate as {};", rust_name(&package.name)));
+    }
+    uses.sort();
+    uses.dedup();
+    resolved.uses = uses;
+    // A package importing its own submodules does not depend on itself
+    if let Some(own) = index(&module.path) {
+        resolved.depends_on.remove(&own);
+    }
+    resolved
+}
+
+/// The module defining the function or class module `from` has as `name`,
+/// by index, and the name it defines it by, following imports
+fn definition(
+    package: &Package,
+    modules: &[HirModule],
+    from: usize,
+    name: &str,
+) -> Option<(usize, String)> {
+    let mut seen = BTreeSet::new();
+    let (mut from, mut name) = (from, name.to_string());
+    while seen.insert((from, name.clone())) {
+        let hir = &modules[from];
+        if hir.functions.iter().any(|f| f.name == name)
+            || hir.classes.iter().any(|c| c.name == name)
+        {
+            return Some((from, name));
+        }
+        let module = &package.modules[from];
+        let (import, imported) = hir.imports.iter().filter(|i| i.is_from).find_map(|i| {
+            i.items.iter().find_map(|item| match item {
+                ImportItem::Named(n) if *n == name => Some((i, n.clone())),
+                ImportItem::Aliased { name: n, alias } if *alias == name => Some((i, n.clone())),
+                _ => None,
+            })
+        })?;
+        let target = import_target(package, module, import)?;
+        from = package.modules.iter().position(|m| m.path == target)?;
+        name = imported;
+    }
+    None
+}
+
+/// The path below the package of the module `import` names, or `None`
+/// when it names a module outside the package
+fn import_target(
+    package: &Package,
+    module: &PackageModule,
+    import: &Import,
+) -> Option<Vec<String>> {
+    let names = import
+        .module
+        .split('.')
+        .filter(|name| !name.is_empty())
+        .map(str::to_string);
+    if import.level == 0 {
+        let mut names = names.peekable();
+        names.next_if(|first| *first == package.name)?;
+        return Some(names.collect());
+    }
+    // Relative to the package the module is in
+    let mut base = module.path.clone();
+    if !module.is_init {
+        base.pop();
+    }
+    let up = import.level as usize - 1;
+    if up > base.len() {
+        return None;
+    }
+    base.truncate(base.len() - up);
+    base.extend(names);
+    Some(base)
+}
+
+/// Groups of modules importing each other, by index, found with Tarjan's
+/// algorithm
+fn cycles(imports: &[ModuleImports]) -> Vec<Vec<usize>> {
+    struct Tarjan<'a> {
+        imports: &'a [ModuleImports],
+        index: HashMap<usize, usize>,
+        low: HashMap<usize, usize>,
+        stack: Vec<usize>,
+        cycles: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            let index = self.index.len();
+            self.index.insert(v, index);
+            self.low.insert(v, index);
+            self.stack.push(v);
+            for &w in &self.imports[v].depends_on {
+                if !self.index.contains_key(&w) {
+                    self.visit(w);
+                    self.low.insert(v, self.low[&v].min(self.low[&w]));
+                } else if self.stack.contains(&w) {
+                    self.low.insert(v, self.low[&v].min(self.index[&w]));
+                }
+            }
+            if self.low[&v] == self.index[&v] {
+                let at = self.stack.iter().rposition(|&w| w == v).unwrap_or_default();
+                let mut cycle = self.stack.split_off(at);
+                if cycle.len() > 1 || self.imports[v].depends_on.contains(&v) {
+                    cycle.sort_unstable();
+                    self.cycles.push(cycle);
+                }
+            }
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        imports,
+        index: HashMap::new(),
+        low: HashMap::new(),
+        stack: Vec::new(),
+        cycles: Vec::new(),
+    };
+    for v in 0..imports.len() {
+        if !tarjan.index.contains_key(&v) {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.cycles.sort();
+    tarjan.cycles
+}
+
+/// The file of the crate holding the module at `path`
+fn crate_file(package: &Package, path: &[String]) -> PathBuf {
+    let mut file = PathBuf::from("src");
+    let Some((last, parents)) = path.split_last() else {
+        return file.join("lib.rs");
+    };
+    file.extend(parents);
+    if let Some(renamed) = renamed_file(package, path) {
+        file.join(renamed)
+    } else if has_children(package, path) {
+        file.join(last).join("mod.rs")
+    } else {
+        file.join(format!("{last}.rs"))
+    }
+}
+
+fn has_children(package: &Package, path: &[String]) -> bool {
+    package
+        .modules
+        .iter()
+        .any(|m| m.path.len() > path.len() && m.path.starts_with(path))
+}
+
+/// The file of a top-level module named `main` or `lib`, whose own name
+/// Cargo would take for a crate root
+fn renamed_file(package: &Package, path: &[String]) -> Option<String> {
+    match path {
+        [name] if matches!(name.as_str(), "main" | "lib") && !has_children(package, path) => {
+            Some(format!("{name}_module.rs"))
+        }
+        _ => None,
+    }
+}
+
+/// The `crate::` path of the module or item at `path` below the package
+fn crate_path(path: &[String]) -> String {
+    std::iter::once("crate".to_string())
+        .chain(path.iter().map(|name| rust_name(name)))
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// `name` as a Rust identifier, raw when it is a keyword
+fn rust_name(name: &str) -> String {
+    if name != "*" && syn::parse_str::<syn::Ident>(name).is_err() {
+        format!("r#{name}")
+    } else {
+        name.to_string()
+    }
+}
+
+fn cargo_toml(name: &str, dependencies: &[&str]) -> String {
+    // A `bin` subpackage would otherwise be taken for binaries
+    let mut toml = format!(
+        "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\nautobins = false\n\n[dependencies]\n"
+    );
+    for dependency in dependencies {
+        toml.push_str(dependency);
+        toml.push('\n');
+    }
+    toml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(modules: &[(&str, &str)]) -> Package {
+        let modules = modules
+            .iter()
+            .map(|&(file, source)| {
+                let mut path: Vec<String> = file
+                    .trim_end_matches(".py")
+                    .split('/')
+                    .map(str::to_string)
+                    .collect();
+                let is_init = path.last().is_some_and(|name| name == "__init__");
+                if is_init {
+                    path.pop();
+                }
+                PackageModule {
+                    path,
+                    is_init,
+                    file: Path::new("pkg").join(file),
+                    source: source.to_string(),
+                }
+            })
+            .collect();
+        Package {
+            name: "pkg".to_string(),
+            modules,
+        }
+    }
+
+    fn file<'a>(project: &'a CrateProject, path: &str) -> &'a str {
+        &project.files[Path::new(path)]
+    }
+
+    #[test]
+    fn test_package_layout() {
+        let package = package(&[
+            ("__init__.py", "from .sub.m import double\n"),
+            ("util.py", "def inc(x: int) -> int:\n    return x + 1\n"),
+            ("sub/__init__.py", ""),
+            (
+                "sub/m.py",
+                "from ..util import inc\n\ndef double(x: int) -> int:\n    return inc(x) * 2\n",
+            ),
+        ]);
+        let project = DepylerPipeline::new().transpile_package(&package).unwrap();
+        let lib = file(&project, "src/lib.rs");
+        assert!(lib.contains("pub mod sub;"));
+        assert!(lib.contains("pub mod util;"));
+        assert!(lib.contains("pub use crate::sub::m::double;"));
+        let sub = file(&project, "src/sub/mod.rs");
+        assert!(sub.contains("pub mod m;"));
+        let m = file(&project, "src/sub/m.rs");
+        assert!(m.contains("use crate::util::inc;"));
+        assert!(m.contains("pub fn double"));
+        assert!(file(&project, "Cargo.toml").contains("name = \"pkg\""));
+        assert_eq!(project.report, PackageReport::default());
+    }
+
+    #[test]
+    fn test_package_dotted_imports_run() {
+        let package = package(&[
+            ("__init__.py", ""),
+            (
+                "util.py",
+                "def inc(x: int) -> int:\n    return x + 1\n\n\
+                 class Box:\n    def __init__(self, v: int):\n        self.v = v\n",
+            ),
+            ("sub/__init__.py", ""),
+            (
+                "sub/tempfile.py",
+                "def twice(x: int) -> int:\n    return x * 2\n",
+            ),
+            (
+                "app.py",
+                "import pkg.util\nimport pkg.sub.tempfile\n\n\
+                 def run(x: int) -> int:\n    b = pkg.util.Box(x)\n    \
+                 return pkg.sub.tempfile.twice(pkg.util.inc(b.v))\n",
+            ),
+        ]);
+        let project = DepylerPipeline::new().transpile_package(&package).unwrap();
+        assert!(file(&project, "src/app.rs").contains("use crate as pkg;"));
+        // A module named like a crate is no dependency on the crate
+        assert!(!file(&project, "Cargo.toml").contains("tempfile ="));
+        assert_eq!(project.report, PackageReport::default());
+        let output =
+            crate::test_programs::run_crate(&project.files, r#"println!("{}", pkg::app::run(3));"#);
+        assert_eq!(output, "8\n");
+    }
+
+    #[test]
+    fn test_package_main_module_is_not_a_binary() {
+        let package = package(&[(
+            "main.py",
+            "import tempfile
+
+def scratch() -> None:
+    with tempfile.TemporaryDirectory() as d:
+        print(d)
+",
+        )]);
+        let project = DepylerPipeline::new().transpile_package(&package).unwrap();
+        assert!(
+            file(&project, "src/lib.rs").contains("#[path = \"main_module.rs\"]\npub mod main;")
+        );
+        assert!(file(&project, "src/main_module.rs").contains("pub fn scratch"));
+        let toml = file(&project, "Cargo.toml");
+        assert!(toml.contains("autobins = false"));
+        assert!(toml.contains("tempfile = \"3\""));
+        assert_eq!(project.report, PackageReport::default());
+    }
+
+    #[test]
+    fn test_package_report() {
+        let package = package(&[
+            ("a.py", "from . import b\nimport os\n"),
+            ("b.py", "from .a import missing\nfrom . import a\n"),
+        ]);
+        let project = DepylerPipeline::new().transpile_package(&package).unwrap();
+        let report = &project.report;
+        assert_eq!(
+            report.cycles,
+            vec![vec!["pkg.a".to_string(), "pkg.b".to_string()]]
+        );
+        let unresolved: Vec<_> = report
+            .unresolved
+            .iter()
+            .map(|u| (u.import.as_str(), u.line))
+            .collect();
+        assert_eq!(unresolved, vec![("os", 2), ("pkg.a.missing", 1)]);
+        // A package without an `__init__.py` still gets a crate root
+        assert!(file(&project, "src/lib.rs").contains("pub mod a;"));
+    }
+}
@@ -1,8 +1,27 @@
 use crate::annotation_aware_type_mapper::AnnotationAwareTypeMapper;
+use crate::arguments;
+use crate::arithmetic::{self, Helper, Overflow};
+use crate::bigint::{self, BigIntSignature, IntScope};
+use crate::borrowing::{self, BorrowTable, FunctionBorrows, ParamPassing};
+use crate::builtins;
+use crate::closures::{self, ClosureKind};
+use crate::context_managers::{self, ClassGuard, ContextManager, FileMode};
+use crate::error::{ErrorKind, SpanExt};
+use crate::exceptions::{self, ExceptionTable, ERROR_TYPE_NAME};
+use crate::fallback::{self, SkippedConstruct};
+use crate::generators::{self, GenExit, GenOp, StateMachine};
 use crate::hir::*;
-use anyhow::{bail, Result};
+use crate::indexing::{self, Bound, IndexPolicy, Items, Sign};
+use crate::lambda_errors::LambdaErrorHandler;
+use crate::mutability::Effects;
+use crate::sets::{self, SetType};
+use crate::source_map::{self, SourceMap};
+use crate::strings;
+use crate::transpile_bail;
+use crate::type_enums;
+use anyhow::Result;
 use quote::quote;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use syn::{self, parse_quote};
 
 /// Context for code generation including type mapping and configuration
@@ -15,10 +34,472 @@ pub struct CodeGenContext<'a> {
     pub needs_arc: bool,
     pub needs_rc: bool,
     pub needs_cow: bool,
+    pub needs_hashset: bool,
+    /// Set types other than `HashSet` the code names
+    pub set_types: BTreeSet<SetType>,
+    /// `num_bigint::BigInt`
+    pub needs_bigint: bool,
+    /// Traits of `num_traits` whose methods the code calls on `BigInt`s
+    pub num_traits: BTreeSet<&'static str>,
+    /// Functions generated for Python's `//` and `%` that the code calls
+    pub arithmetic_helpers: BTreeSet<Helper>,
+    /// Functions generated for `str` methods and conversions
+    pub string_helpers: BTreeSet<strings::Helper>,
     pub declared_vars: Vec<HashSet<String>>,
+    pub class_names: HashSet<String>,
+    /// Fields of the class whose methods are currently being generated
+    pub class_fields: HashMap<String, HirField>,
+    /// Class whose methods are currently being generated
+    pub current_class: Option<String>,
+    /// Exception hierarchy used to match `except` clauses
+    pub exceptions: ExceptionTable,
+    /// Variants of the generated `PythonError` enum
+    pub error_variants: Vec<String>,
+    /// Call keys of functions and methods that return `Result`
+    pub fallible_calls: HashSet<String>,
+    /// Stub out every function and method that fails to generate
+    pub best_effort: bool,
+    /// Functions and methods stubbed out, converting the module or
+    /// generating it; methods are named `Class.method`
+    pub skipped: Vec<SkippedConstruct>,
+    /// How errors leave the function currently being generated
+    pub error_scope: ErrorScope,
+    /// How the function being generated represents ints
+    pub ints: IntScope,
+    /// The Rust type of the sets the function being generated makes
+    pub set_type: SetType,
+    /// Functions of the module whose ints are `BigInt`s, by name
+    pub big_int_functions: HashMap<Symbol, BigIntSignature>,
+    /// The Python return type of the function being generated
+    pub ret_type: Type,
+    /// Python return types of the module's functions, by name
+    pub return_types: HashMap<Symbol, Type>,
+    /// Parameter types of the module's functions taking `Union` or
+    /// `Literal` values, by name
+    pub enum_params: HashMap<Symbol, Vec<Type>>,
+    /// Comprehension variables bound by reference inside `filter` closures
+    pub borrowed_vars: HashSet<String>,
+    /// Classes defining `__exit__`, entered through a generated guard
+    pub guarded_classes: HashMap<String, ClassGuard>,
+    /// Annotated parameter types of the function being generated, the
+    /// container type of locals bound to list or dict displays, and the
+    /// type of string, number and set locals
+    pub var_types: HashMap<String, Type>,
+    /// Integer locals known not to be negative, which index without the
+    /// adjusting a negative index takes: `usize` ones, such as `len(xs)`,
+    /// and loop variables counting up a `range`
+    pub int_signs: HashMap<String, Sign>,
+    /// Names bound to open files by an enclosing `with`
+    pub file_vars: HashSet<String>,
+    /// Names bound by an enclosing `with` to an instance of a guarded
+    /// class, which its guard owns, with the class
+    pub guard_vars: HashMap<String, String>,
+    /// Members of the module's `Enum` classes, checked for exhaustive `match`
+    pub enums: HashMap<String, Vec<Symbol>>,
+    /// Classes of the module by name, used to lower class patterns
+    pub class_defs: HashMap<String, HirClass>,
+    /// Fields of the options structs generated for `**kwargs`, by struct name
+    pub kwargs_structs: HashMap<String, Vec<Symbol>>,
+    /// Lambdas and nested functions of the function being generated
+    pub closures: ClosureScope,
+    /// How the module's functions and methods take their parameters
+    pub borrows: BorrowTable,
+    /// How the function being generated takes its parameters
+    pub borrow_scope: BorrowScope,
+    /// Methods of the module's classes that modify `self`
+    pub mutating_methods: HashSet<Symbol>,
+    /// What the code after the statement being generated does with each binding
+    pub live: LiveScope,
+    /// Mark functions, methods and statements with their Python span, for
+    /// a source map
+    pub source_markers: bool,
+    /// Modules of the package imported by name, dotted or not, with the
+    /// classes each defines
+    pub module_aliases: HashMap<String, HashSet<String>>,
+}
+
+/// The statements around the one being generated, innermost block last
+#[derive(Debug, Clone, Default)]
+pub struct LiveScope {
+    /// Values read for the last time may be moved; off in closures and
+    /// generators, whose code may run again
+    moves: bool,
+    /// The blocks generated are a function's body and its nested blocks
+    function_body: bool,
+    frames: Vec<LiveFrame>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct LiveFrame {
+    /// The statement being generated in this block
+    current: Effects,
+    /// What runs after it: the rest of the block, or the next iteration of a loop
+    later: Effects,
+    /// The statement is the last the function runs, and its value is the
+    /// function's value
+    tail: bool,
+    /// The statement is an `if` with an `else`, whose branches end in tail
+    /// position when it does
+    branches: bool,
+}
+
+impl LiveScope {
+    fn for_function() -> Self {
+        Self {
+            moves: true,
+            function_body: true,
+            frames: Vec::new(),
+        }
+    }
+
+    /// The body of a nested function bound to a closure, which may run again
+    fn for_closure() -> Self {
+        Self {
+            moves: false,
+            function_body: true,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Whether the statement being generated is in tail position
+    fn in_tail(&self) -> bool {
+        self.frames.last().is_some_and(|frame| frame.tail)
+    }
+
+    /// Whether the block about to be generated ends in tail position
+    fn block_in_tail(&self) -> bool {
+        match self.frames.last() {
+            None => self.function_body,
+            Some(frame) => frame.tail && frame.branches,
+        }
+    }
+
+    /// Whether a binding declared by the statement being generated is
+    /// assigned or modified later in its block
+    fn written_later(&self, name: &str) -> bool {
+        self.frames
+            .last()
+            .is_none_or(|frame| frame.later.writes(name))
+    }
+
+    /// Whether anything after the statement being generated may read a binding
+    fn read_later(&self, name: &str) -> bool {
+        self.frames.is_empty() || self.frames.iter().any(|frame| frame.later.reads(name) > 0)
+    }
+
+    /// Whether nothing after the statement being generated reads a binding
+    fn unread_later(&self, name: &str) -> bool {
+        self.moves
+            && !self.frames.is_empty()
+            && self.frames.iter().all(|frame| frame.later.reads(name) == 0)
+    }
+
+    /// Whether the statement being generated reads a binding once, and
+    /// nothing after it reads it again
+    fn is_last_use(&self, name: &str) -> bool {
+        self.moves
+            && !self.frames.is_empty()
+            && self
+                .frames
+                .iter()
+                .all(|frame| frame.current.reads(name) <= 1 && frame.later.reads(name) == 0)
+    }
+}
+
+/// Closures defined in the function being generated
+#[derive(Debug, Clone, Default)]
+pub struct ClosureScope {
+    /// Locals bound to a closure that outlives the function, so it captures
+    /// by `move`
+    escaping: HashSet<Symbol>,
+    /// Locals bound to a closure that the function calls, rather than only
+    /// hands on; an `FnMut` one must be bound `mut` to be called
+    called: HashSet<Symbol>,
+    /// The closure trait of each local bound to a lambda or nested function
+    locals: HashMap<Symbol, ClosureKind>,
+    /// The trait of the closures the function returns
+    returned: Option<ClosureKind>,
+}
+
+impl ClosureScope {
+    fn return_closure(&mut self, kind: ClosureKind) {
+        self.returned = Some(self.returned.map_or(kind, |k| k.join(kind)));
+    }
+
+    fn for_body(body: &[Spanned<HirStmt>]) -> Self {
+        let mut names = Vec::new();
+        closure_locals(body, &mut names);
+        let mut called = HashSet::new();
+        for_each_expr_in(body, &mut |expr| {
+            if let HirExpr::Call { func, .. } = expr {
+                if names.contains(func) {
+                    called.insert(func.clone());
+                }
+            }
+        });
+        Self {
+            escaping: names
+                .into_iter()
+                .filter(|name| closures::escapes(name, body))
+                .collect(),
+            called,
+            ..Default::default()
+        }
+    }
+}
+
+/// Locals bound to a lambda or nested function anywhere in a body
+fn closure_locals(body: &[Spanned<HirStmt>], names: &mut Vec<Symbol>) {
+    for stmt in body {
+        match &stmt.node {
+            HirStmt::FunctionDef { func, .. } => names.push(func.name.clone()),
+            HirStmt::Assign {
+                target: AssignTarget::Symbol(name),
+                value: HirExpr::Lambda { .. },
+            } => names.push(name.clone()),
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                closure_locals(then_body, names);
+                if let Some(else_body) = else_body {
+                    closure_locals(else_body, names);
+                }
+            }
+            HirStmt::While { body, .. }
+            | HirStmt::For { body, .. }
+            | HirStmt::With { body, .. } => closure_locals(body, names),
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                for body in try_bodies(body, handlers, orelse, finalbody) {
+                    closure_locals(body, names);
+                }
+            }
+            HirStmt::Match { cases, .. } => {
+                for case in cases {
+                    closure_locals(&case.body, names);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parameters the function being generated borrows
+#[derive(Debug, Clone, Default)]
+pub struct BorrowScope {
+    params: HashMap<Symbol, ParamPassing>,
+    /// Parameters the returned `&str` is borrowed from
+    returned: HashSet<Symbol>,
+    /// The function returns `&str` rather than `String`
+    lends: bool,
+    /// The returned borrow is tied to its parameters by `'a`, since elision
+    /// would tie it to `self` or cannot pick between several references
+    named_lifetime: bool,
+}
+
+impl BorrowScope {
+    fn new(params: &[(Symbol, Type)], borrows: &FunctionBorrows, has_receiver: bool) -> Self {
+        let name = |i: &usize| params[*i].0.clone();
+        let returned: HashSet<Symbol> = borrows.returned.iter().map(name).collect();
+        let references = borrows
+            .params
+            .iter()
+            .filter(|p| **p != ParamPassing::Owned)
+            .count();
+        Self {
+            params: params
+                .iter()
+                .enumerate()
+                .map(|(i, (param, _))| (param.clone(), borrows.passing(i)))
+                .collect(),
+            lends: !returned.is_empty(),
+            named_lifetime: !returned.is_empty() && (has_receiver || references > 1),
+            returned,
+        }
+    }
+
+    fn passing(&self, name: &str) -> ParamPassing {
+        self.params.get(name).copied().unwrap_or_default()
+    }
+}
+
+/// How a `raise` or a failing call leaves the code being generated
+#[derive(Debug, Clone, Default)]
+pub struct ErrorScope {
+    /// The function returns `Result`; otherwise uncaught errors panic
+    pub returns_result: bool,
+    /// Errors are the Lambda runtime's `LambdaError` rather than `PythonError`
+    pub lambda: bool,
+    /// What indexing a sequence out of range does
+    pub index_policy: IndexPolicy,
+    /// What int arithmetic overflowing does
+    pub overflow: Overflow,
+    /// Builtin exceptions raised by operations such as `int()` rather than
+    /// panicking, because an enclosing `try` catches them
+    raised_builtins: Vec<String>,
+    /// Enclosing `try` constructs, innermost last
+    frames: Vec<ExitFrame>,
+    next_label: usize,
+}
+
+/// Part of an enclosing `try` that an error or `return` passes through
+#[derive(Debug, Clone)]
+enum ExitFrame {
+    /// A `try` body, lowered to a labeled block that breaks with the error
+    TryBody(String),
+    /// A `finally` body, run before control leaves the `try`
+    Finally(Vec<Spanned<HirStmt>>),
+    /// An `except` clause; a bare `raise` re-raises the error it bound
+    Handler(String),
+}
+
+impl ErrorScope {
+    fn new(
+        returns_result: bool,
+        annotations: &depyler_annotations::TranspilationAnnotations,
+    ) -> Self {
+        Self {
+            returns_result,
+            lambda: annotations.lambda_annotations.is_some(),
+            index_policy: IndexPolicy::new(annotations),
+            overflow: Overflow::new(annotations),
+            ..Default::default()
+        }
+    }
+
+    fn error_type(&self) -> syn::Type {
+        if self.lambda {
+            parse_quote! { LambdaError }
+        } else {
+            let ident = syn::Ident::new(ERROR_TYPE_NAME, proc_macro2::Span::call_site());
+            parse_quote! { #ident }
+        }
+    }
+
+    /// The scope of a closure's body, which runs outside any enclosing `try`
+    /// and panics on errors
+    fn closure(&self) -> Self {
+        Self {
+            index_policy: self.index_policy,
+            overflow: self.overflow,
+            ..Default::default()
+        }
+    }
+
+    /// Whether an error must unwind through a `try` rather than leave the function directly
+    fn inside_try(&self) -> bool {
+        self.frames
+            .iter()
+            .any(|f| !matches!(f, ExitFrame::Handler(_)))
+    }
+
+    /// Whether operations raising the builtin `exception` raise it as an error
+    fn raises(&self, exception: &str) -> bool {
+        self.raised_builtins.iter().any(|e| e == exception)
+    }
+
+    /// The error bound by the innermost `except` clause
+    fn handled_error(&self) -> Option<&str> {
+        self.frames.iter().rev().find_map(|f| match f {
+            ExitFrame::Handler(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
 }
 
 impl<'a> CodeGenContext<'a> {
+    /// `tokens` behind a source map marker for `span`, when generating one
+    fn mark(&self, span: Span, tokens: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        if !self.source_markers || span.is_empty() || tokens.is_empty() {
+            return tokens;
+        }
+        let marker = source_map::marker(span);
+        quote! { #marker #tokens }
+    }
+
+    /// Make what another module of the package defines known to calls as
+    /// if it were defined here
+    fn import(&mut self, imported: &Imported) {
+        match *imported {
+            Imported::Item { name, item, module } => {
+                let borrows = BorrowTable::for_module(module, self.type_mapper.string_type);
+                if let Some(func) = module.functions.iter().find(|f| f.name == item) {
+                    if returns_result(&func.properties, &func.annotations) {
+                        self.fallible_calls.insert(name.to_string());
+                    }
+                    if let Some(func_borrows) = borrows.function(item) {
+                        self.borrows.import_function(name, func_borrows.clone());
+                    }
+                }
+                if let Some(class) = module.classes.iter().find(|c| c.name == item) {
+                    self.class_names.insert(name.to_string());
+                    self.class_defs.insert(name.to_string(), class.clone());
+                    if class.is_enum() {
+                        let members = class.enum_members().into_iter().cloned().collect();
+                        self.enums.insert(name.to_string(), members);
+                    }
+                    for method in &class.methods {
+                        if returns_result(&method.properties, &method.annotations) {
+                            self.fallible_calls
+                                .insert(format!("{}.{}", name, method.name));
+                        }
+                        if mutates_self(&method.body) {
+                            self.mutating_methods.insert(method.name.clone());
+                        }
+                        if let Some(method_borrows) = borrows.method(&method.name) {
+                            self.borrows
+                                .import_method(&method.name, method_borrows.clone());
+                        }
+                    }
+                }
+            }
+            Imported::Module { name, module } => {
+                let classes = module.classes.iter().map(|c| c.name.clone()).collect();
+                self.module_aliases.insert(name.to_string(), classes);
+            }
+        }
+    }
+
+    /// Drop what a function that failed to generate left behind, back to
+    /// `scopes` variable scopes
+    fn abandon_function(&mut self, scopes: usize) {
+        self.declared_vars.truncate(scopes);
+        self.var_types.clear();
+        self.int_signs.clear();
+        self.error_scope = ErrorScope::default();
+        self.ints = IntScope::default();
+        self.set_type = SetType::default();
+        self.ret_type = Type::Unknown;
+        self.closures = ClosureScope::default();
+        self.borrow_scope = BorrowScope::default();
+        self.live = LiveScope::default();
+    }
+
+    /// Import set type `set_type`
+    fn use_set(&mut self, set_type: SetType) {
+        if set_type == SetType::Std {
+            self.needs_hashset = true;
+        } else {
+            self.set_types.insert(set_type);
+        }
+    }
+
+    /// The Rust type of fixed-width ints: the configured one, or `i64` for
+    /// the ints proven bounded in a function whose others are `BigInt`s
+    fn fixed_int_type(&self) -> crate::type_mapper::RustType {
+        if self.ints.big {
+            crate::type_mapper::RustType::Primitive(crate::type_mapper::PrimitiveType::I64)
+        } else {
+            self.type_mapper.map_type(&Type::Int)
+        }
+    }
+
     fn enter_scope(&mut self) {
         self.declared_vars.push(HashSet::new());
     }
@@ -27,6 +508,43 @@ impl<'a> CodeGenContext<'a> {
         self.declared_vars.pop();
     }
 
+    /// Whether `name` is an owned binding of the function that the statement
+    /// being generated reads for the last time, so may be moved out of
+    fn can_move(&self, name: &str) -> bool {
+        name != "self"
+            && self.is_declared(name)
+            && !self.borrowed_vars.contains(name)
+            && self.borrow_scope.passing(name) == ParamPassing::Owned
+            && self.live.is_last_use(name)
+    }
+
+    /// Whether `name` is an owned binding of the function that nothing after
+    /// the statement being generated reads, so the statement may move it
+    fn can_move_after(&self, name: &str) -> bool {
+        name != "self"
+            && self.is_declared(name)
+            && !self.borrowed_vars.contains(name)
+            && self.borrow_scope.passing(name) == ParamPassing::Owned
+            && self.live.unread_later(name)
+    }
+
+    /// A loop's next iteration reads what the loop reads, apart from the
+    /// bindings its body declares afresh
+    fn enter_loop(&mut self, stmt: &HirStmt) {
+        let stmt = Spanned::from(stmt.clone());
+        let mut later = Effects::of(std::slice::from_ref(&stmt), &self.mutating_methods);
+        later.retain(|name| self.is_declared(name));
+        self.live.frames.push(LiveFrame {
+            current: Effects::default(),
+            later,
+            ..Default::default()
+        });
+    }
+
+    fn exit_loop(&mut self) {
+        self.live.frames.pop();
+    }
+
     fn is_declared(&self, var_name: &str) -> bool {
         self.declared_vars
             .iter()
@@ -50,6 +568,129 @@ pub fn generate_rust_file(
     module: &HirModule,
     type_mapper: &crate::type_mapper::TypeMapper,
 ) -> Result<String> {
+    let file = generate_rust_file_with_options(module, type_mapper, FileOptions::default())?;
+    Ok(file.code)
+}
+
+/// Generate the file along with a source map back to `python_source`, the
+/// source `module` was converted from. With `line_comments`, the first line
+/// of each statement ends in a `// py:LINE` comment.
+pub fn generate_rust_file_with_source_map(
+    module: &HirModule,
+    type_mapper: &crate::type_mapper::TypeMapper,
+    python_source: &str,
+    line_comments: bool,
+) -> Result<(String, SourceMap)> {
+    let options = FileOptions {
+        python_source: Some(python_source),
+        source_map: true,
+        line_comments,
+        ..Default::default()
+    };
+    let file = generate_rust_file_with_options(module, type_mapper, options)?;
+    Ok((file.code, file.source_map))
+}
+
+/// Generate the file, stubbing out each function that fails to generate as
+/// with `fallback = "manual"`. Returns the constructs skipped, both here
+/// and converting `module` from `python_source`.
+pub fn generate_rust_file_best_effort(
+    module: &HirModule,
+    type_mapper: &crate::type_mapper::TypeMapper,
+    python_source: &str,
+) -> Result<(String, Vec<SkippedConstruct>)> {
+    let options = FileOptions {
+        python_source: Some(python_source),
+        best_effort: true,
+        ..Default::default()
+    };
+    let file = generate_rust_file_with_options(module, type_mapper, options)?;
+    Ok((file.code, file.skipped))
+}
+
+/// How `generate_rust_file_with_options` generates a file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileOptions<'a> {
+    /// The source the module was converted from, which stubs quote and
+    /// source maps point into
+    pub python_source: Option<&'a str>,
+    pub source_map: bool,
+    /// End the first line of each statement in a `// py:LINE` comment
+    pub line_comments: bool,
+    /// Stub out every function that fails to generate
+    pub best_effort: bool,
+    /// What the module imports from other modules of its package
+    pub imports: &'a [Imported<'a>],
+}
+
+/// Something a module imports from another module of its package, which
+/// its code uses as if it were defined locally
+#[derive(Debug, Clone, Copy)]
+pub enum Imported<'a> {
+    /// `from module import item as name`
+    Item {
+        name: &'a str,
+        item: &'a str,
+        module: &'a HirModule,
+    },
+    /// `from package import module as name`, whose items are called as
+    /// `name.item(...)`, or `import package.module`, whose are called by the
+    /// module's dotted name, `package.module.item(...)`
+    Module {
+        name: &'a str,
+        module: &'a HirModule,
+    },
+}
+
+/// A generated file; the source map is empty unless requested
+#[derive(Debug, Clone)]
+pub struct GeneratedFile {
+    pub code: String,
+    pub source_map: SourceMap,
+    /// Functions stubbed out, converting the module or generating it
+    pub skipped: Vec<SkippedConstruct>,
+}
+
+/// Generate and format the file, with the stubs of functions that failed
+/// filled in
+pub fn generate_rust_file_with_options(
+    module: &HirModule,
+    type_mapper: &crate::type_mapper::TypeMapper,
+    options: FileOptions,
+) -> Result<GeneratedFile> {
+    let (file, mut skipped) = generate_file(module, type_mapper, &options)?;
+    let (code, mut map) = match options.python_source {
+        Some(python_source) if options.source_map => SourceMap::extract(
+            &file.to_string(),
+            format_rust_code,
+            python_source,
+            options.line_comments,
+        ),
+        _ => (format_rust_code(file.to_string()), SourceMap::default()),
+    };
+    let (code, inserted) = fallback::expand_stubs(&code, &mut skipped, options.python_source);
+    for (line, count) in inserted.into_iter().rev() {
+        map.insert_lines(line, count);
+    }
+    Ok(GeneratedFile {
+        code,
+        source_map: map,
+        skipped,
+    })
+}
+
+fn generate_file(
+    module: &HirModule,
+    type_mapper: &crate::type_mapper::TypeMapper,
+    options: &FileOptions,
+) -> Result<(proc_macro2::TokenStream, Vec<SkippedConstruct>)> {
+    let exceptions = ExceptionTable::for_module(module);
+    // Exception classes become error variants rather than structs
+    let classes: Vec<&HirClass> = module
+        .classes
+        .iter()
+        .filter(|c| !exceptions.is_exception(&c.name))
+        .collect();
     let mut ctx = CodeGenContext {
         type_mapper,
         annotation_aware_mapper: AnnotationAwareTypeMapper::with_base_mapper(type_mapper.clone()),
@@ -59,14 +700,117 @@ pub fn generate_rust_file(
         needs_arc: false,
         needs_rc: false,
         needs_cow: false,
+        needs_hashset: false,
+        set_types: BTreeSet::new(),
+        needs_bigint: false,
+        num_traits: BTreeSet::new(),
+        arithmetic_helpers: BTreeSet::new(),
+        string_helpers: BTreeSet::new(),
         declared_vars: vec![HashSet::new()],
+        class_names: classes.iter().map(|c| c.name.clone()).collect(),
+        class_fields: HashMap::new(),
+        current_class: None,
+        error_variants: exceptions::raised_exception_names(module, &exceptions),
+        exceptions,
+        fallible_calls: fallible_call_keys(module),
+        best_effort: options.best_effort,
+        skipped: module.skipped.clone(),
+        error_scope: ErrorScope::default(),
+        ints: IntScope::default(),
+        set_type: SetType::default(),
+        big_int_functions: bigint::signatures(module),
+        ret_type: Type::Unknown,
+        return_types: module
+            .functions
+            .iter()
+            .map(|f| (f.name.clone(), f.ret_type.clone()))
+            .collect(),
+        enum_params: enum_params(module),
+        borrowed_vars: HashSet::new(),
+        guarded_classes: context_managers::guarded_classes(module),
+        var_types: HashMap::new(),
+        int_signs: HashMap::new(),
+        file_vars: HashSet::new(),
+        guard_vars: HashMap::new(),
+        enums: module
+            .classes
+            .iter()
+            .filter(|c| c.is_enum())
+            .map(|c| {
+                (
+                    c.name.clone(),
+                    c.enum_members().into_iter().cloned().collect(),
+                )
+            })
+            .collect(),
+        class_defs: module
+            .classes
+            .iter()
+            .map(|c| (c.name.clone(), c.clone()))
+            .collect(),
+        kwargs_structs: kwargs_structs(module)
+            .map(|(name, spec, _)| (name.to_string(), spec.kwargs_fields.clone()))
+            .collect(),
+        closures: ClosureScope::default(),
+        borrows: BorrowTable::for_module(module, type_mapper.string_type),
+        borrow_scope: BorrowScope::default(),
+        mutating_methods: module
+            .classes
+            .iter()
+            .flat_map(|c| &c.methods)
+            .filter(|m| mutates_self(&m.body))
+            .map(|m| m.name.clone())
+            .collect(),
+        live: LiveScope::default(),
+        source_markers: options.source_map,
+        module_aliases: HashMap::new(),
     };
+    for imported in options.imports {
+        ctx.import(imported);
+    }
+
+    let type_enums = type_enums::module_enum_types(module)
+        .iter()
+        .map(|ty| {
+            let members = match ty {
+                Type::Union(members) => members
+                    .iter()
+                    .map(|member| {
+                        let rust_type = ctx.type_mapper.map_type(member);
+                        update_import_needs(&mut ctx, &rust_type);
+                        rust_type_to_syn(&rust_type)
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                _ => Vec::new(),
+            };
+            Ok(type_enums::definition(ty, &members))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Convert all classes and functions first to detect what imports we need
+    let classes: Vec<_> = classes
+        .into_iter()
+        .map(|c| c.to_rust_tokens(&mut ctx))
+        .collect::<Result<Vec<_>>>()?;
+
+    let kwargs_structs: Vec<_> = kwargs_structs(module)
+        .map(|(name, spec, annotations)| generate_kwargs_struct(name, spec, annotations, &mut ctx))
+        .collect::<Result<Vec<_>>>()?;
 
-    // Convert all functions first to detect what imports we need
     let functions: Vec<_> = module
         .functions
         .iter()
-        .map(|f| f.to_rust_tokens(&mut ctx))
+        .map(|f| {
+            let tokens = generate_or_stub(
+                &f.name,
+                f.span,
+                &f.annotations,
+                &mut ctx,
+                |ctx| f.to_rust_tokens(ctx),
+                |stub, ctx| generate_stub(f, stub, ctx),
+            )?;
+            Ok(ctx.mark(f.span, tokens))
+        })
         .collect::<Result<Vec<_>>>()?;
 
     let mut items = Vec::new();
@@ -108,319 +852,6003 @@ pub fn generate_rust_file(
         });
     }
 
-    // Add all functions
-    items.extend(functions);
-
-    let file = quote! {
-        #(#items)*
-    };
+    if ctx.needs_hashset {
+        items.push(quote! {
+            use std::collections::HashSet;
+        });
+    }
 
-    Ok(format_rust_code(file.to_string()))
-}
+    items.extend(ctx.set_types.iter().map(|set_type| set_type.import()));
 
-impl RustCodeGen for HirFunction {
-    fn to_rust_tokens(&self, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
-        let name = syn::Ident::new(&self.name, proc_macro2::Span::call_site());
+    if ctx.needs_bigint {
+        items.push(quote! {
+            use num_bigint::BigInt;
+        });
+    }
 
-        // Convert parameters using annotation-aware mapping
-        let params: Vec<_> = self
-            .params
+    if !ctx.num_traits.is_empty() {
+        let traits = ctx
+            .num_traits
             .iter()
-            .map(|(param_name, param_type)| {
-                let param_ident = syn::Ident::new(param_name, proc_macro2::Span::call_site());
-                let rust_type = ctx
-                    .annotation_aware_mapper
-                    .map_type_with_annotations(param_type, &self.annotations);
+            .map(|t| syn::Ident::new(t, proc_macro2::Span::call_site()));
+        items.push(quote! {
+            use num_traits::{#(#traits),*};
+        });
+    }
 
-                // Check if we need special imports
-                update_import_needs(ctx, &rust_type);
+    if needs_error_enum(module, &ctx.error_variants) {
+        items.push(exceptions::error_enum_tokens(&ctx.error_variants));
+    }
 
-                let ty = rust_type_to_syn(&rust_type)?;
+    if !ctx.arithmetic_helpers.is_empty() {
+        let int = rust_type_to_syn(&ctx.type_mapper.map_type(&Type::Int))?;
+        items.extend(ctx.arithmetic_helpers.iter().map(|h| h.tokens(&int)));
+    }
+    items.extend(ctx.string_helpers.iter().map(|h| h.tokens()));
 
-                // Use references based on annotations
-                let ty = if ctx
-                    .annotation_aware_mapper
-                    .needs_reference_with_annotations(&rust_type, &self.annotations)
-                {
-                    parse_quote! { &#ty }
-                } else {
-                    ty
-                };
+    // Add the enums of union and literal types, all classes, then functions
+    items.extend(type_enums);
+    items.extend(classes);
+    items.extend(kwargs_structs);
+    items.extend(functions);
 
-                Ok(quote! { #param_ident: #ty })
-            })
-            .collect::<Result<Vec<_>>>()?;
+    let file = quote! {
+        #(#items)*
+    };
+    Ok((file, ctx.skipped))
+}
 
-        // Convert return type using annotation-aware mapping
-        let rust_ret_type = ctx
-            .annotation_aware_mapper
-            .map_return_type_with_annotations(&self.ret_type, &self.annotations);
-        let return_type = if matches!(rust_ret_type, crate::type_mapper::RustType::Unit) {
-            quote! {}
-        } else {
-            let ty = rust_type_to_syn(&rust_ret_type)?;
-            quote! { -> #ty }
+/// Parameter types of the module's functions and class constructors that
+/// take `Union` or `Literal` values, or lists, by name
+fn enum_params(module: &HirModule) -> HashMap<Symbol, Vec<Type>> {
+    let functions = module
+        .functions
+        .iter()
+        .map(|f| (&f.name, f.params.iter().map(|(_, ty)| ty.clone()).collect()));
+    // A constructor takes the parameters of `__init__`, or a dataclass's fields
+    let constructors = module.classes.iter().map(|c| {
+        let params: Vec<Type> = match c.init_method() {
+            Some(init) => init.params.iter().map(|(_, ty)| ty.clone()).collect(),
+            None => c
+                .fields
+                .iter()
+                .filter(|f| !f.is_class_var)
+                .map(|f| f.field_type.clone())
+                .collect(),
         };
+        (&c.name, params)
+    });
+    functions
+        .chain(constructors)
+        .filter(|(_, params)| {
+            params
+                .iter()
+                .any(|ty| type_enums::enum_name(ty).is_some() || matches!(ty, Type::List(_)))
+        })
+        .map(|(name, params)| (name.clone(), params))
+        .collect()
+}
 
-        // Enter function scope and declare parameters
-        ctx.enter_scope();
-        for (param_name, _) in &self.params {
-            ctx.declare_var(param_name);
-        }
-
-        // Convert body
-        let body_stmts: Vec<_> = self
-            .body
-            .iter()
-            .map(|stmt| stmt.to_rust_tokens(ctx))
-            .collect::<Result<Vec<_>>>()?;
+/// Functions and methods passing `**kwargs` as an options struct, with the
+/// struct's name
+fn kwargs_structs(
+    module: &HirModule,
+) -> impl Iterator<
+    Item = (
+        &str,
+        &ParamSpec,
+        &depyler_annotations::TranspilationAnnotations,
+    ),
+> {
+    let functions = module
+        .functions
+        .iter()
+        .map(|f| (&f.params, &f.param_spec, &f.annotations));
+    let methods = module
+        .classes
+        .iter()
+        .flat_map(|c| &c.methods)
+        .map(|m| (&m.params, &m.param_spec, &m.annotations));
+    functions
+        .chain(methods)
+        .filter_map(|(params, spec, annotations)| {
+            let (kwargs, _) = spec.var_keyword.as_ref()?;
+            params.iter().find_map(|(name, ty)| match ty {
+                Type::Custom(s) if name == kwargs => Some((s.as_str(), spec, annotations)),
+                _ => None,
+            })
+        })
+}
 
-        ctx.exit_scope();
+/// The options struct passing `**kwargs`, one optional field per keyword
+fn generate_kwargs_struct(
+    name: &str,
+    spec: &ParamSpec,
+    annotations: &depyler_annotations::TranspilationAnnotations,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let name = syn::Ident::new(name, proc_macro2::Span::call_site());
+    let value_type = spec
+        .var_keyword
+        .as_ref()
+        .map(|(_, ty)| ty.clone())
+        .unwrap_or(Type::Unknown);
+    let rust_type = ctx
+        .annotation_aware_mapper
+        .map_type_with_annotations(&value_type, annotations);
+    update_import_needs(ctx, &rust_type);
+    let ty = rust_type_to_syn(&rust_type)?;
+    let fields = spec
+        .kwargs_fields
+        .iter()
+        .map(|field| syn::Ident::new(field, proc_macro2::Span::call_site()));
+    Ok(quote! {
+        #[derive(Debug, Clone, Default)]
+        pub struct #name {
+            #(pub #fields: Option<#ty>,)*
+        }
+    })
+}
 
-        // Add documentation
-        let mut attrs = vec![];
+/// Whether a function that can raise is lowered to return `Result`
+fn returns_result(
+    properties: &FunctionProperties,
+    annotations: &depyler_annotations::TranspilationAnnotations,
+) -> bool {
+    properties.can_fail && !properties.is_generator && !exceptions::panics_on_error(annotations)
+}
 
-        // Add docstring as documentation if present
-        if let Some(docstring) = &self.docstring {
-            attrs.push(quote! {
-                #[doc = #docstring]
-            });
+/// Call keys (see [`exceptions::call_key`]) of everything that returns `Result`
+fn fallible_call_keys(module: &HirModule) -> HashSet<String> {
+    let mut keys: HashSet<String> = module
+        .functions
+        .iter()
+        .filter(|f| returns_result(&f.properties, &f.annotations))
+        .map(|f| f.name.clone())
+        .collect();
+    for class in &module.classes {
+        for method in &class.methods {
+            if returns_result(&method.properties, &method.annotations) {
+                keys.insert(format!("{}.{}", class.name, method.name));
+            }
         }
+    }
+    keys
+}
 
-        if self.properties.panic_free {
-            attrs.push(quote! {
-                #[doc = " Depyler: verified panic-free"]
-            });
-        }
-        if self.properties.always_terminates {
-            attrs.push(quote! {
-                #[doc = " Depyler: proven to terminate"]
-            });
+/// Whether anything outside a Lambda handler returns the generated `PythonError`
+fn needs_error_enum(module: &HirModule, variants: &[String]) -> bool {
+    // A `try` catching what its own body raises names the enum too
+    let uses_enum = |properties: &FunctionProperties,
+                     annotations: &depyler_annotations::TranspilationAnnotations,
+                     body: &[Spanned<HirStmt>]| {
+        annotations.lambda_annotations.is_none()
+            && (returns_result(properties, annotations)
+                || (!variants.is_empty() && contains_try(body)))
+    };
+    module
+        .functions
+        .iter()
+        .any(|f| uses_enum(&f.properties, &f.annotations, &f.body))
+        || module
+            .classes
+            .iter()
+            .flat_map(|c| &c.methods)
+            .any(|m| uses_enum(&m.properties, &m.annotations, &m.body))
+}
+
+/// Whether `body` has a `try` statement, nested ones included
+fn contains_try(body: &[Spanned<HirStmt>]) -> bool {
+    body.iter().any(|stmt| match &stmt.node {
+        HirStmt::Try { .. } => true,
+        HirStmt::If {
+            then_body,
+            else_body,
+            ..
+        } => contains_try(then_body) || else_body.as_deref().is_some_and(contains_try),
+        HirStmt::While { body, .. } | HirStmt::For { body, .. } | HirStmt::With { body, .. } => {
+            contains_try(body)
         }
+        HirStmt::Match { cases, .. } => cases.iter().any(|c| contains_try(&c.body)),
+        HirStmt::FunctionDef { func, .. } => contains_try(&func.body),
+        _ => false,
+    })
+}
 
-        Ok(quote! {
-            #(#attrs)*
-            pub fn #name(#(#params),*) #return_type {
-                #(#body_stmts)*
+impl RustCodeGen for HirFunction {
+    fn to_rust_tokens(&self, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
+        if self.properties.is_generator {
+            if self.is_async {
+                transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                    "Async generator '{}' is not supported",
+                    self.name
+                )));
             }
-        })
+            return generate_generator(self, ctx);
+        }
+        let borrows = ctx
+            .borrows
+            .function(&self.name)
+            .cloned()
+            .unwrap_or_default();
+        generate_function(self, &borrows, quote! { pub }, ctx)
     }
 }
 
-impl RustCodeGen for HirStmt {
-    fn to_rust_tokens(&self, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
-        match self {
-            HirStmt::Assign { target, value } => {
+/// A function item; nested functions that capture nothing are private items
+/// of the enclosing body
+fn generate_function(
+    func: &HirFunction,
+    borrows: &FunctionBorrows,
+    visibility: proc_macro2::TokenStream,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let name = syn::Ident::new(&func.name, proc_macro2::Span::call_site());
+    let scope = BorrowScope::new(&func.params, borrows, false);
+    let effects = Effects::of(&func.body, &ctx.mutating_methods);
+    let params = generate_params(&func.params, &func.annotations, &scope, &effects, ctx)?;
+    let generics = scope.named_lifetime.then(|| quote! { <'a> });
+    ctx.borrow_scope = scope;
+    ctx.live = LiveScope::for_function();
+    ctx.error_scope = ErrorScope::new(
+        returns_result(&func.properties, &func.annotations),
+        &func.annotations,
+    );
+    ctx.ints = IntScope::new(&func.params, &func.body, &func.ret_type, &func.annotations);
+    ctx.set_type = SetType::new(&func.annotations);
+    // An async `main` is the program's entry point, as with `asyncio.run(main())`
+    let is_entry_point = func.is_async && func.name == "main" && func.params.is_empty();
+    let ret_type = match &func.ret_type {
+        Type::Unknown if is_entry_point => Type::None,
+        ret_type => ret_type.clone(),
+    };
+
+    // Enter function scope and declare parameters
+    ctx.enter_scope();
+    for (param_name, _) in &func.params {
+        ctx.declare_var(param_name);
+    }
+    ctx.var_types = func.params.iter().cloned().collect();
+    ctx.closures = ClosureScope::for_body(&func.body);
+
+    // Convert body; returned closures decide the trait of an `impl Fn` return type
+    let body_stmts = generate_fallible_body(&func.body, &ret_type, ctx)?;
+    let return_type = generate_return_type(&ret_type, &func.annotations, ctx)?;
+
+    ctx.exit_scope();
+    ctx.var_types.clear();
+    ctx.int_signs.clear();
+    ctx.error_scope = ErrorScope::default();
+    ctx.ints = IntScope::default();
+    ctx.set_type = SetType::default();
+    ctx.closures = ClosureScope::default();
+    ctx.borrow_scope = BorrowScope::default();
+    ctx.live = LiveScope::default();
+
+    let panics = emits_panic(&quote! { #(#body_stmts)* });
+    let mut attrs = generate_doc_attrs(&func.docstring, &func.properties, panics);
+    if is_entry_point {
+        attrs.push(quote! { #[tokio::main] });
+    }
+    let asyncness = func.is_async.then(|| quote! { async });
+
+    Ok(quote! {
+        #(#attrs)*
+        #visibility #asyncness fn #name #generics(#(#params),*) #return_type {
+            #(#body_stmts)*
+        }
+    })
+}
+
+/// The code `generate` makes for the function or method `name`; or its
+/// stub, as `stub` makes it, when it was stubbed out converting the module
+/// or fails to generate and its fallback allows stubbing it out
+fn generate_or_stub(
+    name: &str,
+    span: Span,
+    annotations: &depyler_annotations::TranspilationAnnotations,
+    ctx: &mut CodeGenContext,
+    generate: impl FnOnce(&mut CodeGenContext) -> Result<proc_macro2::TokenStream>,
+    stub: impl FnOnce(usize, &mut CodeGenContext) -> Result<proc_macro2::TokenStream>,
+) -> Result<proc_macro2::TokenStream> {
+    if let Some(index) = ctx.skipped.iter().position(|s| s.function == name) {
+        return stub(index, ctx);
+    }
+    let scopes = ctx.declared_vars.len();
+    match generate(ctx).at(span) {
+        Ok(tokens) => Ok(tokens),
+        Err(err) if fallback::falls_back(ctx.best_effort, annotations) => {
+            ctx.abandon_function(scopes);
+            ctx.skipped
+                .push(SkippedConstruct::from_error(name, span, &err));
+            stub(ctx.skipped.len() - 1, ctx)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// A function that failed to transpile: its signature, and a body marking
+/// it as stub `stub` for `fallback::expand_stubs` to fill in
+fn generate_stub(
+    func: &HirFunction,
+    stub: usize,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let borrows = ctx
+        .borrows
+        .function(&func.name)
+        .cloned()
+        .unwrap_or_default();
+    generate_stub_with(func, &borrows, None, stub, ctx)
+}
+
+/// A method that failed to transpile, as [`generate_stub`]; not knowing what
+/// it does with its receiver, it borrows it immutably
+fn generate_method_stub(
+    method: &HirMethod,
+    stub: usize,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let borrows = ctx
+        .borrows
+        .method(&method.name)
+        .cloned()
+        .unwrap_or_default();
+    let func = HirFunction {
+        name: method.name.clone(),
+        params: method.params.clone(),
+        param_spec: method.param_spec.clone(),
+        ret_type: method.ret_type.clone(),
+        body: vec![],
+        properties: method.properties.clone(),
+        annotations: method.annotations.clone(),
+        docstring: method.docstring.clone(),
+        is_async: method.is_async,
+        span: method.span,
+    };
+    let receiver = matches!(method.kind, MethodKind::Instance | MethodKind::Property)
+        .then(|| quote! { &self });
+    generate_stub_with(&func, &borrows, receiver, stub, ctx)
+}
+
+fn generate_stub_with(
+    func: &HirFunction,
+    borrows: &FunctionBorrows,
+    receiver: Option<proc_macro2::TokenStream>,
+    stub: usize,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let name = syn::Ident::new(&func.name, proc_macro2::Span::call_site());
+    let scope = BorrowScope::new(&func.params, borrows, receiver.is_some());
+    let mut params = generate_params(
+        &func.params,
+        &func.annotations,
+        &scope,
+        &Effects::default(),
+        ctx,
+    )?;
+    params.splice(0..0, receiver);
+    let generics = scope.named_lifetime.then(|| quote! { <'a> });
+    ctx.borrow_scope = scope;
+    ctx.error_scope = ErrorScope::new(
+        returns_result(&func.properties, &func.annotations),
+        &func.annotations,
+    );
+    let return_type = generate_return_type(&func.ret_type, &func.annotations, ctx)?;
+    ctx.error_scope = ErrorScope::default();
+    ctx.borrow_scope = BorrowScope::default();
+
+    // A stub panics, whatever the Python it stands for would do
+    let attrs = generate_doc_attrs(&func.docstring, &FunctionProperties::default(), false);
+    let asyncness = func.is_async.then(|| quote! { async });
+    let body = fallback::marker(stub);
+    Ok(quote! {
+        #(#attrs)*
+        #[allow(unused_variables)]
+        pub #asyncness fn #name #generics(#(#params),*) #return_type {
+            #body
+        }
+    })
+}
+
+/// The fields of a generator a loop target of locals is stored in
+fn field_pattern(target: &AssignTarget) -> proc_macro2::TokenStream {
+    match target {
+        AssignTarget::Tuple(targets) => {
+            let targets = targets.iter().map(field_pattern);
+            quote! { (#(#targets),*) }
+        }
+        target => {
+            let name = target
+                .as_symbol()
+                .expect("generator loops bind plain names");
+            // `for _ in ...` keeps nothing
+            if name == "_" {
+                return quote! { _ };
+            }
+            let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+            quote! { self.#ident }
+        }
+    }
+}
+
+/// Lower a generator function to a struct implementing `Iterator`, built by a
+/// function of the original name.
+///
+/// Each state of the [`StateMachine`] becomes an arm of a `match` in `next`;
+/// parameters, locals and loop iterators are kept in fields between calls.
+fn generate_generator(
+    func: &HirFunction,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let machine = StateMachine::build(func)?;
+    let name = syn::Ident::new(&func.name, proc_macro2::Span::call_site());
+    let struct_name = syn::Ident::new(
+        &generator_struct_name(&func.name),
+        proc_macro2::Span::call_site(),
+    );
+    let params = generate_stored_params(&func.params, &func.annotations, ctx)?;
+    let item_rust_type = ctx.type_mapper.map_type(&machine.item_type);
+    update_import_needs(ctx, &item_rust_type);
+    let item_ty = rust_type_to_syn(&item_rust_type)?;
+
+    let mut field_defs = Vec::new();
+    let mut field_inits = Vec::new();
+    for (param_name, param_type) in &func.params {
+        let ident = syn::Ident::new(param_name, proc_macro2::Span::call_site());
+        let rust_type = ctx
+            .annotation_aware_mapper
+            .map_type_with_annotations(param_type, &func.annotations);
+        let ty = rust_type_to_syn(&rust_type)?;
+        field_defs.push(quote! { #ident: #ty });
+        // Borrowed parameters are copied so the generator owns its state
+        if matches!(param_type, Type::Function { .. }) {
+            field_inits.push(quote! { #ident: Box::new(#ident) });
+        } else if ctx
+            .annotation_aware_mapper
+            .needs_reference_with_annotations(&rust_type, &func.annotations, ParamPassing::Owned)
+        {
+            field_inits.push(quote! { #ident: #ident.clone() });
+        } else {
+            field_inits.push(quote! { #ident });
+        }
+    }
+    for (local, local_type) in &machine.locals {
+        let ident = syn::Ident::new(local, proc_macro2::Span::call_site());
+        let rust_type = ctx.type_mapper.map_type(local_type);
+        update_import_needs(ctx, &rust_type);
+        let ty = rust_type_to_syn(&rust_type)?;
+        field_defs.push(quote! { #ident: #ty });
+        field_inits.push(quote! { #ident: Default::default() });
+    }
+    for (slot, slot_type) in machine.iter_slots.iter().enumerate() {
+        let ident = iter_slot_ident(slot);
+        let rust_type = ctx.type_mapper.map_type(slot_type);
+        let ty = rust_type_to_syn(&rust_type)?;
+        field_defs.push(quote! { #ident: Option<Box<dyn Iterator<Item = #ty>>> });
+        field_inits.push(quote! { #ident: None });
+    }
+
+    // States read and write the struct's fields like a method would
+    ctx.class_fields = func
+        .params
+        .iter()
+        .chain(&machine.locals)
+        .map(|(field_name, field_type)| {
+            let field = HirField {
+                name: field_name.clone(),
+                field_type: field_type.clone(),
+                default_value: None,
+                is_class_var: false,
+            };
+            (field_name.clone(), field)
+        })
+        .collect();
+    ctx.enter_scope();
+    ctx.declare_var("self");
+    let arms = machine
+        .states
+        .iter()
+        .enumerate()
+        .map(|(i, state)| {
+            let index = proc_macro2::Literal::usize_unsuffixed(i);
+            let ops = state
+                .ops
+                .iter()
+                .map(|op| generate_generator_op(op, ctx))
+                .collect::<Result<Vec<_>>>()?;
+            let exit = generate_generator_exit(&state.exit, ctx)?;
+            Ok(quote! {
+                #index => {
+                    #(#ops)*
+                    #exit
+                }
+            })
+        })
+        .collect::<Result<Vec<_>>>();
+    ctx.exit_scope();
+    ctx.class_fields.clear();
+    let arms = arms?;
+
+    let panics = emits_panic(&quote! { #(#arms)* });
+    let attrs = generate_doc_attrs(&func.docstring, &func.properties, panics);
+
+    Ok(quote! {
+        pub struct #struct_name {
+            state: usize,
+            #(#field_defs),*
+        }
+        impl Iterator for #struct_name {
+            type Item = #item_ty;
+            fn next(&mut self) -> Option<Self::Item> {
+                loop {
+                    match self.state {
+                        #(#arms)*
+                        _ => return None,
+                    }
+                }
+            }
+        }
+        #(#attrs)*
+        pub fn #name(#(#params),*) -> #struct_name {
+            #struct_name {
+                state: 0,
+                #(#field_inits),*
+            }
+        }
+    })
+}
+
+/// `count_up` becomes `CountUpGenerator`
+fn generator_struct_name(func_name: &str) -> String {
+    let mut name = arguments::pascal_case(func_name);
+    name.push_str("Generator");
+    name
+}
+
+fn iter_slot_ident(slot: usize) -> syn::Ident {
+    syn::Ident::new(&format!("iter_{slot}"), proc_macro2::Span::call_site())
+}
+
+fn generate_generator_op(op: &GenOp, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
+    match op {
+        GenOp::Stmt(stmt) => stmt.to_rust_tokens(ctx),
+        GenOp::StartIter { slot, iter } => {
+            let ident = iter_slot_ident(*slot);
+            let iter = builtins::operand_expr(&iter.to_rust_expr(ctx)?);
+            Ok(quote! { self.#ident = Some(Box::new(#iter.into_iter())); })
+        }
+    }
+}
+
+fn generate_generator_exit(
+    exit: &GenExit,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let state = |i: &usize| proc_macro2::Literal::usize_unsuffixed(*i);
+    Ok(match exit {
+        GenExit::Goto(next) => {
+            let next = state(next);
+            quote! { self.state = #next; }
+        }
+        GenExit::Branch {
+            condition,
+            then,
+            otherwise,
+        } => {
+            let condition = condition.to_rust_expr(ctx)?;
+            let (then, otherwise) = (state(then), state(otherwise));
+            quote! { self.state = if #condition { #then } else { #otherwise }; }
+        }
+        GenExit::Yield { value, next } => {
+            let value = match value {
+                Some(value) => unparen(value.to_rust_expr(ctx)?),
+                None => parse_quote! { () },
+            };
+            let next = state(next);
+            quote! {
+                self.state = #next;
+                return Some(#value);
+            }
+        }
+        GenExit::Next {
+            slot,
+            target,
+            body,
+            done,
+        } => {
+            let ident = iter_slot_ident(*slot);
+            let target = field_pattern(target);
+            let (body, done) = (state(body), state(done));
+            quote! {
+                match self.#ident.as_mut().and_then(|it| it.next()) {
+                    Some(item) => {
+                        #target = item;
+                        self.state = #body;
+                    }
+                    None => {
+                        self.#ident = None;
+                        self.state = #done;
+                    }
+                }
+            }
+        }
+        GenExit::Delegate { slot, done } => {
+            let ident = iter_slot_ident(*slot);
+            let done = state(done);
+            quote! {
+                match self.#ident.as_mut().and_then(|it| it.next()) {
+                    Some(item) => return Some(item),
+                    None => {
+                        self.#ident = None;
+                        self.state = #done;
+                    }
+                }
+            }
+        }
+        GenExit::Finish => quote! {
+            self.state = usize::MAX;
+            return None;
+        },
+    })
+}
+
+/// Convert parameters using annotation-aware mapping
+fn generate_params(
+    params: &[(Symbol, Type)],
+    annotations: &depyler_annotations::TranspilationAnnotations,
+    scope: &BorrowScope,
+    body: &Effects,
+    ctx: &mut CodeGenContext,
+) -> Result<Vec<proc_macro2::TokenStream>> {
+    generate_params_with(params, annotations, scope, body, false, ctx)
+}
+
+/// Parameters of a constructor, whose callables are boxed into fields and so
+/// must not borrow from the caller
+fn generate_stored_params(
+    params: &[(Symbol, Type)],
+    annotations: &depyler_annotations::TranspilationAnnotations,
+    ctx: &mut CodeGenContext,
+) -> Result<Vec<proc_macro2::TokenStream>> {
+    generate_params_with(
+        params,
+        annotations,
+        &BorrowScope::default(),
+        &Effects::default(),
+        true,
+        ctx,
+    )
+}
+
+/// Parameters as the function takes them; those the body assigns to or
+/// modifies are `mut`
+fn generate_params_with(
+    params: &[(Symbol, Type)],
+    annotations: &depyler_annotations::TranspilationAnnotations,
+    scope: &BorrowScope,
+    body: &Effects,
+    stored: bool,
+    ctx: &mut CodeGenContext,
+) -> Result<Vec<proc_macro2::TokenStream>> {
+    params
+        .iter()
+        .map(|(param_name, param_type)| {
+            let param_ident = syn::Ident::new(param_name, proc_macro2::Span::call_site());
+            let passing = scope.passing(param_name);
+            let rust_type = if scope.named_lifetime && scope.returned.contains(param_name) {
+                crate::type_mapper::RustType::Str {
+                    lifetime: Some("'a".to_string()),
+                }
+            } else {
+                ctx.annotation_aware_mapper.map_param_type_with_annotations(
+                    param_type,
+                    annotations,
+                    passing,
+                )
+            };
+
+            // Check if we need special imports
+            update_import_needs(ctx, &rust_type);
+
+            // Callables are taken by value as `impl Fn`
+            if let crate::type_mapper::RustType::Function { params, ret } = &rust_type {
+                let bound = closure_bound(ClosureKind::Fn, params, ret)?;
+                let lifetime = stored.then(|| quote! { + 'static });
+                return Ok(quote! { #param_ident: impl #bound #lifetime });
+            }
+
+            let ty = rust_type_to_syn(&rust_type)?;
+
+            // Use references based on annotations
+            let ty = if ctx
+                .annotation_aware_mapper
+                .needs_reference_with_annotations(&rust_type, annotations, passing)
+            {
+                parse_quote! { &#ty }
+            } else {
+                ty
+            };
+
+            let mutability = body.writes(param_name).then(|| quote! { mut });
+            Ok(quote! { #mutability #param_ident: #ty })
+        })
+        .collect()
+}
+
+/// Convert return type using annotation-aware mapping, wrapped in `Result`
+/// when the current error scope returns errors
+fn generate_return_type(
+    ret_type: &Type,
+    annotations: &depyler_annotations::TranspilationAnnotations,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    if ctx.borrow_scope.lends {
+        let lifetime = ctx.borrow_scope.named_lifetime.then(|| quote! { 'a });
+        return Ok(quote! { -> &#lifetime str });
+    }
+    if ctx.error_scope.returns_result {
+        // The error type comes from the exception lowering, not the annotation's generic `Error`
+        let mut annotations = annotations.clone();
+        annotations.error_strategy = depyler_annotations::ErrorStrategy::Panic;
+        let rust_ret_type = ctx
+            .annotation_aware_mapper
+            .map_return_type_with_annotations(ret_type, &annotations);
+        update_import_needs(ctx, &rust_ret_type);
+        let ty = rust_type_to_syn(&rust_ret_type)?;
+        let error_ty = ctx.error_scope.error_type();
+        return Ok(quote! { -> Result<#ty, #error_ty> });
+    }
+    let rust_ret_type = ctx
+        .annotation_aware_mapper
+        .map_return_type_with_annotations(ret_type, annotations);
+    if matches!(rust_ret_type, crate::type_mapper::RustType::Unit) {
+        Ok(quote! {})
+    } else if let crate::type_mapper::RustType::Function { params, ret } = &rust_ret_type {
+        // Returned closures are unboxed, with the trait the returned values implement
+        update_import_needs(ctx, &rust_ret_type);
+        let kind = ctx.closures.returned.unwrap_or(ClosureKind::Fn);
+        let bound = closure_bound(kind, params, ret)?;
+        Ok(quote! { -> impl #bound })
+    } else {
+        update_import_needs(ctx, &rust_ret_type);
+        let ty = rust_type_to_syn(&rust_ret_type)?;
+        Ok(quote! { -> #ty })
+    }
+}
+
+fn generate_body(
+    body: &[Spanned<HirStmt>],
+    ctx: &mut CodeGenContext,
+) -> Result<Vec<proc_macro2::TokenStream>> {
+    let tail = ctx.live.block_in_tail();
+    let body = &tidy_body(body, tail, ctx);
+    let effects = Effects::of_each(body, &ctx.mutating_methods);
+    let mut stmts = body
+        .iter()
+        .zip(effects)
+        .enumerate()
+        .map(|(i, (stmt, (current, later)))| {
+            ctx.live.frames.push(LiveFrame {
+                current,
+                later,
+                tail: tail && i + 1 == body.len(),
+                branches: matches!(
+                    &stmt.node,
+                    HirStmt::If {
+                        else_body: Some(_),
+                        ..
+                    }
+                ),
+            });
+            let tokens = stmt.to_rust_tokens(ctx).at(stmt.span);
+            ctx.live.frames.pop();
+            Ok(ctx.mark(stmt.span, tokens?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // A name bound only to be returned is the value
+    if let (true, [.., bind, ret]) = (tail, stmts.as_slice()) {
+        if let (Ok(syn::Stmt::Local(local)), Ok(syn::Expr::Path(returned))) = (
+            syn::parse2::<syn::Stmt>(bind.clone()),
+            syn::parse2::<syn::Expr>(ret.clone()),
+        ) {
+            if let (syn::Pat::Ident(binding), Some(init)) = (&local.pat, &local.init) {
+                if returned.path.is_ident(&binding.ident) && init.diverge.is_none() {
+                    let mut value = (*init.expr).clone();
+                    returns_as_tail(&mut value);
+                    let value = quote! { #value };
+                    stmts.truncate(stmts.len() - 2);
+                    stmts.push(value);
+                }
+            }
+        }
+    }
+    // A nested function bound to a closure only to be returned is the value
+    if let [.., def, ret] = body.as_slice() {
+        if let (HirStmt::FunctionDef { func, .. }, HirStmt::Return(Some(HirExpr::Var(name)))) =
+            (&def.node, &ret.node)
+        {
+            let closure = match syn::parse2::<syn::Stmt>(stmts[stmts.len() - 2].clone()) {
+                Ok(syn::Stmt::Local(syn::Local {
+                    init: Some(init), ..
+                })) if tail && *name == func.name => Some(init.expr),
+                _ => None,
+            };
+            if let Some(closure) = closure {
+                stmts.truncate(stmts.len() - 2);
+                stmts.push(quote! { #closure });
+            }
+        }
+    }
+    Ok(stmts)
+}
+
+/// `body` with statements whose Rust would be needlessly roundabout folded:
+/// a list display followed by appends to it becomes one display of every
+/// item, and a block in tail position that binds a new name only to return
+/// it returns the value instead
+fn tidy_body(body: &[Spanned<HirStmt>], tail: bool, ctx: &CodeGenContext) -> Vec<Spanned<HirStmt>> {
+    let mut tidy: Vec<Spanned<HirStmt>> = Vec::with_capacity(body.len());
+    for stmt in body {
+        let appended = match &stmt.node {
+            HirStmt::Expr(HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                kwargs,
+            }) if method == "append" && args.len() == 1 && kwargs.is_empty() => match &**receiver {
+                HirExpr::Var(name) => Some((name, &args[0])),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let (Some((name, item)), Some(last)) = (appended, tidy.last_mut()) {
+            if let HirStmt::Assign {
+                target: AssignTarget::Symbol(target),
+                value: HirExpr::List(items),
+            } = &mut last.node
+            {
+                if target == name && !ctx.is_declared(name) && !expr_mentions(item, name) {
+                    items.push(item.clone());
+                    continue;
+                }
+            }
+        }
+        tidy.push(stmt.clone());
+    }
+    if let [.., assign, ret] = tidy.as_slice() {
+        if let (
+            HirStmt::Assign {
+                target: AssignTarget::Symbol(target),
+                value,
+            },
+            HirStmt::Return(Some(HirExpr::Var(returned))),
+        ) = (&assign.node, &ret.node)
+        {
+            if tail && target == returned && !ctx.is_declared(target) {
+                let stmt = Spanned::new(HirStmt::Return(Some(value.clone())), assign.span);
+                tidy.truncate(tidy.len() - 2);
+                tidy.push(stmt);
+            }
+        }
+    }
+    tidy
+}
+
+/// Whether `expr` reads the local `name`
+fn expr_mentions(expr: &HirExpr, name: &str) -> bool {
+    let mut found = false;
+    visit_expr(expr, &mut |e| {
+        found |= matches!(e, HirExpr::Var(v) if v == name)
+    });
+    found
+}
+
+fn generate_scoped_body(
+    body: &[Spanned<HirStmt>],
+    ctx: &mut CodeGenContext,
+) -> Result<Vec<proc_macro2::TokenStream>> {
+    ctx.enter_scope();
+    let stmts = generate_body(body, ctx);
+    ctx.exit_scope();
+    stmts
+}
+
+/// Function body; a unit function returning `Result` falls through to `Ok(())`
+fn generate_fallible_body(
+    body: &[Spanned<HirStmt>],
+    ret_type: &Type,
+    ctx: &mut CodeGenContext,
+) -> Result<Vec<proc_macro2::TokenStream>> {
+    let outer_ret_type = std::mem::replace(&mut ctx.ret_type, ret_type.clone());
+    let stmts = generate_body(body, ctx);
+    ctx.ret_type = outer_ret_type;
+    let mut stmts = stmts?;
+    let returns_unit = matches!(
+        ctx.type_mapper.map_return_type(ret_type),
+        crate::type_mapper::RustType::Unit
+    );
+    if ctx.error_scope.returns_result && returns_unit {
+        stmts.push(quote! { Ok(()) });
+    }
+    Ok(stmts)
+}
+
+/// Docstring and verified-property documentation attributes; a function
+/// whose generated code `panics` is not panic-free, whatever its Python does
+fn generate_doc_attrs(
+    docstring: &Option<String>,
+    properties: &FunctionProperties,
+    panics: bool,
+) -> Vec<proc_macro2::TokenStream> {
+    let mut attrs = vec![];
+
+    // Add docstring as documentation if present
+    if let Some(docstring) = docstring {
+        attrs.push(quote! {
+            #[doc = #docstring]
+        });
+    }
+
+    if properties.panic_free && !panics {
+        attrs.push(quote! {
+            #[doc = " Depyler: verified panic-free"]
+        });
+    }
+    if properties.always_terminates {
+        attrs.push(quote! {
+            #[doc = " Depyler: proven to terminate"]
+        });
+    }
+    attrs
+}
+
+/// Whether generated code calls a panicking macro or unwraps a value
+fn emits_panic(tokens: &proc_macro2::TokenStream) -> bool {
+    use proc_macro2::TokenTree;
+    let tokens: Vec<TokenTree> = tokens.clone().into_iter().collect();
+    tokens.iter().enumerate().any(|(i, token)| match token {
+        TokenTree::Group(group) => emits_panic(&group.stream()),
+        TokenTree::Ident(ident) => {
+            let name = ident.to_string();
+            let next_is_bang =
+                matches!(tokens.get(i + 1), Some(TokenTree::Punct(p)) if p.as_char() == '!');
+            let after_dot =
+                i > 0 && matches!(&tokens[i - 1], TokenTree::Punct(p) if p.as_char() == '.');
+            (next_is_bang && matches!(name.as_str(), "panic" | "unreachable" | "todo" | "assert"))
+                || (after_dot && matches!(name.as_str(), "expect" | "unwrap"))
+        }
+        _ => false,
+    })
+}
+
+impl RustCodeGen for HirClass {
+    fn to_rust_tokens(&self, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
+        if self.is_enum() {
+            return self.generate_enum(ctx);
+        }
+        let name = syn::Ident::new(&self.name, proc_macro2::Span::call_site());
+        ctx.current_class = Some(self.name.clone());
+        ctx.class_fields = self
+            .fields
+            .iter()
+            .map(|f| (f.name.clone(), f.clone()))
+            .collect();
+
+        let mut field_defs = Vec::new();
+        let mut field_types = Vec::new();
+        for field in self.fields.iter().filter(|f| !f.is_class_var) {
+            let field_ident = syn::Ident::new(&field.name, proc_macro2::Span::call_site());
+            let rust_type = ctx
+                .annotation_aware_mapper
+                .map_type_with_annotations(&field.field_type, &self.annotations);
+            update_import_needs(ctx, &rust_type);
+            let ty = rust_type_to_syn(&rust_type)?;
+            field_defs.push(quote! { pub #field_ident: #ty });
+            field_types.push(rust_type);
+        }
+
+        let derives = if !field_types.iter().all(|t| ctx.type_mapper.can_clone(t)) {
+            // Boxed closures implement none of the derivable traits
+            quote! {}
+        } else if self.is_dataclass {
+            if field_types
+                .iter()
+                .all(|t| ctx.type_mapper.supports_eq_hash(t))
+            {
+                quote! { #[derive(Debug, Clone, PartialEq, Eq, Hash)] }
+            } else {
+                quote! { #[derive(Debug, Clone, PartialEq)] }
+            }
+        } else {
+            quote! { #[derive(Debug, Clone)] }
+        };
+
+        let mut impl_items = Vec::new();
+        for field in self.fields.iter().filter(|f| f.is_class_var) {
+            impl_items.push(generate_class_constant(field, ctx)?);
+        }
+        if let Some(constructor) = self.generate_constructor(ctx)? {
+            impl_items.push(constructor);
+        }
+        let guarded = ctx.guarded_classes.contains_key(&self.name);
+        let mut exit_method = None;
+        for method in self.methods.iter().filter(|m| m.name != "__init__") {
+            match method.name.as_str() {
+                // The guard itself stands in for the instance
+                "__enter__" if guarded && context_managers::returns_self(method) => {}
+                "__exit__" if guarded => {
+                    let method = guard_exit_method(method)?;
+                    impl_items.push(generate_method_or_stub(&self.name, &method, ctx)?);
+                    exit_method = Some(method);
+                }
+                _ => impl_items.push(generate_method_or_stub(&self.name, method, ctx)?),
+            }
+        }
+        ctx.class_fields.clear();
+        ctx.current_class = None;
+
+        let doc = self
+            .docstring
+            .as_ref()
+            .map(|d| quote! { #[doc = #d] })
+            .unwrap_or_default();
+        let impl_block = if impl_items.is_empty() {
+            quote! {}
+        } else {
+            quote! {
+                impl #name {
+                    #(#impl_items)*
+                }
+            }
+        };
+
+        let guard = exit_method.map(|exit| self.generate_guard(&exit));
+
+        Ok(quote! {
+            #doc
+            #derives
+            pub struct #name {
+                #(#field_defs),*
+            }
+            #impl_block
+            #guard
+        })
+    }
+}
+
+impl HirClass {
+    /// `Enum` subclasses become fieldless enums, keeping integer values as discriminants
+    fn generate_enum(&self, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
+        let name = syn::Ident::new(&self.name, proc_macro2::Span::call_site());
+        let members: Vec<&HirField> = self.fields.iter().filter(|f| f.is_class_var).collect();
+        if members.is_empty() {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Enum '{}' has no members",
+                self.name
+            )));
+        }
+        let int_values = members
+            .iter()
+            .all(|m| matches!(m.default_value, Some(HirExpr::Literal(Literal::Int(_)))));
+        let variants = members.iter().map(|member| {
+            let variant = syn::Ident::new(&member.name, proc_macro2::Span::call_site());
+            match &member.default_value {
+                Some(HirExpr::Literal(Literal::Int(n))) if int_values => {
+                    let n = proc_macro2::Literal::i64_unsuffixed(*n);
+                    quote! { #variant = #n }
+                }
+                _ => quote! { #variant },
+            }
+        });
+        let allow = members
+            .iter()
+            .any(|m| m.name.contains('_'))
+            .then(|| quote! { #[allow(non_camel_case_types)] });
+
+        ctx.current_class = Some(self.name.clone());
+        let methods = self
+            .methods
+            .iter()
+            .map(|m| generate_method_or_stub(&self.name, m, ctx))
+            .collect::<Result<Vec<_>>>();
+        ctx.current_class = None;
+        let methods = methods?;
+        let impl_block = (!methods.is_empty()).then(|| {
+            quote! {
+                impl #name {
+                    #(#methods)*
+                }
+            }
+        });
+
+        let doc = self
+            .docstring
+            .as_ref()
+            .map(|d| quote! { #[doc = #d] })
+            .unwrap_or_default();
+        Ok(quote! {
+            #doc
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            #allow
+            pub enum #name {
+                #(#variants),*
+            }
+            #impl_block
+        })
+    }
+
+    /// Guard for `with` blocks over instances, calling `__exit__` when dropped
+    fn generate_guard(&self, exit: &HirMethod) -> proc_macro2::TokenStream {
+        let name = syn::Ident::new(&self.name, proc_macro2::Span::call_site());
+        let guard = guard_struct_ident(&self.name);
+        let doc = format!(" Calls `{}.__exit__` when dropped", self.name);
+        let exit_call = if exit.ret_type == Type::None
+            && !returns_result(&exit.properties, &exit.annotations)
+        {
+            quote! { self.0.__exit__(); }
+        } else {
+            quote! { let _ = self.0.__exit__(); }
+        };
+        quote! {
+            #[doc = #doc]
+            pub struct #guard(pub #name);
+
+            impl Drop for #guard {
+                fn drop(&mut self) {
+                    #exit_call
+                }
+            }
+
+            impl std::ops::Deref for #guard {
+                type Target = #name;
+                fn deref(&self) -> &#name {
+                    &self.0
+                }
+            }
+
+            impl std::ops::DerefMut for #guard {
+                fn deref_mut(&mut self) -> &mut #name {
+                    &mut self.0
+                }
+            }
+        }
+    }
+
+    /// Generate `new` from `__init__`, or from the field list for dataclasses
+    fn generate_constructor(
+        &self,
+        ctx: &mut CodeGenContext,
+    ) -> Result<Option<proc_macro2::TokenStream>> {
+        let instance_fields: Vec<&HirField> =
+            self.fields.iter().filter(|f| !f.is_class_var).collect();
+
+        let init_fails = self
+            .init_method()
+            .is_some_and(|init| returns_result(&init.properties, &init.annotations));
+        let (params, body_stmts, initialized) = if let Some(init) = self.init_method() {
+            let params = generate_stored_params(&init.params, &init.annotations, ctx)?;
+            ctx.error_scope = ErrorScope::new(init_fails, &init.annotations);
+            let assigned: HashSet<&str> = instance_fields
+                .iter()
+                .map(|f| f.name.as_str())
+                .filter(|f| init_assigns_field(&init.body, f))
+                .collect();
+
+            // Fields assigned in `__init__` become locals; a parameter of the same
+            // name is left undeclared so the first assignment shadows it
+            ctx.enter_scope();
+            for (param_name, _) in &init.params {
+                if !assigned.contains(param_name.as_str()) {
+                    ctx.declare_var(param_name);
+                }
+            }
+            let body = lower_init_body(&init.body);
+            // Closures stored in fields outlive the constructor
+            ctx.closures = ClosureScope::for_body(&body);
+            ctx.closures
+                .escaping
+                .extend(assigned.iter().map(|f| f.to_string()));
+            let body_stmts = generate_body(&body, ctx)?;
+            ctx.exit_scope();
+            ctx.error_scope = ErrorScope::default();
+            ctx.closures = ClosureScope::default();
+
+            let mut initialized: HashSet<String> = assigned.iter().map(|f| f.to_string()).collect();
+            initialized.extend(
+                init.params
+                    .iter()
+                    .filter(|(p, _)| instance_fields.iter().any(|f| &f.name == p))
+                    .map(|(p, _)| p.clone()),
+            );
+            (params, body_stmts, initialized)
+        } else if self.is_dataclass {
+            let field_params: Vec<(Symbol, Type)> = instance_fields
+                .iter()
+                .map(|f| (f.name.clone(), f.field_type.clone()))
+                .collect();
+            let params = generate_stored_params(&field_params, &self.annotations, ctx)?;
+            let initialized = field_params.into_iter().map(|(name, _)| name).collect();
+            (params, vec![], initialized)
+        } else if instance_fields.iter().all(|f| f.default_value.is_some()) {
+            (vec![], vec![], HashSet::new())
+        } else {
+            return Ok(None);
+        };
+
+        let field_inits = instance_fields
+            .iter()
+            .map(|field| {
+                let field_ident = syn::Ident::new(&field.name, proc_macro2::Span::call_site());
+                if initialized.contains(&field.name) {
+                    if matches!(field.field_type, Type::Function { .. }) {
+                        Ok(quote! { #field_ident: Box::new(#field_ident) })
+                    } else {
+                        Ok(quote! { #field_ident })
+                    }
+                } else if let Some(default) = &field.default_value {
+                    let value = default.to_rust_expr(ctx)?;
+                    Ok(quote! { #field_ident: #value })
+                } else {
+                    Ok(quote! { #field_ident: Default::default() })
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if init_fails {
+            let error_ty = ctx.error_scope.error_type();
+            return Ok(Some(quote! {
+                pub fn new(#(#params),*) -> Result<Self, #error_ty> {
+                    #(#body_stmts)*
+                    Ok(Self { #(#field_inits),* })
+                }
+            }));
+        }
+        Ok(Some(quote! {
+            pub fn new(#(#params),*) -> Self {
+                #(#body_stmts)*
+                Self { #(#field_inits),* }
+            }
+        }))
+    }
+}
+
+impl RustCodeGen for HirMethod {
+    fn to_rust_tokens(&self, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
+        if self.properties.is_generator {
+            transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "Generator method '{}' is not supported yet",
+                self.name
+            )));
+        }
+        let name = syn::Ident::new(&self.name, proc_macro2::Span::call_site());
+        let has_receiver = matches!(self.kind, MethodKind::Instance | MethodKind::Property);
+        let borrows = ctx.borrows.method(&self.name).cloned().unwrap_or_default();
+        let scope = BorrowScope::new(&self.params, &borrows, has_receiver);
+        let effects = Effects::of(&self.body, &ctx.mutating_methods);
+        let mut params = generate_params(&self.params, &self.annotations, &scope, &effects, ctx)?;
+        let generics = scope.named_lifetime.then(|| quote! { <'a> });
+        ctx.borrow_scope = scope;
+        ctx.live = LiveScope::for_function();
+        // Whether the method takes `self`, and whether mutably
+        let receiver = match self.kind {
+            MethodKind::Instance => Some(mutates_self(&self.body)),
+            MethodKind::Property => Some(false),
+            MethodKind::Static | MethodKind::Class => None,
+        };
+        match receiver {
+            Some(true) => params.insert(0, quote! { &mut self }),
+            Some(false) => params.insert(0, quote! { &self }),
+            None => {}
+        }
+        ctx.error_scope = ErrorScope::new(
+            returns_result(&self.properties, &self.annotations),
+            &self.annotations,
+        );
+        ctx.ints = IntScope::new(&self.params, &self.body, &self.ret_type, &self.annotations);
+        ctx.set_type = SetType::new(&self.annotations);
+
+        ctx.enter_scope();
+        ctx.declare_var("self");
+        for (param_name, _) in &self.params {
+            ctx.declare_var(param_name);
+        }
+        ctx.var_types = self.params.iter().cloned().collect();
+        ctx.closures = ClosureScope::for_body(&self.body);
+        let body_stmts = generate_fallible_body(&self.body, &self.ret_type, ctx)?;
+        let mut returned = Vec::new();
+        return_values(&self.body, &mut returned);
+        let return_type = match receiver {
+            // `return self` hands back the receiver, so the method returns the
+            // reference it was called through
+            Some(mutable)
+                if !returned.is_empty() && returned.iter().all(|v| v.is_some_and(is_self)) =>
+            {
+                let ty = match mutable {
+                    true => quote! { &mut Self },
+                    false => quote! { &Self },
+                };
+                match ctx.error_scope.returns_result {
+                    true => {
+                        let error_ty = ctx.error_scope.error_type();
+                        quote! { -> Result<#ty, #error_ty> }
+                    }
+                    false => quote! { -> #ty },
+                }
+            }
+            _ => generate_return_type(&self.ret_type, &self.annotations, ctx)?,
+        };
+        ctx.exit_scope();
+        ctx.var_types.clear();
+        ctx.int_signs.clear();
+        ctx.error_scope = ErrorScope::default();
+        ctx.ints = IntScope::default();
+        ctx.set_type = SetType::default();
+        ctx.closures = ClosureScope::default();
+        ctx.borrow_scope = BorrowScope::default();
+        ctx.live = LiveScope::default();
+
+        let panics = emits_panic(&quote! { #(#body_stmts)* });
+        let attrs = generate_doc_attrs(&self.docstring, &self.properties, panics);
+        let asyncness = self.is_async.then(|| quote! { async });
+
+        Ok(quote! {
+            #(#attrs)*
+            pub #asyncness fn #name #generics(#(#params),*) #return_type {
+                #(#body_stmts)*
+            }
+        })
+    }
+}
+
+/// Method `method` of class `class`, stubbed out as functions are
+fn generate_method_or_stub(
+    class: &str,
+    method: &HirMethod,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let tokens = generate_or_stub(
+        &format!("{class}.{}", method.name),
+        method.span,
+        &method.annotations,
+        ctx,
+        |ctx| method.to_rust_tokens(ctx),
+        |stub, ctx| generate_method_stub(method, stub, ctx),
+    )?;
+    Ok(ctx.mark(method.span, tokens))
+}
+
+/// `__exit__` without the exception parameters, which a guard has no way to supply
+fn guard_exit_method(method: &HirMethod) -> Result<HirMethod> {
+    if let Some((param, _)) = method
+        .params
+        .iter()
+        .find(|(param, _)| exceptions::body_uses_exception(&method.body, param))
+    {
+        transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "__exit__ reading its '{}' parameter is not supported",
+            param
+        )));
+    }
+    Ok(HirMethod {
+        params: Default::default(),
+        ..method.clone()
+    })
+}
+
+fn guard_struct_ident(class: &str) -> syn::Ident {
+    syn::Ident::new(&format!("{class}Guard"), proc_macro2::Span::call_site())
+}
+
+/// Class attributes become associated constants
+fn generate_class_constant(
+    field: &HirField,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let name = syn::Ident::new(&field.name, proc_macro2::Span::call_site());
+    let (ty, value): (syn::Type, syn::Expr) = match &field.default_value {
+        Some(HirExpr::Literal(Literal::String(s))) => {
+            (parse_quote! { &'static str }, parse_quote! { #s })
+        }
+        Some(HirExpr::Literal(lit)) => {
+            let rust_type = ctx.type_mapper.map_type(&field.field_type);
+            (rust_type_to_syn(&rust_type)?, literal_to_rust_expr(lit))
+        }
+        _ => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+            "Class attribute '{}' must be initialized with a literal",
+            field.name
+        ))),
+    };
+    Ok(quote! { pub const #name: #ty = #value; })
+}
+
+fn is_self_attribute<'e>(value: &'e HirExpr, attr: &'e str) -> Option<&'e str> {
+    is_self(value).then_some(attr)
+}
+
+/// Whether a method body writes to any attribute of `self`
+fn mutates_self(body: &[Spanned<HirStmt>]) -> bool {
+    body.iter().any(|stmt| match &stmt.node {
+        HirStmt::Assign { target, .. } if target_mutates_self(target) => true,
+        HirStmt::If {
+            then_body,
+            else_body,
+            ..
+        } => mutates_self(then_body) || else_body.as_deref().is_some_and(mutates_self),
+        HirStmt::While { body, .. } | HirStmt::For { body, .. } | HirStmt::With { body, .. } => {
+            mutates_self(body)
+        }
+        HirStmt::Match { cases, .. } => cases.iter().any(|c| mutates_self(&c.body)),
+        HirStmt::Try {
+            body,
+            handlers,
+            orelse,
+            finalbody,
+        } => {
+            mutates_self(body)
+                || handlers.iter().any(|h| mutates_self(&h.body))
+                || mutates_self(orelse)
+                || mutates_self(finalbody)
+        }
+        HirStmt::Expr(expr) | HirStmt::Assign { value: expr, .. } | HirStmt::Return(Some(expr)) => {
+            mutates_self_in_expr(expr)
+        }
+        _ => false,
+    })
+}
+
+/// Whether assigning to a target writes into `self`, as `self.x = ...` and
+/// `self.items[i] = ...` do
+fn target_mutates_self(target: &AssignTarget) -> bool {
+    fn within_self(expr: &HirExpr) -> bool {
+        match expr {
+            HirExpr::Attribute { value, .. } => is_self(value) || within_self(value),
+            HirExpr::Index { base, .. } => within_self(base),
+            _ => false,
+        }
+    }
+    match target {
+        AssignTarget::Symbol(_) => false,
+        AssignTarget::Attribute { value, .. } => is_self(value) || within_self(value),
+        AssignTarget::Index { base, .. } => within_self(base),
+        AssignTarget::Tuple(targets) => targets.iter().any(target_mutates_self),
+    }
+}
+
+/// Whether an expression calls a mutating builtin method on a field of `self`
+fn mutates_self_in_expr(expr: &HirExpr) -> bool {
+    match expr {
+        HirExpr::MethodCall { receiver, .. } if expr.is_mutating_method_call() => {
+            matches!(receiver.as_ref(), HirExpr::Attribute { value, .. } if is_self(value))
+        }
+        _ => false,
+    }
+}
+
+/// The values of the `return` statements in `body`
+fn return_values<'b>(body: &'b [Spanned<HirStmt>], values: &mut Vec<Option<&'b HirExpr>>) {
+    for stmt in body {
+        match &stmt.node {
+            HirStmt::Return(value) => values.push(value.as_ref()),
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => {
+                return_values(then_body, values);
+                if let Some(else_body) = else_body {
+                    return_values(else_body, values);
+                }
+            }
+            HirStmt::While { body, .. }
+            | HirStmt::For { body, .. }
+            | HirStmt::With { body, .. } => return_values(body, values),
+            HirStmt::Match { cases, .. } => {
+                cases.iter().for_each(|c| return_values(&c.body, values))
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                return_values(body, values);
+                handlers.iter().for_each(|h| return_values(&h.body, values));
+                return_values(orelse, values);
+                return_values(finalbody, values);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_self(expr: &HirExpr) -> bool {
+    matches!(expr, HirExpr::Var(v) if v == "self")
+}
+
+fn init_assigns_field(body: &[Spanned<HirStmt>], field: &str) -> bool {
+    fn assigns(target: &AssignTarget, field: &str) -> bool {
+        match target {
+            AssignTarget::Attribute { value, attr } => {
+                is_self_attribute(value, attr) == Some(field)
+            }
+            AssignTarget::Tuple(targets) => targets.iter().any(|t| assigns(t, field)),
+            AssignTarget::Symbol(_) | AssignTarget::Index { .. } => false,
+        }
+    }
+    body.iter()
+        .any(|stmt| matches!(&stmt.node, HirStmt::Assign { target, .. } if assigns(target, field)))
+}
+
+/// `self.<field>` targets in `__init__` become the locals seeding the struct literal
+fn lower_init_target(target: &AssignTarget) -> AssignTarget {
+    match target {
+        AssignTarget::Attribute { value, attr } if is_self_attribute(value, attr).is_some() => {
+            AssignTarget::Symbol(attr.clone())
+        }
+        AssignTarget::Attribute { value, attr } => AssignTarget::Attribute {
+            value: Box::new(lower_init_expr(value)),
+            attr: attr.clone(),
+        },
+        AssignTarget::Index { base, index } => AssignTarget::Index {
+            base: Box::new(lower_init_expr(base)),
+            index: Box::new(lower_init_expr(index)),
+        },
+        AssignTarget::Tuple(targets) => {
+            AssignTarget::Tuple(targets.iter().map(lower_init_target).collect())
+        }
+        AssignTarget::Symbol(_) => target.clone(),
+    }
+}
+
+/// Rewrite `self.<field>` in `__init__` to locals that seed the struct literal
+fn lower_init_body(body: &[Spanned<HirStmt>]) -> Vec<Spanned<HirStmt>> {
+    body.iter()
+        .filter_map(|stmt| lower_init_stmt(stmt).map(|node| Spanned::new(node, stmt.span)))
+        .collect()
+}
+
+fn lower_init_stmt(stmt: &HirStmt) -> Option<HirStmt> {
+    match stmt {
+        HirStmt::Assign {
+            target: AssignTarget::Attribute { value, attr },
+            value: assigned,
+        } if is_self_attribute(value, attr).is_some() => {
+            let assigned = lower_init_expr(assigned);
+            // `self.x = x` is covered by the field init shorthand
+            if matches!(&assigned, HirExpr::Var(v) if v == attr) {
+                None
+            } else {
+                Some(HirStmt::Assign {
+                    target: AssignTarget::Symbol(attr.clone()),
+                    value: assigned,
+                })
+            }
+        }
+        HirStmt::Assign { target, value } => Some(HirStmt::Assign {
+            target: lower_init_target(target),
+            value: lower_init_expr(value),
+        }),
+        HirStmt::Return(expr) => Some(HirStmt::Return(expr.as_ref().map(lower_init_expr))),
+        HirStmt::If {
+            condition,
+            then_body,
+            else_body,
+        } => Some(HirStmt::If {
+            condition: lower_init_expr(condition),
+            then_body: lower_init_body(then_body),
+            else_body: else_body.as_deref().map(lower_init_body),
+        }),
+        HirStmt::While { condition, body } => Some(HirStmt::While {
+            condition: lower_init_expr(condition),
+            body: lower_init_body(body),
+        }),
+        HirStmt::For { target, iter, body } => Some(HirStmt::For {
+            target: target.clone(),
+            iter: lower_init_expr(iter),
+            body: lower_init_body(body),
+        }),
+        HirStmt::Expr(expr) => Some(HirStmt::Expr(lower_init_expr(expr))),
+        HirStmt::Yield(expr) => Some(HirStmt::Yield(expr.as_ref().map(lower_init_expr))),
+        HirStmt::YieldFrom(expr) => Some(HirStmt::YieldFrom(lower_init_expr(expr))),
+        HirStmt::Raise { exception } => Some(HirStmt::Raise {
+            exception: exception.as_ref().map(lower_init_expr),
+        }),
+        HirStmt::Try {
+            body,
+            handlers,
+            orelse,
+            finalbody,
+        } => Some(HirStmt::Try {
+            body: lower_init_body(body),
+            handlers: handlers
+                .iter()
+                .map(|h| ExceptHandler {
+                    exception_types: h.exception_types.clone(),
+                    name: h.name.clone(),
+                    body: lower_init_body(&h.body),
+                })
+                .collect(),
+            orelse: lower_init_body(orelse),
+            finalbody: lower_init_body(finalbody),
+        }),
+        HirStmt::With { items, body } => Some(HirStmt::With {
+            items: items
+                .iter()
+                .map(|item| WithItem {
+                    context_expr: lower_init_expr(&item.context_expr),
+                    optional_vars: item.optional_vars.clone(),
+                })
+                .collect(),
+            body: lower_init_body(body),
+        }),
+        HirStmt::Match { subject, cases } => Some(HirStmt::Match {
+            subject: lower_init_expr(subject),
+            cases: cases
+                .iter()
+                .map(|case| MatchCase {
+                    pattern: case.pattern.clone(),
+                    guard: case.guard.as_ref().map(lower_init_expr),
+                    body: lower_init_body(&case.body),
+                })
+                .collect(),
+        }),
+        HirStmt::FunctionDef { func, nonlocals } => Some(HirStmt::FunctionDef {
+            func: Box::new(HirFunction {
+                body: lower_init_body(&func.body),
+                ..func.as_ref().clone()
+            }),
+            nonlocals: nonlocals.clone(),
+        }),
+    }
+}
+
+fn lower_init_expr(expr: &HirExpr) -> HirExpr {
+    let lower_all = |exprs: &[HirExpr]| exprs.iter().map(lower_init_expr).collect();
+    let lower_keywords = |kwargs: &[(Symbol, HirExpr)]| {
+        kwargs
+            .iter()
+            .map(|(name, value)| (name.clone(), lower_init_expr(value)))
+            .collect()
+    };
+    match expr {
+        HirExpr::Attribute { value, attr } if is_self_attribute(value, attr).is_some() => {
+            HirExpr::Var(attr.clone())
+        }
+        HirExpr::Attribute { value, attr } => HirExpr::Attribute {
+            value: Box::new(lower_init_expr(value)),
+            attr: attr.clone(),
+        },
+        HirExpr::Binary { op, left, right } => HirExpr::Binary {
+            op: *op,
+            left: Box::new(lower_init_expr(left)),
+            right: Box::new(lower_init_expr(right)),
+        },
+        HirExpr::Unary { op, operand } => HirExpr::Unary {
+            op: *op,
+            operand: Box::new(lower_init_expr(operand)),
+        },
+        HirExpr::Call { func, args, kwargs } => HirExpr::Call {
+            func: func.clone(),
+            args: lower_all(args),
+            kwargs: lower_keywords(kwargs),
+        },
+        HirExpr::MethodCall {
+            receiver,
+            method,
+            args,
+            kwargs,
+        } => HirExpr::MethodCall {
+            receiver: Box::new(lower_init_expr(receiver)),
+            method: method.clone(),
+            args: lower_all(args),
+            kwargs: lower_keywords(kwargs),
+        },
+        HirExpr::Index { base, index } => HirExpr::Index {
+            base: Box::new(lower_init_expr(base)),
+            index: Box::new(lower_init_expr(index)),
+        },
+        HirExpr::Slice {
+            base,
+            start,
+            stop,
+            step,
+        } => HirExpr::Slice {
+            base: Box::new(lower_init_expr(base)),
+            start: start.as_deref().map(|e| Box::new(lower_init_expr(e))),
+            stop: stop.as_deref().map(|e| Box::new(lower_init_expr(e))),
+            step: step.as_deref().map(|e| Box::new(lower_init_expr(e))),
+        },
+        HirExpr::List(elts) => HirExpr::List(lower_all(elts)),
+        HirExpr::Set(elts) => HirExpr::Set(lower_all(elts)),
+        HirExpr::Tuple(elts) => HirExpr::Tuple(lower_all(elts)),
+        HirExpr::FString { parts, values } => HirExpr::FString {
+            parts: parts.clone(),
+            values: lower_all(values),
+        },
+        HirExpr::Dict(items) => HirExpr::Dict(
+            items
+                .iter()
+                .map(|(k, v)| (lower_init_expr(k), lower_init_expr(v)))
+                .collect(),
+        ),
+        HirExpr::Borrow { expr, mutable } => HirExpr::Borrow {
+            expr: Box::new(lower_init_expr(expr)),
+            mutable: *mutable,
+        },
+        HirExpr::Await { value } => HirExpr::Await {
+            value: Box::new(lower_init_expr(value)),
+        },
+        HirExpr::Comprehension {
+            kind,
+            element,
+            value,
+            generators,
+        } => HirExpr::Comprehension {
+            kind: *kind,
+            element: Box::new(lower_init_expr(element)),
+            value: value.as_deref().map(|v| Box::new(lower_init_expr(v))),
+            generators: generators
+                .iter()
+                .map(|g| ComprehensionClause {
+                    target: g.target.clone(),
+                    iter: lower_init_expr(&g.iter),
+                    conditions: lower_all(&g.conditions),
+                })
+                .collect(),
+        },
+        HirExpr::Lambda { params, body } => HirExpr::Lambda {
+            params: params.clone(),
+            body: Box::new(lower_init_expr(body)),
+        },
+        HirExpr::Literal(_) | HirExpr::Var(_) => expr.clone(),
+    }
+}
+
+impl RustCodeGen for HirStmt {
+    fn to_rust_tokens(&self, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
+        match self {
+            HirStmt::Assign {
+                target: AssignTarget::Symbol(target),
+                value: HirExpr::Lambda { params, body },
+            } if !ctx.is_declared(target) => {
+                let escaping = ctx.closures.escaping.contains(target);
+                let (closure, kind) = generate_lambda(params, body, escaping, ctx)?;
+                ctx.declare_var(target);
+                ctx.closures.locals.insert(target.clone(), kind);
+                let target_ident = syn::Ident::new(target, proc_macro2::Span::call_site());
+                let mutable = kind == ClosureKind::FnMut && ctx.closures.called.contains(target);
+                let mutability = mutable.then(|| quote! { mut });
+                Ok(quote! { let #mutability #target_ident = #closure; })
+            }
+            HirStmt::Assign {
+                target: AssignTarget::Symbol(target),
+                value,
+            } => {
                 let target_ident = syn::Ident::new(target, proc_macro2::Span::call_site());
-                let value_expr = value.to_rust_expr(ctx)?;
+                let big = big_target(target, value, ctx);
+                let value_expr = if big {
+                    unparen(big_value(value, true, ctx)?)
+                } else if ctx.ints.is_bounded(target) && big_int(value, ctx) {
+                    // Computed on `BigInt`s, into a local proven to fit an `i64`
+                    let value = unparen(value.to_rust_expr(ctx)?);
+                    bigint::to_i64(&value, "int out of its proven range")
+                } else {
+                    unparen(value.to_rust_expr(ctx)?)
+                };
+
+                // A rebound local may be negative
+                ctx.int_signs.remove(target);
+
+                // A `Cow` parameter owns its value once rebound
+                if ctx.borrow_scope.passing(target) == ParamPassing::Cow {
+                    let value_expr: syn::Expr = match value {
+                        HirExpr::Var(_) => parse_quote! { #value_expr.to_string() },
+                        _ => value_expr,
+                    };
+                    return Ok(quote! { #target_ident = Cow::Owned(#value_expr); });
+                }
+                if ctx.is_declared(target) {
+                    // Variable already exists, just assign; std has no
+                    // compound assignment of sets
+                    let set = matches!(ctx.var_types.get(target), Some(Type::Set(_)));
+                    if let Some(assignment) =
+                        compound_assignment(&target_ident, &value_expr).filter(|_| !set)
+                    {
+                        return Ok(assignment);
+                    }
+                    Ok(quote! { #target_ident = #value_expr; })
+                } else {
+                    // First declaration, `mut` if the rest of the block writes to it
+                    ctx.declare_var(target);
+                    // A list or dict display tells how later subscripts store into it
+                    match value {
+                        HirExpr::List(_) => {
+                            ctx.var_types
+                                .insert(target.clone(), Type::List(Box::new(Type::Unknown)));
+                        }
+                        HirExpr::Dict(_) => {
+                            ctx.var_types.insert(
+                                target.clone(),
+                                Type::Dict(Box::new(Type::Unknown), Box::new(Type::Unknown)),
+                            );
+                        }
+                        // A lock is held by `with` rather than bound
+                        _ if context_managers::is_lock_constructor(value) => {
+                            ctx.var_types
+                                .insert(target.clone(), Type::Custom("Lock".to_string()));
+                        }
+                        // A string tells `str` methods on it from list methods,
+                        // a number how arithmetic on it is lowered, and a set
+                        // how its methods and operators are
+                        _ if big => {
+                            ctx.var_types.insert(target.clone(), Type::Int);
+                        }
+                        _ => {
+                            let ty = declared_type(value, ctx)
+                                .unwrap_or_else(|| generators::infer_type(value, &ctx.var_types));
+                            if matches!(ty, Type::String | Type::Int | Type::Float | Type::Set(_)) {
+                                ctx.var_types.insert(target.clone(), ty);
+                            }
+                        }
+                    }
+                    let mutability = ctx.live.written_later(target).then(|| quote! { mut });
+                    // An int literal takes the configured width, and a type
+                    // methods such as `checked_add` can be called on
+                    if indexing::int_literal(value).is_some() && !big {
+                        let int = rust_type_to_syn(&ctx.fixed_int_type())?;
+                        return Ok(quote! { let #mutability #target_ident: #int = #value_expr; });
+                    }
+                    Ok(quote! { let #mutability #target_ident = #value_expr; })
+                }
+            }
+            HirStmt::Assign {
+                target: AssignTarget::Attribute { value: base, attr },
+                value,
+            } => {
+                let base_expr = base.to_rust_expr(ctx)?;
+                let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+                // A callable field holds a boxed closure, which owns its captures
+                let value_expr = match value {
+                    HirExpr::Lambda { params, body } if is_callable_field(base, attr, ctx) => {
+                        let (closure, _) = generate_lambda(params, body, true, ctx)?;
+                        parse_quote! { Box::new(#closure) }
+                    }
+                    _ => unparen(value.to_rust_expr(ctx)?),
+                };
+                Ok(quote! { #base_expr.#attr_ident = #value_expr; })
+            }
+            HirStmt::Assign {
+                target: AssignTarget::Index { base, index },
+                value,
+            } => {
+                let value_expr = unparen(value.to_rust_expr(ctx)?);
+                // The first values stored into a dict display tell its type
+                if let HirExpr::Var(name) = base.as_ref() {
+                    let stored = [index.as_ref(), value]
+                        .map(|stored| generators::infer_type(stored, &ctx.var_types));
+                    if let Some(Type::Dict(keys, values)) = ctx.var_types.get_mut(name) {
+                        for (ty, stored) in [keys, values].into_iter().zip(stored) {
+                            if **ty == Type::Unknown {
+                                **ty = stored;
+                            }
+                        }
+                    }
+                }
+                match subscript_place(base, index, ctx)? {
+                    SubscriptPlace::Element {
+                        list,
+                        position,
+                        from_end,
+                    } => {
+                        if matches!(
+                            ctx.error_scope.index_policy,
+                            IndexPolicy::Native | IndexPolicy::Default
+                        ) {
+                            return Ok(quote! { #list[#position] = #value_expr; });
+                        }
+                        // The length is read before the list is borrowed mutably
+                        let (setup, position): (Option<proc_macro2::TokenStream>, syn::Expr) =
+                            if from_end {
+                                (Some(quote! { let __i = #position; }), parse_quote! { __i })
+                            } else {
+                                (None, position)
+                            };
+                        let slot = unwrap_element(
+                            indexing::get(&list, &position, true),
+                            "list assignment index out of range",
+                            ctx,
+                        )?;
+                        Ok(match setup {
+                            Some(setup) => quote! { { #setup *#slot = #value_expr; } },
+                            None => quote! { *#slot = #value_expr; },
+                        })
+                    }
+                    // The key is moved into the map before the value is
+                    // evaluated, so a value or a later statement reading it
+                    // needs its own copy
+                    SubscriptPlace::Entry { map, key } => match index.as_ref() {
+                        HirExpr::Var(name)
+                            if exceptions::body_uses_exception(
+                                &[HirStmt::Expr(value.clone()).into()],
+                                name,
+                            ) || (!ctx.live.unread_later(name)
+                                && ctx.var_types.get(name).is_some_and(|ty| !is_copy(ty, ctx))
+                                && matches!(&key, syn::Expr::Path(p) if p.path.is_ident(name))) =>
+                        {
+                            Ok(quote! { #map.insert(#key.clone(), #value_expr); })
+                        }
+                        _ => Ok(quote! { #map.insert(#key, #value_expr); }),
+                    },
+                }
+            }
+            HirStmt::Assign {
+                target: target @ AssignTarget::Tuple(_),
+                value,
+            } => generate_unpacking(target, value, ctx),
+            HirStmt::Return(Some(HirExpr::Lambda { params, body })) => {
+                let (closure, kind) = generate_lambda(params, body, true, ctx)?;
+                ctx.closures.return_closure(kind);
+                generate_return(Some(closure), ctx)
+            }
+            HirStmt::Return(expr) => {
+                if let Some(HirExpr::Var(name)) = expr {
+                    if let Some(&kind) = ctx.closures.locals.get(name) {
+                        ctx.closures.return_closure(kind);
+                    }
+                }
+                let value = match expr {
+                    Some(HirExpr::Var(name))
+                        if ctx.borrow_scope.passing(name) == ParamPassing::Cow =>
+                    {
+                        let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                        Some(parse_quote! { #ident.into_owned() })
+                    }
+                    Some(expr) if ctx.ints.returns_big => Some(big_value(expr, true, ctx)?),
+                    Some(expr) => {
+                        let value = expr.to_rust_expr(ctx)?;
+                        Some(enum_value(expr, value, &ctx.ret_type, ctx)?)
+                    }
+                    None => None,
+                };
+                generate_return(value, ctx)
+            }
+            HirStmt::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                // Names first bound in a branch stay visible after it, as in Python
+                let hoisted = hoist_bindings(
+                    &[then_body, else_body.as_deref().unwrap_or_default()],
+                    if else_body.is_some() {
+                        Bodies::Exhaustive
+                    } else {
+                        Bodies::Partial
+                    },
+                    ctx,
+                );
+                if let Some(tokens) =
+                    generate_isinstance_if(condition, then_body, else_body.as_deref(), ctx)?
+                {
+                    return Ok(declare_hoisted(hoisted, tokens));
+                }
+                let cond = unparen(condition.to_rust_expr(ctx)?);
+                ctx.enter_scope();
+                let then_stmts = generate_body(then_body, ctx)?;
+                ctx.exit_scope();
+
+                if let Some(else_stmts) = else_body {
+                    ctx.enter_scope();
+                    let else_tokens = generate_body(else_stmts, ctx)?;
+                    ctx.exit_scope();
+                    let tokens = quote! {
+                        if #cond {
+                            #(#then_stmts)*
+                        } else {
+                            #(#else_tokens)*
+                        }
+                    };
+                    Ok(declare_hoisted(hoisted, tokens))
+                } else {
+                    Ok(quote! {
+                        #(#hoisted)*
+                        if #cond {
+                            #(#then_stmts)*
+                        }
+                    })
+                }
+            }
+            HirStmt::While { condition, body } => {
+                let cond = unparen(condition.to_rust_expr(ctx)?);
+                ctx.enter_loop(self);
+                ctx.enter_scope();
+                let body_stmts = generate_body(body, ctx);
+                ctx.exit_scope();
+                ctx.exit_loop();
+                let body_stmts = body_stmts?;
+                Ok(quote! {
+                    while #cond {
+                        #(#body_stmts)*
+                    }
+                })
+            }
+            HirStmt::For { target, iter, body } => {
+                let iter_expr = iter.to_rust_expr(ctx)?;
+                // A collection read again later, or borrowed, yields owned
+                // copies of its items; one read for the last time is consumed
+                let iter_expr: syn::Expr = match iter {
+                    HirExpr::Var(name) => {
+                        let consumed = ctx.can_move(name);
+                        match ctx.var_types.get(name) {
+                            Some(Type::Dict(_, _)) if consumed => {
+                                parse_quote! { #iter_expr.into_keys() }
+                            }
+                            Some(Type::Dict(_, _)) => parse_quote! { #iter_expr.keys().cloned() },
+                            Some(Type::List(_) | Type::Set(_)) if !consumed => {
+                                parse_quote! { #iter_expr.iter().cloned() }
+                            }
+                            _ => iter_expr,
+                        }
+                    }
+                    _ => iter_expr,
+                };
+                // The items of a string are strings of one character
+                let chars = string_typed(iter, ctx);
+                let iter_expr: syn::Expr = if chars {
+                    let receiver = builtins::operand_expr(&iter_expr);
+                    parse_quote! { #receiver.chars().map(String::from) }
+                } else {
+                    iter_expr
+                };
+                let writes = Effects::of(body, &ctx.mutating_methods);
+                ctx.enter_loop(self);
+                ctx.enter_scope();
+                // Loop variables are declared in the loop scope; other targets
+                // are assigned from each item at the start of the body
+                // Loop variables the body never reads are bound as `_name`
+                let mut read = HashSet::new();
+                for_each_expr_in(body, &mut |expr| {
+                    if let HirExpr::Var(name) = expr {
+                        read.insert(name.clone());
+                    }
+                });
+                let unread = |name: &str| !read.contains(name) && !name.starts_with('_');
+                let (pattern, assign) =
+                    match binding_pattern(target, &|name| writes.writes(name), &unread) {
+                        Some(pattern) => (pattern, None),
+                        None => {
+                            let item = HirStmt::Assign {
+                                target: target.clone(),
+                                value: HirExpr::Var("__item".to_string()),
+                            };
+                            ctx.declare_var("__item");
+                            (quote! { __item }, Some(item.to_rust_tokens(ctx)?))
+                        }
+                    };
+                for name in target.symbols() {
+                    ctx.declare_var(name);
+                    ctx.int_signs.remove(name);
+                }
+                if let Some((name, sign)) = loop_counter(target, iter, ctx) {
+                    ctx.int_signs.insert(name.to_string(), sign);
+                    ctx.var_types.insert(name.to_string(), Type::Int);
+                }
+                // The items of a collection of ints are ints, `BigInt`s where ints are
+                if let (AssignTarget::Symbol(name), true) = (target, ctx.ints.big) {
+                    if declared_type(iter, ctx).is_some_and(|ty| holds_ints(&ty)) {
+                        ctx.var_types.insert(name.clone(), Type::Int);
+                    }
+                }
+                if let (AssignTarget::Symbol(name), true) = (target, chars) {
+                    ctx.var_types.insert(name.clone(), Type::String);
+                }
+                // The pieces of a split string are strings
+                if let (
+                    AssignTarget::Symbol(name),
+                    HirExpr::MethodCall {
+                        receiver, method, ..
+                    },
+                ) = (target, iter)
+                {
+                    if is_str_method(receiver, method, ctx)
+                        && strings::method_type(method) == Type::List(Box::new(Type::String))
+                    {
+                        ctx.var_types.insert(name.clone(), Type::String);
+                    }
+                }
+                let body_stmts = generate_body(body, ctx);
+                ctx.exit_scope();
+                ctx.exit_loop();
+                let body_stmts = body_stmts?;
+                Ok(quote! {
+                    for #pattern in #iter_expr {
+                        #assign
+                        #(#body_stmts)*
+                    }
+                })
+            }
+            HirStmt::Expr(expr) => {
+                let expr_tokens = expr.to_rust_expr(ctx)?;
+                Ok(quote! { #expr_tokens; })
+            }
+            HirStmt::Raise { exception } => {
+                let error = generate_error_value(exception.as_ref(), ctx)?;
+                exit_with_error(error, ctx)
+            }
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => generate_try(body, handlers, orelse, finalbody, ctx),
+            HirStmt::With { items, body } => generate_with(items, body, ctx),
+            HirStmt::Match { subject, cases } => generate_match(subject, cases, ctx),
+            HirStmt::Yield(_) | HirStmt::YieldFrom(_) => {
+                transpile_bail!(ErrorKind::UnsupportedFeature(
+                    "`yield` is only supported directly in generator functions".to_string(),
+                ))
+            }
+            HirStmt::FunctionDef { func, nonlocals } => {
+                generate_nested_function(func, nonlocals, ctx)
+            }
+        }
+    }
+}
+
+/// `lambda params: body` as a closure, with the trait it implements. A
+/// closure that outlives the enclosing call takes its captures by `move`.
+fn generate_lambda(
+    params: &[Symbol],
+    body: &HirExpr,
+    escaping: bool,
+    ctx: &mut CodeGenContext,
+) -> Result<(syn::Expr, ClosureKind)> {
+    let kind = {
+        let is_local = |name: &str| ctx.is_declared(name);
+        let scope = closures::Scope {
+            is_local: &is_local,
+            types: &ctx.var_types,
+        };
+        closures::lambda_captures(params, body, &scope).kind()
+    };
+
+    // The body runs when the closure is called, outside any enclosing `try`,
+    // and may run more than once
+    let closure_scope = ctx.error_scope.closure();
+    let saved_error_scope = std::mem::replace(&mut ctx.error_scope, closure_scope);
+    let saved_borrowed = ctx.borrowed_vars.clone();
+    let saved_types = ctx.var_types.clone();
+    let saved_signs = ctx.int_signs.clone();
+    let saved_moves = std::mem::replace(&mut ctx.live.moves, false);
+    ctx.enter_scope();
+    for param in params {
+        ctx.borrowed_vars.remove(param);
+        ctx.var_types.remove(param);
+        ctx.int_signs.remove(param);
+        ctx.declare_var(param);
+    }
+    let body_expr = body.to_rust_expr(ctx);
+    ctx.exit_scope();
+    ctx.error_scope = saved_error_scope;
+    ctx.borrowed_vars = saved_borrowed;
+    ctx.var_types = saved_types;
+    ctx.int_signs = saved_signs;
+    ctx.live.moves = saved_moves;
+    let body_expr = unparen(body_expr?);
+
+    let params = params
+        .iter()
+        .map(|p| syn::Ident::new(p, proc_macro2::Span::call_site()));
+    let capture = escaping.then(|| quote! { move });
+    Ok((parse_quote! { #capture |#(#params),*| #body_expr }, kind))
+}
+
+/// A `def` inside a function body. One that captures nothing becomes a
+/// private `fn` item; the others become closures bound to a local.
+fn generate_nested_function(
+    func: &HirFunction,
+    nonlocals: &[Symbol],
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    if func.is_async {
+        transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Nested async function '{}' is not supported",
+            func.name
+        )));
+    }
+    let captures = {
+        let is_local = |name: &str| ctx.is_declared(name);
+        let scope = closures::Scope {
+            is_local: &is_local,
+            types: &ctx.var_types,
+        };
+        closures::function_captures(func, nonlocals, &scope)
+    };
+    let kind = captures.kind();
+    let is_closure = !captures.captured.is_empty();
+    if is_closure {
+        let is_self = |name: &str| name == func.name;
+        let no_types = HashMap::new();
+        let scope = closures::Scope {
+            is_local: &is_self,
+            types: &no_types,
+        };
+        if !closures::function_captures(func, &[], &scope)
+            .captured
+            .is_empty()
+        {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Nested function '{}' is recursive and captures locals of the enclosing function",
+                func.name
+            )));
+        }
+    }
+    let escaping = ctx.closures.escaping.contains(&func.name);
+    let mutable = kind == ClosureKind::FnMut && ctx.closures.called.contains(&func.name);
+
+    // The nested body sees its own parameters and locals, and the enclosing
+    // locals it rebinds through `nonlocal`
+    let saved_vars = std::mem::replace(&mut ctx.declared_vars, vec![HashSet::new()]);
+    let saved_types = std::mem::take(&mut ctx.var_types);
+    let saved_signs = std::mem::take(&mut ctx.int_signs);
+    let saved_ints = std::mem::take(&mut ctx.ints);
+    let saved_set_type = std::mem::take(&mut ctx.set_type);
+    let saved_error_scope = std::mem::take(&mut ctx.error_scope);
+    let saved_borrowed = std::mem::take(&mut ctx.borrowed_vars);
+    let saved_closures = std::mem::take(&mut ctx.closures);
+    let saved_borrows = std::mem::take(&mut ctx.borrow_scope);
+    let saved_live = std::mem::take(&mut ctx.live);
+    let tokens = if is_closure {
+        generate_closure(func, nonlocals, mutable, escaping, ctx)
+    } else {
+        generate_function(func, &FunctionBorrows::default(), quote! {}, ctx)
+    };
+    ctx.declared_vars = saved_vars;
+    ctx.var_types = saved_types;
+    ctx.int_signs = saved_signs;
+    ctx.ints = saved_ints;
+    ctx.set_type = saved_set_type;
+    ctx.error_scope = saved_error_scope;
+    ctx.borrowed_vars = saved_borrowed;
+    ctx.closures = saved_closures;
+    ctx.borrow_scope = saved_borrows;
+    ctx.live = saved_live;
+
+    ctx.declare_var(&func.name);
+    if is_closure {
+        ctx.closures.locals.insert(func.name.clone(), kind);
+    }
+    tokens
+}
+
+/// `let name = |params| -> ret { body };` for a nested function that captures
+fn generate_closure(
+    func: &HirFunction,
+    nonlocals: &[Symbol],
+    mutable: bool,
+    escaping: bool,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    if func
+        .params
+        .iter()
+        .any(|(_, ty)| matches!(ty, Type::Function { .. }))
+        || matches!(func.ret_type, Type::Function { .. })
+    {
+        transpile_bail!(ErrorKind::CodeGenerationError(format!(
+            "Nested function '{}' takes or returns a callable and captures locals",
+            func.name
+        )));
+    }
+    let name = syn::Ident::new(&func.name, proc_macro2::Span::call_site());
+    let effects = Effects::of(&func.body, &ctx.mutating_methods);
+    let params = generate_params(
+        &func.params,
+        &func.annotations,
+        &BorrowScope::default(),
+        &effects,
+        ctx,
+    )?;
+    let ret = match ctx.type_mapper.map_return_type(&func.ret_type) {
+        crate::type_mapper::RustType::Unit => quote! {},
+        _ if func.ret_type == Type::Unknown => quote! {},
+        rust_type => {
+            update_import_needs(ctx, &rust_type);
+            let ty = rust_type_to_syn(&rust_type)?;
+            quote! { -> #ty }
+        }
+    };
+
+    for (param_name, _) in &func.params {
+        ctx.declare_var(param_name);
+    }
+    for name in nonlocals {
+        ctx.declare_var(name);
+    }
+    ctx.var_types = func.params.iter().cloned().collect();
+    ctx.closures = ClosureScope::for_body(&func.body);
+    ctx.live = LiveScope::for_closure();
+    let body_stmts = generate_fallible_body(&func.body, &func.ret_type, ctx)?;
+
+    let mutability = mutable.then(|| quote! { mut });
+    let capture = escaping.then(|| quote! { move });
+    Ok(quote! {
+        let #mutability #name = #capture |#(#params),*| #ret {
+            #(#body_stmts)*
+        };
+    })
+}
+
+/// Whether `base.attr` is a field of the current class holding a callable
+fn is_callable_field(base: &HirExpr, attr: &str, ctx: &CodeGenContext) -> bool {
+    is_self(base)
+        && ctx
+            .class_fields
+            .get(attr)
+            .is_some_and(|f| matches!(f.field_type, Type::Function { .. }))
+}
+
+/// Where `base[index]` is stored
+enum SubscriptPlace {
+    /// An element of a list, assigned in place; `from_end` when the
+    /// position reads the length of the list
+    Element {
+        list: syn::Expr,
+        position: syn::Expr,
+        from_end: bool,
+    },
+    /// An entry of a dict, written with `insert`
+    Entry { map: syn::Expr, key: syn::Expr },
+}
+
+/// Locate `base[index]` for assignment. Dicts are recognised by their
+/// declared type, or by a string key when the type is not known.
+fn subscript_place(
+    base: &HirExpr,
+    index: &HirExpr,
+    ctx: &mut CodeGenContext,
+) -> Result<SubscriptPlace> {
+    let is_dict = match declared_type(base, ctx) {
+        Some(ty) => matches!(ty, Type::Dict(..)),
+        None => matches!(index, HirExpr::Literal(Literal::String(_))),
+    };
+    let base_expr = place_expr(base, ctx)?;
+    if is_dict {
+        return Ok(SubscriptPlace::Entry {
+            map: base_expr,
+            key: index.to_rust_expr(ctx)?,
+        });
+    }
+    let index = index_bound(index, ctx)?;
+    Ok(SubscriptPlace::Element {
+        position: indexing::position(&index, &parse_quote! { #base_expr.len() }),
+        list: base_expr,
+        from_end: indexing::needs_len(&index),
+    })
+}
+
+/// `index` as an index or slice bound
+fn index_bound(index: &HirExpr, ctx: &mut CodeGenContext) -> Result<Bound> {
+    if let Some(n) = indexing::int_literal(index) {
+        return Ok(Bound::Literal(n));
+    }
+    let sign = int_sign(index, ctx);
+    let expr = index.to_rust_expr(ctx)?;
+    // A `BigInt` position is converted, as Python's index-sized integer
+    if big_int(index, ctx) {
+        let position: syn::Expr = match index {
+            HirExpr::Var(_) => parse_quote! { &#expr },
+            _ => unparen(expr),
+        };
+        let message = "cannot fit 'int' into an index-sized integer";
+        return Ok(Bound::Expr(bigint::to_i64(&position, message), sign));
+    }
+    Ok(Bound::Expr(expr, sign))
+}
+
+/// Unwrap `found`, an `Option` of an element, failing with Python's
+/// `message` as the index policy of `ctx` says when it is `None`
+fn unwrap_element(found: syn::Expr, message: &str, ctx: &mut CodeGenContext) -> Result<syn::Expr> {
+    Ok(match ctx.error_scope.index_policy {
+        IndexPolicy::Panic | IndexPolicy::Native => parse_quote! { #found.expect(#message) },
+        IndexPolicy::Default => parse_quote! { #found.unwrap_or_default() },
+        IndexPolicy::Raise => unwrap_or_raise(found, "IndexError", message, ctx)?,
+    })
+}
+
+/// Unwrap `found`, an `Option`, raising `exception` with `message` when it is `None`
+fn unwrap_or_raise(
+    found: syn::Expr,
+    exception: &str,
+    message: &str,
+    ctx: &mut CodeGenContext,
+) -> Result<syn::Expr> {
+    let raised = HirExpr::Call {
+        func: exception.to_string(),
+        args: vec![HirExpr::Literal(Literal::String(message.to_string()))],
+        kwargs: vec![],
+    };
+    let error = generate_error_value(Some(&raised), ctx)?;
+    Ok(
+        if ctx.error_scope.returns_result && !ctx.error_scope.inside_try() {
+            parse_quote! { #found.ok_or_else(|| #error)? }
+        } else {
+            let exit = exit_with_error(error, ctx)?;
+            parse_quote! {
+                match #found {
+                    Some(value) => value,
+                    None => { #exit }
+                }
+            }
+        },
+    )
+}
+
+/// Unwrap `found`, the `Option` of a checked int operation, as `ctx`'s
+/// overflow policy says
+fn unwrap_overflow(found: syn::Expr, ctx: &mut CodeGenContext) -> Result<syn::Expr> {
+    const MESSAGE: &str = "integer overflow";
+    match ctx.error_scope.overflow {
+        Overflow::Raise => unwrap_or_raise(found, "OverflowError", MESSAGE, ctx),
+        _ => Ok(parse_quote! { #found.expect(#MESSAGE) }),
+    }
+}
+
+/// Pattern binding every name of a target made only of names, such as
+/// `(a, (b, c))`, with `_` before each `unread` name; `None` when the
+/// target also writes to places
+fn binding_pattern(
+    target: &AssignTarget,
+    mutable: &dyn Fn(&str) -> bool,
+    unread: &dyn Fn(&str) -> bool,
+) -> Option<proc_macro2::TokenStream> {
+    match target {
+        AssignTarget::Symbol(name) if unread(name) => {
+            let ident = syn::Ident::new(&format!("_{name}"), proc_macro2::Span::call_site());
+            Some(quote! { #ident })
+        }
+        AssignTarget::Symbol(name) => {
+            let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+            let mutability = mutable(name).then(|| quote! { mut });
+            Some(quote! { #mutability #ident })
+        }
+        AssignTarget::Tuple(targets) => {
+            let patterns = targets
+                .iter()
+                .map(|t| binding_pattern(t, mutable, unread))
+                .collect::<Option<Vec<_>>>()?;
+            Some(quote! { (#(#patterns),*) })
+        }
+        AssignTarget::Attribute { .. } | AssignTarget::Index { .. } => None,
+    }
+}
+
+/// An expression without its outer parentheses, for a position where they
+/// are redundant
+fn unparen(expr: syn::Expr) -> syn::Expr {
+    match expr {
+        syn::Expr::Paren(paren) => unparen(*paren.expr),
+        expr => expr,
+    }
+}
+
+/// `x = x + y` as `x += y`
+fn compound_assignment(target: &syn::Ident, value: &syn::Expr) -> Option<proc_macro2::TokenStream> {
+    let syn::Expr::Binary(binary) = value else {
+        return None;
+    };
+    // `&x`, as a `BigInt` operand
+    let left = match binary.left.as_ref() {
+        syn::Expr::Reference(reference) => reference.expr.as_ref(),
+        left => left,
+    };
+    let syn::Expr::Path(left) = left else {
+        return None;
+    };
+    if !left.path.is_ident(target) {
+        return None;
+    }
+    let op = match binary.op {
+        syn::BinOp::Add(_) => quote! { += },
+        syn::BinOp::Sub(_) => quote! { -= },
+        syn::BinOp::Mul(_) => quote! { *= },
+        syn::BinOp::Div(_) => quote! { /= },
+        syn::BinOp::Rem(_) => quote! { %= },
+        syn::BinOp::BitAnd(_) => quote! { &= },
+        syn::BinOp::BitOr(_) => quote! { |= },
+        syn::BinOp::BitXor(_) => quote! { ^= },
+        syn::BinOp::Shl(_) => quote! { <<= },
+        syn::BinOp::Shr(_) => quote! { >>= },
+        _ => return None,
+    };
+    let right = unparen(binary.right.as_ref().clone());
+    Some(quote! { #target #op #right; })
+}
+
+/// Assignee expression writing to every part of a target, as in `(a, self.b) = value`
+fn assignee_expr(
+    target: &AssignTarget,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    Ok(match target {
+        AssignTarget::Symbol(name) => {
+            let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+            quote! { #ident }
+        }
+        AssignTarget::Attribute { value, attr } => {
+            let base = value.to_rust_expr(ctx)?;
+            let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+            quote! { #base.#attr_ident }
+        }
+        AssignTarget::Index { base, index } => match subscript_place(base, index, ctx)? {
+            SubscriptPlace::Element { list, position, .. } => quote! { #list[#position] },
+            SubscriptPlace::Entry { .. } => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Unpacking into dict entries is not supported; assign each entry separately"
+                    .to_string(),
+            )),
+        },
+        AssignTarget::Tuple(targets) => {
+            let parts = targets
+                .iter()
+                .map(|t| assignee_expr(t, ctx))
+                .collect::<Result<Vec<_>>>()?;
+            quote! { (#(#parts),*) }
+        }
+    })
+}
+
+/// Lower `a, b = value`. The value is evaluated in full before any part is
+/// written, so `a, b = b, a` swaps.
+fn generate_unpacking(
+    target: &AssignTarget,
+    value: &HirExpr,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    // A list display unpacks like the tuple of its elements
+    let value_expr = match (target, value) {
+        (AssignTarget::Tuple(targets), HirExpr::Tuple(elts) | HirExpr::List(elts))
+            if ctx.ints.big && targets.len() == elts.len() =>
+        {
+            big_unpacking(targets, elts, ctx)?
+        }
+        (_, HirExpr::List(elts)) => HirExpr::Tuple(elts.clone()).to_rust_expr(ctx)?,
+        (_, value) => value.to_rust_expr(ctx)?,
+    };
+    let names = target.symbols();
+    let all_new = names.iter().all(|name| !ctx.is_declared(name))
+        && names.iter().collect::<HashSet<_>>().len() == names.len();
+    if all_new {
+        let written_later = |name: &str| ctx.live.written_later(name);
+        if let Some(pattern) = binding_pattern(target, &written_later, &|_| false) {
+            for name in names {
+                ctx.declare_var(name);
+            }
+            return Ok(quote! { let #pattern = #value_expr; });
+        }
+    }
+    // Names bound for the first time are declared, then assigned with the rest
+    let mut declarations = Vec::new();
+    for name in names {
+        if !ctx.is_declared(name) {
+            ctx.declare_var(name);
+            let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+            let mutability = ctx.live.written_later(name).then(|| quote! { mut });
+            declarations.push(quote! { let #mutability #ident; });
+        }
+    }
+    let assignee = assignee_expr(target, ctx)?;
+    Ok(quote! {
+        #(#declarations)*
+        #assignee = #value_expr;
+    })
+}
+
+/// The tuple of `elts` unpacked into `targets` when ints are `BigInt`s:
+/// each element bound to a `BigInt` local becomes one, and a local is
+/// copied since the tuple may read it again
+fn big_unpacking(
+    targets: &[AssignTarget],
+    elts: &[HirExpr],
+    ctx: &mut CodeGenContext,
+) -> Result<syn::Expr> {
+    let mut parts: Vec<syn::Expr> = Vec::new();
+    let mut new_ints = Vec::new();
+    for (target, elt) in targets.iter().zip(elts) {
+        match target {
+            AssignTarget::Symbol(name) if big_target(name, elt, ctx) => {
+                if !ctx.is_declared(name) {
+                    new_ints.push(name.clone());
+                }
+                parts.push(unparen(big_value(elt, false, ctx)?));
+            }
+            _ => parts.push(unparen(elt.to_rust_expr(ctx)?)),
+        }
+    }
+    // The elements read the locals as they were before the assignment
+    for name in new_ints {
+        ctx.var_types.insert(name, Type::Int);
+    }
+    Ok(parse_quote! { (#(#parts),*) })
+}
+
+/// `return`, running any enclosing `finally` bodies first
+fn generate_return(
+    value: Option<syn::Expr>,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    // `Ok(())` stays an early return: the body is followed by one
+    let tail = ctx.live.in_tail() && !(value.is_none() && ctx.error_scope.returns_result);
+    let value = match (value.map(unparen), ctx.error_scope.returns_result) {
+        // `Ok(call?)` is the `Result` of the call, which has the same error
+        // type unless the errors are Lambda's
+        (Some(syn::Expr::Try(value)), true) if !ctx.error_scope.lambda => {
+            let value = value.expr;
+            Some(quote! { #value })
+        }
+        (Some(value), true) => Some(quote! { Ok(#value) }),
+        (None, true) => Some(quote! { Ok(()) }),
+        (value, false) => value.map(|v| quote! { #v }),
+    };
+    let (finally, _) = unwind_frames(ctx, false)?;
+    if finally.is_empty() {
+        // The function's value in tail position
+        if tail {
+            return Ok(quote! { #value });
+        }
+        return Ok(quote! { return #value; });
+    }
+    match value {
+        Some(value) => Ok(quote! {
+            {
+                let result = #value;
+                #(#finally)*
+                return result;
+            }
+        }),
+        None => Ok(quote! {
+            {
+                #(#finally)*
+                return;
+            }
+        }),
+    }
+}
+
+/// Generate the `finally` bodies an exit passes through, innermost first.
+///
+/// With `to_try` set, stops at the innermost `try` body and returns its label.
+fn unwind_frames(
+    ctx: &mut CodeGenContext,
+    to_try: bool,
+) -> Result<(Vec<proc_macro2::TokenStream>, Option<String>)> {
+    let mut finally = Vec::new();
+    for i in (0..ctx.error_scope.frames.len()).rev() {
+        match ctx.error_scope.frames[i].clone() {
+            ExitFrame::TryBody(label) if to_try => return Ok((finally, Some(label))),
+            ExitFrame::Finally(body) => {
+                // Errors raised by the `finally` body itself belong to the frames outside it
+                let outer = ctx.error_scope.frames[..i].to_vec();
+                let saved = std::mem::replace(&mut ctx.error_scope.frames, outer);
+                let stmts = generate_scoped_body(&body, ctx);
+                ctx.error_scope.frames = saved;
+                finally.extend(stmts?);
+            }
+            ExitFrame::TryBody(_) | ExitFrame::Handler(_) => {}
+        }
+    }
+    Ok((finally, None))
+}
+
+/// Closure parameter binding the items of a comprehension chain
+/// The items a comprehension clause binds its variable to
+struct ClauseItems {
+    /// `Unknown` unless the clause iterates a typed local or the items of an
+    /// enclosing clause
+    ty: Type,
+    /// Bound by reference to the items of its collection
+    lent: bool,
+    /// The element and value only read the variable
+    read_only: bool,
+}
+
+fn comprehension_pattern(targets: &[syn::Ident]) -> proc_macro2::TokenStream {
+    match targets {
+        [target] => quote! { #target },
+        _ => quote! { (#(#targets),*) },
+    }
+}
+
+/// Leave with `error`: break out of the innermost `try` body, return it from a
+/// function returning `Result`, or panic
+fn exit_with_error(error: syn::Expr, ctx: &mut CodeGenContext) -> Result<proc_macro2::TokenStream> {
+    let (finally, label) = unwind_frames(ctx, true)?;
+    let returns_result = ctx.error_scope.returns_result;
+    let exit = |error: &syn::Expr| match &label {
+        Some(label) => {
+            let label = syn::Lifetime::new(label, proc_macro2::Span::call_site());
+            quote! { break #label Err(#error); }
+        }
+        None if returns_result => quote! { return Err(#error); },
+        None => quote! { panic!("{}", #error); },
+    };
+    if finally.is_empty() {
+        return Ok(exit(&error));
+    }
+    let exit = exit(&parse_quote! { error });
+    Ok(quote! {
+        {
+            let error = #error;
+            #(#finally)*
+            #exit
+        }
+    })
+}
+
+/// The error value for a `raise` statement
+fn generate_error_value(
+    exception: Option<&HirExpr>,
+    ctx: &mut CodeGenContext,
+) -> Result<syn::Expr> {
+    let Some(expr) = exception else {
+        // A bare `raise` re-raises the error bound by the innermost handler
+        let Some(name) = ctx.error_scope.handled_error() else {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Bare 'raise' is only supported inside an except clause".to_string(),
+            ));
+        };
+        let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+        return Ok(parse_quote! { #ident });
+    };
+    let (class, args) = match expr {
+        HirExpr::Call { func, args, .. } if ctx.exceptions.is_exception(func) => {
+            (func, args.as_slice())
+        }
+        HirExpr::Var(name) if ctx.exceptions.is_exception(name) => (name, &[][..]),
+        // Re-raising a caught error
+        HirExpr::Var(_) => return expr.to_rust_expr(ctx),
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Only exception classes and caught errors can be raised".to_string(),
+        )),
+    };
+    let message: syn::Expr = match args.first() {
+        None => parse_quote! { String::new() },
+        Some(HirExpr::Literal(Literal::String(s))) => parse_quote! { #s.to_string() },
+        Some(arg) => {
+            let arg = arg.to_rust_expr(ctx)?;
+            parse_quote! { #arg.to_string() }
+        }
+    };
+    if ctx.error_scope.lambda {
+        let variant = exceptions::lambda_error_variant(&LambdaErrorHandler::new(), class);
+        return Ok(exceptions::lambda_error_expr(variant, class, &message));
+    }
+    let error_ident = syn::Ident::new(ERROR_TYPE_NAME, proc_macro2::Span::call_site());
+    let variant = syn::Ident::new(class, proc_macro2::Span::call_site());
+    Ok(parse_quote! { #error_ident::#variant(#message) })
+}
+
+/// Patterns for the errors an `except` clause catches; `None` catches everything
+fn handler_patterns(handler: &ExceptHandler, ctx: &CodeGenContext) -> Option<Vec<syn::Pat>> {
+    if ctx.exceptions.is_catch_all(handler) {
+        return None;
+    }
+    let caught = exceptions::caught_variants(&ctx.exceptions, handler, &ctx.error_variants);
+    let mut patterns: Vec<syn::Pat> = Vec::new();
+    for class in caught {
+        let pattern: syn::Pat = if ctx.error_scope.lambda {
+            let variant = exceptions::lambda_error_variant(&LambdaErrorHandler::new(), class);
+            let variant = syn::Ident::new(variant, proc_macro2::Span::call_site());
+            parse_quote! { LambdaError::#variant { .. } }
+        } else {
+            let error_ident = syn::Ident::new(ERROR_TYPE_NAME, proc_macro2::Span::call_site());
+            let variant = syn::Ident::new(class, proc_macro2::Span::call_site());
+            parse_quote! { #error_ident::#variant(_) }
+        };
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+    Some(patterns)
+}
+
+/// Symbols assigned at the top level of any of the bodies, or of `if`,
+/// `try`, `with` and `match` statements nested in them, in order
+fn assigned_symbols<'s>(bodies: &[&'s [Spanned<HirStmt>]]) -> Vec<&'s Symbol> {
+    let mut names = Vec::new();
+    for stmt in bodies.iter().copied().flatten() {
+        let nested = match &stmt.node {
+            HirStmt::Assign { target, .. } => target.symbols(),
+            HirStmt::If {
+                then_body,
+                else_body,
+                ..
+            } => assigned_symbols(&[then_body, else_body.as_deref().unwrap_or_default()]),
+            HirStmt::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => assigned_symbols(&try_bodies(body, handlers, orelse, finalbody)),
+            HirStmt::With { body, .. } => assigned_symbols(&[body]),
+            HirStmt::Match { cases, .. } => {
+                let bodies: Vec<&[Spanned<HirStmt>]> =
+                    cases.iter().map(|c| c.body.as_slice()).collect();
+                assigned_symbols(&bodies)
+            }
+            _ => vec![],
+        };
+        for name in nested {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// How the bodies of a statement binding names in its own blocks run
+#[derive(Clone, Copy, PartialEq)]
+enum Bodies {
+    /// Exactly one of them runs
+    Exhaustive,
+    /// At most one of them runs
+    Partial,
+    /// Several may run, one after another, as the parts of a `try` do
+    Sequential,
+}
+
+/// Declarations, ahead of a statement whose `bodies` bind names, of the
+/// names read after the block binding them, which Python keeps visible. A
+/// name each of `Exhaustive` bodies assigns once, or leaves by diverging,
+/// is declared uninitialized; any other starts from the default
+fn hoist_bindings(
+    bodies: &[&[Spanned<HirStmt>]],
+    kind: Bodies,
+    ctx: &mut CodeGenContext,
+) -> Vec<proc_macro2::TokenStream> {
+    let mut hoisted = Vec::new();
+    for name in assigned_symbols(bodies) {
+        let read_later = ctx.live.read_later(name)
+            || (kind == Bodies::Sequential
+                && bodies
+                    .iter()
+                    .any(|body| Effects::of(body, &ctx.mutating_methods).reads(name) > 0));
+        if ctx.is_declared(name) || !read_later {
+            continue;
+        }
+        ctx.declare_var(name);
+        let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+        let once = kind == Bodies::Exhaustive && bodies.iter().all(|body| binds_once(body, name));
+        hoisted.push(if once {
+            let mutability = ctx.live.written_later(name).then(|| quote! { mut });
+            quote! { let #mutability #ident; }
+        } else {
+            quote! { let mut #ident = Default::default(); }
+        });
+    }
+    hoisted
+}
+
+/// Whether every path through `body` either assigns `name` exactly once or
+/// leaves the function without assigning it
+fn binds_once(body: &[Spanned<HirStmt>], name: &str) -> bool {
+    let stores: Vec<&Spanned<HirStmt>> = body.iter().filter(|stmt| stores_to(stmt, name)).collect();
+    match stores.as_slice() {
+        [] => diverges(body),
+        [stmt] => match &stmt.node {
+            HirStmt::Assign { .. } => true,
+            HirStmt::If {
+                then_body,
+                else_body: Some(else_body),
+                ..
+            } => binds_once(then_body, name) && binds_once(else_body, name),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether `stmt`, or a block nested in it, assigns the local `name`
+fn stores_to(stmt: &Spanned<HirStmt>, name: &str) -> bool {
+    let any = |body: &[Spanned<HirStmt>]| body.iter().any(|stmt| stores_to(stmt, name));
+    match &stmt.node {
+        HirStmt::Assign { target, .. } => target.symbols().iter().any(|s| *s == name),
+        HirStmt::If {
+            then_body,
+            else_body,
+            ..
+        } => any(then_body) || any(else_body.as_deref().unwrap_or_default()),
+        HirStmt::While { body, .. } | HirStmt::For { body, .. } | HirStmt::With { body, .. } => {
+            any(body)
+        }
+        HirStmt::Try {
+            body,
+            handlers,
+            orelse,
+            finalbody,
+        } => try_bodies(body, handlers, orelse, finalbody)
+            .into_iter()
+            .any(any),
+        HirStmt::Match { cases, .. } => cases.iter().any(|c| any(&c.body)),
+        _ => false,
+    }
+}
+
+/// The hoisted declarations followed by `stmt`. A single name every branch
+/// of `stmt` assigns last takes the value of `stmt` itself
+fn declare_hoisted(
+    hoisted: Vec<proc_macro2::TokenStream>,
+    stmt: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    if let [declaration] = hoisted.as_slice() {
+        if let (Ok(syn::Stmt::Local(local)), Ok(mut value)) = (
+            syn::parse2::<syn::Stmt>(declaration.clone()),
+            syn::parse2::<syn::Expr>(stmt.clone()),
+        ) {
+            if let (None, syn::Pat::Ident(binding)) = (&local.init, &local.pat) {
+                if yields_assigned(&mut value, &binding.ident) {
+                    return quote! { let #binding = #value; };
+                }
+            }
+        }
+    }
+    quote! { #(#hoisted)* #stmt }
+}
+
+/// Turn every branch of `expr` that ends assigning `name` into one ending
+/// in the assigned value, provided each branch does so or diverges
+fn yields_assigned(expr: &mut syn::Expr, name: &syn::Ident) -> bool {
+    match expr {
+        syn::Expr::If(expr_if) => {
+            let Some((_, else_branch)) = &mut expr_if.else_branch else {
+                return false;
+            };
+            yields_in_block(&mut expr_if.then_branch, name) && yields_assigned(else_branch, name)
+        }
+        syn::Expr::Match(expr_match) => expr_match
+            .arms
+            .iter_mut()
+            .all(|arm| yields_assigned(&mut arm.body, name)),
+        syn::Expr::Block(block) => yields_in_block(&mut block.block, name),
+        _ => false,
+    }
+}
+
+/// Turn the branches of `expr` that end returning into ones ending in the
+/// returned value, once `expr` is itself the function's value
+fn returns_as_tail(expr: &mut syn::Expr) {
+    let blocks: Vec<&mut syn::Block> = match expr {
+        syn::Expr::If(expr_if) => {
+            if let Some((_, else_branch)) = &mut expr_if.else_branch {
+                returns_as_tail(else_branch);
+            }
+            vec![&mut expr_if.then_branch]
+        }
+        syn::Expr::Match(expr_match) => {
+            for arm in &mut expr_match.arms {
+                returns_as_tail(&mut arm.body);
+            }
+            vec![]
+        }
+        syn::Expr::Block(block) => vec![&mut block.block],
+        _ => vec![],
+    };
+    for block in blocks {
+        match block.stmts.last_mut() {
+            Some(last @ syn::Stmt::Expr(syn::Expr::Return(_), _)) => {
+                if let syn::Stmt::Expr(syn::Expr::Return(ret), _) = last.clone() {
+                    if let Some(value) = ret.expr {
+                        *last = syn::Stmt::Expr(*value, None);
+                    }
+                }
+            }
+            Some(syn::Stmt::Expr(expr @ (syn::Expr::If(_) | syn::Expr::Match(_)), _)) => {
+                returns_as_tail(expr)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn yields_in_block(block: &mut syn::Block, name: &syn::Ident) -> bool {
+    let Some(last) = block.stmts.last_mut() else {
+        return false;
+    };
+    match last {
+        syn::Stmt::Expr(syn::Expr::Assign(assign), Some(_)) if matches!(&*assign.left, syn::Expr::Path(p) if p.path.is_ident(name)) =>
+        {
+            *last = syn::Stmt::Expr((*assign.right).clone(), None);
+            true
+        }
+        syn::Stmt::Expr(syn::Expr::Return(_) | syn::Expr::Continue(_) | syn::Expr::Break(_), _) => {
+            true
+        }
+        syn::Stmt::Macro(mac) => mac.mac.path.is_ident("panic"),
+        syn::Stmt::Expr(expr @ (syn::Expr::If(_) | syn::Expr::Match(_)), _) => {
+            yields_assigned(expr, name)
+        }
+        _ => false,
+    }
+}
+
+fn try_bodies<'s>(
+    body: &'s [Spanned<HirStmt>],
+    handlers: &'s [ExceptHandler],
+    orelse: &'s [Spanned<HirStmt>],
+    finalbody: &'s [Spanned<HirStmt>],
+) -> Vec<&'s [Spanned<HirStmt>]> {
+    let mut bodies = vec![body];
+    bodies.extend(handlers.iter().map(|h| h.body.as_slice()));
+    bodies.push(orelse);
+    bodies.push(finalbody);
+    bodies
+}
+
+/// Lower `try` to a labeled block yielding `Result<(), E>`, a `match` of the
+/// error against the `except` clauses, and the `finally` body
+fn generate_try(
+    body: &[Spanned<HirStmt>],
+    handlers: &[ExceptHandler],
+    orelse: &[Spanned<HirStmt>],
+    finalbody: &[Spanned<HirStmt>],
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    // Python leaves names unbound when an error skips their assignment;
+    // starting from the default keeps every path initialized
+    let hoisted = hoist_bindings(
+        &try_bodies(body, handlers, orelse, &[]),
+        Bodies::Sequential,
+        ctx,
+    );
+
+    if !finalbody.is_empty() {
+        ctx.error_scope
+            .frames
+            .push(ExitFrame::Finally(finalbody.to_vec()));
+    }
+    // Nothing raised in the module reaches the clauses: the body runs, then
+    // `else`, and `finally` as on every other path
+    if ctx.error_variants.is_empty() && !ctx.error_scope.lambda {
+        let body_stmts = generate_scoped_body(body, ctx)?;
+        let orelse_stmts = generate_scoped_body(orelse, ctx)?;
+        if !finalbody.is_empty() {
+            ctx.error_scope.frames.pop();
+        }
+        let final_stmts = if diverges(body) || diverges(orelse) {
+            Vec::new()
+        } else {
+            generate_body(finalbody, ctx)?
+        };
+        return Ok(quote! {
+            #(#hoisted)*
+            #(#body_stmts)*
+            #(#orelse_stmts)*
+            #(#final_stmts)*
+        });
+    }
+
+    let label_name = format!("'try_{}", ctx.error_scope.next_label);
+    ctx.error_scope.next_label += 1;
+    let label = syn::Lifetime::new(&label_name, proc_macro2::Span::call_site());
+    let error_ty = ctx.error_scope.error_type();
+
+    // Operations of the body raise the builtin errors the clauses catch
+    let saved_raised = ctx.error_scope.raised_builtins.clone();
+    let saved_policy = ctx.error_scope.index_policy;
+    if !ctx.error_scope.lambda {
+        let mut types = ctx.var_types.clone();
+        types.extend(indexing::local_types(&[], body));
+        for name in ctx.exceptions.implicit_raises(body, handlers, &types) {
+            if ctx.error_variants.contains(&name) && !ctx.error_scope.raises(&name) {
+                ctx.error_scope.raised_builtins.push(name);
+            }
+        }
+        if ctx.error_scope.raises("IndexError") {
+            ctx.error_scope.index_policy = IndexPolicy::Raise;
+        }
+    }
+    ctx.error_scope.frames.push(ExitFrame::TryBody(label_name));
+    let body_stmts = generate_scoped_body(body, ctx);
+    ctx.error_scope.frames.pop();
+    ctx.error_scope.raised_builtins = saved_raised;
+    ctx.error_scope.index_policy = saved_policy;
+    let body_stmts = body_stmts?;
+
+    // A body that always returns or raises never completes, so `else` never
+    // runs and the block yields nothing but errors
+    let body_diverges = diverges(body);
+    let (ok_ty, completes, ok_arm) = if body_diverges {
+        (
+            quote! { std::convert::Infallible },
+            None,
+            quote! { Ok(never) => match never {} },
+        )
+    } else {
+        let orelse_stmts = generate_scoped_body(orelse, ctx)?;
+        (
+            quote! { () },
+            Some(quote! { Ok(()) }),
+            quote! { Ok(()) => { #(#orelse_stmts)* } },
+        )
+    };
+    let mut arms = vec![ok_arm];
+    let mut all_diverge = body_diverges || diverges(orelse);
+    let mut catch_all = false;
+    let mut caught: Vec<syn::Pat> = Vec::new();
+    for handler in handlers {
+        let patterns = handler_patterns(handler, ctx);
+        if patterns.as_ref().is_some_and(|p| p.is_empty()) {
+            // Neither the module nor the body's operations raise what the
+            // clause catches
+            continue;
+        }
+        let name = handler.name.clone().unwrap_or_else(|| "error".to_string());
+        let ident = syn::Ident::new(&name, proc_macro2::Span::call_site());
+        let binding = match (
+            exceptions::body_uses_exception(&handler.body, &name),
+            &patterns,
+        ) {
+            (true, Some(patterns)) => quote! { #ident @ (#(#patterns)|*) },
+            (true, None) => quote! { #ident },
+            (false, Some(patterns)) => quote! { #(#patterns)|* },
+            (false, None) => quote! { _ },
+        };
+
+        ctx.error_scope
+            .frames
+            .push(ExitFrame::Handler(name.clone()));
+        ctx.enter_scope();
+        ctx.declare_var(&name);
+        let handler_stmts = generate_body(&handler.body, ctx);
+        ctx.exit_scope();
+        ctx.error_scope.frames.pop();
+        let handler_stmts = handler_stmts?;
+
+        arms.push(quote! { Err(#binding) => { #(#handler_stmts)* } });
+        all_diverge &= diverges(&handler.body);
+        let Some(patterns) = patterns else {
+            // Later clauses are unreachable
+            catch_all = true;
+            break;
+        };
+        for pattern in patterns {
+            if !caught.contains(&pattern) {
+                caught.push(pattern);
+            }
+        }
+    }
+    // Clauses naming every error the module raises leave nothing to rethrow
+    catch_all |= !ctx.error_scope.lambda && caught.len() == ctx.error_variants.len();
+    if !catch_all {
+        let rethrow = exit_with_error(parse_quote! { error }, ctx)?;
+        arms.push(quote! { Err(error) => { #rethrow } });
+    }
+    if !finalbody.is_empty() {
+        ctx.error_scope.frames.pop();
+    }
+    // Paths leaving by `return` or an error have run `finally` already
+    let final_stmts = if all_diverge {
+        Vec::new()
+    } else {
+        generate_body(finalbody, ctx)?
+    };
+
+    Ok(quote! {
+        #(#hoisted)*
+        let try_result: Result<#ok_ty, #error_ty> = #label: {
+            #(#body_stmts)*
+            #completes
+        };
+        match try_result {
+            #(#arms),*
+        }
+        #(#final_stmts)*
+    })
+}
+
+/// `if isinstance(x, C):` on a union `x` as a `match` on its variants,
+/// rebinding `x` to the member in each branch that knows which one it holds
+fn generate_isinstance_if(
+    condition: &HirExpr,
+    then_body: &[Spanned<HirStmt>],
+    else_body: Option<&[Spanned<HirStmt>]>,
+    ctx: &mut CodeGenContext,
+) -> Result<Option<proc_macro2::TokenStream>> {
+    let HirExpr::Call { func, args, .. } = condition else {
+        return Ok(None);
+    };
+    let (name, class) = match (func.as_str(), args.as_slice()) {
+        ("isinstance", [HirExpr::Var(name), class]) if !ctx.is_declared(func) => (name, class),
+        _ => return Ok(None),
+    };
+    let Some(ty) = ctx.var_types.get(name).cloned() else {
+        return Ok(None);
+    };
+    let Type::Union(members) = &ty else {
+        return Ok(None);
+    };
+    let matched = builtins::isinstance_members(members, class)?;
+    let rest: Vec<&Type> = members.iter().filter(|m| !matched.contains(m)).collect();
+    if matched.is_empty() || rest.is_empty() {
+        // The condition is constant, as `lower_isinstance` makes it
+        return Ok(None);
+    }
+    let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+    // The value is moved into the branch unless something reads it after
+    let scrutinee = if ctx.can_move_after(name) {
+        quote! { #ident }
+    } else {
+        quote! { #ident.clone() }
+    };
+
+    let branch = |members: &[&Type], body: &[Spanned<HirStmt>], ctx: &mut CodeGenContext| {
+        let variants: Vec<syn::Path> = members
+            .iter()
+            .filter_map(|member| type_enums::union_variant(&ty, member))
+            .collect();
+        let reads = Effects::of(body, &ctx.mutating_methods).reads(name) > 0;
+        let pattern = match (variants.as_slice(), reads) {
+            ([variant], true) => quote! { #variant(#ident) },
+            (variants, true) => quote! { #ident @ (#(#variants(_))|*) },
+            (variants, false) => quote! { #(#variants(_))|* },
+        };
+        let declared = ctx.var_types.get(name).cloned();
+        if let [member] = members {
+            ctx.var_types.insert(name.clone(), (*member).clone());
+        }
+        ctx.enter_scope();
+        let stmts = generate_body(body, ctx);
+        ctx.exit_scope();
+        if let Some(declared) = declared {
+            ctx.var_types.insert(name.clone(), declared);
+        }
+        let stmts = stmts?;
+        Ok::<_, anyhow::Error>(quote! { #pattern => { #(#stmts)* } })
+    };
+    let then_arm = branch(&matched, then_body, ctx)?;
+    // `if isinstance(x, int): return ...` leaves `x` the one other member
+    // for the rest of the function, when in its outermost block
+    if let (None, [member]) = (else_body, rest.as_slice()) {
+        // The module's scope, then the function's
+        let outermost = ctx.declared_vars.len() == 2;
+        if outermost && diverges(then_body) && !ctx.live.written_later(name) {
+            let variant = type_enums::union_variant(&ty, member).expect("a member of the union");
+            let owned = !ctx.borrowed_vars.contains(name)
+                && ctx.borrow_scope.passing(name) == ParamPassing::Owned;
+            let scrutinee = if owned {
+                quote! { #ident }
+            } else {
+                quote! { #ident.clone() }
+            };
+            ctx.var_types.insert(name.clone(), (*member).clone());
+            return Ok(Some(quote! {
+                let #ident = match #scrutinee {
+                    #then_arm
+                    #variant(#ident) => #ident,
+                };
+            }));
+        }
+    }
+    let else_arm = branch(&rest, else_body.unwrap_or_default(), ctx)?;
+    Ok(Some(quote! {
+        match #scrutinee {
+            #then_arm
+            #else_arm
+        }
+    }))
+}
+
+/// Whether every path through `body` leaves it by `return` or `raise`
+fn diverges(body: &[Spanned<HirStmt>]) -> bool {
+    body.last().is_some_and(|stmt| match &stmt.node {
+        HirStmt::Return(_) | HirStmt::Raise { .. } => true,
+        HirStmt::If {
+            then_body,
+            else_body: Some(else_body),
+            ..
+        } => diverges(then_body) && diverges(else_body),
+        HirStmt::Try {
+            body,
+            handlers,
+            orelse,
+            finalbody,
+        } => {
+            diverges(finalbody)
+                || ((diverges(body) || diverges(orelse))
+                    && handlers.iter().all(|h| diverges(&h.body)))
+        }
+        _ => false,
+    })
+}
+
+/// Declared type of a variable or `self` field, where one is known
+fn declared_type(expr: &HirExpr, ctx: &CodeGenContext) -> Option<Type> {
+    let ty = match expr {
+        HirExpr::Var(name) => ctx.var_types.get(name).cloned(),
+        HirExpr::Attribute { value, attr } if is_self(value) => {
+            ctx.class_fields.get(attr).map(|f| f.field_type.clone())
+        }
+        HirExpr::Call { func, .. } => ctx.return_types.get(func).cloned(),
+        _ => None,
+    };
+    ty.filter(|ty| *ty != Type::Unknown)
+}
+
+/// `value`, the code of `expr`, as a value of `ty` when that is a generated
+/// enum: a string literal names its `Literal` variant, and a value of a
+/// member type is wrapped in the `Union` variant holding it
+fn enum_value(
+    expr: &HirExpr,
+    value: syn::Expr,
+    ty: &Type,
+    ctx: &CodeGenContext,
+) -> Result<syn::Expr> {
+    match (ty, expr) {
+        (Type::Literal(_), HirExpr::Literal(Literal::String(s))) => {
+            type_enums::literal_variant(ty, s)
+        }
+        (Type::Union(_), _) => {
+            let member = declared_type(expr, ctx)
+                .unwrap_or_else(|| generators::infer_type(expr, &ctx.var_types));
+            Ok(match type_enums::union_variant(ty, &member) {
+                Some(variant) => {
+                    let value = unparen(value);
+                    parse_quote! { #variant(#value) }
+                }
+                None => value,
+            })
+        }
+        _ => Ok(value),
+    }
+}
+
+/// `args` with each tuple display passed for a list parameter, as one of
+/// type `Tuple[int, ...]`, made a list display
+fn list_arguments(args: &[HirExpr], params: &[Type]) -> Vec<HirExpr> {
+    args.iter()
+        .enumerate()
+        .map(|(i, arg)| match (params.get(i), arg) {
+            (Some(Type::List(_)), HirExpr::Tuple(items)) => HirExpr::List(items.clone()),
+            _ => arg.clone(),
+        })
+        .collect()
+}
+
+/// Whether `expr` is known to be a set
+fn set_typed(expr: &HirExpr, ctx: &CodeGenContext) -> bool {
+    let ty =
+        declared_type(expr, ctx).unwrap_or_else(|| generators::infer_type(expr, &ctx.var_types));
+    matches!(ty, Type::Set(_))
+}
+
+/// Whether `expr` is known to be a string
+fn string_typed(expr: &HirExpr, ctx: &CodeGenContext) -> bool {
+    let ty =
+        declared_type(expr, ctx).unwrap_or_else(|| generators::infer_type(expr, &ctx.var_types));
+    ty == Type::String
+}
+
+/// Whether `expr` is known to be an int, of any representation
+fn int_typed(expr: &HirExpr, ctx: &CodeGenContext) -> bool {
+    indexing::int_literal(expr).is_some()
+        || declared_type(expr, ctx).unwrap_or_else(|| generators::infer_type(expr, &ctx.var_types))
+            == Type::Int
+}
+
+/// Whether int expression `expr` is a `BigInt`: under `integer_type =
+/// "bigint"`, an int local not proven bounded, or int arithmetic whose
+/// result is not proven to fit an `i64`
+fn big_int(expr: &HirExpr, ctx: &CodeGenContext) -> bool {
+    if !ctx.ints.big {
+        return false;
+    }
+    match expr {
+        HirExpr::Var(name) => {
+            ctx.var_types.get(name) == Some(&Type::Int) && !ctx.ints.is_bounded(name)
+        }
+        // A negative power of an int is a float
+        HirExpr::Binary {
+            op: BinOp::Pow,
+            right,
+            ..
+        } if indexing::int_literal(right).is_some_and(|n| n < 0) => false,
+        HirExpr::Binary {
+            op: BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::FloorDiv | BinOp::Mod | BinOp::Pow,
+            left,
+            right,
+        } => {
+            let (left_big, right_big) = (big_int(left, ctx), big_int(right, ctx));
+            (left_big || int_typed(left, ctx))
+                && (right_big || int_typed(right, ctx))
+                && (left_big || right_big || ctx.ints.interval(expr).is_none())
+        }
+        HirExpr::Unary {
+            op: UnaryOp::Neg | UnaryOp::Pos,
+            operand,
+        } => big_int(operand, ctx),
+        HirExpr::Index { base, .. } => declared_type(base, ctx).is_some_and(|ty| holds_ints(&ty)),
+        HirExpr::Call { func, args, .. } if !ctx.is_declared(func) => match func.as_str() {
+            "int" => true,
+            "sum" | "min" | "max" if args.len() == 1 => {
+                declared_type(&args[0], ctx).is_some_and(|ty| holds_ints(&ty))
+            }
+            "abs" | "min" | "max" => args.iter().any(|a| big_int(a, ctx)),
+            _ => ctx
+                .big_int_functions
+                .get(func)
+                .is_some_and(|signature| signature.returns_big),
+        },
+        _ => false,
+    }
+}
+
+/// Whether values of `ty` are `Copy`; ints are not where they are `BigInt`s
+fn is_copy(ty: &Type, ctx: &CodeGenContext) -> bool {
+    let holds_int = |ty: &Type| match ty {
+        Type::Tuple(items) => items.contains(&Type::Int),
+        ty => *ty == Type::Int,
+    };
+    closures::is_copy_type(ty) && !(ctx.ints.big && holds_int(ty))
+}
+
+/// Whether `ty` is a collection of ints, which are `BigInt`s where ints are
+fn holds_ints(ty: &Type) -> bool {
+    matches!(ty, Type::List(item) | Type::Set(item) | Type::Dict(_, item) if **item == Type::Int)
+}
+
+/// `expr` as an owned `BigInt`: an int that fits an `i64` converted, and a
+/// local copied unless `may_move` and the statement reads it for the last time
+fn big_value(expr: &HirExpr, may_move: bool, ctx: &mut CodeGenContext) -> Result<syn::Expr> {
+    ctx.needs_bigint = true;
+    let value = expr.to_rust_expr(ctx)?;
+    Ok(match expr {
+        _ if !big_int(expr, ctx) => bigint::from_small(expr, &value),
+        HirExpr::Var(name) if !(may_move && ctx.can_move(name)) => {
+            parse_quote! { #value.clone() }
+        }
+        _ => value,
+    })
+}
+
+/// Whether local `name`, assigned `value`, is a `BigInt`
+fn big_target(name: &str, value: &HirExpr, ctx: &CodeGenContext) -> bool {
+    if ctx.is_declared(name) {
+        big_int(&HirExpr::Var(name.to_string()), ctx)
+    } else {
+        ctx.ints.big && !ctx.ints.is_bounded(name) && (big_int(value, ctx) || int_typed(value, ctx))
+    }
+}
+
+/// How the items of a list of `item`s are taken out of it
+fn list_items(item: &Type, ctx: &CodeGenContext) -> Items {
+    if is_copy(item, ctx) {
+        Items::Copied
+    } else {
+        Items::Cloned
+    }
+}
+
+/// What is known of the sign of an integer expression
+fn int_sign(expr: &HirExpr, ctx: &CodeGenContext) -> Sign {
+    let non_negative = |e: &HirExpr| {
+        indexing::int_literal(e).is_some_and(|n| n >= 0) || int_sign(e, ctx) != Sign::Signed
+    };
+    match expr {
+        HirExpr::Call { func, args, .. } if func == "len" && args.len() == 1 => Sign::NonNegative,
+        HirExpr::Var(name) => ctx.int_signs.get(name).copied().unwrap_or(Sign::Signed),
+        HirExpr::Binary {
+            op: BinOp::Add | BinOp::Mul | BinOp::FloorDiv | BinOp::Mod,
+            left,
+            right,
+        } if non_negative(left) && non_negative(right) => Sign::NonNegative,
+        _ => Sign::Signed,
+    }
+}
+
+/// The loop variable of `for target in iter` and its sign, when it counts
+/// up from zero or more: `range` with a positive step, and `enumerate`
+fn loop_counter<'t>(
+    target: &'t AssignTarget,
+    iter: &HirExpr,
+    ctx: &CodeGenContext,
+) -> Option<(&'t str, Sign)> {
+    let HirExpr::Call { func, args, .. } = iter else {
+        return None;
+    };
+    match (func.as_str(), target) {
+        ("range", AssignTarget::Symbol(name)) => {
+            let from_non_negative = match args.as_slice() {
+                [_] => true,
+                [start, ..] => {
+                    indexing::int_literal(start).is_some_and(|n| n >= 0)
+                        || int_sign(start, ctx) == Sign::NonNegative
+                }
+                [] => false,
+            };
+            let counts_up = args
+                .get(2)
+                .is_none_or(|step| indexing::int_literal(step).is_some_and(|n| n > 0));
+            (from_non_negative && counts_up).then_some((name, Sign::NonNegative))
+        }
+        ("enumerate", AssignTarget::Tuple(targets)) if args.len() == 1 => match targets.first() {
+            Some(AssignTarget::Symbol(name)) => Some((name, Sign::NonNegative)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether `receiver.method(...)` calls a `str` method: `receiver` is a
+/// string, or a value of unknown type when lists have no method by the name
+fn is_str_method(receiver: &HirExpr, method: &str, ctx: &CodeGenContext) -> bool {
+    if !strings::is_method(method) || is_self(receiver) {
+        return false;
+    }
+    if let HirExpr::Var(name) = receiver {
+        if ctx.class_names.contains(name) || ctx.file_vars.contains(name) {
+            return false;
+        }
+    }
+    let ty = declared_type(receiver, ctx)
+        .unwrap_or_else(|| generators::infer_type(receiver, &ctx.var_types));
+    match ty {
+        Type::String => true,
+        Type::Unknown => !matches!(method, "index" | "count"),
+        _ => false,
+    }
+}
+
+/// A field of `self`, or an element or entry of a place, used in place
+/// rather than the clone reading it produces
+fn place_expr(expr: &HirExpr, ctx: &mut CodeGenContext) -> Result<syn::Expr> {
+    match expr {
+        HirExpr::Attribute { value, attr } if is_self(value) => {
+            let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+            Ok(parse_quote! { self.#attr_ident })
+        }
+        HirExpr::Index { base, index } => {
+            let base_type = declared_type(base, ctx);
+            if matches!(base_type, Some(Type::Tuple(_) | Type::String)) {
+                return expr.to_rust_expr(ctx);
+            }
+            let is_dict = match base_type {
+                Some(ty) => matches!(ty, Type::Dict(..)),
+                None => matches!(index.as_ref(), HirExpr::Literal(Literal::String(_))),
+            };
+            let base_expr = place_expr(base, ctx)?;
+            if is_dict {
+                let key = dict_key(index, ctx)?;
+                let found = parse_quote! { #base_expr.get_mut(#key) };
+                return if ctx.error_scope.raises("KeyError") {
+                    unwrap_or_raise(found, "KeyError", "key not found", ctx)
+                } else {
+                    Ok(parse_quote! { #found.expect("key not found") })
+                };
+            }
+            const MESSAGE: &str = "list index out of range";
+            let index = index_bound(index, ctx)?;
+            let position = indexing::position(&index, &parse_quote! { #base_expr.len() });
+            // The length is read before the list is borrowed mutably
+            let from_end = indexing::needs_len(&index);
+            let (setup, position): (Option<syn::Stmt>, syn::Expr) = if from_end {
+                (
+                    Some(parse_quote! { let __i = #position; }),
+                    parse_quote! { __i },
+                )
+            } else {
+                (None, position)
+            };
+            let element = match ctx.error_scope.index_policy {
+                IndexPolicy::Raise => {
+                    let found = parse_quote! { #base_expr.get_mut(#position) };
+                    unwrap_or_raise(found, "IndexError", MESSAGE, ctx)?
+                }
+                _ if from_end => parse_quote! { #base_expr.get_mut(#position).expect(#MESSAGE) },
+                _ => return Ok(parse_quote! { #base_expr[#position] }),
+            };
+            Ok(match setup {
+                Some(setup) => parse_quote! { ({ #setup #element }) },
+                None => element,
+            })
+        }
+        _ => expr.to_rust_expr(ctx),
+    }
+}
+
+/// `key` as the argument of a dict lookup, which borrows it
+fn dict_key(key: &HirExpr, ctx: &mut CodeGenContext) -> Result<syn::Expr> {
+    Ok(match key {
+        HirExpr::Literal(Literal::String(s)) => parse_quote! { #s },
+        HirExpr::Var(name) if ctx.borrow_scope.passing(name) == ParamPassing::Borrowed => {
+            key.to_rust_expr(ctx)?
+        }
+        _ => {
+            let key = key.to_rust_expr(ctx)?;
+            parse_quote! { &#key }
+        }
+    })
+}
+
+/// How the arms of a lowered `match` see the subject
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scrutinee {
+    /// `Copy` subjects are matched by value
+    Value,
+    /// Strings are matched as `&str` so literal patterns apply
+    Str,
+    /// Lists are matched as slices so sequence patterns apply
+    Slice,
+    /// Everything else is matched by reference
+    Ref,
+}
+
+/// How a name captured by a pattern is bound in the arm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaptureKind {
+    /// Bound to an owned value
+    Owned,
+    /// Bound from a mapping entry at the start of the arm
+    Lookup,
+    /// Bound by reference to a `Copy` value
+    CopyRef,
+    /// Bound by reference, cloned for the arm body
+    Ref,
+    /// Bound to a `&str`
+    Str,
+    /// Bound to the `&[T]` rest of a sequence
+    Rest,
+}
+
+/// Rust pattern, extra guard conditions and bindings for one `case` pattern
+#[derive(Default)]
+struct PatternLowering {
+    conditions: Vec<proc_macro2::TokenStream>,
+    captures: Vec<(Symbol, CaptureKind)>,
+    /// Statements binding mapping entries at the start of the arm
+    lookups: Vec<proc_macro2::TokenStream>,
+    next_temp: usize,
+}
+
+impl PatternLowering {
+    fn temp(&mut self, prefix: &str) -> syn::Ident {
+        let name = format!("__{prefix}_{}", self.next_temp);
+        self.next_temp += 1;
+        syn::Ident::new(&name, proc_macro2::Span::call_site())
+    }
+
+    fn capture(&mut self, name: &str, kind: CaptureKind) {
+        if !self.captures.iter().any(|(n, _)| n == name) {
+            self.captures.push((name.to_string(), kind));
+        }
+    }
+
+    fn lower(
+        &mut self,
+        pattern: &Pattern,
+        ty: Option<&Type>,
+        scrutinee: Scrutinee,
+        top: bool,
+        ctx: &mut CodeGenContext,
+    ) -> Result<proc_macro2::TokenStream> {
+        let by_value = top && scrutinee == Scrutinee::Value;
+        match pattern {
+            Pattern::Wildcard => Ok(quote! { _ }),
+            Pattern::Capture { name, pattern } => {
+                let kind = match ty {
+                    _ if by_value => CaptureKind::Owned,
+                    _ if top && scrutinee == Scrutinee::Str => CaptureKind::Str,
+                    Some(Type::Int | Type::Float | Type::Bool) => CaptureKind::CopyRef,
+                    _ => CaptureKind::Ref,
+                };
+                self.capture(name, kind);
+                let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                match pattern {
+                    Some(pattern) => {
+                        let inner = self.lower(pattern, ty, scrutinee, top, ctx)?;
+                        Ok(quote! { #ident @ #inner })
+                    }
+                    None => Ok(quote! { #ident }),
+                }
+            }
+            Pattern::Value(expr) => {
+                self.lower_value(expr, top && scrutinee == Scrutinee::Str, by_value, ctx)
+            }
+            Pattern::Sequence(patterns) => self.lower_sequence(patterns, ty, scrutinee, top, ctx),
+            Pattern::Star(_) => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Star patterns are only allowed inside sequence patterns".to_string(),
+            )),
+            Pattern::Mapping {
+                keys,
+                patterns,
+                rest,
+            } => {
+                if rest.is_some() {
+                    transpile_bail!(ErrorKind::UnsupportedFeature(
+                        "'**rest' in mapping patterns is not supported".to_string(),
+                    ));
+                }
+                let mapping = self.temp("mapping");
+                for (key, pattern) in keys.iter().zip(patterns) {
+                    let key: syn::Expr = match key {
+                        HirExpr::Literal(Literal::String(s)) => parse_quote! { #s },
+                        other => {
+                            let key = other.to_rust_expr(ctx)?;
+                            parse_quote! { &#key }
+                        }
+                    };
+                    match pattern {
+                        Pattern::Wildcard => {
+                            self.conditions.push(quote! { #mapping.contains_key(#key) });
+                        }
+                        Pattern::Capture {
+                            name,
+                            pattern: None,
+                        } => {
+                            self.conditions.push(quote! { #mapping.contains_key(#key) });
+                            let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                            self.lookups
+                                .push(quote! { let #ident = #mapping[#key].clone(); });
+                            self.capture(name, CaptureKind::Lookup);
+                        }
+                        Pattern::Value(value) => {
+                            let value = value.to_rust_expr(ctx)?;
+                            self.conditions
+                                .push(quote! { #mapping.get(#key).is_some_and(|v| *v == #value) });
+                        }
+                        _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                            "Only captures and values are supported inside mapping patterns"
+                                .to_string(),
+                        )),
+                    }
+                }
+                Ok(quote! { #mapping })
+            }
+            Pattern::Class {
+                class,
+                patterns,
+                keywords,
+            } => self.lower_class(class, patterns, keywords, ty, scrutinee, top, ctx),
+            Pattern::Or(alternatives) => {
+                let (conditions, lookups) = (self.conditions.len(), self.lookups.len());
+                let alternatives = alternatives
+                    .iter()
+                    .map(|p| self.lower(p, ty, scrutinee, top, ctx))
+                    .collect::<Result<Vec<_>>>()?;
+                if self.conditions.len() != conditions || self.lookups.len() != lookups {
+                    transpile_bail!(ErrorKind::UnsupportedFeature(
+                        "Or-patterns may only combine literals, captures and class or sequence patterns"
+                            .to_string()
+                    ));
+                }
+                Ok(quote! { #(#alternatives)|* })
+            }
+        }
+    }
+
+    /// Literals and enum members are Rust patterns; other values are compared in the guard
+    fn lower_value(
+        &mut self,
+        expr: &HirExpr,
+        as_str: bool,
+        by_value: bool,
+        ctx: &mut CodeGenContext,
+    ) -> Result<proc_macro2::TokenStream> {
+        match expr {
+            HirExpr::Literal(Literal::Int(n)) => {
+                let lit = proc_macro2::Literal::i64_unsuffixed(*n);
+                return Ok(quote! { #lit });
+            }
+            HirExpr::Unary {
+                op: UnaryOp::Neg,
+                operand,
+            } if matches!(operand.as_ref(), HirExpr::Literal(Literal::Int(_))) => {
+                if let HirExpr::Literal(Literal::Int(n)) = operand.as_ref() {
+                    let lit = proc_macro2::Literal::i64_unsuffixed(-n);
+                    return Ok(quote! { #lit });
+                }
+            }
+            HirExpr::Literal(Literal::Bool(b)) => return Ok(quote! { #b }),
+            HirExpr::Literal(Literal::None) => return Ok(quote! { None }),
+            HirExpr::Literal(Literal::String(s)) if as_str => return Ok(quote! { #s }),
+            HirExpr::Attribute { value, attr } => {
+                if let HirExpr::Var(class) = value.as_ref() {
+                    if ctx.enums.contains_key(class) {
+                        let class = syn::Ident::new(class, proc_macro2::Span::call_site());
+                        let member = syn::Ident::new(attr, proc_macro2::Span::call_site());
+                        return Ok(quote! { #class::#member });
+                    }
+                }
+            }
+            _ => {}
+        }
+        let temp = self.temp("value");
+        let value = expr.to_rust_expr(ctx)?;
+        self.conditions.push(if by_value {
+            quote! { #temp == #value }
+        } else {
+            quote! { *#temp == #value }
+        });
+        Ok(quote! { #temp })
+    }
+
+    fn lower_sequence(
+        &mut self,
+        patterns: &[Pattern],
+        ty: Option<&Type>,
+        scrutinee: Scrutinee,
+        top: bool,
+        ctx: &mut CodeGenContext,
+    ) -> Result<proc_macro2::TokenStream> {
+        if let Some(Type::Tuple(types)) = ty {
+            if patterns.len() != types.len()
+                || patterns.iter().any(|p| matches!(p, Pattern::Star(_)))
+            {
+                transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                    "Sequence pattern does not fit the {}-tuple subject",
+                    types.len()
+                )));
+            }
+            let elements = patterns
+                .iter()
+                .zip(types)
+                .map(|(p, t)| self.lower(p, Some(t), scrutinee, false, ctx))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(quote! { (#(#elements),*) });
+        }
+        if !top {
+            transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Nested sequence patterns are only supported for tuples".to_string(),
+            ));
+        }
+        let element_type = match ty {
+            Some(Type::List(inner)) => Some(inner.as_ref()),
+            _ => None,
+        };
+        let elements = patterns
+            .iter()
+            .map(|p| match p {
+                Pattern::Star(None) => Ok(quote! { .. }),
+                Pattern::Star(Some(name)) => {
+                    self.capture(name, CaptureKind::Rest);
+                    let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                    Ok(quote! { #ident @ .. })
+                }
+                p => self.lower(p, element_type, scrutinee, false, ctx),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(quote! { [#(#elements),*] })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn lower_class(
+        &mut self,
+        class: &str,
+        patterns: &[Pattern],
+        keywords: &[(Symbol, Pattern)],
+        ty: Option<&Type>,
+        scrutinee: Scrutinee,
+        top: bool,
+        ctx: &mut CodeGenContext,
+    ) -> Result<proc_macro2::TokenStream> {
+        // `int(x)` and friends only narrow the type, which is already known statically
+        let builtin = match class {
+            "int" => Some(Type::Int),
+            "float" => Some(Type::Float),
+            "str" => Some(Type::String),
+            "bool" => Some(Type::Bool),
+            _ => None,
+        };
+        if let Some(builtin) = builtin {
+            if ty != Some(&builtin) {
+                transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                    "Class pattern {}() needs a subject declared as {}",
+                    class, class
+                )));
+            }
+            return match (patterns, keywords) {
+                ([], []) => Ok(quote! { _ }),
+                ([pattern], []) => self.lower(pattern, ty, scrutinee, top, ctx),
+                _ => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                    "Class pattern {}() takes at most one positional pattern",
+                    class
+                ))),
+            };
+        }
+        let Some(definition) = ctx.class_defs.get(class).cloned() else {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Class pattern on unknown class '{}'",
+                class
+            )));
+        };
+        if definition.is_enum() {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Match enum members with `case {}.MEMBER:` instead of a class pattern",
+                class
+            )));
+        }
+        let fields: Vec<&HirField> = definition
+            .fields
+            .iter()
+            .filter(|f| !f.is_class_var)
+            .collect();
+        if !patterns.is_empty() && !definition.is_dataclass {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Positional patterns for '{}' need it to be a dataclass",
+                class
+            )));
+        }
+        if patterns.len() > fields.len() {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "'{}' accepts at most {} positional patterns",
+                class,
+                fields.len()
+            )));
+        }
+        let mut field_patterns = Vec::new();
+        let positional = fields.iter().map(|f| f.name.clone()).zip(patterns);
+        let keyword = keywords.iter().map(|(name, p)| (name.clone(), p));
+        for (name, pattern) in positional.chain(keyword) {
+            let Some(field) = fields.iter().find(|f| f.name == name) else {
+                transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                    "'{}' has no field '{}'",
+                    class, name
+                )));
+            };
+            let ident = syn::Ident::new(&name, proc_macro2::Span::call_site());
+            let shorthand =
+                matches!(pattern, Pattern::Capture { name: n, pattern: None } if *n == name);
+            let pattern = self.lower(pattern, Some(&field.field_type), scrutinee, false, ctx)?;
+            field_patterns.push(if shorthand {
+                quote! { #ident }
+            } else {
+                quote! { #ident: #pattern }
+            });
+        }
+        let class = syn::Ident::new(class, proc_macro2::Span::call_site());
+        Ok(quote! { #class { #(#field_patterns,)* .. } })
+    }
+}
+
+/// Lower `match` to a Rust `match` over the subject.
+///
+/// Captures bind by reference and are cloned at the start of the arm, so the
+/// body sees owned values as in Python. Subjects of a known enum type must be
+/// matched exhaustively; other matches fall through an empty `_` arm.
+fn generate_match(
+    subject: &HirExpr,
+    cases: &[MatchCase],
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let subject_type = declared_type(subject, ctx);
+    let top_patterns: Vec<&Pattern> = cases
+        .iter()
+        .flat_map(|c| top_level_patterns(&c.pattern))
+        .collect();
+    let scrutinee = match &subject_type {
+        Some(Type::Int | Type::Float | Type::Bool) => Scrutinee::Value,
+        Some(Type::String) => Scrutinee::Str,
+        Some(Type::List(_)) => Scrutinee::Slice,
+        Some(_) => Scrutinee::Ref,
+        None if top_patterns
+            .iter()
+            .any(|p| matches!(p, Pattern::Sequence(_))) =>
+        {
+            Scrutinee::Slice
+        }
+        None if top_patterns
+            .iter()
+            .any(|p| matches!(p, Pattern::Value(HirExpr::Literal(Literal::String(_))))) =>
+        {
+            Scrutinee::Str
+        }
+        None => Scrutinee::Ref,
+    };
+
+    let bodies: Vec<&[Spanned<HirStmt>]> = cases.iter().map(|c| c.body.as_slice()).collect();
+    let kind = if cases
+        .iter()
+        .any(|c| c.guard.is_none() && c.pattern.is_irrefutable())
+    {
+        Bodies::Exhaustive
+    } else {
+        Bodies::Partial
+    };
+    let hoisted = hoist_bindings(&bodies, kind, ctx);
+
+    // Only matched, so elements are read rather than borrowed mutably
+    let place = match subject {
+        HirExpr::Index { .. } => subject.to_rust_expr(ctx)?,
+        _ => place_expr(subject, ctx)?,
+    };
+    let scrutinee_expr = match scrutinee {
+        Scrutinee::Value => quote! { #place },
+        Scrutinee::Str => quote! { #place.as_str() },
+        Scrutinee::Slice => quote! { #place.as_slice() },
+        Scrutinee::Ref => quote! { &#place },
+    };
+
+    let catch_all = cases
+        .iter()
+        .any(|c| c.guard.is_none() && c.pattern.is_irrefutable());
+    let enum_subject = match &subject_type {
+        Some(Type::Custom(name)) if ctx.enums.contains_key(name) => Some(name),
+        _ => None,
+    };
+    if let (false, Some(name)) = (catch_all, enum_subject) {
+        let missing = missing_enum_members(name, cases, ctx);
+        if !missing.is_empty() {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Non-exhaustive match on enum '{}': {} not covered; add `case _:` to ignore them",
+                name,
+                missing.join(", ")
+            )));
+        }
+    }
+    // The arms of a match covering every subject end in tail position when
+    // it does
+    let exhaustive = catch_all || enum_subject.is_some();
+    if let Some(frame) = ctx.live.frames.last_mut() {
+        frame.branches = exhaustive;
+    }
+
+    let mut arms = Vec::new();
+    for case in cases {
+        arms.push(generate_match_arm(
+            case,
+            subject_type.as_ref(),
+            scrutinee,
+            ctx,
+        )?);
+    }
+    // Python falls through unmatched subjects; Rust may already see the arms as exhaustive
+    if !exhaustive {
+        arms.push(quote! {
+            #[allow(unreachable_patterns)]
+            _ => {}
+        });
+    }
+
+    let tokens = quote! {
+        match #scrutinee_expr {
+            #(#arms)*
+        }
+    };
+    Ok(declare_hoisted(hoisted, tokens))
+}
+
+fn generate_match_arm(
+    case: &MatchCase,
+    subject_type: Option<&Type>,
+    scrutinee: Scrutinee,
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let mut lowering = PatternLowering::default();
+    let pattern = lowering.lower(&case.pattern, subject_type, scrutinee, true, ctx)?;
+
+    ctx.enter_scope();
+    for (name, _) in &lowering.captures {
+        ctx.declare_var(name);
+    }
+    let mut conditions = lowering.conditions.clone();
+    if let Some(guard) = &case.guard {
+        // Mapping entries are only looked up once the arm is taken
+        let looked_up = lowering
+            .captures
+            .iter()
+            .filter(|(_, kind)| *kind == CaptureKind::Lookup)
+            .find(|(name, _)| {
+                exceptions::body_uses_exception(&[HirStmt::Expr(guard.clone()).into()], name)
+            });
+        if let Some((name, _)) = looked_up {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Guard uses '{}', captured from a mapping pattern",
+                name
+            )));
+        }
+        // References captured by the pattern are dereferenced in the guard
+        let derefs: Vec<String> = lowering
+            .captures
+            .iter()
+            .filter(|(_, kind)| matches!(kind, CaptureKind::Ref | CaptureKind::CopyRef))
+            .map(|(name, _)| name.clone())
+            .filter(|name| ctx.borrowed_vars.insert(name.clone()))
+            .collect();
+        let guard = guard.to_rust_expr(ctx);
+        for name in &derefs {
+            ctx.borrowed_vars.remove(name);
+        }
+        let guard = guard?;
+        conditions.push(quote! { #guard });
+    }
+    let guard = (!conditions.is_empty()).then(|| quote! { if #(#conditions)&&* });
+
+    let used = lowering
+        .captures
+        .iter()
+        .filter(|(name, _)| exceptions::body_uses_exception(&case.body, name));
+    let rebinds = used.filter_map(|(name, kind)| {
+        let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+        match kind {
+            CaptureKind::Owned | CaptureKind::Lookup => None,
+            CaptureKind::CopyRef => Some(quote! { let #ident = *#ident; }),
+            CaptureKind::Ref => Some(quote! { let #ident = #ident.clone(); }),
+            CaptureKind::Str => Some(quote! { let #ident = #ident.to_string(); }),
+            CaptureKind::Rest => Some(quote! { let #ident = #ident.to_vec(); }),
+        }
+    });
+    let rebinds: Vec<_> = rebinds.collect();
+    let lookups = &lowering.lookups;
+    let body = generate_body(&case.body, ctx);
+    ctx.exit_scope();
+    let body = body?;
+
+    Ok(quote! {
+        #pattern #guard => {
+            #(#lookups)*
+            #(#rebinds)*
+            #(#body)*
+        }
+    })
+}
+
+/// The alternatives a pattern matches at the top level
+fn top_level_patterns(pattern: &Pattern) -> Vec<&Pattern> {
+    match pattern {
+        Pattern::Or(alternatives) => alternatives.iter().flat_map(top_level_patterns).collect(),
+        Pattern::Capture {
+            pattern: Some(inner),
+            ..
+        } => top_level_patterns(inner),
+        other => vec![other],
+    }
+}
+
+/// Members of `enum_name` that no unguarded case matches
+fn missing_enum_members(enum_name: &str, cases: &[MatchCase], ctx: &CodeGenContext) -> Vec<String> {
+    let covered: HashSet<&str> = cases
+        .iter()
+        .filter(|c| c.guard.is_none())
+        .flat_map(|c| top_level_patterns(&c.pattern))
+        .filter_map(|p| match p {
+            Pattern::Value(HirExpr::Attribute { value, attr }) => {
+                matches!(value.as_ref(), HirExpr::Var(v) if v == enum_name).then_some(attr.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    ctx.enums[enum_name]
+        .iter()
+        .filter(|member| !covered.contains(member.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Lower `with` to a block binding each resource, which is dropped, running
+/// any cleanup, however the block is left
+fn generate_with(
+    items: &[WithItem],
+    body: &[Spanned<HirStmt>],
+    ctx: &mut CodeGenContext,
+) -> Result<proc_macro2::TokenStream> {
+    let hoisted = hoist_bindings(&[body], Bodies::Exhaustive, ctx);
+
+    ctx.enter_scope();
+    let outer_files = ctx.file_vars.clone();
+    let outer_guards = ctx.guard_vars.clone();
+    let mut bindings = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let guard = match i {
+            0 => "_guard".to_string(),
+            i => format!("_guard_{i}"),
+        };
+        bindings.extend(bind_context_manager(item, &guard, ctx)?);
+    }
+    let body_stmts = generate_body(body, ctx);
+    ctx.file_vars = outer_files;
+    ctx.guard_vars = outer_guards;
+    ctx.exit_scope();
+    let body_stmts = body_stmts?;
+
+    Ok(quote! {
+        #(#hoisted)*
+        {
+            #(#bindings)*
+            #(#body_stmts)*
+        }
+    })
+}
+
+/// Statements binding one `with` item until the end of the block
+fn bind_context_manager(
+    item: &WithItem,
+    guard: &str,
+    ctx: &mut CodeGenContext,
+) -> Result<Vec<proc_macro2::TokenStream>> {
+    let manager = context_managers::classify(&item.context_expr, &ctx.guarded_classes, |expr| {
+        declared_type(expr, ctx)
+    })?;
+    let name = item.optional_vars.as_deref().unwrap_or(guard);
+    let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+    let guard_ident = syn::Ident::new(guard, proc_macro2::Span::call_site());
+    ctx.declare_var(name);
+    if manager.is_file() {
+        ctx.file_vars.insert(name.to_string());
+    }
+
+    Ok(match manager {
+        ContextManager::File { path, mode } => {
+            let path = path.to_rust_expr(ctx)?;
+            let open = match mode {
+                FileMode::Read => quote! { std::fs::File::open(&#path) },
+                FileMode::Write => quote! { std::fs::File::create(&#path) },
+                FileMode::Append => quote! {
+                    std::fs::OpenOptions::new().append(true).create(true).open(&#path)
+                },
+            };
+            vec![quote! { let mut #ident = #open.expect("failed to open file"); }]
+        }
+        ContextManager::Lock if context_managers::is_lock_constructor(&item.context_expr) => {
+            // The guard borrows the new lock, which has to outlive it
+            let lock = item.context_expr.to_rust_expr(ctx)?;
+            let lock_ident =
+                syn::Ident::new(&format!("{guard}_lock"), proc_macro2::Span::call_site());
+            vec![
+                quote! { let #lock_ident = #lock; },
+                quote! { let #ident = #lock_ident.lock().unwrap(); },
+            ]
+        }
+        ContextManager::Lock => {
+            // Lock the variable or field itself rather than a clone of it
+            let lock = place_expr(&item.context_expr, ctx)?;
+            vec![quote! { let #ident = #lock.lock().unwrap(); }]
+        }
+        ContextManager::TempFile => vec![quote! {
+            let mut #ident = tempfile::tempfile().expect("failed to create temporary file");
+        }],
+        ContextManager::NamedTempFile => vec![quote! {
+            let mut #ident = tempfile::NamedTempFile::new().expect("failed to create temporary file");
+        }],
+        ContextManager::TempDir => {
+            let dir = quote! {
+                let #guard_ident = tempfile::tempdir().expect("failed to create temporary directory");
+            };
+            match item.optional_vars {
+                // Python binds the directory's path; the guard keeps it alive
+                Some(_) => {
+                    ctx.declare_var(guard);
+                    vec![
+                        dir,
+                        quote! { let #ident = #guard_ident.path().display().to_string(); },
+                    ]
+                }
+                None => vec![dir],
+            }
+        }
+        ContextManager::Class(class) => {
+            let value = item.context_expr.to_rust_expr(ctx)?;
+            ctx.guard_vars.insert(name.to_string(), class.to_string());
+            let guard_type = guard_struct_ident(class);
+            if !ctx.guarded_classes[class].calls_enter {
+                return Ok(vec![quote! { let mut #ident = #guard_type(#value); }]);
+            }
+            ctx.declare_var(guard);
+            let enter_call = HirExpr::MethodCall {
+                receiver: Box::new(HirExpr::Var(class.to_string())),
+                method: "__enter__".to_string(),
+                args: vec![],
+                kwargs: vec![],
+            };
+            let enter =
+                propagate_call_error(&enter_call, parse_quote! { #guard_ident.__enter__() }, ctx)?;
+            let bind_enter = match item.optional_vars {
+                Some(_) => quote! { let #ident = #enter; },
+                None => quote! { #enter; },
+            };
+            vec![
+                quote! { let mut #guard_ident = #guard_type(#value); },
+                bind_enter,
+            ]
+        }
+        ContextManager::Value => {
+            let value = item.context_expr.to_rust_expr(ctx)?;
+            match item.optional_vars {
+                Some(_) => vec![quote! { let mut #ident = #value; }],
+                None => vec![quote! { let #ident = #value; }],
+            }
+        }
+    })
+}
+
+/// Propagate the error of a call to a function returning `Result`
+fn propagate_call_error(
+    call_expr: &HirExpr,
+    call: syn::Expr,
+    ctx: &mut CodeGenContext,
+) -> Result<syn::Expr> {
+    let fallible = exceptions::call_key(call_expr, ctx.current_class.as_deref())
+        .is_some_and(|key| ctx.fallible_calls.contains(&key));
+    if !fallible {
+        return Ok(call);
+    }
+    if ctx.error_scope.returns_result && !ctx.error_scope.inside_try() {
+        return Ok(parse_quote! { #call? });
+    }
+    let exit = exit_with_error(parse_quote! { error }, ctx)?;
+    Ok(parse_quote! {
+        match #call {
+            Ok(value) => value,
+            Err(error) => { #exit }
+        }
+    })
+}
+
+/// A call to a function returning a borrowed `&str` yields a `String`, like
+/// any other string expression
+fn lent_to_owned(call: syn::Expr, borrows: &FunctionBorrows) -> syn::Expr {
+    if borrows.returned.is_empty() {
+        call
+    } else {
+        parse_quote! { #call.to_string() }
+    }
+}
+
+/// Extension trait for converting expressions to Rust
+trait ToRustExpr {
+    fn to_rust_expr(&self, ctx: &mut CodeGenContext) -> Result<syn::Expr>;
+}
+
+/// Expression converter to reduce complexity
+struct ExpressionConverter<'a, 'b> {
+    ctx: &'a mut CodeGenContext<'b>,
+}
+
+impl<'a, 'b> ExpressionConverter<'a, 'b> {
+    fn new(ctx: &'a mut CodeGenContext<'b>) -> Self {
+        Self { ctx }
+    }
+
+    fn convert_variable(&self, name: &str) -> Result<syn::Expr> {
+        let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+        if self.ctx.borrowed_vars.contains(name) {
+            return Ok(parse_quote! { (*#ident) });
+        }
+        Ok(parse_quote! { #ident })
+    }
+
+    /// `mode == "r"` of a `Literal`-typed `mode` compares with the variant
+    fn convert_literal_comparison(
+        &mut self,
+        op: BinOp,
+        left: &HirExpr,
+        right: &HirExpr,
+    ) -> Result<Option<syn::Expr>> {
+        if !matches!(op, BinOp::Eq | BinOp::NotEq) {
+            return Ok(None);
+        }
+        let (value, string) = match (left, right) {
+            (value, HirExpr::Literal(Literal::String(s)))
+            | (HirExpr::Literal(Literal::String(s)), value) => (value, s),
+            _ => return Ok(None),
+        };
+        // A field of another instance of one of the module's classes
+        let ty = match value {
+            HirExpr::Attribute {
+                value: object,
+                attr,
+            } if !is_self(object) => match declared_type(object, self.ctx) {
+                Some(Type::Custom(class)) => self.ctx.class_defs.get(&class).and_then(|c| {
+                    c.fields
+                        .iter()
+                        .find(|f| &f.name == attr)
+                        .map(|f| f.field_type.clone())
+                }),
+                _ => None,
+            },
+            _ => declared_type(value, self.ctx),
+        };
+        let Some(ty @ Type::Literal(_)) = ty else {
+            return Ok(None);
+        };
+        let variant = type_enums::literal_variant(&ty, string)?;
+        let value = value.to_rust_expr(self.ctx)?;
+        Ok(Some(match op {
+            BinOp::Eq => parse_quote! { #value == #variant },
+            _ => parse_quote! { #value != #variant },
+        }))
+    }
+
+    fn convert_binary(&mut self, op: BinOp, left: &HirExpr, right: &HirExpr) -> Result<syn::Expr> {
+        if matches!(op, BinOp::In | BinOp::NotIn) {
+            if let Some(field) = self.kwargs_struct_field(right, left) {
+                return Ok(match op {
+                    BinOp::In => parse_quote! { #field.is_some() },
+                    _ => parse_quote! { #field.is_none() },
+                });
+            }
+        }
+        if let Some(expr) = self.convert_literal_comparison(op, left, right)? {
+            return Ok(expr);
+        }
+        if let Some(expr) = self.convert_set_binary(op, left, right)? {
+            return Ok(expr);
+        }
+        if let Some(expr) = self.convert_big_binary(op, left, right)? {
+            return Ok(expr);
+        }
+        if matches!(op, BinOp::In | BinOp::NotIn) {
+            return self.convert_membership(op, left, right);
+        }
+        if op == BinOp::Add && (string_typed(left, self.ctx) || string_typed(right, self.ctx)) {
+            // `String + &str`, the right operand lent
+            let mut left_expr = left.to_rust_expr(self.ctx)?;
+            if let (HirExpr::Var(name), syn::Expr::Path(_)) = (left, &left_expr) {
+                // A string the right operand reads again cannot be moved
+                let mut reread = false;
+                visit_expr(right, &mut |e| {
+                    reread |= matches!(e, HirExpr::Var(v) if v == name)
+                });
+                if reread {
+                    left_expr = parse_quote! { #left_expr.clone() };
+                }
+            }
+            let right_expr = dict_key(right, self.ctx)?;
+            return Ok(parse_quote! { (#left_expr + #right_expr) });
+        }
+        let left_expr = left.to_rust_expr(self.ctx)?;
+        let right_expr = right.to_rust_expr(self.ctx)?;
+
+        match op {
+            BinOp::Add
+            | BinOp::Sub
+            | BinOp::Mul
+            | BinOp::Div
+            | BinOp::FloorDiv
+            | BinOp::Mod
+            | BinOp::Pow => {
+                if matches!(op, BinOp::Div | BinOp::FloorDiv | BinOp::Mod)
+                    && self.ctx.error_scope.raises("ZeroDivisionError")
+                    && !arithmetic::is_nonzero_literal(right)
+                {
+                    return self.convert_raising_division(op, left, right, left_expr, right_expr);
+                }
+                self.convert_arithmetic(op, left, right, left_expr, right_expr)
+            }
+            _ => {
+                let rust_op = convert_binop(op)?;
+                Ok(parse_quote! { (#left_expr #rust_op #right_expr) })
+            }
+        }
+    }
+
+    /// A division inside a `try` catching `ZeroDivisionError`, raising it
+    /// for a zero divisor
+    fn convert_raising_division(
+        &mut self,
+        op: BinOp,
+        left: &HirExpr,
+        right: &HirExpr,
+        left_expr: syn::Expr,
+        right_expr: syn::Expr,
+    ) -> Result<syn::Expr> {
+        let zero: syn::Expr = match self.numeric_type(right) {
+            Some(Type::Float) => parse_quote! { 0.0 },
+            _ => parse_quote! { 0 },
+        };
+        let message = match op {
+            BinOp::Div => "division by zero",
+            _ => "integer division or modulo by zero",
+        };
+        let quotient =
+            self.convert_arithmetic(op, left, right, left_expr, parse_quote! { __divisor })?;
+        let found = parse_quote! { (__divisor != #zero).then(|| #quotient) };
+        let quotient = unwrap_or_raise(found, "ZeroDivisionError", message, self.ctx)?;
+        Ok(parse_quote! {
+            {
+                let __divisor = #right_expr;
+                #quotient
+            }
+        })
+    }
+
+    /// `+`, `-`, `*`, `/`, `//`, `%` or `**` of numbers
+    fn convert_arithmetic(
+        &mut self,
+        op: BinOp,
+        left: &HirExpr,
+        right: &HirExpr,
+        left_expr: syn::Expr,
+        right_expr: syn::Expr,
+    ) -> Result<syn::Expr> {
+        let types = (self.numeric_type(left), self.numeric_type(right));
+        match types {
+            (Some(Type::Int), Some(Type::Int)) => {
+                self.convert_int_arithmetic(op, left, right, left_expr, right_expr)
+            }
+            (Some(left_type), Some(right_type)) => self.convert_float_arithmetic(
+                op,
+                (left, left_type, left_expr),
+                (right, right_type, right_expr),
+            ),
+            _ if op == BinOp::Pow => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Power of operands not known to be numbers".to_string(),
+            )),
+            // Rust's `/` and `%` truncate, so operands of unknown type
+            // are floats next to a float, and ints otherwise
+            (Some(Type::Float), None) | (None, Some(Type::Float))
+                if matches!(op, BinOp::FloorDiv | BinOp::Mod) =>
+            {
+                self.convert_float_arithmetic(
+                    op,
+                    (left, Type::Float, left_expr),
+                    (right, Type::Float, right_expr),
+                )
+            }
+            (Some(Type::Int) | None, Some(Type::Int) | None)
+                if matches!(op, BinOp::FloorDiv | BinOp::Mod) =>
+            {
+                self.convert_int_arithmetic(op, left, right, left_expr, right_expr)
+            }
+            _ => {
+                let rust_op = convert_binop(op)?;
+                Ok(parse_quote! { (#left_expr #rust_op #right_expr) })
+            }
+        }
+    }
 
-                if ctx.is_declared(target) {
-                    // Variable already exists, just assign
-                    Ok(quote! { #target_ident = #value_expr; })
+    /// Arithmetic and comparisons with a `BigInt` operand, `None` without one
+    fn convert_big_binary(
+        &mut self,
+        op: BinOp,
+        left: &HirExpr,
+        right: &HirExpr,
+    ) -> Result<Option<syn::Expr>> {
+        if !self.ctx.ints.big {
+            return Ok(None);
+        }
+        let (left_big, right_big) = (big_int(left, self.ctx), big_int(right, self.ctx));
+        match op {
+            BinOp::Add
+            | BinOp::Sub
+            | BinOp::Mul
+            | BinOp::Div
+            | BinOp::FloorDiv
+            | BinOp::Mod
+            | BinOp::Pow => {
+                let expr = HirExpr::Binary {
+                    op,
+                    left: Box::new(left.clone()),
+                    right: Box::new(right.clone()),
+                };
+                let float = |e: &HirExpr, ctx: &CodeGenContext| {
+                    declared_type(e, ctx)
+                        .unwrap_or_else(|| generators::infer_type(e, &ctx.var_types))
+                        == Type::Float
+                };
+                let to_float = op == BinOp::Div || float(left, self.ctx) || float(right, self.ctx);
+                if big_int(&expr, self.ctx) || ((left_big || right_big) && !to_float) {
+                    return self.convert_big_arithmetic(op, left, right).map(Some);
+                }
+                if !(left_big || right_big) {
+                    return Ok(None);
+                }
+                // `/`, or arithmetic with a float: the `BigInt` as a float
+                let left_expr = self.big_to_float(left)?;
+                let right_expr = self.big_to_float(right)?;
+                self.convert_float_arithmetic(
+                    op,
+                    (left, Type::Float, left_expr),
+                    (right, Type::Float, right_expr),
+                )
+                .map(Some)
+            }
+            BinOp::Eq | BinOp::NotEq | BinOp::Lt | BinOp::LtEq | BinOp::Gt | BinOp::GtEq
+                if left_big || right_big =>
+            {
+                let left_expr = self.big_comparand(left, left_big)?;
+                let right_expr = self.big_comparand(right, right_big)?;
+                let rust_op = convert_binop(op)?;
+                Ok(Some(parse_quote! { (#left_expr #rust_op #right_expr) }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Int arithmetic computed on `BigInt`s, which never overflows
+    fn convert_big_arithmetic(
+        &mut self,
+        op: BinOp,
+        left: &HirExpr,
+        right: &HirExpr,
+    ) -> Result<syn::Expr> {
+        self.ctx.needs_bigint = true;
+        match op {
+            BinOp::FloorDiv | BinOp::Mod => {
+                let helper = if op == BinOp::FloorDiv {
+                    Helper::BigFloorDiv
                 } else {
-                    // First declaration, use let mut
-                    ctx.declare_var(target);
-                    Ok(quote! { let mut #target_ident = #value_expr; })
+                    Helper::BigFloorMod
+                };
+                self.ctx.arithmetic_helpers.insert(helper);
+                let name = helper.name();
+                let left_expr = self.big_reference(left)?;
+                let right_expr = self.big_reference(right)?;
+                Ok(parse_quote! { #name(#left_expr, #right_expr) })
+            }
+            BinOp::Pow => {
+                let exponent: syn::Expr = match indexing::int_literal(right) {
+                    Some(n) => match u32::try_from(n) {
+                        Ok(n) => parse_quote! { #n },
+                        Err(_) => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                            "Power of an int with exponent {n}"
+                        ))),
+                    },
+                    None => {
+                        let exponent = if big_int(right, self.ctx) {
+                            self.big_reference(right)?
+                        } else {
+                            unparen(right.to_rust_expr(self.ctx)?)
+                        };
+                        parse_quote! { u32::try_from(#exponent).expect("exponent too large") }
+                    }
+                };
+                let base = self.big_receiver(left)?;
+                Ok(parse_quote! { #base.pow(#exponent) })
+            }
+            _ => {
+                let left_expr = self.big_operand(left)?;
+                let right_expr = self.big_operand(right)?;
+                let rust_op = convert_binop(op)?;
+                Ok(parse_quote! { (#left_expr #rust_op #right_expr) })
+            }
+        }
+    }
+
+    /// An operand of a `BigInt` operator: a local borrowed, so it can be
+    /// read again, and an int that fits an `i64` converted
+    fn big_operand(&mut self, expr: &HirExpr) -> Result<syn::Expr> {
+        let value = expr.to_rust_expr(self.ctx)?;
+        Ok(match expr {
+            _ if !big_int(expr, self.ctx) => bigint::from_small(expr, &value),
+            HirExpr::Var(_) => parse_quote! { &#value },
+            _ => value,
+        })
+    }
+
+    /// A `&BigInt` argument
+    fn big_reference(&mut self, expr: &HirExpr) -> Result<syn::Expr> {
+        let value = expr.to_rust_expr(self.ctx)?;
+        Ok(match expr {
+            _ if !big_int(expr, self.ctx) => {
+                let value = bigint::from_small(expr, &value);
+                parse_quote! { &#value }
+            }
+            _ => {
+                let value = builtins::operand_expr(&value);
+                parse_quote! { &#value }
+            }
+        })
+    }
+
+    /// The receiver of a `BigInt` method
+    fn big_receiver(&mut self, expr: &HirExpr) -> Result<syn::Expr> {
+        let value = expr.to_rust_expr(self.ctx)?;
+        Ok(if big_int(expr, self.ctx) {
+            builtins::operand_expr(&value)
+        } else {
+            bigint::from_small(expr, &value)
+        })
+    }
+
+    /// A number as a float: a `BigInt` converted, failing as Python does
+    /// when it is too large, and an int that fits an `i64` cast
+    fn big_to_float(&mut self, expr: &HirExpr) -> Result<syn::Expr> {
+        if big_int(expr, self.ctx) {
+            let receiver = self.big_receiver(expr)?;
+            self.ctx.num_traits.insert("ToPrimitive");
+            return Ok(
+                parse_quote! { #receiver.to_f64().expect("int too large to convert to float") },
+            );
+        }
+        let value = expr.to_rust_expr(self.ctx)?;
+        Ok(if int_typed(expr, self.ctx) {
+            arithmetic::int_to_float(expr, &value)
+        } else {
+            value
+        })
+    }
+
+    /// A side of a comparison with a `BigInt`
+    fn big_comparand(&mut self, expr: &HirExpr, big: bool) -> Result<syn::Expr> {
+        let value = expr.to_rust_expr(self.ctx)?;
+        Ok(if !big && int_typed(expr, self.ctx) {
+            bigint::from_small(expr, &value)
+        } else {
+            value
+        })
+    }
+
+    /// `int` or `float` when `expr` is known to be one
+    fn numeric_type(&self, expr: &HirExpr) -> Option<Type> {
+        let ty = declared_type(expr, self.ctx)
+            .unwrap_or_else(|| generators::infer_type(expr, &self.ctx.var_types));
+        matches!(ty, Type::Int | Type::Float).then_some(ty)
+    }
+
+    /// The Rust type of Python ints
+    fn int_type(&self) -> Result<syn::Type> {
+        rust_type_to_syn(&self.ctx.fixed_int_type())
+    }
+
+    /// Arithmetic on two ints. `//` and `%` round toward negative infinity,
+    /// which Rust's operators do for operands that are not negative, and
+    /// `div_euclid` for positive divisors; `/` divides as floats.
+    fn convert_int_arithmetic(
+        &mut self,
+        op: BinOp,
+        left: &HirExpr,
+        right: &HirExpr,
+        left_expr: syn::Expr,
+        right_expr: syn::Expr,
+    ) -> Result<syn::Expr> {
+        let int = self.ctx.fixed_int_type().to_rust_string();
+        // Fixed-width ints next to `BigInt`s are proven not to overflow
+        let overflow = if self.ctx.ints.big {
+            Overflow::Native
+        } else {
+            self.ctx.error_scope.overflow
+        };
+        let non_negative = |e: &HirExpr, ctx: &CodeGenContext| {
+            indexing::int_literal(e).is_some_and(|n| n >= 0)
+                || int_sign(e, ctx) != Sign::Signed
+                || ctx
+                    .ints
+                    .interval(e)
+                    .is_some_and(bigint::Interval::is_non_negative)
+        };
+        let checked = |call: syn::Expr, ctx: &mut CodeGenContext| {
+            if overflow.is_checked() {
+                unwrap_overflow(call, ctx)
+            } else {
+                Ok(call)
+            }
+        };
+        match op {
+            BinOp::Div => {
+                let left = arithmetic::int_to_float(left, &left_expr);
+                let right = arithmetic::int_to_float(right, &right_expr);
+                Ok(parse_quote! { (#left / #right) })
+            }
+            BinOp::FloorDiv | BinOp::Mod => {
+                let (rust_op, euclid, helper) = if op == BinOp::FloorDiv {
+                    (quote! { / }, "div_euclid", Helper::FloorDiv)
+                } else {
+                    (quote! { % }, "rem_euclid", Helper::FloorMod)
+                };
+                if non_negative(left, self.ctx) && non_negative(right, self.ctx) {
+                    Ok(parse_quote! { (#left_expr #rust_op #right_expr) })
+                } else if indexing::int_literal(right).is_some_and(|n| n > 0)
+                    || self.ctx.ints.interval(right).is_some_and(|i| i.lo > 0)
+                {
+                    let receiver = arithmetic::int_receiver(left, &left_expr, &int);
+                    let method = syn::Ident::new(euclid, proc_macro2::Span::call_site());
+                    Ok(parse_quote! { #receiver.#method(#right_expr) })
+                } else {
+                    self.ctx.arithmetic_helpers.insert(helper);
+                    let name = helper.name();
+                    Ok(parse_quote! { #name(#left_expr, #right_expr) })
                 }
             }
-            HirStmt::Return(expr) => {
-                if let Some(e) = expr {
-                    let expr_tokens = e.to_rust_expr(ctx)?;
-                    Ok(quote! { return #expr_tokens; })
+            // A negative power of an int is a float
+            BinOp::Pow if indexing::int_literal(right).is_some_and(|n| n < 0) => {
+                let base = arithmetic::int_to_float(left, &left_expr);
+                let base = arithmetic::float_receiver(left, &base);
+                Ok(parse_quote! { #base.powi(#right_expr) })
+            }
+            BinOp::Pow => {
+                let receiver = arithmetic::int_receiver(left, &left_expr, &int);
+                let method = overflow.method(op).unwrap_or("pow");
+                let method = syn::Ident::new(method, proc_macro2::Span::call_site());
+                // As numpy does, where Python's power would be a float
+                let exponent: syn::Expr = match indexing::int_literal(right) {
+                    Some(_) => right_expr,
+                    None => parse_quote! {
+                        u32::try_from(#right_expr)
+                            .expect("Integers to negative integer powers are not allowed.")
+                    },
+                };
+                checked(parse_quote! { #receiver.#method(#exponent) }, self.ctx)
+            }
+            _ => match overflow.method(op) {
+                Some(method) => {
+                    let receiver = arithmetic::int_receiver(left, &left_expr, &int);
+                    let method = syn::Ident::new(method, proc_macro2::Span::call_site());
+                    checked(parse_quote! { #receiver.#method(#right_expr) }, self.ctx)
+                }
+                None => {
+                    let rust_op = convert_binop(op)?;
+                    Ok(parse_quote! { (#left_expr #rust_op #right_expr) })
+                }
+            },
+        }
+    }
+
+    /// Arithmetic on a float and an int or float, the int converted to a float
+    fn convert_float_arithmetic(
+        &mut self,
+        op: BinOp,
+        (left, left_type, left_expr): (&HirExpr, Type, syn::Expr),
+        (right, right_type, right_expr): (&HirExpr, Type, syn::Expr),
+    ) -> Result<syn::Expr> {
+        let as_float = |hir: &HirExpr, ty: &Type, expr: syn::Expr| match ty {
+            Type::Int => arithmetic::int_to_float(hir, &expr),
+            _ => expr,
+        };
+        let left_expr = as_float(left, &left_type, left_expr);
+        let receiver = arithmetic::float_receiver(left, &left_expr);
+        match op {
+            // `powi` takes an `i32`
+            BinOp::Pow if right_type == Type::Int => {
+                let exponent = match indexing::int_literal(right) {
+                    None if self.int_type()? != parse_quote! { i32 } => {
+                        let right = builtins::operand_expr(&right_expr);
+                        parse_quote! { #right as i32 }
+                    }
+                    _ => right_expr,
+                };
+                Ok(parse_quote! { #receiver.powi(#exponent) })
+            }
+            BinOp::Pow => Ok(parse_quote! { #receiver.powf(#right_expr) }),
+            BinOp::FloorDiv => {
+                let right_expr = as_float(right, &right_type, right_expr);
+                Ok(parse_quote! { (#left_expr / #right_expr).floor() })
+            }
+            BinOp::Mod => {
+                let positive = indexing::int_literal(right).is_some_and(|n| n > 0)
+                    || matches!(right, HirExpr::Literal(Literal::Float(f)) if *f > 0.0);
+                let right_expr = as_float(right, &right_type, right_expr);
+                if positive {
+                    Ok(parse_quote! { #receiver.rem_euclid(#right_expr) })
                 } else {
-                    Ok(quote! { return; })
+                    self.ctx.arithmetic_helpers.insert(Helper::FloatMod);
+                    let name = Helper::FloatMod.name();
+                    Ok(parse_quote! { #name(#left_expr, #right_expr) })
                 }
             }
-            HirStmt::If {
-                condition,
-                then_body,
-                else_body,
-            } => {
-                let cond = condition.to_rust_expr(ctx)?;
-                ctx.enter_scope();
-                let then_stmts: Vec<_> = then_body
-                    .iter()
-                    .map(|s| s.to_rust_tokens(ctx))
-                    .collect::<Result<Vec<_>>>()?;
-                ctx.exit_scope();
+            _ => {
+                let right_expr = as_float(right, &right_type, right_expr);
+                let rust_op = convert_binop(op)?;
+                Ok(parse_quote! { (#left_expr #rust_op #right_expr) })
+            }
+        }
+    }
 
-                if let Some(else_stmts) = else_body {
-                    ctx.enter_scope();
-                    let else_tokens: Vec<_> = else_stmts
+    fn convert_unary(&mut self, op: &UnaryOp, operand: &HirExpr) -> Result<syn::Expr> {
+        let operand_expr = operand.to_rust_expr(self.ctx)?;
+        match op {
+            // Builtins such as `isinstance` may lower to a constant
+            UnaryOp::Not => match &operand_expr {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Bool(value),
+                    ..
+                }) => {
+                    let negated = !value.value;
+                    Ok(parse_quote! { #negated })
+                }
+                _ => Ok(parse_quote! { !#operand_expr }),
+            },
+            UnaryOp::Neg if big_int(operand, self.ctx) => Ok(match operand {
+                HirExpr::Var(_) => parse_quote! { -&#operand_expr },
+                _ => {
+                    let operand_expr = builtins::operand_expr(&operand_expr);
+                    parse_quote! { -#operand_expr }
+                }
+            }),
+            UnaryOp::Neg
+                if !matches!(operand, HirExpr::Literal(_))
+                    && self.numeric_type(operand) == Some(Type::Int) =>
+            {
+                let operand_expr = builtins::operand_expr(&operand_expr);
+                match self.ctx.error_scope.overflow {
+                    _ if self.ctx.ints.big => Ok(parse_quote! { -#operand_expr }),
+                    Overflow::Native => Ok(parse_quote! { -#operand_expr }),
+                    Overflow::Wrapping => Ok(parse_quote! { #operand_expr.wrapping_neg() }),
+                    _ => unwrap_overflow(parse_quote! { #operand_expr.checked_neg() }, self.ctx),
+                }
+            }
+            UnaryOp::Neg => Ok(parse_quote! { -#operand_expr }),
+            UnaryOp::Pos => Ok(operand_expr), // No +x in Rust
+            UnaryOp::BitNot => Ok(parse_quote! { !#operand_expr }),
+        }
+    }
+
+    fn convert_call(
+        &mut self,
+        func: &str,
+        args: &[HirExpr],
+        kwargs: &[(Symbol, HirExpr)],
+    ) -> Result<syn::Expr> {
+        if self.ctx.kwargs_structs.contains_key(func) {
+            return self.convert_kwargs_struct(func, kwargs);
+        }
+        let builtin = self.is_builtin(func);
+        if builtin {
+            match func {
+                "sorted" => return self.convert_sorted_call(args, kwargs),
+                "set" | "frozenset" => return self.convert_set_call(func, args),
+                "print" => {
+                    let args = args
                         .iter()
-                        .map(|s| s.to_rust_tokens(ctx))
+                        .map(|arg| match arg {
+                            HirExpr::FString { parts, values } => Ok(
+                                builtins::PrintArg::Formatted(parts, self.builtin_args(values)?),
+                            ),
+                            _ => {
+                                let arg = self.builtin_args(std::slice::from_ref(arg))?;
+                                Ok(builtins::PrintArg::Value(arg.into_iter().next().unwrap()))
+                            }
+                        })
                         .collect::<Result<Vec<_>>>()?;
-                    ctx.exit_scope();
-                    Ok(quote! {
-                        if #cond {
-                            #(#then_stmts)*
-                        } else {
-                            #(#else_tokens)*
-                        }
-                    })
-                } else {
-                    Ok(quote! {
-                        if #cond {
-                            #(#then_stmts)*
+                    return builtins::lower_print(&args, kwargs, &mut self.ctx.string_helpers);
+                }
+                _ => {}
+            }
+        }
+        if !kwargs.is_empty() {
+            transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "Keyword arguments to '{}' are not supported; only functions and classes of the module accept them",
+                func
+            )));
+        }
+        // A local closure or nested function shadows the module's function
+        let borrows = match self.ctx.borrows.function(func) {
+            Some(borrows) if borrows.borrows_any() && !self.ctx.is_declared(func) => {
+                Some(borrows.clone())
+            }
+            _ => None,
+        };
+        let big_params = match self.ctx.big_int_functions.get(func) {
+            Some(signature) if !self.ctx.is_declared(func) => signature.params.clone(),
+            _ => Vec::new(),
+        };
+        let enum_params = match self.ctx.enum_params.get(func) {
+            Some(types) if !self.ctx.is_declared(func) => types.clone(),
+            _ => Vec::new(),
+        };
+        let args = list_arguments(args, &enum_params);
+        let args = args.as_slice();
+        if let Some(borrows) = borrows {
+            let arg_exprs = self.convert_arguments(args, &borrows, &big_params)?;
+            let arg_exprs = self.enum_arguments(args, arg_exprs, &enum_params)?;
+            let call = self.convert_generic_call(func, &arg_exprs)?;
+            return Ok(lent_to_owned(call, &borrows));
+        }
+        if builtin {
+            if let ("isinstance", [value, class]) = (func, args) {
+                let value = self.builtin_args(std::slice::from_ref(value))?;
+                return builtins::lower_isinstance(&value[0], class);
+            }
+            let mut builtin_args = self.builtin_args(args)?;
+            if let Some(call) = self.convert_raising_parse(func, &builtin_args)? {
+                return Ok(call);
+            }
+            if self.ctx.ints.big {
+                if let Some(call) = self.convert_big_builtin(func, &mut builtin_args)? {
+                    return Ok(call);
+                }
+                let type_mapper = self.ctx.type_mapper.clone().with_i64();
+                if let Some(call) = builtins::lower(func, &builtin_args, &type_mapper)? {
+                    return Ok(call);
+                }
+            } else if let Some(call) = builtins::lower(func, &builtin_args, self.ctx.type_mapper)? {
+                return Ok(call);
+            }
+        }
+        let arg_exprs = self.convert_arguments(args, &FunctionBorrows::default(), &big_params)?;
+        let arg_exprs = self.enum_arguments(args, arg_exprs, &enum_params)?;
+        self.convert_generic_call(func, &arg_exprs)
+    }
+
+    /// `int()` or `float()` of a string inside a `try` catching `ValueError`
+    fn convert_raising_parse(
+        &mut self,
+        func: &str,
+        args: &[builtins::Arg],
+    ) -> Result<Option<syn::Expr>> {
+        let [arg] = args else {
+            return Ok(None);
+        };
+        if arg.ty != Type::String || !self.ctx.error_scope.raises("ValueError") {
+            return Ok(None);
+        }
+        let (target, message): (syn::Type, &str) = match func {
+            "int" if self.ctx.ints.big => {
+                self.ctx.needs_bigint = true;
+                let bigint = bigint::bigint_ident();
+                (parse_quote! { #bigint }, "invalid literal for int()")
+            }
+            "int" => (self.int_type()?, "invalid literal for int()"),
+            "float" => (parse_quote! { f64 }, "could not convert string to float"),
+            _ => return Ok(None),
+        };
+        let expr = &arg.expr;
+        let parsed = parse_quote! { #expr.trim().parse::<#target>().ok() };
+        unwrap_or_raise(parsed, "ValueError", message, self.ctx).map(Some)
+    }
+
+    /// Builtins taking or returning `BigInt`s; `range` counts in `i64`s, so
+    /// its `BigInt` bounds are converted in place and lowered as usual
+    fn convert_big_builtin(
+        &mut self,
+        func: &str,
+        args: &mut [builtins::Arg],
+    ) -> Result<Option<syn::Expr>> {
+        let big: Vec<bool> = args.iter().map(|a| big_int(a.hir, self.ctx)).collect();
+        let bigint = bigint::bigint_ident();
+        match (func, &mut *args) {
+            ("range", args) => {
+                for (arg, _) in args.iter_mut().zip(&big).filter(|(_, big)| **big) {
+                    let bound = match arg.hir {
+                        HirExpr::Var(_) => {
+                            let expr = &arg.expr;
+                            parse_quote! { &#expr }
                         }
-                    })
+                        _ => unparen(arg.expr.clone()),
+                    };
+                    arg.expr = bigint::to_i64(&bound, "range() bound does not fit an i64");
                 }
+                Ok(None)
             }
-            HirStmt::While { condition, body } => {
-                let cond = condition.to_rust_expr(ctx)?;
-                ctx.enter_scope();
-                let body_stmts: Vec<_> = body
-                    .iter()
-                    .map(|s| s.to_rust_tokens(ctx))
-                    .collect::<Result<Vec<_>>>()?;
-                ctx.exit_scope();
-                Ok(quote! {
-                    while #cond {
-                        #(#body_stmts)*
+            ("int", [arg]) => {
+                self.ctx.needs_bigint = true;
+                let expr = &arg.expr;
+                Ok(Some(match arg.ty {
+                    Type::String => parse_quote! {
+                        #expr.trim().parse::<#bigint>().expect("invalid literal for int()")
+                    },
+                    Type::Float => {
+                        self.ctx.num_traits.insert("FromPrimitive");
+                        parse_quote! {
+                            #bigint::from_f64(#expr).expect("cannot convert float NaN or infinity to integer")
+                        }
                     }
-                })
+                    _ => big_value(arg.hir, true, self.ctx)?,
+                }))
             }
-            HirStmt::For { target, iter, body } => {
-                let target_ident = syn::Ident::new(target, proc_macro2::Span::call_site());
-                let iter_expr = iter.to_rust_expr(ctx)?;
-                ctx.enter_scope();
-                ctx.declare_var(target); // for loop variable is declared in the loop scope
-                let body_stmts: Vec<_> = body
+            ("float", [arg]) if big[0] => self.big_to_float(arg.hir).map(Some),
+            ("sum", [arg]) if holds_ints(&arg.ty) => {
+                self.ctx.needs_bigint = true;
+                let items = builtins::iterator(arg, false);
+                Ok(Some(parse_quote! { #items.sum::<#bigint>() }))
+            }
+            ("abs", [arg]) if big[0] => {
+                self.ctx.num_traits.insert("Signed");
+                let receiver = self.big_receiver(arg.hir)?;
+                Ok(Some(parse_quote! { #receiver.abs() }))
+            }
+            ("min" | "max", args) if args.len() >= 2 && big.contains(&true) => {
+                let method = syn::Ident::new(func, proc_macro2::Span::call_site());
+                let values = args
                     .iter()
-                    .map(|s| s.to_rust_tokens(ctx))
+                    .map(|arg| big_value(arg.hir, false, self.ctx))
                     .collect::<Result<Vec<_>>>()?;
-                ctx.exit_scope();
-                Ok(quote! {
-                    for #target_ident in #iter_expr {
-                        #(#body_stmts)*
-                    }
+                Ok(Some(match values.as_slice() {
+                    [a, b] => parse_quote! { std::cmp::#method(#a, #b) },
+                    _ => parse_quote! { [#(#values),*].into_iter().#method().unwrap() },
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether `func` is the builtin, not a function, class or local of the
+    /// module by the same name
+    fn is_builtin(&self, func: &str) -> bool {
+        builtins::is_builtin(func)
+            && self.ctx.borrows.function(func).is_none()
+            && !self.ctx.class_names.contains(func)
+            && !self.ctx.is_declared(func)
+    }
+
+    /// The arguments of a call to a builtin, with their types as far as
+    /// they are known
+    fn builtin_args<'e>(&mut self, args: &'e [HirExpr]) -> Result<Vec<builtins::Arg<'e>>> {
+        args.iter()
+            .map(|arg| {
+                let ty = declared_type(arg, self.ctx)
+                    .unwrap_or_else(|| generators::infer_type(arg, &self.ctx.var_types));
+                let movable = matches!(arg, HirExpr::Var(name) if self.ctx.can_move(name));
+                Ok(builtins::Arg {
+                    hir: arg,
+                    expr: arg.to_rust_expr(self.ctx)?,
+                    ty,
+                    movable,
                 })
+            })
+            .collect()
+    }
+
+    /// `set()` and `set(iterable)`, and the same of `frozenset`
+    fn convert_set_call(&mut self, func: &str, args: &[HirExpr]) -> Result<syn::Expr> {
+        let set_type = self.ctx.set_type;
+        self.ctx.use_set(set_type);
+        match self.builtin_args(args)?.as_slice() {
+            [] => Ok(set_type.empty()),
+            // `set({...})` is the display itself
+            [display @ builtins::Arg {
+                hir: HirExpr::Set(_),
+                ..
+            }] => Ok(display.expr.clone()),
+            [iterable] => Ok(set_type.collect(&builtins::iterator(iterable, false))),
+            _ => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "{func}() takes at most one argument"
+            ))),
+        }
+    }
+
+    /// `sorted(xs, key=..., reverse=...)` sorts a copy of the items
+    fn convert_sorted_call(
+        &mut self,
+        args: &[HirExpr],
+        kwargs: &[(Symbol, HirExpr)],
+    ) -> Result<syn::Expr> {
+        let [iterable] = args else {
+            transpile_bail!(ErrorKind::CodeGenerationError(
+                "sorted() takes exactly one positional argument".to_string(),
+            ));
+        };
+        let items: syn::Expr = match iterable {
+            HirExpr::Var(name) if self.ctx.can_move(name) => {
+                let iterable = iterable.to_rust_expr(self.ctx)?;
+                parse_quote! { #iterable.into_iter() }
             }
-            HirStmt::Expr(expr) => {
-                let expr_tokens = expr.to_rust_expr(ctx)?;
-                Ok(quote! { #expr_tokens; })
+            _ => {
+                let iterable = iterable.to_rust_expr(self.ctx)?;
+                parse_quote! { #iterable.clone().into_iter() }
+            }
+        };
+        let compare = self.sort_comparator("sorted", kwargs)?;
+        Ok(parse_quote! {
+            {
+                let mut __sorted = #items.collect::<Vec<_>>();
+                __sorted.sort_by(#compare);
+                __sorted
+            }
+        })
+    }
+
+    /// The `sort_by` comparator for the `key` and `reverse` keywords of
+    /// `sorted` and `list.sort`. The key is evaluated for both items, each
+    /// bound by reference to the key's parameter.
+    fn sort_comparator(&mut self, func: &str, kwargs: &[(Symbol, HirExpr)]) -> Result<syn::Expr> {
+        let mut key = None;
+        let mut reverse = None;
+        for (name, value) in kwargs {
+            match name.as_str() {
+                "key" => key = Some(value),
+                "reverse" => reverse = Some(value),
+                _ => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                    "{}() got an unexpected keyword argument '{}'",
+                    func, name
+                ))),
+            }
+        }
+        let (first, second) = match reverse {
+            Some(HirExpr::Literal(Literal::Bool(true))) => (quote! { __b }, quote! { __a }),
+            _ => (quote! { __a }, quote! { __b }),
+        };
+        let (bindings, ordering): (proc_macro2::TokenStream, syn::Expr) = match key {
+            None => (quote! {}, parse_quote! { #first.partial_cmp(#second) }),
+            Some(key) => {
+                let (param, body) = match key {
+                    HirExpr::Lambda { params, body } => match params.as_slice() {
+                        [param] => (param.clone(), body.as_ref().clone()),
+                        _ => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                            "The key function of {}() must take one argument",
+                            func
+                        ))),
+                    },
+                    // A named key function is called on each item
+                    HirExpr::Var(name) => (
+                        "__item".to_string(),
+                        HirExpr::Call {
+                            func: name.clone(),
+                            args: vec![HirExpr::Var("__item".to_string())],
+                            kwargs: vec![],
+                        },
+                    ),
+                    _ => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                        "The key of {}() must be a lambda or a function name",
+                        func
+                    ))),
+                };
+                let param_ident = syn::Ident::new(&param, proc_macro2::Span::call_site());
+                let saved = self.ctx.borrowed_vars.clone();
+                let saved_moves = std::mem::replace(&mut self.ctx.live.moves, false);
+                self.ctx.enter_scope();
+                self.ctx.declare_var(&param);
+                self.ctx.borrowed_vars.insert(param);
+                let key_expr = body.to_rust_expr(self.ctx);
+                self.ctx.exit_scope();
+                self.ctx.borrowed_vars = saved;
+                self.ctx.live.moves = saved_moves;
+                let key_expr = key_expr?;
+                (
+                    quote! {
+                        let #param_ident = __a;
+                        let __ka = &(#key_expr);
+                        let #param_ident = __b;
+                        let __kb = &(#key_expr);
+                    },
+                    match reverse {
+                        Some(HirExpr::Literal(Literal::Bool(true))) => {
+                            parse_quote! { __kb.partial_cmp(__ka) }
+                        }
+                        _ => parse_quote! { __ka.partial_cmp(__kb) },
+                    },
+                )
+            }
+        };
+        let ordering: syn::Expr = parse_quote! { #ordering.unwrap_or(std::cmp::Ordering::Equal) };
+        let ordering = match reverse {
+            None | Some(HirExpr::Literal(Literal::Bool(_))) => ordering,
+            Some(reverse) => {
+                let reverse = reverse.to_rust_expr(self.ctx)?;
+                parse_quote! {
+                    if #reverse { #ordering.reverse() } else { #ordering }
+                }
+            }
+        };
+        Ok(parse_quote! {
+            |__a, __b| {
+                #bindings
+                #ordering
+            }
+        })
+    }
+
+    fn convert_generic_call(&self, func: &str, args: &[syn::Expr]) -> Result<syn::Expr> {
+        let args = args.iter().cloned().map(unparen);
+        if func == "cls" {
+            // `cls(...)` inside a classmethod constructs the enclosing class
+            return Ok(parse_quote! { Self::new(#(#args),*) });
+        }
+        let func_ident = syn::Ident::new(func, proc_macro2::Span::call_site());
+        if self.ctx.class_names.contains(func) {
+            Ok(parse_quote! { #func_ident::new(#(#args),*) })
+        } else {
+            Ok(parse_quote! { #func_ident(#(#args),*) })
+        }
+    }
+
+    /// `module.item(...)` through a module of the package imported by name:
+    /// a call of its function, or construction of its class
+    fn module_call(
+        &mut self,
+        receiver: &HirExpr,
+        item: &str,
+        args: &[HirExpr],
+    ) -> Result<Option<syn::Expr>> {
+        let mut names = vec![];
+        let mut module = receiver;
+        while let HirExpr::Attribute { value, attr } = module {
+            names.push(attr.as_str());
+            module = value;
+        }
+        let HirExpr::Var(root) = module else {
+            return Ok(None);
+        };
+        if self.ctx.is_declared(root) {
+            return Ok(None);
+        }
+        names.push(root);
+        names.reverse();
+        let Some(classes) = self.ctx.module_aliases.get(&names.join(".")) else {
+            return Ok(None);
+        };
+        let is_class = classes.contains(item);
+        let module = names
+            .iter()
+            .map(|name| syn::Ident::new(name, proc_macro2::Span::call_site()));
+        let module: syn::Path = parse_quote! { #(#module)::* };
+        let item = syn::Ident::new(item, proc_macro2::Span::call_site());
+        let args = args
+            .iter()
+            .map(|arg| arg.to_rust_expr(self.ctx).map(unparen))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(if is_class {
+            parse_quote! { #module::#item::new(#(#args),*) }
+        } else {
+            parse_quote! { #module::#item(#(#args),*) }
+        }))
+    }
+
+    fn convert_method_call(
+        &mut self,
+        receiver: &HirExpr,
+        method: &str,
+        args: &[HirExpr],
+        kwargs: &[(Symbol, HirExpr)],
+    ) -> Result<syn::Expr> {
+        if method == "sort" && args.is_empty() && !kwargs.is_empty() {
+            let list = place_expr(receiver, self.ctx)?;
+            let compare = self.sort_comparator("sort", kwargs)?;
+            return Ok(parse_quote! { #list.sort_by(#compare) });
+        }
+        if !kwargs.is_empty() {
+            transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "Keyword arguments to method '{}' are not supported; only methods of the module's classes accept them",
+                method
+            )));
+        }
+        if let Some(expr) = self.kwargs_struct_get(receiver, method, args)? {
+            return Ok(expr);
+        }
+        if let Some(expr) = self.module_call(receiver, method, args)? {
+            return Ok(expr);
+        }
+        if let Some(expr) = self.convert_str_method(receiver, method, args)? {
+            return Ok(expr);
+        }
+        if let Some(expr) = self.convert_set_method(receiver, method, args)? {
+            return Ok(expr);
+        }
+        let method_ident = syn::Ident::new(method, proc_macro2::Span::call_site());
+        let lowered_receiver = is_callable_field(receiver, method, self.ctx)
+            || matches!(receiver, HirExpr::Var(name)
+                if name == "asyncio" || name == "threading" || self.ctx.file_vars.contains(name));
+        let borrows = match self.ctx.borrows.method(method) {
+            Some(borrows) if !lowered_receiver => borrows.clone(),
+            _ => FunctionBorrows::default(),
+        };
+        let arg_exprs = self.convert_arguments(args, &borrows, &[])?;
+
+        // A callable field is called through the field, not as a method
+        if is_callable_field(receiver, method, self.ctx) {
+            return Ok(parse_quote! { (self.#method_ident)(#(#arg_exprs),*) });
+        }
+
+        // `ClassName.method(...)` calls an associated function
+        if let HirExpr::Var(name) = receiver {
+            if name == "asyncio" {
+                return self.convert_asyncio_call(method, &arg_exprs);
+            }
+            if name == "threading" {
+                return self.convert_threading_call(method, &arg_exprs);
             }
+            if self.ctx.file_vars.contains(name) {
+                return self.convert_file_method(name, method, &arg_exprs);
+            }
+            if self.ctx.class_names.contains(name) {
+                let class_ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                let args = arg_exprs.into_iter().map(unparen);
+                let call = parse_quote! { #class_ident::#method_ident(#(#args),*) };
+                return Ok(lent_to_owned(call, &borrows));
+            }
+        }
+
+        let recv = match receiver {
+            // Call through the field itself rather than a clone of it
+            HirExpr::Attribute { value, attr } if is_self(value) => {
+                let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+                parse_quote! { self.#attr_ident }
+            }
+            // Modify the element or entry itself
+            HirExpr::Index { .. }
+                if is_mutating_method(method) || self.ctx.mutating_methods.contains(method) =>
+            {
+                place_expr(receiver, self.ctx)?
+            }
+            _ => receiver.to_rust_expr(self.ctx)?,
+        };
+        let recv_type = declared_type(receiver, self.ctx);
+        let mut arg_exprs = arg_exprs;
+        if let ("get", Some(key)) = (method, args.first()) {
+            arg_exprs[0] = dict_key(key, self.ctx)?;
         }
+        if let Some(expr) =
+            self.convert_builtin_method(&recv, recv_type.as_ref(), method, &arg_exprs)?
+        {
+            return Ok(expr);
+        }
+        let args = arg_exprs.into_iter().map(unparen);
+        let call = parse_quote! { #recv.#method_ident(#(#args),*) };
+        Ok(lent_to_owned(call, &borrows))
     }
-}
-
-/// Extension trait for converting expressions to Rust
-trait ToRustExpr {
-    fn to_rust_expr(&self, ctx: &mut CodeGenContext) -> Result<syn::Expr>;
-}
 
-/// Expression converter to reduce complexity
-struct ExpressionConverter<'a, 'b> {
-    ctx: &'a mut CodeGenContext<'b>,
-}
+    /// A `str` method called on a string
+    fn convert_str_method(
+        &mut self,
+        receiver: &HirExpr,
+        method: &str,
+        args: &[HirExpr],
+    ) -> Result<Option<syn::Expr>> {
+        if !is_str_method(receiver, method, self.ctx) {
+            return Ok(None);
+        }
+        let int_ty = rust_type_to_syn(&self.ctx.fixed_int_type())?;
+        let mut str_args = Vec::new();
+        for arg in self
+            .builtin_args(std::slice::from_ref(receiver))?
+            .into_iter()
+            .chain(self.builtin_args(args)?)
+        {
+            let lent = self.lent_arg(&arg);
+            str_args.push(strings::StrArg { arg, lent });
+        }
+        let mut str_args = str_args.into_iter();
+        let mut recv = str_args.next().expect("the receiver is converted");
+        if let HirExpr::Literal(Literal::String(_)) = receiver {
+            recv.arg.expr = recv.lent.clone();
+        }
+        let args: Vec<strings::StrArg> = str_args.collect();
+        strings::lower_method(&recv, method, &args, &int_ty, &mut self.ctx.string_helpers)
+    }
 
-impl<'a, 'b> ExpressionConverter<'a, 'b> {
-    fn new(ctx: &'a mut CodeGenContext<'b>) -> Self {
-        Self { ctx }
+    /// A `set` method called on a set
+    fn convert_set_method(
+        &mut self,
+        receiver: &HirExpr,
+        method: &str,
+        args: &[HirExpr],
+    ) -> Result<Option<syn::Expr>> {
+        if !sets::is_method(method) || !set_typed(receiver, self.ctx) {
+            return Ok(None);
+        }
+        let mut set_args = self.set_args(std::slice::from_ref(receiver))?;
+        let recv = set_args.pop().expect("the receiver is converted");
+        let args = self.set_args(args)?;
+        let set_type = self.ctx.set_type;
+        let expr = sets::lower_method(&recv, method, &args, set_type);
+        if expr.is_some() && args.iter().any(|arg| !matches!(arg.arg.ty, Type::Set(_))) {
+            self.ctx.use_set(set_type);
+        }
+        Ok(expr)
     }
 
-    fn convert_variable(&self, name: &str) -> Result<syn::Expr> {
-        let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
-        Ok(parse_quote! { #ident })
+    /// Operands of set methods and operators, with the fields of `self`
+    /// used in place
+    fn set_args<'e>(&mut self, args: &'e [HirExpr]) -> Result<Vec<sets::SetArg<'e>>> {
+        let mut set_args = Vec::new();
+        for mut arg in self.builtin_args(args)? {
+            if let HirExpr::Attribute { value, .. } = arg.hir {
+                if is_self(value) {
+                    arg.expr = place_expr(arg.hir, self.ctx)?;
+                }
+            }
+            let lent = self.lent_arg(&arg);
+            set_args.push(sets::SetArg { arg, lent });
+        }
+        Ok(set_args)
     }
 
-    fn convert_binary(&mut self, op: BinOp, left: &HirExpr, right: &HirExpr) -> Result<syn::Expr> {
-        let left_expr = left.to_rust_expr(self.ctx)?;
+    /// Membership in a string, a list or, by default, a dict's keys
+    fn convert_membership(
+        &mut self,
+        op: BinOp,
+        left: &HirExpr,
+        right: &HirExpr,
+    ) -> Result<syn::Expr> {
+        let container = declared_type(right, self.ctx)
+            .unwrap_or_else(|| generators::infer_type(right, &self.ctx.var_types));
         let right_expr = right.to_rust_expr(self.ctx)?;
+        let found: syn::Expr = match container {
+            Type::String => {
+                let item = dict_key(left, self.ctx)?;
+                parse_quote! { #right_expr.contains(#item) }
+            }
+            Type::List(_) => {
+                let item = left.to_rust_expr(self.ctx)?;
+                parse_quote! { #right_expr.contains(&#item) }
+            }
+            _ => {
+                let key = dict_key(left, self.ctx)?;
+                parse_quote! { #right_expr.contains_key(#key) }
+            }
+        };
+        Ok(match op {
+            BinOp::In => found,
+            _ => parse_quote! { !#found },
+        })
+    }
 
+    /// Membership in a set and set operators, `None` for other operands
+    fn convert_set_binary(
+        &mut self,
+        op: BinOp,
+        left: &HirExpr,
+        right: &HirExpr,
+    ) -> Result<Option<syn::Expr>> {
+        let operands = [left.clone(), right.clone()];
         match op {
-            BinOp::In => {
-                // Convert "x in dict" to "dict.contains_key(&x)" for dicts
-                // For now, assume it's a dict/hashmap
-                Ok(parse_quote! { #right_expr.contains_key(&#left_expr) })
-            }
-            BinOp::NotIn => {
-                // Convert "x not in dict" to "!dict.contains_key(&x)"
-                Ok(parse_quote! { !#right_expr.contains_key(&#left_expr) })
-            }
-            BinOp::Sub => {
-                // Check if we're subtracting from a .len() call to prevent underflow
-                if self.is_len_call(left) {
-                    // Use saturating_sub to prevent underflow when subtracting from array length
-                    Ok(parse_quote! { #left_expr.saturating_sub(#right_expr) })
-                } else {
-                    let rust_op = convert_binop(op)?;
-                    Ok(parse_quote! { (#left_expr #rust_op #right_expr) })
-                }
+            BinOp::In | BinOp::NotIn if set_typed(right, self.ctx) => {
+                let [item, set] = <[_; 2]>::try_from(self.set_args(&operands)?)
+                    .unwrap_or_else(|_| unreachable!("both operands are converted"));
+                let (set, item) = (builtins::operand_expr(&set.arg.expr), item.lent);
+                Ok(Some(match op {
+                    BinOp::In => parse_quote! { #set.contains(#item) },
+                    _ => parse_quote! { !#set.contains(#item) },
+                }))
+            }
+            _ if set_typed(left, self.ctx) => {
+                let [left, right] = <[_; 2]>::try_from(self.set_args(&operands)?)
+                    .unwrap_or_else(|_| unreachable!("both operands are converted"));
+                Ok(sets::lower_operator(op, &left.lent, &right.lent))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// An argument lent by reference: a string literal as a `&str`, a
+    /// borrowed parameter as it is, anything else by reference
+    fn lent_arg(&self, arg: &builtins::Arg) -> syn::Expr {
+        match arg.hir {
+            HirExpr::Literal(Literal::String(s)) => {
+                let lit = syn::LitStr::new(s, proc_macro2::Span::call_site());
+                parse_quote! { #lit }
+            }
+            HirExpr::Var(name) if self.ctx.borrow_scope.passing(name) == ParamPassing::Borrowed => {
+                arg.expr.clone()
             }
             _ => {
-                let rust_op = convert_binop(op)?;
-                Ok(parse_quote! { (#left_expr #rust_op #right_expr) })
+                let expr = builtins::operand_expr(&arg.expr);
+                parse_quote! { &#expr }
             }
         }
     }
 
-    fn convert_unary(&mut self, op: &UnaryOp, operand: &HirExpr) -> Result<syn::Expr> {
-        let operand_expr = operand.to_rust_expr(self.ctx)?;
-        match op {
-            UnaryOp::Not => Ok(parse_quote! { !#operand_expr }),
-            UnaryOp::Neg => Ok(parse_quote! { -#operand_expr }),
-            UnaryOp::Pos => Ok(operand_expr), // No +x in Rust
-            UnaryOp::BitNot => Ok(parse_quote! { !#operand_expr }),
+    /// An f-string or other formatted string as `format!`
+    fn convert_fstring(&mut self, parts: &[FStringPart], values: &[HirExpr]) -> Result<syn::Expr> {
+        let values = self.builtin_args(values)?;
+        strings::lower_fstring(parts, &values, &mut self.ctx.string_helpers)
+    }
+
+    /// Arguments for a callee taking its parameters as `borrows` says;
+    /// `big_params` flags the `BigInt` ones
+    fn convert_arguments(
+        &mut self,
+        args: &[HirExpr],
+        borrows: &FunctionBorrows,
+        big_params: &[bool],
+    ) -> Result<Vec<syn::Expr>> {
+        args.iter()
+            .enumerate()
+            .map(|(i, arg)| match big_params.get(i) {
+                Some(true) => big_value(arg, true, self.ctx),
+                _ => self.convert_argument(arg, borrows.passing(i)),
+            })
+            .collect()
+    }
+
+    /// Arguments for parameters of `Union` or `Literal` types `params`
+    /// converted into their enums
+    fn enum_arguments(
+        &self,
+        args: &[HirExpr],
+        arg_exprs: Vec<syn::Expr>,
+        params: &[Type],
+    ) -> Result<Vec<syn::Expr>> {
+        args.iter()
+            .zip(arg_exprs)
+            .enumerate()
+            .map(|(i, (arg, expr))| match params.get(i) {
+                Some(ty) => enum_value(arg, expr, ty, self.ctx),
+                None => Ok(expr),
+            })
+            .collect()
+    }
+
+    /// A string literal is lent as is, and so is a parameter the caller
+    /// itself borrows; a list display is lent as an array, and anything
+    /// else by reference
+    fn convert_argument(&mut self, arg: &HirExpr, passing: ParamPassing) -> Result<syn::Expr> {
+        if passing == ParamPassing::Owned {
+            return arg.to_rust_expr(self.ctx);
+        }
+        let borrowed: syn::Expr = match arg {
+            HirExpr::Literal(Literal::String(s)) => {
+                let lit = syn::LitStr::new(s, proc_macro2::Span::call_site());
+                parse_quote! { #lit }
+            }
+            HirExpr::Var(name) if self.ctx.borrow_scope.passing(name) == ParamPassing::Borrowed => {
+                self.convert_variable(name)?
+            }
+            _ => {
+                let expr = arg.to_rust_expr(self.ctx)?;
+                match expr {
+                    syn::Expr::Macro(list)
+                        if matches!(arg, HirExpr::List(_)) && list.mac.path.is_ident("vec") =>
+                    {
+                        let items = list.mac.tokens;
+                        parse_quote! { &[#items] }
+                    }
+                    syn::Expr::Path(_)
+                    | syn::Expr::Call(_)
+                    | syn::Expr::MethodCall(_)
+                    | syn::Expr::Field(_)
+                    | syn::Expr::Index(_)
+                    | syn::Expr::Macro(_)
+                    | syn::Expr::Paren(_) => parse_quote! { &#expr },
+                    _ => parse_quote! { &(#expr) },
+                }
+            }
+        };
+        if passing == ParamPassing::Cow {
+            self.ctx.needs_cow = true;
+            return Ok(parse_quote! { Cow::Borrowed(#borrowed) });
         }
+        Ok(borrowed)
     }
 
-    fn convert_call(&mut self, func: &str, args: &[HirExpr]) -> Result<syn::Expr> {
-        let arg_exprs: Vec<syn::Expr> = args
+    /// `Options { key: Some(value), .. }` for the keywords packed into `**kwargs`
+    fn convert_kwargs_struct(
+        &mut self,
+        name: &str,
+        kwargs: &[(Symbol, HirExpr)],
+    ) -> Result<syn::Expr> {
+        let struct_ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+        if kwargs.is_empty() {
+            return Ok(parse_quote! { #struct_ident::default() });
+        }
+        let fields = kwargs
             .iter()
-            .map(|arg| arg.to_rust_expr(self.ctx))
+            .map(|(key, value)| {
+                let field = syn::Ident::new(key, proc_macro2::Span::call_site());
+                let value = value.to_rust_expr(self.ctx)?;
+                Ok(quote! { #field: Some(#value) })
+            })
             .collect::<Result<Vec<_>>>()?;
+        if fields.len() == self.ctx.kwargs_structs[name].len() {
+            return Ok(parse_quote! { #struct_ident { #(#fields),* } });
+        }
+        Ok(parse_quote! { #struct_ident { #(#fields,)* ..Default::default() } })
+    }
+
+    /// The options struct field a string key of `**kwargs` reads
+    fn kwargs_struct_field(&self, kwargs: &HirExpr, key: &HirExpr) -> Option<syn::Expr> {
+        let (HirExpr::Var(name), HirExpr::Literal(Literal::String(key))) = (kwargs, key) else {
+            return None;
+        };
+        match self.ctx.var_types.get(name) {
+            Some(Type::Custom(s)) if self.ctx.kwargs_structs.contains_key(s) => {
+                let kwargs = syn::Ident::new(name, proc_macro2::Span::call_site());
+                let field = syn::Ident::new(key, proc_macro2::Span::call_site());
+                Some(parse_quote! { #kwargs.#field })
+            }
+            _ => None,
+        }
+    }
+
+    /// `kwargs.get("key", default)` on an options struct
+    fn kwargs_struct_get(
+        &mut self,
+        receiver: &HirExpr,
+        method: &str,
+        args: &[HirExpr],
+    ) -> Result<Option<syn::Expr>> {
+        let (Some(key), "get") = (args.first(), method) else {
+            return Ok(None);
+        };
+        let Some(field) = self.kwargs_struct_field(receiver, key) else {
+            return Ok(None);
+        };
+        Ok(Some(match &args[1..] {
+            [] => parse_quote! { #field.clone() },
+            [default] => {
+                let default = default.to_rust_expr(self.ctx)?;
+                parse_quote! { #field.clone().unwrap_or(#default) }
+            }
+            _ => transpile_bail!(ErrorKind::CodeGenerationError(
+                "get() takes at most 2 arguments".to_string(),
+            )),
+        }))
+    }
+
+    /// Map the `asyncio` functions onto tokio
+    fn convert_asyncio_call(&self, function: &str, args: &[syn::Expr]) -> Result<syn::Expr> {
+        match (function, args) {
+            ("sleep", [seconds]) => Ok(parse_quote! {
+                tokio::time::sleep(std::time::Duration::from_secs_f64(#seconds as f64))
+            }),
+            // `join!` awaits the futures itself
+            ("gather", _) => Ok(parse_quote! { tokio::join!(#(#args),*) }),
+            ("run", [coroutine]) => Ok(parse_quote! {
+                tokio::runtime::Runtime::new().unwrap().block_on(#coroutine)
+            }),
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "asyncio.{} is not supported",
+                function
+            ))),
+        }
+    }
 
-        match func {
-            "len" => self.convert_len_call(&arg_exprs),
-            "range" => self.convert_range_call(&arg_exprs),
-            _ => self.convert_generic_call(func, &arg_exprs),
+    /// Locks are shared like Python objects, so they live behind an `Arc`
+    fn convert_threading_call(&mut self, function: &str, args: &[syn::Expr]) -> Result<syn::Expr> {
+        match (function, args) {
+            ("Lock" | "RLock", []) => {
+                self.ctx.needs_arc = true;
+                Ok(parse_quote! { Arc::new(std::sync::Mutex::new(())) })
+            }
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "threading.{} is not supported",
+                function
+            ))),
         }
     }
 
-    fn convert_len_call(&self, args: &[syn::Expr]) -> Result<syn::Expr> {
-        if args.len() != 1 {
-            bail!("len() requires exactly one argument");
+    /// Reads and writes on a file bound by `with`
+    fn convert_file_method(
+        &self,
+        file: &str,
+        method: &str,
+        args: &[syn::Expr],
+    ) -> Result<syn::Expr> {
+        let file = syn::Ident::new(file, proc_macro2::Span::call_site());
+        match (method, args) {
+            ("read", []) => Ok(parse_quote! {
+                std::io::read_to_string(&mut #file).expect("failed to read file")
+            }),
+            ("write", [data]) => Ok(parse_quote! {
+                std::io::Write::write_all(&mut #file, #data.as_bytes()).expect("failed to write file")
+            }),
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                "File method '{}' is not supported",
+                method
+            ))),
         }
-        let arg = &args[0];
-        Ok(parse_quote! { #arg.len() })
     }
 
-    fn convert_range_call(&self, args: &[syn::Expr]) -> Result<syn::Expr> {
-        match args.len() {
-            1 => {
-                let end = &args[0];
-                Ok(parse_quote! { 0..#end })
+    fn convert_await(&mut self, value: &HirExpr) -> Result<syn::Expr> {
+        match value {
+            HirExpr::MethodCall {
+                receiver, method, ..
+            } if method == "gather"
+                && matches!(receiver.as_ref(), HirExpr::Var(v) if v == "asyncio") =>
+            {
+                value.to_rust_expr(self.ctx)
             }
-            2 => {
-                let start = &args[0];
-                let end = &args[1];
-                Ok(parse_quote! { #start..#end })
+            // A failing coroutine is propagated once it has been awaited
+            HirExpr::Call { func, args, kwargs } => {
+                let call = self.convert_call(func, args, kwargs)?;
+                propagate_call_error(value, parse_quote! { #call.await }, self.ctx)
             }
-            3 => {
-                let start = &args[0];
-                let end = &args[1];
-                let step = &args[2];
-                Ok(parse_quote! { (#start..#end).step_by(#step as usize) })
+            HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                kwargs,
+            } => {
+                let call = self.convert_method_call(receiver, method, args, kwargs)?;
+                propagate_call_error(value, parse_quote! { #call.await }, self.ctx)
+            }
+            _ => {
+                let future = value.to_rust_expr(self.ctx)?;
+                Ok(parse_quote! { #future.await })
             }
-            _ => bail!("Invalid number of arguments for range()"),
         }
     }
 
-    fn convert_generic_call(&self, func: &str, args: &[syn::Expr]) -> Result<syn::Expr> {
-        let func_ident = syn::Ident::new(func, proc_macro2::Span::call_site());
-        Ok(parse_quote! { #func_ident(#(#args),*) })
+    /// Map Python list and dict methods onto their Rust equivalents
+    fn convert_builtin_method(
+        &self,
+        recv: &syn::Expr,
+        recv_type: Option<&Type>,
+        method: &str,
+        args: &[syn::Expr],
+    ) -> Result<Option<syn::Expr>> {
+        let int_ty = rust_type_to_syn(&self.ctx.fixed_int_type())?;
+        // Dict entries borrowed by `iter()`, copied or cloned out
+        let entry = || {
+            let owned = |name: &str, ty: Option<&Type>| {
+                let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                match ty {
+                    Some(ty) if closures::is_copy_type(ty) => quote! { *#ident },
+                    _ => quote! { #ident.clone() },
+                }
+            };
+            let (key, value) = match recv_type {
+                Some(Type::Dict(key, value)) => (Some(&**key), Some(&**value)),
+                _ => (None, None),
+            };
+            let (key, value) = (owned("k", key), owned("v", value));
+            quote! { |(k, v)| (#key, #value) }
+        };
+        let expr = match (method, args) {
+            // list
+            ("append", [x]) => {
+                let x = unparen(x.clone());
+                parse_quote! { #recv.push(#x) }
+            }
+            ("extend", [xs]) => parse_quote! { #recv.extend(#xs.iter().cloned()) },
+            ("insert", [i, x]) => {
+                let x = unparen(x.clone());
+                parse_quote! { #recv.insert(#i as usize, #x) }
+            }
+            ("pop", []) => parse_quote! { #recv.pop().unwrap_or_default() },
+            ("pop", [i]) => parse_quote! { #recv.remove(#i as usize) },
+            ("remove", [x]) => parse_quote! {
+                if let Some(pos) = #recv.iter().position(|e| *e == #x) {
+                    #recv.remove(pos);
+                }
+            },
+            ("index", [x]) => parse_quote! {
+                #recv.iter().position(|e| *e == #x).expect("value not in list") as #int_ty
+            },
+            ("count", [x]) => {
+                parse_quote! { #recv.iter().filter(|e| **e == #x).count() as #int_ty }
+            }
+            ("copy", []) => parse_quote! { #recv.clone() },
+            // dict
+            // The key is already lent
+            ("get", [k]) => parse_quote! { #recv.get(#k).cloned() },
+            ("get", [k, default]) => parse_quote! { #recv.get(#k).cloned().unwrap_or(#default) },
+            ("keys", []) => parse_quote! { #recv.keys().cloned().collect::<Vec<_>>() },
+            ("values", []) => parse_quote! { #recv.values().cloned().collect::<Vec<_>>() },
+            ("items", []) => {
+                let entry = entry();
+                parse_quote! { #recv.iter().map(#entry).collect::<Vec<_>>() }
+            }
+            ("update", [other]) => {
+                let entry = entry();
+                parse_quote! { #recv.extend(#other.iter().map(#entry)) }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(expr))
     }
 
     fn convert_index(&mut self, base: &HirExpr, index: &HirExpr) -> Result<syn::Expr> {
+        if let Some(field) = self.kwargs_struct_field(base, index) {
+            let HirExpr::Literal(Literal::String(key)) = index else {
+                unreachable!("options struct fields are read by string key")
+            };
+            let message = format!("missing keyword argument '{key}'");
+            return Ok(parse_quote! { #field.clone().expect(#message) });
+        }
+        let base_type = declared_type(base, self.ctx)
+            .unwrap_or_else(|| generators::infer_type(base, &self.ctx.var_types));
         let base_expr = base.to_rust_expr(self.ctx)?;
-        let index_expr = index.to_rust_expr(self.ctx)?;
-        // V1: Safe indexing with bounds checking
-        Ok(parse_quote! {
-            #base_expr.get(#index_expr as usize).copied().unwrap_or_default()
+        match base_type {
+            Type::Dict(_, value) => self.convert_dict_lookup(base_expr, index, &value),
+            Type::Unknown if matches!(index, HirExpr::Literal(Literal::String(_))) => {
+                self.convert_dict_lookup(base_expr, index, &Type::Unknown)
+            }
+            Type::Tuple(items) => {
+                let position = indexing::int_literal(index)
+                    .map(|i| if i < 0 { i + items.len() as i64 } else { i })
+                    .filter(|i| (0..items.len() as i64).contains(i));
+                let Some(position) = position else {
+                    transpile_bail!(ErrorKind::UnsupportedFeature(
+                        "Tuples can only be indexed by an integer literal in range".to_string(),
+                    ));
+                };
+                let field = syn::Index::from(position as usize);
+                Ok(parse_quote! { #base_expr.#field })
+            }
+            Type::String => self.convert_element(base_expr, index, Items::Chars),
+            Type::List(item) => self.convert_element(base_expr, index, list_items(&item, self.ctx)),
+            _ => self.convert_element(base_expr, index, Items::Cloned),
+        }
+    }
+
+    /// `list[index]`, or the character `string[index]` as a string
+    fn convert_element(
+        &mut self,
+        base: syn::Expr,
+        index: &HirExpr,
+        items: Items,
+    ) -> Result<syn::Expr> {
+        let index = index_bound(index, self.ctx)?;
+        let message = match items {
+            Items::Chars => "string index out of range",
+            _ => "list index out of range",
+        };
+        let policy = self.ctx.error_scope.index_policy;
+        // The sequence is named once more by its length
+        let (base, binding) = if indexing::needs_len(&index) && !strings::is_place(&base) {
+            (parse_quote! { __s }, Some(base))
+        } else {
+            (base, None)
+        };
+        let len: syn::Expr = match items {
+            Items::Chars => parse_quote! { #base.chars().count() },
+            _ => parse_quote! { #base.len() },
+        };
+        let position = indexing::position(&index, &len);
+        let element: syn::Expr = match (items, policy) {
+            (Items::Copied, IndexPolicy::Native) => parse_quote! { #base[#position] },
+            (Items::Cloned, IndexPolicy::Native) => parse_quote! { #base[#position].clone() },
+            (Items::Copied, _) => {
+                let found = indexing::get(&base, &position, false);
+                unwrap_element(parse_quote! { #found.copied() }, message, self.ctx)?
+            }
+            (Items::Cloned, _) => {
+                let found = indexing::get(&base, &position, false);
+                unwrap_element(parse_quote! { #found.cloned() }, message, self.ctx)?
+            }
+            (Items::Chars, _) => unwrap_element(
+                parse_quote! { #base.chars().nth(#position).map(String::from) },
+                message,
+                self.ctx,
+            )?,
+        };
+        Ok(match binding {
+            Some(binding) => parse_quote! {
+                {
+                    let __s = &#binding;
+                    #element
+                }
+            },
+            None => element,
+        })
+    }
+
+    /// `dict[key]`, which panics on a missing key unless bounds checking is disabled
+    fn convert_dict_lookup(
+        &mut self,
+        base: syn::Expr,
+        key: &HirExpr,
+        value: &Type,
+    ) -> Result<syn::Expr> {
+        let key = dict_key(key, self.ctx)?;
+        let copied = list_items(value, self.ctx) == Items::Copied;
+        if self.ctx.error_scope.raises("KeyError") {
+            let found: syn::Expr = if copied {
+                parse_quote! { #base.get(#key).copied() }
+            } else {
+                parse_quote! { #base.get(#key).cloned() }
+            };
+            return unwrap_or_raise(found, "KeyError", "key not found", self.ctx);
+        }
+        Ok(match self.ctx.error_scope.index_policy {
+            IndexPolicy::Default if copied => {
+                parse_quote! { #base.get(#key).copied().unwrap_or_default() }
+            }
+            IndexPolicy::Default => parse_quote! { #base.get(#key).cloned().unwrap_or_default() },
+            _ if copied => parse_quote! { #base[#key] },
+            _ => parse_quote! { #base[#key].clone() },
         })
     }
 
+    /// `base[start:stop:step]` of a list, or of a string by character
+    fn convert_slice(
+        &mut self,
+        base: &HirExpr,
+        start: Option<&HirExpr>,
+        stop: Option<&HirExpr>,
+        step: Option<&HirExpr>,
+    ) -> Result<syn::Expr> {
+        let items = match declared_type(base, self.ctx)
+            .unwrap_or_else(|| generators::infer_type(base, &self.ctx.var_types))
+        {
+            Type::String => Items::Chars,
+            Type::List(item) => list_items(&item, self.ctx),
+            Type::Unknown => Items::Cloned,
+            _ => transpile_bail!(ErrorKind::UnsupportedFeature(
+                "Only lists and strings can be sliced".to_string(),
+            )),
+        };
+        let step = match step {
+            None => 1,
+            Some(step) => match indexing::int_literal(step) {
+                Some(step) => step,
+                None => {
+                    let mut bound = |b: Option<&HirExpr>| b.map(|b| b.to_rust_expr(self.ctx));
+                    let (start, stop) = (bound(start).transpose()?, bound(stop).transpose()?);
+                    let step = step.to_rust_expr(self.ctx)?;
+                    let base_expr = base.to_rust_expr(self.ctx)?;
+                    return Ok(indexing::slice_by_step(
+                        &base_expr, items, start, stop, &step,
+                    ));
+                }
+            },
+        };
+        let start = start.map(|s| index_bound(s, self.ctx)).transpose()?;
+        let stop = stop.map(|s| index_bound(s, self.ctx)).transpose()?;
+        let base_expr = base.to_rust_expr(self.ctx)?;
+        indexing::slice(&base_expr, items, start, stop, step)
+    }
+
     fn convert_list(&mut self, elts: &[HirExpr]) -> Result<syn::Expr> {
         let elt_exprs: Vec<syn::Expr> = elts
             .iter()
@@ -431,6 +6859,9 @@ impl<'a, 'b> ExpressionConverter<'a, 'b> {
 
     fn convert_dict(&mut self, items: &[(HirExpr, HirExpr)]) -> Result<syn::Expr> {
         self.ctx.needs_hashmap = true;
+        if items.is_empty() {
+            return Ok(parse_quote! { HashMap::new() });
+        }
         let mut insert_stmts = Vec::new();
         for (key, value) in items {
             let key_expr = key.to_rust_expr(self.ctx)?;
@@ -446,6 +6877,37 @@ impl<'a, 'b> ExpressionConverter<'a, 'b> {
         })
     }
 
+    /// A set display; sets among its elements become `BTreeSet`s
+    fn convert_set(&mut self, elts: &[HirExpr]) -> Result<syn::Expr> {
+        let set_type = self.ctx.set_type;
+        self.ctx.use_set(set_type);
+        let mut elt_exprs = Vec::new();
+        for elt in elts {
+            let elt_expr = match elt {
+                HirExpr::Set(_) => self.convert_btree_set(elt)?,
+                HirExpr::Call { func, .. } if matches!(func.as_str(), "set" | "frozenset") => {
+                    self.convert_btree_set(elt)?
+                }
+                _ if set_typed(elt, self.ctx) => {
+                    self.ctx.use_set(SetType::BTree);
+                    let elt = &self.builtin_args(std::slice::from_ref(elt))?[0];
+                    SetType::BTree.collect(&builtins::iterator(elt, false))
+                }
+                _ => elt.to_rust_expr(self.ctx)?,
+            };
+            elt_exprs.push(elt_expr);
+        }
+        Ok(set_type.display(&elt_exprs))
+    }
+
+    /// A set made in place as a `BTreeSet`
+    fn convert_btree_set(&mut self, set: &HirExpr) -> Result<syn::Expr> {
+        let saved = std::mem::replace(&mut self.ctx.set_type, SetType::BTree);
+        let expr = set.to_rust_expr(self.ctx);
+        self.ctx.set_type = saved;
+        expr
+    }
+
     fn convert_tuple(&mut self, elts: &[HirExpr]) -> Result<syn::Expr> {
         let elt_exprs: Vec<syn::Expr> = elts
             .iter()
@@ -455,11 +6917,362 @@ impl<'a, 'b> ExpressionConverter<'a, 'b> {
     }
 
     fn convert_attribute(&mut self, value: &HirExpr, attr: &str) -> Result<syn::Expr> {
-        let value_expr = value.to_rust_expr(self.ctx)?;
         let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+        if let HirExpr::Var(name) = value {
+            // Class attributes are associated constants
+            if self.ctx.class_names.contains(name) {
+                let class_ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                return Ok(parse_quote! { #class_ident::#attr_ident });
+            }
+            if name == "self" {
+                if let Some(field) = self.ctx.class_fields.get(attr) {
+                    if field.is_class_var {
+                        return Ok(parse_quote! { Self::#attr_ident });
+                    }
+                    // Reading a non-Copy field through `&self` needs an owned copy
+                    let rust_type = self.ctx.type_mapper.map_type(&field.field_type);
+                    if !self.ctx.type_mapper.can_copy(&rust_type) {
+                        return Ok(parse_quote! { self.#attr_ident.clone() });
+                    }
+                }
+            }
+            // So does reading one through the guard owning a `with` resource
+            let guarded_field = self
+                .ctx
+                .guard_vars
+                .get(name)
+                .and_then(|class| self.ctx.class_defs.get(class))
+                .and_then(|class| class.fields.iter().find(|f| f.name == attr));
+            if let Some(field) = guarded_field {
+                let rust_type = self.ctx.type_mapper.map_type(&field.field_type);
+                if !self.ctx.type_mapper.can_copy(&rust_type) {
+                    let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                    return Ok(parse_quote! { #ident.#attr_ident.clone() });
+                }
+            }
+        }
+        let value_expr = value.to_rust_expr(self.ctx)?;
+        if self.is_property(value, attr) {
+            return Ok(parse_quote! { #value_expr.#attr_ident() });
+        }
         Ok(parse_quote! { #value_expr.#attr_ident })
     }
 
+    /// Whether `value.attr` reads a `@property` of one of the module's classes
+    fn is_property(&self, value: &HirExpr, attr: &str) -> bool {
+        let class = if is_self(value) {
+            self.ctx.current_class.clone()
+        } else {
+            match declared_type(value, self.ctx) {
+                Some(Type::Custom(class)) => Some(class),
+                _ => None,
+            }
+        };
+        class
+            .and_then(|class| self.ctx.class_defs.get(&class))
+            .is_some_and(|class| {
+                class
+                    .methods
+                    .iter()
+                    .any(|m| m.name == attr && m.kind == MethodKind::Property)
+            })
+    }
+
+    /// Lower a comprehension to an iterator chain.
+    ///
+    /// Every `for` clause after the first is flattened with `flat_map` into
+    /// tuples of all loop variables, so filters and the element see them all.
+    fn convert_comprehension(
+        &mut self,
+        kind: ComprehensionKind,
+        element: &HirExpr,
+        value: Option<&HirExpr>,
+        generators: &[ComprehensionClause],
+    ) -> Result<syn::Expr> {
+        if generators.is_empty() {
+            transpile_bail!(ErrorKind::CodeGenerationError(
+                "Comprehension without a for clause".to_string(),
+            ));
+        }
+        let items = self.comprehension_items(element, value, generators);
+        let source = self.comprehension_source(generators, &items, 0)?;
+        // Everything else runs in closures called for each item
+        let closure_scope = self.ctx.error_scope.closure();
+        let saved_error_scope = std::mem::replace(&mut self.ctx.error_scope, closure_scope);
+        let saved_moves = std::mem::replace(&mut self.ctx.live.moves, false);
+        let comprehension =
+            self.comprehension_chain(source, kind, element, value, generators, &items);
+        self.ctx.live.moves = saved_moves;
+        self.ctx.error_scope = saved_error_scope;
+        comprehension
+    }
+
+    fn comprehension_chain(
+        &mut self,
+        mut chain: syn::Expr,
+        kind: ComprehensionKind,
+        element: &HirExpr,
+        value: Option<&HirExpr>,
+        generators: &[ComprehensionClause],
+        items: &[ClauseItems],
+    ) -> Result<syn::Expr> {
+        let (first, rest) = generators
+            .split_first()
+            .expect("comprehensions have a for clause");
+        let mut targets = vec![syn::Ident::new(
+            &first.target,
+            proc_macro2::Span::call_site(),
+        )];
+        for (i, generator) in rest.iter().enumerate() {
+            let outer = comprehension_pattern(&targets);
+            let source = self.comprehension_source(generators, items, i + 1)?;
+            let target = syn::Ident::new(&generator.target, proc_macro2::Span::call_site());
+            // References and copies pass on as they are; owned items are
+            // cloned for each item of the inner clause
+            let captured = targets.iter().zip(items).map(|(target, item)| {
+                if item.lent || closures::is_copy_type(&item.ty) {
+                    quote! { #target }
+                } else {
+                    quote! { #target.clone() }
+                }
+            });
+            chain = parse_quote! {
+                #chain.flat_map(|#outer| #source.map(move |#target| (#(#captured,)* #target)))
+            };
+            targets.push(target);
+        }
+        let pattern = comprehension_pattern(&targets);
+        let names: Vec<&str> = generators.iter().map(|g| g.target.as_str()).collect();
+
+        // Filters see each item through a reference
+        let saved = self.ctx.borrowed_vars.clone();
+        self.ctx
+            .borrowed_vars
+            .extend(names.iter().map(|n| n.to_string()));
+        let conditions = generators
+            .iter()
+            .flat_map(|g| &g.conditions)
+            .map(|c| c.to_rust_expr(self.ctx))
+            .collect::<Result<Vec<_>>>();
+        self.ctx.borrowed_vars = saved.clone();
+        // Lent items are copied out of the filter's reference, so filters see
+        // every item through one reference
+        let filter_pattern = match targets.as_slice() {
+            _ if !items.iter().any(|item| item.lent) => pattern.clone(),
+            [target] => quote! { &#target },
+            _ => {
+                let bindings = targets.iter().zip(items).map(|(target, item)| {
+                    if item.lent {
+                        quote! { #target }
+                    } else {
+                        quote! { ref #target }
+                    }
+                });
+                quote! { &(#(#bindings),*) }
+            }
+        };
+        for condition in conditions? {
+            let condition = unparen(condition);
+            chain = parse_quote! { #chain.filter(|#filter_pattern| #condition) };
+        }
+
+        // The element takes ownership of the item, or reads a lent one
+        // through its reference
+        for name in &names {
+            self.ctx.borrowed_vars.remove(*name);
+        }
+        let saved_passing = self.ctx.borrow_scope.params.clone();
+        for (name, item) in names.iter().zip(items) {
+            if item.lent && item.read_only {
+                let passing = ParamPassing::Borrowed;
+                self.ctx
+                    .borrow_scope
+                    .params
+                    .insert(name.to_string(), passing);
+            } else {
+                self.ctx.borrow_scope.params.remove(*name);
+            }
+        }
+        let item = match value {
+            Some(value) => {
+                let key = element.to_rust_expr(self.ctx)?;
+                let value = value.to_rust_expr(self.ctx)?;
+                // A bare loop variable as key would be moved before the value reads it
+                if matches!(element, HirExpr::Var(_)) {
+                    Ok(parse_quote! { (#key.clone(), #value) })
+                } else {
+                    Ok(parse_quote! { (#key, #value) })
+                }
+            }
+            None => element.to_rust_expr(self.ctx),
+        };
+        self.ctx.borrowed_vars = saved;
+        self.ctx.borrow_scope.params = saved_passing;
+        let mut item = unparen(item?);
+        // Lent items iterated by a later clause are cloned for an element
+        // that takes ownership of them
+        let owned: Vec<&syn::Ident> = targets
+            .iter()
+            .zip(items)
+            .filter(|(_, item)| item.lent && !item.read_only)
+            .map(|(target, _)| target)
+            .collect();
+        if !owned.is_empty() {
+            item = parse_quote! { { #(let #owned = #owned.clone();)* #item } };
+        }
+        let is_identity = value.is_none()
+            && names.len() == 1
+            && matches!(element, HirExpr::Var(v) if v == names[0]);
+        if !is_identity {
+            chain = parse_quote! { #chain.map(|#pattern| #item) };
+        }
+
+        Ok(match kind {
+            ComprehensionKind::List => parse_quote! { #chain.collect::<Vec<_>>() },
+            ComprehensionKind::Set => {
+                let set_type = self.ctx.set_type;
+                self.ctx.use_set(set_type);
+                set_type.collect(&chain)
+            }
+            ComprehensionKind::Dict => {
+                self.ctx.needs_hashmap = true;
+                parse_quote! { #chain.collect::<HashMap<_, _>>() }
+            }
+            ComprehensionKind::Generator => chain,
+        })
+    }
+
+    /// The type of each clause's items, and whether they are lent: those of
+    /// a named collection that the element only reads or a later clause
+    /// iterates, or that cannot be cloned, are bound by reference
+    fn comprehension_items(
+        &self,
+        element: &HirExpr,
+        value: Option<&HirExpr>,
+        generators: &[ComprehensionClause],
+    ) -> Vec<ClauseItems> {
+        let mut items: Vec<ClauseItems> = Vec::new();
+        for (i, generator) in generators.iter().enumerate() {
+            let outer = match &generator.iter {
+                HirExpr::Var(name) => generators[..i].iter().position(|g| &g.target == name),
+                _ => None,
+            };
+            let (source, lendable) = match (&generator.iter, outer) {
+                (_, Some(j)) => (Some(&items[j].ty), items[j].lent),
+                (HirExpr::Var(name), None) => (self.ctx.var_types.get(name), true),
+                _ => (None, false),
+            };
+            let ty = match source {
+                Some(Type::List(item) | Type::Set(item) | Type::Dict(item, _)) => (**item).clone(),
+                // Characters are made as they are iterated, so none can be lent
+                Some(Type::String) => {
+                    items.push(ClauseItems {
+                        ty: Type::String,
+                        lent: false,
+                        read_only: false,
+                    });
+                    continue;
+                }
+                _ => Type::Unknown,
+            };
+            let target = &generator.target;
+            let reads =
+                |expr: &HirExpr| borrowing::only_reads(expr, target, &ty, &self.ctx.borrows);
+            let read_only = reads(element) && value.is_none_or(reads);
+            let iterated = generators[i + 1..]
+                .iter()
+                .any(|g| matches!(&g.iter, HirExpr::Var(name) if name == target));
+            let iterable = matches!(ty, Type::List(_) | Type::Set(_));
+            let cloneable = self.can_clone(&ty);
+            let lent = lendable && (read_only || iterated && iterable || !cloneable);
+            items.push(ClauseItems {
+                ty,
+                lent,
+                read_only,
+            });
+        }
+        items
+    }
+
+    fn can_clone(&self, ty: &Type) -> bool {
+        let rust_type = self.ctx.type_mapper.map_type(ty);
+        self.ctx.type_mapper.can_clone(&rust_type)
+    }
+
+    /// Iterator over the items of the `in` expression of clause `i`.
+    ///
+    /// Lent items are borrowed with `iter()`. Other named collections yield
+    /// owned copies so they stay usable after the comprehension, unless
+    /// nothing reads them again; temporaries are consumed with `into_iter()`.
+    fn comprehension_source(
+        &mut self,
+        generators: &[ComprehensionClause],
+        items: &[ClauseItems],
+        i: usize,
+    ) -> Result<syn::Expr> {
+        let iter = &generators[i].iter;
+        let lent = items[i].lent;
+        let outer = match iter {
+            HirExpr::Var(name) => generators[..i].iter().position(|g| &g.target == name),
+            _ => None,
+        };
+        match (iter, outer) {
+            (HirExpr::Call { func, .. }, _) if func == "range" => {
+                let range = iter.to_rust_expr(self.ctx)?;
+                Ok(parse_quote! { (#range) })
+            }
+            // A variable of an enclosing clause is a reference, or owned by
+            // its closure
+            (HirExpr::Var(name), Some(j)) => {
+                let ident = syn::Ident::new(name, proc_macro2::Span::call_site());
+                Ok(match (items[j].lent, lent) {
+                    _ if items[j].ty == Type::String => {
+                        parse_quote! { #ident.chars().map(String::from) }
+                    }
+                    (true, true) => parse_quote! { #ident.iter() },
+                    (true, false) => parse_quote! { #ident.iter().cloned() },
+                    (false, _) => parse_quote! { #ident.clone().into_iter() },
+                })
+            }
+            (HirExpr::Var(name), None) => {
+                let collection = iter.to_rust_expr(self.ctx)?;
+                let consumed = self.ctx.can_move(name);
+                Ok(match self.ctx.var_types.get(name) {
+                    Some(Type::String) => parse_quote! { #collection.chars().map(String::from) },
+                    Some(Type::Dict(_, _)) if lent => parse_quote! { #collection.keys() },
+                    Some(Type::Dict(_, _)) if consumed => {
+                        parse_quote! { #collection.into_keys() }
+                    }
+                    Some(Type::Dict(_, _)) => parse_quote! { #collection.keys().cloned() },
+                    _ if lent => parse_quote! { #collection.iter() },
+                    _ if consumed => parse_quote! { #collection.into_iter() },
+                    _ => parse_quote! { #collection.iter().cloned() },
+                })
+            }
+            (HirExpr::Attribute { value, attr }, _) if is_self(value) => {
+                let attr_ident = syn::Ident::new(attr, proc_macro2::Span::call_site());
+                let cloneable = self
+                    .ctx
+                    .class_fields
+                    .get(attr)
+                    .is_none_or(|field| self.can_clone(&field.field_type));
+                Ok(if cloneable {
+                    parse_quote! { self.#attr_ident.iter().cloned() }
+                } else {
+                    parse_quote! { self.#attr_ident.iter() }
+                })
+            }
+            _ if string_typed(iter, self.ctx) => {
+                let collection = builtins::operand_expr(&iter.to_rust_expr(self.ctx)?);
+                Ok(parse_quote! { #collection.chars().map(String::from) })
+            }
+            _ => {
+                let collection = iter.to_rust_expr(self.ctx)?;
+                Ok(parse_quote! { #collection.into_iter() })
+            }
+        }
+    }
+
     fn convert_borrow(&mut self, expr: &HirExpr, mutable: bool) -> Result<syn::Expr> {
         let expr_tokens = expr.to_rust_expr(self.ctx)?;
         if mutable {
@@ -468,11 +7281,6 @@ impl<'a, 'b> ExpressionConverter<'a, 'b> {
             Ok(parse_quote! { &#expr_tokens })
         }
     }
-
-    /// Check if an expression is a len() call
-    fn is_len_call(&self, expr: &HirExpr) -> bool {
-        matches!(expr, HirExpr::Call { func, args } if func == "len" && args.len() == 1)
-    }
 }
 
 impl ToRustExpr for HirExpr {
@@ -484,13 +7292,43 @@ impl ToRustExpr for HirExpr {
             HirExpr::Var(name) => converter.convert_variable(name),
             HirExpr::Binary { op, left, right } => converter.convert_binary(*op, left, right),
             HirExpr::Unary { op, operand } => converter.convert_unary(op, operand),
-            HirExpr::Call { func, args } => converter.convert_call(func, args),
+            HirExpr::Call { func, args, kwargs } => {
+                let call = converter.convert_call(func, args, kwargs)?;
+                propagate_call_error(self, call, ctx)
+            }
+            HirExpr::MethodCall {
+                receiver,
+                method,
+                args,
+                kwargs,
+            } => {
+                let call = converter.convert_method_call(receiver, method, args, kwargs)?;
+                propagate_call_error(self, call, ctx)
+            }
             HirExpr::Index { base, index } => converter.convert_index(base, index),
+            HirExpr::Slice {
+                base,
+                start,
+                stop,
+                step,
+            } => converter.convert_slice(base, start.as_deref(), stop.as_deref(), step.as_deref()),
             HirExpr::List(elts) => converter.convert_list(elts),
             HirExpr::Dict(items) => converter.convert_dict(items),
+            HirExpr::Set(elts) => converter.convert_set(elts),
             HirExpr::Tuple(elts) => converter.convert_tuple(elts),
+            HirExpr::FString { parts, values } => converter.convert_fstring(parts, values),
             HirExpr::Attribute { value, attr } => converter.convert_attribute(value, attr),
             HirExpr::Borrow { expr, mutable } => converter.convert_borrow(expr, *mutable),
+            HirExpr::Await { value } => converter.convert_await(value),
+            HirExpr::Lambda { params, body } => {
+                generate_lambda(params, body, false, converter.ctx).map(|(closure, _)| closure)
+            }
+            HirExpr::Comprehension {
+                kind,
+                element,
+                value,
+                generators,
+            } => converter.convert_comprehension(*kind, element, value.as_deref(), generators),
         }
     }
 }
@@ -501,8 +7339,9 @@ fn literal_to_rust_expr(lit: &Literal) -> syn::Expr {
             let lit = syn::LitInt::new(&n.to_string(), proc_macro2::Span::call_site());
             parse_quote! { #lit }
         }
+        // `Debug` keeps the `.0` of a whole number
         Literal::Float(f) => {
-            let lit = syn::LitFloat::new(&f.to_string(), proc_macro2::Span::call_site());
+            let lit = syn::LitFloat::new(&format!("{f:?}"), proc_macro2::Span::call_site());
             parse_quote! { #lit }
         }
         Literal::String(s) => {
@@ -530,7 +7369,9 @@ fn convert_binop(op: BinOp) -> Result<syn::BinOp> {
 
         // Special arithmetic cases
         FloorDiv => Ok(parse_quote! { / }), // TODO: Handle floor division properly
-        Pow => bail!("Power operator not directly supported in Rust"),
+        Pow => transpile_bail!(ErrorKind::UnsupportedFeature(
+            "Power operator not directly supported in Rust".to_string(),
+        )),
 
         // Comparison operators
         Eq => Ok(parse_quote! { == }),
@@ -552,7 +7393,9 @@ fn convert_binop(op: BinOp) -> Result<syn::BinOp> {
         RShift => Ok(parse_quote! { >> }),
 
         // Special membership operators handled in convert_binary
-        In | NotIn => bail!("in/not in operators should be handled by convert_binary"),
+        In | NotIn => transpile_bail!(ErrorKind::InternalError(
+            "in/not in operators should be handled by convert_binary".to_string(),
+        )),
     }
 }
 
@@ -581,11 +7424,23 @@ fn rust_type_to_syn(rust_type: &crate::type_mapper::RustType) -> Result<syn::Typ
             let inner_ty = rust_type_to_syn(inner)?;
             parse_quote! { Vec<#inner_ty> }
         }
+        RustType::Slice(inner) => {
+            let inner_ty = rust_type_to_syn(inner)?;
+            parse_quote! { [#inner_ty] }
+        }
         RustType::HashMap(k, v) => {
             let key_ty = rust_type_to_syn(k)?;
             let val_ty = rust_type_to_syn(v)?;
             parse_quote! { HashMap<#key_ty, #val_ty> }
         }
+        RustType::HashSet(inner) => {
+            let inner_ty = rust_type_to_syn(inner)?;
+            parse_quote! { HashSet<#inner_ty> }
+        }
+        RustType::BTreeSet(inner) => {
+            let inner_ty = rust_type_to_syn(inner)?;
+            parse_quote! { BTreeSet<#inner_ty> }
+        }
         RustType::Option(inner) => {
             let inner_ty = rust_type_to_syn(inner)?;
             parse_quote! { Option<#inner_ty> }
@@ -627,14 +7482,98 @@ fn rust_type_to_syn(rust_type: &crate::type_mapper::RustType) -> Result<syn::Typ
             let ty: syn::Type = syn::parse_str(name)?;
             ty
         }
-        RustType::Unsupported(reason) => bail!("Unsupported Rust type: {}", reason),
+        RustType::Function { params, ret } => {
+            let bound = closure_bound(ClosureKind::Fn, params, ret)?;
+            parse_quote! { Box<dyn #bound> }
+        }
+        RustType::Unsupported(reason) => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "Unsupported Rust type: {}",
+            reason
+        ),)),
     })
 }
 
+/// The `Fn(A, B) -> R` bound of a closure type
+fn closure_bound(
+    kind: ClosureKind,
+    params: &[crate::type_mapper::RustType],
+    ret: &crate::type_mapper::RustType,
+) -> Result<proc_macro2::TokenStream> {
+    let trait_ident = syn::Ident::new(kind.trait_name(), proc_macro2::Span::call_site());
+    let params = params
+        .iter()
+        .map(rust_type_to_syn)
+        .collect::<Result<Vec<_>>>()?;
+    let ret = match ret {
+        crate::type_mapper::RustType::Unit => quote! {},
+        ret => {
+            let ty = rust_type_to_syn(ret)?;
+            quote! { -> #ty }
+        }
+    };
+    Ok(quote! { #trait_ident(#(#params),*) #ret })
+}
+
 /// Format Rust code using basic prettification
 /// TODO: Replace with proper rustfmt integration
 fn format_rust_code(code: String) -> String {
-    code.replace(" ; ", ";\n    ")
+    // The closing brace of a body ending in a tail expression
+    let code = match code.strip_suffix(" }") {
+        Some(code) => format!("{code}\n}}"),
+        None => code,
+    };
+    let (code, strings) = hide_strings(&code);
+    let mut formatted = String::with_capacity(code.len());
+    for (i, part) in format_tokens(code).split('\0').enumerate() {
+        if i > 0 {
+            formatted.push_str(&strings[i - 1]);
+        }
+        formatted.push_str(part);
+    }
+    formatted
+}
+
+/// `code` with each string literal replaced by a NUL, which the literals
+/// cannot contain, so formatting leaves their text alone; and the literals
+fn hide_strings(code: &str) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(code.len());
+    let mut strings = Vec::new();
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // The character literals `'"'` and `'\"'`
+            '\'' => {
+                out.push(c);
+                if let Some(next @ ('"' | '\\')) = chars.clone().next() {
+                    out.push(next);
+                    chars.next();
+                    if next == '\\' {
+                        out.extend(chars.next());
+                    }
+                }
+            }
+            '"' => {
+                let mut literal = String::from(c);
+                while let Some(c) = chars.next() {
+                    literal.push(c);
+                    match c {
+                        '\\' => literal.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+                strings.push(literal);
+                out.push('\0');
+            }
+            _ => out.push(c),
+        }
+    }
+    (out, strings)
+}
+
+fn format_tokens(code: String) -> String {
+    let code = code
+        .replace(" ; ", ";\n    ")
         .replace(" { ", " {\n    ")
         .replace(" } ", "\n}\n")
         .replace("} ;", "};")
@@ -646,6 +7585,9 @@ fn format_rust_code(code: String) -> String {
         .replace(" . ", ".")
         .replace(" (", "(")
         .replace(" )", ")")
+        // Fix error propagation spacing
+        .replace(") ?", ")?")
+        .replace(".await ?", ".await?")
         // Fix specific common patterns
         .replace(".len ()", ".len()")
         .replace(".push (", ".push(")
@@ -656,19 +7598,26 @@ fn format_rust_code(code: String) -> String {
         // Fix spacing around operators in some contexts
         .replace(" ::", "::")
         .replace(":: ", "::")
+        // Fix method receiver spacing
+        .replace("& mut self", "&mut self")
+        .replace("& self", "&self")
         // Fix attribute spacing
         .replace("# [", "#[")
         // Fix type annotations
         .replace(" : ", ": ")
+        // Fix borrowed parameter and return types
+        .replace(": & ", ": &")
+        .replace("-> & ", "-> &")
+        .replace("& 'a ", "&'a ")
         // Fix parameter spacing
-        .replace(" , ", ", ")
-        // Fix assignment operator spacing issues
-        .replace("=(", " = (")
-        .replace("= (", " = (")
+        .replace(" , ", ", ");
+    // Fix assignment operator spacing issues
+    space_assignments(&code)
         .replace("  =", " =") // Fix multiple spaces before =
         .replace("   =", " =") // Fix even more spaces
         // Fix generic type spacing
         .replace("Vec < ", "Vec<")
+        .replace("Result <", "Result<")
         .replace(" < ", "<")
         .replace(" > ", ">")
         .replace("> ", ">")
@@ -683,16 +7632,59 @@ fn format_rust_code(code: String) -> String {
         .replace(" ..", "..")
         .replace(".. ", "..")
         // Fix 'in' keyword spacing
-        .replace("in(", "in (")
+        .replace(" in(", " in (")
         .replace(";\n    }", "\n}")
 }
 
+/// Space an `=` from the parenthesized value after it, leaving operators
+/// ending in `=`, such as `!=` and `+=`, whole
+fn space_assignments(code: &str) -> String {
+    let mut spaced = String::with_capacity(code.len());
+    let mut rest = code;
+    while let Some(at) = rest.find('=') {
+        spaced.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+        let value = after.trim_start_matches(' ');
+        if value.starts_with('(') {
+            let operator =
+                spaced.ends_with(['=', '!', '<', '>', '+', '-', '*', '/', '%', '&', '|', '^']);
+            spaced.push_str(if operator { "= " } else { " = " });
+            rest = value;
+        } else {
+            spaced.push('=');
+            rest = after;
+        }
+    }
+    spaced.push_str(rest);
+    spaced
+}
+
 /// Updates the import needs based on the rust type being used
 fn update_import_needs(ctx: &mut CodeGenContext, rust_type: &crate::type_mapper::RustType) {
     match rust_type {
         crate::type_mapper::RustType::HashMap(_, _) => ctx.needs_hashmap = true,
+        crate::type_mapper::RustType::HashSet(inner) => {
+            ctx.use_set(SetType::Std);
+            update_import_needs(ctx, inner);
+        }
+        crate::type_mapper::RustType::BTreeSet(inner) => {
+            ctx.use_set(SetType::BTree);
+            update_import_needs(ctx, inner);
+        }
         crate::type_mapper::RustType::Cow { .. } => ctx.needs_cow = true,
         crate::type_mapper::RustType::Custom(name) => {
+            if name.contains(bigint::BIGINT) {
+                ctx.needs_bigint = true;
+            }
+            let std_sets = name.replace("FnvHashSet<", "").replace("AHashSet<", "");
+            for set_type in [SetType::Fnv, SetType::AHash, SetType::BTree] {
+                if name.contains(&format!("{}<", set_type.name())) {
+                    ctx.use_set(set_type);
+                }
+            }
+            if std_sets.contains("HashSet<") {
+                ctx.use_set(SetType::Std);
+            }
             if name.contains("FnvHashMap") {
                 ctx.needs_fnv_hashmap = true;
             } else if name.contains("AHashMap") {
@@ -711,7 +7703,7 @@ fn update_import_needs(ctx: &mut CodeGenContext, rust_type: &crate::type_mapper:
         crate::type_mapper::RustType::Reference { inner, .. } => {
             update_import_needs(ctx, inner);
         }
-        crate::type_mapper::RustType::Vec(inner) => {
+        crate::type_mapper::RustType::Vec(inner) | crate::type_mapper::RustType::Slice(inner) => {
             update_import_needs(ctx, inner);
         }
         crate::type_mapper::RustType::Option(inner) => {
@@ -726,6 +7718,12 @@ fn update_import_needs(ctx: &mut CodeGenContext, rust_type: &crate::type_mapper:
                 update_import_needs(ctx, t);
             }
         }
+        crate::type_mapper::RustType::Function { params, ret } => {
+            for t in params {
+                update_import_needs(ctx, t);
+            }
+            update_import_needs(ctx, ret);
+        }
         _ => {}
     }
 }
@@ -750,7 +7748,44 @@ mod tests {
             needs_arc: false,
             needs_rc: false,
             needs_cow: false,
+            needs_hashset: false,
+            set_types: BTreeSet::new(),
+            needs_bigint: false,
+            num_traits: BTreeSet::new(),
+            arithmetic_helpers: BTreeSet::new(),
+            string_helpers: BTreeSet::new(),
             declared_vars: vec![HashSet::new()],
+            class_names: HashSet::new(),
+            class_fields: HashMap::new(),
+            current_class: None,
+            exceptions: ExceptionTable::new(),
+            error_variants: vec![],
+            fallible_calls: HashSet::new(),
+            best_effort: false,
+            skipped: vec![],
+            error_scope: ErrorScope::default(),
+            ints: IntScope::default(),
+            set_type: SetType::default(),
+            big_int_functions: HashMap::new(),
+            ret_type: Type::Unknown,
+            return_types: HashMap::new(),
+            enum_params: HashMap::new(),
+            borrowed_vars: HashSet::new(),
+            guarded_classes: HashMap::new(),
+            var_types: HashMap::new(),
+            int_signs: HashMap::new(),
+            file_vars: HashSet::new(),
+            guard_vars: HashMap::new(),
+            enums: HashMap::new(),
+            class_defs: HashMap::new(),
+            kwargs_structs: HashMap::new(),
+            closures: ClosureScope::default(),
+            borrows: BorrowTable::default(),
+            borrow_scope: BorrowScope::default(),
+            mutating_methods: HashSet::new(),
+            live: LiveScope::default(),
+            source_markers: false,
+            module_aliases: HashMap::new(),
         }
     }
 
@@ -759,15 +7794,19 @@ mod tests {
         let func = HirFunction {
             name: "add".to_string(),
             params: vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)].into(),
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Add,
                 left: Box::new(HirExpr::Var("a".to_string())),
                 right: Box::new(HirExpr::Var("b".to_string())),
-            }))],
+            }))
+            .into()],
             properties: FunctionProperties::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Span::default(),
         };
 
         let mut ctx = create_test_context();
@@ -776,7 +7815,8 @@ mod tests {
 
         assert!(code.contains("pub fn add"));
         assert!(code.contains("i32"));
-        assert!(code.contains("return"));
+        // The returned value is the body's tail expression
+        assert!(code.contains("{ a + b }"));
     }
 
     #[test]
@@ -789,10 +7829,12 @@ mod tests {
             },
             then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::String(
                 "positive".to_string(),
-            ))))],
+            ))))
+            .into()],
             else_body: Some(vec![HirStmt::Return(Some(HirExpr::Literal(
                 Literal::String("negative".to_string()),
-            )))]),
+            )))
+            .into()]),
         };
 
         let mut ctx = create_test_context();
@@ -859,4 +7901,1045 @@ mod tests {
         assert!(convert_binop(BinOp::In).is_err());
         assert!(convert_binop(BinOp::NotIn).is_err());
     }
+
+    fn counter_class() -> HirClass {
+        HirClass {
+            name: "Counter".to_string(),
+            base_classes: vec![],
+            fields: vec![HirField {
+                name: "count".to_string(),
+                field_type: Type::Int,
+                default_value: None,
+                is_class_var: false,
+            }],
+            methods: vec![HirMethod {
+                name: "incr".to_string(),
+                params: smallvec::smallvec![],
+                param_spec: Default::default(),
+                ret_type: Type::None,
+                body: vec![HirStmt::Assign {
+                    target: AssignTarget::Attribute {
+                        value: Box::new(HirExpr::Var("self".to_string())),
+                        attr: "count".to_string(),
+                    },
+                    value: HirExpr::Literal(Literal::Int(1)),
+                }
+                .into()],
+                kind: MethodKind::Instance,
+                properties: FunctionProperties::default(),
+                annotations: TranspilationAnnotations::default(),
+                docstring: None,
+                is_async: false,
+                span: Span::default(),
+            }],
+            is_dataclass: true,
+            annotations: TranspilationAnnotations::default(),
+            docstring: None,
+        }
+    }
+
+    #[test]
+    fn test_dataclass_generation() {
+        let mut ctx = create_test_context();
+        let tokens = counter_class().to_rust_tokens(&mut ctx).unwrap();
+        let code = tokens.to_string();
+
+        assert!(code.contains("derive (Debug , Clone , PartialEq , Eq , Hash)"));
+        assert!(code.contains("pub struct Counter"));
+        assert!(code.contains("pub count : i32"));
+        assert!(code.contains("pub fn new (count : i32) -> Self"));
+        assert!(code.contains("pub fn incr (& mut self)"));
+        assert!(code.contains("self . count = 1"));
+    }
+
+    #[test]
+    fn test_properties_and_field_defaults_run() {
+        let python = r#"
+from dataclasses import dataclass, field
+from typing import Dict, List, Set
+
+class Circle:
+    def __init__(self, r: int):
+        self.r = r
+
+    @property
+    def diameter(self) -> int:
+        return self.r * 2
+
+    def twice(self) -> int:
+        return self.diameter * 2
+
+@dataclass
+class Bag:
+    name: str
+    items: List[int] = field(default_factory=list)
+    tags: Dict[str, int] = field(default_factory=dict)
+    seen: Set[int] = field(default_factory=set)
+    pair: List[int] = field(default_factory=lambda: [1, 2])
+    size: int = field(default=5)
+
+def area(c: Circle) -> int:
+    return c.diameter * c.r
+
+def bag() -> int:
+    b = Bag("x")
+    b.items.append(3)
+    return len(b.items) + len(b.tags) + len(b.seen) + len(b.pair) + b.size
+"#;
+        let main = r#"let c = Circle::new(3);
+println!("{} {} {} {}", c.diameter(), c.twice(), area(c), bag());"#;
+        assert_eq!(crate::test_programs::run(python, main), "6 12 18 8\n");
+    }
+
+    #[test]
+    fn test_float_fields_skip_eq_hash() {
+        let mut class = counter_class();
+        class.fields[0].field_type = Type::Float;
+
+        let mut ctx = create_test_context();
+        let code = class.to_rust_tokens(&mut ctx).unwrap().to_string();
+        assert!(code.contains("derive (Debug , Clone , PartialEq)"));
+    }
+
+    #[test]
+    fn test_class_constructor_call() {
+        let mut ctx = create_test_context();
+        ctx.class_names.insert("Counter".to_string());
+        let call = HirExpr::Call {
+            func: "Counter".to_string(),
+            args: vec![HirExpr::Literal(Literal::Int(0))],
+            kwargs: vec![],
+        };
+        let expr = call.to_rust_expr(&mut ctx).unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "Counter :: new (0)");
+    }
+
+    fn method_call(receiver: &str, method: &str, args: Vec<HirExpr>) -> HirExpr {
+        HirExpr::MethodCall {
+            receiver: Box::new(HirExpr::Var(receiver.to_string())),
+            method: method.to_string(),
+            args,
+            kwargs: vec![],
+        }
+    }
+
+    #[test]
+    fn test_builtin_method_calls() {
+        let mut ctx = create_test_context();
+        let cases = vec![
+            (
+                method_call("items", "append", vec![HirExpr::Literal(Literal::Int(1))]),
+                "items . push (1)",
+            ),
+            (
+                method_call("s", "strip", vec![]),
+                "s . trim () . to_string ()",
+            ),
+            (
+                method_call(
+                    "d",
+                    "get",
+                    vec![
+                        HirExpr::Var("k".to_string()),
+                        HirExpr::Literal(Literal::Int(0)),
+                    ],
+                ),
+                "d . get (& k) . cloned () . unwrap_or (0)",
+            ),
+            (
+                method_call("obj", "custom", vec![HirExpr::Var("x".to_string())]),
+                "obj . custom (x)",
+            ),
+        ];
+
+        for (expr, expected) in cases {
+            let result = expr.to_rust_expr(&mut ctx).unwrap();
+            assert_eq!(quote! { #result }.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_static_method_call_on_class() {
+        let mut ctx = create_test_context();
+        ctx.class_names.insert("Counter".to_string());
+        let expr = method_call("Counter", "zero", vec![]);
+        let result = expr.to_rust_expr(&mut ctx).unwrap();
+        assert_eq!(quote! { #result }.to_string(), "Counter :: zero ()");
+    }
+
+    fn clause(target: &str, iter: HirExpr, conditions: Vec<HirExpr>) -> ComprehensionClause {
+        ComprehensionClause {
+            target: target.to_string(),
+            iter,
+            conditions,
+        }
+    }
+
+    #[test]
+    fn test_list_comprehension_generation() {
+        let mut ctx = create_test_context();
+        let comp = HirExpr::Comprehension {
+            kind: ComprehensionKind::List,
+            element: Box::new(HirExpr::Binary {
+                op: BinOp::Mul,
+                left: Box::new(HirExpr::Var("x".to_string())),
+                right: Box::new(HirExpr::Var("x".to_string())),
+            }),
+            value: None,
+            generators: vec![clause(
+                "x",
+                HirExpr::Var("xs".to_string()),
+                vec![HirExpr::Binary {
+                    op: BinOp::Gt,
+                    left: Box::new(HirExpr::Var("x".to_string())),
+                    right: Box::new(HirExpr::Literal(Literal::Int(0))),
+                }],
+            )],
+        };
+        let expr = comp.to_rust_expr(&mut ctx).unwrap();
+        assert_eq!(
+            quote! { #expr }.to_string(),
+            "xs . iter () . cloned () . filter (| x | (* x) > 0) . map (| x | x * x) . collect :: < Vec < _ > > ()"
+        );
+    }
+
+    #[test]
+    fn test_set_comprehension_consumes_temporaries() {
+        let mut ctx = create_test_context();
+        let comp = HirExpr::Comprehension {
+            kind: ComprehensionKind::Set,
+            element: Box::new(HirExpr::Var("w".to_string())),
+            value: None,
+            generators: vec![clause("w", method_call("s", "split", vec![]), vec![])],
+        };
+        let expr = comp.to_rust_expr(&mut ctx).unwrap();
+        let code = quote! { #expr }.to_string();
+        assert!(code.contains(") . into_iter () . collect :: < HashSet < _ > > ()"));
+        assert!(ctx.needs_hashset);
+    }
+
+    #[test]
+    fn test_nested_comprehension_generation() {
+        let mut ctx = create_test_context();
+        let comp = HirExpr::Comprehension {
+            kind: ComprehensionKind::Dict,
+            element: Box::new(HirExpr::Var("row".to_string())),
+            value: Some(Box::new(HirExpr::Var("v".to_string()))),
+            generators: vec![
+                clause("row", HirExpr::Var("rows".to_string()), vec![]),
+                clause("v", HirExpr::Var("row".to_string()), vec![]),
+            ],
+        };
+        let expr = comp.to_rust_expr(&mut ctx).unwrap();
+        let code = quote! { #expr }.to_string();
+        assert!(code.contains(
+            "flat_map (| row | row . clone () . into_iter () . map (move | v | (row . clone () , v)))"
+        ));
+        assert!(code.contains(". map (| (row , v) | (row . clone () , v))"));
+        assert!(code.ends_with("collect :: < HashMap < _ , _ > > ()"));
+        assert!(ctx.needs_hashmap);
+
+        // Typed rows only read by the element are borrowed, not cloned
+        let rows = Type::List(Box::new(Type::List(Box::new(Type::String))));
+        ctx.var_types.insert("rows".to_string(), rows);
+        let comp = HirExpr::Comprehension {
+            kind: ComprehensionKind::List,
+            element: Box::new(HirExpr::MethodCall {
+                receiver: Box::new(HirExpr::Var("v".to_string())),
+                method: "upper".to_string(),
+                args: vec![],
+                kwargs: vec![],
+            }),
+            value: None,
+            generators: vec![
+                clause("row", HirExpr::Var("rows".to_string()), vec![]),
+                clause("v", HirExpr::Var("row".to_string()), vec![]),
+            ],
+        };
+        let expr = comp.to_rust_expr(&mut ctx).unwrap();
+        let code = quote! { #expr }.to_string();
+        assert!(code.starts_with(
+            "rows . iter () . flat_map (| row | row . iter () . map (move | v | (row , v)))"
+        ));
+        assert!(!code.contains("clone"));
+    }
+
+    #[test]
+    fn test_borrowing_comprehensions_run() {
+        let python = r#"
+from typing import Callable, Dict, List
+
+def upper_all(xs: List[str]) -> List[str]:
+    return [x.upper() for x in xs]
+
+def flatten(m: List[List[int]]) -> List[int]:
+    return [v for row in m for v in row]
+
+def rows(m: List[List[str]]) -> List[List[str]]:
+    return [row for row in m for s in row if s != "a"]
+
+def pairs(xs: List[str], n: int) -> List[str]:
+    return [x.lower() for x in xs for i in range(n) if i > 0]
+
+def keys_of(d: Dict[str, int]) -> List[str]:
+    return sorted([k.lower() for k in d])
+
+def apply_all(fs: List[Callable[[int], int]], xs: List[int]) -> List[int]:
+    return [f(x) for f in fs for x in xs]
+
+class Pipeline:
+    def __init__(self, steps: List[Callable[[int], int]]):
+        self.steps = steps
+
+    def run(self, x: int) -> List[int]:
+        return [step(x) for step in self.steps]
+"#;
+        let main = r#"let words = vec!["a".to_string(), "Bc".to_string()];
+let m = vec![vec![1, 2], vec![3]];
+let s = vec![vec!["a".to_string()], vec!["a".to_string(), "b".to_string()]];
+let d = HashMap::from([("K".to_string(), 1)]);
+println!("{:?} {:?} {:?}", upper_all(&words), flatten(&m), rows(&s));
+println!("{:?} {:?}", pairs(&words, 3), keys_of(&d));
+let fs: Vec<Box<dyn Fn(i32) -> i32>> = vec![Box::new(|x| x + 1), Box::new(|x| x * 10)];
+println!("{:?} {:?}", apply_all(&fs, &[1, 2]), Pipeline::new(fs).run(4));"#;
+        assert_eq!(
+            crate::test_programs::run(python, main),
+            "[\"A\", \"BC\"] [1, 2, 3] [[\"a\", \"b\"]]\n\
+             [\"a\", \"a\", \"bc\", \"bc\"] [\"k\"]\n\
+             [2, 3, 10, 20] [5, 40]\n"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_operators_before_parentheses() {
+        let code = format_rust_code("let c = a != (b) ; let d = a == (b) ; x += (y) ;".to_string());
+        assert!(code.contains("let c = a != (b);"));
+        assert!(code.contains("let d = a == (b);"));
+        assert!(code.contains("x += (y)"));
+    }
+
+    fn raise(class: &str, message: &str) -> HirStmt {
+        HirStmt::Raise {
+            exception: Some(HirExpr::Call {
+                func: class.to_string(),
+                args: vec![HirExpr::Literal(Literal::String(message.to_string()))],
+                kwargs: vec![],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_raise_returns_err() {
+        let mut ctx = create_test_context();
+        ctx.error_scope.returns_result = true;
+        let tokens = raise("ValueError", "bad").to_rust_tokens(&mut ctx).unwrap();
+        assert_eq!(
+            tokens.to_string(),
+            "return Err (PythonError :: ValueError (\"bad\" . to_string ())) ;"
+        );
+
+        // Without a `Result` return type an uncaught error panics
+        ctx.error_scope.returns_result = false;
+        let tokens = raise("ValueError", "bad").to_rust_tokens(&mut ctx).unwrap();
+        assert!(tokens.to_string().starts_with("panic !"));
+    }
+
+    #[test]
+    fn test_try_lowers_to_match() {
+        let mut ctx = create_test_context();
+        ctx.error_variants = vec!["KeyError".to_string(), "ValueError".to_string()];
+        let stmt = HirStmt::Try {
+            body: vec![raise("KeyError", "missing").into()],
+            handlers: vec![ExceptHandler {
+                exception_types: vec!["LookupError".to_string()],
+                name: None,
+                body: vec![],
+            }],
+            orelse: vec![],
+            finalbody: vec![HirStmt::Expr(HirExpr::Call {
+                func: "cleanup".to_string(),
+                args: vec![],
+                kwargs: vec![],
+            })
+            .into()],
+        };
+        let code = stmt.to_rust_tokens(&mut ctx).unwrap().to_string();
+        assert!(code.contains("'try_0 : {"));
+        assert!(code.contains("break 'try_0 Err (PythonError :: KeyError"));
+        assert!(code.contains("Err (PythonError :: KeyError (_)) => { }"));
+        // Uncaught errors run the finally body before leaving
+        assert!(code.contains("Err (error) => { { let error = error ; cleanup () ;"));
+        assert!(code.ends_with("cleanup () ;"));
+    }
+
+    #[test]
+    fn test_try_whose_paths_all_return() {
+        let mut ctx = create_test_context();
+        ctx.error_variants = vec!["ValueError".to_string()];
+        let stmt = HirStmt::Try {
+            body: vec![raise("ValueError", "bad").into()],
+            handlers: vec![ExceptHandler {
+                exception_types: vec!["ValueError".to_string()],
+                name: None,
+                body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(0)))).into()],
+            }],
+            orelse: vec![],
+            finalbody: vec![HirStmt::Expr(HirExpr::Call {
+                func: "cleanup".to_string(),
+                args: vec![],
+                kwargs: vec![],
+            })
+            .into()],
+        };
+        let code = stmt.to_rust_tokens(&mut ctx).unwrap().to_string();
+        assert!(code.contains("Result < std :: convert :: Infallible , PythonError >"));
+        assert!(code.contains("Ok (never) => match never { }"));
+        // Every error is caught, and the handler's return runs `finally`
+        assert!(!code.contains("Err (error)"));
+        assert!(code.ends_with("cleanup () ; return result ; } } }"));
+    }
+
+    #[test]
+    fn test_emits_panic() {
+        assert!(emits_panic(&quote! { if x { panic!("{}", e); } }));
+        assert!(emits_panic(&quote! { v.first().expect("empty") }));
+        assert!(!emits_panic(&quote! { let expect = 1; expect! }));
+        assert!(!emits_panic(&quote! { Err(e)? }));
+    }
+
+    #[test]
+    fn test_fallible_call_propagation() {
+        let mut ctx = create_test_context();
+        ctx.fallible_calls.insert("parse".to_string());
+        let call = HirExpr::Call {
+            func: "parse".to_string(),
+            args: vec![HirExpr::Var("s".to_string())],
+            kwargs: vec![],
+        };
+
+        ctx.error_scope.returns_result = true;
+        let expr = call.to_rust_expr(&mut ctx).unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "parse (s) ?");
+
+        ctx.error_scope.returns_result = false;
+        let expr = call.to_rust_expr(&mut ctx).unwrap();
+        assert!(quote! { #expr }.to_string().starts_with("match parse (s)"));
+    }
+
+    #[test]
+    fn test_generator_state_machine() {
+        let mut ctx = create_test_context();
+        let func = HirFunction {
+            name: "count_up".to_string(),
+            params: smallvec::smallvec![("n".to_string(), Type::Int)],
+            param_spec: Default::default(),
+            ret_type: Type::Int,
+            body: vec![HirStmt::For {
+                target: AssignTarget::Symbol("i".to_string()),
+                iter: HirExpr::Call {
+                    func: "range".to_string(),
+                    args: vec![HirExpr::Var("n".to_string())],
+                    kwargs: vec![],
+                },
+                body: vec![HirStmt::Yield(Some(HirExpr::Var("i".to_string()))).into()],
+            }
+            .into()],
+            properties: FunctionProperties {
+                is_generator: true,
+                ..Default::default()
+            },
+            annotations: Default::default(),
+            docstring: None,
+            is_async: false,
+            span: Span::default(),
+        };
+
+        let code = func.to_rust_tokens(&mut ctx).unwrap().to_string();
+        assert!(code.contains("pub struct CountUpGenerator"));
+        assert!(code.contains("iter_0 : Option < Box < dyn Iterator < Item = i32 >> >"));
+        assert!(code.contains("impl Iterator for CountUpGenerator"));
+        assert!(code.contains("type Item = i32"));
+        assert!(code.contains("self . i = item"));
+        assert!(code.contains("return Some (self . i)"));
+        assert!(code.contains("pub fn count_up (n : i32) -> CountUpGenerator"));
+    }
+
+    #[test]
+    fn test_yield_outside_generator_is_rejected() {
+        let mut ctx = create_test_context();
+        assert!(HirStmt::Yield(None).to_rust_tokens(&mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_await_generation() {
+        let mut ctx = create_test_context();
+        ctx.fallible_calls.insert("fetch".to_string());
+        ctx.error_scope.returns_result = true;
+        let awaited = HirExpr::Await {
+            value: Box::new(HirExpr::Call {
+                func: "fetch".to_string(),
+                args: vec![],
+                kwargs: vec![],
+            }),
+        };
+        let expr = awaited.to_rust_expr(&mut ctx).unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "fetch () . await ?");
+
+        let sleep = HirExpr::Await {
+            value: Box::new(method_call(
+                "asyncio",
+                "sleep",
+                vec![HirExpr::Literal(Literal::Float(0.5))],
+            )),
+        };
+        let expr = sleep.to_rust_expr(&mut ctx).unwrap();
+        assert_eq!(
+            quote! { #expr }.to_string(),
+            "tokio :: time :: sleep (std :: time :: Duration :: from_secs_f64 (0.5 as f64)) . await"
+        );
+
+        let gather = HirExpr::Await {
+            value: Box::new(method_call(
+                "asyncio",
+                "gather",
+                vec![HirExpr::Var("a".to_string()), HirExpr::Var("b".to_string())],
+            )),
+        };
+        let expr = gather.to_rust_expr(&mut ctx).unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "tokio :: join ! (a , b)");
+    }
+
+    #[test]
+    fn test_with_generation() {
+        let mut ctx = create_test_context();
+        ctx.var_types
+            .insert("lock".to_string(), Type::Custom("Lock".to_string()));
+        let with_lock = HirStmt::With {
+            items: vec![WithItem {
+                context_expr: HirExpr::Var("lock".to_string()),
+                optional_vars: None,
+            }],
+            body: vec![HirStmt::Assign {
+                target: AssignTarget::Symbol("total".to_string()),
+                value: HirExpr::Literal(Literal::Int(1)),
+            }
+            .into()],
+        };
+        let tokens = with_lock.to_rust_tokens(&mut ctx).unwrap();
+        assert_eq!(
+            tokens.to_string(),
+            "let mut total ; { let _guard = lock . lock () . unwrap () ; total = 1 ; }"
+        );
+
+        let with_file = HirStmt::With {
+            items: vec![WithItem {
+                context_expr: HirExpr::Call {
+                    func: "open".to_string(),
+                    args: vec![
+                        HirExpr::Var("path".to_string()),
+                        HirExpr::Literal(Literal::String("a".to_string())),
+                    ],
+                    kwargs: vec![],
+                },
+                optional_vars: Some("log".to_string()),
+            }],
+            body: vec![HirStmt::Expr(method_call(
+                "log",
+                "write",
+                vec![HirExpr::Var("line".to_string())],
+            ))
+            .into()],
+        };
+        let code = with_file.to_rust_tokens(&mut ctx).unwrap().to_string();
+        assert!(code.contains(
+            "std :: fs :: OpenOptions :: new () . append (true) . create (true) . open (& path)"
+        ));
+        assert!(code.contains("std :: io :: Write :: write_all (& mut log , line . as_bytes ())"));
+        // The file binding ends with the block
+        assert!(ctx.file_vars.is_empty());
+    }
+
+    #[test]
+    fn test_assign_target_generation() {
+        let mut ctx = create_test_context();
+        let sym = |name: &str| AssignTarget::Symbol(name.to_string());
+        let var = |name: &str| HirExpr::Var(name.to_string());
+        let swap = |ctx: &mut CodeGenContext| {
+            HirStmt::Assign {
+                target: AssignTarget::Tuple(vec![sym("a"), sym("b")]),
+                value: HirExpr::Tuple(vec![var("b"), var("a")]),
+            }
+            .to_rust_tokens(ctx)
+            .unwrap()
+            .to_string()
+        };
+        assert_eq!(swap(&mut ctx), "let (mut a , mut b) = (b , a) ;");
+        assert_eq!(swap(&mut ctx), "(a , b) = (b , a) ;");
+
+        ctx.var_types.insert(
+            "counts".to_string(),
+            Type::Dict(Box::new(Type::String), Box::new(Type::Int)),
+        );
+        let entry = HirStmt::Assign {
+            target: AssignTarget::Index {
+                base: Box::new(var("counts")),
+                index: Box::new(var("word")),
+            },
+            value: HirExpr::Literal(Literal::Int(1)),
+        };
+        assert_eq!(
+            entry.to_rust_tokens(&mut ctx).unwrap().to_string(),
+            "counts . insert (word , 1) ;"
+        );
+        let element = HirStmt::Assign {
+            target: AssignTarget::Tuple(vec![
+                sym("c"),
+                AssignTarget::Index {
+                    base: Box::new(var("xs")),
+                    index: Box::new(HirExpr::Literal(Literal::Int(0))),
+                },
+            ]),
+            value: var("pair"),
+        };
+        assert_eq!(
+            element.to_rust_tokens(&mut ctx).unwrap().to_string(),
+            "let mut c ; (c , xs [0]) = pair ;"
+        );
+
+        let for_pairs = HirStmt::For {
+            target: AssignTarget::Tuple(vec![sym("k"), sym("v")]),
+            iter: var("pairs"),
+            body: vec![],
+        };
+        assert_eq!(
+            for_pairs.to_rust_tokens(&mut ctx).unwrap().to_string(),
+            "for (_k , _v) in pairs { }"
+        );
+    }
+
+    #[test]
+    fn test_match_generation() {
+        let mut ctx = create_test_context();
+        ctx.var_types.insert("n".to_string(), Type::Int);
+        let int_case = |n, body: &str| MatchCase {
+            pattern: Pattern::Value(HirExpr::Literal(Literal::Int(n))),
+            guard: None,
+            body: vec![
+                HirStmt::Return(Some(HirExpr::Literal(Literal::String(body.to_string())))).into(),
+            ],
+        };
+        let match_int = HirStmt::Match {
+            subject: HirExpr::Var("n".to_string()),
+            cases: vec![int_case(0, "zero"), int_case(1, "one")],
+        };
+        let code = match_int.to_rust_tokens(&mut ctx).unwrap().to_string();
+        assert!(code.starts_with("match n { 0 => {"));
+        assert!(code.ends_with("# [allow (unreachable_patterns)] _ => { } }"));
+
+        let color = Type::Custom("Color".to_string());
+        ctx.enums.insert(
+            "Color".to_string(),
+            vec!["RED".to_string(), "GREEN".to_string(), "BLUE".to_string()],
+        );
+        ctx.var_types.insert("c".to_string(), color);
+        let member = |name: &str| {
+            Pattern::Value(HirExpr::Attribute {
+                value: Box::new(HirExpr::Var("Color".to_string())),
+                attr: name.to_string(),
+            })
+        };
+        let mut cases = vec![
+            MatchCase {
+                pattern: member("RED"),
+                guard: None,
+                body: vec![HirStmt::Return(None).into()],
+            },
+            MatchCase {
+                pattern: Pattern::Capture {
+                    name: "other".to_string(),
+                    pattern: Some(Box::new(member("GREEN"))),
+                },
+                guard: None,
+                body: vec![HirStmt::Return(None).into()],
+            },
+        ];
+        let match_enum = |cases: Vec<MatchCase>| HirStmt::Match {
+            subject: HirExpr::Var("c".to_string()),
+            cases,
+        };
+        let err = match_enum(cases.clone())
+            .to_rust_tokens(&mut ctx)
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Non-exhaustive match on enum 'Color': BLUE not covered"));
+
+        cases.push(MatchCase {
+            pattern: member("BLUE"),
+            guard: None,
+            body: vec![HirStmt::Return(None).into()],
+        });
+        let code = match_enum(cases)
+            .to_rust_tokens(&mut ctx)
+            .unwrap()
+            .to_string();
+        assert!(code.contains("Color :: RED =>"));
+        assert!(code.contains("other @ Color :: GREEN =>"));
+        assert!(!code.contains("_ =>"));
+    }
+
+    #[test]
+    fn test_kwargs_struct_generation() {
+        let mut ctx = create_test_context();
+        let spec = ParamSpec {
+            var_keyword: Some(("style".to_string(), Type::String)),
+            kwargs_fields: vec!["color".to_string(), "width".to_string()],
+            ..ParamSpec::default()
+        };
+        let item = generate_kwargs_struct(
+            "PlotKwargs",
+            &spec,
+            &depyler_annotations::TranspilationAnnotations::default(),
+            &mut ctx,
+        )
+        .unwrap();
+        assert_eq!(
+            item.to_string(),
+            "# [derive (Debug , Clone , Default)] pub struct PlotKwargs { \
+             pub color : Option < String > , pub width : Option < String > , }"
+        );
+
+        ctx.kwargs_structs
+            .insert("PlotKwargs".to_string(), spec.kwargs_fields.clone());
+        ctx.var_types
+            .insert("style".to_string(), Type::Custom("PlotKwargs".to_string()));
+        let string = |s: &str| HirExpr::Literal(Literal::String(s.to_string()));
+        let style = || Box::new(HirExpr::Var("style".to_string()));
+        let expr = |e: HirExpr, ctx: &mut CodeGenContext| {
+            let e = e.to_rust_expr(ctx).unwrap();
+            quote! { #e }.to_string()
+        };
+
+        let packed = HirExpr::Call {
+            func: "PlotKwargs".to_string(),
+            args: vec![],
+            kwargs: vec![("color".to_string(), string("red"))],
+        };
+        assert_eq!(
+            expr(packed, &mut ctx),
+            "PlotKwargs { color : Some (\"red\" . to_string ()) , .. Default :: default () }"
+        );
+        let get = HirExpr::MethodCall {
+            receiver: style(),
+            method: "get".to_string(),
+            args: vec![string("color"), string("black")],
+            kwargs: vec![],
+        };
+        assert_eq!(
+            expr(get, &mut ctx),
+            "style . color . clone () . unwrap_or (\"black\" . to_string ())"
+        );
+        let contains = HirExpr::Binary {
+            op: BinOp::In,
+            left: Box::new(string("width")),
+            right: style(),
+        };
+        assert_eq!(expr(contains, &mut ctx), "style . width . is_some ()");
+
+        let unresolved = HirExpr::Call {
+            func: "sorted".to_string(),
+            args: vec![],
+            kwargs: vec![("reverse".to_string(), HirExpr::Literal(Literal::Bool(true)))],
+        };
+        assert!(unresolved.to_rust_expr(&mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_closure_generation() {
+        let mut ctx = create_test_context();
+        let var = |name: &str| HirExpr::Var(name.to_string());
+        let add_n = || HirExpr::Lambda {
+            params: vec!["x".to_string()],
+            body: Box::new(HirExpr::Binary {
+                op: BinOp::Add,
+                left: Box::new(var("x")),
+                right: Box::new(var("n")),
+            }),
+        };
+        ctx.declare_var("n");
+        ctx.var_types.insert("n".to_string(), Type::Int);
+
+        let bind = HirStmt::Assign {
+            target: AssignTarget::Symbol("add".to_string()),
+            value: add_n(),
+        };
+        assert_eq!(
+            bind.to_rust_tokens(&mut ctx).unwrap().to_string(),
+            "let add = | x | x + n ;"
+        );
+        assert_eq!(ctx.closures.locals["add"], ClosureKind::Fn);
+
+        // A returned closure outlives the call, so it owns its captures
+        let ret = HirStmt::Return(Some(add_n()));
+        assert_eq!(
+            ret.to_rust_tokens(&mut ctx).unwrap().to_string(),
+            "return move | x | x + n ;"
+        );
+        assert_eq!(ctx.closures.returned, Some(ClosureKind::Fn));
+
+        let sorted = HirExpr::Call {
+            func: "sorted".to_string(),
+            args: vec![var("people")],
+            kwargs: vec![(
+                "key".to_string(),
+                HirExpr::Lambda {
+                    params: vec!["p".to_string()],
+                    body: Box::new(HirExpr::Attribute {
+                        value: Box::new(var("p")),
+                        attr: "age".to_string(),
+                    }),
+                },
+            )],
+        };
+        let sorted = sorted.to_rust_expr(&mut ctx).unwrap();
+        let code = quote! { #sorted }.to_string();
+        assert!(code.contains("let p = __a ; let __ka = & ((* p) . age) ;"));
+        assert!(code.contains("__ka . partial_cmp (__kb)"));
+
+        // A nested function capturing nothing is a plain `fn` item
+        let double = HirStmt::FunctionDef {
+            func: Box::new(HirFunction {
+                name: "double".to_string(),
+                params: vec![("y".to_string(), Type::Int)].into(),
+                param_spec: Default::default(),
+                ret_type: Type::Int,
+                body: vec![HirStmt::Return(Some(HirExpr::Binary {
+                    op: BinOp::Mul,
+                    left: Box::new(var("y")),
+                    right: Box::new(HirExpr::Literal(Literal::Int(2))),
+                }))
+                .into()],
+                properties: FunctionProperties::default(),
+                annotations: TranspilationAnnotations::default(),
+                docstring: None,
+                is_async: false,
+                span: Span::default(),
+            }),
+            nonlocals: vec![],
+        };
+        assert_eq!(
+            double.to_rust_tokens(&mut ctx).unwrap().to_string(),
+            "fn double (y : i32) -> i32 { y * 2 }"
+        );
+        assert!(ctx.is_declared("double"));
+    }
+
+    #[test]
+    fn test_methods_modify_elements_in_place() {
+        let python = r#"
+from typing import Dict, List
+
+def grow(m: List[List[int]]) -> List[List[int]]:
+    m[0].append(5)
+    m[-1].append(6)
+    m[0][-1] = 7
+    return m
+
+def tally(d: Dict[str, List[int]], k: str) -> Dict[str, List[int]]:
+    d[k].append(1)
+    d["a"].extend([2, 3])
+    return d
+"#;
+        let printed = crate::test_programs::run(
+            python,
+            r#"
+            println!("{:?}", grow(vec![vec![1], vec![2]]));
+            let d = [("a".to_string(), vec![]), ("b".to_string(), vec![0])];
+            let d = tally(d.into_iter().collect(), "b".to_string());
+            println!("{:?} {:?}", d["a"], d["b"]);
+            "#,
+        );
+        assert_eq!(printed, "[[1, 7], [2, 6]]\n[2, 3] [0, 1]\n");
+    }
+
+    #[test]
+    fn test_idiomatic_tails() {
+        let python = r#"
+from typing import Dict, List
+
+def sides(n: int) -> int:
+    match n:
+        case 0:
+            return 0
+        case _:
+            return 4
+
+def doubled(d: Dict[int, float]) -> List[float]:
+    out = []
+    for k, v in d.items():
+        out.append(v * 2.0)
+    return out
+
+def build() -> List[int]:
+    xs = []
+    xs.append(1)
+    xs.append(2)
+    return xs
+
+def counter() -> int:
+    count = 0
+    def bump() -> int:
+        return count + 1
+    return bump()
+
+def bump_key(d: Dict[str, int], k: str) -> int:
+    d[k] = 1
+    return d[k]
+
+def bump_local(k: str) -> int:
+    d = {}
+    d[k] = 2
+    return d[k]
+
+def arms(n: int, xs: List[int]) -> int:
+    total = 0
+    match n:
+        case 0:
+            total = total + 1
+        case 1:
+            y = xs[0]
+            total = total * y
+        case _:
+            total = total - n
+    return total
+
+def arm_tail(n: int, xs: List[int]) -> int:
+    match n:
+        case 0:
+            y = n * 2
+            return y
+        case _:
+            z = xs[0]
+            return z
+
+def picked(n: int) -> int:
+    match n:
+        case 0:
+            y = 1
+        case _:
+            y = 2
+    return y + 1
+
+def branched(n: int) -> int:
+    if n == 0:
+        y = 10
+    elif n == 1:
+        y = 20
+    else:
+        return 0
+    return y
+"#;
+        crate::test_programs::assert_lint_free(python);
+        let printed = crate::test_programs::run(
+            python,
+            r#"
+            println!("{} {} {:?} {:?}", sides(0), sides(3), doubled(&[(1, 1.5)].into()), build());
+            println!("{} {} {}", counter(), bump_key(Default::default(), "k".to_string()), bump_local("k".to_string()));
+            println!("{} {} {}", arms(0, &[5]), arms(1, &[5]), arms(2, &[5]));
+            println!("{} {} {} {}", arm_tail(3, &[7]), arm_tail(0, &[7]), picked(0), picked(5));
+            println!("{} {} {}", branched(0), branched(1), branched(2));
+            "#,
+        );
+        assert_eq!(
+            printed,
+            "0 4 [3.0] [1, 2]\n1 1 2\n1 0 -2\n7 0 2 3\n10 20 0\n"
+        );
+    }
+
+    #[test]
+    fn test_with_resources_run() {
+        let python = r#"
+import threading
+from typing import List
+
+class Timer:
+    def __init__(self) -> None:
+        self.count = 0
+
+    def __enter__(self):
+        self.count += 1
+        return self
+
+    def __exit__(self, exc_type, exc, tb) -> None:
+        self.count -= 1
+
+class Res:
+    def __init__(self, items: List[int], name: str) -> None:
+        self.items = items
+        self.name = name
+
+    def __enter__(self):
+        self.items.append(3)
+        return self
+
+    def __exit__(self, exc_type, exc, tb) -> None:
+        print("closed", self.name)
+
+def locked(n: int) -> int:
+    l = threading.Lock()
+    with l:
+        n += 1
+    with threading.RLock():
+        n += 1
+    return n
+
+def timed() -> int:
+    with Timer() as t:
+        return t.count
+
+def entered() -> List[int]:
+    with Res([1, 2], "r") as r:
+        v = r.items
+        n = r.name
+    print(n, len(v))
+    return v
+"#;
+        let printed = crate::test_programs::run(
+            python,
+            r#"println!("{} {} {:?}", locked(1), timed(), entered());"#,
+        );
+        // `__exit__` runs when the block is left, before what follows it
+        assert_eq!(printed, "closed r\nr 3\n3 1 [1, 2, 3]\n");
+    }
+
+    #[test]
+    fn test_isinstance_narrowing_and_variadic_tuples_run() {
+        let python = r#"
+from typing import Tuple, Union
+
+def describe(x: Union[int, str]) -> str:
+    if isinstance(x, int):
+        return str(x + 1)
+    else:
+        return x.upper()
+
+def shout(x: Union[int, str]) -> str:
+    if isinstance(x, int):
+        return str(x)
+    return x.upper()
+
+def total(xs: Tuple[int, ...]) -> int:
+    s = 0
+    for x in xs:
+        s += x
+    return s
+
+def six() -> int:
+    return total((1, 2, 3))
+"#;
+        let main = r#"println!("{} {}", describe(IntOrStr::Int(4)), describe(IntOrStr::Str("a".to_string())));
+println!("{} {}", shout(IntOrStr::Int(7)), shout(IntOrStr::Str("hi".to_string())));
+println!("{} {}", six(), total(&[]));"#;
+        assert_eq!(crate::test_programs::run(python, main), "5 A\n7 HI\n6 0\n");
+    }
 }
@@ -0,0 +1,437 @@
+//! Lowering of Python sets: displays, `set` methods and set operators
+//!
+//! `set` and `frozenset` are `HashSet`s hashed as the function's
+//! `hash_strategy` says. A set held in a set or keying a dict is a
+//! `BTreeSet` instead, as a `HashSet` is not `Hash`. The operators `|`,
+//! `&`, `-` and `^` are std's, applied to references to both sets, and
+//! the methods returning a new set lower to the same operators.
+
+use crate::builtins::{self, Arg};
+use crate::hir::{BinOp, HirExpr, Type};
+use depyler_annotations::{HashStrategy, TranspilationAnnotations};
+use quote::quote;
+use syn::parse_quote;
+
+/// The Rust type of a set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SetType {
+    #[default]
+    Std,
+    Fnv,
+    AHash,
+    /// A set that is itself hashed
+    BTree,
+}
+
+impl SetType {
+    /// The set type of a function annotated with `annotations`
+    pub fn new(annotations: &TranspilationAnnotations) -> Self {
+        match annotations.hash_strategy {
+            HashStrategy::Standard => SetType::Std,
+            HashStrategy::Fnv => SetType::Fnv,
+            HashStrategy::AHash => SetType::AHash,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SetType::Std => "HashSet",
+            SetType::Fnv => "FnvHashSet",
+            SetType::AHash => "AHashSet",
+            SetType::BTree => "BTreeSet",
+        }
+    }
+
+    /// The `use` naming the type
+    pub fn import(self) -> proc_macro2::TokenStream {
+        match self {
+            SetType::Std => quote! { use std::collections::HashSet; },
+            SetType::Fnv => quote! { use fnv::FnvHashSet; },
+            SetType::AHash => quote! { use ahash::AHashSet; },
+            SetType::BTree => quote! { use std::collections::BTreeSet; },
+        }
+    }
+
+    fn ident(self) -> syn::Ident {
+        syn::Ident::new(self.name(), proc_macro2::Span::call_site())
+    }
+
+    /// An empty set, as `set()` makes
+    pub fn empty(self) -> syn::Expr {
+        let ty = self.ident();
+        match self {
+            // Only the std hasher has `new`
+            SetType::Std | SetType::BTree => parse_quote! { #ty::new() },
+            SetType::Fnv | SetType::AHash => parse_quote! { #ty::default() },
+        }
+    }
+
+    /// The set of the items of iterator `items`
+    pub fn collect(self, items: &syn::Expr) -> syn::Expr {
+        let ty = self.ident();
+        let items = builtins::operand_expr(items);
+        parse_quote! { #items.collect::<#ty<_>>() }
+    }
+
+    /// A set display of `elts`
+    pub fn display(self, elts: &[syn::Expr]) -> syn::Expr {
+        let ty = self.ident();
+        match self {
+            _ if elts.is_empty() => self.empty(),
+            SetType::Std | SetType::BTree => parse_quote! { #ty::from([#(#elts),*]) },
+            SetType::Fnv | SetType::AHash => {
+                self.collect(&parse_quote! { [#(#elts),*].into_iter() })
+            }
+        }
+    }
+}
+
+/// The `set` methods `lower_method` lowers
+const METHODS: &[&str] = &[
+    "add",
+    "discard",
+    "remove",
+    "pop",
+    "clear",
+    "copy",
+    "update",
+    "union",
+    "intersection",
+    "difference",
+    "symmetric_difference",
+    "intersection_update",
+    "difference_update",
+    "symmetric_difference_update",
+    "issubset",
+    "issuperset",
+    "isdisjoint",
+];
+
+/// Whether `method` is a `set` method lowered here
+pub fn is_method(method: &str) -> bool {
+    METHODS.contains(&method)
+}
+
+/// Whether `method` leaves the set it is called on as it is
+pub fn reads_receiver(method: &str) -> bool {
+    matches!(
+        method,
+        "copy"
+            | "union"
+            | "intersection"
+            | "difference"
+            | "symmetric_difference"
+            | "issubset"
+            | "issuperset"
+            | "isdisjoint"
+    )
+}
+
+/// Whether `method`, a method only sets have, only reads its argument
+pub fn reads_argument(method: &str) -> bool {
+    matches!(
+        method,
+        "discard"
+            | "union"
+            | "intersection"
+            | "difference"
+            | "symmetric_difference"
+            | "intersection_update"
+            | "difference_update"
+            | "symmetric_difference_update"
+            | "issubset"
+            | "issuperset"
+            | "isdisjoint"
+    )
+}
+
+/// The type a `set` method called on a set of type `set` returns
+pub fn method_type(method: &str, set: &Type) -> Type {
+    match method {
+        "union" | "intersection" | "difference" | "symmetric_difference" | "copy" => set.clone(),
+        "issubset" | "issuperset" | "isdisjoint" => Type::Bool,
+        "pop" => match set {
+            Type::Set(item) => (**item).clone(),
+            _ => Type::Unknown,
+        },
+        _ => Type::None,
+    }
+}
+
+/// The std operator a set operator or method applies
+fn operator(method: &str) -> Option<proc_macro2::TokenStream> {
+    Some(match method {
+        "union" => quote! { | },
+        "intersection" | "intersection_update" => quote! { & },
+        "difference" | "difference_update" => quote! { - },
+        "symmetric_difference" | "symmetric_difference_update" => quote! { ^ },
+        _ => return None,
+    })
+}
+
+/// An argument of a `set` method, with the same argument lent by reference
+pub struct SetArg<'a> {
+    pub arg: Arg<'a>,
+    pub lent: syn::Expr,
+}
+
+impl SetArg<'_> {
+    /// The argument as a set lent by reference, collecting any other iterable
+    fn lent_set(&self, set_type: SetType) -> syn::Expr {
+        if let Type::Set(_) = self.arg.ty {
+            return self.lent.clone();
+        }
+        let set = set_type.collect(&builtins::iterator(&self.arg, false));
+        parse_quote! { &#set }
+    }
+}
+
+/// The Rust a call of `set` method `method` becomes, or `None` when it is
+/// not one lowered here; a set made from an argument that is not a set is
+/// a `set_type`
+pub fn lower_method(
+    recv: &SetArg,
+    method: &str,
+    args: &[SetArg],
+    set_type: SetType,
+) -> Option<syn::Expr> {
+    let r = builtins::operand_expr(&recv.arg.expr);
+    let lent = &recv.lent;
+    let expr: syn::Expr = match (method, args) {
+        ("add", [x]) => {
+            let x = owned(&x.arg);
+            parse_quote! { #r.insert(#x) }
+        }
+        ("discard", [x]) => {
+            let x = &x.lent;
+            parse_quote! { #r.remove(#x) }
+        }
+        ("remove", [x]) => {
+            let x = &x.lent;
+            parse_quote! { assert!(#r.remove(#x), "element not in set") }
+        }
+        ("pop", []) => parse_quote! {
+            {
+                let __item = #r.iter().next().cloned().expect("pop from an empty set");
+                #r.remove(&__item);
+                __item
+            }
+        },
+        ("clear", []) => parse_quote! { #r.clear() },
+        ("copy", []) => parse_quote! { #r.clone() },
+        ("update", [xs]) => {
+            let items = builtins::iterator(&xs.arg, true);
+            parse_quote! { #r.extend(#items) }
+        }
+        ("issubset", [x]) => {
+            let x = x.lent_set(set_type);
+            parse_quote! { #r.is_subset(#x) }
+        }
+        ("issuperset", [x]) => {
+            let x = x.lent_set(set_type);
+            parse_quote! { #r.is_superset(#x) }
+        }
+        ("isdisjoint", [x]) => {
+            let x = x.lent_set(set_type);
+            parse_quote! { #r.is_disjoint(#x) }
+        }
+        (_, [x]) if method.ends_with("_update") => {
+            let op = operator(method)?;
+            let x = x.lent_set(set_type);
+            parse_quote! { #r = &#r #op #x }
+        }
+        (_, [x]) => {
+            let op = operator(method)?;
+            let x = x.lent_set(set_type);
+            parse_quote! { (#lent #op #x) }
+        }
+        _ => return None,
+    };
+    Some(expr)
+}
+
+/// An item added to a set: a local read again later is copied
+fn owned(arg: &Arg) -> syn::Expr {
+    let expr = &arg.expr;
+    match arg.ty {
+        Type::Int | Type::Float | Type::Bool | Type::Unknown => expr.clone(),
+        _ if matches!(arg.hir, HirExpr::Var(_)) && !arg.movable => parse_quote! { #expr.clone() },
+        _ => expr.clone(),
+    }
+}
+
+/// The std operator set operator `op` is, or `None` when sets have no such
+/// operator; comparisons other than `==` and `!=` test for subsets, a
+/// proper subset being one that is also unequal, as its size tells
+pub fn lower_operator(op: BinOp, left: &syn::Expr, right: &syn::Expr) -> Option<syn::Expr> {
+    let (receiver, other) = (receiver(left), receiver(right));
+    match op {
+        BinOp::LtEq => return Some(parse_quote! { #receiver.is_subset(#right) }),
+        BinOp::GtEq => return Some(parse_quote! { #receiver.is_superset(#right) }),
+        BinOp::Lt => {
+            return Some(parse_quote! {
+                (#receiver.is_subset(#right) && #receiver.len() != #other.len())
+            })
+        }
+        BinOp::Gt => {
+            return Some(parse_quote! {
+                (#receiver.is_superset(#right) && #receiver.len() != #other.len())
+            })
+        }
+        _ => {}
+    }
+    let op = match op {
+        BinOp::BitOr => quote! { | },
+        BinOp::BitAnd => quote! { & },
+        BinOp::Sub => quote! { - },
+        BinOp::BitXor => quote! { ^ },
+        _ => return None,
+    };
+    Some(parse_quote! { (#left #op #right) })
+}
+
+/// A set lent by reference as a method receiver, which it is as it is
+fn receiver(lent: &syn::Expr) -> syn::Expr {
+    match lent {
+        syn::Expr::Reference(reference) => builtins::operand_expr(&reference.expr),
+        _ => builtins::operand_expr(lent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_arg(hir: &HirExpr, ty: Type) -> SetArg<'_> {
+        let HirExpr::Var(name) = hir else {
+            unreachable!("tests pass variables")
+        };
+        let expr: syn::Expr = syn::parse_str(name).unwrap();
+        SetArg {
+            lent: parse_quote! { &#expr },
+            arg: Arg {
+                hir,
+                expr,
+                ty,
+                movable: false,
+            },
+        }
+    }
+
+    fn method(recv: &SetArg, method: &str, args: &[(&str, Type)], set_type: SetType) -> String {
+        let hirs: Vec<HirExpr> = args
+            .iter()
+            .map(|(name, _)| HirExpr::Var(name.to_string()))
+            .collect();
+        let args: Vec<SetArg> = hirs
+            .iter()
+            .zip(args)
+            .map(|(hir, (_, ty))| set_arg(hir, ty.clone()))
+            .collect();
+        let expr = lower_method(recv, method, &args, set_type).unwrap();
+        quote! { #expr }.to_string()
+    }
+
+    #[test]
+    fn test_set_type_from_hash_strategy() {
+        let mut annotations = TranspilationAnnotations::default();
+        assert_eq!(SetType::new(&annotations), SetType::Std);
+        annotations.hash_strategy = HashStrategy::Fnv;
+        assert_eq!(SetType::new(&annotations), SetType::Fnv);
+        annotations.hash_strategy = HashStrategy::AHash;
+        assert_eq!(SetType::new(&annotations), SetType::AHash);
+    }
+
+    #[test]
+    fn test_display() {
+        let elts: Vec<syn::Expr> = vec![parse_quote! { 1 }, parse_quote! { 2 }];
+        let display = |set_type: SetType, elts: &[syn::Expr]| {
+            let expr = set_type.display(elts);
+            quote! { #expr }.to_string()
+        };
+        assert_eq!(display(SetType::Std, &elts), "HashSet :: from ([1 , 2])");
+        assert_eq!(
+            display(SetType::Fnv, &elts),
+            "[1 , 2] . into_iter () . collect :: < FnvHashSet < _ > > ()"
+        );
+        assert_eq!(display(SetType::AHash, &[]), "AHashSet :: default ()");
+        assert_eq!(display(SetType::BTree, &[]), "BTreeSet :: new ()");
+    }
+
+    #[test]
+    fn test_lower_method() {
+        let set = Type::Set(Box::new(Type::String));
+        let s = HirExpr::Var("s".to_string());
+        let s = set_arg(&s, set.clone());
+        let std = SetType::Std;
+        let word = [("w", Type::String)];
+        assert_eq!(method(&s, "add", &word, std), "s . insert (w . clone ())");
+        let n = [("n", Type::Int)];
+        assert_eq!(method(&s, "add", &n, std), "s . insert (n)");
+        assert_eq!(method(&s, "discard", &word, std), "s . remove (& w)");
+        let t = [("t", set.clone())];
+        assert_eq!(method(&s, "union", &t, std), "(& s | & t)");
+        assert_eq!(
+            method(
+                &s,
+                "intersection",
+                &[("xs", Type::List(Box::new(Type::String)))],
+                SetType::Fnv
+            ),
+            "(& s & & xs . iter () . cloned () . collect :: < FnvHashSet < _ > > ())"
+        );
+        assert_eq!(method(&s, "difference_update", &t, std), "s = & s - & t");
+        assert_eq!(method(&s, "issubset", &t, std), "s . is_subset (& t)");
+        assert!(lower_method(&s, "sort", &[], std).is_none());
+    }
+
+    #[test]
+    fn test_method_type() {
+        let set = Type::Set(Box::new(Type::String));
+        assert_eq!(method_type("union", &set), set);
+        assert_eq!(method_type("isdisjoint", &set), Type::Bool);
+        assert_eq!(method_type("pop", &set), Type::String);
+        assert_eq!(method_type("add", &set), Type::None);
+    }
+
+    #[test]
+    fn test_lower_operator() {
+        let (a, b): (syn::Expr, syn::Expr) = (parse_quote! { &a }, parse_quote! { &b });
+        let expr = lower_operator(BinOp::Sub, &a, &b).unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "(& a - & b)");
+        assert!(lower_operator(BinOp::Add, &a, &b).is_none());
+        let expr = lower_operator(BinOp::LtEq, &a, &b).unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "a . is_subset (& b)");
+        let expr = lower_operator(BinOp::Gt, &a, &b).unwrap();
+        assert_eq!(
+            quote! { #expr }.to_string(),
+            "(a . is_superset (& b) && a . len () != b . len ())"
+        );
+    }
+
+    #[test]
+    fn test_set_comparisons_run() {
+        let python = r#"
+from typing import List, Set
+
+def compare(p: Set[int], q: Set[int]) -> List[bool]:
+    return [p <= q, p < q, p >= q, p > q, p == q]
+
+def superset() -> bool:
+    a = {1, 2}
+    b = {1, 2, 3}
+    return b > a
+"#;
+        let main = r#"let p = HashSet::from([1, 2]);
+let q = HashSet::from([1, 2, 3]);
+println!("{:?}", compare(p.clone(), q.clone()));
+println!("{:?}", compare(q, p.clone()));
+println!("{:?} {}", compare(p.clone(), p), superset());"#;
+        assert_eq!(
+            crate::test_programs::run(python, main),
+            "[true, true, false, false, false]\n\
+             [false, false, true, true, false]\n\
+             [true, false, true, false, true] true\n"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Source maps from generated Rust lines back to the Python they came from
+//!
+//! While generating a file with a source map, codegen puts a marker macro
+//! call before each function, method and statement, holding its Python
+//! span. `SourceMap::extract` removes the markers before the code is
+//! formatted, then finds the lines they stood on in the formatted code.
+
+use crate::hir::Span;
+use anyhow::Result;
+use quote::quote;
+use serde::{Deserialize, Serialize};
+
+/// The macro marking generated code with its Python span
+const MARKER: &str = "__depyler_py_span";
+
+/// Marker tokens for code generated from `span`
+pub fn marker(span: Span) -> proc_macro2::TokenStream {
+    let name = syn::Ident::new(MARKER, proc_macro2::Span::call_site());
+    let start = proc_macro2::Literal::u32_unsuffixed(span.start);
+    let end = proc_macro2::Literal::u32_unsuffixed(span.end);
+    quote! { #name!(#start, #end); }
+}
+
+/// Rust lines `rust_start..=rust_end`, counted from 1, generated from the
+/// Python at `span`, which starts at `line` and `column`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mapping {
+    pub rust_start: usize,
+    pub rust_end: usize,
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where each line of a generated Rust file came from
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceMap {
+    /// The Python file the Rust file was generated from
+    pub python_file: String,
+    /// Ordered by Rust line; lines before the first mapping, such as
+    /// imports, come from no particular Python
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Remove the markers from `raw`, the unformatted code generated from
+    /// `python_source`, and format it with `format`, mapping each line to
+    /// the innermost marker at or above it. With `line_comments`, a line
+    /// starting a statement ends in `// py:LINE`.
+    ///
+    /// Formatting only changes whitespace, so markers are found again in
+    /// the formatted code by counting the other characters before them.
+    pub fn extract(
+        raw: &str,
+        format: impl FnOnce(String) -> String,
+        python_source: &str,
+        line_comments: bool,
+    ) -> (String, SourceMap) {
+        let (raw, markers) = strip_markers(raw);
+        let code = format(raw);
+
+        let mut anchors: Vec<(usize, Span)> = Vec::new();
+        let mut markers = markers.into_iter().peekable();
+        let mut line = 1;
+        let mut seen = 0;
+        for c in code.chars() {
+            if c == '\n' {
+                line += 1;
+            } else if !c.is_whitespace() {
+                while let Some((_, span)) = markers.next_if(|&(at, _)| at <= seen) {
+                    // Of markers on the same line, the last is the innermost
+                    match anchors.last_mut() {
+                        Some(last) if last.0 == line => last.1 = span,
+                        _ => anchors.push((line, span)),
+                    }
+                }
+                seen += 1;
+            }
+        }
+
+        let mut lines: Vec<String> = code.lines().map(str::to_string).collect();
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(python_source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let mut mappings = Vec::with_capacity(anchors.len());
+        for (i, &(rust_start, span)) in anchors.iter().enumerate() {
+            let rust_end = anchors
+                .get(i + 1)
+                .map_or(lines.len(), |&(next, _)| next - 1);
+            let offset = span.range().start.min(python_source.len());
+            let line = line_starts.partition_point(|&start| start <= offset);
+            let column = python_source[line_starts[line - 1]..offset].chars().count() + 1;
+            if line_comments {
+                lines[rust_start - 1].push_str(&format!(" // py:{line}"));
+            }
+            mappings.push(Mapping {
+                rust_start,
+                rust_end,
+                span,
+                line,
+                column,
+            });
+        }
+
+        let mut code_out = lines.join("\n");
+        if code.ends_with('\n') {
+            code_out.push('\n');
+        }
+        let map = SourceMap {
+            python_file: String::new(),
+            mappings,
+        };
+        (code_out, map)
+    }
+
+    /// The mapping of Rust line `line`, counted from 1
+    pub fn lookup(&self, line: usize) -> Option<&Mapping> {
+        let i = self.mappings.partition_point(|m| m.rust_start <= line);
+        i.checked_sub(1)
+            .map(|i| &self.mappings[i])
+            .filter(|m| line <= m.rust_end)
+    }
+
+    /// Account for `count` lines inserted after Rust line `line`, which
+    /// belong to the mapping of that line
+    pub fn insert_lines(&mut self, line: usize, count: usize) {
+        for mapping in &mut self.mappings {
+            if mapping.rust_start > line {
+                mapping.rust_start += count;
+            }
+            if mapping.rust_end >= line {
+                mapping.rust_end += count;
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Point the `-->` locations of rustc `diagnostics` about `rust_file`
+    /// at the Python they were generated from, keeping the Rust location
+    /// alongside
+    pub fn rewrite_diagnostics(&self, rust_file: &str, diagnostics: &str) -> String {
+        diagnostics
+            .lines()
+            .map(|line| {
+                self.rewrite_location(rust_file, line)
+                    .unwrap_or_else(|| line.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn rewrite_location(&self, rust_file: &str, line: &str) -> Option<String> {
+        let (indent, location) = line.split_once("--> ")?;
+        if !indent.trim().is_empty() {
+            return None;
+        }
+        let mut parts = location.rsplitn(3, ':');
+        let column = parts.next()?;
+        let rust_line: usize = parts.next()?.parse().ok()?;
+        let file = parts.next()?;
+        if !file.ends_with(rust_file) && !rust_file.ends_with(file) {
+            return None;
+        }
+        let mapping = self.lookup(rust_line)?;
+        Some(format!(
+            "{indent}--> {}:{}:{} (generated {file}:{rust_line}:{column})",
+            self.python_file, mapping.line, mapping.column
+        ))
+    }
+}
+
+/// `raw` without its markers, and the span of each with the number of
+/// non-whitespace characters before it
+fn strip_markers(raw: &str) -> (String, Vec<(usize, Span)>) {
+    let mut out = String::with_capacity(raw.len());
+    let mut markers = Vec::new();
+    let mut seen = 0;
+    let mut rest = raw;
+    while let Some(start) = rest.find(MARKER) {
+        let Some(len) = rest[start..].find(';') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        seen += rest[..start].chars().filter(|c| !c.is_whitespace()).count();
+        let mut offsets = rest[start + MARKER.len()..start + len]
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|n| n.parse().ok());
+        if let (Some(from), Some(to)) = (offsets.next(), offsets.next()) {
+            markers.push((seen, Span::new(from, to)));
+        }
+        rest = rest[start + len + 1..].trim_start_matches(' ');
+    }
+    out.push_str(rest);
+    (out, markers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_round_trip() {
+        let raw = format!(
+            "{} pub fn f () {{ let x = 1 ; {} x }}",
+            marker(Span::new(0, 30)),
+            marker(Span::new(30, 38))
+        );
+        let format = |code: String| code.replace("{ ", "{\n    ").replace("; ", ";\n    ");
+        let source = "def f() -> int:\n    x = 1\n    return x\n";
+        let (code, map) = SourceMap::extract(&raw, format, source, false);
+        assert_eq!(code, "pub fn f () {\n    let x = 1 ;\n    x }");
+        assert_eq!(map.lookup(1).unwrap().line, 1);
+        assert_eq!(map.lookup(2).unwrap().line, 1);
+        let tail = map.lookup(3).unwrap();
+        assert_eq!((tail.line, tail.column), (3, 5));
+        assert_eq!(tail.span, Span::new(30, 38));
+        assert!(map.lookup(4).is_none());
+    }
+
+    #[test]
+    fn test_line_comments() {
+        let source = "x = 1\ny = 2\n";
+        let raw = "let x = 1 ; __depyler_py_span ! (6 , 11) ; let y = 2 ;";
+        let format = |code: String| code.replace("; ", ";\n");
+        let (code, map) = SourceMap::extract(raw, format, source, true);
+        assert_eq!(code, "let x = 1 ;\nlet y = 2 ; // py:2");
+        assert_eq!(map.mappings.len(), 1);
+        assert!(map.lookup(1).is_none());
+        assert_eq!(map.lookup(2).unwrap().line, 2);
+    }
+
+    #[test]
+    fn test_insert_lines() {
+        let mapping = |rust_start, rust_end| Mapping {
+            rust_start,
+            rust_end,
+            span: Span::new(0, 1),
+            line: 1,
+            column: 1,
+        };
+        let mut map = SourceMap {
+            python_file: String::new(),
+            mappings: vec![mapping(1, 3), mapping(4, 5)],
+        };
+        map.insert_lines(2, 3);
+        assert_eq!(map.mappings, vec![mapping(1, 6), mapping(7, 8)]);
+    }
+
+    #[test]
+    fn test_rewrite_diagnostics() {
+        let map = SourceMap {
+            python_file: "example.py".to_string(),
+            mappings: vec![Mapping {
+                rust_start: 3,
+                rust_end: 5,
+                span: Span::new(20, 30),
+                line: 4,
+                column: 5,
+            }],
+        };
+        let diagnostics = "error[E0308]: mismatched types\n --> out/example.rs:4:13\n  |";
+        assert_eq!(
+            map.rewrite_diagnostics("example.rs", diagnostics),
+            "error[E0308]: mismatched types\n --> example.py:4:5 (generated out/example.rs:4:13)\n  |"
+        );
+        // Lines outside the mappings keep their Rust location
+        let unmapped = " --> out/example.rs:1:1";
+        assert_eq!(map.rewrite_diagnostics("example.rs", unmapped), unmapped);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let (_, mut map) =
+            SourceMap::extract("__depyler_py_span!(0, 5); x", |c| c, "x = 1\n", false);
+        map.python_file = "m.py".to_string();
+        assert_eq!(SourceMap::from_json(&map.to_json().unwrap()).unwrap(), map);
+    }
+}
@@ -0,0 +1,1308 @@
+//! Lowering of Python strings: formatted strings and `str` methods
+//!
+//! A formatted string becomes `format!`, each field formatted by the Rust
+//! spec doing what its Python format spec does; specs Rust has no
+//! equivalent for are reported as unsupported rather than formatted
+//! differently. `str` methods keep Python's semantics, as in `split()`
+//! splitting on runs of whitespace and `find()` counting in characters.
+//! Where no Rust method does, a [`Helper`] is generated next to the code.
+
+use crate::builtins::{self, Arg};
+use crate::error::ErrorKind;
+use crate::hir::{FStringPart, HirExpr, Literal, Type};
+use crate::transpile_bail;
+use crate::type_enums;
+use anyhow::Result;
+use quote::quote;
+use std::collections::BTreeSet;
+use syn::parse_quote;
+
+/// The `str` methods `lower_method` lowers
+const METHODS: &[&str] = &[
+    "strip",
+    "lstrip",
+    "rstrip",
+    "split",
+    "rsplit",
+    "splitlines",
+    "join",
+    "replace",
+    "startswith",
+    "endswith",
+    "upper",
+    "lower",
+    "casefold",
+    "capitalize",
+    "swapcase",
+    "title",
+    "find",
+    "rfind",
+    "index",
+    "rindex",
+    "count",
+    "isdigit",
+    "isdecimal",
+    "isnumeric",
+    "isalpha",
+    "isalnum",
+    "isspace",
+    "isupper",
+    "islower",
+    "zfill",
+    "ljust",
+    "rjust",
+    "center",
+    "removeprefix",
+    "removesuffix",
+];
+
+/// Whether `method` is a `str` method lowered here
+pub fn is_method(method: &str) -> bool {
+    METHODS.contains(&method)
+}
+
+/// The type a `str` method returns
+pub fn method_type(method: &str) -> Type {
+    match method {
+        "split" | "rsplit" | "splitlines" => Type::List(Box::new(Type::String)),
+        "find" | "rfind" | "index" | "rindex" | "count" => Type::Int,
+        _ if method.starts_with("is") || method.ends_with("with") => Type::Bool,
+        _ => Type::String,
+    }
+}
+
+/// Whether Rust's debug format of a `ty` differs from Python's `repr()`,
+/// which quotes strings in single quotes and capitalizes bools
+fn repr_differs(ty: &Type) -> bool {
+    match ty {
+        Type::String | Type::Bool => true,
+        Type::List(item) | Type::Set(item) => repr_differs(item),
+        Type::Dict(key, value) => repr_differs(key) || repr_differs(value),
+        Type::Tuple(items) => items.iter().any(repr_differs),
+        _ => false,
+    }
+}
+
+/// Functions generated next to the code calling them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Helper {
+    /// `repr()` of a string
+    Repr,
+    /// `str.splitlines()`
+    SplitLines,
+}
+
+impl Helper {
+    pub fn name(self) -> syn::Ident {
+        let name = match self {
+            Helper::Repr => "py_repr",
+            Helper::SplitLines => "py_splitlines",
+        };
+        syn::Ident::new(name, proc_macro2::Span::call_site())
+    }
+
+    /// The definition of the helper
+    pub fn tokens(self) -> proc_macro2::TokenStream {
+        let name = self.name();
+        match self {
+            Helper::Repr => quote! {
+                #[doc = " Python's `repr()` of a string: quoted, in single quotes unless it has"]
+                #[doc = " only single ones, with special characters escaped"]
+                fn #name(s: &str) -> String {
+                    let quote = if s.contains('\'') && !s.contains('"') { '"' } else { '\'' };
+                    let mut repr = String::from(quote);
+                    for c in s.chars() {
+                        match c {
+                            '\\' => repr.push_str("\\\\"),
+                            '\n' => repr.push_str("\\n"),
+                            '\r' => repr.push_str("\\r"),
+                            '\t' => repr.push_str("\\t"),
+                            c if c == quote => {
+                                repr.push('\\');
+                                repr.push(c);
+                            }
+                            '\0'..='\x1f' | '\x7f'..='\u{a0}' => {
+                                repr.push_str(&format!("\\x{:02x}", c as u32))
+                            }
+                            c => repr.push(c),
+                        }
+                    }
+                    repr.push(quote);
+                    repr
+                }
+            },
+            Helper::SplitLines => quote! {
+                #[doc = " Python's `str.splitlines()`: the lines split at every line boundary,"]
+                #[doc = " `\\r\\n` being one"]
+                fn #name(s: &str) -> Vec<String> {
+                    let mut lines = Vec::new();
+                    let mut line = String::new();
+                    let mut chars = s.chars().peekable();
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\r' => {
+                                chars.next_if_eq(&'\n');
+                                lines.push(std::mem::take(&mut line));
+                            }
+                            '\n' | '\x0b' | '\x0c' | '\x1c' | '\x1d' | '\x1e' | '\u{85}'
+                            | '\u{2028}' | '\u{2029}' => lines.push(std::mem::take(&mut line)),
+                            c => line.push(c),
+                        }
+                    }
+                    if !line.is_empty() {
+                        lines.push(line);
+                    }
+                    lines
+                }
+            },
+        }
+    }
+}
+
+/// A Python format spec, `[[fill]align][sign][z][#][0][width][grouping][.precision][type]`
+#[derive(Debug, Default, PartialEq)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    sign: Option<char>,
+    z: bool,
+    alternate: bool,
+    zero: bool,
+    width: Option<Count>,
+    grouping: Option<char>,
+    precision: Option<Count>,
+    kind: Option<char>,
+}
+
+/// A width or precision: a number, or a value given by the field `{N}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Count {
+    Literal(usize),
+    Value(usize),
+}
+
+fn parse_spec(spec: &str) -> Result<Spec> {
+    let chars: Vec<char> = spec.chars().collect();
+    let mut parsed = Spec::default();
+    let mut i = 0;
+    let is_align = |c: Option<&char>| matches!(c, Some('<' | '>' | '=' | '^'));
+    if is_align(chars.get(1)) {
+        parsed.fill = Some(chars[0]);
+        parsed.align = Some(chars[1]);
+        i = 2;
+    } else if is_align(chars.first()) {
+        parsed.align = Some(chars[0]);
+        i = 1;
+    }
+    if let Some(&sign @ ('+' | '-' | ' ')) = chars.get(i) {
+        parsed.sign = Some(sign);
+        i += 1;
+    }
+    if chars.get(i) == Some(&'z') {
+        parsed.z = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'#') {
+        parsed.alternate = true;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'0') {
+        parsed.zero = true;
+        i += 1;
+    }
+    parsed.width = parse_count(&chars, &mut i)?;
+    if let Some(&grouping @ (',' | '_')) = chars.get(i) {
+        parsed.grouping = Some(grouping);
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        parsed.precision = parse_count(&chars, &mut i)?;
+        if parsed.precision.is_none() {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Format specifier missing precision in '{spec}'"
+            )));
+        }
+    }
+    parsed.kind = chars.get(i).copied();
+    if chars.len() > i + 1
+        || parsed
+            .kind
+            .is_some_and(|c| !c.is_ascii_alphabetic() && c != '%')
+    {
+        transpile_bail!(ErrorKind::CodeGenerationError(format!(
+            "Invalid format specifier '{spec}'"
+        )));
+    }
+    Ok(parsed)
+}
+
+fn parse_count(chars: &[char], i: &mut usize) -> Result<Option<Count>> {
+    let field = chars.get(*i) == Some(&'{');
+    let start = if field { *i + 1 } else { *i };
+    let mut end = start;
+    while chars.get(end).is_some_and(char::is_ascii_digit) {
+        end += 1;
+    }
+    if end == start {
+        return Ok(None);
+    }
+    let number: usize = chars[start..end].iter().collect::<String>().parse()?;
+    if !field {
+        *i = end;
+        return Ok(Some(Count::Literal(number)));
+    }
+    if chars.get(end) != Some(&'}') {
+        transpile_bail!(ErrorKind::CodeGenerationError(
+            "Unclosed field in format specifier".to_string()
+        ));
+    }
+    *i = end + 1;
+    Ok(Some(Count::Value(number)))
+}
+
+/// A piece of the `format!` string
+enum Piece {
+    Text(String),
+    /// A value formatted in place by name
+    Inline(String, String),
+    /// A positional argument, by index into `Format::positional`
+    Positional(usize, String),
+}
+
+/// A `format!` string under construction, with its arguments
+#[derive(Default)]
+pub struct Format {
+    pieces: Vec<Piece>,
+    positional: Vec<syn::Expr>,
+    /// How often each positional argument is referred to
+    uses: Vec<usize>,
+    named: Vec<(syn::Ident, syn::Expr)>,
+    /// The helpers the arguments call
+    pub helpers: BTreeSet<Helper>,
+}
+
+impl Format {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    /// Text as it is
+    pub fn text(&mut self, text: &str) {
+        match self.pieces.last_mut() {
+            Some(Piece::Text(last)) => last.push_str(text),
+            _ => self.pieces.push(Piece::Text(text.to_string())),
+        }
+    }
+
+    /// `arg` as `str()` shows it
+    pub fn value(&mut self, arg: &Arg) -> Result<()> {
+        self.field(arg, None, "", &[])
+    }
+
+    /// Python's `repr()` of `expr`, of type `ty`, as a `String`; `lent` when
+    /// `expr` is a reference to the value
+    fn repr(&mut self, expr: &syn::Expr, ty: &Type, lent: bool) -> syn::Expr {
+        let expr = &builtins::operand_expr(expr);
+        let items = |this: &mut Self, item: &Type| -> syn::Expr {
+            let item = this.repr(&parse_quote! { __x }, item, true);
+            parse_quote! { #expr.iter().map(|__x| #item).collect::<Vec<_>>().join(", ") }
+        };
+        match ty {
+            Type::String => {
+                self.helpers.insert(Helper::Repr);
+                let name = Helper::Repr.name();
+                if lent {
+                    parse_quote! { #name(#expr) }
+                } else {
+                    parse_quote! { #name(&#expr) }
+                }
+            }
+            Type::Bool if lent => {
+                parse_quote! { if *#expr { "True" } else { "False" }.to_string() }
+            }
+            Type::Bool => parse_quote! { if #expr { "True" } else { "False" }.to_string() },
+            Type::List(item) if repr_differs(item) => {
+                let items = items(self, item);
+                parse_quote! { format!("[{}]", #items) }
+            }
+            Type::Set(item) if repr_differs(item) => {
+                let items = items(self, item);
+                parse_quote! {
+                    if #expr.is_empty() { "set()".to_string() } else { format!("{{{}}}", #items) }
+                }
+            }
+            Type::Dict(key, value) if repr_differs(key) || repr_differs(value) => {
+                let key = self.repr(&parse_quote! { __k }, key, true);
+                let value = self.repr(&parse_quote! { __v }, value, true);
+                parse_quote! {
+                    format!(
+                        "{{{}}}",
+                        #expr.iter().map(|(__k, __v)| format!("{}: {}", #key, #value)).collect::<Vec<_>>().join(", ")
+                    )
+                }
+            }
+            Type::Tuple(items) if items.iter().any(repr_differs) => {
+                let items: Vec<syn::Expr> = items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let index = syn::Index::from(i);
+                        self.repr(&parse_quote! { #expr.#index }, item, false)
+                    })
+                    .collect();
+                let format = match items.len() {
+                    1 => "({},)".to_string(),
+                    n => format!("({})", vec!["{}"; n].join(", ")),
+                };
+                parse_quote! { format!(#format, #(#items),*) }
+            }
+            _ => parse_quote! { format!("{:?}", #expr) },
+        }
+    }
+
+    /// The text and fields of a formatted string
+    pub fn fstring(&mut self, parts: &[FStringPart], values: &[Arg]) -> Result<()> {
+        for part in parts {
+            match part {
+                FStringPart::Text(text) => self.text(text),
+                FStringPart::Field {
+                    value,
+                    conversion,
+                    spec,
+                } => {
+                    let Some(arg) = values.get(*value) else {
+                        transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                            "Formatted string refers to a missing value {value}"
+                        )));
+                    };
+                    self.field(arg, *conversion, spec, values)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `arg` converted by `conversion` and formatted by the Python format
+    /// spec `spec`, whose `{N}` are `nested[N]`
+    pub fn field(
+        &mut self,
+        arg: &Arg,
+        conversion: Option<char>,
+        spec: &str,
+        nested: &[Arg],
+    ) -> Result<()> {
+        let debug = match conversion {
+            None | Some('s') => false,
+            Some('r' | 'a') => true,
+            Some(c) => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Unknown conversion specifier {c}"
+            ))),
+        };
+        // Literals are written into the format string
+        let literal = match arg.hir {
+            HirExpr::Literal(Literal::String(text)) => Some(text.as_str()),
+            HirExpr::Literal(Literal::None) => Some("None"),
+            HirExpr::Literal(Literal::Bool(b)) => Some(if *b { "True" } else { "False" }),
+            _ => None,
+        };
+        if let (Some(text), "", false) = (literal, spec, debug) {
+            self.text(text);
+            return Ok(());
+        }
+        let spec = parse_spec(spec)?;
+        if spec.grouping.is_some() {
+            return unsupported_spec("a thousands separator");
+        }
+        if spec.z {
+            return unsupported_spec("the 'z' option");
+        }
+        if spec.sign == Some(' ') {
+            return unsupported_spec("the ' ' sign option");
+        }
+        if spec.align == Some('=') && spec.fill.is_some_and(|fill| fill != '0') {
+            return unsupported_spec("'=' alignment padded by other than '0'");
+        }
+        if matches!(spec.fill, Some('{' | '}')) {
+            return unsupported_spec("a brace as the fill character");
+        }
+
+        let expr = &arg.expr;
+        let numeric = |expr: &syn::Expr| -> syn::Expr {
+            if arg.ty == Type::Int {
+                parse_quote! { (#expr as f64) }
+            } else {
+                expr.clone()
+            }
+        };
+        let mut precision = spec.precision;
+        let mut suffix = "";
+        let (value, kind): (syn::Expr, &str) = match spec.kind {
+            None | Some('s' | 'd') => {
+                match &arg.ty {
+                    Type::String if debug => {
+                        self.helpers.insert(Helper::Repr);
+                        let name = Helper::Repr.name();
+                        (parse_quote! { #name(&#expr) }, "")
+                    }
+                    // Containers of strings and bools show their items' reprs
+                    ty if !matches!(ty, Type::String | Type::Bool) && repr_differs(ty) => {
+                        (self.repr(expr, ty, false), "")
+                    }
+                    // Numbers show as `str()` shows them
+                    ty if debug && !matches!(ty, Type::Int | Type::Bool | Type::Float) => {
+                        (expr.clone(), "?")
+                    }
+                    // `bool` formats as an `int` once given a spec
+                    Type::Bool if spec == Spec::default() => {
+                        (parse_quote! { if #expr { "True" } else { "False" } }, "")
+                    }
+                    Type::Bool => (parse_quote! { u8::from(#expr) }, ""),
+                    Type::Float if precision.is_some() => {
+                        return unsupported_spec("a precision but no presentation type for a float")
+                    }
+                    // Rust shows `1.0` as `1`, where its debug format shows `1.0`
+                    Type::Float => (expr.clone(), "?"),
+                    Type::Optional(inner) if matches!(**inner, Type::Int | Type::String) => {
+                        let expr = builtins::operand_expr(expr);
+                        (
+                            parse_quote! {
+                                #expr.as_ref().map_or_else(|| "None".to_string(), |v| v.to_string())
+                            },
+                            "",
+                        )
+                    }
+                    Type::Unknown | Type::Int | Type::String | Type::Custom(_) => {
+                        (expr.clone(), "")
+                    }
+                    ty if type_enums::displays_as_python(ty) => (expr.clone(), ""),
+                    _ => (expr.clone(), "?"),
+                }
+            }
+            Some('f' | 'F') => {
+                precision.get_or_insert(Count::Literal(6));
+                (numeric(expr), "")
+            }
+            Some('%') => {
+                precision.get_or_insert(Count::Literal(6));
+                suffix = "%";
+                let value = numeric(expr);
+                (parse_quote! { (#value * 100.0) }, "")
+            }
+            Some('x') => (expr.clone(), "x"),
+            Some('X') => (expr.clone(), "X"),
+            Some('o') => (expr.clone(), "o"),
+            Some('b') => (expr.clone(), "b"),
+            Some(kind) => return unsupported_spec(&format!("the '{kind}' presentation type")),
+        };
+
+        let mut rust = String::new();
+        match (spec.fill, spec.align) {
+            (_, Some('=')) => {}
+            (Some(fill), Some(align)) => {
+                rust.push(fill);
+                rust.push(align);
+            }
+            (None, Some(align)) => rust.push(align),
+            _ => {}
+        }
+        if spec.sign == Some('+') {
+            rust.push('+');
+        }
+        if spec.alternate {
+            rust.push('#');
+        }
+        if spec.zero || spec.align == Some('=') {
+            rust.push('0');
+        }
+        if let Some(width) = spec.width {
+            rust.push_str(&self.count(width, nested, "width")?);
+        }
+        if let Some(precision) = precision {
+            rust.push('.');
+            rust.push_str(&self.count(precision, nested, "precision")?);
+        }
+        rust.push_str(kind);
+        let rust = if rust.is_empty() {
+            rust
+        } else {
+            format!(":{rust}")
+        };
+
+        // A string formatted only to be formatted again is formatted in place
+        let value = match value {
+            syn::Expr::Macro(mut m) if kind.is_empty() && m.mac.path.is_ident("format") => {
+                m.mac.path = parse_quote! { format_args };
+                syn::Expr::Macro(m)
+            }
+            value => value,
+        };
+        match &value {
+            syn::Expr::Path(path) if path.path.get_ident().is_some() => {
+                self.pieces.push(Piece::Inline(tokens(&value), rust));
+            }
+            _ => {
+                let key = tokens(&value);
+                let slot = match self.positional.iter().position(|v| tokens(v) == key) {
+                    Some(slot) => slot,
+                    None => {
+                        self.positional.push(value);
+                        self.uses.push(0);
+                        self.positional.len() - 1
+                    }
+                };
+                self.uses[slot] += 1;
+                self.pieces.push(Piece::Positional(slot, rust));
+            }
+        }
+        if !suffix.is_empty() {
+            self.text(suffix);
+        }
+        Ok(())
+    }
+
+    /// A width or precision as Rust writes it, a value given as a named
+    /// argument
+    fn count(&mut self, count: Count, nested: &[Arg], what: &str) -> Result<String> {
+        let index = match count {
+            Count::Literal(n) => return Ok(n.to_string()),
+            Count::Value(index) => index,
+        };
+        let Some(arg) = nested.get(index) else {
+            transpile_bail!(ErrorKind::CodeGenerationError(format!(
+                "Format spec refers to a missing value {index}"
+            )));
+        };
+        let name = match &arg.expr {
+            syn::Expr::Path(path) if path.path.get_ident().is_some() => tokens(&arg.expr),
+            _ => format!("{what}{index}"),
+        };
+        let ident = syn::Ident::new(&name, proc_macro2::Span::call_site());
+        if !self.named.iter().any(|(named, _)| *named == ident) {
+            self.named.push((ident, usize_count(arg)));
+        }
+        Ok(format!("{name}$"))
+    }
+
+    /// The `format!` string and its arguments
+    pub fn finish(self) -> (String, Vec<syn::Expr>) {
+        // Arguments used more than once are referred to by index
+        let indexed = self.uses.iter().any(|&uses| uses > 1);
+        let mut format = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => format.push_str(&escape(text)),
+                Piece::Inline(name, spec) => format.push_str(&format!("{{{name}{spec}}}")),
+                Piece::Positional(slot, spec) if indexed => {
+                    format.push_str(&format!("{{{slot}{spec}}}"))
+                }
+                Piece::Positional(_, spec) => format.push_str(&format!("{{{spec}}}")),
+            }
+        }
+        let mut args = self.positional;
+        args.extend(
+            self.named
+                .into_iter()
+                .map(|(name, value)| parse_quote! { #name = #value }),
+        );
+        (format, args)
+    }
+
+    /// A lone field needing no `format!`, as its value converted to a string
+    fn single_value(&self) -> Option<syn::Expr> {
+        match (self.pieces.as_slice(), self.positional.as_slice()) {
+            ([Piece::Positional(0, spec)], [value]) if spec.is_empty() && self.named.is_empty() => {
+                Some(value.clone())
+            }
+            ([Piece::Inline(name, spec)], []) if spec.is_empty() => syn::parse_str(name).ok(),
+            _ => None,
+        }
+    }
+}
+
+fn unsupported_spec<T>(what: &str) -> Result<T> {
+    transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+        "Format spec with {what}"
+    )))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('{', "{{").replace('}', "}}")
+}
+
+fn tokens(expr: &syn::Expr) -> String {
+    quote! { #expr }.to_string()
+}
+
+/// An integer argument as a `usize` count, no count where it is negative
+fn usize_count(arg: &Arg) -> syn::Expr {
+    let expr = &arg.expr;
+    match arg.hir {
+        HirExpr::Literal(Literal::Int(n)) => {
+            let n = syn::LitInt::new(&(*n).max(0).to_string(), proc_macro2::Span::call_site());
+            parse_quote! { #n }
+        }
+        HirExpr::Call { func, .. } if func == "len" => expr.clone(),
+        _ => {
+            let expr = builtins::operand_expr(expr);
+            parse_quote! { (#expr.max(0) as usize) }
+        }
+    }
+}
+
+/// An f-string, or another formatted string, as `format!`, adding the
+/// helpers it calls to `helpers`
+pub fn lower_fstring(
+    parts: &[FStringPart],
+    values: &[Arg],
+    helpers: &mut BTreeSet<Helper>,
+) -> Result<syn::Expr> {
+    let mut format = Format::new();
+    format.fstring(parts, values)?;
+    helpers.append(&mut format.helpers);
+    if let Some(value) = format.single_value() {
+        if let syn::Expr::Macro(mut m) = value {
+            m.mac.path = parse_quote! { format };
+            return Ok(syn::Expr::Macro(m));
+        }
+        let value = builtins::operand_expr(&value);
+        return Ok(parse_quote! { #value.to_string() });
+    }
+    let (format, args) = format.finish();
+    if args.is_empty() {
+        return Ok(parse_quote! { format!(#format) });
+    }
+    Ok(parse_quote! { format!(#format, #(#args),*) })
+}
+
+/// An argument of a `str` method, with the same argument lent as a `&str`
+pub struct StrArg<'a> {
+    pub arg: Arg<'a>,
+    pub lent: syn::Expr,
+}
+
+/// The Rust a call of `str` method `method` becomes, or `None` when it is
+/// not one lowered here; the helpers it calls are added to `helpers`
+pub fn lower_method(
+    recv: &StrArg,
+    method: &str,
+    args: &[StrArg],
+    int_type: &syn::Type,
+    helpers: &mut BTreeSet<Helper>,
+) -> Result<Option<syn::Expr>> {
+    if !is_method(method) {
+        return Ok(None);
+    }
+    let r = builtins::operand_expr(&recv.arg.expr);
+    let p = |i: usize| &args[i].lent;
+    let none = |i: usize| matches!(args[i].arg.hir, HirExpr::Literal(Literal::None));
+    let collect = quote! { .map(|s| s.to_string()).collect::<Vec<String>>() };
+    let expr: syn::Expr = match (method, args.len()) {
+        ("strip" | "lstrip" | "rstrip", 0) => {
+            let trim = syn::Ident::new(trim(method, ""), proc_macro2::Span::call_site());
+            parse_quote! { #r.#trim().to_string() }
+        }
+        ("strip" | "lstrip" | "rstrip", 1) if none(0) => {
+            let trim = syn::Ident::new(trim(method, ""), proc_macro2::Span::call_site());
+            parse_quote! { #r.#trim().to_string() }
+        }
+        ("strip" | "lstrip" | "rstrip", 1) => {
+            let trim = syn::Ident::new(trim(method, "_matches"), proc_macro2::Span::call_site());
+            let chars = p(0);
+            parse_quote! { #r.#trim(|__c: char| #chars.contains(__c)).to_string() }
+        }
+        ("split" | "rsplit", 0) => {
+            parse_quote! { #r.split_whitespace()#collect }
+        }
+        ("split" | "rsplit", 1) if none(0) => {
+            parse_quote! { #r.split_whitespace()#collect }
+        }
+        ("split" | "rsplit", 1) => {
+            let sep = p(0);
+            parse_quote! { #r.split(#sep)#collect }
+        }
+        ("split" | "rsplit", 2)
+            if matches!(args[1].arg.hir, HirExpr::Literal(Literal::Int(-1))) =>
+        {
+            let sep = p(0);
+            parse_quote! { #r.split(#sep)#collect }
+        }
+        ("split" | "rsplit", 2) if none(0) => transpile_bail!(ErrorKind::UnsupportedFeature(
+            format!("{method}() on whitespace with a maxsplit")
+        )),
+        ("split", 2) => {
+            let sep = p(0);
+            let pieces = pieces(&args[1]);
+            parse_quote! { #r.splitn(#pieces, #sep)#collect }
+        }
+        ("rsplit", 2) => {
+            let sep = p(0);
+            let pieces = pieces(&args[1]);
+            parse_quote! {
+                {
+                    let mut parts = #r.rsplitn(#pieces, #sep)#collect;
+                    parts.reverse();
+                    parts
+                }
+            }
+        }
+        ("splitlines", 0) => {
+            helpers.insert(Helper::SplitLines);
+            let name = Helper::SplitLines.name();
+            let s = &recv.lent;
+            parse_quote! { #name(#s) }
+        }
+        ("join", 1) => {
+            let sep = &recv.lent;
+            let items = &args[0].arg;
+            let list = match items.hir {
+                HirExpr::Var(_) | HirExpr::Attribute { .. } | HirExpr::List(_) => true,
+                HirExpr::MethodCall { method, .. } => {
+                    is_method(method) && matches!(method_type(method), Type::List(_))
+                }
+                _ => matches!(items.ty, Type::List(_)),
+            };
+            if list {
+                let items = builtins::operand_expr(&items.expr);
+                parse_quote! { #items.join(#sep) }
+            } else {
+                let items = builtins::iterator(items, false);
+                parse_quote! { #items.collect::<Vec<String>>().join(#sep) }
+            }
+        }
+        ("replace", 2) => {
+            let (old, new) = (p(0), p(1));
+            parse_quote! { #r.replace(#old, #new) }
+        }
+        ("replace", 3) => {
+            let (old, new) = (p(0), p(1));
+            let count = usize_count(&args[2].arg);
+            parse_quote! { #r.replacen(#old, #new, #count) }
+        }
+        ("startswith" | "endswith", 1) => {
+            let test = syn::Ident::new(
+                if method == "startswith" {
+                    "starts_with"
+                } else {
+                    "ends_with"
+                },
+                proc_macro2::Span::call_site(),
+            );
+            match args[0].arg.hir {
+                HirExpr::Tuple(options) => {
+                    let options = options
+                        .iter()
+                        .map(|option| match option {
+                            HirExpr::Literal(Literal::String(s)) => Ok(s.as_str()),
+                            _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+                                "{method}() of a tuple other than of string literals"
+                            ))),
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    with_receiver(&r, |s| {
+                        parse_quote! { [#(#options),*].iter().any(|__p| #s.#test(__p)) }
+                    })
+                }
+                _ => {
+                    let x = p(0);
+                    parse_quote! { #r.#test(#x) }
+                }
+            }
+        }
+        ("upper", 0) => parse_quote! { #r.to_uppercase() },
+        ("lower" | "casefold", 0) => parse_quote! { #r.to_lowercase() },
+        ("capitalize", 0) => parse_quote! {
+            {
+                let mut __chars = #r.chars();
+                match __chars.next() {
+                    Some(__first) => __first
+                        .to_uppercase()
+                        .chain(__chars.flat_map(char::to_lowercase))
+                        .collect::<String>(),
+                    None => String::new(),
+                }
+            }
+        },
+        ("swapcase", 0) => parse_quote! {
+            #r.chars()
+                .map(|__c| {
+                    if __c.is_uppercase() {
+                        __c.to_lowercase().collect::<String>()
+                    } else {
+                        __c.to_uppercase().collect::<String>()
+                    }
+                })
+                .collect::<String>()
+        },
+        // A letter following a letter is lowercase, any other uppercase
+        ("title", 0) => parse_quote! {
+            {
+                let mut __cased = false;
+                #r.chars()
+                    .map(|__c| {
+                        let __title: String = if __cased {
+                            __c.to_lowercase().collect()
+                        } else {
+                            __c.to_uppercase().collect()
+                        };
+                        __cased = __c.is_alphabetic();
+                        __title
+                    })
+                    .collect::<String>()
+            }
+        },
+        // Python counts in characters where Rust counts in bytes
+        ("find" | "rfind" | "index" | "rindex", 1) => {
+            let sub = p(0);
+            let search = syn::Ident::new(
+                if method.starts_with('r') {
+                    "rfind"
+                } else {
+                    "find"
+                },
+                proc_macro2::Span::call_site(),
+            );
+            with_receiver(&r, |s| {
+                let found = quote! {
+                    #s.#search(#sub).map(|__i| #s[..__i].chars().count() as #int_type)
+                };
+                if method.ends_with("find") {
+                    parse_quote! { #found.unwrap_or(-1) }
+                } else {
+                    parse_quote! { #found.expect("substring not found") }
+                }
+            })
+        }
+        ("count", 1) => {
+            let sub = p(0);
+            parse_quote! { #r.matches(#sub).count() as #int_type }
+        }
+        // Not empty, and every character of the class
+        ("isdigit" | "isdecimal" | "isnumeric" | "isalpha" | "isalnum" | "isspace", 0) => {
+            let test: syn::Expr = match method {
+                "isdigit" | "isdecimal" => parse_quote! { |__c: char| __c.is_ascii_digit() },
+                "isnumeric" => parse_quote! { char::is_numeric },
+                "isalpha" => parse_quote! { char::is_alphabetic },
+                "isalnum" => parse_quote! { char::is_alphanumeric },
+                _ => parse_quote! { char::is_whitespace },
+            };
+            if is_place(&r) {
+                parse_quote! { !#r.is_empty() && #r.chars().all(#test) }
+            } else {
+                parse_quote! { #r.chars().map(#test).reduce(|__a, __b| __a && __b).unwrap_or(false) }
+            }
+        }
+        // At least one cased character, and all in the case asked for
+        ("isupper" | "islower", 0) => {
+            let case = syn::Ident::new(
+                if method == "isupper" {
+                    "is_uppercase"
+                } else {
+                    "is_lowercase"
+                },
+                proc_macro2::Span::call_site(),
+            );
+            parse_quote! {
+                #r.chars()
+                    .filter(|__c| __c.is_uppercase() || __c.is_lowercase())
+                    .map(char::#case)
+                    .reduce(|__a, __b| __a && __b)
+                    .unwrap_or(false)
+            }
+        }
+        // Zeros go after a sign
+        ("zfill", 1) => {
+            let width = usize_count(&args[0].arg);
+            bound_receiver(&r, |s| {
+                parse_quote! {
+                    {
+                        let __width: usize = #width;
+                        let __pad = "0".repeat(__width.saturating_sub(#s.chars().count()));
+                        match #s.strip_prefix(['+', '-']) {
+                            Some(__digits) => format!("{}{__pad}{__digits}", &#s[..1]),
+                            None => format!("{__pad}{}", #s),
+                        }
+                    }
+                }
+            })
+        }
+        ("ljust" | "rjust", 1 | 2) => {
+            let width = usize_count(&args[0].arg);
+            let fill = fill_char(method, args)?;
+            let align = if method == "ljust" { '<' } else { '>' };
+            let format = format!("{{:{fill}{align}1$}}");
+            parse_quote! { format!(#format, #r, #width) }
+        }
+        // Python puts the odd padding character on the left for an odd width
+        ("center", 1 | 2) => {
+            let width = usize_count(&args[0].arg);
+            let fill = fill_char(method, args)?.to_string();
+            bound_receiver(&r, |s| {
+                parse_quote! {
+                    {
+                        let __width: usize = #width;
+                        let __margin = __width.saturating_sub(#s.chars().count());
+                        let __left = __margin / 2 + (__margin & __width & 1);
+                        format!(
+                            "{}{}{}",
+                            #fill.repeat(__left),
+                            #s,
+                            #fill.repeat(__margin - __left)
+                        )
+                    }
+                }
+            })
+        }
+        ("removeprefix" | "removesuffix", 1) => {
+            let strip = syn::Ident::new(
+                if method == "removeprefix" {
+                    "strip_prefix"
+                } else {
+                    "strip_suffix"
+                },
+                proc_macro2::Span::call_site(),
+            );
+            let affix = p(0);
+            let whole = &recv.lent;
+            if is_place(&r) {
+                parse_quote! { #r.#strip(#affix).unwrap_or(#whole).to_string() }
+            } else {
+                bound_receiver(&r, |s| {
+                    parse_quote! { #s.#strip(#affix).unwrap_or(#s).to_string() }
+                })
+            }
+        }
+        _ => transpile_bail!(ErrorKind::CodeGenerationError(format!(
+            "Invalid number of arguments for str.{method}()"
+        ))),
+    };
+    Ok(Some(expr))
+}
+
+/// The `trim` method doing what `method` does
+fn trim(method: &str, suffix: &str) -> &'static str {
+    match (method, suffix) {
+        ("strip", "") => "trim",
+        ("lstrip", "") => "trim_start",
+        ("rstrip", "") => "trim_end",
+        ("strip", _) => "trim_matches",
+        ("lstrip", _) => "trim_start_matches",
+        _ => "trim_end_matches",
+    }
+}
+
+/// The number of pieces a split with `maxsplit` makes at most
+fn pieces(maxsplit: &StrArg) -> syn::Expr {
+    match maxsplit.arg.hir {
+        HirExpr::Literal(Literal::Int(n)) => {
+            let n = syn::LitInt::new(&(n + 1).to_string(), proc_macro2::Span::call_site());
+            parse_quote! { #n }
+        }
+        _ => {
+            let n = builtins::operand_expr(&maxsplit.arg.expr);
+            parse_quote! { #n as usize + 1 }
+        }
+    }
+}
+
+/// The padding character of `ljust`, `rjust` or `center`
+fn fill_char(method: &str, args: &[StrArg]) -> Result<char> {
+    let Some(fill) = args.get(1) else {
+        return Ok(' ');
+    };
+    match fill.arg.hir {
+        HirExpr::Literal(Literal::String(s)) if s.chars().count() == 1 && s != "{" && s != "}" => {
+            Ok(s.chars().next().unwrap())
+        }
+        _ => transpile_bail!(ErrorKind::UnsupportedFeature(format!(
+            "{method}() with a fill character other than a one-character literal"
+        ))),
+    }
+}
+
+/// Whether `expr` reads a place or a literal, which may be read again
+pub(crate) fn is_place(expr: &syn::Expr) -> bool {
+    matches!(
+        expr,
+        syn::Expr::Path(_) | syn::Expr::Field(_) | syn::Expr::Lit(_) | syn::Expr::Paren(_)
+    )
+}
+
+/// `body` of a receiver it refers to more than once, bound first unless
+/// it is a place
+pub(crate) fn with_receiver(
+    recv: &syn::Expr,
+    body: impl FnOnce(&syn::Expr) -> syn::Expr,
+) -> syn::Expr {
+    if is_place(recv) {
+        body(recv)
+    } else {
+        bound_receiver(recv, body)
+    }
+}
+
+/// `body` of a receiver bound to `__s`
+fn bound_receiver(recv: &syn::Expr, body: impl FnOnce(&syn::Expr) -> syn::Expr) -> syn::Expr {
+    let stmts = match body(&parse_quote! { __s }) {
+        syn::Expr::Block(block) => block.block.stmts,
+        body => vec![syn::Stmt::Expr(body, None)],
+    };
+    parse_quote! {
+        {
+            let __s = &#recv;
+            #(#stmts)*
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> HirExpr {
+        HirExpr::Var(name.to_string())
+    }
+
+    fn arg<'a>(hir: &'a HirExpr, ty: Type) -> Arg<'a> {
+        let expr = match hir {
+            HirExpr::Var(name) => syn::parse_str(name).unwrap(),
+            HirExpr::Literal(Literal::Int(n)) => syn::parse_str(&n.to_string()).unwrap(),
+            HirExpr::Literal(Literal::String(s)) => parse_quote! { #s.to_string() },
+            _ => parse_quote! { f() },
+        };
+        Arg {
+            hir,
+            expr,
+            ty,
+            movable: false,
+        }
+    }
+
+    fn fstring(parts: &[FStringPart], values: &[Arg]) -> String {
+        let expr = lower_fstring(parts, values, &mut BTreeSet::new()).unwrap();
+        quote! { #expr }.to_string()
+    }
+
+    fn field(value: usize, spec: &str) -> FStringPart {
+        FStringPart::Field {
+            value,
+            conversion: None,
+            spec: spec.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = parse_spec("*^+#010,.3f").unwrap();
+        assert_eq!(spec.fill, Some('*'));
+        assert_eq!(spec.align, Some('^'));
+        assert_eq!(spec.sign, Some('+'));
+        assert!(spec.alternate && spec.zero);
+        assert_eq!(spec.width, Some(Count::Literal(10)));
+        assert_eq!(spec.grouping, Some(','));
+        assert_eq!(spec.precision, Some(Count::Literal(3)));
+        assert_eq!(spec.kind, Some('f'));
+        let spec = parse_spec(">{1}.{2}").unwrap();
+        assert_eq!(spec.width, Some(Count::Value(1)));
+        assert_eq!(spec.precision, Some(Count::Value(2)));
+        assert!(parse_spec("10q2").is_err());
+        assert!(parse_spec(".f").is_err());
+    }
+
+    #[test]
+    fn test_lower_fstring_specs() {
+        let (x, n, name, width) = (var("x"), var("n"), var("name"), var("width"));
+        let values = [
+            arg(&x, Type::Float),
+            arg(&n, Type::Int),
+            arg(&name, Type::String),
+            arg(&width, Type::Int),
+        ];
+        let parts = [
+            field(0, ".2f"),
+            FStringPart::Text(" ".to_string()),
+            field(1, ">8"),
+            FStringPart::Text(" ".to_string()),
+            field(2, "<{3}"),
+            FStringPart::Text(" {".to_string()),
+            field(1, "08x"),
+            field(0, ".1%"),
+            field(1, ""),
+            field(0, ""),
+        ];
+        assert_eq!(
+            fstring(&parts, &values),
+            "format ! (\"{x:.2} {n:>8} {name:<width$} {{{n:08x}{:.1}%{n}{x:?}\" , (x * 100.0) , width = (width . max (0) as usize))"
+        );
+        for spec in ["{0:,}", "{0:g}", "{0: d}", "{0:.2}"] {
+            let spec = &spec[3..spec.len() - 1];
+            assert!(
+                lower_fstring(&[field(0, spec)], &values, &mut BTreeSet::new()).is_err(),
+                "{spec}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_lower_fstring_values() {
+        let (flag, maybe, items, call) = (
+            var("flag"),
+            var("maybe"),
+            var("items"),
+            HirExpr::Tuple(vec![]),
+        );
+        let values = [
+            arg(&flag, Type::Bool),
+            arg(&maybe, Type::Optional(Box::new(Type::Int))),
+            arg(&items, Type::List(Box::new(Type::Int))),
+            arg(&call, Type::Unknown),
+        ];
+        let parts = [
+            field(0, ""),
+            field(1, ""),
+            field(2, ""),
+            FStringPart::Field {
+                value: 3,
+                conversion: Some('r'),
+                spec: String::new(),
+            },
+            field(3, ""),
+            field(3, ""),
+        ];
+        assert_eq!(
+            fstring(&parts, &values),
+            "format ! (\"{0}{1}{items:?}{2:?}{2}{2}\" , if flag { \"True\" } else { \"False\" } , maybe . as_ref () . map_or_else (| | \"None\" . to_string () , | v | v . to_string ()) , f ())"
+        );
+        assert_eq!(fstring(&[field(3, "")], &values), "f () . to_string ()");
+        let (name, n) = (var("name"), var("n"));
+        let reprs = [
+            FStringPart::Field {
+                value: 0,
+                conversion: Some('r'),
+                spec: String::new(),
+            },
+            FStringPart::Field {
+                value: 1,
+                conversion: Some('r'),
+                spec: String::new(),
+            },
+        ];
+        let mut helpers = BTreeSet::new();
+        let values = [arg(&name, Type::String), arg(&n, Type::Int)];
+        let expr = lower_fstring(&reprs, &values, &mut helpers).unwrap();
+        assert_eq!(
+            quote! { #expr }.to_string(),
+            "format ! (\"{}{n}\" , py_repr (& name))"
+        );
+        assert!(helpers.contains(&Helper::Repr));
+        let text = HirExpr::Literal(Literal::String("a {b}".to_string()));
+        assert_eq!(
+            fstring(&[field(0, "")], &[arg(&text, Type::String)]),
+            "format ! (\"a {{b}}\")"
+        );
+    }
+
+    #[test]
+    fn test_lower_method() {
+        let s = var("s");
+        let sep = HirExpr::Literal(Literal::String(",".to_string()));
+        let two = HirExpr::Literal(Literal::Int(2));
+        let str_arg = |hir| StrArg {
+            arg: arg(hir, Type::String),
+            lent: match hir {
+                HirExpr::Literal(Literal::String(s)) => parse_quote! { #s },
+                _ => parse_quote! { &s },
+            },
+        };
+        let int_type: syn::Type = parse_quote! { i32 };
+        let lower = |method: &str, args: &[StrArg]| {
+            let expr = lower_method(&str_arg(&s), method, args, &int_type, &mut BTreeSet::new())
+                .unwrap()
+                .unwrap();
+            quote! { #expr }.to_string()
+        };
+        assert_eq!(
+            lower("split", &[]),
+            "s . split_whitespace () . map (| s | s . to_string ()) . collect :: < Vec < String > > ()"
+        );
+        let mut helpers = BTreeSet::new();
+        let expr = lower_method(&str_arg(&s), "splitlines", &[], &int_type, &mut helpers);
+        let expr = expr.unwrap().unwrap();
+        assert_eq!(quote! { #expr }.to_string(), "py_splitlines (& s)");
+        assert!(helpers.contains(&Helper::SplitLines));
+        assert_eq!(
+            lower("split", &[str_arg(&sep), str_arg(&two)]),
+            "s . splitn (3 , \",\") . map (| s | s . to_string ()) . collect :: < Vec < String > > ()"
+        );
+        assert_eq!(
+            lower("find", &[str_arg(&sep)]),
+            "s . find (\",\") . map (| __i | s [.. __i] . chars () . count () as i32) . unwrap_or (- 1)"
+        );
+        assert_eq!(
+            lower("count", &[str_arg(&sep)]),
+            "s . matches (\",\") . count () as i32"
+        );
+        assert_eq!(
+            lower("rjust", &[str_arg(&two)]),
+            "format ! (\"{: >1$}\" , s , 2)"
+        );
+        assert!(
+            lower_method(&str_arg(&s), "encode", &[], &int_type, &mut BTreeSet::new())
+                .unwrap()
+                .is_none()
+        );
+        assert!(lower_method(
+            &str_arg(&s),
+            "upper",
+            &[str_arg(&sep)],
+            &int_type,
+            &mut BTreeSet::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_string_programs_run() {
+        let python = r#"
+from typing import List
+
+def show(name: str, n: int, ok: bool) -> str:
+    return f"{name!r} {n!r} {ok!r}"
+
+def lines(text: str) -> List[str]:
+    return text.splitlines()
+
+def vowels(s: str) -> int:
+    n = 0
+    for c in s:
+        if c in "aeiou":
+            n += 1
+    return n
+
+def doubled(s: str) -> str:
+    out = ""
+    for c in s.upper():
+        out += c + c
+    return out
+
+def letters(words: List[str]) -> List[str]:
+    return [c for w in words for c in w if c != "a"]
+
+def initials() -> List[str]:
+    s = "ab" + " c"
+    return [c.upper() for c in s if c != " "]
+"#;
+        let main = r#"println!("{}", show("it's", 3, true));
+println!("{:?}", lines("a\r\nb\u{2028}c\n"));
+let words = vec!["ab".to_string(), "ca".to_string()];
+println!("{} {} {:?} {:?}", vowels("education"), doubled("ab"), letters(&words), initials());"#;
+        assert_eq!(
+            crate::test_programs::run(python, main),
+            "\"it's\" 3 True\n[\"a\", \"b\", \"c\"]\n5 AABB [\"b\", \"c\"] [\"A\", \"B\", \"C\"]\n"
+        );
+    }
+}
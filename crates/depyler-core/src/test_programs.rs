@@ -0,0 +1,151 @@
+//! Running generated code in tests
+//!
+//! A test transpiles a Python module, appends a Rust `main` calling its
+//! functions, and compares what the program prints with what Python would.
+//! Code using `num_bigint` is built by cargo, offline; other code by rustc.
+//! Code expected to be idiomatic is also checked by clippy. The crate of a
+//! package is built with a binary calling it.
+
+use crate::DepylerPipeline;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static PROGRAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// What the transpiled `python` followed by `fn main() { main }` prints
+pub(crate) fn run(python: &str, main: &str) -> String {
+    let code = DepylerPipeline::new()
+        .transpile(python)
+        .unwrap_or_else(|err| panic!("transpiling failed: {err}"));
+    run_rust(&code, main)
+}
+
+/// What `code` followed by `fn main() { main }` prints
+pub(crate) fn run_rust(code: &str, main: &str) -> String {
+    let dir = program_dir();
+    let source = format!("{code}\n\nfn main() {{\n{main}\n}}\n");
+    let binary = if code.contains("num_bigint") {
+        build_with_cargo(&dir, &source)
+    } else {
+        build_with_rustc(&dir, &source)
+    };
+    run_binary(&dir, &binary)
+}
+
+fn run_binary(dir: &Path, binary: &Path) -> String {
+    let output = Command::new(binary).output().expect("running the program");
+    let _ = std::fs::remove_dir_all(dir);
+    assert!(
+        output.status.success(),
+        "the program failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// What a binary with `fn main() { main }` prints, built against the crate
+/// of `files` generated from a package
+pub(crate) fn run_crate(files: &BTreeMap<PathBuf, String>, main: &str) -> String {
+    let dir = program_dir();
+    for (path, code) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, code).unwrap();
+    }
+    let manifest = dir.join("Cargo.toml");
+    let bin = "\n[[bin]]\nname = \"program\"\npath = \"program.rs\"\n";
+    let manifest_text = std::fs::read_to_string(&manifest).unwrap();
+    // The bin table goes before `[dependencies]`, which ends the manifest
+    let manifest_text = match manifest_text.split_once("\n[dependencies]") {
+        Some((package, dependencies)) => format!("{package}{bin}\n[dependencies]{dependencies}"),
+        None => format!("{manifest_text}{bin}"),
+    };
+    std::fs::write(&manifest, manifest_text).unwrap();
+    let source = format!("fn main() {{\n{main}\n}}\n");
+    std::fs::write(dir.join("program.rs"), &source).unwrap();
+    let output = Command::new("cargo")
+        .args(["build", "--quiet", "--offline"])
+        .env("RUSTFLAGS", "-A warnings")
+        .current_dir(&dir)
+        .output()
+        .expect("running cargo");
+    assert_built(&output, &source);
+    run_binary(&dir, &dir.join("target/debug/program"))
+}
+
+/// Assert clippy finds nothing to warn about in the transpiled `python`
+pub(crate) fn assert_lint_free(python: &str) {
+    let code = DepylerPipeline::new()
+        .transpile(python)
+        .unwrap_or_else(|err| panic!("transpiling failed: {err}"));
+    let dir = program_dir();
+    let source = dir.join("lib.rs");
+    std::fs::write(&source, &code).unwrap();
+    let output = Command::new("clippy-driver")
+        .args([
+            "--edition",
+            "2021",
+            "--crate-type",
+            "lib",
+            "-D",
+            "warnings",
+            "-o",
+        ])
+        .arg(dir.join("lib.rlib"))
+        .arg(&source)
+        .output()
+        .expect("running clippy-driver");
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(
+        output.status.success(),
+        "clippy warns about:\n{code}\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn program_dir() -> PathBuf {
+    let id = PROGRAMS.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("depyler-program-{}-{id}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn build_with_rustc(dir: &Path, source: &str) -> PathBuf {
+    let main = dir.join("main.rs");
+    let binary = dir.join("main");
+    std::fs::write(&main, source).unwrap();
+    let output = Command::new("rustc")
+        .args(["--edition", "2021", "-A", "warnings", "-o"])
+        .arg(&binary)
+        .arg(&main)
+        .output()
+        .expect("running rustc");
+    assert_built(&output, source);
+    binary
+}
+
+fn build_with_cargo(dir: &Path, source: &str) -> PathBuf {
+    let manifest = "[package]\nname = \"program\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+                    [dependencies]\nnum-bigint = \"0.4\"\nnum-traits = \"0.2\"\n";
+    std::fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("src/main.rs"), source).unwrap();
+    let output = Command::new("cargo")
+        .args(["build", "--quiet", "--offline"])
+        .env("RUSTFLAGS", "-A warnings")
+        .current_dir(dir)
+        .output()
+        .expect("running cargo");
+    assert_built(&output, source);
+    dir.join("target/debug/program")
+}
+
+fn assert_built(output: &std::process::Output, source: &str) {
+    assert!(
+        output.status.success(),
+        "the program does not compile:\n{source}\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,310 @@
+//! Enums generated for `Union` types and `Literal` types of strings
+//!
+//! `Union[int, str]` becomes `IntOrStr`, an enum with a variant holding a
+//! value of each member type and a `From` impl for each, so a value of a
+//! member type converts into it with `.into()`. `Literal["r", "w"]`
+//! becomes `ROrW`, a fieldless enum with a variant for each string that
+//! displays as the string it stands for.
+
+use crate::error::ErrorKind;
+use crate::hir::{HirModule, Type};
+use crate::transpile_bail;
+use anyhow::Result;
+use quote::quote;
+use syn::parse_quote;
+
+/// The name of the enum generated for `ty`, or `None` when it is not a
+/// `Union` or `Literal` type
+pub fn enum_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Union(members) => Some(
+            members
+                .iter()
+                .map(variant_name)
+                .collect::<Vec<_>>()
+                .join("Or"),
+        ),
+        Type::Literal(values) => Some(literal_variants(values).join("Or")),
+        _ => None,
+    }
+}
+
+/// The variant of a union holding a value of type `ty`
+fn variant_name(ty: &Type) -> String {
+    match ty {
+        Type::Unknown => "Value".to_string(),
+        Type::Int => "Int".to_string(),
+        Type::Float => "Float".to_string(),
+        Type::String => "Str".to_string(),
+        Type::Bool => "Bool".to_string(),
+        Type::None => "None".to_string(),
+        Type::List(item) => format!("{}List", variant_name(item)),
+        Type::Dict(key, value) => format!("{}{}Dict", variant_name(key), variant_name(value)),
+        Type::Set(item) => format!("{}Set", variant_name(item)),
+        Type::Tuple(items) => format!(
+            "{}Tuple",
+            items.iter().map(variant_name).collect::<String>()
+        ),
+        Type::Optional(inner) => format!("Optional{}", variant_name(inner)),
+        Type::Function { .. } => "Function".to_string(),
+        Type::Union(_) | Type::Literal(_) => enum_name(ty).expect("a generated enum"),
+        Type::Custom(name) => name.chars().filter(|c| c.is_alphanumeric()).collect(),
+    }
+}
+
+/// The variants of a `Literal` enum, in the order of `values`: each string
+/// in camel case, made unique and a valid identifier
+fn literal_variants(values: &[String]) -> Vec<String> {
+    let mut variants: Vec<String> = Vec::new();
+    for value in values {
+        let mut variant: String = value
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let mut chars = word.chars();
+                let first = chars.next().expect("words are not empty");
+                first.to_uppercase().chain(chars).collect::<String>()
+            })
+            .collect();
+        if !variant.starts_with(|c: char| c.is_alphabetic()) {
+            variant.insert(0, 'V');
+        }
+        if variants.contains(&variant) {
+            variant = format!("{variant}{}", variants.len());
+        }
+        variants.push(variant);
+    }
+    variants
+}
+
+/// The variant of `Literal` type `ty` standing for `value`
+pub fn literal_variant(ty: &Type, value: &str) -> Result<syn::Expr> {
+    let Type::Literal(values) = ty else {
+        unreachable!("only `Literal` types have literal variants")
+    };
+    let Some(position) = values.iter().position(|v| v == value) else {
+        transpile_bail!(ErrorKind::TypeInferenceError(format!(
+            "{value:?} is not one of Literal{values:?}"
+        )));
+    };
+    let name = ident(&enum_name(ty).expect("a generated enum"));
+    let variant = ident(&literal_variants(values)[position]);
+    Ok(parse_quote! { #name::#variant })
+}
+
+/// The variant of `Union` type `ty` holding a value of type `member`, if
+/// that is one of its members
+pub fn union_variant(ty: &Type, member: &Type) -> Option<syn::Path> {
+    let Type::Union(members) = ty else {
+        return None;
+    };
+    if !members.contains(member) {
+        return None;
+    }
+    let name = ident(&enum_name(ty).expect("a generated enum"));
+    let variant = ident(&variant_name(member));
+    Some(parse_quote! { #name::#variant })
+}
+
+fn ident(name: &str) -> syn::Ident {
+    syn::Ident::new(name, proc_macro2::Span::call_site())
+}
+
+/// The `Union` and `Literal` types of the module's signatures and fields,
+/// each once, in the order they first appear
+pub fn module_enum_types(module: &HirModule) -> Vec<Type> {
+    let mut types = Vec::new();
+    let functions = module.functions.iter().map(|f| (&f.params, &f.ret_type));
+    let methods = module
+        .classes
+        .iter()
+        .flat_map(|c| &c.methods)
+        .map(|m| (&m.params, &m.ret_type));
+    for (params, ret_type) in functions.chain(methods) {
+        for (_, ty) in params.iter() {
+            collect_enum_types(ty, &mut types);
+        }
+        collect_enum_types(ret_type, &mut types);
+    }
+    for field in module.classes.iter().flat_map(|c| &c.fields) {
+        collect_enum_types(&field.field_type, &mut types);
+    }
+    types
+}
+
+fn collect_enum_types(ty: &Type, types: &mut Vec<Type>) {
+    match ty {
+        Type::Union(members) => {
+            members.iter().for_each(|m| collect_enum_types(m, types));
+            if !types.contains(ty) {
+                types.push(ty.clone());
+            }
+        }
+        Type::Literal(_) if !types.contains(ty) => types.push(ty.clone()),
+        Type::List(inner) | Type::Set(inner) | Type::Optional(inner) => {
+            collect_enum_types(inner, types)
+        }
+        Type::Dict(key, value) => {
+            collect_enum_types(key, types);
+            collect_enum_types(value, types);
+        }
+        Type::Tuple(items) => items.iter().for_each(|t| collect_enum_types(t, types)),
+        Type::Function { params, ret } => {
+            params.iter().for_each(|t| collect_enum_types(t, types));
+            collect_enum_types(ret, types);
+        }
+        _ => {}
+    }
+}
+
+/// Whether the enum generated for `ty` implements `Display`, showing its
+/// values as Python shows them: those of `Literal` types, and of unions of
+/// numbers, strings and literals
+pub fn displays_as_python(ty: &Type) -> bool {
+    match ty {
+        Type::Literal(_) => true,
+        Type::Union(members) => members.iter().all(|t| {
+            matches!(
+                t,
+                Type::Int | Type::Float | Type::String | Type::Bool | Type::Literal(_)
+            )
+        }),
+        _ => false,
+    }
+}
+
+/// The enum generated for `ty`; `members` are the Rust types of the
+/// members of a `Union`
+pub fn definition(ty: &Type, members: &[syn::Type]) -> proc_macro2::TokenStream {
+    let name = ident(&enum_name(ty).expect("a generated enum"));
+    match ty {
+        Type::Union(types) => {
+            let variants: Vec<syn::Ident> = types.iter().map(|t| ident(&variant_name(t))).collect();
+            // A member type shared by two members converts into neither
+            let conversions = variants
+                .iter()
+                .zip(members)
+                .filter(|(_, member)| members.iter().filter(|m| m == member).count() == 1)
+                .map(|(variant, member)| {
+                    quote! {
+                        impl From<#member> for #name {
+                            fn from(value: #member) -> Self {
+                                #name::#variant(value)
+                            }
+                        }
+                    }
+                });
+            let display = displays_as_python(ty).then(|| {
+                let arms = variants.iter().zip(types).map(|(variant, member)| match member {
+                    // Rust shows `1.0` as `1`, where its debug format shows `1.0`
+                    Type::Float => quote! { #name::#variant(value) => write!(f, "{:?}", value) },
+                    Type::Bool => quote! {
+                        #name::#variant(value) => f.write_str(if *value { "True" } else { "False" })
+                    },
+                    _ => quote! { #name::#variant(value) => write!(f, "{}", value) },
+                });
+                quote! {
+                    impl std::fmt::Display for #name {
+                        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                            match self {
+                                #(#arms,)*
+                            }
+                        }
+                    }
+                }
+            });
+            quote! {
+                #[derive(Debug, Clone, PartialEq)]
+                pub enum #name {
+                    #(#variants(#members),)*
+                }
+                #(#conversions)*
+                #display
+            }
+        }
+        Type::Literal(values) => {
+            let variants: Vec<syn::Ident> =
+                literal_variants(values).iter().map(|v| ident(v)).collect();
+            quote! {
+                #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+                pub enum #name {
+                    #(#variants,)*
+                }
+                impl #name {
+                    /// The string the variant stands for
+                    pub fn as_str(&self) -> &'static str {
+                        match self {
+                            #(#name::#variants => #values,)*
+                        }
+                    }
+                }
+                impl std::fmt::Display for #name {
+                    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        f.write_str(self.as_str())
+                    }
+                }
+            }
+        }
+        _ => unreachable!("only `Union` and `Literal` types generate enums"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(values: &[&str]) -> Type {
+        Type::Literal(values.iter().map(|v| v.to_string()).collect())
+    }
+
+    #[test]
+    fn test_enum_names() {
+        let union = Type::Union(vec![Type::Int, Type::String]);
+        assert_eq!(enum_name(&union).unwrap(), "IntOrStr");
+        let nested = Type::Union(vec![
+            Type::List(Box::new(Type::Int)),
+            Type::Custom("Point".to_string()),
+        ]);
+        assert_eq!(enum_name(&nested).unwrap(), "IntListOrPoint");
+        assert_eq!(
+            enum_name(&literal(&["read", "read-write"])).unwrap(),
+            "ReadOrReadWrite"
+        );
+        assert_eq!(
+            literal_variants(&["2d".to_string(), "".to_string()]),
+            ["V2d", "V"]
+        );
+        assert_eq!(
+            literal_variants(&["a b".to_string(), "a_b".to_string()]),
+            ["AB", "AB1"]
+        );
+        assert!(enum_name(&Type::Int).is_none());
+    }
+
+    #[test]
+    fn test_literal_variant() {
+        let mode = literal(&["r", "w"]);
+        let variant = literal_variant(&mode, "w").unwrap();
+        assert_eq!(quote! { #variant }.to_string(), "ROrW :: W");
+        assert!(literal_variant(&mode, "x").is_err());
+    }
+
+    #[test]
+    fn test_union_definition() {
+        let union = Type::Union(vec![Type::Int, Type::String]);
+        let members: [syn::Type; 2] = [parse_quote! { i32 }, parse_quote! { String }];
+        let code = definition(&union, &members).to_string();
+        assert!(code.contains("pub enum IntOrStr { Int (i32) , Str (String) , }"));
+        assert!(code.contains("impl From < String > for IntOrStr"));
+        assert!(code.contains("impl std :: fmt :: Display for IntOrStr"));
+    }
+
+    #[test]
+    fn test_module_enum_types_are_collected_once() {
+        let union = Type::Union(vec![Type::Int, Type::Float]);
+        let mut types = Vec::new();
+        collect_enum_types(&Type::List(Box::new(union.clone())), &mut types);
+        collect_enum_types(&Type::Optional(Box::new(union.clone())), &mut types);
+        assert_eq!(types, [union]);
+    }
+}
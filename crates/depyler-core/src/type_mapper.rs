@@ -1,4 +1,6 @@
+use crate::context_managers;
 use crate::hir::Type as PythonType;
+use crate::type_enums;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -10,9 +12,9 @@ pub enum IntWidth {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StringStrategy {
-    AlwaysOwned,    // String everywhere (safe, simple)
-    InferBorrowing, // &str where possible (V1.1)
-    CowByDefault,   // Cow<'static, str> (V1.2)
+    AlwaysOwned,    // String and owned containers everywhere (safe, simple)
+    InferBorrowing, // &str, &[T] and &HashMap for parameters only read
+    CowByDefault,   // Cow<'_, str> for str parameters not consumed
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,7 +34,12 @@ pub enum RustType {
         lifetime: String,
     },
     Vec(Box<RustType>),
+    /// The unsized `[T]` behind a borrowed list
+    Slice(Box<RustType>),
     HashMap(Box<RustType>, Box<RustType>),
+    HashSet(Box<RustType>),
+    /// A set held in a set or keying a dict, which a `HashSet` cannot be
+    BTreeSet(Box<RustType>),
     Option(Box<RustType>),
     Result(Box<RustType>, Box<RustType>),
     Reference {
@@ -41,6 +48,12 @@ pub enum RustType {
         inner: Box<RustType>,
     },
     Tuple(Vec<RustType>),
+    /// A boxed closure, `Box<dyn Fn(params) -> ret>`; parameters and return
+    /// values take `impl Fn` instead
+    Function {
+        params: Vec<RustType>,
+        ret: Box<RustType>,
+    },
     Unit,
     Custom(String),
     Unsupported(String),
@@ -69,7 +82,7 @@ impl Default for TypeMapper {
     fn default() -> Self {
         Self {
             width_preference: IntWidth::I32,
-            string_type: StringStrategy::AlwaysOwned,
+            string_type: StringStrategy::InferBorrowing,
         }
     }
 }
@@ -98,27 +111,39 @@ impl TypeMapper {
                 IntWidth::ISize => PrimitiveType::ISize,
             }),
             PythonType::Float => RustType::Primitive(PrimitiveType::F64),
-            PythonType::String => match self.string_type {
-                StringStrategy::AlwaysOwned => RustType::String,
-                StringStrategy::InferBorrowing => RustType::String, // V1: Always owned
-                StringStrategy::CowByDefault => RustType::String,   // V1: Always owned
-            },
+            // Only parameters are borrowed, see `borrowing`
+            PythonType::String => RustType::String,
             PythonType::Bool => RustType::Primitive(PrimitiveType::Bool),
             PythonType::None => RustType::Unit,
             PythonType::List(inner) => RustType::Vec(Box::new(self.map_type(inner))),
             PythonType::Dict(k, v) => {
-                RustType::HashMap(Box::new(self.map_type(k)), Box::new(self.map_type(v)))
+                RustType::HashMap(Box::new(self.map_hashable(k)), Box::new(self.map_type(v)))
             }
+            PythonType::Set(inner) => RustType::HashSet(Box::new(self.map_hashable(inner))),
             PythonType::Tuple(types) => {
                 let rust_types = types.iter().map(|t| self.map_type(t)).collect();
                 RustType::Tuple(rust_types)
             }
             PythonType::Optional(inner) => RustType::Option(Box::new(self.map_type(inner))),
-            PythonType::Function { params: _, ret: _ } => {
-                // For V1, we don't map function types directly
-                RustType::Unsupported("function".to_string())
+            PythonType::Function { params, ret } => RustType::Function {
+                params: params.iter().map(|p| self.map_type(p)).collect(),
+                ret: Box::new(self.map_return_type(ret)),
+            },
+            PythonType::Custom(_) if context_managers::is_lock_type(py_type) => {
+                RustType::Custom(context_managers::LOCK_RUST_TYPE.to_string())
             }
             PythonType::Custom(name) => RustType::Custom(name.clone()),
+            PythonType::Union(_) | PythonType::Literal(_) => {
+                RustType::Custom(type_enums::enum_name(py_type).expect("a generated enum"))
+            }
+        }
+    }
+
+    /// Maps the item type of a set or the key type of a dict, which is hashed
+    pub fn map_hashable(&self, py_type: &PythonType) -> RustType {
+        match py_type {
+            PythonType::Set(inner) => RustType::BTreeSet(Box::new(self.map_hashable(inner))),
+            _ => self.map_type(py_type),
         }
     }
 
@@ -132,7 +157,10 @@ impl TypeMapper {
     pub fn needs_reference(&self, rust_type: &RustType) -> bool {
         match rust_type {
             RustType::String => false, // V1: Always owned
-            RustType::Vec(_) | RustType::HashMap(_, _) => true,
+            RustType::Vec(_)
+            | RustType::HashMap(_, _)
+            | RustType::HashSet(_)
+            | RustType::BTreeSet(_) => true,
             RustType::Primitive(_) => false,
             _ => false,
         }
@@ -146,6 +174,45 @@ impl TypeMapper {
             _ => false,
         }
     }
+
+    /// Whether values of a type implement `Clone`, which boxed closures do not
+    #[allow(clippy::only_used_in_recursion)]
+    pub fn can_clone(&self, rust_type: &RustType) -> bool {
+        match rust_type {
+            RustType::Function { .. } => false,
+            RustType::Vec(inner)
+            | RustType::Slice(inner)
+            | RustType::Option(inner)
+            | RustType::HashSet(inner)
+            | RustType::BTreeSet(inner) => self.can_clone(inner),
+            RustType::HashMap(key, value) | RustType::Result(key, value) => {
+                self.can_clone(key) && self.can_clone(value)
+            }
+            RustType::Tuple(types) => types.iter().all(|t| self.can_clone(t)),
+            _ => true,
+        }
+    }
+
+    /// Whether a type can take part in `#[derive(Eq, Hash)]`
+    #[allow(clippy::only_used_in_recursion)]
+    pub fn supports_eq_hash(&self, rust_type: &RustType) -> bool {
+        match rust_type {
+            RustType::Primitive(PrimitiveType::F32 | PrimitiveType::F64) => false,
+            RustType::Primitive(_)
+            | RustType::Unit
+            | RustType::String
+            | RustType::Str { .. }
+            | RustType::Cow { .. } => true,
+            RustType::Vec(inner)
+            | RustType::Slice(inner)
+            | RustType::Option(inner)
+            | RustType::BTreeSet(inner) => self.supports_eq_hash(inner),
+            RustType::Reference { inner, .. } => self.supports_eq_hash(inner),
+            RustType::Tuple(types) => types.iter().all(|t| self.supports_eq_hash(t)),
+            // HashMap and HashSet have no Hash impl; user types may not derive it
+            _ => false,
+        }
+    }
 }
 
 impl RustType {
@@ -162,9 +229,12 @@ impl RustType {
             }
             RustType::Cow { lifetime } => format!("Cow<{lifetime}, str>"),
             RustType::Vec(inner) => format!("Vec<{}>", inner.to_rust_string()),
+            RustType::Slice(inner) => format!("[{}]", inner.to_rust_string()),
             RustType::HashMap(k, v) => {
                 format!("HashMap<{}, {}>", k.to_rust_string(), v.to_rust_string())
             }
+            RustType::HashSet(inner) => format!("HashSet<{}>", inner.to_rust_string()),
+            RustType::BTreeSet(inner) => format!("BTreeSet<{}>", inner.to_rust_string()),
             RustType::Option(inner) => format!("Option<{}>", inner.to_rust_string()),
             RustType::Result(ok, err) => {
                 format!("Result<{}, {}>", ok.to_rust_string(), err.to_rust_string())
@@ -189,6 +259,17 @@ impl RustType {
                     format!("({})", type_strs.join(", "))
                 }
             }
+            RustType::Function { params, ret } => {
+                let params: Vec<String> = params.iter().map(|p| p.to_rust_string()).collect();
+                match ret.as_ref() {
+                    RustType::Unit => format!("Box<dyn Fn({})>", params.join(", ")),
+                    ret => format!(
+                        "Box<dyn Fn({}) -> {}>",
+                        params.join(", "),
+                        ret.to_rust_string()
+                    ),
+                }
+            }
             RustType::Unit => "()".to_string(),
             RustType::Custom(name) => name.clone(),
             RustType::Unsupported(desc) => format!("/* unsupported: {desc} */"),
@@ -226,7 +307,7 @@ mod tests {
     fn test_default_type_mapper() {
         let mapper = TypeMapper::default();
         assert_eq!(mapper.width_preference, IntWidth::I32);
-        assert_eq!(mapper.string_type, StringStrategy::AlwaysOwned);
+        assert_eq!(mapper.string_type, StringStrategy::InferBorrowing);
     }
 
     #[test]
@@ -451,18 +532,27 @@ mod tests {
     }
 
     #[test]
-    fn test_function_type_unsupported() {
+    fn test_function_type() {
         let mapper = TypeMapper::new();
 
         let func_type = PythonType::Function {
             params: vec![PythonType::Int],
             ret: Box::new(PythonType::String),
         };
+        let rust_type = mapper.map_type(&func_type);
+        assert_eq!(
+            rust_type,
+            RustType::Function {
+                params: vec![RustType::Primitive(PrimitiveType::I32)],
+                ret: Box::new(RustType::String),
+            }
+        );
+        assert_eq!(rust_type.to_rust_string(), "Box<dyn Fn(i32) -> String>");
 
-        if let RustType::Unsupported(desc) = mapper.map_type(&func_type) {
-            assert_eq!(desc, "function");
-        } else {
-            panic!("Expected unsupported function type");
-        }
+        let callback = PythonType::Function {
+            params: vec![],
+            ret: Box::new(PythonType::None),
+        };
+        assert_eq!(mapper.map_type(&callback).to_rust_string(), "Box<dyn Fn()>");
     }
 }
@@ -553,27 +553,34 @@ mod tests {
     use smallvec::smallvec;
 
     fn create_test_function(complexity: u32) -> HirFunction {
-        let mut body = vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42))))];
+        let mut body = vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42)))).into()];
 
         // Add if statements to increase complexity
         for i in 0..complexity.saturating_sub(1) {
-            body.push(HirStmt::If {
-                condition: HirExpr::Literal(Literal::Bool(true)),
-                then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(
-                    i as i64,
-                ))))],
-                else_body: None,
-            });
+            body.push(
+                HirStmt::If {
+                    condition: HirExpr::Literal(Literal::Bool(true)),
+                    then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(
+                        i as i64,
+                    ))))
+                    .into()],
+                    else_body: None,
+                }
+                .into(),
+            );
         }
 
         HirFunction {
             name: "test_func".to_string(),
             params: smallvec![],
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body,
             properties: Default::default(),
             annotations: Default::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }
     }
 
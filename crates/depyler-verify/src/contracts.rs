@@ -191,6 +191,12 @@ fn check_expr_contracts(expr: &HirExpr) -> Vec<String> {
                 violations.extend(check_expr_contracts(arg));
             }
         }
+        HirExpr::MethodCall { receiver, args, .. } => {
+            violations.extend(check_expr_contracts(receiver));
+            for arg in args {
+                violations.extend(check_expr_contracts(arg));
+            }
+        }
         _ => {}
     }
 
@@ -201,7 +207,7 @@ fn check_expr_contracts(expr: &HirExpr) -> Vec<String> {
 mod tests {
     use super::*;
     use depyler_annotations::TranspilationAnnotations;
-    use depyler_core::hir::{FunctionProperties, Literal};
+    use depyler_core::hir::{AssignTarget, FunctionProperties, Literal, Spanned};
 
     fn create_test_function(
         name: &str,
@@ -213,11 +219,14 @@ mod tests {
         HirFunction {
             name: name.to_string(),
             params: params.into(),
+            param_spec: Default::default(),
             ret_type,
-            body,
+            body: body.into_iter().map(Spanned::from).collect(),
             properties,
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }
     }
 
@@ -338,6 +347,9 @@ mod tests {
             always_terminates: false,
             panic_free: true,
             max_stack_depth: Some(100),
+            can_fail: false,
+            error_types: vec![],
+            is_generator: false,
         };
 
         let func = create_test_function("safe_function", vec![], Type::Int, vec![], properties);
@@ -358,6 +370,9 @@ mod tests {
             always_terminates: true,
             panic_free: false,
             max_stack_depth: Some(100),
+            can_fail: false,
+            error_types: vec![],
+            is_generator: false,
         };
 
         let func = create_test_function(
@@ -384,6 +399,9 @@ mod tests {
             always_terminates: true,
             panic_free: true,
             max_stack_depth: Some(100),
+            can_fail: false,
+            error_types: vec![],
+            is_generator: false,
         };
 
         let func = create_test_function(
@@ -447,7 +465,7 @@ mod tests {
     #[test]
     fn test_check_contract_violations_with_index() {
         let body = vec![HirStmt::Assign {
-            target: "result".to_string(),
+            target: AssignTarget::Symbol("result".to_string()),
             value: HirExpr::Index {
                 base: Box::new(HirExpr::Var("arr".to_string())),
                 index: Box::new(HirExpr::Literal(Literal::Int(0))),
@@ -502,12 +520,13 @@ mod tests {
     #[test]
     fn test_check_contract_violations_with_control_flow() {
         let then_body = vec![HirStmt::Assign {
-            target: "x".to_string(),
+            target: AssignTarget::Symbol("x".to_string()),
             value: HirExpr::Index {
                 base: Box::new(HirExpr::Var("data".to_string())),
                 index: Box::new(HirExpr::Literal(Literal::Int(0))),
             },
-        }];
+        }
+        .into()];
 
         let body = vec![HirStmt::If {
             condition: HirExpr::Literal(Literal::Bool(true)),
@@ -533,7 +552,7 @@ mod tests {
     fn test_check_contract_violations_no_violations() {
         let body = vec![
             HirStmt::Assign {
-                target: "result".to_string(),
+                target: AssignTarget::Symbol("result".to_string()),
                 value: HirExpr::Literal(Literal::Int(42)),
             },
             HirStmt::Return(Some(HirExpr::Var("result".to_string()))),
@@ -1,6 +1,6 @@
 use crate::{PropertyStatus, TestCase, VerificationMethod, VerificationResult};
 use depyler_annotations::TranspilationAnnotations;
-use depyler_core::hir::{HirExpr, HirFunction, HirStmt, Type};
+use depyler_core::hir::{AssignTarget, HirExpr, HirFunction, HirStmt, Type};
 use std::collections::{HashMap, HashSet};
 
 /// Memory safety analyzer for HIR functions
@@ -117,7 +117,11 @@ impl MemorySafetyAnalyzer {
                 }
 
                 // Register new variable or update existing
-                self.register_variable(target, &self.infer_type(value), true);
+                for (target, value) in target.split_value(value) {
+                    for name in target.symbols() {
+                        self.register_variable(name, &self.infer_type(value), true);
+                    }
+                }
 
                 // Handle moves for non-copy types
                 self.handle_expr_moves(value, annotations);
@@ -182,7 +186,9 @@ impl MemorySafetyAnalyzer {
                 }
 
                 self.scope_depth += 1;
-                self.register_variable(target, &Type::Unknown, false); // Iterator item type
+                for name in target.symbols() {
+                    self.register_variable(name, &Type::Unknown, false); // Iterator item type
+                }
 
                 for stmt in body {
                     if let Some(violation) = self.analyze_statement(stmt, annotations) {
@@ -228,6 +234,13 @@ impl MemorySafetyAnalyzer {
                 None
             }
 
+            HirExpr::MethodCall { receiver, args, .. } => {
+                // The receiver is borrowed, so a moved receiver is still a use-after-move
+                std::iter::once(receiver.as_ref())
+                    .chain(args)
+                    .find_map(|e| self.check_expr_moves(e, location))
+            }
+
             HirExpr::Index { base, index } => {
                 // Check for potential buffer overflow
                 if self.is_unsafe_index(base, index) {
@@ -246,13 +259,12 @@ impl MemorySafetyAnalyzer {
 
     fn handle_expr_moves(&mut self, expr: &HirExpr, annotations: &TranspilationAnnotations) {
         match expr {
-            HirExpr::Var(name) => {
-                // Move non-copy types unless borrowing
+            // Move non-copy types unless borrowing
+            HirExpr::Var(name)
                 if annotations.ownership_model != depyler_annotations::OwnershipModel::Borrowed
-                    && !self.is_copy_type(name)
-                {
-                    self.moved_values.insert(name.clone());
-                }
+                    && !self.is_copy_type(name) =>
+            {
+                self.moved_values.insert(name.clone());
             }
 
             HirExpr::List(items) | HirExpr::Tuple(items) => {
@@ -318,7 +330,10 @@ impl MemorySafetyAnalyzer {
 
     fn check_stmt_for_races(&self, stmt: &HirStmt) -> Option<MemorySafetyViolation> {
         match stmt {
-            HirStmt::Assign { target, .. } => {
+            HirStmt::Assign {
+                target: AssignTarget::Symbol(target),
+                ..
+            } => {
                 // Check if target is shared and mutable without synchronization
                 if self.is_shared_mutable(target) {
                     Some(MemorySafetyViolation::DataRace {
@@ -425,7 +440,7 @@ mod tests {
         };
 
         let stmt = HirStmt::Assign {
-            target: "x".to_string(),
+            target: AssignTarget::Symbol("x".to_string()),
             value: HirExpr::Literal(Literal::Int(42)),
         };
 
@@ -454,15 +469,19 @@ mod tests {
         let func = HirFunction {
             name: "safe_func".to_string(),
             params: smallvec![("x".to_string(), Type::Int)],
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Add,
                 left: Box::new(HirExpr::Var("x".to_string())),
                 right: Box::new(HirExpr::Literal(Literal::Int(1))),
-            }))],
+            }))
+            .into()],
             properties: Default::default(),
             annotations: Default::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         };
 
         let result = analyzer.analyze_function(&func);
@@ -234,11 +234,14 @@ mod tests {
         HirFunction {
             name: name.to_string(),
             params: params.into(),
+            param_spec: Default::default(),
             ret_type,
             body: vec![],
             properties,
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }
     }
 
@@ -282,6 +285,9 @@ mod tests {
             always_terminates: true,
             panic_free: false,
             max_stack_depth: None,
+            can_fail: false,
+            error_types: vec![],
+            is_generator: false,
         };
 
         let func = create_test_function(
@@ -432,6 +438,9 @@ mod tests {
             always_terminates: true,
             panic_free: true,
             max_stack_depth: Some(10),
+            can_fail: false,
+            error_types: vec![],
+            is_generator: false,
         };
 
         let func = create_test_function(
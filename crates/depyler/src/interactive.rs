@@ -1,5 +1,6 @@
 use anyhow::Result;
 use colored::Colorize;
+use depyler_analyzer::inference::infer_types;
 use depyler_annotations::{AnnotationParser, AnnotationValidator};
 use depyler_core::DepylerPipeline;
 use depyler_quality::QualityAnalyzer;
@@ -52,7 +53,7 @@ impl Default for InteractiveSession {
 impl InteractiveSession {
     pub fn new() -> Self {
         Self {
-            pipeline: DepylerPipeline::new(),
+            pipeline: DepylerPipeline::new().with_type_inference(infer_types),
             annotation_parser: AnnotationParser::new(),
             annotation_validator: AnnotationValidator::new(),
             quality_analyzer: QualityAnalyzer::new(),
@@ -520,9 +521,9 @@ impl InteractiveSession {
 
     // Helper functions for analysis
     #[allow(clippy::only_used_in_recursion)]
-    fn has_loops(&self, stmts: &[depyler_core::hir::HirStmt]) -> bool {
+    fn has_loops(&self, stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>]) -> bool {
         use depyler_core::hir::HirStmt;
-        stmts.iter().any(|stmt| match stmt {
+        stmts.iter().any(|stmt| match &stmt.node {
             HirStmt::For { .. } | HirStmt::While { .. } => true,
             HirStmt::If {
                 then_body,
@@ -533,9 +534,12 @@ impl InteractiveSession {
         })
     }
 
-    fn has_nested_loops(&self, stmts: &[depyler_core::hir::HirStmt]) -> bool {
+    fn has_nested_loops(
+        &self,
+        stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>],
+    ) -> bool {
         use depyler_core::hir::HirStmt;
-        stmts.iter().any(|stmt| match stmt {
+        stmts.iter().any(|stmt| match &stmt.node {
             HirStmt::For { body, .. } | HirStmt::While { body, .. } => self.has_loops(body),
             HirStmt::If {
                 then_body,
@@ -549,9 +553,12 @@ impl InteractiveSession {
         })
     }
 
-    fn has_simple_numeric_loop(&self, stmts: &[depyler_core::hir::HirStmt]) -> bool {
+    fn has_simple_numeric_loop(
+        &self,
+        stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>],
+    ) -> bool {
         use depyler_core::hir::{HirExpr, HirStmt};
-        stmts.iter().any(|stmt| match stmt {
+        stmts.iter().any(|stmt| match &stmt.node {
             HirStmt::For { iter, body, .. } => {
                 // Check if iterating over range
                 matches!(iter, HirExpr::Call { func, .. } if func == "range") &&
@@ -575,16 +582,13 @@ impl InteractiveSession {
     }
 
     #[allow(clippy::only_used_in_recursion)]
-    fn has_modification_patterns(&self, stmts: &[depyler_core::hir::HirStmt]) -> bool {
-        use depyler_core::hir::{HirExpr, HirStmt};
-        stmts.iter().any(|stmt| match stmt {
-            HirStmt::Expr(HirExpr::Call { func, .. }) => {
-                matches!(
-                    func.as_str(),
-                    "append" | "extend" | "insert" | "remove" | "pop" | "clear"
-                )
-            }
-            HirStmt::Expr(_) => false,
+    fn has_modification_patterns(
+        &self,
+        stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>],
+    ) -> bool {
+        use depyler_core::hir::HirStmt;
+        stmts.iter().any(|stmt| match &stmt.node {
+            HirStmt::Expr(expr) => expr.is_mutating_method_call(),
             HirStmt::If {
                 then_body,
                 else_body,
@@ -607,9 +611,12 @@ impl InteractiveSession {
         self.has_lookup_in_loop(&func.body)
     }
 
-    fn has_lookup_in_loop(&self, stmts: &[depyler_core::hir::HirStmt]) -> bool {
+    fn has_lookup_in_loop(
+        &self,
+        stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>],
+    ) -> bool {
         use depyler_core::hir::HirStmt;
-        stmts.iter().any(|stmt| match stmt {
+        stmts.iter().any(|stmt| match &stmt.node {
             HirStmt::For { body, .. } | HirStmt::While { body, .. } => self.has_dict_access(body),
             _ => false,
         })
@@ -621,9 +628,12 @@ impl InteractiveSession {
             || matches!(func.ret_type, Type::String)
     }
 
-    fn has_string_concatenation(&self, stmts: &[depyler_core::hir::HirStmt]) -> bool {
+    fn has_string_concatenation(
+        &self,
+        stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>],
+    ) -> bool {
         use depyler_core::hir::HirStmt;
-        stmts.iter().any(|stmt| match stmt {
+        stmts.iter().any(|stmt| match &stmt.node {
             HirStmt::Assign { value, .. } => self.has_string_concat_expr(value),
             HirStmt::Return(Some(expr)) => self.has_string_concat_expr(expr),
             _ => false,
@@ -658,9 +668,12 @@ impl InteractiveSession {
         self.has_index_access(&func.body)
     }
 
-    fn has_index_access(&self, stmts: &[depyler_core::hir::HirStmt]) -> bool {
+    fn has_index_access(
+        &self,
+        stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>],
+    ) -> bool {
         use depyler_core::hir::HirStmt;
-        stmts.iter().any(|stmt| match stmt {
+        stmts.iter().any(|stmt| match &stmt.node {
             HirStmt::Assign { value, .. } => self.has_index_expr(value),
             HirStmt::Return(Some(expr)) => self.has_index_expr(expr),
             HirStmt::Expr(expr) => self.has_index_expr(expr),
@@ -683,7 +696,10 @@ impl InteractiveSession {
         matches!(expr, HirExpr::Index { .. })
     }
 
-    fn has_dict_access(&self, stmts: &[depyler_core::hir::HirStmt]) -> bool {
+    fn has_dict_access(
+        &self,
+        stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>],
+    ) -> bool {
         self.has_index_access(stmts) // Simplified - dict access uses same index syntax
     }
 
@@ -694,11 +710,14 @@ impl InteractiveSession {
     }
 
     #[allow(clippy::only_used_in_recursion)]
-    fn calculate_complexity(&self, stmts: &[depyler_core::hir::HirStmt]) -> u32 {
+    fn calculate_complexity(
+        &self,
+        stmts: &[depyler_core::hir::Spanned<depyler_core::hir::HirStmt>],
+    ) -> u32 {
         use depyler_core::hir::HirStmt;
         stmts
             .iter()
-            .map(|stmt| match stmt {
+            .map(|stmt| match &stmt.node {
                 HirStmt::If {
                     then_body,
                     else_body,
@@ -4,10 +4,13 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use colored::Colorize;
+use depyler_analyzer::inference::infer_types;
 use depyler_analyzer::Analyzer;
 use depyler_core::{
-    lambda_codegen::LambdaCodeGenerator, lambda_inference::LambdaTypeInferencer,
-    lambda_optimizer::LambdaOptimizer, lambda_testing::LambdaTestHarness, DepylerPipeline,
+    error::TranspileError, lambda_codegen::LambdaCodeGenerator,
+    lambda_inference::LambdaTypeInferencer, lambda_optimizer::LambdaOptimizer,
+    lambda_testing::LambdaTestHarness, package::Package, rust_gen::FileOptions,
+    source_map::SourceMap, DepylerPipeline,
 };
 use depyler_quality::QualityAnalyzer;
 use indicatif::{ProgressBar, ProgressStyle};
@@ -33,10 +36,11 @@ pub struct Cli {
 pub enum Commands {
     /// Transpile Python code to Rust
     Transpile {
-        /// Input Python file
+        /// Input Python file, or a package directory to transpile into a crate
         input: PathBuf,
 
-        /// Output Rust file (defaults to input with .rs extension)
+        /// Output Rust file (defaults to input with .rs extension), or crate
+        /// directory (defaults to the package's with a `_rs` suffix)
         #[arg(short, long)]
         output: Option<PathBuf>,
 
@@ -47,6 +51,19 @@ pub enum Commands {
         /// Generate property tests
         #[arg(long)]
         gen_tests: bool,
+
+        /// Write a source map from Rust lines to Python next to the output
+        #[arg(long)]
+        source_map: bool,
+
+        /// End each generated statement with a `// py:LINE` comment
+        #[arg(long)]
+        line_comments: bool,
+
+        /// Stub out functions that fail to transpile with `todo!()`
+        /// instead of failing the whole file
+        #[arg(long)]
+        best_effort: bool,
     },
 
     /// Analyze Python code complexity and metrics
@@ -226,7 +243,18 @@ pub fn transpile_command(
     output: Option<PathBuf>,
     verify: bool,
     gen_tests: bool,
+    source_map: bool,
+    line_comments: bool,
+    best_effort: bool,
 ) -> Result<()> {
+    if input.is_dir() {
+        if source_map || line_comments || best_effort {
+            anyhow::bail!(
+                "--source-map, --line-comments and --best-effort apply to single files, not packages"
+            );
+        }
+        return transpile_package_command(input, output);
+    }
     let start = Instant::now();
 
     // Read input file
@@ -244,7 +272,7 @@ pub fn transpile_command(
 
     // Initialize pipeline
     pb.set_message("Initializing pipeline...");
-    let mut pipeline = DepylerPipeline::new();
+    let mut pipeline = DepylerPipeline::new().with_type_inference(infer_types);
     if verify {
         pipeline = pipeline.with_verification();
     }
@@ -253,7 +281,20 @@ pub fn transpile_command(
     // Parse Python
     pb.set_message("Parsing Python source...");
     let parse_start = Instant::now();
-    let rust_code = pipeline.transpile(&python_source)?;
+    let options = FileOptions {
+        source_map: source_map || line_comments,
+        line_comments,
+        best_effort,
+        ..Default::default()
+    };
+    let file = pipeline
+        .transpile_with_options(&python_source, options)
+        .map_err(|err| {
+            pb.finish_and_clear();
+            render_transpile_error(err, &input, &python_source)
+        })?;
+    let rust_code = file.code;
+    let skipped = file.skipped;
     let parse_time = parse_start.elapsed();
     pb.inc(1);
 
@@ -273,6 +314,11 @@ pub fn transpile_command(
     });
 
     fs::write(&output_path, &rust_code)?;
+    if source_map {
+        let mut map = file.source_map;
+        map.python_file = input.display().to_string();
+        fs::write(source_map_path(&output_path), map.to_json()?)?;
+    }
     pb.inc(1);
 
     pb.finish_and_clear();
@@ -301,10 +347,68 @@ pub fn transpile_command(
     if verify {
         println!("✓ Properties Verified");
     }
+    for stub in &skipped {
+        eprintln!(
+            "⚠️  Stubbed out {}: unsupported: {} at {}:{}:{}",
+            stub.function,
+            stub.construct,
+            input.display(),
+            stub.line,
+            stub.column
+        );
+    }
 
     Ok(())
 }
 
+/// Transpile the Python package in directory `input` into a crate
+pub fn transpile_package_command(input: PathBuf, output: Option<PathBuf>) -> Result<()> {
+    let package = Package::from_dir(&input)?;
+    let pipeline = DepylerPipeline::new().with_type_inference(infer_types);
+    let project = pipeline.transpile_package(&package).map_err(|err| {
+        let file = err
+            .downcast_ref::<TranspileError>()
+            .and_then(|e| e.location.as_ref())
+            .map(|location| location.file.clone());
+        match package
+            .modules
+            .iter()
+            .find(|m| Some(m.file.display().to_string()) == file)
+        {
+            Some(module) => render_transpile_error(err, &module.file, &module.source),
+            None => err,
+        }
+    })?;
+
+    let output_dir = output.unwrap_or_else(|| input.with_file_name(format!("{}_rs", package.name)));
+    for (path, contents) in &project.files {
+        let path = output_dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+    }
+
+    println!(
+        "📦 Package: {} ({} modules)",
+        package.name,
+        package.modules.len()
+    );
+    println!("📝 Crate: {}", output_dir.display());
+    for cycle in &project.report.cycles {
+        eprintln!("⚠️  Import cycle among {}", cycle.join(", "));
+    }
+    for import in &project.report.unresolved {
+        eprintln!(
+            "⚠️  Unresolved import of {} in {}:{}",
+            import.import,
+            import.file.display(),
+            import.line
+        );
+    }
+    Ok(())
+}
+
 pub fn analyze_command(input: PathBuf, format: String) -> Result<()> {
     // Read and parse
     let python_source = fs::read_to_string(&input)?;
@@ -346,6 +450,11 @@ pub fn analyze_command(input: PathBuf, format: String) -> Result<()> {
                 "Type Coverage: {:.0}%",
                 analysis.type_coverage.coverage_percentage
             );
+            println!(
+                "Inferred Types: {} parameters, {} return types",
+                analysis.type_coverage.inferred_parameters,
+                analysis.type_coverage.functions_with_inferred_return_type
+            );
         }
     }
 
@@ -354,7 +463,7 @@ pub fn analyze_command(input: PathBuf, format: String) -> Result<()> {
 
 pub fn check_command(input: PathBuf) -> Result<()> {
     let python_source = fs::read_to_string(&input)?;
-    let pipeline = DepylerPipeline::new();
+    let pipeline = DepylerPipeline::new().with_type_inference(infer_types);
 
     // Try to transpile
     match pipeline.transpile(&python_source) {
@@ -363,12 +472,26 @@ pub fn check_command(input: PathBuf) -> Result<()> {
             Ok(())
         }
         Err(e) => {
+            let e = render_transpile_error(e, &input, &python_source);
             println!("✗ {} cannot be transpiled: {}", input.display(), e);
             std::process::exit(1);
         }
     }
 }
 
+/// Render a transpile error as a diagnostic pointing into `input`; other
+/// errors are returned as they are
+pub fn render_transpile_error(
+    err: anyhow::Error,
+    input: &std::path::Path,
+    python_source: &str,
+) -> anyhow::Error {
+    match err.downcast_ref::<TranspileError>() {
+        Some(e) => anyhow::anyhow!(e.render(&input.display().to_string(), python_source)),
+        None => err,
+    }
+}
+
 pub fn complexity_rating(complexity: f64) -> colored::ColoredString {
     if complexity <= 5.0 {
         "(✓ Good)".green()
@@ -537,7 +660,7 @@ pub fn inspect_command(
     output: Option<PathBuf>,
 ) -> Result<()> {
     let python_source = fs::read_to_string(&input)?;
-    let pipeline = DepylerPipeline::new();
+    let pipeline = DepylerPipeline::new().with_type_inference(infer_types);
 
     let output_content = match repr.as_str() {
         "python-ast" => inspect_python_ast(&python_source, &format)?,
@@ -670,20 +793,41 @@ pub fn check_rust_compilation(python_file: &std::path::Path) -> Result<bool> {
     check_rust_compilation_for_file(rust_file.to_str().unwrap())
 }
 
+/// Where the source map of the generated `rust_file` is written
+pub fn source_map_path(rust_file: &std::path::Path) -> PathBuf {
+    let mut path = rust_file.as_os_str().to_owned();
+    path.push(".map");
+    PathBuf::from(path)
+}
+
 pub fn check_rust_compilation_for_file(rust_file: &str) -> Result<bool> {
     if !std::path::Path::new(rust_file).exists() {
         return Ok(false);
     }
 
+    // Generated packages are of the 2021 edition, whose prelude has `TryFrom`.
+    // rustc replaces its output file, so it must not be `/dev/null`
+    let library = std::env::temp_dir().join(format!("depyler-check-{}.rlib", std::process::id()));
     let output = Command::new("rustc")
+        .arg("--edition")
+        .arg("2021")
         .arg("--check-cfg")
         .arg("cfg()")
         .arg("--crate-type")
         .arg("lib")
         .arg(rust_file)
         .arg("-o")
-        .arg("/dev/null")
+        .arg(&library)
         .output()?;
+    let _ = fs::remove_file(&library);
+
+    // With a source map, report the errors at the Python they come from
+    let map_path = source_map_path(std::path::Path::new(rust_file));
+    if !output.status.success() && map_path.exists() {
+        let map = SourceMap::from_json(&fs::read_to_string(map_path)?)?;
+        let diagnostics = String::from_utf8_lossy(&output.stderr);
+        eprintln!("{}", map.rewrite_diagnostics(rust_file, &diagnostics));
+    }
 
     Ok(output.status.success())
 }
@@ -829,7 +973,9 @@ pub fn lambda_convert_command(
 
     // Step 3: Transpile to Rust
     pb.set_message("🦀 Transpiling to Rust...");
-    let rust_code = pipeline.transpile(&python_source)?;
+    let rust_code = pipeline
+        .transpile(&python_source)
+        .map_err(|err| render_transpile_error(err, &input, &python_source))?;
 
     let generation_context = depyler_core::lambda_codegen::LambdaGenerationContext {
         event_type: lambda_annotations.event_type.clone(),
@@ -1155,11 +1301,57 @@ mod tests {
         (temp_dir, file_path)
     }
 
+    /// What `python`, transpiled by `transpile_command` and followed by
+    /// `fn main() { main }`, prints
+    fn transpile_and_run(python: &str, main: &str) -> String {
+        let (temp_dir, input_path) = create_test_python_file(python);
+        let output_path = input_path.with_extension("rs");
+        transpile_command(
+            input_path,
+            Some(output_path.clone()),
+            false,
+            false,
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        let code = fs::read_to_string(&output_path).unwrap();
+        fs::write(
+            &output_path,
+            format!("{code}\n\nfn main() {{\n{main}\n}}\n"),
+        )
+        .unwrap();
+        let binary = temp_dir.path().join("program");
+        let output = Command::new("rustc")
+            .args(["--edition", "2021", "-A", "warnings", "-o"])
+            .arg(&binary)
+            .arg(&output_path)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let output = Command::new(&binary).output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_inferred_union_return_runs() {
+        let printed = transpile_and_run(
+            "def pick(flag: bool):\n    if flag:\n        return 1\n    return \"a\"\n",
+            r#"println!("{:?} {:?}", pick(true), pick(false));"#,
+        );
+        assert_eq!(printed, "Int(1) Str(\"a\")\n");
+    }
+
     #[test]
     fn test_transpile_command_basic() {
         let (_temp_dir, input_path) = create_test_python_file("def hello() -> int: return 42");
 
-        let result = transpile_command(input_path, None, false, false);
+        let result = transpile_command(input_path, None, false, false, false, false, false);
         assert!(result.is_ok());
     }
 
@@ -1168,11 +1360,124 @@ mod tests {
         let (_temp_dir, input_path) = create_test_python_file("def hello() -> int: return 42");
         let output_path = input_path.with_extension("rs");
 
-        let result = transpile_command(input_path, Some(output_path.clone()), false, false);
+        let result = transpile_command(
+            input_path,
+            Some(output_path.clone()),
+            false,
+            false,
+            false,
+            false,
+            false,
+        );
         assert!(result.is_ok());
         assert!(output_path.exists());
     }
 
+    #[test]
+    fn test_transpile_command_with_source_map() {
+        let (_temp_dir, input_path) =
+            create_test_python_file("def hello() -> int:\n    return 42\n");
+        let output_path = input_path.with_extension("rs");
+
+        let result = transpile_command(
+            input_path.clone(),
+            Some(output_path.clone()),
+            false,
+            false,
+            true,
+            true,
+            false,
+        );
+        assert!(result.is_ok());
+        assert!(fs::read_to_string(&output_path)
+            .unwrap()
+            .contains("42 // py:2"));
+        let map = fs::read_to_string(source_map_path(&output_path)).unwrap();
+        let map = SourceMap::from_json(&map).unwrap();
+        assert_eq!(map.python_file, input_path.display().to_string());
+        assert!(!map.mappings.is_empty());
+    }
+
+    #[test]
+    fn test_transpile_command_best_effort() {
+        let (_temp_dir, input_path) =
+            create_test_python_file("def hello(x: int) -> int:\n    del x\n    return 42\n");
+        let output_path = input_path.with_extension("rs");
+
+        let result = transpile_command(
+            input_path.clone(),
+            Some(output_path.clone()),
+            false,
+            false,
+            false,
+            false,
+            false,
+        );
+        assert!(result.is_err());
+
+        let result = transpile_command(
+            input_path,
+            Some(output_path.clone()),
+            false,
+            false,
+            false,
+            false,
+            true,
+        );
+        assert!(result.is_ok());
+        assert!(fs::read_to_string(&output_path)
+            .unwrap()
+            .contains("todo!(\"unsupported: `del` statement at line 2\")"));
+    }
+
+    #[test]
+    fn test_transpile_command_best_effort_with_source_map() {
+        let (_temp_dir, input_path) =
+            create_test_python_file("def hello(x: int) -> int:\n    del x\n    return 42\n");
+        let output_path = input_path.with_extension("rs");
+
+        let result = transpile_command(
+            input_path,
+            Some(output_path.clone()),
+            false,
+            false,
+            true,
+            true,
+            true,
+        );
+        assert!(result.is_ok());
+        let rust_code = fs::read_to_string(&output_path).unwrap();
+        assert!(rust_code.contains("todo!(\"unsupported: `del` statement at line 2\")"));
+        assert!(source_map_path(&output_path).exists());
+    }
+
+    #[test]
+    fn test_transpile_command_package() {
+        let temp_dir = TempDir::new().unwrap();
+        let package = temp_dir.path().join("shapes");
+        fs::create_dir_all(package.join("geo")).unwrap();
+        fs::write(package.join("__init__.py"), "from .geo.area import area\n").unwrap();
+        fs::write(package.join("geo").join("__init__.py"), "").unwrap();
+        fs::write(
+            package.join("geo").join("area.py"),
+            "def area(w: int, h: int) -> int:\n    return w * h\n",
+        )
+        .unwrap();
+
+        let result = transpile_command(package.clone(), None, false, false, false, false, false);
+        assert!(result.is_ok());
+        let output = temp_dir.path().join("shapes_rs");
+        assert!(output.join("Cargo.toml").exists());
+        let lib = fs::read_to_string(output.join("src").join("lib.rs")).unwrap();
+        assert!(lib.contains("pub use crate::geo::area::area;"));
+        assert!(output.join("src").join("geo").join("mod.rs").exists());
+        assert!(output.join("src").join("geo").join("area.rs").exists());
+
+        // Per-file options have no meaning for a package
+        let result = transpile_command(package, None, false, false, false, false, true);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_analyze_command_text_format() {
         let (_temp_dir, input_path) = create_test_python_file("def hello() -> int: return 42");
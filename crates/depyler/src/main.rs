@@ -19,8 +19,19 @@ fn main() -> Result<()> {
             output,
             verify,
             gen_tests,
+            source_map,
+            line_comments,
+            best_effort,
         } => {
-            transpile_command(input, output, verify, gen_tests)?;
+            transpile_command(
+                input,
+                output,
+                verify,
+                gen_tests,
+                source_map,
+                line_comments,
+                best_effort,
+            )?;
         }
         Commands::Analyze { input, format } => {
             analyze_command(input, format)?;
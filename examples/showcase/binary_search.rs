@@ -1,22 +1,30 @@
-# [doc = "Find target in sorted array, return -1 if not found."] pub fn binary_search (arr : Vec < i32 > , target : i32) -> i32 {
-    let mut left = 0;
-    let mut right = (arr . len () - 1);
-    while (left <= right) {
-    let mut mid = ((left + right) / 2);
-    if (arr . get (mid as usize) . copied () . unwrap_or_default () == target) {
+#[doc = "Find target in sorted array, return -1 if not found."] pub fn binary_search(arr: &[i32], target: i32)  -> i32 {
+    let mut left: i32 = 0;
+    let mut right = (arr.len() as i32) - 1;
+    while left <= right {
+    let mid = (left + right).div_euclid(2);
+    if arr.get(if mid<0 {
+    arr.len().wrapping_sub(mid.unsigned_abs() as usize)
+}
+else {
+    mid as usize }).copied().expect("list index out of range") == target {
     return mid;
    
 }
 else {
-    if (arr . get (mid as usize) . copied () . unwrap_or_default () < target) {
-    left = (mid + 1);
+    if arr.get(if mid<0 {
+    arr.len().wrapping_sub(mid.unsigned_abs() as usize)
+}
+else {
+    mid as usize }).copied().expect("list index out of range")<target {
+    left = mid + 1;
    
 }
 else {
-    right = (mid - 1);
+    right = mid - 1;
    
 }
 }
 }
-return - 1;
-    }
\ No newline at end of file
+- 1
+}
\ No newline at end of file
@@ -124,6 +124,7 @@ fn test_quality_analyzer_edge_cases() {
     let problematic_function = HirFunction {
         name: "problematic".to_string(),
         params: smallvec![],
+        param_spec: Default::default(),
         ret_type: Type::Unknown, // Unknown type
         body: vec![
             // Very deeply nested structure
@@ -133,17 +134,24 @@ fn test_quality_analyzer_edge_cases() {
                     condition: HirExpr::Literal(Literal::Bool(true)),
                     then_body: vec![HirStmt::If {
                         condition: HirExpr::Literal(Literal::Bool(true)),
-                        then_body: vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42))))],
+                        then_body: vec![
+                            HirStmt::Return(Some(HirExpr::Literal(Literal::Int(42)))).into()
+                        ],
                         else_body: None,
-                    }],
+                    }
+                    .into()],
                     else_body: None,
-                }],
+                }
+                .into()],
                 else_body: None,
-            },
+            }
+            .into(),
         ],
         properties: FunctionProperties::default(),
         annotations: TranspilationAnnotations::default(),
         docstring: None,
+        is_async: false,
+        span: Default::default(),
     };
 
     let functions = vec![problematic_function];
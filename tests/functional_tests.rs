@@ -140,10 +140,7 @@ fn test_integration_transpilation_pipeline() {
             rust_code.contains("i32"),
             "Should contain Rust integer type"
         );
-        assert!(
-            rust_code.contains("return"),
-            "Should contain return statement"
-        );
+        assert!(rust_code.contains("n * n"), "Should return the product");
     }
 
     println!("✅ Core transpilation pipeline test passed!");
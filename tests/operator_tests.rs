@@ -1,6 +1,8 @@
 use depyler_annotations::TranspilationAnnotations;
 use depyler_core::direct_rules::apply_rules;
-use depyler_core::hir::{BinOp, HirExpr, HirFunction, HirModule, HirStmt, Literal, Type};
+use depyler_core::hir::{
+    AssignTarget, BinOp, HirExpr, HirFunction, HirModule, HirStmt, Literal, Type,
+};
 use depyler_core::rust_gen::generate_rust_file;
 use depyler_core::type_mapper::TypeMapper;
 
@@ -10,28 +12,35 @@ fn test_augmented_assignment() {
         functions: vec![HirFunction {
             name: "test_aug_assign".to_string(),
             params: vec![("x".to_string(), Type::Int)].into(),
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![
                 HirStmt::Assign {
-                    target: "total".to_string(),
+                    target: AssignTarget::Symbol("total".to_string()),
                     value: HirExpr::Literal(Literal::Int(0)),
-                },
+                }
+                .into(),
                 // total += x (converted to total = total + x)
                 HirStmt::Assign {
-                    target: "total".to_string(),
+                    target: AssignTarget::Symbol("total".to_string()),
                     value: HirExpr::Binary {
                         op: BinOp::Add,
                         left: Box::new(HirExpr::Var("total".to_string())),
                         right: Box::new(HirExpr::Var("x".to_string())),
                     },
-                },
-                HirStmt::Return(Some(HirExpr::Var("total".to_string()))),
+                }
+                .into(),
+                HirStmt::Return(Some(HirExpr::Var("total".to_string()))).into(),
             ],
             properties: Default::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
@@ -48,26 +57,33 @@ fn test_in_operator() {
         functions: vec![HirFunction {
             name: "test_in".to_string(),
             params: vec![].into(),
+            param_spec: Default::default(),
             ret_type: Type::Bool,
             body: vec![
                 HirStmt::Assign {
-                    target: "dict".to_string(),
+                    target: AssignTarget::Symbol("dict".to_string()),
                     value: HirExpr::Dict(vec![(
                         HirExpr::Literal(Literal::String("key".to_string())),
                         HirExpr::Literal(Literal::String("value".to_string())),
                     )]),
-                },
+                }
+                .into(),
                 HirStmt::Return(Some(HirExpr::Binary {
                     op: BinOp::In,
                     left: Box::new(HirExpr::Literal(Literal::String("key".to_string()))),
                     right: Box::new(HirExpr::Var("dict".to_string())),
-                })),
+                }))
+                .into(),
             ],
             properties: Default::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
@@ -84,23 +100,30 @@ fn test_not_in_operator() {
         functions: vec![HirFunction {
             name: "test_not_in".to_string(),
             params: vec![].into(),
+            param_spec: Default::default(),
             ret_type: Type::Bool,
             body: vec![
                 HirStmt::Assign {
-                    target: "dict".to_string(),
+                    target: AssignTarget::Symbol("dict".to_string()),
                     value: HirExpr::Dict(vec![]),
-                },
+                }
+                .into(),
                 HirStmt::Return(Some(HirExpr::Binary {
                     op: BinOp::NotIn,
                     left: Box::new(HirExpr::Literal(Literal::String("key".to_string()))),
                     right: Box::new(HirExpr::Var("dict".to_string())),
-                })),
+                }))
+                .into(),
             ],
             properties: Default::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
@@ -126,17 +149,23 @@ fn test_all_arithmetic_operators() {
             functions: vec![HirFunction {
                 name: format!("test_{op:?}").to_lowercase(),
                 params: vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)].into(),
+                param_spec: Default::default(),
                 ret_type: Type::Int,
                 body: vec![HirStmt::Return(Some(HirExpr::Binary {
                     op,
                     left: Box::new(HirExpr::Var("a".to_string())),
                     right: Box::new(HirExpr::Var("b".to_string())),
-                }))],
+                }))
+                .into()],
                 properties: Default::default(),
                 annotations: TranspilationAnnotations::default(),
                 docstring: None,
+                is_async: false,
+                span: Default::default(),
             }],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let type_mapper = TypeMapper::default();
@@ -166,17 +195,23 @@ fn test_comparison_operators() {
             functions: vec![HirFunction {
                 name: format!("test_{op:?}").to_lowercase(),
                 params: vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)].into(),
+                param_spec: Default::default(),
                 ret_type: Type::Bool,
                 body: vec![HirStmt::Return(Some(HirExpr::Binary {
                     op,
                     left: Box::new(HirExpr::Var("a".to_string())),
                     right: Box::new(HirExpr::Var("b".to_string())),
-                }))],
+                }))
+                .into()],
                 properties: Default::default(),
                 annotations: TranspilationAnnotations::default(),
                 docstring: None,
+                is_async: false,
+                span: Default::default(),
             }],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let type_mapper = TypeMapper::default();
@@ -199,17 +234,23 @@ fn test_logical_operators() {
             functions: vec![HirFunction {
                 name: format!("test_{op:?}").to_lowercase(),
                 params: vec![("a".to_string(), Type::Bool), ("b".to_string(), Type::Bool)].into(),
+                param_spec: Default::default(),
                 ret_type: Type::Bool,
                 body: vec![HirStmt::Return(Some(HirExpr::Binary {
                     op,
                     left: Box::new(HirExpr::Var("a".to_string())),
                     right: Box::new(HirExpr::Var("b".to_string())),
-                }))],
+                }))
+                .into()],
                 properties: Default::default(),
                 annotations: TranspilationAnnotations::default(),
                 docstring: None,
+                is_async: false,
+                span: Default::default(),
             }],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let type_mapper = TypeMapper::default();
@@ -238,17 +279,23 @@ fn test_bitwise_operators() {
             functions: vec![HirFunction {
                 name: format!("test_{op:?}").to_lowercase(),
                 params: vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)].into(),
+                param_spec: Default::default(),
                 ret_type: Type::Int,
                 body: vec![HirStmt::Return(Some(HirExpr::Binary {
                     op,
                     left: Box::new(HirExpr::Var("a".to_string())),
                     right: Box::new(HirExpr::Var("b".to_string())),
-                }))],
+                }))
+                .into()],
                 properties: Default::default(),
                 annotations: TranspilationAnnotations::default(),
                 docstring: None,
+                is_async: false,
+                span: Default::default(),
             }],
+            classes: vec![],
             imports: vec![],
+            skipped: vec![],
         };
 
         let type_mapper = TypeMapper::default();
@@ -269,17 +316,23 @@ fn test_power_operator_not_supported() {
         functions: vec![HirFunction {
             name: "test_pow".to_string(),
             params: vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)].into(),
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Pow,
                 left: Box::new(HirExpr::Var("a".to_string())),
                 right: Box::new(HirExpr::Var("b".to_string())),
-            }))],
+            }))
+            .into()],
             properties: Default::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
@@ -288,34 +341,40 @@ fn test_power_operator_not_supported() {
 
 #[test]
 fn test_array_length_subtraction_safety() {
-    // Test that len(arr) - 1 uses saturating_sub
+    // Test that len(arr) - 1 is an int subtraction, -1 for an empty list
     let module = HirModule {
         functions: vec![HirFunction {
             name: "safe_last_index".to_string(),
             params: vec![("arr".to_string(), Type::List(Box::new(Type::Int)))].into(),
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Sub,
                 left: Box::new(HirExpr::Call {
                     func: "len".to_string(),
                     args: vec![HirExpr::Var("arr".to_string())],
+                    kwargs: vec![],
                 }),
                 right: Box::new(HirExpr::Literal(Literal::Int(1))),
-            }))],
+            }))
+            .into()],
             properties: Default::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: Some("Get the last index of an array safely".to_string()),
+            is_async: false,
+            span: Default::default(),
         }],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
     let result = generate_rust_file(&module, &type_mapper).unwrap();
 
-    // Verify that saturating_sub is used
     assert!(
-        result.contains("saturating_sub"),
-        "Expected saturating_sub for array length subtraction, got: {}",
+        result.contains("(arr.len() as i32) - 1") && !result.contains("saturating_sub"),
+        "Expected int subtraction from the array length, got: {}",
         result
     );
 }
@@ -327,17 +386,23 @@ fn test_regular_subtraction_unchanged() {
         functions: vec![HirFunction {
             name: "regular_sub".to_string(),
             params: vec![("x".to_string(), Type::Int), ("y".to_string(), Type::Int)].into(),
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Sub,
                 left: Box::new(HirExpr::Var("x".to_string())),
                 right: Box::new(HirExpr::Var("y".to_string())),
-            }))],
+            }))
+            .into()],
             properties: Default::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         }],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
@@ -353,7 +418,7 @@ fn test_regular_subtraction_unchanged() {
 
 #[test]
 fn test_len_variable_subtraction_safety() {
-    // Test that len(items) - offset uses saturating_sub
+    // Test that len(items) - offset is an int subtraction
     let module = HirModule {
         functions: vec![HirFunction {
             name: "complex_len_sub".to_string(),
@@ -362,29 +427,35 @@ fn test_len_variable_subtraction_safety() {
                 ("offset".to_string(), Type::Int),
             ]
             .into(),
+            param_spec: Default::default(),
             ret_type: Type::Int,
             body: vec![HirStmt::Return(Some(HirExpr::Binary {
                 op: BinOp::Sub,
                 left: Box::new(HirExpr::Call {
                     func: "len".to_string(),
                     args: vec![HirExpr::Var("items".to_string())],
+                    kwargs: vec![],
                 }),
                 right: Box::new(HirExpr::Var("offset".to_string())),
-            }))],
+            }))
+            .into()],
             properties: Default::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: Some("Subtract offset from list length".to_string()),
+            is_async: false,
+            span: Default::default(),
         }],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
     let result = generate_rust_file(&module, &type_mapper).unwrap();
 
-    // Should use saturating_sub for len() - variable
     assert!(
-        result.contains("saturating_sub"),
-        "Expected saturating_sub for len() - variable, got: {}",
+        result.contains("(items.len() as i32) - offset") && !result.contains("saturating_sub"),
+        "Expected int subtraction from the list length, got: {}",
         result
     );
 }
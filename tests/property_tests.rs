@@ -3,7 +3,7 @@ use depyler_annotations::TranspilationAnnotations;
 #[cfg(not(feature = "coverage"))]
 use depyler_core::hir::{HirExpr, Literal, Type};
 #[cfg(not(feature = "coverage"))]
-use depyler_core::hir::{HirFunction, HirStmt};
+use depyler_core::hir::{HirFunction, HirStmt, Spanned};
 
 #[cfg(not(feature = "coverage"))]
 use depyler_core::direct_rules::apply_rules;
@@ -30,7 +30,9 @@ fn prop_transpiled_functions_are_valid_rust(func: ArbitraryFunction) -> TestResu
 
     let module = HirModule {
         functions: vec![func.0],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
@@ -58,16 +60,21 @@ fn prop_type_preservation(expr: ArbitraryTypedExpr) -> TestResult {
     let func = HirFunction {
         name: "test".to_string(),
         params: vec![].into(),
+        param_spec: Default::default(),
         ret_type: expr.ty.clone(),
-        body: vec![HirStmt::Return(Some(expr.expr))],
+        body: vec![HirStmt::Return(Some(expr.expr)).into()],
         properties: Default::default(),
         annotations: TranspilationAnnotations::default(),
         docstring: None,
+        is_async: false,
+        span: Default::default(),
     };
 
     let module = HirModule {
         functions: vec![func],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     match apply_rules(&module, &type_mapper) {
@@ -94,7 +101,9 @@ fn prop_type_preservation(expr: ArbitraryTypedExpr) -> TestResult {
 fn prop_pure_functions_have_no_side_effects(func: ArbitraryPureFunction) -> TestResult {
     let module = HirModule {
         functions: vec![func.0],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
@@ -122,7 +131,9 @@ fn prop_pure_functions_have_no_side_effects(func: ArbitraryPureFunction) -> Test
 fn prop_panic_free_functions_dont_panic(func: ArbitraryPanicFreeFunction) -> bool {
     let module = HirModule {
         functions: vec![func.0],
+        classes: vec![],
         imports: vec![],
+        skipped: vec![],
     };
 
     let type_mapper = TypeMapper::default();
@@ -164,11 +175,14 @@ impl Arbitrary for ArbitraryFunction {
         ArbitraryFunction(HirFunction {
             name,
             params: params.into(),
+            param_spec: Default::default(),
             ret_type,
             body,
             properties: Default::default(),
             annotations: TranspilationAnnotations::default(),
             docstring: None,
+            is_async: false,
+            span: Default::default(),
         })
     }
 }
@@ -198,7 +212,7 @@ impl Arbitrary for ArbitraryPureFunction {
     fn arbitrary(g: &mut Gen) -> Self {
         let mut func = ArbitraryFunction::arbitrary(g).0;
         // Ensure the function is pure by only using arithmetic operations
-        func.body = vec![HirStmt::Return(Some(arbitrary_pure_expr(g)))];
+        func.body = vec![HirStmt::Return(Some(arbitrary_pure_expr(g))).into()];
         func.properties.is_pure = true;
         ArbitraryPureFunction(func)
     }
@@ -213,7 +227,7 @@ impl Arbitrary for ArbitraryPanicFreeFunction {
     fn arbitrary(g: &mut Gen) -> Self {
         let mut func = ArbitraryFunction::arbitrary(g).0;
         // Ensure the function is panic-free by avoiding dangerous operations
-        func.body = vec![HirStmt::Return(Some(arbitrary_safe_expr(g)))];
+        func.body = vec![HirStmt::Return(Some(arbitrary_safe_expr(g))).into()];
         func.properties.panic_free = true;
         ArbitraryPanicFreeFunction(func)
     }
@@ -320,9 +334,9 @@ fn arbitrary_safe_expr(g: &mut Gen) -> HirExpr {
 }
 
 #[cfg(not(feature = "coverage"))]
-fn arbitrary_function_body(g: &mut Gen, ret_type: &Type) -> Vec<HirStmt> {
+fn arbitrary_function_body(g: &mut Gen, ret_type: &Type) -> Vec<Spanned<HirStmt>> {
     // Simple body that just returns a value of the correct type
-    vec![HirStmt::Return(Some(arbitrary_expr_of_type(g, ret_type)))]
+    vec![HirStmt::Return(Some(arbitrary_expr_of_type(g, ret_type))).into()]
 }
 
 #[cfg(all(test, not(feature = "coverage")))]
@@ -7,11 +7,14 @@ fn create_simple_function() -> HirFunction {
     HirFunction {
         name: "simple".to_string(),
         params: smallvec![("x".to_string(), Type::Int)],
+        param_spec: Default::default(),
         ret_type: Type::Int,
-        body: vec![HirStmt::Return(Some(HirExpr::Var("x".to_string())))],
+        body: vec![HirStmt::Return(Some(HirExpr::Var("x".to_string()))).into()],
         properties: FunctionProperties::default(),
         annotations: TranspilationAnnotations::default(),
         docstring: None,
+        is_async: false,
+        span: Default::default(),
     }
 }
 
@@ -23,6 +26,7 @@ fn create_complex_function() -> HirFunction {
             ("b".to_string(), Type::Int),
             ("c".to_string(), Type::Int)
         ],
+        param_spec: Default::default(),
         ret_type: Type::Int,
         body: vec![HirStmt::If {
             condition: HirExpr::Binary {
@@ -40,16 +44,24 @@ fn create_complex_function() -> HirFunction {
                     op: BinOp::Add,
                     left: Box::new(HirExpr::Var("a".to_string())),
                     right: Box::new(HirExpr::Var("b".to_string())),
-                }))],
-                else_body: Some(vec![HirStmt::Return(Some(HirExpr::Var("a".to_string())))]),
-            }],
+                }))
+                .into()],
+                else_body: Some(vec![
+                    HirStmt::Return(Some(HirExpr::Var("a".to_string()))).into()
+                ]),
+            }
+            .into()],
             else_body: Some(vec![HirStmt::Return(Some(HirExpr::Literal(Literal::Int(
                 0,
-            ))))]),
-        }],
+            ))))
+            .into()]),
+        }
+        .into()],
         properties: FunctionProperties::default(),
         annotations: TranspilationAnnotations::default(),
         docstring: None,
+        is_async: false,
+        span: Default::default(),
     }
 }
 
@@ -196,11 +208,14 @@ fn test_pmat_edge_cases() {
     let zero_complexity_func = HirFunction {
         name: "empty".to_string(),
         params: smallvec![],
+        param_spec: Default::default(),
         ret_type: Type::None,
         body: vec![], // Empty body
         properties: FunctionProperties::default(),
         annotations: TranspilationAnnotations::default(),
         docstring: None,
+        is_async: false,
+        span: Default::default(),
     };
 
     let functions = vec![zero_complexity_func];